@@ -0,0 +1,275 @@
+//! Addresses worked out from the networks the engine created, in `ips`:
+//!
+//! ```yaml
+//! output:
+//!   - network: backend
+//!     proto: udp
+//!     ips: ["host_ip(backend)", "cidr_offset(backend, 53)"]
+//!     dst_ports: [53]
+//! ```
+//!
+//! `host_ip(net)` is the gateway of network `net`, the host's address on
+//! it; `subnet(net)` its whole subnet; `cidr_offset(net, n)` the `n`th
+//! address of the subnet, counted back from its last address (`-1`) when
+//! `n` is negative. They are resolved whenever rules are rendered, from the
+//! networks last read from the engine, so one rules label fits hosts whose
+//! subnets differ. Only IPv4 subnets are used, as container chains match
+//! IPv4. A template that can't be resolved, such as for a network not
+//! created yet, is left out, and a rule left with no addresses skipped.
+
+use super::{AddrOrRange, Config};
+use crate::network::NetworkGatewayInfo;
+use ipnet::{IpNet, Ipv4Net};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use tracing::debug;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpTemplate {
+    /// `host_ip(net)`
+    HostIp(String),
+    /// `subnet(net)`
+    Subnet(String),
+    /// `cidr_offset(net, n)`
+    CidrOffset(String, i64),
+}
+
+impl IpTemplate {
+    pub fn network(&self) -> &str {
+        match self {
+            IpTemplate::HostIp(network)
+            | IpTemplate::Subnet(network)
+            | IpTemplate::CidrOffset(network, _) => network,
+        }
+    }
+
+    /// The address this stands for with `networks`, by name
+    pub fn resolve(&self, networks: &HashMap<String, NetworkGatewayInfo>) -> Option<AddrOrRange> {
+        let network = networks.get(self.network())?;
+        let subnet = || {
+            network.subnets.iter().find_map(|subnet| match subnet {
+                IpNet::V4(subnet) => Some(subnet.trunc()),
+                IpNet::V6(_) => None,
+            })
+        };
+        match self {
+            IpTemplate::HostIp(_) => network
+                .gateway_ips
+                .iter()
+                .find(|ip| ip.is_ipv4())
+                .map(|ip| AddrOrRange::Addr(*ip)),
+            IpTemplate::Subnet(_) => subnet().map(|subnet| AddrOrRange::Net(IpNet::V4(subnet))),
+            IpTemplate::CidrOffset(_, offset) => {
+                offset_address(&subnet()?, *offset).map(|ip| AddrOrRange::Addr(IpAddr::V4(ip)))
+            }
+        }
+    }
+}
+
+/// Address `offset` into `subnet`, from its end when negative
+fn offset_address(subnet: &Ipv4Net, offset: i64) -> Option<Ipv4Addr> {
+    let first = i64::from(u32::from(subnet.network()));
+    let last = i64::from(u32::from(subnet.broadcast()));
+    let ip = if offset < 0 {
+        last + 1 + offset
+    } else {
+        first + offset
+    };
+    (first..=last)
+        .contains(&ip)
+        .then(|| Ipv4Addr::from(ip as u32))
+}
+
+impl fmt::Display for IpTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpTemplate::HostIp(network) => write!(f, "host_ip({})", network),
+            IpTemplate::Subnet(network) => write!(f, "subnet({})", network),
+            IpTemplate::CidrOffset(network, offset) => {
+                write!(f, "cidr_offset({}, {})", network, offset)
+            }
+        }
+    }
+}
+
+impl FromStr for IpTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("invalid address template '{}': {}", s, reason);
+        let (name, rest) = s
+            .trim()
+            .split_once('(')
+            .ok_or_else(|| invalid("expected name(arguments)"))?;
+        let args = rest
+            .strip_suffix(')')
+            .ok_or_else(|| invalid("missing ')'"))?;
+        let args: Vec<&str> = args.split(',').map(str::trim).collect();
+        let network = |args: &[&str]| -> std::result::Result<String, String> {
+            match args.first() {
+                Some(network) if !network.is_empty() => Ok(network.to_string()),
+                _ => Err(invalid("missing the network name")),
+            }
+        };
+        match (name.trim(), args.len()) {
+            ("host_ip", 1) => Ok(IpTemplate::HostIp(network(&args)?)),
+            ("subnet", 1) => Ok(IpTemplate::Subnet(network(&args)?)),
+            ("cidr_offset", 2) => {
+                let offset = args[1]
+                    .parse()
+                    .map_err(|_| invalid("the offset must be a whole number"))?;
+                Ok(IpTemplate::CidrOffset(network(&args)?, offset))
+            }
+            ("host_ip" | "subnet", _) => Err(invalid("takes a network name")),
+            ("cidr_offset", _) => Err(invalid("takes a network name and an offset")),
+            _ => Err(invalid("expected host_ip, subnet or cidr_offset")),
+        }
+    }
+}
+
+impl Serialize for IpTemplate {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for IpTemplate {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An entry of `ips`: an address, range or network, or a template
+#[derive(Debug, Clone)]
+pub enum IpEntry {
+    Literal(AddrOrRange),
+    Template(IpTemplate),
+}
+
+impl<'de> Deserialize<'de> for IpEntry {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        if s.contains('(') {
+            s.parse()
+                .map(IpEntry::Template)
+                .map_err(serde::de::Error::custom)
+        } else {
+            s.parse()
+                .map(IpEntry::Literal)
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+/// Split `ips` into its addresses and its templates
+pub fn split_entries(entries: Vec<IpEntry>) -> (Vec<AddrOrRange>, Vec<IpTemplate>) {
+    let mut ips = Vec::new();
+    let mut templates = Vec::new();
+    for entry in entries {
+        match entry {
+            IpEntry::Literal(ip) => ips.push(ip),
+            IpEntry::Template(template) => templates.push(template),
+        }
+    }
+    (ips, templates)
+}
+
+/// Replace the templates of output rules with the addresses they stand for
+/// with `networks`
+pub fn expand_ip_templates(config: &mut Config, networks: &HashMap<String, NetworkGatewayInfo>) {
+    for (idx, rule) in config.output.iter_mut().enumerate() {
+        if rule.ip_templates.is_empty() {
+            continue;
+        }
+        for template in std::mem::take(&mut rule.ip_templates) {
+            match template.resolve(networks) {
+                Some(ip) => rule.ips.push(ip),
+                None => debug!(
+                    "Can't resolve {} for output rule {}: no IPv4 data for network '{}'",
+                    template,
+                    idx + 1,
+                    template.network()
+                ),
+            }
+        }
+        if rule.ips.is_empty() && !rule.host {
+            debug!(
+                "Output rule {} has no addresses left after templates, skipping",
+                idx + 1
+            );
+            rule.skip = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_ip_templates() {
+        let networks = HashMap::from([(
+            "backend".to_string(),
+            NetworkGatewayInfo {
+                network_id: "backend-id".to_string(),
+                network_name: "backend".to_string(),
+                gateway_ips: vec!["fd00::1".parse().unwrap(), "172.20.0.1".parse().unwrap()],
+                subnet: Some("fd00::/64".to_string()),
+                subnets: vec![
+                    "fd00::/64".parse().unwrap(),
+                    "172.20.0.0/16".parse().unwrap(),
+                ],
+            },
+        )]);
+        let mut config: Config = serde_yaml::from_str(
+            "output:\n  \
+             - {proto: udp, ips: [\"host_ip(backend)\", \"cidr_offset(backend, 53)\", 10.0.0.1], dst_ports: [53]}\n  \
+             - {proto: tcp, ips: [\"subnet(backend)\", \"cidr_offset(backend, -2)\"], dst_ports: [443]}\n  \
+             - {proto: tcp, ips: [\"host_ip(frontend)\"], dst_ports: [80]}\n  \
+             - {proto: tcp, ips: [\"cidr_offset(backend, 65536)\", 10.0.0.2], dst_ports: [80]}\n",
+        )
+        .unwrap();
+        // Kept as written until resolved
+        let written = serde_yaml::to_string(&config.output[0]).unwrap();
+        assert!(written.contains("cidr_offset(backend, 53)"), "{}", written);
+        let reparsed: super::super::RuleConfig = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(reparsed.ip_templates, config.output[0].ip_templates);
+
+        expand_ip_templates(&mut config, &networks);
+        let ips = |idx: usize| -> Vec<String> {
+            config.output[idx]
+                .ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect()
+        };
+        assert_eq!(ips(0), ["10.0.0.1", "172.20.0.1", "172.20.0.53"]);
+        assert_eq!(ips(1), ["172.20.0.0/16", "172.20.255.254"]);
+        assert!(config.output[2].skip);
+        assert_eq!(ips(3), ["10.0.0.2"]);
+        assert!(!config.output[3].skip);
+
+        for invalid in [
+            "host_ip()",
+            "cidr_offset(backend)",
+            "cidr_offset(backend, x)",
+            "gateway(a)",
+            "subnet(a",
+        ] {
+            assert!(invalid.parse::<IpTemplate>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Rules opening sensitive ports to the whole internet need
+//! `expose_to_internet: true`, so a template copied with `0.0.0.0/0` in it
+//! can't quietly let a container reach every SSH or database server, or
+//! every source reach the container's own. Since nftables refuses
+//! `0.0.0.0/0` itself, this catches what stands in for it: `ips` that
+//! between them cover a family, such as `::/0` or `0.0.0.0/1` with
+//! `128.0.0.0/1`, and rules that name no address at all.
+//!
+//! Output rules are checked with [`Config::check_exposure`] once the rules
+//! are read. `mapped_ports.external` admits sources to whichever ports the
+//! container publishes or forwards, so it is checked when the container's
+//! rules are built, and left out when it would expose one without the
+//! confirmation.
+//!
+//! The caller picks the ports; the daemon's `--sensitive-ports` and
+//! [`RuleSetBuilder`](crate::RuleSetBuilder) default to
+//! [`DEFAULT_SENSITIVE_PORTS`].
+
+use super::{AddrOrRange, Config, ExternalRules, RuleConfig};
+use std::net::IpAddr;
+
+/// Remote access, databases, caches and container runtimes
+pub const DEFAULT_SENSITIVE_PORTS: &[u16] = &[
+    22, 23, 445, 2375, 2376, 3306, 3389, 5432, 5900, 6379, 9200, 11211, 27017,
+];
+
+fn bits(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(u32::from(*ip)),
+        IpAddr::V6(ip) => u128::from(*ip),
+    }
+}
+
+/// Whether `ips` between them cover every address of a family, as `::/0`
+/// or `0.0.0.0/1` with `128.0.0.0/1` do
+fn covers_family(ips: &[AddrOrRange], ipv6: bool) -> bool {
+    let mut spans: Vec<(u128, u128)> = ips
+        .iter()
+        .filter(|ip| ip.is_ipv6() == ipv6)
+        .map(|ip| match ip {
+            AddrOrRange::Addr(addr) => (bits(addr), bits(addr)),
+            AddrOrRange::Range(start, end) => (bits(start), bits(end)),
+            AddrOrRange::Net(net) => (bits(&net.network()), bits(&net.broadcast())),
+        })
+        .collect();
+    spans.sort_unstable();
+    let last = if ipv6 {
+        u128::MAX
+    } else {
+        u128::from(u32::MAX)
+    };
+    // The first address not covered yet
+    let mut next = 0;
+    for (start, end) in spans {
+        if start > next {
+            return false;
+        }
+        if end >= last {
+            return true;
+        }
+        next = next.max(end + 1);
+    }
+    false
+}
+
+fn covers_any_family(ips: &[AddrOrRange]) -> bool {
+    covers_family(ips, false) || covers_family(ips, true)
+}
+
+/// Whether the addresses of `rule` take in the whole internet
+fn world(rule: &RuleConfig) -> bool {
+    let unaddressed = rule.ips.is_empty()
+        && rule.ip_templates.is_empty()
+        && !rule.host
+        && rule.hostname.is_none()
+        && rule.container.is_empty()
+        && rule.from.is_none();
+    unaddressed || covers_any_family(&rule.ips)
+}
+
+/// The ports of `sensitive` that `rule` opens to the whole internet; all
+/// of them when it matches any port
+pub fn exposed_ports(rule: &RuleConfig, sensitive: &[u16]) -> Vec<u16> {
+    if rule.proto.is_icmp() || !world(rule) {
+        return Vec::new();
+    }
+    sensitive
+        .iter()
+        .copied()
+        .filter(|port| {
+            rule.dst_ports.is_empty() || rule.dst_ports.iter().any(|ports| ports.contains(*port))
+        })
+        .collect()
+}
+
+/// The ports of `sensitive` among `ports` that `external` admits every
+/// source to
+pub fn external_exposed_ports(
+    external: &ExternalRules,
+    ports: &[u16],
+    sensitive: &[u16],
+) -> Vec<u16> {
+    let unaddressed = external.ips.is_empty() && !external.host && external.rdns.is_empty();
+    if !external.allow || !(unaddressed || covers_any_family(&external.ips)) {
+        return Vec::new();
+    }
+    sensitive
+        .iter()
+        .copied()
+        .filter(|port| ports.contains(port))
+        .collect()
+}
+
+/// Stop `config` admitting every source when it would expose one of
+/// `ports` that is in `sensitive` unconfirmed, returning those ports
+pub fn withhold_external(config: &mut Config, ports: &[u16], sensitive: &[u16]) -> Vec<u16> {
+    let external = &mut config.mapped_ports.external;
+    if external.expose_to_internet {
+        return Vec::new();
+    }
+    let exposed = external_exposed_ports(external, ports, sensitive);
+    if !exposed.is_empty() {
+        external.allow = false;
+    }
+    exposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_exposed_ports() {
+        let rule = |yaml: &str| -> RuleConfig { serde_yaml::from_str(yaml).unwrap() };
+        let sensitive = [22, 5432];
+
+        let ssh = rule("{proto: tcp, ips: [0.0.0.0/1, 128.0.0.0/1], dst_ports: [22, 443]}");
+        assert_eq!(exposed_ports(&ssh, &sensitive), [22]);
+        let range = rule("{proto: tcp, ips: [\"::/0\"], dst_ports: [\"5000-6000\"]}");
+        assert_eq!(exposed_ports(&range, &sensitive), [5432]);
+        let anywhere =
+            rule("{proto: udp, ips: [0.0.0.0-255.255.255.255], dst_ports: [\"1-65535\"]}");
+        assert_eq!(exposed_ports(&anywhere, &sensitive), [22, 5432]);
+        let unset = rule("{proto: tcp, dst_ports: [22]}");
+        assert_eq!(exposed_ports(&unset, &sensitive), [22]);
+        let named = rule("{proto: tcp, host: git.example.com, dst_ports: [22]}");
+        assert!(exposed_ports(&named, &sensitive).is_empty());
+        let lan = rule("{proto: tcp, ips: [10.0.0.0/8, 0.0.0.0-9.255.255.255], dst_ports: [22]}");
+        assert!(exposed_ports(&lan, &sensitive).is_empty());
+        let https = rule("{proto: tcp, ips: [0.0.0.0-255.255.255.255], dst_ports: [443]}");
+        assert!(exposed_ports(&https, &sensitive).is_empty());
+
+        let config = |yaml: &str| serde_yaml::from_str::<Config>(yaml);
+        let err = config("output:\n  - {proto: tcp, ips: [\"::/0\"], dst_ports: [22]}\n")
+            .unwrap()
+            .check_exposure(&sensitive)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("expose_to_internet"), "{}", err);
+        config(
+            "output:\n  - {proto: tcp, ips: [\"::/0\"], dst_ports: [22], expose_to_internet: true}\n",
+        )
+        .unwrap()
+        .check_exposure(&sensitive)
+        .unwrap();
+        let external = |yaml: &str| -> ExternalRules { serde_yaml::from_str(yaml).unwrap() };
+        let published = [22, 8080];
+        let open = external("{allow: true}");
+        assert_eq!(external_exposed_ports(&open, &published, &sensitive), [22]);
+        let wide = external("{allow: true, ips: [\"::/0\"]}");
+        assert_eq!(external_exposed_ports(&wide, &published, &sensitive), [22]);
+        let office = external("{allow: true, ips: [192.0.2.0/24]}");
+        assert!(external_exposed_ports(&office, &published, &sensitive).is_empty());
+        let verified = external("{allow: true, rdns: [\"*.example.com\"]}");
+        assert!(external_exposed_ports(&verified, &published, &sensitive).is_empty());
+        assert!(external_exposed_ports(&open, &[8080], &sensitive).is_empty());
+
+        let mut open = config("mapped_ports:\n  external:\n    allow: true\n").unwrap();
+        assert_eq!(withhold_external(&mut open, &[22], &sensitive), [22]);
+        assert!(!open.mapped_ports.external.allow);
+        let mut confirmed =
+            config("mapped_ports:\n  external:\n    allow: true\n    expose_to_internet: true\n")
+                .unwrap();
+        assert!(withhold_external(&mut confirmed, &[22], &sensitive).is_empty());
+        assert!(confirmed.mapped_ports.external.allow);
+    }
+}
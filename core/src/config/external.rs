@@ -0,0 +1,380 @@
+use crate::Result;
+use crate::config::ToNftablesRule;
+use crate::dns::rdns::{
+    DEFAULT_RDNS_NEGATIVE_TTL, DEFAULT_RDNS_TTL, HostnamePattern, RdnsFailurePolicy,
+};
+use crate::dns::{LookupFamily, LookupPolicy};
+use bon::Builder;
+use nftables::expr::{Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField, SetItem};
+use nftables::stmt::{Match, Operator, Statement};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Serialize, Builder)]
+pub struct ExternalRules {
+    #[serde(default)]
+    #[builder(default)]
+    pub allow: bool,
+    #[serde(default)]
+    #[builder(default)]
+    pub log_prefix: String,
+    #[serde(default)]
+    #[builder(default)]
+    pub ips: Vec<super::AddrOrRange>,
+    /// Also allow the host's own addresses, kept current across address
+    /// changes such as DHCP lease renewals
+    #[serde(default)]
+    #[builder(default)]
+    pub host: bool,
+    #[serde(default)]
+    #[builder(default)]
+    pub verdict: super::ConfigVerdict,
+    /// Hostname patterns whose sources are allowed after forward-confirmed
+    /// reverse DNS verification
+    #[serde(default)]
+    #[builder(default)]
+    pub rdns: Vec<HostnamePattern>,
+    /// Seconds a verified source stays allowed before it is re-verified
+    #[serde(default = "default_rdns_ttl")]
+    #[builder(default = DEFAULT_RDNS_TTL)]
+    pub rdns_ttl: u32,
+    /// Seconds a source that failed verification is refused before it is
+    /// checked again
+    #[serde(default = "default_rdns_negative_ttl")]
+    #[builder(default = DEFAULT_RDNS_NEGATIVE_TTL)]
+    pub rdns_negative_ttl: u32,
+    /// What happens to sources while their DNS lookups fail
+    #[serde(default)]
+    #[builder(default)]
+    pub rdns_on_failure: RdnsFailurePolicy,
+    /// Records the forward lookup of a verified hostname asks for, instead
+    /// of `--dns-family`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rdns_family: Option<LookupFamily>,
+    /// Whether both of them must resolve, instead of `--dns-require-both`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rdns_require_both: Option<bool>,
+    /// Only allow external access inside this window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<super::TimeWindow>,
+    /// Host interfaces external traffic may arrive on, matched as one
+    /// interface set; any interface when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub iif_in: Vec<String>,
+    /// Rate external traffic is allowed at; what exceeds it is dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<super::RateLimit>,
+    /// Connections a single source may hold open to each mapped port; new
+    /// ones past it are dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections_per_ip: Option<u32>,
+    /// Confirms admitting every source to `--sensitive-ports` is meant
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub expose_to_internet: bool,
+}
+
+fn default_rdns_ttl() -> u32 {
+    DEFAULT_RDNS_TTL
+}
+
+fn default_rdns_negative_ttl() -> u32 {
+    DEFAULT_RDNS_NEGATIVE_TTL
+}
+
+impl Default for ExternalRules {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl ExternalRules {
+    /// Sources are only allowed through rdns verification, so the plain
+    /// allow-from-anywhere rule must not be emitted
+    pub fn rdns_only(&self) -> bool {
+        self.ips.is_empty() && !self.host && !self.rdns.is_empty()
+    }
+
+    /// How verified hostnames are resolved, the rule's own settings over
+    /// `default`
+    pub fn rdns_lookup(&self, default: LookupPolicy) -> LookupPolicy {
+        LookupPolicy {
+            family: self.rdns_family.unwrap_or(default.family),
+            require_both: self.rdns_require_both.unwrap_or(default.require_both),
+        }
+    }
+
+    /// `iifname` matching `iif_in`, as an anonymous set when there are
+    /// several
+    pub fn match_iif(&self) -> Option<Statement<'static>> {
+        let mut names: Vec<Expression<'static>> = self
+            .iif_in
+            .iter()
+            .map(|name| Expression::String(Cow::Owned(name.clone())))
+            .collect();
+        let right = match names.len() {
+            0 => return None,
+            1 => names.remove(0),
+            _ => Expression::Named(NamedExpression::Set(
+                names.into_iter().map(SetItem::Element).collect(),
+            )),
+        };
+        Some(Statement::Match(Match {
+            left: Expression::Named(NamedExpression::Meta(Meta {
+                key: MetaKey::Iifname,
+            })),
+            right,
+            op: Operator::EQ,
+        }))
+    }
+}
+
+impl ExternalRules {
+    /// `limit rate` for `limit`, placed before the counter so it counts
+    /// only what is let through
+    pub fn match_limit(&self) -> Option<Statement<'static>> {
+        self.limit.as_ref().map(super::RateLimit::statement)
+    }
+}
+
+/// Interface names the kernel accepts: 1 to 15 bytes, without whitespace,
+/// `/` or wildcards
+fn valid_interface(name: &str) -> bool {
+    (1..16).contains(&name.len())
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '/' | '*' | '"' | ':'))
+}
+
+// Custom Deserialize for ExternalRules with validation
+impl<'de> Deserialize<'de> for ExternalRules {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct TempExternalRules {
+            #[serde(default)]
+            allow: bool,
+            #[serde(default)]
+            log_prefix: String,
+            #[serde(default)]
+            ips: Vec<super::AddrOrRange>,
+            #[serde(default)]
+            host: bool,
+            #[serde(default)]
+            verdict: super::ConfigVerdict,
+            #[serde(default)]
+            rdns: Vec<HostnamePattern>,
+            #[serde(default = "default_rdns_ttl")]
+            rdns_ttl: u32,
+            #[serde(default = "default_rdns_negative_ttl")]
+            rdns_negative_ttl: u32,
+            #[serde(default)]
+            rdns_on_failure: RdnsFailurePolicy,
+            #[serde(default)]
+            rdns_family: Option<LookupFamily>,
+            #[serde(default)]
+            rdns_require_both: Option<bool>,
+            #[serde(default)]
+            time: Option<super::TimeWindow>,
+            #[serde(default)]
+            iif_in: Vec<String>,
+            #[serde(default)]
+            limit: Option<super::RateLimit>,
+            #[serde(default)]
+            max_connections_per_ip: Option<u32>,
+            #[serde(default)]
+            expose_to_internet: bool,
+        }
+
+        let mut temp = TempExternalRules::deserialize(deserializer)?;
+
+        // Validate log prefix if present
+        if temp.allow && !temp.log_prefix.is_empty() && temp.log_prefix.len() > 64 {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "log_prefix".to_string(),
+                    reason: "Log prefix too long (max 64 characters)".to_string(),
+                    value: temp.log_prefix.clone(),
+                    expected_format: Some("String with max 64 characters".to_string()),
+                },
+            ));
+        }
+
+        if !temp.rdns.is_empty() && temp.rdns_ttl == 0 {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "rdns_ttl".to_string(),
+                    reason: "Verified sources need a non-zero lifetime".to_string(),
+                    value: temp.rdns_ttl.to_string(),
+                    expected_format: Some("Number of seconds greater than 0".to_string()),
+                },
+            ));
+        }
+
+        if let Some(name) = temp.iif_in.iter().find(|name| !valid_interface(name)) {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "iif_in".to_string(),
+                    reason: "not an interface name".to_string(),
+                    value: name.clone(),
+                    expected_format: Some(
+                        "Interface names of up to 15 characters such as 'eth0'".to_string(),
+                    ),
+                },
+            ));
+        }
+        if temp.max_connections_per_ip == Some(0) {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "max_connections_per_ip".to_string(),
+                    reason: "would refuse every connection".to_string(),
+                    value: "0".to_string(),
+                    expected_format: Some("Number of connections greater than 0".to_string()),
+                },
+            ));
+        }
+
+        temp.iif_in.sort();
+        temp.iif_in.dedup();
+
+        Ok(ExternalRules {
+            allow: temp.allow,
+            log_prefix: temp.log_prefix,
+            ips: temp.ips,
+            host: temp.host,
+            verdict: temp.verdict,
+            rdns: temp.rdns,
+            rdns_ttl: temp.rdns_ttl,
+            rdns_negative_ttl: temp.rdns_negative_ttl,
+            rdns_on_failure: temp.rdns_on_failure,
+            rdns_family: temp.rdns_family,
+            rdns_require_both: temp.rdns_require_both,
+            time: temp.time,
+            iif_in: temp.iif_in,
+            limit: temp.limit,
+            max_connections_per_ip: temp.max_connections_per_ip,
+            expose_to_internet: temp.expose_to_internet,
+        })
+    }
+}
+
+/// Implementation for ExternalRules (external mapped ports)
+impl ToNftablesRule for ExternalRules {
+    fn to_nftables_statements(&self) -> Result<Vec<Statement<'static>>> {
+        let mut statements: Vec<Statement<'static>> = self.match_iif().into_iter().collect();
+
+        // Match source IPs if specified
+        if !self.ips.is_empty() {
+            // Create a set expression for multiple IPs
+            let mut ip_exprs = Vec::new();
+
+            for addr_or_range in &self.ips {
+                match addr_or_range {
+                    super::AddrOrRange::Addr(ip) => {
+                        ip_exprs.push(Expression::String(Cow::Owned(ip.to_string())));
+                    }
+                    super::AddrOrRange::Range(start, end) => {
+                        ip_exprs.push(Expression::Range(Box::new(nftables::expr::Range {
+                            range: [
+                                Expression::String(Cow::Owned(start.to_string())),
+                                Expression::String(Cow::Owned(end.to_string())),
+                            ],
+                        })));
+                    }
+                    super::AddrOrRange::Net(net) => {
+                        // Parse CIDR notation to create a prefix expression
+                        let addr = net.addr();
+                        let prefix_len = net.prefix_len() as u32;
+
+                        ip_exprs.push(Expression::Named(NamedExpression::Prefix(
+                            nftables::expr::Prefix {
+                                addr: Box::new(Expression::String(Cow::Owned(addr.to_string()))),
+                                len: prefix_len,
+                            },
+                        )));
+                    }
+                }
+            }
+
+            // Determine protocol based on first IP
+            let protocol = match self.ips.first() {
+                Some(super::AddrOrRange::Addr(ip)) => {
+                    if ip.is_ipv4() {
+                        "ip"
+                    } else {
+                        "ip6"
+                    }
+                }
+                Some(super::AddrOrRange::Range(start, _)) => {
+                    if start.is_ipv4() {
+                        "ip"
+                    } else {
+                        "ip6"
+                    }
+                }
+                Some(super::AddrOrRange::Net(net)) => {
+                    if net.addr().is_ipv4() {
+                        "ip"
+                    } else {
+                        "ip6"
+                    }
+                }
+                _ => "ip",
+            };
+
+            if ip_exprs.len() == 1 {
+                // Single IP/range/prefix - use direct match
+                statements.push(Statement::Match(Match {
+                    left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                        PayloadField {
+                            protocol: Cow::Borrowed(protocol),
+                            field: Cow::Borrowed("saddr"),
+                        },
+                    ))),
+                    right: ip_exprs.into_iter().next().unwrap(),
+                    op: Operator::EQ,
+                }));
+            } else {
+                // Multiple IPs - use anonymous set
+                let set_items: Vec<nftables::expr::SetItem> = ip_exprs
+                    .into_iter()
+                    .map(nftables::expr::SetItem::Element)
+                    .collect();
+
+                statements.push(Statement::Match(Match {
+                    left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                        PayloadField {
+                            protocol: Cow::Borrowed(protocol),
+                            field: Cow::Borrowed("saddr"),
+                        },
+                    ))),
+                    right: Expression::Named(NamedExpression::Set(set_items)),
+                    op: Operator::EQ,
+                }));
+            }
+        }
+
+        statements.extend(self.match_limit());
+
+        // Add counter
+        statements.push(Self::counter_statement());
+
+        // Add log if configured
+        if !self.log_prefix.is_empty() {
+            statements.push(Self::log_statement(Some(&self.log_prefix)));
+        }
+
+        // Add verdict
+        let verdict = if self.allow {
+            Self::verdict_to_statement(&self.verdict)
+        } else {
+            Statement::Drop(None)
+        };
+        statements.push(verdict);
+
+        Ok(statements)
+    }
+}
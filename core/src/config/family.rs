@@ -0,0 +1,121 @@
+use super::{AddrOrRange, Config, Protocol};
+use crate::dns::{LookupFamily, LookupPolicy};
+use nftables::types::NfFamily;
+
+impl AddrOrRange {
+    pub fn is_ipv6(&self) -> bool {
+        match self {
+            AddrOrRange::Addr(addr) => addr.is_ipv6(),
+            AddrOrRange::Range(start, _) => start.is_ipv6(),
+            AddrOrRange::Net(net) => net.addr().is_ipv6(),
+        }
+    }
+}
+
+/// Keep the addresses of one family; `None` when there were addresses and
+/// none of them are left, so the rule must not apply at all
+fn retain_family(ips: &mut Vec<AddrOrRange>, ipv6: bool) -> Option<()> {
+    let had_ips = !ips.is_empty();
+    ips.retain(|ip| ip.is_ipv6() == ipv6);
+    (!had_ips || !ips.is_empty()).then_some(())
+}
+
+impl Config {
+    /// The rules as they apply in a `family` table. Rules with addresses
+    /// only keep those of that family, and are left out when none remain;
+    /// rules without addresses apply in both, unless they resolve a hostname
+    /// to the other family only (under `dns` unless they choose their own
+    /// policy). Reverse DNS verification only feeds IPv4 sets, so IPv6
+    /// sources it would admit stay dropped, and raw rules are written for
+    /// the IPv4 chain only
+    pub fn for_family(&self, family: NfFamily, dns: LookupPolicy) -> Config {
+        let ipv6 = family == NfFamily::IP6;
+        let mut config = self.clone();
+        if ipv6 {
+            config.raw_rules.clear();
+        }
+
+        let external = &mut config.mapped_ports.external;
+        if retain_family(&mut external.ips, ipv6).is_none() {
+            external.allow = false;
+        }
+        if ipv6 && !external.rdns.is_empty() {
+            if external.ips.is_empty() && !external.host {
+                external.allow = false;
+            }
+            external.rdns.clear();
+        }
+
+        let other = if ipv6 {
+            LookupFamily::A
+        } else {
+            LookupFamily::Aaaa
+        };
+        for rule in config.output.iter_mut() {
+            if retain_family(&mut rule.ips, ipv6).is_none() {
+                rule.skip = true;
+            }
+            // Nor when the network gives the container no address of it
+            let attached = !rule.attachment.is_empty();
+            rule.attachment.retain(|ip| ip.is_ipv6() == ipv6);
+            if attached && rule.attachment.is_empty() {
+                rule.skip = true;
+            }
+            // ICMP is IPv4 only, ICMPv6 IPv6 only
+            if rule.proto.is_icmp() && (rule.proto == Protocol::Icmpv6) != ipv6 {
+                rule.skip = true;
+            }
+            // A name resolved to the other family only matches nothing here
+            if rule.hostname.is_some() && rule.dns_lookup(dns).family == other {
+                rule.skip = true;
+            }
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_family() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+mapped_ports:
+  external:
+    allow: true
+    ips: [192.168.1.0/24]
+output:
+  - ips: ["fd00::1"]
+    proto: tcp
+    dst_ports: [443]
+  - ips: [10.0.0.2]
+    proto: tcp
+    dst_ports: [5432]
+  - proto: udp
+    dst_ports: [53]
+  - host: api.stripe.com
+    dns_family: aaaa
+    proto: tcp
+    dst_ports: [443]
+"#,
+        )
+        .unwrap();
+
+        let v4 = config.for_family(NfFamily::IP, LookupPolicy::default());
+        assert!(v4.mapped_ports.external.allow);
+        assert!(v4.output[0].skip);
+        assert!(!v4.output[1].skip);
+        assert!(!v4.output[2].skip);
+        assert!(v4.output[3].skip);
+
+        let v6 = config.for_family(NfFamily::IP6, LookupPolicy::default());
+        assert!(!v6.mapped_ports.external.allow);
+        assert_eq!(v6.output[0].ips[0].to_string(), "fd00::1");
+        assert!(!v6.output[0].skip);
+        assert!(v6.output[1].skip);
+        assert!(!v6.output[2].skip);
+        assert!(!v6.output[3].skip);
+    }
+}
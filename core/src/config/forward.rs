@@ -0,0 +1,52 @@
+use super::Protocol;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+
+/// A host port forwarded to the container by harborshield instead of
+/// docker-proxy, for containers run without published ports:
+///
+/// ```yaml
+/// forward:
+///   - host_port: 8080
+///     port: 80
+///   - host_port: 5353
+///     proto: udp
+///     host_ip: 192.0.2.10
+/// ```
+///
+/// Forwarded ports are filtered like published ones, by `mapped_ports`.
+/// See `harborshield::nftables::forward`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwardRule {
+    pub host_port: u16,
+    /// Container port; the host port when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default = "default_proto")]
+    pub proto: Protocol,
+    /// Only forward connections to this host address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_ip: Option<Ipv4Addr>,
+    /// Also forward connections from the host's loopback and from the
+    /// container to itself, masquerading them so replies come back through
+    /// the host
+    #[serde(default = "super::default_true")]
+    pub hairpin: bool,
+}
+
+fn default_proto() -> Protocol {
+    Protocol::Tcp
+}
+
+impl ForwardRule {
+    pub fn container_port(&self) -> u16 {
+        self.port.unwrap_or(self.host_port)
+    }
+
+    /// Whether both forward connections to the same host port
+    pub fn overlaps(&self, other: &ForwardRule) -> bool {
+        self.host_port == other.host_port
+            && self.proto == other.proto
+            && (self.host_ip.is_none() || other.host_ip.is_none() || self.host_ip == other.host_ip)
+    }
+}
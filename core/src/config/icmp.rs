@@ -0,0 +1,90 @@
+//! ICMP message types for `icmp_types` on `proto: icmp` and `icmpv6` rules,
+//! by the names nftables gives them.
+
+use super::Protocol;
+
+const ICMP_TYPES: &[&str] = &[
+    "echo-reply",
+    "destination-unreachable",
+    "source-quench",
+    "redirect",
+    "echo-request",
+    "router-advertisement",
+    "router-solicitation",
+    "time-exceeded",
+    "parameter-problem",
+    "timestamp-request",
+    "timestamp-reply",
+    "info-request",
+    "info-reply",
+    "address-mask-request",
+    "address-mask-reply",
+];
+
+const ICMPV6_TYPES: &[&str] = &[
+    "destination-unreachable",
+    "packet-too-big",
+    "time-exceeded",
+    "parameter-problem",
+    "echo-request",
+    "echo-reply",
+    "mld-listener-query",
+    "mld-listener-report",
+    "mld-listener-done",
+    "nd-router-solicit",
+    "nd-router-advert",
+    "nd-neighbor-solicit",
+    "nd-neighbor-advert",
+    "nd-redirect",
+    "router-renumbering",
+    "ind-neighbor-solicit",
+    "ind-neighbor-advert",
+    "mld2-listener-report",
+];
+
+/// The type names `proto` knows, empty for protocols without types
+pub fn known_types(proto: Protocol) -> &'static [&'static str] {
+    match proto {
+        Protocol::Icmp => ICMP_TYPES,
+        Protocol::Icmpv6 => ICMPV6_TYPES,
+        Protocol::Tcp | Protocol::Udp => &[],
+    }
+}
+
+/// The first of `types` that isn't a `proto` message type
+pub fn unknown_type(proto: Protocol, types: &[String]) -> Option<&String> {
+    let known = known_types(proto);
+    types.iter().find(|name| !known.contains(&name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_type() {
+        let types = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            unknown_type(
+                Protocol::Icmp,
+                &types(&["echo-request", "destination-unreachable"])
+            ),
+            None
+        );
+        assert_eq!(
+            unknown_type(
+                Protocol::Icmp,
+                &types(&["echo-request", "nd-neighbor-solicit"])
+            ),
+            Some(&"nd-neighbor-solicit".to_string())
+        );
+        assert_eq!(
+            unknown_type(Protocol::Icmpv6, &types(&["nd-neighbor-solicit"])),
+            None
+        );
+        assert_eq!(
+            unknown_type(Protocol::Tcp, &types(&["echo-request"])),
+            Some(&"echo-request".to_string())
+        );
+    }
+}
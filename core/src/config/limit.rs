@@ -0,0 +1,155 @@
+use nftables::stmt::{Limit, Statement};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// What a rate counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateUnit {
+    Packets,
+    Bytes,
+    KBytes,
+    MBytes,
+}
+
+impl RateUnit {
+    fn as_str(self) -> &'static str {
+        match self {
+            RateUnit::Packets => "packets",
+            RateUnit::Bytes => "bytes",
+            RateUnit::KBytes => "kbytes",
+            RateUnit::MBytes => "mbytes",
+        }
+    }
+}
+
+/// A rate such as `50/second` or `2 mbytes/second`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub amount: u32,
+    pub unit: RateUnit,
+    /// `second`, `minute`, `hour`, `day` or `week`
+    pub per: &'static str,
+}
+
+const PERIODS: [&str; 5] = ["second", "minute", "hour", "day", "week"];
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid rate '{}', expected <count>[ bytes|kbytes|mbytes]/<second|minute|hour|day|week>",
+                s
+            )
+        };
+        let (amount, per) = s.split_once('/').ok_or_else(invalid)?;
+        let per = PERIODS
+            .into_iter()
+            .find(|period| *period == per.trim())
+            .ok_or_else(invalid)?;
+        let (amount, unit) = match amount.trim().split_once(' ') {
+            Some((amount, unit)) => {
+                let unit = [RateUnit::Bytes, RateUnit::KBytes, RateUnit::MBytes]
+                    .into_iter()
+                    .find(|u| u.as_str() == unit.trim())
+                    .ok_or_else(invalid)?;
+                (amount, unit)
+            }
+            None => (amount.trim(), RateUnit::Packets),
+        };
+        let amount: u32 = amount.parse().map_err(|_| invalid())?;
+        if amount == 0 {
+            return Err(format!(
+                "Invalid rate '{}': the count must be at least 1",
+                s
+            ));
+        }
+        Ok(Self { amount, unit, per })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unit {
+            RateUnit::Packets => write!(f, "{}/{}", self.amount, self.per),
+            unit => write!(f, "{} {}/{}", self.amount, unit.as_str(), self.per),
+        }
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Traffic allowed through a rule, as `limit: { rate: 50/second, burst: 100 }`.
+/// Traffic over the rate doesn't match the rule and falls through to the
+/// rules after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub rate: Rate,
+    /// Packets, or units of a byte rate, allowed over the rate at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn statement(&self) -> Statement<'static> {
+        let bytes = self.rate.unit != RateUnit::Packets;
+        Statement::Limit(Limit {
+            rate: self.rate.amount,
+            rate_unit: bytes.then(|| Cow::Borrowed(self.rate.unit.as_str())),
+            per: Some(Cow::Borrowed(self.rate.per)),
+            burst: self.burst,
+            burst_unit: (bytes && self.burst.is_some())
+                .then(|| Cow::Borrowed(self.rate.unit.as_str())),
+            inv: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let limit: RateLimit = serde_yaml::from_str("{rate: 50/second, burst: 100}").unwrap();
+        assert_eq!(limit.rate.to_string(), "50/second");
+        assert_eq!(
+            serde_json::to_value(limit.statement()).unwrap(),
+            serde_json::json!({"limit": {"rate": 50, "per": "second", "burst": 100}})
+        );
+
+        let limit: RateLimit = serde_yaml::from_str("rate: 2 mbytes/minute").unwrap();
+        assert_eq!(limit.rate.to_string(), "2 mbytes/minute");
+        assert_eq!(
+            serde_json::to_value(limit.statement()).unwrap(),
+            serde_json::json!({"limit": {"rate": 2, "rate_unit": "mbytes", "per": "minute"}})
+        );
+
+        for invalid in [
+            "50",
+            "0/second",
+            "50/fortnight",
+            "5 gbytes/second",
+            "x/hour",
+        ] {
+            assert!(invalid.parse::<Rate>().is_err(), "{}", invalid);
+        }
+        assert!(serde_yaml::from_str::<RateLimit>("{rate: 5/second, brust: 1}").is_err());
+    }
+}
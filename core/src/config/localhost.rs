@@ -1,5 +1,5 @@
 use crate::Result;
-use crate::docker::config::ToNftablesRule;
+use crate::config::ToNftablesRule;
 use bon::Builder;
 use nftables::stmt::Statement;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -46,17 +46,15 @@ impl<'de> Deserialize<'de> for LocalRules {
         let temp = TempLocalRules::deserialize(deserializer)?;
 
         // Validate log prefix if present
-        if temp.allow && !temp.log_prefix.is_empty() {
-            if temp.log_prefix.len() > 64 {
-                return Err(serde::de::Error::custom(
-                    super::ValidationError::InvalidFieldValue {
-                        field: "log_prefix".to_string(),
-                        reason: "Log prefix too long (max 64 characters)".to_string(),
-                        value: temp.log_prefix.clone(),
-                        expected_format: Some("String with max 64 characters".to_string()),
-                    },
-                ));
-            }
+        if temp.allow && !temp.log_prefix.is_empty() && temp.log_prefix.len() > 64 {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "log_prefix".to_string(),
+                    reason: "Log prefix too long (max 64 characters)".to_string(),
+                    value: temp.log_prefix.clone(),
+                    expected_format: Some("String with max 64 characters".to_string()),
+                },
+            ));
         }
 
         Ok(LocalRules {
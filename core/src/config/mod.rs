@@ -0,0 +1,688 @@
+mod cidr;
+pub mod exposure;
+mod external;
+mod family;
+mod forward;
+mod icmp;
+mod limit;
+mod localhost;
+pub mod nftables_convert;
+pub mod profiles;
+mod quarantine;
+mod rule;
+mod service_ref;
+mod services;
+pub mod templates;
+#[cfg(test)]
+mod tests;
+mod time_window;
+mod tproxy;
+pub mod validation;
+mod verdict;
+
+use crate::{Error, Result};
+use bon::Builder;
+pub use cidr::{IpTemplate, expand_ip_templates};
+pub use external::ExternalRules;
+pub use forward::ForwardRule;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+pub use limit::{Rate, RateLimit, RateUnit};
+pub use localhost::LocalRules;
+pub use nftables_convert::{RuleContext, ToNftablesRule};
+pub use quarantine::{DEFAULT_QUARANTINE_WINDOW, QuarantinePolicy};
+pub use rule::RuleConfig;
+use serde::{Deserialize, Deserializer, Serialize};
+pub use service_ref::ServiceRef;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+pub use time_window::{Day, TimeOfDay, TimeWindow};
+pub use tproxy::Tproxy;
+use validation::error::ValidationError;
+pub use verdict::ConfigVerdict;
+
+#[derive(Debug, Clone, Serialize, Builder)]
+pub struct Config {
+    #[serde(default)]
+    #[builder(default)]
+    pub mapped_ports: MappedPorts,
+    #[serde(default)]
+    #[builder(default)]
+    pub output: Vec<RuleConfig>,
+    /// Range the container's addresses must fall in for allow rules to be
+    /// applied, catching a container attached to the wrong network
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_subnet"
+    )]
+    pub expected_subnet: Option<IpNet>,
+    /// Allow traffic to the servers behind NFS and SMB volumes the
+    /// container mounts
+    #[serde(default)]
+    #[builder(default)]
+    pub storage_egress: bool,
+    /// Quarantine the container once its drops pass a threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<QuarantinePolicy>,
+    /// nftables rule expressions put at the head of the container's IPv4
+    /// chain as written, such as `meta l4proto gre accept`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub raw_rules: Vec<String>,
+    /// Host ports DNATed to the container, in place of published ports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub forward: Vec<ForwardRule>,
+}
+
+fn serialize_subnet<S>(
+    subnet: &Option<IpNet>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match subnet {
+        Some(net) => serializer.collect_str(net),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Builder)]
+pub struct MappedPorts {
+    #[serde(default)]
+    #[builder(default)]
+    pub localhost: LocalRules,
+    #[serde(default)]
+    #[builder(default)]
+    pub external: ExternalRules,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+pub enum AddrOrRange {
+    Addr(IpAddr),
+    Range(IpAddr, IpAddr),
+    Net(IpNet),
+}
+
+impl AddrOrRange {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match self {
+            AddrOrRange::Addr(addr) => addr == ip,
+            AddrOrRange::Range(start, end) => {
+                start.is_ipv4() == ip.is_ipv4() && start <= ip && ip <= end
+            }
+            AddrOrRange::Net(net) => net.contains(ip),
+        }
+    }
+}
+
+impl Serialize for AddrOrRange {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AddrOrRange {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for AddrOrRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrOrRange::Addr(addr) => write!(f, "{}", addr),
+            AddrOrRange::Range(start, end) => write!(f, "{}-{}", start, end),
+            AddrOrRange::Net(net) => write!(f, "{}", net),
+        }
+    }
+}
+
+impl FromStr for AddrOrRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Check for invalid "0.0.0.0/0" which causes nftables to fail
+        if s == "0.0.0.0/0" {
+            return Err(Error::invalid_ip(
+                s,
+                "0.0.0.0/0 is not supported by nftables. Use specific IP addresses or ranges instead",
+            ));
+        }
+
+        if s.contains('/') {
+            // CIDR notation
+            if let Ok(net) = s.parse::<Ipv4Net>() {
+                return Ok(AddrOrRange::Net(IpNet::V4(net)));
+            }
+            if let Ok(net) = s.parse::<Ipv6Net>() {
+                return Ok(AddrOrRange::Net(IpNet::V6(net)));
+            }
+            return Err(Error::invalid_ip(s, "Invalid CIDR notation"));
+        } else if s.contains('-') {
+            // Range notation
+            let parts: Vec<&str> = s.split('-').collect();
+            if parts.len() != 2 {
+                return Err(Error::invalid_ip(
+                    s,
+                    "Range must have exactly two parts separated by '-'",
+                ));
+            }
+
+            let start = parts[0]
+                .parse::<IpAddr>()
+                .map_err(|_| Error::invalid_ip(parts[0], "Invalid IP address in range start"))?;
+            let end = parts[1]
+                .parse::<IpAddr>()
+                .map_err(|_| Error::invalid_ip(parts[1], "Invalid IP address in range end"))?;
+
+            return Ok(AddrOrRange::Range(start, end));
+        }
+
+        // Single address
+        s.parse::<IpAddr>()
+            .map(AddrOrRange::Addr)
+            .map_err(|_| Error::invalid_ip(s, "Invalid IP address"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[serde(rename = "tcp")]
+    Tcp,
+    #[serde(rename = "udp")]
+    Udp,
+    #[serde(rename = "icmp")]
+    Icmp,
+    #[serde(rename = "icmpv6")]
+    Icmpv6,
+}
+
+impl Protocol {
+    /// Whether the protocol has message types instead of ports
+    pub fn is_icmp(self) -> bool {
+        matches!(self, Protocol::Icmp | Protocol::Icmpv6)
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+            Protocol::Icmp => write!(f, "icmp"),
+            Protocol::Icmpv6 => write!(f, "icmpv6"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RulePorts {
+    Single(u16),
+    Range(u16, u16),
+}
+
+impl RulePorts {
+    pub fn contains(&self, port: u16) -> bool {
+        match self {
+            RulePorts::Single(single) => *single == port,
+            RulePorts::Range(start, end) => (*start..=*end).contains(&port),
+        }
+    }
+}
+
+impl Serialize for RulePorts {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RulePorts {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for RulePorts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulePorts::Single(port) => write!(f, "{}", port),
+            RulePorts::Range(start, end) => write!(f, "{}-{}", start, end),
+        }
+    }
+}
+
+impl FromStr for RulePorts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.contains('-') {
+            let parts: Vec<&str> = s.split('-').collect();
+            if parts.len() != 2 {
+                return Err(Error::config(format!("Invalid port range: {}", s)));
+            }
+
+            let start = parts[0]
+                .parse::<u16>()
+                .map_err(|_| Error::config(format!("Invalid port: {}", parts[0])))?;
+            let end = parts[1]
+                .parse::<u16>()
+                .map_err(|_| Error::config(format!("Invalid port: {}", parts[1])))?;
+
+            if start > end {
+                return Err(Error::config(format!(
+                    "Invalid port range: start {} > end {}",
+                    start, end
+                )));
+            }
+
+            Ok(RulePorts::Range(start, end))
+        } else {
+            let port = s
+                .parse::<u16>()
+                .map_err(|_| Error::config(format!("Invalid port: {}", s)))?;
+            Ok(RulePorts::Single(port))
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            mapped_ports: MappedPorts::default(),
+            output: Vec::new(),
+            expected_subnet: None,
+            storage_egress: false,
+            quarantine: None,
+            raw_rules: Vec::new(),
+            forward: Vec::new(),
+        }
+    }
+
+    pub fn with_mapped_ports(mut self, mapped_ports: MappedPorts) -> Self {
+        self.mapped_ports = mapped_ports;
+        self
+    }
+
+    pub fn with_output(mut self, output: Vec<RuleConfig>) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn add_output_rule(mut self, rule: RuleConfig) -> Self {
+        self.output.push(rule);
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        // Validate output rules
+        for (i, rule) in self.output.iter().enumerate() {
+            Self::validate_rule(rule, i)?;
+        }
+
+        // Validate mapped ports
+        Self::validate_mapped_ports(&self.mapped_ports)?;
+
+        if let Some(policy) = &self.quarantine
+            && (policy.max_drops == 0 || policy.window.is_zero())
+        {
+            return Err(Error::config(
+                "quarantine: 'max_drops' and 'window' must be greater than zero",
+            ));
+        }
+
+        // Each raw rule becomes one line of an nft script
+        for (i, rule) in self.raw_rules.iter().enumerate() {
+            if rule.trim().is_empty() || rule.contains([';', '#', '\n']) {
+                return Err(Error::config(format!(
+                    "raw_rules[{}]: expected a single rule without ';', '#' or line breaks",
+                    i + 1
+                )));
+            }
+        }
+
+        for (i, forward) in self.forward.iter().enumerate() {
+            if forward.host_port == 0 || forward.container_port() == 0 {
+                return Err(Error::config(format!(
+                    "forward[{}]: ports must be between 1 and 65535",
+                    i + 1
+                )));
+            }
+            if forward.proto.is_icmp() {
+                return Err(Error::config(format!(
+                    "forward[{}]: only tcp and udp ports can be forwarded",
+                    i + 1
+                )));
+            }
+            if self.forward[..i]
+                .iter()
+                .any(|other| other.overlaps(forward))
+            {
+                return Err(Error::config(format!(
+                    "forward[{}]: {}/{} is already forwarded",
+                    i + 1,
+                    forward.host_port,
+                    forward.proto
+                )));
+            }
+        }
+
+        // Verdicts are already validated in their custom deserializers
+
+        Ok(())
+    }
+
+    /// Groups of output rules (numbered from 1) that are identical apart
+    /// from their position, such as a template pasted next to a hand-written
+    /// rule. Only the first of each group decides precedence.
+    pub fn duplicate_output_rules(&self) -> Vec<Vec<usize>> {
+        let keys: Vec<Option<serde_json::Value>> = self
+            .output
+            .iter()
+            .map(|rule| serde_json::to_value(rule).ok())
+            .collect();
+
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            if key.is_none() || groups.iter().flatten().any(|&n| n == i + 1) {
+                continue;
+            }
+            let group: Vec<usize> = (i..keys.len())
+                .filter(|&j| keys[j] == *key)
+                .map(|j| j + 1)
+                .collect();
+            if group.len() > 1 {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /// Refuse output rules allowing every address on one of `sensitive`
+    /// without `expose_to_internet`
+    pub fn check_exposure(&self, sensitive: &[u16]) -> Result<()> {
+        for (i, rule) in self.output.iter().enumerate() {
+            let exposed = exposure::exposed_ports(rule, sensitive);
+            if !exposed.is_empty() && !rule.expose_to_internet {
+                let ports: Vec<String> = exposed.iter().map(u16::to_string).collect();
+                return Err(Error::config(format!(
+                    "Output rule #{}: allows every address on sensitive ports {}; set 'expose_to_internet: true' if that is intended",
+                    i + 1,
+                    ports.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_rule(rule: &RuleConfig, index: usize) -> Result<()> {
+        if rule.ips.is_empty()
+            && !rule.host
+            && rule.hostname.is_none()
+            && rule.container.is_empty()
+            && rule.from.is_none()
+            && rule.src_ports.is_empty()
+            && rule.dst_ports.is_empty()
+            && rule.icmp_types.is_empty()
+        {
+            return Err(Error::config(format!("Output rule #{} is empty", index)));
+        }
+
+        if rule.from.is_some()
+            && (!rule.ips.is_empty()
+                || rule.host
+                || rule.hostname.is_some()
+                || !rule.container.is_empty())
+        {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'from' excludes 'ips', 'host' and 'container'",
+                index
+            )));
+        }
+
+        if !rule.ips.is_empty() && !rule.container.is_empty() {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'ips' and 'container' are mutually exclusive",
+                index
+            )));
+        }
+
+        if rule.host && !rule.container.is_empty() {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'host' and 'container' are mutually exclusive",
+                index
+            )));
+        }
+
+        if rule.hostname.is_some() && (!rule.ips.is_empty() || !rule.container.is_empty()) {
+            return Err(Error::config(format!(
+                "Output rule #{}: a hostname in 'host' excludes 'ips' and 'container'",
+                index
+            )));
+        }
+
+        if rule.network.is_empty() && !rule.container.is_empty() {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'network' must be set when 'container' is set",
+                index
+            )));
+        }
+
+        if !rule.enabled && rule.reason.as_deref().is_none_or(|r| r.trim().is_empty()) {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'reason' must be set when 'enabled' is false",
+                index
+            )));
+        }
+
+        // Validate IP family consistency
+        if !rule.ips.is_empty() {
+            Self::validate_ip_family_consistency(&rule.ips, &format!("Output rule #{}", index))?;
+        }
+
+        if !rule.src_ports.is_empty() && rule.dst_ports.is_empty() {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'dst_ports' must be set when 'src_ports' is set",
+                index
+            )));
+        }
+
+        // ICMP has message types instead of ports
+        if rule.proto.is_icmp() {
+            if !rule.dst_ports.is_empty() || !rule.src_ports.is_empty() {
+                return Err(Error::config(format!(
+                    "Output rule #{}: {} rules have no ports; use 'icmp_types'",
+                    index, rule.proto
+                )));
+            }
+        } else if !rule.icmp_types.is_empty() {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'icmp_types' needs 'proto: icmp' or 'icmpv6'",
+                index
+            )));
+        } else if rule.dst_ports.is_empty() {
+            return Err(Error::config(format!(
+                "Output rule #{}: 'dst_ports' must be set when 'proto' is set",
+                index
+            )));
+        }
+
+        Self::validate_verdict(&rule.verdict)?;
+
+        Ok(())
+    }
+
+    fn validate_verdict(verdict: &ConfigVerdict) -> Result<()> {
+        if !verdict.chain.is_empty() && verdict.queue != 0 {
+            return Err(Error::config(
+                "'chain' and 'queue' are mutually exclusive".to_string(),
+            ));
+        }
+
+        if verdict.queue == 0 && verdict.input_est_queue != 0 {
+            return Err(Error::config(
+                "'queue' must be set when 'input_est_queue' is set".to_string(),
+            ));
+        }
+
+        if verdict.queue == 0 && verdict.output_est_queue != 0 {
+            return Err(Error::config(
+                "'queue' must be set when 'output_est_queue' is set".to_string(),
+            ));
+        }
+
+        if verdict.input_est_queue == 0 && verdict.output_est_queue != 0 {
+            return Err(Error::config(
+                "'input_est_queue' must be set when 'output_est_queue' is set".to_string(),
+            ));
+        }
+
+        if verdict.output_est_queue == 0 && verdict.input_est_queue != 0 {
+            return Err(Error::config(
+                "'output_est_queue' must be set when 'input_est_queue' is set".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_mapped_ports(mapped_ports: &MappedPorts) -> Result<()> {
+        // Validate external IPs consistency
+        if !mapped_ports.external.ips.is_empty() {
+            Self::validate_ip_family_consistency(
+                &mapped_ports.external.ips,
+                "External mapped ports",
+            )?;
+        }
+
+        // Note: localhost should not have IPs field per the spec
+        // This is enforced by not having an ips field in LocalRules struct
+
+        Ok(())
+    }
+
+    fn validate_ip_family_consistency(ips: &[AddrOrRange], context: &str) -> Result<()> {
+        if ips.is_empty() {
+            return Ok(());
+        }
+
+        // Determine the family of the first IP
+        let first_is_ipv4 = match &ips[0] {
+            AddrOrRange::Addr(addr) => addr.is_ipv4(),
+            AddrOrRange::Range(start, _) => start.is_ipv4(),
+            AddrOrRange::Net(net) => match net {
+                IpNet::V4(_) => true,
+                IpNet::V6(_) => false,
+            },
+        };
+
+        // Check that all subsequent IPs match the first family
+        for ip in ips.iter().skip(1) {
+            let is_ipv4 = match ip {
+                AddrOrRange::Addr(addr) => addr.is_ipv4(),
+                AddrOrRange::Range(start, _) => start.is_ipv4(),
+                AddrOrRange::Net(net) => match net {
+                    IpNet::V4(_) => true,
+                    IpNet::V6(_) => false,
+                },
+            };
+
+            if is_ipv4 != first_is_ipv4 {
+                return Err(Error::config(format!(
+                    "{}: Mixed IPv4 and IPv6 addresses are not supported. {} is {}, but {} is {}",
+                    context,
+                    ips[0],
+                    if first_is_ipv4 { "IPv4" } else { "IPv6" },
+                    ip,
+                    if is_ipv4 { "IPv4" } else { "IPv6" }
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Custom Deserialize implementation with validation
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // First deserialize to a temporary structure
+        #[derive(Deserialize)]
+        struct TempConfig {
+            #[serde(default)]
+            mapped_ports: MappedPorts,
+            #[serde(default)]
+            output: Vec<RuleConfig>,
+            #[serde(default)]
+            expected_subnet: Option<String>,
+            #[serde(default)]
+            storage_egress: bool,
+            #[serde(default)]
+            quarantine: Option<QuarantinePolicy>,
+            #[serde(default)]
+            raw_rules: Vec<String>,
+            #[serde(default)]
+            forward: Vec<ForwardRule>,
+        }
+
+        let temp = TempConfig::deserialize(deserializer)?;
+
+        let expected_subnet = match temp.expected_subnet {
+            Some(subnet) => Some(subnet.parse::<IpNet>().map_err(|e| {
+                serde::de::Error::custom(ValidationError::InvalidFieldValue {
+                    field: "expected_subnet".to_string(),
+                    reason: e.to_string(),
+                    value: subnet.clone(),
+                    expected_format: Some("CIDR such as 172.20.0.0/16".to_string()),
+                })
+            })?),
+            None => None,
+        };
+
+        // Create the actual Config
+        let config = Config {
+            mapped_ports: temp.mapped_ports,
+            output: temp.output,
+            expected_subnet,
+            storage_egress: temp.storage_egress,
+            quarantine: temp.quarantine,
+            raw_rules: temp.raw_rules,
+            forward: temp.forward,
+        };
+
+        // Basic structural validation - component types handle their own field validation
+        config.validate().map_err(serde::de::Error::custom)?;
+
+        Ok(config)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Result, docker::config::ConfigVerdict};
+use crate::{Result, config::ConfigVerdict};
 use nftables::{
     expr::{Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField},
     schema::Rule,
@@ -176,3 +176,48 @@ pub trait ToNftablesRule {
         }
     }
 }
+
+/// Rules for numbered output rule statements. Statements that are identical
+/// are emitted once, with a comment naming every source rule.
+pub fn merge_output_rules(
+    ctx: &RuleContext,
+    numbered: Vec<(usize, Vec<Statement<'static>>)>,
+) -> Vec<Rule<'static>> {
+    let mut merged: Vec<(Vec<Statement<'static>>, Vec<usize>)> = Vec::new();
+    for (number, statements) in numbered {
+        match merged
+            .iter_mut()
+            .find(|(existing, _)| *existing == statements)
+        {
+            Some((_, numbers)) => numbers.push(number),
+            None => merged.push((statements, vec![number])),
+        }
+    }
+
+    merged
+        .into_iter()
+        .map(|(statements, numbers)| {
+            let comment = match numbers.as_slice() {
+                [n] => format!("Output rule {} for {}", n, ctx.container_name),
+                _ => format!(
+                    "Output rules {} for {}",
+                    numbers
+                        .iter()
+                        .map(|n| n.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    ctx.container_name
+                ),
+            };
+            Rule {
+                family: ctx.family,
+                table: Cow::Owned(ctx.table_name.to_string()),
+                chain: Cow::Owned(ctx.chain_name.to_string()),
+                expr: Cow::Owned(statements),
+                handle: None,
+                index: None,
+                comment: Some(Cow::Owned(comment)),
+            }
+        })
+        .collect()
+}
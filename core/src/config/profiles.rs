@@ -0,0 +1,379 @@
+//! Named rule profiles that rule sets can build on with `extends`.
+//!
+//! Profiles are read from a YAML file mapping names to rule sets:
+//!
+//! ```yaml
+//! profiles:
+//!   base_web:
+//!     mapped_ports:
+//!       external:
+//!         allow: true
+//!   team_web:
+//!     extends: base_web
+//!     output:
+//!       - proto: udp
+//!         dst_ports: [53]
+//! ```
+//!
+//! A rules label, guest or profile names one parent or a list of them.
+//! Parents are merged in order, then the rule set itself on top:
+//!
+//! - lists append, so `output` rules of every layer are kept, parents first
+//! - mappings merge key by key
+//! - scalars override, the last layer to set a field wins; `null` resets a
+//!   field to its default
+//!
+//! The examples printed by `harborshield examples` are available as
+//! profiles too, under their scenario names (e.g. `extends: worker`).
+//!
+//! The file can also name lists of ports, which `src_ports` and `dst_ports`
+//! of output rules then use in place of a port:
+//!
+//! ```yaml
+//! ports:
+//!   web: [http, https, 8000-8100]
+//! ```
+
+use super::services;
+use super::templates::Scenario;
+use super::{Config, Protocol};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+pub const EXTENDS_KEY: &str = "extends";
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("unknown profile '{0}'")]
+    Unknown(String),
+    #[error("profiles extend each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("'extends' must be a profile name or a list of them")]
+    InvalidExtends,
+    #[error("{0}")]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+/// Rule sets by profile name and port lists by their name, as written in
+/// the profiles file
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    rules: HashMap<String, Value>,
+    ports: HashMap<String, Vec<String>>,
+}
+
+impl Profiles {
+    /// Built-in example rule sets under their scenario names
+    fn builtin(name: &str) -> Option<Value> {
+        let scenario = <Scenario as ValueEnum>::from_str(name, false).ok()?;
+        serde_yaml::from_str(scenario.template().rules).ok()
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        self.rules
+            .get(name)
+            .cloned()
+            .or_else(|| Self::builtin(name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Resolve the `extends` chain of `value`, returning the merged rule set
+    pub fn resolve(&self, value: Value) -> Result<Value, ProfileError> {
+        let mut resolved = self.resolve_with(value, &mut Vec::new())?;
+        self.expand_ports(&mut resolved);
+        Ok(resolved)
+    }
+
+    /// Replace port list names in output rules with the ports they stand for
+    fn expand_ports(&self, value: &mut Value) {
+        let Some(rules) = value.get_mut("output").and_then(Value::as_sequence_mut) else {
+            return;
+        };
+        for rule in rules {
+            for key in ["src_ports", "dst_ports"] {
+                let Some(ports) = rule.get_mut(key).and_then(Value::as_sequence_mut) else {
+                    continue;
+                };
+                *ports = std::mem::take(ports)
+                    .into_iter()
+                    .flat_map(|port| match port.as_str().and_then(|n| self.ports.get(n)) {
+                        Some(list) => list.iter().cloned().map(Value::String).collect(),
+                        None => vec![port],
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    fn resolve_with(
+        &self,
+        mut value: Value,
+        stack: &mut Vec<String>,
+    ) -> Result<Value, ProfileError> {
+        let parents = match value.as_mapping_mut().and_then(|m| m.remove(EXTENDS_KEY)) {
+            None => return Ok(value),
+            Some(Value::String(name)) => vec![name],
+            Some(Value::Sequence(names)) => names
+                .into_iter()
+                .map(|name| match name {
+                    Value::String(name) => Ok(name),
+                    _ => Err(ProfileError::InvalidExtends),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(ProfileError::InvalidExtends),
+        };
+
+        let mut merged = Value::Mapping(Mapping::new());
+        for name in parents {
+            if stack.contains(&name) {
+                let mut cycle = stack.clone();
+                cycle.push(name);
+                return Err(ProfileError::Cycle(cycle));
+            }
+            let parent = self
+                .get(&name)
+                .ok_or_else(|| ProfileError::Unknown(name.clone()))?;
+            stack.push(name);
+            let parent = self.resolve_with(parent, stack)?;
+            stack.pop();
+            merge(&mut merged, parent);
+        }
+        merge(&mut merged, value);
+        Ok(merged)
+    }
+
+    /// Parse a rule set in the label format, resolving `extends`
+    pub fn parse_rules(&self, yaml: &str) -> Result<Config, ProfileError> {
+        self.parse_value(serde_yaml::from_str(yaml)?)
+    }
+
+    /// [`Profiles::parse_rules`] for a rule set already read, such as one
+    /// embedded in another file
+    pub fn parse_value(&self, value: Value) -> Result<Config, ProfileError> {
+        Ok(to_config(self.resolve(value)?)?)
+    }
+
+    /// Parse a rule set with `layers` merged over it in order, as if each
+    /// layer extended the one before
+    pub fn parse_layered(&self, yaml: &str, layers: &[String]) -> Result<Config, ProfileError> {
+        let mut merged = self.resolve(serde_yaml::from_str(yaml)?)?;
+        for layer in layers {
+            merge(&mut merged, self.resolve(serde_yaml::from_str(layer)?)?);
+        }
+        Ok(to_config(merged)?)
+    }
+}
+
+/// Read a merged rule set. It goes back through YAML text because ports are
+/// parsed from strings, which a plain scalar provides but a `Value` number
+/// doesn't
+fn to_config(value: Value) -> Result<Config, serde_yaml::Error> {
+    serde_yaml::from_str(&serde_yaml::to_string(&value)?)
+}
+
+/// Lay `overlay` over `base`: mappings merge, lists append and anything else
+/// replaces what was there
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                if value.is_null() {
+                    base.remove(&key);
+                } else if let Some(existing) = base.get_mut(&key) {
+                    merge(existing, value);
+                } else {
+                    base.insert(key, value);
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(overlay)) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileFile {
+    #[serde(default)]
+    profiles: HashMap<String, Value>,
+    #[serde(default)]
+    ports: HashMap<String, Vec<String>>,
+}
+
+/// Read a profiles file, checking that every profile resolves to valid rules
+pub fn load(path: &Path) -> std::io::Result<Profiles> {
+    let text = std::fs::read_to_string(path)?;
+    parse(&text).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse(text: &str) -> Result<Profiles, String> {
+    let file: ProfileFile = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    let profiles = Profiles {
+        rules: file.profiles,
+        ports: file.ports,
+    };
+
+    let is_port = |spec: &String| {
+        [Protocol::Tcp, Protocol::Udp]
+            .into_iter()
+            .any(|proto| services::resolve(std::slice::from_ref(spec), proto).is_ok())
+    };
+    for (name, ports) in &profiles.ports {
+        if is_port(name) {
+            return Err(format!("port list {} shadows a port or service name", name));
+        }
+        if ports.is_empty() {
+            return Err(format!("port list {} is empty", name));
+        }
+        if let Some(port) = ports.iter().find(|port| !is_port(port)) {
+            return Err(format!(
+                "port list {}: '{}' is not a port, range or service name",
+                name, port
+            ));
+        }
+    }
+
+    for (name, value) in &profiles.rules {
+        if Profiles::builtin(name).is_some() {
+            return Err(format!("profile {} shadows the built-in example", name));
+        }
+        let mut resolved = profiles
+            .resolve_with(value.clone(), &mut vec![name.clone()])
+            .map_err(|e| format!("profile {}: {}", name, e))?;
+        profiles.expand_ports(&mut resolved);
+        to_config(resolved).map_err(|e| format!("profile {}: {}", name, e))?;
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+profiles:
+  org:
+    mapped_ports:
+      localhost:
+        allow: true
+    output:
+      - proto: udp
+        dst_ports: [53]
+  team:
+    extends: org
+    mapped_ports:
+      external:
+        allow: true
+        log_prefix: team
+  service:
+    extends: [team, worker]
+    mapped_ports:
+      external:
+        log_prefix: service
+"#;
+
+    #[test]
+    fn test_extends_merges_layers() {
+        let profiles = parse(FILE).unwrap();
+        assert_eq!(profiles.len(), 3);
+
+        let config = profiles
+            .parse_rules("extends: service\noutput:\n  - proto: tcp\n    dst_ports: [8080]\n")
+            .unwrap();
+        // Scalars from the nearest layer, mappings merged across all
+        assert!(config.mapped_ports.localhost.allow);
+        assert!(config.mapped_ports.external.allow);
+        assert_eq!(config.mapped_ports.external.log_prefix, "service");
+        // Lists append, parents first: org's DNS, the worker example's two
+        // rules, then the label's own
+        assert_eq!(config.output.len(), 4);
+        assert_eq!(config.output[0].dst_ports[0].to_string(), "53");
+        assert_eq!(config.output[3].dst_ports[0].to_string(), "8080");
+    }
+
+    #[test]
+    fn test_null_resets_field() {
+        let profiles = parse(FILE).unwrap();
+        let config = profiles
+            .parse_rules("extends: team\nmapped_ports:\n  external:\n    log_prefix: null\n")
+            .unwrap();
+        assert!(config.mapped_ports.external.allow);
+        assert_eq!(config.mapped_ports.external.log_prefix, "");
+    }
+
+    #[test]
+    fn test_parse_layered() {
+        let profiles = parse(FILE).unwrap();
+        let config = profiles
+            .parse_layered(
+                "extends: team\noutput:\n  - proto: tcp\n    dst_ports: [443]\n",
+                &[
+                    "output:\n  - proto: tcp\n    ips: [10.0.0.0/8]\n    dst_ports: [22]\n"
+                        .to_string(),
+                ],
+            )
+            .unwrap();
+        assert!(config.mapped_ports.external.allow);
+        // The label's rules stay ahead of the layer's
+        assert_eq!(config.output.len(), 3);
+        assert_eq!(config.output[1].dst_ports[0].to_string(), "443");
+        assert_eq!(config.output[2].dst_ports[0].to_string(), "22");
+    }
+
+    #[test]
+    fn test_port_lists() {
+        let profiles = parse("ports:\n  web: [http, 8443, 9000-9010]\n").unwrap();
+        let config = profiles
+            .parse_rules(
+                "output:\n  - proto: tcp\n    ips: [10.0.0.0/8]\n    dst_ports: [web, ssh]\n",
+            )
+            .unwrap();
+        let ports: Vec<String> = config.output[0]
+            .dst_ports
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(ports, ["80", "8443", "9000-9010", "22"]);
+
+        // `ntp` is only a udp service
+        assert!(
+            profiles
+                .parse_rules("output:\n  - proto: tcp\n    dst_ports: [ntp]\n")
+                .is_err()
+        );
+        assert!(parse("ports:\n  https: [8443]\n").is_err());
+        assert!(parse("ports:\n  web: [htp]\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        assert!(matches!(
+            Profiles::default().parse_rules("extends: missing"),
+            Err(ProfileError::Unknown(_))
+        ));
+        assert!(matches!(
+            Profiles::default().parse_rules("extends: 3"),
+            Err(ProfileError::InvalidExtends)
+        ));
+
+        let cycle = parse("profiles:\n  a: {extends: b}\n  b: {extends: a}\n").unwrap_err();
+        assert!(cycle.contains("cycle"), "{}", cycle);
+        assert!(parse("profiles:\n  worker: {}\n").is_err());
+        assert!(parse("profiles:\n  bad: {output: [{proto: tcp}]}\n").is_err());
+    }
+}
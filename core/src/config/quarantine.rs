@@ -0,0 +1,72 @@
+use super::{AddrOrRange, Config, ExternalRules, LocalRules, MappedPorts};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
+
+pub const DEFAULT_QUARANTINE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Automatic containment for a container whose traffic keeps hitting its
+/// drop rule:
+///
+/// ```yaml
+/// quarantine:
+///   max_drops: 500
+///   window: 5m
+///   management: ["10.0.9.0/24"]
+/// ```
+///
+/// Once `max_drops` packets are dropped within `window` the container is
+/// switched to the `quarantined` enforcement mode until released with
+/// `harborshield release`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinePolicy {
+    pub max_drops: u64,
+    #[serde(
+        default = "default_window",
+        serialize_with = "serialize_window",
+        deserialize_with = "deserialize_window"
+    )]
+    pub window: Duration,
+    /// Sources still admitted to the container's published ports while it
+    /// is quarantined
+    #[serde(default)]
+    pub management: Vec<AddrOrRange>,
+}
+
+fn default_window() -> Duration {
+    DEFAULT_QUARANTINE_WINDOW
+}
+
+fn serialize_window<S: Serializer>(window: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{}s", window.as_secs()))
+}
+
+fn deserialize_window<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    crate::parse_duration(&text).map_err(serde::de::Error::custom)
+}
+
+impl Config {
+    /// What a quarantined container keeps: no egress, and inbound only from
+    /// the management addresses of its policy
+    pub fn quarantined(&self) -> Config {
+        let management = self
+            .quarantine
+            .as_ref()
+            .map(|policy| policy.management.clone())
+            .unwrap_or_default();
+        Config::builder()
+            .mapped_ports(
+                MappedPorts::builder()
+                    .localhost(LocalRules::default())
+                    .external(
+                        ExternalRules::builder()
+                            .allow(!management.is_empty())
+                            .ips(management)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .maybe_quarantine(self.quarantine.clone())
+            .build()
+    }
+}
@@ -0,0 +1,791 @@
+use crate::Result;
+use crate::config::{ConfigVerdict, Protocol, RulePorts, ToNftablesRule};
+use crate::dns::{LookupFamily, LookupPolicy};
+use bon::Builder;
+use nftables::expr::{Expression, NamedExpression, Payload, PayloadField};
+use nftables::stmt::{Match, Operator, Statement};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+#[derive(Debug, Clone, Serialize, Builder)]
+pub struct RuleConfig {
+    #[serde(default)]
+    #[builder(default)]
+    pub log_prefix: String,
+    /// Only apply the rule on the container's attachment to this network,
+    /// and reach a `container:` target through it
+    #[serde(default)]
+    #[builder(default)]
+    pub network: String,
+    #[serde(default)]
+    #[builder(default)]
+    pub ips: Vec<super::AddrOrRange>,
+    /// Entries of `ips` such as `host_ip(net)`, resolved from the engine's
+    /// networks when rules are rendered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub ip_templates: Vec<super::IpTemplate>,
+    /// Also match the host's own addresses, kept current across address
+    /// changes
+    #[serde(default)]
+    #[builder(default)]
+    pub host: bool,
+    /// Match the addresses this name resolves to, given as `host: <name>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Records `hostname` is resolved to, over `--dns-family`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_family: Option<LookupFamily>,
+    /// Over `--dns-require-both`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_require_both: Option<bool>,
+    /// Seconds between lookups of `hostname`, over `--dns-refresh`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_refresh: Option<u32>,
+    #[serde(default)]
+    #[builder(default)]
+    pub container: String,
+    /// Allow traffic from the containers of a compose service instead of
+    /// to a destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<super::ServiceRef>,
+    pub proto: Protocol,
+    #[serde(default)]
+    #[builder(default)]
+    pub src_ports: Vec<RulePorts>,
+    #[serde(default)]
+    #[builder(default)]
+    pub dst_ports: Vec<RulePorts>,
+    /// ICMP message types to match, such as `echo-request`, for `proto:
+    /// icmp` and `icmpv6` rules; every type when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub icmp_types: Vec<String>,
+    #[serde(default)]
+    #[builder(default)]
+    pub verdict: ConfigVerdict,
+    /// Only apply the rule inside this window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<super::TimeWindow>,
+    /// Set to false to keep the rule in the config without applying it
+    #[serde(default = "super::default_true")]
+    #[builder(default = true)]
+    pub enabled: bool,
+    /// Why the rule is disabled, required with `enabled: false`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Hand accepted flows to the fastpath flowtable once established, when
+    /// `--flowtable-device` is set
+    #[serde(default)]
+    #[builder(default)]
+    pub offload: bool,
+    /// Announce traffic the rule matches, sampled each stats interval,
+    /// through the audit log and event bus
+    #[serde(default)]
+    #[builder(default)]
+    pub notify_on_hit: bool,
+    /// Intercept the flows the rule matches with a local TPROXY service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tproxy: Option<super::Tproxy>,
+    /// Confirms a rule opening `--sensitive-ports` to `0.0.0.0/0` is meant
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub expose_to_internet: bool,
+    /// The container's addresses on `network`, filled in when rules are
+    /// resolved; the rule only matches traffic of that attachment
+    #[serde(skip)]
+    #[builder(default)]
+    pub attachment: Vec<std::net::IpAddr>,
+
+    #[serde(skip)]
+    #[builder(default = false)]
+    pub skip: bool,
+}
+
+impl RuleConfig {
+    /// How `hostname` is resolved, the rule's own settings over `default`
+    pub fn dns_lookup(&self, default: LookupPolicy) -> LookupPolicy {
+        LookupPolicy {
+            family: self.dns_family.unwrap_or(default.family),
+            require_both: self.dns_require_both.unwrap_or(default.require_both),
+        }
+    }
+
+    /// Seconds between lookups of `hostname`, `default` unless the rule
+    /// sets its own
+    pub fn dns_refresh(&self, default: u32) -> u32 {
+        self.dns_refresh.unwrap_or(default)
+    }
+}
+
+/// `host: true` for the host's own addresses, or a name to resolve
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HostField {
+    Own(bool),
+    Name(String),
+}
+
+impl Default for HostField {
+    fn default() -> Self {
+        HostField::Own(false)
+    }
+}
+
+// Custom Deserialize for RuleConfig with validation
+impl<'de> Deserialize<'de> for RuleConfig {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct TempRuleConfig {
+            #[serde(default)]
+            log_prefix: String,
+            #[serde(default)]
+            network: String,
+            #[serde(default)]
+            ips: Vec<super::cidr::IpEntry>,
+            #[serde(default)]
+            ip_templates: Vec<super::IpTemplate>,
+            #[serde(default)]
+            host: HostField,
+            #[serde(default)]
+            hostname: Option<String>,
+            #[serde(default)]
+            dns_family: Option<LookupFamily>,
+            #[serde(default)]
+            dns_require_both: Option<bool>,
+            #[serde(default)]
+            dns_refresh: Option<u32>,
+            #[serde(default)]
+            container: String,
+            #[serde(default)]
+            from: Option<super::ServiceRef>,
+            proto: Protocol,
+            #[serde(default)]
+            src_ports: Vec<String>,
+            #[serde(default)]
+            dst_ports: Vec<String>,
+            #[serde(default)]
+            icmp_types: Vec<String>,
+            #[serde(default)]
+            verdict: ConfigVerdict,
+            #[serde(default)]
+            time: Option<super::TimeWindow>,
+            #[serde(default = "super::default_true")]
+            enabled: bool,
+            #[serde(default)]
+            reason: Option<String>,
+            #[serde(default)]
+            offload: bool,
+            #[serde(default)]
+            notify_on_hit: bool,
+            #[serde(default)]
+            tproxy: Option<super::Tproxy>,
+            #[serde(default)]
+            expose_to_internet: bool,
+            #[serde(skip)]
+            skip: bool,
+        }
+
+        let mut temp = TempRuleConfig::deserialize(deserializer)?;
+        let (ips, mut ip_templates) = super::cidr::split_entries(std::mem::take(&mut temp.ips));
+        ip_templates.append(&mut temp.ip_templates);
+        let has_ips = !ips.is_empty() || !ip_templates.is_empty();
+        if temp.proto.is_icmp() {
+            if let Some(field) = [
+                ("src_ports", &temp.src_ports),
+                ("dst_ports", &temp.dst_ports),
+            ]
+            .into_iter()
+            .find_map(|(field, ports)| (!ports.is_empty()).then_some(field))
+            {
+                return Err(serde::de::Error::custom(
+                    super::ValidationError::InvalidFieldValue {
+                        field: field.to_string(),
+                        reason: format!("{} has no ports", temp.proto),
+                        value: format!("{} with ports", temp.proto),
+                        expected_format: Some(
+                            "'icmp_types' to narrow an icmp rule, such as [echo-request]"
+                                .to_string(),
+                        ),
+                    },
+                ));
+            }
+            if let Some(name) = super::icmp::unknown_type(temp.proto, &temp.icmp_types) {
+                return Err(serde::de::Error::custom(
+                    super::ValidationError::InvalidFieldValue {
+                        field: "icmp_types".to_string(),
+                        reason: format!("not an {} message type", temp.proto),
+                        value: name.clone(),
+                        expected_format: Some(format!(
+                            "One of {}",
+                            super::icmp::known_types(temp.proto).join(", ")
+                        )),
+                    },
+                ));
+            }
+            // The other family's ICMP never reaches these addresses
+            // Templates resolve to IPv4 addresses
+            if ips
+                .iter()
+                .any(|ip| ip.is_ipv6() != (temp.proto == Protocol::Icmpv6))
+                || (!ip_templates.is_empty() && temp.proto == Protocol::Icmpv6)
+            {
+                return Err(serde::de::Error::custom(
+                    super::ValidationError::InvalidFieldValue {
+                        field: "ips".to_string(),
+                        reason: format!("{} rules only match addresses of its family", temp.proto),
+                        value: format!("{} with addresses of the other family", temp.proto),
+                        expected_format: Some(
+                            "IPv4 addresses for icmp, IPv6 addresses for icmpv6".to_string(),
+                        ),
+                    },
+                ));
+            }
+        } else if !temp.icmp_types.is_empty() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "icmp_types".to_string(),
+                    reason: format!("{} has no message types", temp.proto),
+                    value: temp.icmp_types.join(", "),
+                    expected_format: Some("'proto: icmp' or 'proto: icmpv6'".to_string()),
+                },
+            ));
+        }
+        let src_ports = super::services::resolve(&temp.src_ports, temp.proto)
+            .map_err(serde::de::Error::custom)?;
+        let dst_ports = super::services::resolve(&temp.dst_ports, temp.proto)
+            .map_err(serde::de::Error::custom)?;
+
+        // `hostname` is how a resolved `host` is written back out
+        let (host, named) = match temp.host {
+            HostField::Own(own) => (own, temp.hostname.take()),
+            HostField::Name(name) => (false, Some(name)),
+        };
+        let hostname = match named {
+            Some(name) => Some(crate::dns::hostname::parse_hostname(&name).ok_or_else(|| {
+                serde::de::Error::custom(super::ValidationError::InvalidFieldValue {
+                    field: "host".to_string(),
+                    reason: "not a hostname to resolve".to_string(),
+                    value: name.clone(),
+                    expected_format: Some(
+                        "true, or a fully qualified name such as 'api.stripe.com'".to_string(),
+                    ),
+                })
+            })?),
+            None => None,
+        };
+
+        if hostname.is_some() && (has_ips || !temp.container.is_empty()) {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "rule".to_string(),
+                    reason: "a hostname in 'host' excludes 'ips' and 'container'".to_string(),
+                    value: "host name with ips or container specified".to_string(),
+                    expected_format: Some("One of a host name, 'ips' or 'container'".to_string()),
+                },
+            ));
+        }
+
+        if temp.dns_refresh == Some(0) {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "dns_refresh".to_string(),
+                    reason: "the refresh interval must be at least a second".to_string(),
+                    value: "0".to_string(),
+                    expected_format: Some("Seconds between lookups, at least 1".to_string()),
+                },
+            ));
+        }
+
+        // Validate rule is not empty
+        if !has_ips
+            && !host
+            && hostname.is_none()
+            && temp.container.is_empty()
+            && temp.from.is_none()
+            && src_ports.is_empty()
+            && dst_ports.is_empty()
+            && temp.icmp_types.is_empty()
+        {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidRule {
+                    message: "Rule is empty (no ips, container, ports or icmp_types specified)"
+                        .to_string(),
+                    rule_type: "output".to_string(),
+                    rule_text: "empty rule".to_string(),
+                    position: None,
+                },
+            ));
+        }
+
+        // Check mutually exclusive fields
+        if has_ips && !temp.container.is_empty() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "rule".to_string(),
+                    reason: "'ips' and 'container' are mutually exclusive".to_string(),
+                    value: "both ips and container specified".to_string(),
+                    expected_format: Some("Either 'ips' or 'container', not both".to_string()),
+                },
+            ));
+        }
+
+        if temp.from.is_some()
+            && (has_ips || host || hostname.is_some() || !temp.container.is_empty())
+        {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "from".to_string(),
+                    reason: "'from' names the peers itself, so it excludes 'ips', 'host' and 'container'".to_string(),
+                    value: "from with a destination specified".to_string(),
+                    expected_format: Some("Either 'from' or a destination, not both".to_string()),
+                },
+            ));
+        }
+
+        if host && !temp.container.is_empty() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "rule".to_string(),
+                    reason: "'host' and 'container' are mutually exclusive".to_string(),
+                    value: "both host and container specified".to_string(),
+                    expected_format: Some("Either 'host' or 'container', not both".to_string()),
+                },
+            ));
+        }
+
+        // Check network requirement
+        if temp.network.is_empty() && !temp.container.is_empty() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::MissingRequiredField {
+                    field: "network".to_string(),
+                    context: "rule with container reference".to_string(),
+                },
+            ));
+        }
+
+        // Check port requirements
+        if !src_ports.is_empty() && dst_ports.is_empty() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::MissingRequiredField {
+                    field: "dst_ports".to_string(),
+                    context: "rule with src_ports".to_string(),
+                },
+            ));
+        }
+
+        if dst_ports.is_empty() && !temp.proto.is_icmp() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::MissingRequiredField {
+                    field: "dst_ports".to_string(),
+                    context: format!("rule with protocol '{}'", temp.proto),
+                },
+            ));
+        }
+
+        if !temp.enabled && temp.reason.as_deref().is_none_or(|r| r.trim().is_empty()) {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::MissingRequiredField {
+                    field: "reason".to_string(),
+                    context: "rule with 'enabled: false'".to_string(),
+                },
+            ));
+        }
+
+        // Offloaded flows skip the chain, so only plain accepts can be offloaded
+        if temp.offload && (temp.verdict.queue != 0 || !temp.verdict.chain.is_empty()) {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "offload".to_string(),
+                    reason: "offloaded flows bypass 'queue' and 'chain' verdicts".to_string(),
+                    value: "offload with a queue or chain verdict".to_string(),
+                    expected_format: Some("'offload' only on rules that accept".to_string()),
+                },
+            ));
+        }
+
+        if let Some(tproxy) = &temp.tproxy {
+            let conflict = if temp.proto.is_icmp() {
+                Some("icmp has no ports to intercept")
+            } else if temp.from.is_some() {
+                Some("only flows the container opens can be intercepted")
+            } else if host || hostname.is_some() {
+                Some("'host' is not matched before routing")
+            } else if temp.offload {
+                Some("offloaded flows are forwarded, not intercepted")
+            } else if temp.verdict.queue != 0 || !temp.verdict.chain.is_empty() {
+                Some("intercepted flows are accepted, not queued or jumped")
+            } else if tproxy.port == 0 || tproxy.mark == 0 {
+                Some("'port' and 'mark' must both be set")
+            } else {
+                None
+            };
+            if let Some(reason) = conflict {
+                return Err(serde::de::Error::custom(
+                    super::ValidationError::InvalidFieldValue {
+                        field: "tproxy".to_string(),
+                        reason: reason.to_string(),
+                        value: format!("port {}, mark {}", tproxy.port, tproxy.mark),
+                        expected_format: Some(
+                            "'port' and a non-zero 'mark' on a tcp or udp rule to addresses"
+                                .to_string(),
+                        ),
+                    },
+                ));
+            }
+        }
+
+        // Validate log prefix
+        if !temp.log_prefix.is_empty() && temp.log_prefix.len() > 64 {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "log_prefix".to_string(),
+                    reason: "Log prefix too long (max 64 characters)".to_string(),
+                    value: temp.log_prefix.clone(),
+                    expected_format: Some("String with max 64 characters".to_string()),
+                },
+            ));
+        }
+
+        // Validate ports
+        for port_spec in &dst_ports {
+            match port_spec {
+                RulePorts::Single(port) => {
+                    if *port == 0 {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPort {
+                                port: port.to_string(),
+                                reason: "Port 0 is not valid".to_string(),
+                            },
+                        ));
+                    }
+                }
+                RulePorts::Range(start, end) => {
+                    if *start > *end {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPortRange {
+                                range: format!("{}-{}", start, end),
+                                reason: "Start port is greater than end port".to_string(),
+                            },
+                        ));
+                    }
+                    if *start == 0 {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPort {
+                                port: start.to_string(),
+                                reason: "Port 0 is not valid".to_string(),
+                            },
+                        ));
+                    }
+                    if *end == 0 {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPort {
+                                port: end.to_string(),
+                                reason: "Port 0 is not valid".to_string(),
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
+        // Validate source ports as well
+        for port_spec in &src_ports {
+            match port_spec {
+                RulePorts::Single(port) => {
+                    if *port == 0 {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPort {
+                                port: port.to_string(),
+                                reason: "Port 0 is not valid".to_string(),
+                            },
+                        ));
+                    }
+                }
+                RulePorts::Range(start, end) => {
+                    if *start > *end {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPortRange {
+                                range: format!("{}-{}", start, end),
+                                reason: "Start port is greater than end port".to_string(),
+                            },
+                        ));
+                    }
+                    if *start == 0 {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPort {
+                                port: start.to_string(),
+                                reason: "Port 0 is not valid".to_string(),
+                            },
+                        ));
+                    }
+                    if *end == 0 {
+                        return Err(serde::de::Error::custom(
+                            super::ValidationError::InvalidPort {
+                                port: end.to_string(),
+                                reason: "Port 0 is not valid".to_string(),
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(RuleConfig {
+            log_prefix: temp.log_prefix,
+            network: temp.network,
+            ips,
+            ip_templates,
+            host,
+            hostname,
+            dns_family: temp.dns_family,
+            dns_require_both: temp.dns_require_both,
+            dns_refresh: temp.dns_refresh,
+            container: temp.container,
+            from: temp.from,
+            proto: temp.proto,
+            src_ports,
+            dst_ports,
+            icmp_types: temp.icmp_types,
+            verdict: temp.verdict,
+            time: temp.time,
+            enabled: temp.enabled,
+            reason: temp.reason,
+            offload: temp.offload,
+            notify_on_hit: temp.notify_on_hit,
+            tproxy: temp.tproxy,
+            expose_to_internet: temp.expose_to_internet,
+            attachment: Vec::new(),
+            skip: temp.skip,
+        })
+    }
+}
+
+/// Implementation for RuleConfig (output rules)
+impl ToNftablesRule for RuleConfig {
+    fn to_nftables_statements(&self) -> Result<Vec<Statement<'static>>> {
+        let mut statements = Vec::new();
+
+        // Match protocol
+        let protocol_str = match self.proto {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Icmp => "icmp",
+            Protocol::Icmpv6 => "icmpv6",
+        };
+        statements.push(Self::match_protocol(protocol_str));
+
+        // Match destination IPs if specified, or the peers' source IPs
+        let field = if self.from.is_some() {
+            "saddr"
+        } else {
+            "daddr"
+        };
+        if !self.ips.is_empty() {
+            // Create a set expression for multiple IPs
+            let mut ip_exprs = Vec::new();
+
+            for addr_or_range in &self.ips {
+                match addr_or_range {
+                    super::AddrOrRange::Addr(ip) => {
+                        ip_exprs.push(Expression::String(Cow::Owned(ip.to_string())));
+                    }
+                    super::AddrOrRange::Range(start, end) => {
+                        ip_exprs.push(Expression::Range(Box::new(nftables::expr::Range {
+                            range: [
+                                Expression::String(Cow::Owned(start.to_string())),
+                                Expression::String(Cow::Owned(end.to_string())),
+                            ],
+                        })));
+                    }
+                    super::AddrOrRange::Net(net) => {
+                        // Parse CIDR notation to create a prefix expression
+                        let addr = net.addr();
+                        let prefix_len = net.prefix_len() as u32;
+
+                        ip_exprs.push(Expression::Named(NamedExpression::Prefix(
+                            nftables::expr::Prefix {
+                                addr: Box::new(Expression::String(Cow::Owned(addr.to_string()))),
+                                len: prefix_len,
+                            },
+                        )));
+                    }
+                }
+            }
+
+            // Determine protocol based on first IP
+            let protocol = match self.ips.first() {
+                Some(super::AddrOrRange::Addr(ip)) => {
+                    if ip.is_ipv4() {
+                        "ip"
+                    } else {
+                        "ip6"
+                    }
+                }
+                Some(super::AddrOrRange::Range(start, _)) => {
+                    if start.is_ipv4() {
+                        "ip"
+                    } else {
+                        "ip6"
+                    }
+                }
+                Some(super::AddrOrRange::Net(net)) => {
+                    if net.addr().is_ipv4() {
+                        "ip"
+                    } else {
+                        "ip6"
+                    }
+                }
+                _ => "ip",
+            };
+
+            if ip_exprs.len() == 1 {
+                // Single IP/range/prefix - use direct match
+                statements.push(Statement::Match(Match {
+                    left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                        PayloadField {
+                            protocol: Cow::Borrowed(protocol),
+                            field: Cow::Borrowed(field),
+                        },
+                    ))),
+                    right: ip_exprs.into_iter().next().unwrap(),
+                    op: Operator::EQ,
+                }));
+            } else {
+                // Multiple IPs - use anonymous set
+                let set_items: Vec<nftables::expr::SetItem> = ip_exprs
+                    .into_iter()
+                    .map(nftables::expr::SetItem::Element)
+                    .collect();
+
+                statements.push(Statement::Match(Match {
+                    left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                        PayloadField {
+                            protocol: Cow::Borrowed(protocol),
+                            field: Cow::Borrowed(field),
+                        },
+                    ))),
+                    right: Expression::Named(NamedExpression::Set(set_items)),
+                    op: Operator::EQ,
+                }));
+            }
+        }
+
+        // Match the container's address on the rule's network
+        if !self.attachment.is_empty() {
+            let protocol = if self.attachment[0].is_ipv6() {
+                "ip6"
+            } else {
+                "ip"
+            };
+            let right = match self.attachment.as_slice() {
+                [ip] => Expression::String(Cow::Owned(ip.to_string())),
+                ips => Expression::Named(NamedExpression::Set(
+                    ips.iter()
+                        .map(|ip| {
+                            nftables::expr::SetItem::Element(Expression::String(Cow::Owned(
+                                ip.to_string(),
+                            )))
+                        })
+                        .collect(),
+                )),
+            };
+            // Peers come in to the container, everything else goes out of it
+            let field = if self.from.is_some() {
+                "daddr"
+            } else {
+                "saddr"
+            };
+            statements.push(Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                    PayloadField {
+                        protocol: Cow::Borrowed(protocol),
+                        field: Cow::Borrowed(field),
+                    },
+                ))),
+                right,
+                op: Operator::EQ,
+            }));
+        }
+
+        // Match ICMP message types if specified
+        if !self.icmp_types.is_empty() {
+            let right = match self.icmp_types.as_slice() {
+                [name] => Expression::String(Cow::Owned(name.clone())),
+                names => Expression::Named(NamedExpression::Set(
+                    names
+                        .iter()
+                        .map(|name| {
+                            nftables::expr::SetItem::Element(Expression::String(Cow::Owned(
+                                name.clone(),
+                            )))
+                        })
+                        .collect(),
+                )),
+            };
+            statements.push(Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                    PayloadField {
+                        protocol: Cow::Borrowed(protocol_str),
+                        field: Cow::Borrowed("type"),
+                    },
+                ))),
+                right,
+                op: Operator::EQ,
+            }));
+        }
+
+        // Match source ports if specified; only the first port/range for now
+        if let Some(port) = self.src_ports.first() {
+            match port {
+                RulePorts::Single(p) => {
+                    statements.push(Self::match_src_port(protocol_str, *p));
+                }
+                RulePorts::Range(start, end) => {
+                    statements.push(Statement::Match(Match {
+                        left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                            PayloadField {
+                                protocol: Cow::Owned(protocol_str.to_string()),
+                                field: Cow::Borrowed("sport"),
+                            },
+                        ))),
+                        right: Expression::Range(Box::new(nftables::expr::Range {
+                            range: [
+                                Expression::Number(*start as u32),
+                                Expression::Number(*end as u32),
+                            ],
+                        })),
+                        op: Operator::EQ,
+                    }));
+                }
+            }
+        }
+
+        // Match destination ports, the first port/range only for now
+        if let Some(port) = self.dst_ports.first() {
+            match port {
+                RulePorts::Single(p) => {
+                    statements.push(Self::match_dst_port(protocol_str, *p));
+                }
+                RulePorts::Range(start, end) => {
+                    statements.push(Self::match_dst_port_range(protocol_str, *start, *end));
+                }
+            }
+        }
+
+        // Add counter
+        statements.push(Self::counter_statement());
+
+        // Add log if configured
+        if !self.log_prefix.is_empty() {
+            statements.push(Self::log_statement(Some(&self.log_prefix)));
+        }
+
+        // Add verdict
+        statements.push(Self::verdict_to_statement(&self.verdict));
+
+        Ok(statements)
+    }
+}
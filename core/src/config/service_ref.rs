@@ -0,0 +1,40 @@
+use crate::{COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The containers of a compose service, by their compose labels:
+///
+/// ```yaml
+/// from:
+///   service: web
+///   project: myapp
+/// ```
+///
+/// Without `project` the service is looked up in the project of the
+/// container the rule belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceRef {
+    pub service: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+}
+
+impl ServiceRef {
+    /// Whether a container with `labels` belongs to the service, for a rule
+    /// of a container in `own_project`
+    pub fn matches(&self, labels: &HashMap<String, String>, own_project: Option<&str>) -> bool {
+        let project = self.project.as_deref().or(own_project);
+        labels.get(COMPOSE_SERVICE_LABEL) == Some(&self.service)
+            && labels.get(COMPOSE_PROJECT_LABEL).map(String::as_str) == project
+    }
+}
+
+impl std::fmt::Display for ServiceRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.project {
+            Some(project) => write!(f, "service {} of {}", self.service, project),
+            None => write!(f, "service {}", self.service),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Service names for rule ports, so `dst_ports: [https, domain]` can stand
+//! in for the numbers.
+//!
+//! Names are looked up for the rule's protocol in `/etc/services`, aliases
+//! included. Images without the file, such as slim ones missing netbase,
+//! fall back to a built-in table of common services.
+
+use super::{Protocol, RulePorts, ValidationError};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+const SERVICES_FILE: &str = "/etc/services";
+
+/// Used when `/etc/services` can't be read
+const BUILTIN: &str = "\
+ftp 21/tcp
+ssh 22/tcp
+telnet 23/tcp
+smtp 25/tcp mail
+domain 53/tcp
+domain 53/udp
+bootps 67/udp
+bootpc 68/udp
+tftp 69/udp
+http 80/tcp www
+kerberos 88/tcp kerberos5
+kerberos 88/udp kerberos5
+pop3 110/tcp
+ntp 123/udp
+imap 143/tcp imap2
+snmp 161/udp
+ldap 389/tcp
+https 443/tcp
+https 443/udp
+submissions 465/tcp ssmtp smtps
+syslog 514/udp
+submission 587/tcp
+ldaps 636/tcp
+imaps 993/tcp
+pop3s 995/tcp
+mysql 3306/tcp
+postgresql 5432/tcp postgres
+amqp 5672/tcp
+redis 6379/tcp
+http-alt 8080/tcp webcache
+";
+
+static SERVICES: LazyLock<HashMap<(String, Protocol), u16>> = LazyLock::new(|| {
+    let text = std::fs::read_to_string(SERVICES_FILE).unwrap_or_else(|_| BUILTIN.to_string());
+    parse_services(&text)
+});
+
+/// Lines of `name port/proto [aliases...] [# comment]`
+fn parse_services(text: &str) -> HashMap<(String, Protocol), u16> {
+    let mut services = HashMap::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(port)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Some((port, proto)) = port.split_once('/') else {
+            continue;
+        };
+        let proto = match proto {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            _ => continue,
+        };
+        let Ok(port) = port.parse::<u16>() else {
+            continue;
+        };
+        for name in std::iter::once(name).chain(fields) {
+            services.entry((name.to_string(), proto)).or_insert(port);
+        }
+    }
+    services
+}
+
+pub fn lookup(name: &str, proto: Protocol) -> Option<u16> {
+    SERVICES.get(&(name.to_string(), proto)).copied()
+}
+
+/// Parse the entries of a rule's port list: numbers, ranges or service names
+pub fn resolve(specs: &[String], proto: Protocol) -> Result<Vec<RulePorts>, ValidationError> {
+    specs
+        .iter()
+        .map(|spec| {
+            if spec.starts_with(|c: char| c.is_ascii_digit()) {
+                spec.parse()
+                    .map_err(|e: crate::Error| ValidationError::InvalidPort {
+                        port: spec.clone(),
+                        reason: e.to_string(),
+                    })
+            } else {
+                lookup(spec, proto).map(RulePorts::Single).ok_or_else(|| {
+                    ValidationError::InvalidPort {
+                        port: spec.clone(),
+                        reason: format!("no {} service of that name in {}", proto, SERVICES_FILE),
+                    }
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_services() {
+        let services = parse_services(
+            "# comment\nhttp\t\t80/tcp\t\twww\t# WorldWideWeb HTTP\ndomain 53/udp\nbogus 70000/tcp\nddp 2/ddp\n",
+        );
+        assert_eq!(services.get(&("www".to_string(), Protocol::Tcp)), Some(&80));
+        assert_eq!(
+            services.get(&("domain".to_string(), Protocol::Udp)),
+            Some(&53)
+        );
+        assert!(!services.contains_key(&("domain".to_string(), Protocol::Tcp)));
+        assert_eq!(services.len(), 3);
+
+        let builtin = parse_services(BUILTIN);
+        assert_eq!(
+            builtin.get(&("https".to_string(), Protocol::Tcp)),
+            Some(&443)
+        );
+        assert_eq!(
+            builtin.get(&("postgres".to_string(), Protocol::Tcp)),
+            Some(&5432)
+        );
+    }
+}
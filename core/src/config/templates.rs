@@ -0,0 +1,171 @@
+//! Ready-made rule sets for common kinds of services.
+//!
+//! `harborshield examples` prints these as compose labels. They are checked
+//! against the current schema by parsing them into [`Config`], so an example
+//! can never drift from what the label parser accepts.
+
+use super::Config;
+use crate::{ENABLED_LABEL, RULES_LABEL};
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scenario {
+    /// Public HTTP(S) entry point forwarding to an app container
+    ReverseProxy,
+    /// Database only reachable from the host and from allowed containers
+    Database,
+    /// Publicly reachable game server that registers with a master server
+    GameServer,
+    /// Background worker calling external HTTPS APIs
+    Worker,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Template {
+    pub scenario: Scenario,
+    pub summary: &'static str,
+    /// Value of the rules label
+    pub rules: &'static str,
+}
+
+const REVERSE_PROXY: &str = "\
+mapped_ports:
+  external:
+    allow: true
+output:
+  # Forward to the application; replace with your service and network
+  - network: backend
+    container: app
+    proto: tcp
+    dst_ports:
+      - 8080
+";
+
+const DATABASE: &str = "\
+mapped_ports:
+  localhost:
+    allow: true
+# No output rules: the database makes no connections of its own.
+# Clients are let in by an output rule naming this container.
+";
+
+const GAME_SERVER: &str = "\
+mapped_ports:
+  external:
+    allow: true
+    log_prefix: game-in
+output:
+  # Master server registration
+  - proto: tcp
+    dst_ports:
+      - 443
+";
+
+const WORKER: &str = "\
+output:
+  - proto: tcp
+    dst_ports:
+      - 443
+  - proto: udp
+    dst_ports:
+      - 53
+";
+
+impl Scenario {
+    pub fn template(&self) -> Template {
+        let (summary, rules) = match self {
+            Scenario::ReverseProxy => (
+                "Accept published ports from anywhere and only talk to the app behind it",
+                REVERSE_PROXY,
+            ),
+            Scenario::Database => (
+                "Accept connections from the host, make no outbound connections",
+                DATABASE,
+            ),
+            Scenario::GameServer => (
+                "Accept players on published ports, register with a master server over HTTPS",
+                GAME_SERVER,
+            ),
+            Scenario::Worker => ("Accept nothing, reach external HTTPS APIs and DNS", WORKER),
+        };
+
+        Template {
+            scenario: *self,
+            summary,
+            rules,
+        }
+    }
+}
+
+impl Template {
+    /// The rules parsed against the current schema
+    pub fn config(&self) -> std::result::Result<Config, serde_yaml::Error> {
+        serde_yaml::from_str(self.rules)
+    }
+
+    /// Compose `labels:` block enabling harborshield with these rules
+    pub fn labels_yaml(&self) -> String {
+        labels_yaml(self.rules)
+    }
+}
+
+/// Compose labels enabling harborshield with `rules` as the rules label
+pub fn labels_yaml(rules: &str) -> String {
+    let mut out = format!(
+        "labels:\n  {}: \"true\"\n  {}: |\n",
+        ENABLED_LABEL, RULES_LABEL
+    );
+    for line in rules.lines() {
+        if line.is_empty() {
+            out.push('\n');
+        } else {
+            out.push_str("    ");
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Every available example, for `harborshield examples` without a scenario
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateList {
+    pub templates: Vec<Template>,
+}
+
+impl TemplateList {
+    pub fn all() -> Self {
+        Self {
+            templates: Scenario::value_variants()
+                .iter()
+                .map(Scenario::template)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_match_schema() {
+        for scenario in Scenario::value_variants() {
+            let template = scenario.template();
+            if let Err(e) = template.config() {
+                panic!("{:?} example does not parse: {}", scenario, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_labels_yaml_round_trips() {
+        let template = Scenario::ReverseProxy.template();
+        let labels: serde_yaml::Value = serde_yaml::from_str(&template.labels_yaml()).unwrap();
+        let rules = labels["labels"][RULES_LABEL].as_str().unwrap();
+        assert_eq!(rules, template.rules);
+        assert_eq!(labels["labels"][ENABLED_LABEL].as_str(), Some("true"));
+    }
+}
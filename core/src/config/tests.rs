@@ -99,6 +99,7 @@ mod tests {
     fn test_protocol_serialization() {
         assert_eq!(Protocol::Tcp.to_string(), "tcp");
         assert_eq!(Protocol::Udp.to_string(), "udp");
+        assert_eq!(Protocol::Icmpv6.to_string(), "icmpv6");
     }
 
     #[test]
@@ -109,13 +110,34 @@ mod tests {
                 log_prefix: String::new(),
                 network: String::new(),
                 ips: vec![],
+                ip_templates: vec![],
+                host: false,
+                hostname: None,
+                dns_family: None,
+                dns_require_both: None,
+                dns_refresh: None,
                 container: String::new(),
+                from: None,
                 proto: Protocol::Tcp,
                 src_ports: vec![],
                 dst_ports: vec![],
+                icmp_types: vec![],
                 verdict: ConfigVerdict::default(),
+                time: None,
+                enabled: true,
+                reason: None,
+                offload: false,
+                notify_on_hit: false,
+                tproxy: None,
+                expose_to_internet: false,
+                attachment: vec![],
                 skip: false,
             }],
+            expected_subnet: None,
+            storage_egress: false,
+            quarantine: None,
+            raw_rules: Vec::new(),
+            forward: Vec::new(),
         };
 
         let result = config.validate();
@@ -131,13 +153,34 @@ mod tests {
                 log_prefix: String::new(),
                 network: String::new(),
                 ips: vec!["192.168.1.1".parse().unwrap()],
+                ip_templates: vec![],
+                host: false,
+                hostname: None,
+                dns_family: None,
+                dns_require_both: None,
+                dns_refresh: None,
                 container: "test".to_string(),
+                from: None,
                 proto: Protocol::Tcp,
                 src_ports: vec![],
                 dst_ports: vec![RulePorts::Single(80)],
+                icmp_types: vec![],
                 verdict: ConfigVerdict::default(),
+                time: None,
+                enabled: true,
+                reason: None,
+                offload: false,
+                notify_on_hit: false,
+                tproxy: None,
+                expose_to_internet: false,
+                attachment: vec![],
                 skip: false,
             }],
+            expected_subnet: None,
+            storage_egress: false,
+            quarantine: None,
+            raw_rules: Vec::new(),
+            forward: Vec::new(),
         };
 
         let result = config.validate();
@@ -158,13 +201,34 @@ mod tests {
                 log_prefix: String::new(),
                 network: String::new(), // Empty network
                 ips: vec![],
+                ip_templates: vec![],
+                host: false,
+                hostname: None,
+                dns_family: None,
+                dns_require_both: None,
+                dns_refresh: None,
                 container: "test".to_string(),
+                from: None,
                 proto: Protocol::Tcp,
                 src_ports: vec![],
                 dst_ports: vec![RulePorts::Single(80)],
+                icmp_types: vec![],
                 verdict: ConfigVerdict::default(),
+                time: None,
+                enabled: true,
+                reason: None,
+                offload: false,
+                notify_on_hit: false,
+                tproxy: None,
+                expose_to_internet: false,
+                attachment: vec![],
                 skip: false,
             }],
+            expected_subnet: None,
+            storage_egress: false,
+            quarantine: None,
+            raw_rules: Vec::new(),
+            forward: Vec::new(),
         };
 
         let result = config.validate();
@@ -206,19 +270,41 @@ mod tests {
                     log_prefix: String::new(),
                     ips: vec!["192.168.1.0/24".parse().unwrap()],
                     verdict: ConfigVerdict::default(),
+                    ..Default::default()
                 },
             },
             output: vec![RuleConfig {
                 log_prefix: String::new(),
                 network: "default".to_string(),
                 ips: vec![],
+                ip_templates: vec![],
+                host: false,
+                hostname: None,
+                dns_family: None,
+                dns_require_both: None,
+                dns_refresh: None,
                 container: "database".to_string(),
+                from: None,
                 proto: Protocol::Tcp,
                 src_ports: vec![],
                 dst_ports: vec![RulePorts::Single(5432)],
+                icmp_types: vec![],
                 verdict: ConfigVerdict::default(),
+                time: None,
+                enabled: true,
+                reason: None,
+                offload: false,
+                notify_on_hit: false,
+                tproxy: None,
+                expose_to_internet: false,
+                attachment: vec![],
                 skip: false,
             }],
+            expected_subnet: None,
+            storage_egress: false,
+            quarantine: None,
+            raw_rules: Vec::new(),
+            forward: Vec::new(),
         };
 
         assert!(config.validate().is_ok());
@@ -335,6 +421,32 @@ mapped_ports:
         assert_eq!(config.mapped_ports.external.ips.len(), 3);
     }
 
+    #[test]
+    fn test_external_interface_set() {
+        let yaml = r#"
+mapped_ports:
+  external:
+    allow: true
+    iif_in: [eth1, eth0, eth1]
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.mapped_ports.external.iif_in, vec!["eth0", "eth1"]);
+        let statements = config
+            .mapped_ports
+            .external
+            .to_nftables_statements()
+            .unwrap();
+        let json = serde_json::to_string(&statements[0]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"match":{"left":{"meta":{"key":"iifname"}},"right":{"set":["eth0","eth1"]},"op":"=="}}"#
+        );
+
+        let invalid = yaml.replace("eth0", "a-very-long-ifname");
+        assert!(serde_yaml::from_str::<Config>(&invalid).is_err());
+    }
+
     #[test]
     fn test_zero_ip_validation() {
         let yaml = r#"
@@ -349,4 +461,169 @@ output:
         let err = result.unwrap_err();
         assert!(err.to_string().contains("0.0.0.0/0"));
     }
+
+    #[test]
+    fn test_duplicate_output_rules_merged() {
+        let yaml = r#"
+output:
+  - proto: tcp
+    dst_ports: ["443"]
+    ips: ["10.0.0.1"]
+  - proto: udp
+    dst_ports: ["53"]
+  - proto: tcp
+    dst_ports: ["443"]
+    ips: ["10.0.0.1"]
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.duplicate_output_rules(), vec![vec![1, 3]]);
+
+        let ctx = RuleContext {
+            container_id: "abc123",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &[],
+            chain_name: "hs-web-abc123",
+            table_name: "harborshield",
+            family: nftables::types::NfFamily::IP,
+        };
+        let numbered = config
+            .output
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| (i + 1, rule.to_nftables_statements().unwrap()))
+            .collect();
+        let rules = nftables_convert::merge_output_rules(&ctx, numbered);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            rules[0].comment.as_deref(),
+            Some("Output rules 1, 3 for web")
+        );
+        assert_eq!(rules[1].comment.as_deref(), Some("Output rule 2 for web"));
+    }
+
+    #[test]
+    fn test_raw_rules() {
+        let config: Config =
+            serde_yaml::from_str("raw_rules:\n  - meta l4proto gre accept\n").unwrap();
+        assert_eq!(config.raw_rules, vec!["meta l4proto gre accept"]);
+        // Raw rules are written for the IPv4 chain
+        assert!(
+            config
+                .for_family(nftables::types::NfFamily::IP6, Default::default())
+                .raw_rules
+                .is_empty()
+        );
+
+        for yaml in [
+            "raw_rules: ['']",
+            "raw_rules: ['accept; flush ruleset']",
+            "raw_rules: ['accept # all']",
+        ] {
+            assert!(serde_yaml::from_str::<Config>(yaml).is_err(), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_forward() {
+        let config: Config = serde_yaml::from_str(
+            "forward:\n  - host_port: 8080\n    port: 80\n  - host_port: 8080\n    proto: udp\n",
+        )
+        .unwrap();
+        assert_eq!(config.forward[0].container_port(), 80);
+        assert_eq!(config.forward[1].container_port(), 8080);
+        assert!(config.forward[1].hairpin);
+
+        for yaml in [
+            "forward: [{host_port: 0}]",
+            "forward: [{host_port: 8080, proto: icmp}]",
+            "forward: [{host_port: 8080}, {host_port: 8080, port: 81}]",
+            "forward: [{host_port: 8080, host_ip: 192.0.2.10}, {host_port: 8080}]",
+        ] {
+            assert!(serde_yaml::from_str::<Config>(yaml).is_err(), "{}", yaml);
+        }
+        assert!(
+            serde_yaml::from_str::<Config>(
+                "forward: [{host_port: 8080, host_ip: 192.0.2.10}, {host_port: 8080, host_ip: 192.0.2.11}]"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_icmp_rules() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+output:
+  - from: {service: monitor}
+    proto: icmp
+    icmp_types: [echo-request, destination-unreachable]
+  - ips: ["fd00::1"]
+    proto: icmpv6
+    icmp_types: [echo-request]
+"#,
+        )
+        .unwrap();
+        let statements = serde_json::to_value(config.output[0].to_nftables_statements().unwrap())
+            .unwrap()
+            .to_string();
+        assert!(
+            statements.contains(
+                r#"{"match":{"left":{"payload":{"field":"type","protocol":"icmp"}},"op":"==","right":{"set":["echo-request","destination-unreachable"]}}}"#
+            ),
+            "{}",
+            statements
+        );
+        assert!(!statements.contains("dport"));
+
+        // Each protocol only applies in its own family's table
+        let ipv4 = config.for_family(nftables::types::NfFamily::IP, Default::default());
+        assert!(!ipv4.output[0].skip && ipv4.output[1].skip);
+        let ipv6 = config.for_family(nftables::types::NfFamily::IP6, Default::default());
+        assert!(ipv6.output[0].skip && !ipv6.output[1].skip);
+
+        for yaml in [
+            "output: [{proto: icmp, ips: [10.0.0.1], dst_ports: [\"8\"]}]",
+            "output: [{proto: icmp, icmp_types: [nd-neighbor-solicit]}]",
+            "output: [{proto: tcp, dst_ports: [\"443\"], icmp_types: [echo-request]}]",
+            "output: [{proto: icmp, ips: [\"fd00::1\"]}]",
+            "output: [{proto: icmp}]",
+        ] {
+            assert!(serde_yaml::from_str::<Config>(yaml).is_err(), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_tproxy_validation() {
+        let rule = |extra: &str| {
+            serde_yaml::from_str::<Config>(&format!(
+                "output: [{{ips: [10.0.0.0/8], proto: tcp, dst_ports: [\"80\"], {}}}]",
+                extra
+            ))
+        };
+        let config = rule("tproxy: {port: 8080, mark: 1}").unwrap();
+        assert_eq!(
+            config.output[0].tproxy,
+            Some(Tproxy {
+                port: 8080,
+                mark: 1
+            })
+        );
+
+        for extra in [
+            "tproxy: {port: 8080, mark: 0}",
+            "tproxy: {port: 0, mark: 1}",
+            "tproxy: {port: 8080, mark: 1}, offload: true",
+            "tproxy: {port: 8080, mark: 1}, verdict: {queue: 2}",
+        ] {
+            assert!(rule(extra).is_err(), "{}", extra);
+        }
+        assert!(
+            serde_yaml::from_str::<Config>(
+                "output: [{from: {service: api}, proto: tcp, dst_ports: [\"80\"], tproxy: {port: 8080, mark: 1}}]"
+            )
+            .is_err()
+        );
+    }
 }
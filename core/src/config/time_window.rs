@@ -0,0 +1,250 @@
+use crate::tz::TimeZone;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Day {
+    pub const ALL: [Day; 7] = [
+        Day::Mon,
+        Day::Tue,
+        Day::Wed,
+        Day::Thu,
+        Day::Fri,
+        Day::Sat,
+        Day::Sun,
+    ];
+
+    fn from_weekday(weekday: chrono::Weekday) -> Self {
+        Self::ALL[weekday.num_days_from_monday() as usize]
+    }
+
+    fn previous(self) -> Self {
+        Self::ALL[(self as usize + 6) % 7]
+    }
+}
+
+/// Wall-clock time as minutes since midnight, written `HH:MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    pub fn new(hour: u16, minute: u16) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self(hour * 60 + minute))
+    }
+
+    pub fn minutes(&self) -> u16 {
+        self.0
+    }
+}
+
+impl std::str::FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split_once(':')
+            .and_then(|(h, m)| Self::new(h.parse().ok()?, m.parse().ok()?))
+            .ok_or_else(|| format!("Invalid time '{}', expected HH:MM", s))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Days and hours during which a rule is in effect, in `tz` or the host's
+/// zone. A window whose end is not after its start runs past midnight into
+/// the following day.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimeWindow {
+    pub days: Vec<Day>,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+    /// tz database name such as `Europe/Berlin`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+}
+
+impl TimeWindow {
+    fn zone(&self) -> Arc<TimeZone> {
+        match &self.tz {
+            Some(name) => TimeZone::named(name).unwrap_or_else(|_| Arc::new(TimeZone::utc())),
+            None => TimeZone::local(),
+        }
+    }
+
+    /// Whether the window is open at `unix` seconds. Local time is derived
+    /// from the zone's offset at that instant, so daylight saving changes
+    /// shift the window with the wall clock.
+    pub fn is_active(&self, unix: i64) -> bool {
+        let offset = self.zone().offset_at(unix) as i64;
+        let Some(local) = chrono::DateTime::from_timestamp(unix + offset, 0) else {
+            return false;
+        };
+        let local = local.naive_utc();
+        let day = Day::from_weekday(chrono::Datelike::weekday(&local));
+        let minute =
+            chrono::Timelike::hour(&local) as u16 * 60 + chrono::Timelike::minute(&local) as u16;
+        let (start, end) = (self.start.minutes(), self.end.minutes());
+
+        if start < end {
+            self.days.contains(&day) && minute >= start && minute < end
+        } else {
+            (self.days.contains(&day) && minute >= start)
+                || (self.days.contains(&day.previous()) && minute < end)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeWindow {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct TempTimeWindow {
+            #[serde(default = "all_days")]
+            days: Vec<Day>,
+            start: TimeOfDay,
+            end: TimeOfDay,
+            #[serde(default)]
+            tz: Option<String>,
+        }
+
+        fn all_days() -> Vec<Day> {
+            Day::ALL.to_vec()
+        }
+
+        let temp = TempTimeWindow::deserialize(deserializer)?;
+
+        if temp.days.is_empty() {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "time.days".to_string(),
+                    reason: "A time window needs at least one day".to_string(),
+                    value: "[]".to_string(),
+                    expected_format: Some("List of mon, tue, wed, thu, fri, sat, sun".to_string()),
+                },
+            ));
+        }
+
+        if temp.start == temp.end {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "time".to_string(),
+                    reason: "Start and end are the same".to_string(),
+                    value: temp.start.to_string(),
+                    expected_format: Some("Different start and end times".to_string()),
+                },
+            ));
+        }
+
+        if let Some(tz) = &temp.tz
+            && let Err(e) = TimeZone::named(tz)
+        {
+            return Err(serde::de::Error::custom(
+                super::ValidationError::InvalidFieldValue {
+                    field: "time.tz".to_string(),
+                    reason: format!("Unknown time zone: {}", e),
+                    value: tz.clone(),
+                    expected_format: Some("tz database name such as Europe/Berlin".to_string()),
+                },
+            ));
+        }
+
+        Ok(TimeWindow {
+            days: temp.days,
+            start: temp.start,
+            end: temp.end,
+            tz: temp.tz,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(s).unwrap().timestamp()
+    }
+
+    fn window(yaml: &str) -> TimeWindow {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_utc_window() {
+        let w = window("{days: [mon, tue, wed, thu, fri], start: '08:00', end: '18:00', tz: UTC}");
+        // 2024-05-06 is a Monday
+        assert!(w.is_active(ts("2024-05-06T08:00:00Z")));
+        assert!(!w.is_active(ts("2024-05-06T18:00:00Z")));
+        assert!(!w.is_active(ts("2024-05-05T12:00:00Z")));
+    }
+
+    #[test]
+    fn test_overnight_window() {
+        let w = window("{days: [fri], start: '22:00', end: '02:00', tz: UTC}");
+        assert!(w.is_active(ts("2024-05-10T23:00:00Z")));
+        // Saturday morning belongs to Friday's window
+        assert!(w.is_active(ts("2024-05-11T01:59:00Z")));
+        assert!(!w.is_active(ts("2024-05-11T22:30:00Z")));
+    }
+
+    #[test]
+    fn test_dst_follows_wall_clock() {
+        let Ok(_) = TimeZone::named("Europe/Berlin") else {
+            // No tz database on this machine
+            return;
+        };
+        let w = window("{start: '09:00', end: '10:00', tz: Europe/Berlin}");
+        // 09:00 in Berlin is 08:00 UTC in winter and 07:00 UTC in summer
+        assert!(w.is_active(ts("2024-01-15T08:30:00Z")));
+        assert!(!w.is_active(ts("2024-01-15T07:30:00Z")));
+        assert!(w.is_active(ts("2024-07-15T07:30:00Z")));
+        assert!(!w.is_active(ts("2024-07-15T08:30:00Z")));
+    }
+
+    #[test]
+    fn test_invalid_windows() {
+        for yaml in [
+            "{start: '25:00', end: '10:00'}",
+            "{start: '09:00', end: '09:00'}",
+            "{days: [], start: '09:00', end: '10:00'}",
+            "{start: '09:00', end: '10:00', tz: Mars/Olympus_Mons}",
+        ] {
+            assert!(
+                serde_yaml::from_str::<TimeWindow>(yaml).is_err(),
+                "{}",
+                yaml
+            );
+        }
+    }
+}
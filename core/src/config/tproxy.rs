@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// Hand the flows an output rule matches to a local interception service
+/// instead of routing them on:
+///
+/// ```yaml
+/// output:
+///   - ips: [10.0.0.0/8]
+///     proto: tcp
+///     dst_ports: [80, 443]
+///     tproxy:
+///       port: 8443
+///       mark: 1
+/// ```
+///
+/// The service listens on `port` with `IP_TRANSPARENT`, and packets marked
+/// `mark` must be routed to the host itself; `harborshield status` prints
+/// the routes. See `harborshield::nftables::tproxy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tproxy {
+    pub port: u16,
+    pub mark: u32,
+}
@@ -0,0 +1,66 @@
+//! The hostname rules of built chains.
+//!
+//! Building a chain with hostname rules yields one target per rule and
+//! family: the set to keep filled with the addresses the name resolves to.
+//! Resolving the names and updating the sets is up to the caller (the
+//! daemon's refresher).
+
+use super::LookupPolicy;
+use std::net::IpAddr;
+
+/// A name and the policy it is resolved under
+pub type Query = (String, LookupPolicy);
+
+/// The set holding one hostname rule's addresses of one family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostTarget {
+    pub ipv6: bool,
+    pub chain: String,
+    pub set: String,
+    pub hostname: String,
+    pub policy: LookupPolicy,
+    /// Seconds between lookups
+    pub refresh: u32,
+}
+
+impl HostTarget {
+    pub fn query(&self) -> Query {
+        (self.hostname.clone(), self.policy)
+    }
+}
+
+/// Normalize a hostname to resolve, or `None` if it isn't one
+pub fn parse_hostname(name: &str) -> Option<String> {
+    let name = name.trim().trim_end_matches('.').to_lowercase();
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    // An address is not a name, and a single label is left to search domains
+    let is_name = name.len() <= 253
+        && name.contains('.')
+        && name.parse::<IpAddr>().is_err()
+        && name.split('.').all(valid_label);
+    is_name.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hostname() {
+        assert_eq!(
+            parse_hostname("API.Stripe.com.").as_deref(),
+            Some("api.stripe.com")
+        );
+        assert!(parse_hostname("10.0.0.1").is_none());
+        assert!(parse_hostname("localhost").is_none());
+        assert!(parse_hostname("*.stripe.com").is_none());
+    }
+}
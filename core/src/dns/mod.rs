@@ -0,0 +1,37 @@
+//! How hostnames in rules are resolved: the address families asked for and
+//! how often. Rules that don't choose their own follow the policy and
+//! interval the caller passes in, the daemon's `--dns-family`,
+//! `--dns-require-both` and `--dns-refresh`.
+
+pub mod hostname;
+pub mod rdns;
+
+use serde::{Deserialize, Serialize};
+
+/// Seconds between lookups of a hostname rule's name, unless it or
+/// `--dns-refresh` says otherwise
+pub const DEFAULT_REFRESH: u32 = 300;
+
+/// Which address records a hostname is resolved to
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LookupFamily {
+    /// IPv4 only
+    A,
+    /// IPv6 only
+    Aaaa,
+    /// IPv4 and IPv6
+    #[default]
+    Both,
+}
+
+/// How hostnames are resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LookupPolicy {
+    pub family: LookupFamily,
+    /// With [`LookupFamily::Both`], fail unless both families resolve, so a
+    /// missing AAAA record isn't taken as "IPv4 only"
+    pub require_both: bool,
+}
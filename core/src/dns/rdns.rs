@@ -0,0 +1,158 @@
+//! Hostname patterns of rules gated on forward-confirmed reverse DNS, and
+//! what to do with a source when its lookups fail.
+
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Default lifetime of a verified source in seconds
+pub const DEFAULT_RDNS_TTL: u32 = 3600;
+
+/// Default seconds a failed verification is remembered before retrying
+pub const DEFAULT_RDNS_NEGATIVE_TTL: u32 = 300;
+
+/// What to do with a source whose DNS lookups fail
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RdnsFailurePolicy {
+    /// Keep sources verified before, refuse new ones
+    Keep,
+    /// Refuse the source
+    #[default]
+    Closed,
+    /// Allow the source
+    Open,
+}
+
+impl RdnsFailurePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Closed => "closed",
+            Self::Open => "open",
+        }
+    }
+}
+
+/// Hostname pattern such as `crawl.googlebot.com` or `*.googlebot.com`.
+///
+/// A leading `*.` matches one or more labels, so `*.googlebot.com` matches
+/// `crawl-66-249-66-1.googlebot.com` but not `googlebot.com` itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostnamePattern {
+    suffix: String,
+    wildcard: bool,
+}
+
+impl HostnamePattern {
+    pub fn matches(&self, hostname: &str) -> bool {
+        let hostname = hostname.trim_end_matches('.').to_lowercase();
+        if self.wildcard {
+            hostname
+                .strip_suffix(&self.suffix)
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.'))
+        } else {
+            hostname == self.suffix
+        }
+    }
+}
+
+impl fmt::Display for HostnamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.wildcard {
+            write!(f, "*.{}", self.suffix)
+        } else {
+            write!(f, "{}", self.suffix)
+        }
+    }
+}
+
+impl FromStr for HostnamePattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim().trim_end_matches('.').to_lowercase();
+        let (wildcard, suffix) = match s.strip_prefix("*.") {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, s.clone()),
+        };
+
+        if suffix.is_empty() || !suffix.contains('.') {
+            return Err(Error::config(format!(
+                "Invalid rdns pattern '{}': expected a hostname with at least two labels",
+                s
+            )));
+        }
+
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if !suffix.split('.').all(valid_label) {
+            return Err(Error::config(format!(
+                "Invalid rdns pattern '{}': only a leading '*.' wildcard and letters, digits, '-' and '_' are allowed",
+                s
+            )));
+        }
+
+        Ok(Self { suffix, wildcard })
+    }
+}
+
+impl Serialize for HostnamePattern {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for HostnamePattern {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_matching() {
+        let wildcard: HostnamePattern = "*.googlebot.com".parse().unwrap();
+        assert!(wildcard.matches("crawl-66-249-66-1.googlebot.com"));
+        assert!(wildcard.matches("a.b.googlebot.com."));
+        assert!(!wildcard.matches("googlebot.com"));
+        assert!(!wildcard.matches("evilgooglebot.com"));
+
+        let exact: HostnamePattern = "crawl.example.org".parse().unwrap();
+        assert!(exact.matches("CRAWL.example.org"));
+        assert!(!exact.matches("x.crawl.example.org"));
+    }
+
+    #[test]
+    fn test_pattern_validation() {
+        assert!("localhost".parse::<HostnamePattern>().is_err());
+        assert!("*.".parse::<HostnamePattern>().is_err());
+        assert!("foo.*.com".parse::<HostnamePattern>().is_err());
+        assert!("-bad.example.com".parse::<HostnamePattern>().is_err());
+        assert_eq!(
+            "*.Example.COM."
+                .parse::<HostnamePattern>()
+                .unwrap()
+                .to_string(),
+            "*.example.com"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use crate::config::profiles::ProfileError;
+use crate::config::validation::error::ValidationError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The rules YAML doesn't parse or validate
+    #[error("invalid rules: {0}")]
+    Rules(#[from] ProfileError),
+
+    #[error("Configuration error at {location}: {message}")]
+    Config {
+        message: String,
+        location: String,
+        suggestion: Option<String>,
+    },
+
+    #[error("Invalid IP address or range: {input} - {reason}")]
+    InvalidIpAddress { input: String, reason: String },
+
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+
+    #[error("YAML parsing error")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("JSON parsing error")]
+    Json(#[from] serde_json::Error),
+
+    /// Running `nft` failed
+    #[error(transparent)]
+    Nftables(#[from] nftables::helper::NftablesError),
+}
+
+impl Error {
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::Config {
+            message: message.into(),
+            location: "unknown".to_string(),
+            suggestion: None,
+        }
+    }
+
+    pub fn invalid_ip(input: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::InvalidIpAddress {
+            input: input.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
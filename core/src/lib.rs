@@ -0,0 +1,106 @@
+//! harborshield's policy engine, for embedding in other Rust projects
+//! without the daemon that watches Docker.
+//!
+//! A [`ContainerPolicy`] is one container's rules (the YAML of its
+//! `harborshield.rules` label) with the addresses and published ports they
+//! apply to. A [`RuleSetBuilder`] turns policies into the nftables objects
+//! the daemon would install, as a batch to apply with `nft -j -f -` or
+//! hand to the `nftables` crate. A [`Reconciler`] compares what a rule set
+//! expects with a live ruleset listing, reporting missing and left-over
+//! container chains.
+//!
+//! ```
+//! use harborshield_core::{ContainerPolicy, Reconciler, RuleSetBuilder};
+//!
+//! let rules = ContainerPolicy::parse_rules(
+//!     "output:\n  - {ips: [10.0.5.20], proto: tcp, dst_ports: [5432]}\n",
+//! )?;
+//! let policy = ContainerPolicy::builder()
+//!     .id("4f1c2a9e0b7d")
+//!     .name("web")
+//!     .ips(vec!["172.18.0.2".parse().unwrap()])
+//!     .rules(rules)
+//!     .build();
+//!
+//! let ruleset = RuleSetBuilder::new().policy(policy).build()?;
+//! let json = ruleset.to_json()?;
+//! assert!(json.contains("hs-web-4f1c2a9e0b7d"));
+//!
+//! // Nothing is installed yet, so the chain is reported missing
+//! let drift = Reconciler::new(&ruleset).drift(&serde_json::json!({"nftables": []}));
+//! assert_eq!(drift.len(), 1);
+//! # Ok::<(), harborshield_core::Error>(())
+//! ```
+//!
+//! The daemon builds its chains with this crate, so a rule set built here
+//! is what the daemon would install for the same policies and settings.
+//! What the daemon takes from its command line (profiles, sensitive ports,
+//! the NFLOG group and DNS policy) is set on the [`RuleSetBuilder`]. Each
+//! build stands alone: the crate keeps no state between calls, is
+//! synchronous and touches neither Docker nor the database; only
+//! [`Reconciler::drift_live`] runs `nft`.
+
+pub mod config;
+pub mod dns;
+mod error;
+mod mode;
+pub mod network;
+pub mod nftables;
+mod policy;
+mod reconcile;
+mod ruleset;
+pub mod tz;
+
+use std::time::Duration;
+
+pub use ::nftables::types::NfFamily;
+pub use config::{AddrOrRange, Config, ConfigVerdict, MappedPorts, Protocol, RuleConfig};
+pub use error::{Error, Result};
+pub use mode::EnforcementMode;
+pub use policy::ContainerPolicy;
+pub use reconcile::Reconciler;
+pub use reconcile::{Drift, DriftKind, ExpectedChain, find_drift, parse_chain_rule_counts};
+pub use ruleset::{RuleSet, RuleSetBuilder};
+
+pub const ENABLED_LABEL: &str = "harborshield.enabled";
+pub const RULES_LABEL: &str = "harborshield.rules";
+
+/// Docker Compose label constants
+pub const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+pub const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+
+    if let Some(stripped) = s.strip_suffix("ms") {
+        stripped
+            .parse::<u64>()
+            .map(Duration::from_millis)
+            .map_err(|e| format!("Invalid milliseconds: {}", e))
+    } else if let Some(stripped) = s.strip_suffix('s') {
+        stripped
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|e| format!("Invalid seconds: {}", e))
+    } else if let Some(stripped) = s.strip_suffix('m') {
+        stripped
+            .parse::<u64>()
+            .map(|m| Duration::from_secs(m * 60))
+            .map_err(|e| format!("Invalid minutes: {}", e))
+    } else if let Some(stripped) = s.strip_suffix('h') {
+        stripped
+            .parse::<u64>()
+            .map(|h| Duration::from_secs(h * 3600))
+            .map_err(|e| format!("Invalid hours: {}", e))
+    } else if let Some(stripped) = s.strip_suffix('d') {
+        stripped
+            .parse::<u64>()
+            .map(|d| Duration::from_secs(d * 86400))
+            .map_err(|e| format!("Invalid days: {}", e))
+    } else {
+        // Default to seconds if no suffix
+        s.parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|e| format!("Invalid duration: {}", e))
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// How a container's chain treats traffic its rules don't allow
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementMode {
+    /// Drop traffic that no rule allows
+    #[default]
+    Enforce,
+    /// Log traffic that would be dropped, but let it through
+    Permissive,
+    /// Ignore the container's rules and accept everything
+    Disabled,
+    /// Deny all egress and admit inbound traffic only from the management
+    /// addresses of the container's `quarantine` policy
+    Quarantined,
+}
+
+impl EnforcementMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnforcementMode::Enforce => "enforce",
+            EnforcementMode::Permissive => "permissive",
+            EnforcementMode::Disabled => "disabled",
+            EnforcementMode::Quarantined => "quarantined",
+        }
+    }
+}
+
+impl std::fmt::Display for EnforcementMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for EnforcementMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "enforce" => Ok(EnforcementMode::Enforce),
+            "permissive" => Ok(EnforcementMode::Permissive),
+            "disabled" => Ok(EnforcementMode::Disabled),
+            "quarantined" => Ok(EnforcementMode::Quarantined),
+            other => Err(format!(
+                "Unknown enforcement mode '{}', expected enforce, permissive, disabled or quarantined",
+                other
+            )),
+        }
+    }
+}
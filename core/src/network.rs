@@ -0,0 +1,13 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Information about a Docker network including gateway IPs
+#[derive(Debug, Clone)]
+pub struct NetworkGatewayInfo {
+    pub network_id: String,
+    pub network_name: String,
+    pub gateway_ips: Vec<IpAddr>,
+    pub subnet: Option<String>,
+    /// Every subnet of the network, for address templates
+    pub subnets: Vec<IpNet>,
+}
@@ -0,0 +1,165 @@
+//! nftables meters backing `max_connections_per_ip` on external rules.
+//!
+//! Each mapped port gets a rule ahead of the external allow rules that
+//! drops new connections from a source already holding the maximum. The
+//! count lives in a dynamic meter per port, which nft creates with the rule.
+//! Deleting the meters of ports a chain no longer limits, and those of
+//! deleted chains, is up to whoever keeps track of the installed chains.
+
+use crate::config::{Config, ExternalRules, RuleContext, ToNftablesRule};
+use crate::nftables::FILTER_TABLE;
+use nftables::{
+    batch::Batch,
+    expr::{CT, Expression, NamedExpression, Payload, PayloadField},
+    schema::{NfListObject, Rule, Set, SetFlag, SetType, SetTypeValue},
+    stmt::{CTCount, Match, Meter, Operator, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Name of the meter counting connections to `port` of `chain`
+pub fn meter_name(chain: &str, protocol: &str, port: u16) -> String {
+    format!("{}-conn-{}{}", chain, protocol, port)
+}
+
+fn meter_set(family: NfFamily, name: String) -> Set<'static> {
+    let set_type = if family == NfFamily::IP6 {
+        SetType::Ipv6Addr
+    } else {
+        SetType::Ipv4Addr
+    };
+    Set {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name),
+        handle: None,
+        set_type: SetTypeValue::Single(set_type),
+        policy: None,
+        flags: Some(HashSet::from([SetFlag::Dynamic])),
+        elem: None,
+        timeout: None,
+        gc_interval: None,
+        size: None,
+        comment: None,
+    }
+}
+
+/// Statements dropping new connections to `port` from a source that has
+/// more than `max` open
+fn drop_statements(
+    family: NfFamily,
+    external: &ExternalRules,
+    meter: String,
+    protocol: &str,
+    port: u16,
+    max: u32,
+) -> Vec<Statement<'static>> {
+    let ip = if family == NfFamily::IP6 { "ip6" } else { "ip" };
+    let mut statements: Vec<Statement<'static>> = external.match_iif().into_iter().collect();
+    statements.extend([
+        <ExternalRules as ToNftablesRule>::match_protocol(protocol),
+        <ExternalRules as ToNftablesRule>::match_dst_port(protocol, port),
+        Statement::Match(Match {
+            left: Expression::Named(NamedExpression::CT(CT {
+                key: Cow::Borrowed("state"),
+                family: None,
+                dir: None,
+            })),
+            right: Expression::String(Cow::Borrowed("new")),
+            op: Operator::IN,
+        }),
+        Statement::Meter(Meter {
+            name: Cow::Owned(meter),
+            key: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                PayloadField {
+                    protocol: Cow::Borrowed(ip),
+                    field: Cow::Borrowed("saddr"),
+                },
+            ))),
+            stmt: Box::new(Statement::CTCount(CTCount {
+                val: Expression::Number(max),
+                inv: Some(true),
+            })),
+        }),
+        <ExternalRules as ToNftablesRule>::counter_statement(),
+        Statement::Drop(None),
+    ]);
+    statements
+}
+
+/// Add the connection limit rules of the chain, returning the meters they
+/// count in. Goes after the chain is flushed and before its external rules
+pub fn add_to_batch(batch: &mut Batch<'static>, ctx: &RuleContext, config: &Config) -> Vec<String> {
+    let external = &config.mapped_ports.external;
+    let mut meters = Vec::new();
+    if let Some(max) = external.max_connections_per_ip.filter(|_| external.allow) {
+        for (port, protocol) in ctx.container_ports {
+            let meter = meter_name(ctx.chain_name, protocol, *port);
+            batch.add(NfListObject::Rule(Rule {
+                family: ctx.family,
+                table: Cow::Owned(ctx.table_name.to_string()),
+                chain: Cow::Owned(ctx.chain_name.to_string()),
+                expr: Cow::Owned(drop_statements(
+                    ctx.family,
+                    external,
+                    meter.clone(),
+                    protocol,
+                    *port,
+                    max,
+                )),
+                handle: None,
+                index: None,
+                comment: Some(Cow::Owned(format!(
+                    "Limit {} port {} to {} connections per source for {}",
+                    protocol, port, max, ctx.container_name
+                ))),
+            }));
+            meters.push(meter);
+        }
+    }
+    meters
+}
+
+/// Delete meter `name`, once no rule counts in it any more
+pub fn delete_meter(batch: &mut Batch<'static>, family: NfFamily, name: String) {
+    batch.delete(NfListObject::Set(Box::new(meter_set(family, name))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connlimit_rules() {
+        let config: Config = serde_yaml::from_str(
+            "mapped_ports:\n  external:\n    allow: true\n    max_connections_per_ip: 10\n",
+        )
+        .unwrap();
+        let ports = [(443, "tcp".to_string())];
+        let ctx = RuleContext {
+            container_id: "connlimit123",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &ports,
+            chain_name: "hs-web-connlimit123",
+            table_name: FILTER_TABLE,
+            family: NfFamily::IP,
+        };
+
+        let mut batch = Batch::new();
+        let meters = add_to_batch(&mut batch, &ctx, &config);
+        assert_eq!(meters, ["hs-web-connlimit123-conn-tcp443"]);
+        let json = serde_json::to_value(batch.to_nftables()).unwrap();
+        let rule = &json["nftables"][0]["add"]["rule"];
+        assert_eq!(
+            rule["expr"][3],
+            serde_json::json!({"meter": {
+                "name": "hs-web-connlimit123-conn-tcp443",
+                "key": {"payload": {"protocol": "ip", "field": "saddr"}},
+                "stmt": {"ct count": {"val": 10, "inv": true}},
+            }})
+        );
+        assert_eq!(rule["expr"][5], serde_json::json!({"drop": null}));
+    }
+}
@@ -0,0 +1,178 @@
+//! nftables sets backing `host: <name>` output rules.
+//!
+//! Each such rule matches destinations against a set of its own next to
+//! the container chain. The set starts out empty; filling it with the
+//! addresses the name resolves to (see [`replace`]) is up to the caller.
+
+use crate::config::{Config, RuleConfig, RuleContext};
+use crate::dns::hostname::HostTarget;
+use crate::nftables::{FILTER_TABLE, RuleDefaults};
+use nftables::{
+    batch::Batch,
+    expr::{Expression, NamedExpression, Payload, PayloadField},
+    schema::{Element, FlushObject, NfCmd, NfListObject, Set, SetType, SetTypeValue},
+    stmt::{Match, Operator, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+/// Name of the set for output rule `number` (counting from 1) of `chain`
+pub fn set_name(chain: &str, number: usize) -> String {
+    format!("{}-host-{}", chain, number)
+}
+
+fn set(family: NfFamily, name: String) -> Set<'static> {
+    let set_type = if family == NfFamily::IP6 {
+        SetType::Ipv6Addr
+    } else {
+        SetType::Ipv4Addr
+    };
+    Set {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name),
+        handle: None,
+        set_type: SetTypeValue::Single(set_type),
+        policy: None,
+        flags: None,
+        elem: None,
+        timeout: None,
+        gc_interval: None,
+        size: None,
+        comment: None,
+    }
+}
+
+/// Replace everything in a set with `addrs`: flushing and re-adding in one
+/// batch, so no packet sees it half updated
+pub fn replace(batch: &mut Batch<'static>, family: NfFamily, name: &str, addrs: &[IpAddr]) {
+    batch.add_cmd(NfCmd::Flush(FlushObject::Set(Box::new(set(
+        family,
+        name.to_string(),
+    )))));
+    if addrs.is_empty() {
+        return;
+    }
+    batch.add(NfListObject::Element(Element {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name.to_string()),
+        elem: Cow::Owned(
+            addrs
+                .iter()
+                .map(|addr| Expression::String(Cow::Owned(addr.to_string())))
+                .collect(),
+        ),
+    }));
+}
+
+/// Match destinations in the set of output rule `number`
+pub fn daddr_match(ctx: &RuleContext, number: usize) -> Statement<'static> {
+    let protocol = if ctx.family == NfFamily::IP6 {
+        "ip6"
+    } else {
+        "ip"
+    };
+    Statement::Match(Match {
+        left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+            PayloadField {
+                protocol: Cow::Borrowed(protocol),
+                field: Cow::Borrowed("daddr"),
+            },
+        ))),
+        right: Expression::String(Cow::Owned(format!("@{}", set_name(ctx.chain_name, number)))),
+        op: Operator::EQ,
+    })
+}
+
+/// The output rules of `config` that match a hostname, with their numbers
+pub fn hostname_rules(config: &Config) -> impl Iterator<Item = (usize, &RuleConfig)> {
+    config
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, rule)| !rule.skip && rule.hostname.is_some())
+        .map(|(i, rule)| (i + 1, rule))
+}
+
+/// Create the sets of the chain's hostname rules, returning the target of
+/// each to resolve. Goes after the chain is flushed and before its rules
+/// are added
+pub fn add_to_batch(
+    batch: &mut Batch<'static>,
+    ctx: &RuleContext,
+    config: &Config,
+    defaults: &RuleDefaults,
+) -> Vec<HostTarget> {
+    let mut targets = Vec::new();
+    for (number, rule) in hostname_rules(config) {
+        let Some(hostname) = &rule.hostname else {
+            continue;
+        };
+        let name = set_name(ctx.chain_name, number);
+        batch.add(NfListObject::Set(Box::new(set(ctx.family, name.clone()))));
+        targets.push(HostTarget {
+            ipv6: ctx.family == NfFamily::IP6,
+            chain: ctx.chain_name.to_string(),
+            set: name,
+            hostname: hostname.clone(),
+            policy: rule.dns_lookup(defaults.dns_policy),
+            refresh: rule.dns_refresh(defaults.dns_refresh),
+        });
+    }
+    targets
+}
+
+/// Delete hostname set `name`, once no rule matches against it any more
+pub fn delete_set(batch: &mut Batch<'static>, family: NfFamily, name: String) {
+    batch.delete(NfListObject::Set(Box::new(set(family, name))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostname_sets() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+output:
+  - host: api.stripe.com
+    proto: tcp
+    dst_ports: [443]
+  - ips: [10.0.0.2]
+    proto: tcp
+    dst_ports: [5432]
+"#,
+        )
+        .unwrap();
+        let ctx = RuleContext {
+            container_id: "abc",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &[],
+            chain_name: "hs-web-hostname-sets",
+            table_name: FILTER_TABLE,
+            family: NfFamily::IP,
+        };
+
+        let defaults = RuleDefaults {
+            dns_refresh: 60,
+            ..Default::default()
+        };
+        let mut batch = Batch::new();
+        let targets = add_to_batch(&mut batch, &ctx, &config, &defaults);
+        let json = serde_json::to_string(&batch.to_nftables()).unwrap();
+        assert!(json.contains(r#""name":"hs-web-hostname-sets-host-1""#));
+        assert!(json.contains(r#""type":"ipv4_addr""#));
+        assert!(!json.contains("-host-2"));
+        let json = serde_json::to_string(&daddr_match(&ctx, 1)).unwrap();
+        assert!(json.contains("@hs-web-hostname-sets-host-1"));
+
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].set, "hs-web-hostname-sets-host-1");
+        assert_eq!(targets[0].hostname, "api.stripe.com");
+        assert_eq!(targets[0].refresh, 60);
+    }
+}
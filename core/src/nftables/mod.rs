@@ -0,0 +1,478 @@
+//! Building the nftables objects of container chains: the chain, its rules
+//! from a [`Config`] and the terminal rule of its enforcement mode.
+//! Applying them, and keeping track of the sets each chain uses, is up to
+//! the caller.
+
+pub mod connlimit;
+pub mod hostname;
+pub mod nflog;
+pub mod raw;
+pub mod rdns;
+
+use crate::config::{Config, RuleContext, ToNftablesRule, nftables_convert::merge_output_rules};
+use crate::dns::hostname::HostTarget;
+use crate::dns::{DEFAULT_REFRESH, LookupPolicy};
+use crate::{EnforcementMode, Result};
+use nftables::{
+    batch::Batch,
+    schema::{Chain, NfListObject, Rule},
+    stmt::{Counter, Log, LogLevel, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+
+/// Table the container chains live in, Docker's own filter table
+pub const FILTER_TABLE: &str = "filter";
+
+/// What rules follow unless they choose their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleDefaults {
+    /// How hostnames are resolved
+    pub dns_policy: LookupPolicy,
+    /// Seconds between lookups of a hostname
+    pub dns_refresh: u32,
+    /// NFLOG group dropped packets are sent to; `None` logs them to the
+    /// kernel log
+    pub nflog_group: Option<u16>,
+}
+
+impl Default for RuleDefaults {
+    fn default() -> Self {
+        Self {
+            dns_policy: LookupPolicy::default(),
+            dns_refresh: DEFAULT_REFRESH,
+            nflog_group: None,
+        }
+    }
+}
+
+/// The sets the rules of one chain use, besides those deleted with it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainSets {
+    /// Connection limit meters, see [`connlimit`]
+    pub meters: Vec<String>,
+    /// Sets of hostname rules, see [`hostname`]
+    pub hosts: Vec<HostTarget>,
+}
+
+/// Convert NfFamily to string for nft command
+pub fn family_to_string(family: &NfFamily) -> &'static str {
+    match family {
+        NfFamily::IP => "ip",
+        NfFamily::IP6 => "ip6",
+        NfFamily::INet => "inet",
+        NfFamily::ARP => "arp",
+        NfFamily::Bridge => "bridge",
+        NfFamily::NetDev => "netdev",
+    }
+}
+
+/// Name of a container's chain: `hs-<name>-<first 12 characters of the ID>`
+pub fn chain_name(container_id: &str, container_name: &str) -> String {
+    format!(
+        "hs-{}-{}",
+        container_name.replace(['_', '.', '/'], "-"),
+        &container_id[..12.min(container_id.len())]
+    )
+}
+
+/// The container chain `name` in the filter table
+pub fn container_chain(family: NfFamily, name: String) -> Chain<'static> {
+    Chain {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name),
+        newname: None,
+        handle: None,
+        _type: None,
+        hook: None,
+        prio: None,
+        dev: None,
+        policy: None,
+    }
+}
+
+/// The rule that ends a container chain: DROP when enforcing, a logged
+/// ACCEPT when permissive and a plain ACCEPT when disabled. Drops are
+/// logged to `nflog_group` when set
+pub fn terminal_rule(
+    family: NfFamily,
+    container_id: &str,
+    container_name: &str,
+    mode: EnforcementMode,
+    nflog_group: Option<u16>,
+) -> Rule<'static> {
+    let chain_name = chain_name(container_id, container_name);
+
+    let log = |tag: &str| {
+        Statement::Log(Some(Log {
+            prefix: Some(Cow::Owned(nflog::prefix(&chain_name, tag))),
+            level: Some(LogLevel::Info),
+            flags: None,
+            group: None,
+            queue_threshold: None,
+            snaplen: None,
+        }))
+    };
+    let (expr, comment) = match mode {
+        EnforcementMode::Enforce => (
+            vec![
+                Statement::Counter(Counter::Anonymous(None)),
+                nflog::drop_log(&chain_name, "DROP", nflog_group),
+                Statement::Drop(None),
+            ],
+            format!("Default DROP for container {}", container_name),
+        ),
+        EnforcementMode::Permissive => (
+            vec![
+                Statement::Counter(Counter::Anonymous(None)),
+                log("PERMISSIVE"),
+                Statement::Accept(None),
+            ],
+            format!(
+                "Permissive: log instead of DROP for container {}",
+                container_name
+            ),
+        ),
+        EnforcementMode::Disabled => (
+            vec![
+                Statement::Counter(Counter::Anonymous(None)),
+                Statement::Accept(None),
+            ],
+            format!("Enforcement disabled for container {}", container_name),
+        ),
+        EnforcementMode::Quarantined => (
+            vec![
+                Statement::Counter(Counter::Anonymous(None)),
+                nflog::drop_log(&chain_name, "QUARANTINE", nflog_group),
+                Statement::Drop(None),
+            ],
+            format!("Quarantine DROP for container {}", container_name),
+        ),
+    };
+
+    Rule {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        chain: Cow::Owned(chain_name),
+        expr: Cow::Owned(expr),
+        handle: None,
+        index: None,
+        comment: Some(Cow::Owned(comment)),
+    }
+}
+
+/// Add the rules `config` gives the chain of `ctx` to `batch`, and the
+/// `nft -f -` lines of its raw rules to `raw_rules`, returning the sets
+/// they use
+pub fn add_container_rules(
+    batch: &mut Batch<'static>,
+    raw_rules: &mut Vec<String>,
+    ctx: &RuleContext,
+    config: &Config,
+    defaults: &RuleDefaults,
+) -> Result<ChainSets> {
+    let mut sets = ChainSets::default();
+    let loopback = if ctx.family == NfFamily::IP6 {
+        "::1"
+    } else {
+        "127.0.0.1"
+    };
+
+    // Add mapped port rules - create individual rules for each container port
+    if config.mapped_ports.localhost.allow || config.mapped_ports.external.allow {
+        tracing::debug!(
+            "Creating mapped port rules for container {}: ports={:?}",
+            ctx.container_name,
+            ctx.container_ports
+        );
+
+        // Group ports by protocol
+        let mut tcp_ports = Vec::new();
+        let mut udp_ports = Vec::new();
+
+        for (port, protocol) in ctx.container_ports {
+            match protocol.as_str() {
+                "tcp" => tcp_ports.push(*port),
+                "udp" => udp_ports.push(*port),
+                _ => {} // Ignore other protocols for now
+            }
+        }
+
+        // Create localhost rules for each port
+        if config.mapped_ports.localhost.allow {
+            tracing::debug!("Creating localhost rules for TCP ports: {:?}", tcp_ports);
+            for port in &tcp_ports {
+                let mut statements = vec![
+                    // Match source IP as localhost
+                    <crate::config::LocalRules as ToNftablesRule>::match_src_ip(loopback),
+                    // Match protocol and destination port
+                    <crate::config::LocalRules as ToNftablesRule>::match_protocol("tcp"),
+                    <crate::config::LocalRules as ToNftablesRule>::match_dst_port("tcp", *port),
+                    // Add counter
+                    <crate::config::LocalRules as ToNftablesRule>::counter_statement(),
+                ];
+
+                // Add log if configured
+                if !config.mapped_ports.localhost.log_prefix.is_empty() {
+                    statements.push(
+                        <crate::config::LocalRules as ToNftablesRule>::log_statement(Some(
+                            &config.mapped_ports.localhost.log_prefix,
+                        )),
+                    );
+                }
+
+                // Add verdict
+                statements.push(
+                    <crate::config::LocalRules as ToNftablesRule>::verdict_to_statement(
+                        &config.mapped_ports.localhost.verdict,
+                    ),
+                );
+
+                let rule = Rule {
+                    family: ctx.family,
+                    table: Cow::Owned(ctx.table_name.to_string()),
+                    chain: Cow::Owned(ctx.chain_name.to_string()),
+                    expr: Cow::Owned(statements),
+                    handle: None,
+                    index: None,
+                    comment: Some(Cow::Owned(format!(
+                        "Localhost access to port {} for {}",
+                        port, ctx.container_name
+                    ))),
+                };
+
+                batch.add(NfListObject::Rule(rule));
+            }
+
+            for port in &udp_ports {
+                let mut statements = vec![
+                    // Match source IP as localhost
+                    <crate::config::LocalRules as ToNftablesRule>::match_src_ip(loopback),
+                    // Match protocol and destination port
+                    <crate::config::LocalRules as ToNftablesRule>::match_protocol("udp"),
+                    <crate::config::LocalRules as ToNftablesRule>::match_dst_port("udp", *port),
+                    // Add counter
+                    <crate::config::LocalRules as ToNftablesRule>::counter_statement(),
+                ];
+
+                // Add log if configured
+                if !config.mapped_ports.localhost.log_prefix.is_empty() {
+                    statements.push(
+                        <crate::config::LocalRules as ToNftablesRule>::log_statement(Some(
+                            &config.mapped_ports.localhost.log_prefix,
+                        )),
+                    );
+                }
+
+                // Add verdict
+                statements.push(
+                    <crate::config::LocalRules as ToNftablesRule>::verdict_to_statement(
+                        &config.mapped_ports.localhost.verdict,
+                    ),
+                );
+
+                let rule = Rule {
+                    family: ctx.family,
+                    table: Cow::Owned(ctx.table_name.to_string()),
+                    chain: Cow::Owned(ctx.chain_name.to_string()),
+                    expr: Cow::Owned(statements),
+                    handle: None,
+                    index: None,
+                    comment: Some(Cow::Owned(format!(
+                        "Localhost access to UDP port {} for {}",
+                        port, ctx.container_name
+                    ))),
+                };
+
+                batch.add(NfListObject::Rule(rule));
+            }
+        }
+
+        sets.meters = connlimit::add_to_batch(batch, ctx, config);
+
+        // Create external rules for each port
+        if config.mapped_ports.external.allow && !config.mapped_ports.external.rdns_only() {
+            for port in &tcp_ports {
+                let mut statements: Vec<_> = config
+                    .mapped_ports
+                    .external
+                    .match_iif()
+                    .into_iter()
+                    .collect();
+
+                // Match source IPs if specified
+                if !config.mapped_ports.external.ips.is_empty() {
+                    // For simplicity, handle the first IP only for now
+                    if let Some(first_ip) = config.mapped_ports.external.ips.first() {
+                        match first_ip {
+                            crate::config::AddrOrRange::Addr(ip) => {
+                                statements.push(
+                                    <crate::config::ExternalRules as ToNftablesRule>::match_src_ip(
+                                        &ip.to_string(),
+                                    ),
+                                );
+                            }
+                            crate::config::AddrOrRange::Net(net) => {
+                                statements.push(
+                                    <crate::config::ExternalRules as ToNftablesRule>::match_src_ip(
+                                        &net.to_string(),
+                                    ),
+                                );
+                            }
+                            _ => {} // Handle ranges later
+                        }
+                    }
+                }
+
+                // Match protocol and destination port
+                statements
+                    .push(<crate::config::LocalRules as ToNftablesRule>::match_protocol("tcp"));
+                statements.push(
+                    <crate::config::LocalRules as ToNftablesRule>::match_dst_port("tcp", *port),
+                );
+
+                statements.extend(config.mapped_ports.external.match_limit());
+
+                // Add counter
+                statements.push(<crate::config::LocalRules as ToNftablesRule>::counter_statement());
+
+                // Add log if configured
+                if !config.mapped_ports.external.log_prefix.is_empty() {
+                    statements.push(
+                        <crate::config::ExternalRules as ToNftablesRule>::log_statement(Some(
+                            &config.mapped_ports.external.log_prefix,
+                        )),
+                    );
+                }
+
+                // Add verdict
+                statements.push(
+                    <crate::config::ExternalRules as ToNftablesRule>::verdict_to_statement(
+                        &config.mapped_ports.external.verdict,
+                    ),
+                );
+
+                let rule = Rule {
+                    family: ctx.family,
+                    table: Cow::Owned(ctx.table_name.to_string()),
+                    chain: Cow::Owned(ctx.chain_name.to_string()),
+                    expr: Cow::Owned(statements),
+                    handle: None,
+                    index: None,
+                    comment: Some(Cow::Owned(format!(
+                        "External access to port {} for {}",
+                        port, ctx.container_name
+                    ))),
+                };
+
+                batch.add(NfListObject::Rule(rule));
+            }
+
+            for port in &udp_ports {
+                let mut statements: Vec<_> = config
+                    .mapped_ports
+                    .external
+                    .match_iif()
+                    .into_iter()
+                    .collect();
+
+                // Match source IPs if specified
+                if !config.mapped_ports.external.ips.is_empty() {
+                    // For simplicity, handle the first IP only for now
+                    if let Some(first_ip) = config.mapped_ports.external.ips.first() {
+                        match first_ip {
+                            crate::config::AddrOrRange::Addr(ip) => {
+                                statements.push(
+                                    <crate::config::ExternalRules as ToNftablesRule>::match_src_ip(
+                                        &ip.to_string(),
+                                    ),
+                                );
+                            }
+                            crate::config::AddrOrRange::Net(net) => {
+                                statements.push(
+                                    <crate::config::ExternalRules as ToNftablesRule>::match_src_ip(
+                                        &net.to_string(),
+                                    ),
+                                );
+                            }
+                            _ => {} // Handle ranges later
+                        }
+                    }
+                }
+
+                // Match protocol and destination port
+                statements
+                    .push(<crate::config::LocalRules as ToNftablesRule>::match_protocol("udp"));
+                statements.push(
+                    <crate::config::LocalRules as ToNftablesRule>::match_dst_port("udp", *port),
+                );
+
+                statements.extend(config.mapped_ports.external.match_limit());
+
+                // Add counter
+                statements.push(<crate::config::LocalRules as ToNftablesRule>::counter_statement());
+
+                // Add log if configured
+                if !config.mapped_ports.external.log_prefix.is_empty() {
+                    statements.push(
+                        <crate::config::ExternalRules as ToNftablesRule>::log_statement(Some(
+                            &config.mapped_ports.external.log_prefix,
+                        )),
+                    );
+                }
+
+                // Add verdict
+                statements.push(
+                    <crate::config::ExternalRules as ToNftablesRule>::verdict_to_statement(
+                        &config.mapped_ports.external.verdict,
+                    ),
+                );
+
+                let rule = Rule {
+                    family: ctx.family,
+                    table: Cow::Owned(ctx.table_name.to_string()),
+                    chain: Cow::Owned(ctx.chain_name.to_string()),
+                    expr: Cow::Owned(statements),
+                    handle: None,
+                    index: None,
+                    comment: Some(Cow::Owned(format!(
+                        "External access to UDP port {} for {}",
+                        port, ctx.container_name
+                    ))),
+                };
+
+                batch.add(NfListObject::Rule(rule));
+            }
+        }
+
+        for object in rdns::rdns_objects(ctx, config) {
+            batch.add(object);
+        }
+    }
+
+    // Add output rules, merging identical ones
+    sets.hosts = hostname::add_to_batch(batch, ctx, config, defaults);
+    let mut numbered = Vec::new();
+    for (i, output_rule) in config.output.iter().enumerate() {
+        if !output_rule.skip {
+            let mut statements = output_rule.to_nftables_statements()?;
+            if output_rule.hostname.is_some() {
+                statements.insert(1, hostname::daddr_match(ctx, i + 1));
+            }
+            numbered.push((i + 1, statements));
+        }
+    }
+    for rule in merge_output_rules(ctx, numbered) {
+        batch.add(NfListObject::Rule(rule));
+    }
+
+    raw_rules.extend(raw::commands(
+        ctx.family,
+        ctx.chain_name,
+        ctx.container_name,
+        &config.raw_rules,
+    ));
+
+    Ok(sets)
+}
@@ -0,0 +1,54 @@
+//! Sending the packets container chains drop to an NFLOG group instead of
+//! the kernel log, for the daemon to count and attribute.
+//!
+//! The log prefix stays `<chain> DROP: ` (or `QUARANTINE: `) either way, so
+//! a drop can be traced back to its chain when no container address matches.
+
+use nftables::stmt::{Log, LogLevel, Statement};
+use std::borrow::Cow;
+
+/// Prefix of the log statement ending `chain`
+pub fn prefix(chain: &str, tag: &str) -> String {
+    format!("{} {}: ", chain, tag)
+}
+
+/// Chain and tag of a prefix made by [`prefix`]
+pub fn parse_prefix(prefix: &str) -> Option<(&str, &str)> {
+    let (chain, tag) = prefix.trim_end().strip_suffix(':')?.rsplit_once(' ')?;
+    chain.starts_with("hs-").then_some((chain, tag))
+}
+
+/// Log statement for packets `chain` drops, sent to NFLOG `group` or, with
+/// `None`, the kernel log
+pub fn drop_log(chain: &str, tag: &str, group: Option<u16>) -> Statement<'static> {
+    Statement::Log(Some(Log {
+        prefix: Some(Cow::Owned(prefix(chain, tag))),
+        group: group.map(u32::from),
+        snaplen: None,
+        queue_threshold: None,
+        // nft refuses a level on group logging
+        level: group.is_none().then_some(LogLevel::Info),
+        flags: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_log() {
+        let chain = "hs-web-abc123def456";
+        assert_eq!(parse_prefix(&prefix(chain, "DROP")), Some((chain, "DROP")));
+        assert_eq!(parse_prefix("ssh-in: "), None);
+
+        assert_eq!(
+            serde_json::to_value(drop_log(chain, "DROP", Some(5))).unwrap(),
+            serde_json::json!({"log": {"prefix": "hs-web-abc123def456 DROP: ", "group": 5}})
+        );
+        assert_eq!(
+            serde_json::to_value(drop_log(chain, "DROP", None)).unwrap()["log"]["level"],
+            "info"
+        );
+    }
+}
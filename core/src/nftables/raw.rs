@@ -0,0 +1,61 @@
+//! `raw_rules`: nftables rule expressions inserted into a container's chain
+//! as written, for what the rule schema can't express.
+//!
+//! nft's JSON input has no room for rule text, so raw rules are applied as
+//! an `nft -f -` script once the chain's transaction is in. They go at the
+//! head of the chain in the order listed, ahead of the generated rules, and
+//! go away with the chain like the rest of its rules.
+
+use crate::nftables::{FILTER_TABLE, family_to_string};
+use nftables::types::NfFamily;
+
+/// Comment marking the raw rules of a chain, numbered from 1
+pub fn comment(container_name: &str, n: usize) -> String {
+    format!("Raw rule {} for {}", n, container_name)
+}
+
+/// Script lines inserting `rules` at the head of `chain`, in order
+pub fn commands(
+    family: NfFamily,
+    chain: &str,
+    container_name: &str,
+    rules: &[String],
+) -> Vec<String> {
+    let family = family_to_string(&family);
+    // Each insert goes first, so the last rule is inserted first
+    rules
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, rule)| {
+            format!(
+                "insert rule {} {} {} {} comment \"{}\"",
+                family,
+                FILTER_TABLE,
+                chain,
+                rule.trim(),
+                comment(container_name, i + 1)
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_commands() {
+        let rules = [
+            "ip saddr 10.8.0.0/16 tcp dport 9100 accept".to_string(),
+            " meta l4proto gre accept ".to_string(),
+        ];
+        assert_eq!(
+            commands(NfFamily::IP, "hs-web-abc123", "web", &rules),
+            [
+                "insert rule ip filter hs-web-abc123 meta l4proto gre accept comment \"Raw rule 2 for web\"",
+                "insert rule ip filter hs-web-abc123 ip saddr 10.8.0.0/16 tcp dport 9100 accept comment \"Raw rule 1 for web\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! nftables objects backing reverse-DNS gated inbound rules.
+//!
+//! Each container with `rdns` patterns gets three timed sets next to its
+//! chain: a pending set that the packet path fills with unverified sources, a
+//! verified set that the daemon fills after FCrDNS succeeds, and a rejected
+//! set holding sources that failed until their negative TTL passes, so they
+//! aren't queued again meanwhile. Every set is capped at [`SET_SIZE`].
+
+use crate::config::{Config, ExternalRules, RuleContext, ToNftablesRule};
+use crate::nftables::FILTER_TABLE;
+use nftables::{
+    batch::Batch,
+    expr::{Expression, NamedExpression, Payload, PayloadField},
+    schema::{NfListObject, Rule, Set, SetFlag, SetType, SetTypeValue},
+    stmt::{Match, Operator, Set as SetStatement, SetOp, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+/// Seconds an unverified source stays in the pending set
+pub const PENDING_TIMEOUT: u32 = 60;
+
+/// Most elements an rdns set holds, so a flood of sources can't grow it
+/// without bound
+pub const SET_SIZE: u32 = 65536;
+
+pub fn pending_set_name(chain: &str) -> String {
+    format!("{}-rdns-p", chain)
+}
+
+pub fn verified_set_name(chain: &str) -> String {
+    format!("{}-rdns-ok", chain)
+}
+
+pub fn rejected_set_name(chain: &str) -> String {
+    format!("{}-rdns-no", chain)
+}
+
+fn timed_set(family: NfFamily, name: String, timeout: u32, dynamic: bool) -> Set<'static> {
+    let mut flags = HashSet::from([SetFlag::Timeout]);
+    if dynamic {
+        flags.insert(SetFlag::Dynamic);
+    }
+
+    Set {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name),
+        handle: None,
+        set_type: SetTypeValue::Single(SetType::Ipv4Addr),
+        policy: None,
+        flags: Some(flags),
+        elem: None,
+        timeout: Some(timeout),
+        gc_interval: None,
+        size: Some(SET_SIZE),
+        comment: None,
+    }
+}
+
+fn saddr() -> Expression<'static> {
+    Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+        PayloadField {
+            protocol: Cow::Borrowed("ip"),
+            field: Cow::Borrowed("saddr"),
+        },
+    )))
+}
+
+/// Sets and rules for the rdns patterns of `config`, or nothing if it has none
+pub fn rdns_objects(ctx: &RuleContext, config: &Config) -> Vec<NfListObject<'static>> {
+    let external = &config.mapped_ports.external;
+    if !external.allow || external.rdns.is_empty() {
+        return Vec::new();
+    }
+
+    let pending = pending_set_name(ctx.chain_name);
+    let verified = verified_set_name(ctx.chain_name);
+    let rejected = rejected_set_name(ctx.chain_name);
+
+    let mut objects = vec![
+        NfListObject::Set(Box::new(timed_set(
+            ctx.family,
+            pending.clone(),
+            PENDING_TIMEOUT,
+            true,
+        ))),
+        NfListObject::Set(Box::new(timed_set(
+            ctx.family,
+            verified.clone(),
+            external.rdns_ttl,
+            false,
+        ))),
+        NfListObject::Set(Box::new(timed_set(
+            ctx.family,
+            rejected.clone(),
+            external.rdns_negative_ttl.max(1),
+            false,
+        ))),
+    ];
+
+    for (port, protocol) in ctx.container_ports {
+        let rule = |expr: Vec<Statement<'static>>, comment: String| Rule {
+            family: ctx.family,
+            table: Cow::Owned(ctx.table_name.to_string()),
+            chain: Cow::Owned(ctx.chain_name.to_string()),
+            expr: Cow::Owned(expr),
+            handle: None,
+            index: None,
+            comment: Some(Cow::Owned(comment)),
+        };
+
+        let iif = external.match_iif();
+        let mut allow: Vec<Statement<'static>> = iif.clone().into_iter().collect();
+        allow.extend([
+            Statement::Match(Match {
+                left: saddr(),
+                right: Expression::String(Cow::Owned(format!("@{}", verified))),
+                op: Operator::EQ,
+            }),
+            <ExternalRules as ToNftablesRule>::match_protocol(protocol),
+            <ExternalRules as ToNftablesRule>::match_dst_port(protocol, *port),
+        ]);
+        allow.extend(external.match_limit());
+        allow.push(<ExternalRules as ToNftablesRule>::counter_statement());
+        if !external.log_prefix.is_empty() {
+            allow.push(<ExternalRules as ToNftablesRule>::log_statement(Some(
+                &external.log_prefix,
+            )));
+        }
+        allow.push(<ExternalRules as ToNftablesRule>::verdict_to_statement(
+            &external.verdict,
+        ));
+        objects.push(NfListObject::Rule(rule(
+            allow,
+            format!(
+                "Allow {} port {} from rdns-verified sources for {}",
+                protocol, port, ctx.container_name
+            ),
+        )));
+
+        // Unknown sources fall through to the default drop after being
+        // recorded, unless they failed verification recently
+        let mut record: Vec<Statement<'static>> = iif.into_iter().collect();
+        record.extend([
+            Statement::Match(Match {
+                left: saddr(),
+                right: Expression::String(Cow::Owned(format!("@{}", rejected))),
+                op: Operator::NEQ,
+            }),
+            <ExternalRules as ToNftablesRule>::match_protocol(protocol),
+            <ExternalRules as ToNftablesRule>::match_dst_port(protocol, *port),
+            <ExternalRules as ToNftablesRule>::counter_statement(),
+            Statement::Set(SetStatement {
+                op: SetOp::Add,
+                elem: saddr(),
+                set: Cow::Owned(format!("@{}", pending)),
+            }),
+        ]);
+        objects.push(NfListObject::Rule(rule(
+            record,
+            format!(
+                "Queue {} port {} sources for rdns verification for {}",
+                protocol, port, ctx.container_name
+            ),
+        )));
+    }
+
+    objects
+}
+
+/// Add the rdns sets and rules for a container to `batch`
+pub fn add_to_batch(batch: &mut Batch<'static>, ctx: &RuleContext, config: &Config) {
+    for object in rdns_objects(ctx, config) {
+        batch.add(object);
+    }
+}
+
+/// Delete the rdns sets of a container chain (the chain must be gone first)
+pub fn delete_sets(batch: &mut Batch<'static>, family: NfFamily, chain: &str) {
+    batch.delete(NfListObject::Set(Box::new(timed_set(
+        family,
+        pending_set_name(chain),
+        PENDING_TIMEOUT,
+        true,
+    ))));
+    batch.delete(NfListObject::Set(Box::new(timed_set(
+        family,
+        verified_set_name(chain),
+        0,
+        false,
+    ))));
+    batch.delete(NfListObject::Set(Box::new(timed_set(
+        family,
+        rejected_set_name(chain),
+        0,
+        false,
+    ))));
+}
+
+/// Delete one rdns set by name
+pub fn delete_set(batch: &mut Batch<'static>, family: NfFamily, name: String) {
+    batch.delete(NfListObject::Set(Box::new(timed_set(
+        family, name, 0, false,
+    ))));
+}
+
+/// Extract addresses from `nft -j list set` output. Elements of timed sets
+/// are wrapped as `{"elem": {"val": ..., "expires": ...}}`.
+pub fn parse_set_elements(json: &serde_json::Value) -> Vec<IpAddr> {
+    json.get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("set")?.get("elem")?.as_array())
+        .flatten()
+        .filter_map(|elem| {
+            let value = elem.get("elem").and_then(|e| e.get("val")).unwrap_or(elem);
+            value.as_str()?.parse().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MappedPorts;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_set_elements() {
+        let output = json!({
+            "nftables": [
+                {"metainfo": {"json_schema_version": 1}},
+                {"set": {
+                    "family": "ip",
+                    "name": "hs-web-abc-rdns-p",
+                    "elem": [
+                        {"elem": {"val": "66.249.66.1", "timeout": 60, "expires": 42}},
+                        "66.249.66.2",
+                        {"elem": {"val": "not-an-ip"}}
+                    ]
+                }}
+            ]
+        });
+
+        assert_eq!(
+            parse_set_elements(&output),
+            vec![
+                "66.249.66.1".parse::<IpAddr>().unwrap(),
+                "66.249.66.2".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rdns_objects() {
+        let config = Config::builder()
+            .mapped_ports(
+                MappedPorts::builder()
+                    .external(
+                        ExternalRules::builder()
+                            .allow(true)
+                            .rdns(vec!["*.googlebot.com".parse().unwrap()])
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let ports = vec![(443, "tcp".to_string())];
+        let ctx = RuleContext {
+            container_id: "abc",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &ports,
+            chain_name: "hs-web-abc",
+            table_name: FILTER_TABLE,
+            family: NfFamily::IP,
+        };
+
+        let objects = rdns_objects(&ctx, &config);
+        // Three sets plus an allow and a record rule for the single port
+        assert_eq!(objects.len(), 5);
+        for object in &objects[..3] {
+            let NfListObject::Set(set) = object else {
+                panic!("expected a set, got {:?}", object);
+            };
+            assert_eq!(set.size, Some(SET_SIZE));
+        }
+
+        let json = serde_json::to_string(&objects).unwrap();
+        assert!(json.contains("@hs-web-abc-rdns-ok"));
+        assert!(json.contains("@hs-web-abc-rdns-p"));
+        assert!(json.contains("@hs-web-abc-rdns-no"));
+    }
+
+    #[test]
+    fn test_no_objects_without_patterns() {
+        let config = Config::new();
+        let ctx = RuleContext {
+            container_id: "abc",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &[],
+            chain_name: "hs-web-abc",
+            table_name: FILTER_TABLE,
+            family: NfFamily::IP,
+        };
+        assert!(rdns_objects(&ctx, &config).is_empty());
+    }
+}
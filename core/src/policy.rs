@@ -0,0 +1,40 @@
+use crate::config::Config;
+use crate::{EnforcementMode, Result, RuleSetBuilder};
+use bon::Builder;
+use std::net::IpAddr;
+
+/// A container's rules and what they apply to
+#[derive(Debug, Clone, Builder)]
+#[builder(on(String, into))]
+pub struct ContainerPolicy {
+    /// Container ID; its first 12 characters name the chain
+    pub id: String,
+    pub name: String,
+    /// The container's addresses, which dispatch to its chain
+    #[builder(default)]
+    pub ips: Vec<IpAddr>,
+    /// Published ports as (host port, protocol), such as `(8080, "tcp")`
+    #[builder(default)]
+    pub ports: Vec<(u16, String)>,
+    pub rules: Config,
+    #[builder(default)]
+    pub mode: EnforcementMode,
+}
+
+impl ContainerPolicy {
+    /// Parse and validate a rules label, without profiles and with the
+    /// default sensitive ports; see [`RuleSetBuilder::parse_rules`]
+    pub fn parse_rules(yaml: &str) -> Result<Config> {
+        RuleSetBuilder::new().parse_rules(yaml)
+    }
+
+    /// The rules the chain is built from: only what a quarantine policy
+    /// lets through while quarantined
+    pub fn effective_rules(&self) -> Config {
+        if self.mode == EnforcementMode::Quarantined {
+            self.rules.quarantined()
+        } else {
+            self.rules.clone()
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Comparing the container chains a rule set expects with those installed.
+
+use crate::nftables::FILTER_TABLE;
+use crate::{Result, RuleSet};
+use nftables::helper::{DEFAULT_NFT, get_current_ruleset_raw};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    /// A running container's chain is gone
+    MissingChain,
+    /// A chain is left over from a container that no longer runs
+    OrphanedChain,
+}
+
+impl DriftKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DriftKind::MissingChain => "missing chain",
+            DriftKind::OrphanedChain => "orphaned chain",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    pub kind: DriftKind,
+    pub chain: String,
+    pub container_id: Option<String>,
+    pub container_name: Option<String>,
+    pub repaired: bool,
+}
+
+/// The chain of a running container
+#[derive(Debug, Clone)]
+pub struct ExpectedChain {
+    pub chain: String,
+    pub container_id: String,
+    pub container_name: String,
+    /// Whether the container's rules should have produced the chain
+    pub required: bool,
+}
+
+/// Compare expected chains with the rule counts of the chains that exist
+pub fn find_drift(expected: &[ExpectedChain], actual: &HashMap<String, u64>) -> Vec<Drift> {
+    let mut drift: Vec<Drift> = expected
+        .iter()
+        .filter(|e| e.required && !actual.contains_key(&e.chain))
+        .map(|e| Drift {
+            kind: DriftKind::MissingChain,
+            chain: e.chain.clone(),
+            container_id: Some(e.container_id.clone()),
+            container_name: Some(e.container_name.clone()),
+            repaired: false,
+        })
+        .collect();
+
+    let mut orphaned: Vec<&String> = actual
+        .keys()
+        .filter(|chain| !expected.iter().any(|e| &e.chain == *chain))
+        .collect();
+    orphaned.sort();
+    drift.extend(orphaned.into_iter().map(|chain| Drift {
+        kind: DriftKind::OrphanedChain,
+        chain: chain.clone(),
+        container_id: None,
+        container_name: None,
+        repaired: false,
+    }));
+
+    drift
+}
+
+/// Rule count of every harborshield container chain, including empty ones,
+/// from `nft -j list table` output
+pub fn parse_chain_rule_counts(json: &serde_json::Value) -> HashMap<String, u64> {
+    let mut chains: HashMap<String, u64> = HashMap::new();
+
+    let items = json
+        .get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten();
+
+    for item in items {
+        let (name, rules) = if let Some(chain) = item.get("chain") {
+            (chain.get("name"), 0)
+        } else if let Some(rule) = item.get("rule") {
+            (rule.get("chain"), 1)
+        } else {
+            continue;
+        };
+        if let Some(name) = name.and_then(|n| n.as_str())
+            && name.starts_with("hs-")
+        {
+            *chains.entry(name.to_string()).or_default() += rules;
+        }
+    }
+
+    chains
+}
+
+/// Compares the chains a rule set expects with those installed
+#[derive(Debug, Clone)]
+pub struct Reconciler {
+    expected: Vec<ExpectedChain>,
+}
+
+impl Reconciler {
+    pub fn new(ruleset: &RuleSet) -> Self {
+        Self {
+            expected: ruleset.chains.clone(),
+        }
+    }
+
+    /// Chains missing from `listing`, the output of
+    /// `nft -j list table ip filter`, and container chains in it that no
+    /// policy has
+    pub fn drift(&self, listing: &serde_json::Value) -> Vec<Drift> {
+        find_drift(&self.expected, &parse_chain_rule_counts(listing))
+    }
+
+    /// [`Reconciler::drift`] against the host's live ruleset
+    pub fn drift_live(&self) -> Result<Vec<Drift>> {
+        let listing = get_current_ruleset_raw(DEFAULT_NFT, ["list", "table", "ip", FILTER_TABLE])?;
+        Ok(self.drift(&serde_json::from_str(&listing)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expected(name: &str, id: &str, required: bool) -> ExpectedChain {
+        ExpectedChain {
+            chain: format!("hs-{}-{}", name, id),
+            container_id: id.to_string(),
+            container_name: name.to_string(),
+            required,
+        }
+    }
+
+    #[test]
+    fn test_find_drift() {
+        let expected = [
+            expected("web", "abc", true),
+            expected("db", "def", true),
+            // Running without rules: no chain expected, but one isn't orphaned
+            expected("cache", "123", false),
+        ];
+        let actual = HashMap::from([
+            ("hs-web-abc".to_string(), 3),
+            ("hs-cache-123".to_string(), 0),
+            ("hs-old-999".to_string(), 1),
+        ]);
+
+        let drift = find_drift(&expected, &actual);
+        assert_eq!(drift.len(), 2);
+        assert_eq!(drift[0].kind, DriftKind::MissingChain);
+        assert_eq!(drift[0].container_name.as_deref(), Some("db"));
+        assert_eq!(drift[1].kind, DriftKind::OrphanedChain);
+        assert_eq!(drift[1].chain, "hs-old-999");
+    }
+
+    #[test]
+    fn test_parse_chain_rule_counts() {
+        let output = json!({
+            "nftables": [
+                {"chain": {"family": "ip", "table": "filter", "name": "hs-web-abc"}},
+                {"chain": {"family": "ip", "table": "filter", "name": "hs-empty-def"}},
+                {"chain": {"family": "ip", "table": "filter", "name": "DOCKER-USER"}},
+                {"rule": {"chain": "hs-web-abc", "expr": [{"accept": null}]}},
+                {"rule": {"chain": "hs-web-abc", "expr": [{"drop": null}]}},
+                {"rule": {"chain": "DOCKER-USER", "expr": [{"drop": null}]}}
+            ]
+        });
+
+        let counts = parse_chain_rule_counts(&output);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["hs-web-abc"], 2);
+        assert_eq!(counts["hs-empty-def"], 0);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::config::exposure::DEFAULT_SENSITIVE_PORTS;
+use crate::config::{Config, RuleContext, profiles::Profiles};
+use crate::dns::{DEFAULT_REFRESH, LookupPolicy};
+use crate::nftables::{
+    FILTER_TABLE, RuleDefaults, add_container_rules, chain_name, container_chain, terminal_rule,
+};
+use crate::{ContainerPolicy, EnforcementMode, ExpectedChain, Result};
+use nftables::{batch::Batch, schema::NfListObject, types::NfFamily};
+
+/// Builds the container chains of a set of policies
+#[derive(Debug, Clone)]
+pub struct RuleSetBuilder {
+    family: NfFamily,
+    policies: Vec<ContainerPolicy>,
+    profiles: Profiles,
+    sensitive_ports: Vec<u16>,
+    nflog_group: Option<u16>,
+    dns_policy: LookupPolicy,
+    dns_refresh: u32,
+}
+
+impl Default for RuleSetBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuleSetBuilder {
+    /// An IPv4 rule set without policies, with the daemon's defaults
+    pub fn new() -> Self {
+        Self {
+            family: NfFamily::IP,
+            policies: Vec::new(),
+            profiles: Profiles::default(),
+            sensitive_ports: DEFAULT_SENSITIVE_PORTS.to_vec(),
+            nflog_group: None,
+            dns_policy: LookupPolicy::default(),
+            dns_refresh: DEFAULT_REFRESH,
+        }
+    }
+
+    /// Build for `family` instead, `NfFamily::IP6` for the IPv6 rules
+    pub fn family(mut self, family: NfFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    pub fn policy(mut self, policy: ContainerPolicy) -> Self {
+        self.policies.push(policy);
+        self
+    }
+
+    pub fn policies(mut self, policies: impl IntoIterator<Item = ContainerPolicy>) -> Self {
+        self.policies.extend(policies);
+        self
+    }
+
+    /// Profiles the rules given to [`RuleSetBuilder::parse_rules`] can
+    /// extend, as `--profiles` loads them
+    pub fn profiles(mut self, profiles: Profiles) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    /// Ports rules may only open to every address with
+    /// `expose_to_internet`, as `--sensitive-ports` gives them
+    pub fn sensitive_ports(mut self, ports: impl Into<Vec<u16>>) -> Self {
+        self.sensitive_ports = ports.into();
+        self
+    }
+
+    /// Send dropped packets to NFLOG `group` instead of the kernel log
+    pub fn nflog_group(mut self, group: u16) -> Self {
+        self.nflog_group = Some(group);
+        self
+    }
+
+    /// How hostnames of rules that don't choose their own are resolved
+    pub fn dns_policy(mut self, policy: LookupPolicy) -> Self {
+        self.dns_policy = policy;
+        self
+    }
+
+    /// Seconds between lookups of hostnames of rules that don't choose
+    /// their own
+    pub fn dns_refresh(mut self, seconds: u32) -> Self {
+        self.dns_refresh = seconds.max(1);
+        self
+    }
+
+    /// Parse and validate a rules label against the builder's profiles and
+    /// sensitive ports
+    pub fn parse_rules(&self, yaml: &str) -> Result<Config> {
+        let config = self.profiles.parse_rules(yaml)?;
+        config.check_exposure(&self.sensitive_ports)?;
+        Ok(config)
+    }
+
+    /// Each container's chain with its rules, ending in the terminal rule
+    /// of its enforcement mode, as the daemon builds them. Hostname sets
+    /// are left empty, for the caller to fill
+    pub fn build(self) -> Result<RuleSet> {
+        let defaults = RuleDefaults {
+            dns_policy: self.dns_policy,
+            dns_refresh: self.dns_refresh,
+            nflog_group: self.nflog_group,
+        };
+        let mut batch = Batch::new();
+        let mut raw_rules = Vec::new();
+        let mut terminal_rules = Vec::new();
+        let mut chains = Vec::new();
+
+        for policy in &self.policies {
+            policy.rules.check_exposure(&self.sensitive_ports)?;
+            let chain = chain_name(&policy.id, &policy.name);
+            batch.add(NfListObject::Chain(container_chain(
+                self.family,
+                chain.clone(),
+            )));
+            let rules = policy
+                .effective_rules()
+                .for_family(self.family, self.dns_policy);
+            if policy.mode != EnforcementMode::Disabled {
+                let ctx = RuleContext {
+                    container_id: &policy.id,
+                    container_name: &policy.name,
+                    container_ips: &policy.ips,
+                    container_ports: &policy.ports,
+                    chain_name: &chain,
+                    table_name: FILTER_TABLE,
+                    family: self.family,
+                };
+                add_container_rules(&mut batch, &mut raw_rules, &ctx, &rules, &defaults)?;
+            }
+            terminal_rules.push(terminal_rule(
+                self.family,
+                &policy.id,
+                &policy.name,
+                policy.mode,
+                self.nflog_group,
+            ));
+            chains.push(ExpectedChain {
+                chain,
+                container_id: policy.id.clone(),
+                container_name: policy.name.clone(),
+                required: true,
+            });
+        }
+
+        // Terminal rules go last, as on commit
+        for rule in terminal_rules {
+            batch.add(NfListObject::Rule(rule));
+        }
+        Ok(RuleSet {
+            batch,
+            raw_rules,
+            chains,
+        })
+    }
+}
+
+/// The objects built for a set of policies
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    pub batch: Batch<'static>,
+    /// Rules the `nftables` crate can't express, as `nft -f -` lines to
+    /// apply after the batch
+    pub raw_rules: Vec<String>,
+    /// The chain of each policy
+    pub chains: Vec<ExpectedChain>,
+}
+
+impl RuleSet {
+    /// The batch as `nft -j -f -` takes it
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.batch.clone().to_nftables())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DriftKind, Reconciler};
+
+    #[test]
+    fn test_build_and_reconcile() {
+        let policy = |id: &str, name: &str, mode| {
+            ContainerPolicy::builder()
+                .id(id)
+                .name(name)
+                .ips(vec!["172.18.0.2".parse().unwrap()])
+                .rules(
+                    ContainerPolicy::parse_rules(
+                        "output:\n  - {ips: [10.0.5.20], proto: tcp, dst_ports: [5432]}\n",
+                    )
+                    .unwrap(),
+                )
+                .mode(mode)
+                .build()
+        };
+        let ruleset = RuleSetBuilder::new()
+            .policy(policy("4f1c2a9e0b7d11", "web", EnforcementMode::Enforce))
+            .policy(policy("9a8b7c6d5e4f", "db_1", EnforcementMode::Disabled))
+            .build()
+            .unwrap();
+        let chains: Vec<&str> = ruleset.chains.iter().map(|c| c.chain.as_str()).collect();
+        assert_eq!(chains, ["hs-web-4f1c2a9e0b7d", "hs-db-1-9a8b7c6d5e4f"]);
+
+        let json = ruleset.to_json().unwrap();
+        assert!(json.contains("5432"), "{}", json);
+        assert!(json.contains("Default DROP for container web"), "{}", json);
+        assert!(
+            json.contains("Enforcement disabled for container db_1"),
+            "{}",
+            json
+        );
+
+        // One chain installed, with a stray one left over
+        let live = serde_json::json!({"nftables": [
+            {"chain": {"family": "ip", "table": "filter", "name": "hs-web-4f1c2a9e0b7d"}},
+            {"chain": {"family": "ip", "table": "filter", "name": "hs-old-000000000000"}},
+        ]});
+        let mut drift = Reconciler::new(&ruleset).drift(&live);
+        drift.sort_by(|a, b| a.chain.cmp(&b.chain));
+        assert_eq!(drift.len(), 2);
+        assert_eq!(drift[0].chain, "hs-db-1-9a8b7c6d5e4f");
+        assert_eq!(drift[0].kind, DriftKind::MissingChain);
+        assert_eq!(drift[1].kind, DriftKind::OrphanedChain);
+
+        // Builds don't share state: dropping a limit leaves no meter to
+        // delete, and the NFLOG group stays with its builder
+        let api = |yaml: &str| {
+            ContainerPolicy::builder()
+                .id("5b6c7d8e9f00")
+                .name("api")
+                .ports(vec![(443, "tcp".to_string())])
+                .rules(ContainerPolicy::parse_rules(yaml).unwrap())
+                .build()
+        };
+        let limited = RuleSetBuilder::new()
+            .nflog_group(5)
+            .policy(api(
+                "mapped_ports:\n  external:\n    allow: true\n    max_connections_per_ip: 10\n",
+            ))
+            .build()
+            .unwrap()
+            .to_json()
+            .unwrap();
+        assert!(
+            limited.contains("hs-api-5b6c7d8e9f00-conn-tcp443"),
+            "{}",
+            limited
+        );
+        assert!(limited.contains(r#""group":5"#), "{}", limited);
+        let open = RuleSetBuilder::new()
+            .policy(api("mapped_ports:\n  external:\n    allow: true\n"))
+            .build()
+            .unwrap()
+            .to_json()
+            .unwrap();
+        assert!(!open.contains("delete"), "{}", open);
+        assert!(!open.contains("group"), "{}", open);
+
+        let ssh = "output:\n  - {proto: tcp, ips: [\"::/0\"], dst_ports: [22]}\n";
+        assert!(ContainerPolicy::parse_rules(ssh).is_err());
+        RuleSetBuilder::new()
+            .sensitive_ports([5432])
+            .parse_rules(ssh)
+            .unwrap();
+    }
+}
@@ -0,0 +1,450 @@
+//! Time zones read from the system tz database.
+//!
+//! Zones are loaded from compiled TZif files (`$TZDIR`, or
+//! `/usr/share/zoneinfo`), including the POSIX TZ rule in their footer that
+//! describes daylight saving time beyond the last listed transition. Only
+//! UTC offsets are needed here, so abbreviations and leap seconds are
+//! ignored.
+
+use chrono::{Datelike, NaiveDate};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+const DEFAULT_TZDIR: &str = "/usr/share/zoneinfo";
+const LOCALTIME: &str = "/etc/localtime";
+
+static ZONES: LazyLock<Mutex<HashMap<String, Arc<TimeZone>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Transition instants (unix seconds), ascending
+    transitions: Vec<i64>,
+    /// Offset in effect from the matching transition on
+    offsets: Vec<i32>,
+    /// Offset before the first transition
+    initial: i32,
+    /// Rule for instants after the last transition
+    rule: Option<PosixRule>,
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        Self::fixed(0)
+    }
+
+    pub fn fixed(offset: i32) -> Self {
+        Self {
+            transitions: Vec::new(),
+            offsets: Vec::new(),
+            initial: offset,
+            rule: None,
+        }
+    }
+
+    /// A zone from the tz database such as `Europe/Berlin`, cached after the
+    /// first load
+    pub fn named(name: &str) -> io::Result<Arc<Self>> {
+        if let Ok(zones) = ZONES.lock()
+            && let Some(zone) = zones.get(name)
+        {
+            return Ok(zone.clone());
+        }
+
+        let zone = Arc::new(Self::load(name)?);
+        if let Ok(mut zones) = ZONES.lock() {
+            zones.insert(name.to_string(), zone.clone());
+        }
+        Ok(zone)
+    }
+
+    fn load(name: &str) -> io::Result<Self> {
+        if name.eq_ignore_ascii_case("utc") {
+            return Ok(Self::utc());
+        }
+        if name.is_empty() || name.starts_with('/') || name.split('/').any(|p| p == "..") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid time zone name '{}'", name),
+            ));
+        }
+        let dir = std::env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_TZDIR));
+        Self::from_file(&dir.join(name))
+    }
+
+    /// The host's zone: `TZ` if set, else `/etc/localtime`, else UTC
+    pub fn local() -> Arc<Self> {
+        if let Ok(tz) = std::env::var("TZ") {
+            let tz = tz.trim_start_matches(':');
+            if let Ok(zone) = Self::named(tz) {
+                return zone;
+            }
+            if let Some(rule) = PosixRule::parse(tz) {
+                return Arc::new(Self {
+                    initial: rule.std_offset,
+                    rule: Some(rule),
+                    ..Self::utc()
+                });
+            }
+        }
+        Self::from_file(Path::new(LOCALTIME))
+            .map(Arc::new)
+            .unwrap_or_else(|_| Arc::new(Self::utc()))
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::parse_tzif(&std::fs::read(path)?)
+    }
+
+    pub fn parse_tzif(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed TZif data");
+
+        let header = |data: &[u8]| -> Option<[usize; 6]> {
+            if data.len() < 44 || &data[..4] != b"TZif" {
+                return None;
+            }
+            let mut counts = [0usize; 6];
+            for (i, count) in counts.iter_mut().enumerate() {
+                let at = 20 + i * 4;
+                *count = u32::from_be_bytes(data[at..at + 4].try_into().ok()?) as usize;
+            }
+            Some(counts)
+        };
+        let block_len = |[isut, isstd, leap, time, typ, chars]: [usize; 6], time_size: usize| {
+            time * time_size + time + typ * 6 + chars + leap * (time_size + 4) + isstd + isut
+        };
+
+        let counts = header(data).ok_or_else(invalid)?;
+        let version = data[4];
+        let (data, counts, time_size) = if version >= b'2' {
+            let rest = data.get(44 + block_len(counts, 4)..).ok_or_else(invalid)?;
+            (rest, header(rest).ok_or_else(invalid)?, 8)
+        } else {
+            (data, counts, 4)
+        };
+        let [_, _, _, timecnt, typecnt, _] = counts;
+        let body = data
+            .get(44..44 + block_len(counts, time_size))
+            .ok_or_else(invalid)?;
+        if typecnt == 0 {
+            return Err(invalid());
+        }
+
+        let transitions: Vec<i64> = body[..timecnt * time_size]
+            .chunks(time_size)
+            .map(|c| match time_size {
+                8 => i64::from_be_bytes(c.try_into().unwrap_or_default()),
+                _ => i32::from_be_bytes(c.try_into().unwrap_or_default()) as i64,
+            })
+            .collect();
+        let indices = &body[timecnt * time_size..timecnt * (time_size + 1)];
+        let types: Vec<i32> = body[timecnt * (time_size + 1)..]
+            .chunks(6)
+            .take(typecnt)
+            .map(|t| i32::from_be_bytes([t[0], t[1], t[2], t[3]]))
+            .collect();
+        let offsets = indices
+            .iter()
+            .map(|&i| types.get(i as usize).copied().ok_or_else(invalid))
+            .collect::<io::Result<Vec<i32>>>()?;
+
+        // The footer is only present from version 2 on
+        let rule = (time_size == 8)
+            .then(|| {
+                let footer = &data[44 + block_len(counts, time_size)..];
+                let footer = std::str::from_utf8(footer).ok()?;
+                PosixRule::parse(footer.trim_matches('\n'))
+            })
+            .flatten();
+
+        Ok(Self {
+            transitions,
+            offsets,
+            initial: types[0],
+            rule,
+        })
+    }
+
+    /// Seconds east of UTC in effect at `unix` seconds
+    pub fn offset_at(&self, unix: i64) -> i32 {
+        match self.transitions.partition_point(|&t| t <= unix) {
+            0 if self.transitions.is_empty() => self
+                .rule
+                .as_ref()
+                .map_or(self.initial, |r| r.offset_at(unix)),
+            0 => self.initial,
+            n if n == self.transitions.len() => self
+                .rule
+                .as_ref()
+                .map_or(self.offsets[n - 1], |r| r.offset_at(unix)),
+            n => self.offsets[n - 1],
+        }
+    }
+}
+
+/// A POSIX TZ rule such as `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PosixRule {
+    std_offset: i32,
+    dst: Option<DstRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DstRule {
+    offset: i32,
+    start: (RuleDate, i32),
+    end: (RuleDate, i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleDate {
+    /// Day `d` (0 = Sunday) of week `w` (5 = last) of month `m`
+    MonthWeekDay(u32, u32, u32),
+    /// Day of year 1-365, never counting February 29
+    Julian(u32),
+    /// Day of year 0-365, counting February 29
+    Ordinal(u32),
+}
+
+impl RuleDate {
+    fn in_year(&self, year: i32) -> Option<NaiveDate> {
+        match *self {
+            RuleDate::MonthWeekDay(m, w, d) => {
+                let first = NaiveDate::from_ymd_opt(year, m, 1)?;
+                let first_wd = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (d + 7 - first_wd) % 7 + (w - 1) * 7;
+                while NaiveDate::from_ymd_opt(year, m, day).is_none() {
+                    day -= 7;
+                }
+                NaiveDate::from_ymd_opt(year, m, day)
+            }
+            RuleDate::Julian(n) => {
+                let date = NaiveDate::from_ymd_opt(year, 1, 1)? + chrono::Days::new(n as u64 - 1);
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                Some(if leap && date.ordinal() >= 60 {
+                    date + chrono::Days::new(1)
+                } else {
+                    date
+                })
+            }
+            RuleDate::Ordinal(n) => NaiveDate::from_yo_opt(year, n + 1),
+        }
+    }
+}
+
+/// Parse `[+|-]hh[:mm[:ss]]` from the start of `s`
+fn parse_hms(s: &str) -> Option<(i32, &str)> {
+    let (sign, s) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, s),
+    };
+    let end = s
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(s.len());
+    let mut seconds = 0;
+    for (i, part) in s[..end].split(':').enumerate() {
+        if i > 2 {
+            return None;
+        }
+        seconds += part.parse::<i32>().ok()? * [3600, 60, 1][i];
+    }
+    Some((sign * seconds, &s[end..]))
+}
+
+/// Zone abbreviation: letters, or anything between `<` and `>`
+fn parse_name(s: &str) -> Option<&str> {
+    if let Some(quoted) = s.strip_prefix('<') {
+        return Some(&quoted[quoted.find('>')? + 1..]);
+    }
+    let end = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    (end >= 3).then(|| &s[end..])
+}
+
+fn parse_rule_date(s: &str) -> Option<(RuleDate, i32)> {
+    let (date, time) = match s.split_once('/') {
+        Some((date, time)) => (date, parse_hms(time)?.0),
+        None => (s, 7200),
+    };
+    let date = if let Some(mwd) = date.strip_prefix('M') {
+        let mut parts = mwd.split('.').map(|p| p.parse::<u32>().ok());
+        let (m, w, d) = (parts.next()??, parts.next()??, parts.next()??);
+        if !(1..=12).contains(&m) || !(1..=5).contains(&w) || d > 6 {
+            return None;
+        }
+        RuleDate::MonthWeekDay(m, w, d)
+    } else if let Some(n) = date.strip_prefix('J') {
+        let n = n.parse().ok()?;
+        (1..=365).contains(&n).then_some(RuleDate::Julian(n))?
+    } else {
+        let n = date.parse().ok()?;
+        (n <= 365).then_some(RuleDate::Ordinal(n))?
+    };
+    Some((date, time))
+}
+
+impl PosixRule {
+    pub fn parse(s: &str) -> Option<Self> {
+        let rest = parse_name(s)?;
+        // POSIX offsets count hours west of UTC
+        let (std_west, rest) = parse_hms(rest)?;
+        let std_offset = -std_west;
+        if rest.is_empty() {
+            return Some(Self {
+                std_offset,
+                dst: None,
+            });
+        }
+
+        let rest = parse_name(rest)?;
+        let (offset, rest) = match rest.chars().next()? {
+            ',' => (std_offset + 3600, rest),
+            _ => {
+                let (west, rest) = parse_hms(rest)?;
+                (-west, rest)
+            }
+        };
+        let mut dates = rest.strip_prefix(',')?.split(',');
+        let start = parse_rule_date(dates.next()?)?;
+        let end = parse_rule_date(dates.next()?)?;
+        if dates.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            std_offset,
+            dst: Some(DstRule { offset, start, end }),
+        })
+    }
+
+    fn offset_at(&self, unix: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+        let year = chrono::DateTime::from_timestamp(unix + self.std_offset as i64, 0)
+            .map_or(1970, |t| t.year());
+        let instant = |(date, time): (RuleDate, i32), offset: i32| {
+            date.in_year(year).map(|d| {
+                d.and_hms_opt(0, 0, 0)
+                    .unwrap_or_default()
+                    .and_utc()
+                    .timestamp()
+                    + time as i64
+                    - offset as i64
+            })
+        };
+        // The start is given in standard time, the end in daylight time
+        let (Some(start), Some(end)) = (
+            instant(dst.start, self.std_offset),
+            instant(dst.end, dst.offset),
+        ) else {
+            return self.std_offset;
+        };
+
+        let in_dst = if start < end {
+            unix >= start && unix < end
+        } else {
+            // Southern hemisphere: DST spans the new year
+            unix >= start || unix < end
+        };
+        if in_dst { dst.offset } else { self.std_offset }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(s).unwrap().timestamp()
+    }
+
+    #[test]
+    fn test_posix_rule() {
+        let berlin = PosixRule::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(berlin.offset_at(ts("2024-01-15T12:00:00Z")), 3600);
+        assert_eq!(berlin.offset_at(ts("2024-07-15T12:00:00Z")), 7200);
+        // Clocks go forward at 01:00 UTC on the last Sunday of March
+        assert_eq!(berlin.offset_at(ts("2024-03-31T00:59:59Z")), 3600);
+        assert_eq!(berlin.offset_at(ts("2024-03-31T01:00:00Z")), 7200);
+        // And back at 01:00 UTC on the last Sunday of October
+        assert_eq!(berlin.offset_at(ts("2024-10-27T00:59:59Z")), 7200);
+        assert_eq!(berlin.offset_at(ts("2024-10-27T01:00:00Z")), 3600);
+
+        let sydney = PosixRule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(ts("2024-01-15T00:00:00Z")), 11 * 3600);
+        assert_eq!(sydney.offset_at(ts("2024-07-15T00:00:00Z")), 10 * 3600);
+
+        let quoted = PosixRule::parse("<-03>3").unwrap();
+        assert_eq!(quoted.offset_at(0), -3 * 3600);
+
+        assert!(PosixRule::parse("CET-1CEST,M13.5.0,M10.5.0").is_none());
+        assert!(PosixRule::parse("X1").is_none());
+    }
+
+    /// Build a version 2 TZif image with the given transitions and footer
+    fn tzif(transitions: &[(i64, u8)], types: &[i32], footer: &str) -> Vec<u8> {
+        let header = |timecnt: usize, typecnt: usize| {
+            let mut h = b"TZif2".to_vec();
+            h.extend([0u8; 15]);
+            for count in [0, 0, 0, timecnt, typecnt, 4] {
+                h.extend((count as u32).to_be_bytes());
+            }
+            h
+        };
+        let block = |time_size: usize| {
+            let mut b = Vec::new();
+            for (t, _) in transitions {
+                match time_size {
+                    8 => b.extend(t.to_be_bytes()),
+                    _ => b.extend((*t as i32).to_be_bytes()),
+                }
+            }
+            b.extend(transitions.iter().map(|(_, i)| *i));
+            for offset in types {
+                b.extend(offset.to_be_bytes());
+                b.extend([0, 0]);
+            }
+            b.extend(b"STD\0");
+            b
+        };
+
+        let mut data = header(transitions.len(), types.len());
+        data.extend(block(4));
+        data.extend(header(transitions.len(), types.len()));
+        data.extend(block(8));
+        data.extend(format!("\n{}\n", footer).as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse_tzif() {
+        let data = tzif(
+            &[(1000, 1), (2000, 0)],
+            &[3600, 7200],
+            "CET-1CEST,M3.5.0,M10.5.0/3",
+        );
+        let zone = TimeZone::parse_tzif(&data).unwrap();
+        assert_eq!(zone.offset_at(0), 3600);
+        assert_eq!(zone.offset_at(1500), 7200);
+        assert_eq!(zone.offset_at(2500), 3600);
+        // Past the last transition the footer rule applies
+        assert_eq!(zone.offset_at(ts("2030-07-01T00:00:00Z")), 7200);
+
+        assert!(TimeZone::parse_tzif(b"nope").is_err());
+        assert!(TimeZone::parse_tzif(&data[..60]).is_err());
+    }
+
+    #[test]
+    fn test_named_rejects_paths() {
+        assert!(TimeZone::named("../etc/passwd").is_err());
+        assert!(TimeZone::named("/etc/localtime").is_err());
+        assert_eq!(TimeZone::named("UTC").unwrap().offset_at(0), 0);
+    }
+}
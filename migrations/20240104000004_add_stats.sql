@@ -0,0 +1,38 @@
+-- Raw counter samples and rule changes, drained into the rollup tables by
+-- the stats aggregation job
+
+CREATE TABLE stat_events (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  ts              INTEGER NOT NULL,   -- unix seconds
+  container_name  TEXT NOT NULL,
+  kind            TEXT NOT NULL,      -- 'drop', 'accept' or 'rule_change'
+  packets         INTEGER NOT NULL DEFAULT 0,
+  bytes           INTEGER NOT NULL DEFAULT 0
+) STRICT;
+
+CREATE INDEX idx_stat_events_ts ON stat_events(ts);
+
+-- Aggregates keyed by bucket start (unix seconds). Containers are referenced
+-- by name so history survives container removal.
+
+CREATE TABLE stats_hourly (
+  bucket           INTEGER NOT NULL,
+  container_name   TEXT NOT NULL,
+  dropped_packets  INTEGER NOT NULL DEFAULT 0,
+  dropped_bytes    INTEGER NOT NULL DEFAULT 0,
+  accepted_bytes   INTEGER NOT NULL DEFAULT 0,
+  rule_changes     INTEGER NOT NULL DEFAULT 0,
+
+  PRIMARY KEY(bucket, container_name)
+) STRICT;
+
+CREATE TABLE stats_daily (
+  bucket           INTEGER NOT NULL,
+  container_name   TEXT NOT NULL,
+  dropped_packets  INTEGER NOT NULL DEFAULT 0,
+  dropped_bytes    INTEGER NOT NULL DEFAULT 0,
+  accepted_bytes   INTEGER NOT NULL DEFAULT 0,
+  rule_changes     INTEGER NOT NULL DEFAULT 0,
+
+  PRIMARY KEY(bucket, container_name)
+) STRICT;
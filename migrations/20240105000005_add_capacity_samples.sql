@@ -0,0 +1,9 @@
+-- Hourly snapshots of ruleset size, used for capacity trends
+
+CREATE TABLE capacity_samples (
+  bucket          INTEGER PRIMARY KEY,   -- hour start, unix seconds
+  chains          INTEGER NOT NULL,
+  rules           INTEGER NOT NULL,
+  set_elements    INTEGER NOT NULL,
+  memory_bytes    INTEGER NOT NULL
+) STRICT;
@@ -0,0 +1,8 @@
+-- Per-container enforcement overrides; containers without a row are enforced.
+-- Keyed by name so an override survives the container being recreated.
+
+CREATE TABLE enforcement_modes (
+  container_name  TEXT PRIMARY KEY,
+  mode            TEXT NOT NULL,         -- permissive | disabled
+  updated_at      INTEGER NOT NULL       -- unix seconds
+) STRICT;
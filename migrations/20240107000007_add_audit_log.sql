@@ -0,0 +1,13 @@
+-- Append-only record of reconcile runs and the drift they found, kept as a
+-- compliance trail
+
+CREATE TABLE audit_log (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  ts              INTEGER NOT NULL,      -- unix seconds
+  kind            TEXT NOT NULL,         -- reconcile | drift
+  container_name  TEXT,
+  detail          TEXT NOT NULL,
+  repaired        INTEGER NOT NULL DEFAULT 0
+) STRICT;
+
+CREATE INDEX idx_audit_log_ts ON audit_log(ts);
@@ -0,0 +1,23 @@
+-- Learning mode: containers run permissive for a while and the flows seen in
+-- conntrack are recorded, so `harborshield suggest` can propose rules
+
+CREATE TABLE learning_sessions (
+  container_name  TEXT PRIMARY KEY,
+  started_at      INTEGER NOT NULL,      -- unix seconds
+  ends_at         INTEGER NOT NULL       -- unix seconds
+) STRICT;
+
+CREATE TABLE observed_flows (
+  container_name  TEXT NOT NULL,
+  direction       TEXT NOT NULL,         -- inbound | outbound
+  proto           TEXT NOT NULL,         -- tcp | udp
+  peer            TEXT NOT NULL,         -- remote address
+  port            INTEGER NOT NULL,      -- container port inbound, remote port outbound
+  peer_container  TEXT,                  -- set when the peer is a known container
+  peer_network    TEXT,                  -- network shared with that container
+  first_seen      INTEGER NOT NULL,
+  last_seen       INTEGER NOT NULL,
+  sightings       INTEGER NOT NULL DEFAULT 1,
+
+  PRIMARY KEY(container_name, direction, proto, peer, port)
+) STRICT;
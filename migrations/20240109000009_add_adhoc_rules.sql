@@ -0,0 +1,13 @@
+-- Short-lived rules applied with `harborshield apply-adhoc`, layered over a
+-- container's rules label until they expire
+
+CREATE TABLE adhoc_rules (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  container_name  TEXT NOT NULL,         -- container identity
+  rules           TEXT NOT NULL,         -- rules label YAML
+  reason          TEXT,
+  created_at      INTEGER NOT NULL,      -- unix seconds
+  expires_at      INTEGER NOT NULL       -- unix seconds
+) STRICT;
+
+CREATE INDEX idx_adhoc_rules_container ON adhoc_rules(container_name);
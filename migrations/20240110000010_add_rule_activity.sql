@@ -0,0 +1,12 @@
+-- When each allow rule of a container last matched traffic, so allow rules
+-- that stopped matching anything can be reported as stale exposure
+
+CREATE TABLE rule_activity (
+  container_name  TEXT NOT NULL,
+  rule            TEXT NOT NULL,         -- rule set entry, such as "Output rule 2"
+  first_seen      INTEGER NOT NULL,      -- unix seconds
+  last_seen       INTEGER NOT NULL,      -- last sample the rule was installed in
+  last_hit        INTEGER,               -- NULL until it matches anything
+
+  PRIMARY KEY(container_name, rule)
+) STRICT;
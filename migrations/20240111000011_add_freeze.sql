@@ -0,0 +1,18 @@
+-- Change freezes started with `harborshield freeze`, and the containers whose
+-- Docker events were held back while one lasted
+
+CREATE TABLE freeze (
+  id              INTEGER PRIMARY KEY CHECK (id = 1),  -- at most one freeze
+  reason          TEXT,
+  started_at      INTEGER NOT NULL,      -- unix seconds
+  until           INTEGER                -- unix seconds; NULL until unfrozen
+) STRICT;
+
+CREATE TABLE held_events (
+  container_id    TEXT PRIMARY KEY,
+  container_name  TEXT NOT NULL,
+  action          TEXT NOT NULL,         -- the last Docker event held
+  events          INTEGER NOT NULL DEFAULT 1,
+  first_held      INTEGER NOT NULL,      -- unix seconds
+  last_held       INTEGER NOT NULL       -- unix seconds
+) STRICT;
@@ -0,0 +1,12 @@
+-- Addresses banned with `harborshield ban` or `POST /bans`, dropped by the
+-- harborshield chain until they expire
+
+CREATE TABLE bans (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  ip              TEXT NOT NULL,         -- IPv4 address
+  reason          TEXT,
+  created_at      INTEGER NOT NULL,      -- unix seconds
+  expires_at      INTEGER NOT NULL       -- unix seconds
+) STRICT;
+
+CREATE INDEX idx_bans_ip ON bans(ip);
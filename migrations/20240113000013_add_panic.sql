@@ -0,0 +1,9 @@
+-- The host kill switch engaged with `harborshield panic`, dropping all
+-- traffic of tracked containers until `harborshield panic --release`
+
+CREATE TABLE panic (
+  id              INTEGER PRIMARY KEY CHECK (id = 1),  -- at most one
+  reason          TEXT,
+  started_at      INTEGER NOT NULL,      -- unix seconds
+  allow           TEXT NOT NULL DEFAULT ''  -- comma-separated IPv4 networks let through
+) STRICT;
@@ -0,0 +1,128 @@
+//! Build and runtime capability report served at `GET /about`.
+
+use crate::nftables::{FILTER_TABLE, runner};
+use serde::Serialize;
+
+/// Optional components compiled into this build
+pub fn compiled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(target_os = "linux") {
+        features.extend(["landlock", "seccomp", "capabilities", "netlink"]);
+    }
+    if crate::offline::COMPILED {
+        features.push("offline");
+    }
+    features
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Build {
+    pub version: String,
+    pub git_commit: &'static str,
+    pub build_time: &'static str,
+    pub target_os: &'static str,
+    pub target_arch: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Kernel {
+    /// `uname -r`, if readable
+    pub release: Option<String>,
+    pub capabilities_ok: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Backend {
+    pub name: &'static str,
+    pub program: &'static str,
+    pub table: &'static str,
+    pub family: &'static str,
+    /// `nft --version`, if nft could be run
+    pub nft_version: Option<String>,
+    /// Whether nft accepts JSON, which every rule change relies on
+    pub nft_json: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Schemas {
+    /// Database migrations this build knows, oldest first
+    pub database: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct About {
+    pub build: Build,
+    pub kernel: Kernel,
+    pub backend: Backend,
+    pub schemas: Schemas,
+    pub network: crate::offline::Network,
+}
+
+/// Database schema versions embedded in this build
+pub fn database_schema_versions() -> Vec<i64> {
+    sqlx::migrate!("./migrations")
+        .iter()
+        .map(|migration| migration.version)
+        .collect()
+}
+
+fn kernel_release() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string())
+}
+
+async fn nft_stdout(operation: &str, args: &[&str]) -> Option<String> {
+    let output = runner::run_nft(operation, args).await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+pub async fn collect(version: &str) -> About {
+    let nft_version = nft_stdout("about_version", &["--version"]).await;
+    let nft_json = nft_stdout("about_json", &["-j", "list", "tables"])
+        .await
+        .is_some_and(|out| out.starts_with('{'));
+
+    About {
+        build: Build {
+            version: version.to_string(),
+            git_commit: option_env!("GIT_COMMIT").unwrap_or("unknown"),
+            build_time: option_env!("BUILD_TIME").unwrap_or("unknown"),
+            target_os: std::env::consts::OS,
+            target_arch: std::env::consts::ARCH,
+            features: compiled_features(),
+        },
+        kernel: Kernel {
+            release: kernel_release(),
+            capabilities_ok: crate::security::check_capabilities().is_ok(),
+        },
+        backend: Backend {
+            name: "nftables",
+            program: runner::NFT_PROGRAM,
+            table: FILTER_TABLE,
+            family: "ip",
+            nft_version,
+            nft_json,
+        },
+        schemas: Schemas {
+            database: database_schema_versions(),
+        },
+        network: crate::offline::network(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_schema_versions() {
+        let versions = database_schema_versions();
+        assert!(versions.windows(2).all(|w| w[0] < w[1]));
+        assert!(versions.contains(&20240101000001));
+    }
+}
@@ -0,0 +1,265 @@
+//! Scoped bearer tokens for the enforcement override endpoint and the admin
+//! gRPC service.
+//!
+//! Without a tokens file the endpoint answers anyone who can reach the admin
+//! listener. Once one is configured every `/enforcement` request needs an
+//! `Authorization: Bearer <token>` header, and a token only sees and switches
+//! the containers it is scoped to:
+//!
+//! ```yaml
+//! tokens:
+//!   - name: chatops
+//!     sha256: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//!     containers: ["web", "api-*"]
+//!     projects: [shop]
+//!     modes: [enforce, permissive]
+//!     forbidden_ports: [22]
+//! ```
+//!
+//! Tokens are stored as the hex SHA-256 of their value. A token without
+//! `containers` or `projects` covers every container; `modes` defaults to
+//! all of them.
+//!
+//! `permissive` and `disabled` accept all traffic, so they open every port
+//! the container listens on to every source. A token with
+//! `forbidden_ports` can't set either mode on a container publishing or
+//! exposing one of them, nor on a container that isn't tracked, whose
+//! ports aren't known. Both modes still open the container's egress
+//! entirely; leave them out of `modes` to rule that out.
+//!
+//! The endpoint only switches modes and never adds rules, so there is no
+//! rule shape to scope beyond that: grants limited to source addresses
+//! would need an override that adds rules, which harborshield doesn't have.
+
+use crate::database::EnforcementMode;
+use crate::docker::container::PortMapping;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenScope {
+    pub name: String,
+    /// Hex SHA-256 of the token
+    sha256: String,
+    /// Container names; a trailing `*` matches by prefix
+    #[serde(default)]
+    pub containers: Vec<String>,
+    /// Compose projects whose containers are covered
+    #[serde(default)]
+    pub projects: Vec<String>,
+    /// Modes the token may set
+    #[serde(default = "all_modes")]
+    pub modes: Vec<EnforcementMode>,
+    /// Ports the token may never open by relaxing a container
+    #[serde(default)]
+    pub forbidden_ports: Vec<u16>,
+}
+
+fn all_modes() -> Vec<EnforcementMode> {
+    vec![
+        EnforcementMode::Enforce,
+        EnforcementMode::Permissive,
+        EnforcementMode::Disabled,
+        EnforcementMode::Quarantined,
+    ]
+}
+
+pub(crate) fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+impl TokenScope {
+    /// Whether the token covers every container
+    pub fn unrestricted(&self) -> bool {
+        self.containers.is_empty() && self.projects.is_empty()
+    }
+
+    /// Whether the token covers a container, given its compose project
+    pub fn covers(&self, container_name: &str, project: Option<&str>) -> bool {
+        if self.unrestricted() {
+            return true;
+        }
+        self.containers
+            .iter()
+            .any(|pattern| name_matches(pattern, container_name))
+            || project.is_some_and(|project| self.projects.iter().any(|p| p == project))
+    }
+
+    pub fn allows_mode(&self, mode: EnforcementMode) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /// Why setting `mode` on a container with `ports`, `None` when it isn't
+    /// tracked, would open a forbidden port
+    pub fn check_opening(
+        &self,
+        mode: EnforcementMode,
+        ports: Option<&[PortMapping]>,
+    ) -> std::result::Result<(), String> {
+        let opens = matches!(
+            mode,
+            EnforcementMode::Permissive | EnforcementMode::Disabled
+        );
+        if !opens || self.forbidden_ports.is_empty() {
+            return Ok(());
+        }
+        let Some(ports) = ports else {
+            return Err(format!(
+                "token {} may only set mode {} on a tracked container, as it forbids ports",
+                self.name,
+                mode.as_str()
+            ));
+        };
+        let forbidden = ports.iter().find_map(|port| {
+            [Some(port.container_port), port.host_port]
+                .into_iter()
+                .flatten()
+                .find(|port| self.forbidden_ports.contains(port))
+        });
+        match forbidden {
+            Some(port) => Err(format!(
+                "token {} may not set mode {}: it would open port {}",
+                self.name,
+                mode.as_str(),
+                port
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tokens(Vec<TokenScope>);
+
+#[derive(Debug, Deserialize)]
+struct TokenFile {
+    #[serde(default)]
+    tokens: Vec<TokenScope>,
+}
+
+fn sha256_hex(value: &str) -> String {
+    ring::digest::digest(&ring::digest::SHA256, value.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Tokens {
+    /// The scope of the token in an `Authorization` header value
+    pub fn authorize(&self, authorization: Option<&str>) -> Option<&TokenScope> {
+        let token = authorization?.strip_prefix("Bearer ")?.trim();
+        if token.is_empty() {
+            return None;
+        }
+        let digest = sha256_hex(token);
+        self.0.iter().find(|scope| scope.sha256 == digest)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Read and check a tokens file
+pub fn load(path: &Path) -> std::io::Result<Tokens> {
+    let text = std::fs::read_to_string(path)?;
+    parse(&text).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse(text: &str) -> std::result::Result<Tokens, String> {
+    let file: TokenFile = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    let mut tokens = file.tokens;
+    for scope in &mut tokens {
+        scope.sha256 = scope.sha256.to_lowercase();
+        if scope.sha256.len() != 64 || !scope.sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!(
+                "token {}: sha256 must be 64 hex digits",
+                scope.name
+            ));
+        }
+    }
+    Ok(Tokens(tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_scopes() {
+        let tokens = parse(&format!(
+            r#"
+tokens:
+  - name: chatops
+    sha256: {}
+    containers: ["api-*"]
+    projects: [shop]
+    modes: [permissive]
+    forbidden_ports: [22]
+  - name: admin
+    sha256: {}
+"#,
+            sha256_hex("bot-secret"),
+            sha256_hex("admin-secret").to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(tokens.len(), 2);
+
+        let bot = tokens.authorize(Some("Bearer bot-secret")).unwrap();
+        assert_eq!(bot.name, "chatops");
+        assert!(bot.covers("api-1", None));
+        assert!(bot.covers("web", Some("shop")));
+        assert!(!bot.covers("web", Some("billing")));
+        assert!(bot.allows_mode(EnforcementMode::Permissive));
+        assert!(!bot.allows_mode(EnforcementMode::Disabled));
+
+        let port = |container_port, host_port| PortMapping {
+            container_port,
+            host_port,
+            host_ip: None,
+            protocol: "tcp".to_string(),
+        };
+        let permissive = EnforcementMode::Permissive;
+        assert!(
+            bot.check_opening(permissive, Some(&[port(80, Some(8080))]))
+                .is_ok()
+        );
+        assert!(
+            bot.check_opening(permissive, Some(&[port(22, None)]))
+                .is_err()
+        );
+        assert!(
+            bot.check_opening(permissive, Some(&[port(2222, Some(22))]))
+                .is_err()
+        );
+        assert!(bot.check_opening(permissive, None).is_err());
+        assert!(bot.check_opening(EnforcementMode::Enforce, None).is_ok());
+
+        let admin = tokens.authorize(Some("Bearer admin-secret")).unwrap();
+        assert!(admin.covers("anything", None));
+        assert!(admin.allows_mode(EnforcementMode::Disabled));
+        assert!(
+            admin
+                .check_opening(EnforcementMode::Disabled, Some(&[port(22, Some(22))]))
+                .is_ok()
+        );
+
+        assert!(tokens.authorize(Some("Bearer wrong")).is_none());
+        assert!(tokens.authorize(Some("bot-secret")).is_none());
+        assert!(tokens.authorize(None).is_none());
+        assert!(parse("tokens: [{name: x, sha256: abc}]").is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! `harborshield adopt --from-nft`: rules for containers from a hand-written
+//! nftables ruleset, easing a migration off it.
+//!
+//! The ruleset is read as `nft -j list ruleset` prints it. Rules accepting
+//! traffic to or from a known container address are turned into the flows
+//! they allow and proposed as rules labels, the way `suggest` proposes rules
+//! from learned flows. Drop rules for a container are covered by the drop
+//! harborshield ends its chains with. Everything else is listed with why it
+//! was left alone, for porting by hand.
+
+use crate::database::ObservedFlow;
+use crate::database::learning::Suggestion;
+use crate::host::conntrack::Direction;
+use crate::nftables::HARBORSHIELD_CHAIN;
+use crate::output::{Cell, Color, Column, Render, Table};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+
+/// A container an address belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub container: String,
+    /// The network the address is on, when known
+    pub network: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Turned into rules for a container
+    Adopted,
+    /// Matches what harborshield does already
+    Covered,
+    /// Needs porting by hand
+    Skipped,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Adopted => "adopted",
+            Outcome::Covered => "covered",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// What became of one rule of the ruleset
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleOutcome {
+    pub table: String,
+    pub chain: String,
+    pub handle: Option<u64>,
+    pub outcome: Outcome,
+    /// The container for adopted and covered rules, otherwise why it was
+    /// skipped
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AdoptReport {
+    /// Proposed rules, one per container
+    pub containers: Vec<Suggestion>,
+    pub rules: Vec<RuleOutcome>,
+}
+
+impl AdoptReport {
+    /// Whether any rule has to be ported by hand
+    pub fn has_skipped(&self) -> bool {
+        self.rules.iter().any(|r| r.outcome == Outcome::Skipped)
+    }
+}
+
+impl Render for AdoptReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("TABLE").wide(),
+            Column::left("CHAIN"),
+            Column::right("HANDLE"),
+            Column::left("RESULT"),
+            Column::left("DETAIL"),
+        ]);
+        for rule in &self.rules {
+            let outcome = match rule.outcome {
+                Outcome::Adopted => Cell::colored(rule.outcome.as_str(), Color::Green),
+                Outcome::Covered => Cell::colored(rule.outcome.as_str(), Color::Dim),
+                Outcome::Skipped => Cell::colored(rule.outcome.as_str(), Color::Yellow),
+            };
+            table.row(vec![
+                rule.table.clone().into(),
+                rule.chain.clone().into(),
+                rule.handle
+                    .map(|h| h.to_string())
+                    .unwrap_or_default()
+                    .into(),
+                outcome,
+                rule.detail.clone().into(),
+            ]);
+        }
+        let count = |outcome| self.rules.iter().filter(|r| r.outcome == outcome).count();
+        table.footer(format!(
+            "{} adopted, {} covered, {} to port by hand",
+            count(Outcome::Adopted),
+            count(Outcome::Covered),
+            count(Outcome::Skipped)
+        ));
+        table
+    }
+}
+
+/// A `--container NAME=ADDR` argument
+pub fn parse_container_arg(arg: &str) -> Result<(String, IpAddr), String> {
+    let (name, addr) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=ADDR, got '{}'", arg))?;
+    let addr = addr
+        .parse()
+        .map_err(|e| format!("invalid address '{}': {}", addr, e))?;
+    Ok((name.to_string(), addr))
+}
+
+/// The matches of a rule this understands
+#[derive(Debug, Default)]
+struct Matches {
+    saddr: Vec<String>,
+    daddr: Vec<String>,
+    proto: Option<String>,
+    dports: Vec<u16>,
+    verdict: Option<&'static str>,
+}
+
+/// An address, prefix or set of them as nft prints the right side of a
+/// match
+fn addresses(right: &Value) -> Result<Vec<String>, String> {
+    if let Some(addr) = right.as_str() {
+        if addr.starts_with('@') {
+            return Err(format!("matches the named set {}", addr));
+        }
+        return Ok(vec![addr.to_string()]);
+    }
+    if let Some(prefix) = right.get("prefix") {
+        let addr = prefix["addr"].as_str().ok_or("malformed prefix")?;
+        let len = prefix["len"].as_u64().ok_or("malformed prefix")?;
+        return Ok(vec![format!("{}/{}", addr, len)]);
+    }
+    if let Some(set) = right.get("set").and_then(|s| s.as_array()) {
+        let mut all = Vec::new();
+        for item in set {
+            all.extend(addresses(item)?);
+        }
+        return Ok(all);
+    }
+    Err(format!("matches addresses as {}", right))
+}
+
+fn ports(right: &Value) -> Result<Vec<u16>, String> {
+    if let Some(port) = right.as_u64() {
+        return u16::try_from(port)
+            .map(|p| vec![p])
+            .map_err(|e| e.to_string());
+    }
+    if let Some(set) = right.get("set").and_then(|s| s.as_array()) {
+        let mut all = Vec::new();
+        for item in set {
+            all.extend(ports(item)?);
+        }
+        return Ok(all);
+    }
+    Err(format!("matches ports as {}", right))
+}
+
+/// The matches and verdict of a rule, or why it isn't understood
+fn parse_rule(expr: &[Value]) -> Result<Matches, String> {
+    let mut matches = Matches::default();
+    for stmt in expr {
+        if let Some(m) = stmt.get("match") {
+            if m["op"].as_str().is_some_and(|op| op != "==" && op != "in") {
+                return Err(format!("uses the {} operator", m["op"]));
+            }
+            let (left, right) = (&m["left"], &m["right"]);
+            if let Some(payload) = left.get("payload") {
+                let protocol = payload["protocol"].as_str().unwrap_or_default();
+                match (protocol, payload["field"].as_str().unwrap_or_default()) {
+                    ("ip" | "ip6", "saddr") => matches.saddr.extend(addresses(right)?),
+                    ("ip" | "ip6", "daddr") => matches.daddr.extend(addresses(right)?),
+                    ("tcp" | "udp", "dport") => {
+                        matches.proto = Some(protocol.to_string());
+                        matches.dports.extend(ports(right)?);
+                    }
+                    ("th", "dport") => matches.dports.extend(ports(right)?),
+                    ("ip", "protocol") | ("ip6", "nexthdr") => {
+                        matches.proto = right.as_str().map(str::to_string)
+                    }
+                    (protocol, field) => return Err(format!("matches {} {}", protocol, field)),
+                }
+            } else if let Some(key) = left.get("meta").and_then(|m| m["key"].as_str()) {
+                match key {
+                    "l4proto" => matches.proto = right.as_str().map(str::to_string),
+                    // Interfaces are Docker's to pick
+                    "iifname" | "oifname" | "iif" | "oif" => {}
+                    key => return Err(format!("matches meta {}", key)),
+                }
+            } else if left.get("ct").is_some_and(|ct| ct["key"] == "state") {
+                if right != "new" {
+                    return Err(format!("matches ct state {}", right));
+                }
+            } else {
+                return Err(format!("matches {}", left));
+            }
+            continue;
+        }
+
+        let Some(key) = stmt.as_object().and_then(|o| o.keys().next()) else {
+            continue;
+        };
+        match key.as_str() {
+            "counter" | "log" => {}
+            "accept" => matches.verdict = Some("accept"),
+            "drop" | "reject" => matches.verdict = Some("drop"),
+            other => return Err(format!("uses the {} statement", other)),
+        }
+    }
+    Ok(matches)
+}
+
+/// The containers all of `addrs` belong to, if they all do
+fn containers<'a>(
+    addrs: &[String],
+    known: &'a HashMap<IpAddr, Endpoint>,
+) -> Option<Vec<&'a Endpoint>> {
+    if addrs.is_empty() {
+        return None;
+    }
+    addrs
+        .iter()
+        .map(|addr| addr.parse::<IpAddr>().ok().and_then(|ip| known.get(&ip)))
+        .collect()
+}
+
+fn flow(container: &str, direction: Direction, proto: &str, peer: &str, port: u16) -> ObservedFlow {
+    ObservedFlow::builder()
+        .container_name(container)
+        .direction(direction.as_str())
+        .proto(proto)
+        .peer(peer)
+        .port(i64::from(port))
+        .first_seen(0)
+        .last_seen(0)
+        .build()
+}
+
+/// The flows an accept rule allows, by container, or why it was skipped
+fn rule_flows(
+    matches: &Matches,
+    known: &HashMap<IpAddr, Endpoint>,
+) -> Result<Vec<ObservedFlow>, String> {
+    let proto = matches.proto.as_deref().unwrap_or_default();
+    if let Some(sources) = containers(&matches.saddr, known) {
+        if !matches!(proto, "tcp" | "udp") || matches.dports.is_empty() {
+            return Err("allows every port or protocol out of a container".to_string());
+        }
+        if matches.daddr.is_empty() {
+            return Err("allows every destination".to_string());
+        }
+        let mut flows = Vec::new();
+        for source in sources {
+            for peer in &matches.daddr {
+                let peer_endpoint = peer
+                    .parse::<IpAddr>()
+                    .ok()
+                    .and_then(|ip| known.get(&ip))
+                    .filter(|endpoint| endpoint.network.is_some());
+                for port in &matches.dports {
+                    let mut flow = flow(&source.container, Direction::Outbound, proto, peer, *port);
+                    if let Some(endpoint) = peer_endpoint {
+                        flow.peer_container = Some(endpoint.container.clone());
+                        flow.peer_network = endpoint.network.clone();
+                    }
+                    flows.push(flow);
+                }
+            }
+        }
+        return Ok(flows);
+    }
+
+    let Some(targets) = containers(&matches.daddr, known) else {
+        return Err("matches no known container address".to_string());
+    };
+    let mut sources = matches.saddr.clone();
+    if sources.is_empty() {
+        let ipv6 = matches.daddr.iter().any(|addr| addr.contains(':'));
+        sources.push(if ipv6 { "::/0" } else { "0.0.0.0/0" }.to_string());
+    }
+    let port = matches.dports.first().copied().unwrap_or_default();
+    let proto = if proto.is_empty() { "tcp" } else { proto };
+    Ok(targets
+        .into_iter()
+        .flat_map(|target| {
+            sources
+                .iter()
+                .map(|peer| flow(&target.container, Direction::Inbound, proto, peer, port))
+        })
+        .collect())
+}
+
+/// Map the rules of a `nft -j list ruleset` document onto containers at the
+/// `known` addresses. Rules in harborshield's own chains are left out
+pub fn adopt(ruleset: &Value, known: &HashMap<IpAddr, Endpoint>) -> AdoptReport {
+    let rules = ruleset
+        .get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("rule"));
+
+    let mut report = AdoptReport::default();
+    let mut by_container: BTreeMap<String, Vec<ObservedFlow>> = BTreeMap::new();
+    for rule in rules {
+        let chain = rule["chain"].as_str().unwrap_or_default();
+        if chain.starts_with("hs-") || chain == HARBORSHIELD_CHAIN {
+            continue;
+        }
+        let expr = rule["expr"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let result = parse_rule(expr).and_then(|matches| match matches.verdict {
+            Some("accept") => rule_flows(&matches, known).map(|flows| (Outcome::Adopted, flows)),
+            Some(_) => rule_flows(&matches, known).map(|flows| (Outcome::Covered, flows)),
+            None => Err("has no verdict".to_string()),
+        });
+        let (outcome, detail) = match result {
+            Ok((outcome, flows)) => {
+                let mut names: Vec<String> =
+                    flows.iter().map(|f| f.container_name.clone()).collect();
+                names.dedup();
+                if outcome == Outcome::Adopted {
+                    for flow in flows {
+                        by_container
+                            .entry(flow.container_name.clone())
+                            .or_default()
+                            .push(flow);
+                    }
+                }
+                (outcome, names.join(", "))
+            }
+            Err(reason) => (Outcome::Skipped, reason),
+        };
+        report.rules.push(RuleOutcome {
+            table: rule["table"].as_str().unwrap_or_default().to_string(),
+            chain: chain.to_string(),
+            handle: rule["handle"].as_u64(),
+            outcome,
+            detail,
+        });
+    }
+
+    report.containers = by_container
+        .into_iter()
+        .map(|(container, flows)| Suggestion::new(&container, flows))
+        .collect();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::config::Config;
+
+    #[test]
+    fn test_adopt() {
+        let ruleset = serde_json::json!({"nftables": [
+            {"metainfo": {"json_schema_version": 1}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "forward", "handle": 4, "expr": [
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}},
+                    "right": {"prefix": {"addr": "203.0.113.0", "len": 24}}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}},
+                    "right": "172.18.0.2"}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}},
+                    "right": 443}},
+                {"counter": {"packets": 0, "bytes": 0}},
+                {"accept": null}
+            ]}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "forward", "handle": 5, "expr": [
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}},
+                    "right": "172.18.0.2"}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}},
+                    "right": "172.18.0.3"}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}},
+                    "right": {"set": [5432, 6432]}}},
+                {"accept": null}
+            ]}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "forward", "handle": 6, "expr": [
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}},
+                    "right": "172.18.0.3"}},
+                {"drop": null}
+            ]}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "forward", "handle": 7, "expr": [
+                {"match": {"op": "in", "left": {"ct": {"key": "state"}},
+                    "right": ["established", "related"]}},
+                {"accept": null}
+            ]}},
+            {"rule": {"family": "ip", "table": "filter", "chain": "hs-web-0123456789ab", "handle": 8,
+                "expr": [{"drop": null}]}}
+        ]});
+        let known = HashMap::from([
+            (
+                "172.18.0.2".parse().unwrap(),
+                Endpoint {
+                    container: "web".to_string(),
+                    network: Some("backend".to_string()),
+                },
+            ),
+            (
+                "172.18.0.3".parse().unwrap(),
+                Endpoint {
+                    container: "db".to_string(),
+                    network: Some("backend".to_string()),
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            parse_container_arg("web=172.18.0.2").unwrap(),
+            ("web".to_string(), "172.18.0.2".parse().unwrap())
+        );
+        assert!(parse_container_arg("web").is_err());
+
+        let report = adopt(&ruleset, &known);
+        let outcomes: Vec<Outcome> = report.rules.iter().map(|r| r.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Adopted,
+                Outcome::Adopted,
+                Outcome::Covered,
+                Outcome::Skipped
+            ]
+        );
+        assert_eq!(report.rules[2].detail, "db");
+        assert!(report.rules[3].detail.contains("ct state"));
+        assert!(report.has_skipped());
+
+        assert_eq!(report.containers.len(), 1);
+        let config: Config = serde_yaml::from_str(&report.containers[0].rules).unwrap();
+        assert!(config.mapped_ports.external.allow);
+        assert_eq!(config.mapped_ports.external.ips.len(), 1);
+        assert_eq!(config.output[0].container, "db");
+        assert_eq!(config.output[0].dst_ports.len(), 2);
+    }
+}
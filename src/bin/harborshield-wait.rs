@@ -0,0 +1,139 @@
+//! Block until harborshield reports a container's rules as active, for use
+//! in an entrypoint or healthcheck:
+//!
+//! ```text
+//! harborshield-wait --addr unix:/run/harborshield.sock -- my-server --flag
+//! ```
+//!
+//! It asks the admin server's `/ready/<container>` until it answers 200,
+//! then runs the given command in its place, or exits 0 without one. The
+//! container defaults to its hostname, which Docker sets to the short ID.
+//! Bind-mounting the admin socket keeps the check independent of the
+//! container's own rules.
+
+use clap::Parser;
+use harborshield::tr;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Wait until harborshield has applied a container's rules"
+)]
+struct Args {
+    /// Admin server address: "host:port", "unix:/path" or "unix:@name"
+    #[arg(
+        long,
+        env = "HARBORSHIELD_ADDR",
+        default_value = "unix:/run/harborshield.sock"
+    )]
+    addr: String,
+
+    /// Container name or ID; the hostname when omitted
+    #[arg(long, env = "HARBORSHIELD_CONTAINER")]
+    container: Option<String>,
+
+    /// How long to wait before giving up; 0 checks once, as a healthcheck
+    #[arg(long, default_value = "60s", value_parser = harborshield::parse_duration)]
+    timeout: Duration,
+
+    /// Time between checks
+    #[arg(long, default_value = "500ms", value_parser = harborshield::parse_duration)]
+    interval: Duration,
+
+    /// Command to run once the rules are active
+    #[arg(last = true)]
+    command: Vec<String>,
+}
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+fn connect(addr: &str) -> std::io::Result<Box<dyn Stream>> {
+    let timeout = Some(Duration::from_secs(5));
+    match addr.strip_prefix("unix:") {
+        #[cfg(target_os = "linux")]
+        Some(name) if name.starts_with('@') => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name[1..])?;
+            let stream = UnixStream::connect_addr(&addr)?;
+            stream.set_read_timeout(timeout)?;
+            Ok(Box::new(stream))
+        }
+        Some(path) => {
+            let stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(timeout)?;
+            Ok(Box::new(stream))
+        }
+        None => {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_read_timeout(timeout)?;
+            Ok(Box::new(stream))
+        }
+    }
+}
+
+/// Whether the admin server answers 200 for `container`
+fn is_ready(addr: &str, container: &str) -> std::io::Result<bool> {
+    let mut stream = connect(addr)?;
+    write!(
+        stream,
+        "GET /ready/{} HTTP/1.1\r\nHost: harborshield\r\nConnection: close\r\n\r\n",
+        container
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response.starts_with("HTTP/1.1 200"))
+}
+
+fn hostname() -> Option<String> {
+    let name = std::fs::read_to_string("/etc/hostname").ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+fn main() {
+    let args = Args::parse();
+    let Some(container) = args.container.clone().or_else(hostname) else {
+        eprintln!("{}", tr!("wait-no-container"));
+        std::process::exit(2);
+    };
+
+    let deadline = Instant::now() + args.timeout;
+    loop {
+        let error = match is_ready(&args.addr, &container) {
+            Ok(true) => break,
+            Ok(false) => None,
+            Err(e) => Some(e),
+        };
+        if Instant::now() + args.interval > deadline {
+            match error {
+                Some(e) => eprintln!(
+                    "{}",
+                    tr!("wait-connect-failed", addr = args.addr, error = e)
+                ),
+                None => eprintln!(
+                    "{}",
+                    tr!(
+                        "wait-timed-out",
+                        container = container,
+                        timeout = format!("{:?}", args.timeout)
+                    )
+                ),
+            }
+            std::process::exit(1);
+        }
+        std::thread::sleep(args.interval);
+    }
+
+    if let Some((program, rest)) = args.command.split_first() {
+        let e = Command::new(program).args(rest).exec();
+        eprintln!("{}", tr!("wait-exec-failed", program = program, error = e));
+        std::process::exit(127);
+    }
+}
@@ -0,0 +1,307 @@
+//! `--blocklists`: threat feeds of networks no container may talk to.
+//!
+//! The file names the feeds, URLs such as Spamhaus DROP or abuse.ch's
+//! Feodo Tracker or local files, and how often they are refreshed:
+//!
+//! ```yaml
+//! blocklists:
+//!   - https://www.spamhaus.org/drop/drop.txt
+//!   - https://feodotracker.abuse.ch/downloads/ipblocklist.txt
+//!   - /etc/harborshield/blocked-networks.txt
+//! refresh: 6h
+//! ```
+//!
+//! Feeds list a network or address per line; `#` and `;` start comments,
+//! as in Spamhaus' SBL references. Their IPv4 networks are merged into the
+//! set the harborshield chain drops traffic from and to (see
+//! [`crate::nftables::blocklist`]); IPv6 entries are skipped, as the chain
+//! is IPv4 only. A feed that can't be fetched keeps its networks from the
+//! last pass. Downloads are refused with `--offline`, local files are still
+//! read.
+
+use crate::nftables::blocklist as nft_blocklist;
+use crate::offline::{self, Fetch};
+use ipnet::{IpNet, Ipv4Net};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// How often feeds are fetched again unless the file says otherwise
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(6 * 3600);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a feed comes from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    Url(String),
+    File(PathBuf),
+}
+
+impl Source {
+    fn parse(entry: &str) -> Self {
+        if entry.starts_with("http://") || entry.starts_with("https://") {
+            Source::Url(entry.to_string())
+        } else {
+            Source::File(PathBuf::from(entry))
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Url(url) => write!(f, "{}", url),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Blocklists {
+    pub sources: Vec<Source>,
+    pub refresh: Duration,
+}
+
+impl Blocklists {
+    /// The local feeds, which are re-read on every refresh
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.sources.iter().filter_map(|source| match source {
+            Source::File(path) => Some(path.as_path()),
+            Source::Url(_) => None,
+        })
+    }
+
+    /// Local feeds by absolute path, as they are re-read once sandboxed
+    pub fn canonicalized(mut self) -> Self {
+        for source in &mut self.sources {
+            if let Source::File(path) = source
+                && let Ok(absolute) = path.canonicalize()
+            {
+                *path = absolute;
+            }
+        }
+        self
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BlocklistFile {
+    #[serde(default)]
+    blocklists: Vec<String>,
+    #[serde(default)]
+    refresh: Option<String>,
+}
+
+/// Read and check a blocklists file
+pub fn load(path: &Path) -> std::io::Result<Blocklists> {
+    let text = std::fs::read_to_string(path)?;
+    parse(&text).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse(text: &str) -> std::result::Result<Blocklists, String> {
+    let file: BlocklistFile = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    if file.blocklists.is_empty() {
+        return Err("no blocklists listed".to_string());
+    }
+    let mut sources = Vec::new();
+    for entry in &file.blocklists {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            return Err("empty blocklist entry".to_string());
+        }
+        let source = Source::parse(entry);
+        if sources.contains(&source) {
+            return Err(format!("blocklist {} is listed twice", entry));
+        }
+        sources.push(source);
+    }
+    let refresh = match &file.refresh {
+        Some(refresh) => crate::parse_duration(refresh)?,
+        None => DEFAULT_REFRESH,
+    };
+    if refresh.is_zero() {
+        return Err("'refresh' must be greater than zero".to_string());
+    }
+    Ok(Blocklists { sources, refresh })
+}
+
+/// The IPv4 networks of a feed, and how many IPv6 entries were skipped
+pub fn parse_feed(text: &str) -> (Vec<Ipv4Net>, usize) {
+    let mut networks = Vec::new();
+    let mut skipped = 0;
+    for line in text.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or_default();
+        let Some(entry) = line.split_whitespace().next() else {
+            continue;
+        };
+        let network = entry
+            .parse::<IpNet>()
+            .ok()
+            .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from));
+        match network {
+            Some(IpNet::V4(network)) => networks.push(network.trunc()),
+            Some(IpNet::V6(_)) => skipped += 1,
+            // Headers and the like
+            None => {}
+        }
+    }
+    (networks, skipped)
+}
+
+/// Fetches the feeds and keeps the last networks of each
+pub struct Feeds {
+    lists: Blocklists,
+    client: Option<reqwest::Client>,
+    networks: HashMap<Source, Vec<Ipv4Net>>,
+}
+
+impl Feeds {
+    pub fn new(lists: Blocklists) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| warn!("Failed to set up blocklist downloads: {}", e))
+            .ok();
+        Self {
+            lists,
+            client,
+            networks: HashMap::new(),
+        }
+    }
+
+    async fn fetch(&self, source: &Source) -> std::result::Result<String, String> {
+        match source {
+            Source::File(path) => tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| e.to_string()),
+            Source::Url(url) => {
+                offline::guard(Fetch::Blocklist)?;
+                let client = self
+                    .client
+                    .as_ref()
+                    .ok_or_else(|| "no HTTP client".to_string())?;
+                client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| e.to_string())?
+                    .text()
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Fetch every feed again, returning the networks to block, merged
+    pub async fn refresh(&mut self) -> Vec<Ipv4Net> {
+        for source in self.lists.sources.clone() {
+            match self.fetch(&source).await {
+                Ok(text) => {
+                    let (networks, skipped) = parse_feed(&text);
+                    if skipped > 0 {
+                        debug!("Skipped {} IPv6 entries of blocklist {}", skipped, source);
+                    }
+                    self.networks.insert(source, networks);
+                }
+                Err(e) => warn!(
+                    "Failed to fetch blocklist {}, keeping its previous networks: {}",
+                    source, e
+                ),
+            }
+        }
+        let networks: Vec<Ipv4Net> = self.networks.values().flatten().copied().collect();
+        Ipv4Net::aggregate(&networks)
+    }
+}
+
+/// Refresh the feeds every [`Blocklists::refresh`] until `cancel` fires
+pub async fn run_blocklists(lists: Blocklists, cancel: CancellationToken) {
+    let mut ticker = tokio::time::interval(lists.refresh);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut feeds = Feeds::new(lists);
+    let mut previous = None;
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        let networks = feeds.refresh().await;
+        if previous.as_ref() == Some(&networks) {
+            continue;
+        }
+        match nft_blocklist::replace_elements(&networks).await {
+            Ok(()) => {
+                info!(
+                    "Blocking {} networks of {} blocklists",
+                    networks.len(),
+                    feeds.networks.len()
+                );
+                previous = Some(networks);
+            }
+            Err(e) => warn!("Failed to update the blocklist set: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_feeds() {
+        let (networks, skipped) = parse_feed(
+            "; Spamhaus DROP List\n\
+             1.10.16.0/20 ; SBL256894\n\
+             # abuse.ch\n\
+             198.51.100.7\n\
+             2001:db8::/32 ; SBL1\n\
+             10.1.2.3/8\n\
+             not an address\n",
+        );
+        let networks: Vec<String> = networks.iter().map(|net| net.to_string()).collect();
+        assert_eq!(networks, ["1.10.16.0/20", "198.51.100.7/32", "10.0.0.0/8"]);
+        assert_eq!(skipped, 1);
+
+        assert!(parse("blocklists: []\n").is_err());
+        assert!(parse("blocklists: [a.txt, a.txt]\n").is_err());
+        assert!(parse("blocklists: [a.txt]\nrefresh: 0s\n").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let feed = dir.path().join("feed.txt");
+        std::fs::write(&feed, "10.0.0.0/9\n10.128.0.0/9\n192.0.2.1\n").unwrap();
+        let lists = parse(&format!(
+            "blocklists:\n  - {}\n  - {}\nrefresh: 1h\n",
+            feed.display(),
+            dir.path().join("missing.txt").display()
+        ))
+        .unwrap();
+        assert_eq!(lists.refresh, Duration::from_secs(3600));
+        assert_eq!(lists.files().count(), 2);
+
+        let mut feeds = Feeds::new(lists);
+        let merged: Vec<String> = feeds
+            .refresh()
+            .await
+            .iter()
+            .map(|net| net.to_string())
+            .collect();
+        assert_eq!(merged, ["10.0.0.0/8", "192.0.2.1/32"]);
+
+        // A feed that goes away keeps what it last listed
+        std::fs::remove_file(&feed).unwrap();
+        assert_eq!(feeds.refresh().await.len(), 2);
+    }
+}
@@ -0,0 +1,158 @@
+//! Enough of MQTT 3.1.1 to publish at QoS 0: `CONNECT`, `PUBLISH` and
+//! keep-alive pings.
+
+use super::{BusConfig, Message};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::info;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: [u8; 2] = [0xC0, 0x00];
+
+const CLEAN_SESSION: u8 = 0x02;
+const PASSWORD_FLAG: u8 = 0x40;
+const USERNAME_FLAG: u8 = 0x80;
+
+/// Variable length "remaining length" of a fixed header
+fn encode_length(mut length: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_string(value: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+fn packet(kind: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![kind];
+    encode_length(body.len(), &mut packet);
+    packet.extend(body);
+    packet
+}
+
+pub fn connect_packet(client_id: &str, config: &BusConfig) -> Vec<u8> {
+    let mut flags = CLEAN_SESSION;
+    if config.username.is_some() {
+        flags |= USERNAME_FLAG;
+    }
+    if config.password.is_some() {
+        flags |= PASSWORD_FLAG;
+    }
+
+    let mut body = Vec::new();
+    encode_string(b"MQTT", &mut body);
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    encode_string(client_id.as_bytes(), &mut body);
+    if let Some(user) = &config.username {
+        encode_string(user.as_bytes(), &mut body);
+    }
+    if let Some(pass) = &config.password {
+        encode_string(pass.as_bytes(), &mut body);
+    }
+    packet(CONNECT, body)
+}
+
+pub fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(topic.as_bytes(), &mut body);
+    body.extend_from_slice(payload);
+    packet(PUBLISH, body)
+}
+
+pub(super) async fn session(
+    mut stream: TcpStream,
+    config: &BusConfig,
+    rx: &mut mpsc::Receiver<Message>,
+) -> io::Result<()> {
+    let client_id = format!("harborshield-{}", std::process::id());
+    stream
+        .write_all(&connect_packet(&client_id, config))
+        .await?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack).await?;
+    if connack[0] != CONNACK {
+        return Err(io::Error::other("MQTT broker did not answer CONNECT"));
+    }
+    if connack[3] != 0 {
+        return Err(io::Error::other(format!(
+            "MQTT broker refused the connection (code {})",
+            connack[3]
+        )));
+    }
+    info!("Publishing events to {}", config);
+
+    let (mut read, mut write) = stream.into_split();
+    let mut ping = tokio::time::interval(KEEP_ALIVE / 2);
+    ping.tick().await;
+    // Only ping responses arrive at QoS 0; they are read and discarded
+    let mut incoming = [0u8; 64];
+
+    loop {
+        tokio::select! {
+            message = rx.recv() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                let topic = config.topic(&message.levels);
+                write.write_all(&publish_packet(&topic, &message.payload)).await?;
+            }
+            _ = ping.tick() => write.write_all(&PINGREQ).await?,
+            read = read.read(&mut incoming) => {
+                if read? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "MQTT broker closed the connection",
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let config: BusConfig = "mqtt://ha:pw@broker".parse().unwrap();
+        let connect = connect_packet("hs", &config);
+        assert_eq!(connect[0], CONNECT);
+        assert_eq!(connect[1] as usize, connect.len() - 2);
+        assert_eq!(&connect[2..8], b"\x00\x04MQTT");
+        assert_eq!(connect[9], CLEAN_SESSION | USERNAME_FLAG | PASSWORD_FLAG);
+        assert!(connect.ends_with(b"\x00\x02ha\x00\x02pw"));
+
+        let publish = publish_packet("harborshield/audit", b"{}");
+        assert_eq!(publish, b"\x30\x16\x00\x12harborshield/audit{}");
+
+        let mut length = Vec::new();
+        encode_length(321, &mut length);
+        assert_eq!(length, [0xC1, 0x02]);
+
+        assert_eq!(
+            super::super::nats::pub_command("harborshield.audit", b"{}"),
+            b"PUB harborshield.audit 2\r\n{}\r\n"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! The publishing half of the NATS client protocol: `CONNECT`, `PUB` and
+//! answering the server's `PING`s.
+
+use super::{BusConfig, Message};
+use serde_json::json;
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::info;
+
+fn protocol_error(line: &str) -> io::Error {
+    io::Error::other(format!("NATS server replied '{}'", line))
+}
+
+fn closed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "NATS server closed the connection",
+    )
+}
+
+/// `CONNECT` followed by a `PING`, whose `PONG` confirms the connection
+pub fn connect_command(config: &BusConfig) -> Vec<u8> {
+    let mut options = json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": "harborshield",
+        "lang": "rust",
+        "version": crate::VERSION,
+    });
+    if let Some(user) = &config.username {
+        options["user"] = json!(user);
+    }
+    if let Some(pass) = &config.password {
+        options["pass"] = json!(pass);
+    }
+    format!("CONNECT {}\r\nPING\r\n", options).into_bytes()
+}
+
+pub fn pub_command(subject: &str, payload: &[u8]) -> Vec<u8> {
+    let mut command = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+    command.extend_from_slice(payload);
+    command.extend_from_slice(b"\r\n");
+    command
+}
+
+pub(super) async fn session(
+    stream: TcpStream,
+    config: &BusConfig,
+    rx: &mut mpsc::Receiver<Message>,
+) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    let info = lines.next_line().await?.ok_or_else(closed)?;
+    if !info.starts_with("INFO") {
+        return Err(protocol_error(&info));
+    }
+    write.write_all(&connect_command(config)).await?;
+    loop {
+        let line = lines.next_line().await?.ok_or_else(closed)?;
+        match line.as_str() {
+            "PONG" => break,
+            "+OK" => {}
+            _ if line.starts_with("INFO") => {}
+            _ => return Err(protocol_error(&line)),
+        }
+    }
+    info!("Publishing events to {}", config);
+
+    loop {
+        tokio::select! {
+            message = rx.recv() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                let subject = config.topic(&message.levels);
+                write.write_all(&pub_command(&subject, &message.payload)).await?;
+            }
+            line = lines.next_line() => {
+                match line?.ok_or_else(closed)?.as_str() {
+                    "PING" => write.write_all(b"PONG\r\n").await?,
+                    line if line.starts_with("-ERR") => return Err(protocol_error(line)),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Whether a flow would be allowed by a container's live policy, answered by
+//! `POST /check`.
+//!
+//! The container's config is resolved the way it is before its chain is
+//! built, so container references, `host`, time windows and disabled rules
+//! count as they do right now. The flow is then matched in chain order: the
+//! first output rule (outbound) or mapped-port rule (inbound) covering it
+//! decides, and a flow nothing covers falls to the enforcement mode's
+//! terminal rule.
+
+use crate::database::{DB, DbOp, DbOpResult, EnforcementMode};
+use crate::docker::config::{Config, ConfigVerdict, Protocol};
+use crate::docker::container::PortMapping;
+use crate::handlers::disabled::rule_key;
+use crate::handlers::subnet::{unexpected_addresses, withdraw_allow_rules};
+use crate::handlers::utils::resolve_config;
+use crate::runtime::ContainerRuntime;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the container, matched against its output rules
+    #[serde(alias = "egress")]
+    Outbound,
+    /// To one of the container's published ports
+    #[serde(alias = "ingress")]
+    Inbound,
+}
+
+/// A flow to check, as posted to `/check`:
+///
+/// ```json
+/// {"container": "web", "direction": "outbound", "proto": "tcp",
+///  "dst": "10.0.5.20", "dst_port": 5432}
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Flow {
+    /// Name, ID or identity of the container
+    pub container: String,
+    pub direction: Direction,
+    pub proto: Protocol,
+    /// Required inbound; the container's own address outbound
+    #[serde(default)]
+    pub src: Option<IpAddr>,
+    /// Only needed against rules restricting source ports
+    #[serde(default)]
+    pub src_port: Option<u16>,
+    /// Required outbound; the container inbound
+    #[serde(default)]
+    pub dst: Option<IpAddr>,
+    /// Inbound, either the container port or the host port it is
+    /// published on
+    pub dst_port: u16,
+}
+
+impl Flow {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.direction {
+            Direction::Outbound if self.dst.is_none() => {
+                Err("outbound flows need a 'dst' address".to_string())
+            }
+            Direction::Inbound if self.src.is_none() => {
+                Err("inbound flows need a 'src' address".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub container: String,
+    pub mode: EnforcementMode,
+    pub allowed: bool,
+    /// `accept`, `drop`, `queue` or `jump`
+    pub verdict: &'static str,
+    /// `output.<n>` (numbered from 1 as in the chain's rule comments),
+    /// `mapped_ports.external` or `mapped_ports.localhost`; unset when the
+    /// terminal rule decided
+    pub rule_id: Option<String>,
+    /// The matching rule in the words of the audit log
+    pub rule: Option<String>,
+    pub reason: String,
+}
+
+struct Decision {
+    allowed: bool,
+    verdict: &'static str,
+    rule_id: Option<String>,
+    rule: Option<String>,
+    reason: String,
+}
+
+impl Decision {
+    fn rule(rule_id: String, rule: Option<String>, verdict: &ConfigVerdict) -> Self {
+        let (allowed, verdict_name, reason) = if verdict.drop {
+            (false, "drop", "dropped by the rule".to_string())
+        } else if !verdict.chain.is_empty() {
+            (
+                true,
+                "jump",
+                format!("handed to chain {}, which decides", verdict.chain),
+            )
+        } else if verdict.queue > 0 {
+            (
+                true,
+                "queue",
+                format!("handed to userspace queue {}", verdict.queue),
+            )
+        } else {
+            (true, "accept", "accepted by the rule".to_string())
+        };
+        Self {
+            allowed,
+            verdict: verdict_name,
+            rule_id: Some(rule_id),
+            rule,
+            reason,
+        }
+    }
+
+    fn terminal(mode: EnforcementMode, unmatched: &str) -> Self {
+        let (allowed, verdict, reason) = match mode {
+            EnforcementMode::Enforce => (false, "drop", format!("{}; default drop", unmatched)),
+            EnforcementMode::Permissive => (
+                true,
+                "accept",
+                format!("{}; logged and accepted in permissive mode", unmatched),
+            ),
+            EnforcementMode::Disabled => (
+                true,
+                "accept",
+                "enforcement is disabled for the container".to_string(),
+            ),
+            EnforcementMode::Quarantined => (
+                false,
+                "drop",
+                format!("{}; the container is quarantined", unmatched),
+            ),
+        };
+        Self {
+            allowed,
+            verdict,
+            rule_id: None,
+            rule: None,
+            reason,
+        }
+    }
+}
+
+fn check_outbound(config: &Config, flow: &Flow) -> Option<Decision> {
+    let dst = flow.dst?;
+    config
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, rule)| !rule.skip && rule.proto == flow.proto)
+        // A container reference still set is waiting for its target's
+        // addresses and matches nothing yet
+        .filter(|(_, rule)| rule.container.is_empty())
+        .filter(|(_, rule)| rule.ips.is_empty() || rule.ips.iter().any(|ip| ip.contains(&dst)))
+        .filter(|(_, rule)| {
+            rule.dst_ports.is_empty() || rule.dst_ports.iter().any(|p| p.contains(flow.dst_port))
+        })
+        .find(|(_, rule)| {
+            rule.src_ports.is_empty()
+                || flow
+                    .src_port
+                    .is_some_and(|port| rule.src_ports.iter().any(|p| p.contains(port)))
+        })
+        .map(|(index, rule)| {
+            Decision::rule(
+                format!("output.{}", index + 1),
+                Some(rule_key(rule)),
+                &rule.verdict,
+            )
+        })
+}
+
+fn check_inbound(config: &Config, ports: &[PortMapping], flow: &Flow) -> Option<Decision> {
+    let src = flow.src?;
+    let published = ports.iter().any(|mapping| {
+        mapping.host_port.is_some()
+            && mapping.protocol == flow.proto.to_string()
+            && (mapping.container_port == flow.dst_port || mapping.host_port == Some(flow.dst_port))
+    });
+    if !published {
+        return Some(Decision {
+            allowed: false,
+            verdict: "drop",
+            rule_id: None,
+            rule: None,
+            reason: format!("{}/{} is not a published port", flow.dst_port, flow.proto),
+        });
+    }
+
+    if src.is_loopback() {
+        let localhost = &config.mapped_ports.localhost;
+        return localhost.allow.then(|| {
+            Decision::rule(
+                "mapped_ports.localhost".to_string(),
+                None,
+                &localhost.verdict,
+            )
+        });
+    }
+
+    let external = &config.mapped_ports.external;
+    let rule_id = "mapped_ports.external".to_string();
+    if !external.allow {
+        return None;
+    }
+    if external.ips.iter().any(|ip| ip.contains(&src))
+        || (external.ips.is_empty() && external.rdns.is_empty())
+    {
+        return Some(Decision::rule(rule_id, None, &external.verdict));
+    }
+    (!external.rdns.is_empty()).then(|| Decision {
+        allowed: false,
+        verdict: "drop",
+        rule_id: Some(rule_id),
+        rule: None,
+        reason: "admitted only once its reverse DNS is verified against the rdns patterns"
+            .to_string(),
+    })
+}
+
+/// Decide `flow` against an already resolved `config` under `mode`
+pub fn evaluate(
+    container: &str,
+    config: &Config,
+    ports: &[PortMapping],
+    flow: &Flow,
+    mode: EnforcementMode,
+) -> CheckResult {
+    let decision = if mode == EnforcementMode::Disabled {
+        Decision::terminal(mode, "")
+    } else {
+        let matched = match flow.direction {
+            Direction::Outbound => check_outbound(config, flow),
+            Direction::Inbound => check_inbound(config, ports, flow),
+        };
+        matched.unwrap_or_else(|| Decision::terminal(mode, "no rule matches"))
+    };
+
+    CheckResult {
+        container: container.to_string(),
+        mode,
+        allowed: decision.allowed,
+        verdict: decision.verdict,
+        rule_id: decision.rule_id,
+        rule: decision.rule,
+        reason: decision.reason,
+    }
+}
+
+/// Check `flow` against the live policy of its container, or `None` when
+/// the container is not tracked
+pub async fn check(
+    db: &DB,
+    docker: &dyn ContainerRuntime,
+    host_addrs: &[IpAddr],
+    flow: &Flow,
+) -> Option<CheckResult> {
+    let tracker = docker.container_tracker();
+    let container = tracker
+        .find_container(&flow.container)
+        .or_else(|| tracker.find_by_identity(&flow.container).into_iter().next())?;
+    let identity = container.identity();
+
+    if !container.enabled {
+        return Some(CheckResult {
+            container: identity,
+            mode: EnforcementMode::Disabled,
+            allowed: true,
+            verdict: "accept",
+            rule_id: None,
+            rule: None,
+            reason: "the container is not managed by harborshield".to_string(),
+        });
+    }
+
+    let mode = match db.execute(&DbOp::GetEnforcementMode(&identity)).await {
+        Ok(DbOpResult::EnforcementMode(mode)) => mode,
+        _ => EnforcementMode::Enforce,
+    };
+    let config = container.config.clone().unwrap_or_else(Config::new);
+    let mut resolved = resolve_config(
+        &container,
+        &config,
+        tracker,
+        host_addrs,
+        chrono::Utc::now().timestamp(),
+    );
+    if let Some(subnet) = &config.expected_subnet {
+        let addrs: Vec<IpAddr> = container
+            .networks
+            .values()
+            .flat_map(|network| network.ip_addresses.iter().copied())
+            .collect();
+        if !unexpected_addresses(subnet, &addrs).is_empty() {
+            withdraw_allow_rules(&mut resolved);
+        }
+    }
+    if mode == EnforcementMode::Quarantined {
+        resolved = resolved.quarantined();
+    }
+
+    Some(evaluate(&identity, &resolved, &container.ports, flow, mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(json: &str) -> Flow {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+mapped_ports:
+  external:
+    allow: true
+    ips: ["198.51.100.0/24"]
+output:
+  - ips: ["10.0.5.0/24"]
+    proto: tcp
+    dst_ports: [5432]
+  - ips: ["10.0.6.1"]
+    proto: udp
+    dst_ports: [53]
+    verdict:
+      queue: 3
+"#,
+        )
+        .unwrap();
+        let ports = vec![PortMapping {
+            container_port: 80,
+            host_port: Some(8080),
+            host_ip: None,
+            protocol: "tcp".to_string(),
+        }];
+        let mode = EnforcementMode::Enforce;
+
+        let db = flow(
+            r#"{"container":"web","direction":"outbound","proto":"tcp","dst":"10.0.5.20","dst_port":5432}"#,
+        );
+        let result = evaluate("web", &config, &ports, &db, mode);
+        assert!(result.allowed);
+        assert_eq!(result.rule_id.as_deref(), Some("output.1"));
+        assert_eq!(result.rule.as_deref(), Some("tcp to 10.0.5.0/24 port 5432"));
+
+        let dns = flow(
+            r#"{"container":"web","direction":"egress","proto":"udp","dst":"10.0.6.1","dst_port":53}"#,
+        );
+        let result = evaluate("web", &config, &ports, &dns, mode);
+        assert_eq!(
+            (result.verdict, result.rule_id.as_deref()),
+            ("queue", Some("output.2"))
+        );
+
+        let other = flow(
+            r#"{"container":"web","direction":"outbound","proto":"tcp","dst":"10.0.5.20","dst_port":22}"#,
+        );
+        let result = evaluate("web", &config, &ports, &other, mode);
+        assert!(!result.allowed);
+        assert_eq!(result.rule_id, None);
+        assert!(evaluate("web", &config, &ports, &other, EnforcementMode::Permissive).allowed);
+
+        let inbound = flow(
+            r#"{"container":"web","direction":"inbound","proto":"tcp","src":"198.51.100.7","dst_port":8080}"#,
+        );
+        let result = evaluate("web", &config, &ports, &inbound, mode);
+        assert_eq!(result.rule_id.as_deref(), Some("mapped_ports.external"));
+        assert!(result.allowed);
+        let stranger = flow(
+            r#"{"container":"web","direction":"inbound","proto":"tcp","src":"203.0.113.9","dst_port":80}"#,
+        );
+        assert!(!evaluate("web", &config, &ports, &stranger, mode).allowed);
+        assert!(
+            !evaluate(
+                "web",
+                &config.quarantined(),
+                &ports,
+                &db,
+                EnforcementMode::Quarantined
+            )
+            .allowed
+        );
+
+        assert!(
+            flow(r#"{"container":"web","direction":"outbound","proto":"tcp","dst_port":443}"#)
+                .validate()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Grafana dashboard for the metrics endpoint, printed by
+//! `harborshield export dashboard`.
+//!
+//! The dashboard is generated from [`METRICS`], so every exported metric
+//! gets a panel and a renamed metric cannot leave a stale query behind.
+//! Import it in Grafana and pick the Prometheus data source scraping
+//! `/metrics`.
+
+use crate::server::{METRICS, MetricKind, MetricSpec};
+use serde_json::{Value, json};
+
+/// Dashboard rows, in display order
+const GROUPS: &[(&str, &str)] = &[
+    ("overview", "Overview"),
+    ("rules", "Rules"),
+    ("events", "Docker events"),
+    ("nftables", "nftables"),
+    ("dns", "DNS"),
+];
+
+/// Grid columns Grafana lays panels out in
+const GRID_WIDTH: u32 = 24;
+const PANEL_WIDTH: u32 = 12;
+const PANEL_HEIGHT: u32 = 8;
+
+/// Stable identifier, so re-importing updates the existing dashboard
+pub const DASHBOARD_UID: &str = "harborshield";
+
+/// The PromQL query a panel for `spec` plots
+pub fn panel_query(spec: &MetricSpec) -> String {
+    let selector = format!("{}{{instance=~\"$instance\"}}", spec.name);
+    let by = if spec.labels.is_empty() {
+        String::new()
+    } else {
+        format!(" by ({})", spec.labels.join(", "))
+    };
+    match spec.kind {
+        MetricKind::Counter => format!("sum{} (rate({}[$__rate_interval]))", by, selector),
+        MetricKind::Gauge => format!("sum{} ({})", by, selector),
+        MetricKind::Histogram => format!(
+            "max{} ({}{{instance=~\"$instance\", quantile=\"0.99\"}})",
+            by, spec.name
+        ),
+    }
+}
+
+fn legend(spec: &MetricSpec) -> String {
+    match spec.labels {
+        [] => spec.name.trim_start_matches("harborshield_").to_string(),
+        labels => labels
+            .iter()
+            .map(|label| format!("{{{{{}}}}}", label))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn unit(spec: &MetricSpec) -> &'static str {
+    match spec.kind {
+        MetricKind::Histogram if spec.name.ends_with("_seconds") => "s",
+        MetricKind::Counter => "ops",
+        _ => "short",
+    }
+}
+
+fn title(spec: &MetricSpec) -> String {
+    let name = spec.name.trim_start_matches("harborshield_");
+    let mut title = name.replace('_', " ");
+    if let Some(first) = title.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+    match spec.kind {
+        MetricKind::Counter => format!("{} (per second)", title),
+        MetricKind::Histogram => format!("{} (p99)", title),
+        MetricKind::Gauge => title,
+    }
+}
+
+/// The dashboard JSON model, ready for Grafana's import dialog
+pub fn grafana_dashboard(version: &str) -> Value {
+    let mut panels = Vec::new();
+    let mut id = 0;
+    let mut y = 0;
+
+    for (group, group_title) in GROUPS {
+        let specs: Vec<&MetricSpec> = METRICS.iter().filter(|m| m.group == *group).collect();
+        if specs.is_empty() {
+            continue;
+        }
+        id += 1;
+        panels.push(json!({
+            "id": id,
+            "type": "row",
+            "title": group_title,
+            "collapsed": false,
+            "gridPos": { "h": 1, "w": GRID_WIDTH, "x": 0, "y": y },
+            "panels": [],
+        }));
+        y += 1;
+
+        for (i, spec) in specs.iter().enumerate() {
+            id += 1;
+            let x = (i as u32 * PANEL_WIDTH) % GRID_WIDTH;
+            panels.push(json!({
+                "id": id,
+                "type": "timeseries",
+                "title": title(spec),
+                "description": spec.help,
+                "datasource": { "type": "prometheus", "uid": "${DS_PROMETHEUS}" },
+                "gridPos": { "h": PANEL_HEIGHT, "w": PANEL_WIDTH, "x": x, "y": y },
+                "fieldConfig": { "defaults": { "unit": unit(spec) }, "overrides": [] },
+                "targets": [{
+                    "refId": "A",
+                    "expr": panel_query(spec),
+                    "legendFormat": legend(spec),
+                }],
+            }));
+            if x + PANEL_WIDTH >= GRID_WIDTH || i + 1 == specs.len() {
+                y += PANEL_HEIGHT;
+            }
+        }
+    }
+
+    json!({
+        "__inputs": [{
+            "name": "DS_PROMETHEUS",
+            "label": "Prometheus",
+            "type": "datasource",
+            "pluginId": "prometheus",
+            "pluginName": "Prometheus",
+        }],
+        "uid": DASHBOARD_UID,
+        "title": "Harborshield",
+        "description": format!("Generated by harborshield {}", version),
+        "tags": ["harborshield", "firewall"],
+        "schemaVersion": 39,
+        "version": 1,
+        "editable": true,
+        "time": { "from": "now-6h", "to": "now" },
+        "refresh": "30s",
+        "templating": {
+            "list": [{
+                "name": "instance",
+                "label": "Instance",
+                "type": "query",
+                "datasource": { "type": "prometheus", "uid": "${DS_PROMETHEUS}" },
+                "query": "label_values(harborshield_active_containers, instance)",
+                "refresh": 2,
+                "includeAll": true,
+                "multi": true,
+                "current": { "text": "All", "value": "$__all" },
+            }],
+        },
+        "panels": panels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dashboard_covers_metrics() {
+        let dashboard = grafana_dashboard("test");
+        let panels = dashboard["panels"].as_array().unwrap();
+        let exprs: Vec<&str> = panels
+            .iter()
+            .filter_map(|panel| panel["targets"][0]["expr"].as_str())
+            .collect();
+        assert_eq!(exprs.len(), METRICS.len());
+        for spec in METRICS {
+            assert!(
+                GROUPS.iter().any(|(group, _)| *group == spec.group),
+                "{} has no dashboard row",
+                spec.name
+            );
+            assert!(exprs.iter().any(|expr| expr.contains(spec.name)));
+        }
+
+        let ids: std::collections::HashSet<_> =
+            panels.iter().map(|panel| panel["id"].as_u64()).collect();
+        assert_eq!(ids.len(), panels.len());
+
+        let stage_errors = METRICS
+            .iter()
+            .find(|m| m.name == "harborshield_pipeline_stage_errors_total")
+            .unwrap();
+        assert_eq!(
+            panel_query(stage_errors),
+            "sum by (stage) (rate(harborshield_pipeline_stage_errors_total{instance=~\"$instance\"}[$__rate_interval]))"
+        );
+    }
+}
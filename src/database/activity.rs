@@ -0,0 +1,227 @@
+//! Allow rules that stopped matching traffic, behind `harborshield unused`.
+//!
+//! Rule counters start from zero whenever a chain is rebuilt, so they can't
+//! tell how long a rule has gone unmatched. Each stats pass of the daemon
+//! records, per container and rule set entry, when an allow rule was first
+//! installed and when its counter last grew. A rule is unused once its last
+//! hit, or its installation when it never matched, is older than the period
+//! asked about.
+
+use crate::Result;
+use crate::database::{DB, DbOp, DbOpResult, RuleActivity};
+use crate::output::{Cell, Color, Column, Render, Table};
+use crate::status::RuleHits;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Rules not installed for this long are forgotten
+pub const PRUNE_AFTER: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Packet counts of allow rules at the last sample, by chain and rule
+#[derive(Debug, Default)]
+pub struct ActivitySampler {
+    last: HashMap<(String, String), u64>,
+}
+
+impl ActivitySampler {
+    /// Activity of the allow rules in `rules` at `now`. A rule was hit when
+    /// its counter grew since the last sample or, not sampled before, when
+    /// it counted anything at all
+    pub fn sample(&mut self, rules: &[RuleHits], now: i64) -> Vec<RuleActivity> {
+        // Rules built from one entry, such as per-port ones, count together
+        let mut packets: HashMap<(String, String), (&str, u64)> = HashMap::new();
+        for rule in rules.iter().filter(|r| r.allow) {
+            let entry = packets
+                .entry((rule.chain.clone(), rule.rule.clone()))
+                .or_insert((&rule.container_name, 0));
+            entry.1 += rule.packets;
+        }
+
+        let mut activity: Vec<RuleActivity> = packets
+            .iter()
+            .map(|(key, (container_name, packets))| {
+                let hit = match self.last.get(key) {
+                    Some(before) => packets != before && *packets > 0,
+                    None => *packets > 0,
+                };
+                RuleActivity {
+                    container_name: container_name.to_string(),
+                    rule: key.1.clone(),
+                    first_seen: now,
+                    last_seen: now,
+                    last_hit: hit.then_some(now),
+                }
+            })
+            .collect();
+        activity.sort_by(|a, b| (&a.container_name, &a.rule).cmp(&(&b.container_name, &b.rule)));
+        self.last = packets
+            .into_iter()
+            .map(|(key, (_, packets))| (key, packets))
+            .collect();
+        activity
+    }
+}
+
+/// Record one sample and forget rules not installed since `prune_before`
+pub async fn record(db: &mut DB, activity: &[RuleActivity], prune_before: i64) -> Result<()> {
+    let mut ops: Vec<DbOp> = activity.iter().map(DbOp::RecordRuleActivity).collect();
+    ops.push(DbOp::PruneRuleActivity {
+        before: prune_before,
+    });
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    Ok(())
+}
+
+pub async fn list(db: &DB) -> Result<Vec<RuleActivity>> {
+    match db.execute(&DbOp::ListRuleActivity).await? {
+        DbOpResult::RuleActivity(activity) => Ok(activity),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// An allow rule that matched nothing for a while
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnusedRule {
+    pub container_name: String,
+    pub rule: String,
+    /// Unix seconds; `None` when it never matched
+    pub last_hit: Option<i64>,
+    /// Whole days since it last matched or was installed
+    pub idle_days: i64,
+}
+
+/// The allow rules of the latest sample idle for at least `after` at `now`
+pub fn unused(activity: &[RuleActivity], now: i64, after: Duration) -> Vec<UnusedRule> {
+    // Rules missing from the latest sample aren't installed any more
+    let latest = activity
+        .iter()
+        .map(|a| a.last_seen)
+        .max()
+        .unwrap_or_default();
+    activity
+        .iter()
+        .filter(|a| a.last_seen == latest)
+        .filter_map(|a| {
+            let idle = now - a.last_hit.unwrap_or(a.first_seen);
+            (idle >= after.as_secs() as i64).then(|| UnusedRule {
+                container_name: a.container_name.clone(),
+                rule: a.rule.clone(),
+                last_hit: a.last_hit,
+                idle_days: idle / 86400,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnusedReport {
+    /// Idle period asked about, in seconds
+    pub after: u64,
+    pub rules: Vec<UnusedRule>,
+}
+
+impl Render for UnusedReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::left("RULE"),
+            Column::left("LAST HIT"),
+            Column::right("IDLE DAYS"),
+        ]);
+        for rule in &self.rules {
+            let last_hit = match rule.last_hit {
+                Some(ts) => chrono::DateTime::from_timestamp(ts, 0)
+                    .map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+                    .into(),
+                None => Cell::colored("never", Color::Yellow),
+            };
+            table.row(vec![
+                rule.container_name.clone().into(),
+                rule.rule.clone().into(),
+                last_hit,
+                rule.idle_days.to_string().into(),
+            ]);
+        }
+        let days = self.after / 86400;
+        if self.rules.is_empty() {
+            table.footer(format!(
+                "every allow rule matched traffic in the last {} days",
+                days
+            ));
+        } else {
+            table.footer(format!(
+                "{} allow rule(s) matched nothing for {} days or more; remove them if they are no longer needed",
+                self.rules.len(),
+                days
+            ));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits(rule: &str, packets: u64) -> RuleHits {
+        RuleHits {
+            container_name: "web".to_string(),
+            chain: "hs-web-1".to_string(),
+            position: 1,
+            rule: rule.to_string(),
+            verdict: Some("accept"),
+            packets,
+            bytes: packets * 60,
+            allow: true,
+            unused: packets == 0,
+        }
+    }
+
+    #[test]
+    fn test_unused() {
+        let mut sampler = ActivitySampler::default();
+        let first = sampler.sample(&[hits("Output rule 1", 4), hits("Output rule 2", 0)], 100);
+        assert_eq!(first[0].last_hit, Some(100));
+        assert_eq!(first[1].last_hit, None);
+        // Unchanged counters aren't hits
+        let second = sampler.sample(&[hits("Output rule 1", 4), hits("Output rule 2", 0)], 200);
+        assert!(second.iter().all(|a| a.last_hit.is_none()));
+
+        let day = 86400;
+        let activity = [
+            RuleActivity {
+                container_name: "web".to_string(),
+                rule: "Output rule 1".to_string(),
+                first_seen: 0,
+                last_seen: 40 * day,
+                last_hit: Some(35 * day),
+            },
+            RuleActivity {
+                container_name: "web".to_string(),
+                rule: "Output rule 2".to_string(),
+                first_seen: 0,
+                last_seen: 40 * day,
+                last_hit: None,
+            },
+            // Since removed from the rules
+            RuleActivity {
+                container_name: "web".to_string(),
+                rule: "Output rule 3".to_string(),
+                first_seen: 0,
+                last_seen: 20 * day,
+                last_hit: None,
+            },
+        ];
+        let unused = unused(&activity, 40 * day, Duration::from_secs(30 * day as u64));
+        assert_eq!(
+            unused,
+            [UnusedRule {
+                container_name: "web".to_string(),
+                rule: "Output rule 2".to_string(),
+                last_hit: None,
+                idle_days: 40,
+            }]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Short-lived rules, behind `harborshield apply-adhoc`.
+//!
+//! A rules document is stored against a container identity with an expiry
+//! and layered over the container's rules label like a profile it extends
+//! (see [`crate::docker::config::profiles`]). A running daemon applies it
+//! within seconds and drops it again once it expires; both ends are
+//! recorded in the audit log.
+
+use crate::Result;
+use crate::database::{AdhocRule, AuditEntry, DB, DbOp, DbOpResult, audit};
+use crate::output::{Column, Render, Table};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+/// Active rule sets by container identity, oldest first
+static ACTIVE: LazyLock<RwLock<HashMap<String, Vec<String>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+fn time(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0).map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+}
+
+/// Store `rules` for `container_name` until `ttl` from now, with its audit
+/// entry in the same transaction
+pub async fn apply(
+    db: &mut DB,
+    container_name: &str,
+    rules: &str,
+    ttl: Duration,
+    reason: Option<&str>,
+) -> Result<AdhocRule> {
+    let now = chrono::Utc::now().timestamp();
+    let rule = AdhocRule {
+        id: 0,
+        container_name: container_name.to_string(),
+        rules: rules.to_string(),
+        reason: reason.map(str::to_string),
+        created_at: now,
+        expires_at: now + ttl.as_secs() as i64,
+    };
+    let mut detail = format!(
+        "ad-hoc rules until {}: {}",
+        time(rule.expires_at),
+        rules.trim()
+    );
+    if let Some(reason) = reason {
+        detail.push_str(&format!(" ({})", reason));
+    }
+    let entry = AuditEntry::builder()
+        .ts(now)
+        .kind(audit::KIND_ADHOC_APPLIED)
+        .container_name(container_name.to_string())
+        .detail(detail)
+        .build();
+
+    let ops = [DbOp::InsertAdhocRule(&rule), DbOp::InsertAuditEntry(&entry)];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(rule)
+}
+
+/// Every stored rule set, expired ones included until [`expire`] removes
+/// them
+pub async fn list(db: &DB) -> Result<Vec<AdhocRule>> {
+    match db.execute(&DbOp::ListAdhocRules).await? {
+        DbOpResult::AdhocRules(rules) => Ok(rules),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Delete the rule sets expired at `now`, recording each, and return those
+/// still active
+pub async fn expire(db: &mut DB, now: i64) -> Result<Vec<AdhocRule>> {
+    let (active, expired): (Vec<AdhocRule>, Vec<AdhocRule>) = list(db)
+        .await?
+        .into_iter()
+        .partition(|rule| rule.expires_at > now);
+    if expired.is_empty() {
+        return Ok(active);
+    }
+
+    let entries: Vec<AuditEntry> = expired
+        .iter()
+        .map(|rule| {
+            AuditEntry::builder()
+                .ts(now)
+                .kind(audit::KIND_ADHOC_EXPIRED)
+                .container_name(rule.container_name.clone())
+                .detail(format!(
+                    "ad-hoc rules applied at {} expired",
+                    time(rule.created_at)
+                ))
+                .build()
+        })
+        .collect();
+    let mut ops: Vec<DbOp> = expired
+        .iter()
+        .map(|rule| DbOp::DeleteAdhocRule(rule.id))
+        .collect();
+    ops.extend(entries.iter().map(DbOp::InsertAuditEntry));
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(&entries);
+    Ok(active)
+}
+
+/// Layer `rules` over the rules labels of their containers from now on
+pub fn set_active(rules: &[AdhocRule]) {
+    let mut active: HashMap<String, Vec<String>> = HashMap::new();
+    for rule in rules {
+        active
+            .entry(rule.container_name.clone())
+            .or_default()
+            .push(rule.rules.clone());
+    }
+    if let Ok(mut current) = ACTIVE.write() {
+        *current = active;
+    }
+}
+
+/// The rule sets layered over the rules label of `identity`
+pub fn layers(identity: &str) -> Vec<String> {
+    ACTIVE
+        .read()
+        .ok()
+        .and_then(|active| active.get(identity).cloned())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdhocReport {
+    pub rules: Vec<AdhocRule>,
+}
+
+impl Render for AdhocReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::left("APPLIED").wide(),
+            Column::left("EXPIRES"),
+            Column::left("REASON"),
+        ]);
+
+        for rule in &self.rules {
+            table.row(vec![
+                rule.container_name.clone().into(),
+                time(rule.created_at).into(),
+                time(rule.expires_at).into(),
+                rule.reason
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string())
+                    .into(),
+            ]);
+        }
+
+        if self.rules.is_empty() {
+            table.footer("no ad-hoc rules are active");
+        }
+        table
+    }
+}
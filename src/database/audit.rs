@@ -0,0 +1,123 @@
+//! Audit log of reconcile runs, read by `harborshield audit`.
+
+use crate::Result;
+use crate::database::{AuditEntry, DB, DbOp, DbOpResult};
+use crate::output::{Cell, Color, Column, Render, Table};
+use serde::Serialize;
+
+pub const KIND_RECONCILE: &str = "reconcile";
+pub const KIND_DRIFT: &str = "drift";
+pub const KIND_RULE_DISABLED: &str = "rule_disabled";
+pub const KIND_RULE_ENABLED: &str = "rule_enabled";
+/// A disabled rule was deleted from the config
+pub const KIND_RULE_REMOVED: &str = "rule_removed";
+pub const KIND_QUARANTINED: &str = "quarantined";
+pub const KIND_QUARANTINE_RELEASED: &str = "quarantine_released";
+pub const KIND_ADHOC_APPLIED: &str = "adhoc_applied";
+pub const KIND_ADHOC_EXPIRED: &str = "adhoc_expired";
+/// An address was blocked through the admin API
+pub const KIND_ADDRESS_BLOCKED: &str = "address_blocked";
+/// An address was banned with `harborshield ban` or `POST /bans`
+pub const KIND_ADDRESS_BANNED: &str = "address_banned";
+/// A ban was lifted before it expired
+pub const KIND_ADDRESS_UNBANNED: &str = "address_unbanned";
+pub const KIND_BAN_EXPIRED: &str = "ban_expired";
+/// A rule with `notify_on_hit` matched traffic
+pub const KIND_RULE_HIT: &str = "rule_hit";
+/// An allow rule matched no traffic for `--notify-unused-after`
+pub const KIND_UNUSED_ALLOW: &str = "unused_allow";
+/// An allow rule let new flows through without counting them; see
+/// [`crate::handlers::sampling`]
+pub const KIND_RULE_SUSPECT: &str = "rule_suspect";
+/// A change freeze started
+pub const KIND_FROZEN: &str = "frozen";
+/// A change freeze ended and the changes it held were applied
+pub const KIND_UNFROZEN: &str = "unfrozen";
+/// A container's enforcement mode was set through the CLI or admin API
+pub const KIND_ENFORCEMENT_CHANGED: &str = "enforcement_changed";
+/// The kill switch was engaged with `harborshield panic`
+pub const KIND_PANIC_ENGAGED: &str = "panic_engaged";
+/// The kill switch was released with `harborshield panic --release`
+pub const KIND_PANIC_RELEASED: &str = "panic_released";
+
+/// Append `entries` in one transaction
+pub async fn record(db: &mut DB, entries: &[AuditEntry]) -> Result<()> {
+    let ops: Vec<DbOp> = entries.iter().map(DbOp::InsertAuditEntry).collect();
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(entries);
+    Ok(())
+}
+
+/// A container's entries of the given kinds, oldest first
+pub async fn list_for_container(
+    db: &DB,
+    container_name: &str,
+    kinds: &[&str],
+) -> Result<Vec<AuditEntry>> {
+    match db
+        .execute(&DbOp::ListContainerAuditEntries {
+            container_name,
+            kinds,
+        })
+        .await?
+    {
+        DbOpResult::AuditEntries(entries) => Ok(entries),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Entries at or after `since` (unix seconds), oldest first
+pub async fn list_since(db: &DB, since: i64) -> Result<Vec<AuditEntry>> {
+    match db.execute(&DbOp::ListAuditEntries { since }).await? {
+        DbOpResult::AuditEntries(entries) => Ok(entries),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditReport {
+    pub entries: Vec<AuditEntry>,
+}
+
+impl Render for AuditReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("TIME"),
+            Column::left("KIND"),
+            Column::left("CONTAINER"),
+            Column::left("REPAIRED"),
+            Column::left("DETAIL"),
+        ]);
+
+        for entry in &self.entries {
+            let time = chrono::DateTime::from_timestamp(entry.ts, 0)
+                .map_or_else(|| entry.ts.to_string(), |t| t.to_rfc3339());
+            let kind = match entry.kind.as_str() {
+                KIND_DRIFT => Cell::colored(entry.kind.clone(), Color::Yellow),
+                KIND_QUARANTINED => Cell::colored(entry.kind.clone(), Color::Red),
+                _ => entry.kind.clone().into(),
+            };
+            let repaired = match (entry.kind.as_str(), entry.repaired) {
+                (KIND_DRIFT, true) => Cell::colored("yes", Color::Green),
+                (KIND_DRIFT, false) => Cell::colored("no", Color::Red),
+                _ => "-".into(),
+            };
+            table.row(vec![
+                time.into(),
+                kind,
+                entry
+                    .container_name
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string())
+                    .into(),
+                repaired,
+                entry.detail.clone().into(),
+            ]);
+        }
+
+        if self.entries.is_empty() {
+            table.footer("no audit entries in this period");
+        }
+        table
+    }
+}
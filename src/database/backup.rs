@@ -0,0 +1,430 @@
+//! `harborshield db`: snapshots of the database before upgrades, a JSON
+//! dump for inspection, and restoring a snapshot after a host rebuild.
+//!
+//! Backups are taken with `VACUUM INTO`, which gives a consistent,
+//! self-contained copy while the daemon keeps writing. Restoring checks that
+//! every migration the backup went through is one this build knows; a
+//! backup from a newer release, or of a schema that diverged, is refused.
+//! Older backups are migrated forward when the daemon next opens them.
+//!
+//! The daemon holds an exclusive lock on `db.sqlite.lock` while it runs, and
+//! a restore takes the same lock for the swap, so it is refused while the
+//! daemon still has the database open.
+
+use crate::database::DB;
+use crate::database::error::DatabaseError;
+use crate::output::{Column, Render, Table};
+use crate::{Error, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteRow};
+use sqlx::{Column as _, ConnectOptions, Connection, Row, SqliteConnection, TypeInfo, ValueRef};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+/// Where the database being replaced is kept by a restore
+pub const PRE_RESTORE_SUFFIX: &str = "pre-restore";
+
+/// Exclusive lock beside a database, released when dropped
+#[derive(Debug)]
+pub struct DbLock {
+    _file: File,
+}
+
+impl DbLock {
+    /// The lock file of the database at `db_path`
+    pub fn path(db_path: &Path) -> PathBuf {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Lock the database at `db_path`, or `None` if another process holds it
+    pub fn try_acquire(db_path: &Path) -> std::io::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(Self::path(db_path))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    /// As [`Self::try_acquire`], failing when the lock is held
+    pub async fn acquire(db_path: &Path) -> Result<Self> {
+        Self::try_acquire(db_path)?.ok_or_else(|| {
+            DatabaseError::Locked {
+                path: Self::path(db_path),
+            }
+            .into()
+        })
+    }
+}
+
+/// The schema version of this build: its latest migration
+pub fn schema_version() -> i64 {
+    super::MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The latest migration applied to a database; 0 before any
+async fn applied_version(conn: &mut SqliteConnection) -> Result<i64> {
+    sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT MAX(version) FROM _sqlx_migrations WHERE success = 1",
+    )
+    .fetch_one(conn)
+    .await
+    .map(Option::unwrap_or_default)
+    .map_err(|e| Error::Database(format!("Failed to read the schema version: {}", e)))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupReport {
+    pub path: PathBuf,
+    pub schema_version: i64,
+    pub bytes: u64,
+}
+
+impl Render for BackupReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("BACKUP"),
+            Column::right("SCHEMA"),
+            Column::right("BYTES"),
+        ]);
+        table.row(vec![
+            self.path.display().to_string().into(),
+            self.schema_version.to_string().into(),
+            self.bytes.to_string().into(),
+        ]);
+        table
+    }
+}
+
+/// Write a snapshot of `db` to `dest`, which must not exist yet
+pub async fn backup(db: &DB, dest: &Path) -> Result<BackupReport> {
+    let failed = |reason: String| {
+        Error::from(DatabaseError::BackupFailed {
+            destination: dest.to_path_buf(),
+            reason,
+            progress: 0.0,
+        })
+    };
+    if dest.exists() {
+        return Err(failed("the file already exists".to_string()));
+    }
+
+    let mut conn = db
+        .pool()
+        .acquire()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    let schema_version = applied_version(&mut conn).await?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(dest.to_string_lossy().into_owned())
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+
+    let bytes = std::fs::metadata(dest)
+        .map_err(|e| failed(e.to_string()))?
+        .len();
+    Ok(BackupReport {
+        path: dest.to_path_buf(),
+        schema_version,
+        bytes,
+    })
+}
+
+/// Every table of the database, rows as objects keyed by column
+#[derive(Debug, Clone, Serialize)]
+pub struct DbExport {
+    pub schema_version: i64,
+    /// Unix seconds
+    pub exported_at: i64,
+    pub tables: BTreeMap<String, Vec<Map<String, Value>>>,
+}
+
+/// A column value as JSON; blobs, such as packed addresses, as hex
+fn json_value(row: &SqliteRow, index: usize) -> Value {
+    let Ok(raw) = row.try_get_raw(index) else {
+        return Value::Null;
+    };
+    if raw.is_null() {
+        return Value::Null;
+    }
+    match raw.type_info().name() {
+        "INTEGER" => row
+            .try_get_unchecked::<i64, _>(index)
+            .map_or(Value::Null, Value::from),
+        "REAL" => row
+            .try_get_unchecked::<f64, _>(index)
+            .map_or(Value::Null, Value::from),
+        "BLOB" => row
+            .try_get_unchecked::<Vec<u8>, _>(index)
+            .map_or(Value::Null, |bytes| {
+                Value::String(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+            }),
+        _ => row
+            .try_get_unchecked::<String, _>(index)
+            .map_or(Value::Null, Value::String),
+    }
+}
+
+/// Dump every table of `db` but the migration bookkeeping
+pub async fn export(db: &DB) -> Result<DbExport> {
+    let query_failed = |e: sqlx::Error| Error::Database(format!("Failed to export: {}", e));
+    let mut conn = db.pool().acquire().await.map_err(query_failed)?;
+    let schema_version = applied_version(&mut conn).await?;
+
+    let names: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' \
+         AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations' ORDER BY name",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(query_failed)?;
+
+    let mut tables = BTreeMap::new();
+    for name in names {
+        // Names come from sqlite_master, quoted in case of odd ones
+        let rows = sqlx::query(&format!("SELECT * FROM \"{}\"", name.replace('"', "\"\"")))
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(query_failed)?;
+        let rows = rows
+            .iter()
+            .map(|row| {
+                row.columns()
+                    .iter()
+                    .map(|column| (column.name().to_string(), json_value(row, column.ordinal())))
+                    .collect()
+            })
+            .collect();
+        tables.insert(name, rows);
+    }
+
+    Ok(DbExport {
+        schema_version,
+        exported_at: chrono::Utc::now().timestamp(),
+        tables,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub source: PathBuf,
+    pub schema_version: i64,
+    /// Migrations the daemon applies when it next opens the database
+    pub pending_migrations: usize,
+    /// The replaced database, when there was one
+    pub previous: Option<PathBuf>,
+}
+
+impl Render for RestoreReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("RESTORED"),
+            Column::right("SCHEMA"),
+            Column::right("PENDING MIGRATIONS"),
+        ]);
+        table.row(vec![
+            self.source.display().to_string().into(),
+            self.schema_version.to_string().into(),
+            self.pending_migrations.to_string().into(),
+        ]);
+        if let Some(previous) = &self.previous {
+            table.footer(format!("previous database kept at {}", previous.display()));
+        }
+        table
+    }
+}
+
+/// Check that `source` is a sound harborshield database this build can
+/// take over; its schema version and pending migration count
+pub async fn check_backup(source: &Path) -> Result<(i64, usize)> {
+    let failed = |reason: String| {
+        Error::from(DatabaseError::RestoreFailed {
+            source_path: source.to_path_buf(),
+            reason,
+            tables_restored: 0,
+            tables_failed: 0,
+        })
+    };
+    if !source.is_file() {
+        return Err(failed("no such file".to_string()));
+    }
+    let mut conn = SqliteConnectOptions::new()
+        .filename(source)
+        .read_only(true)
+        .connect()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+
+    let integrity: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&mut conn)
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    if integrity != "ok" {
+        return Err(failed(format!("integrity check failed: {}", integrity)));
+    }
+
+    let applied: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+        "SELECT version, checksum FROM _sqlx_migrations WHERE success = 1 ORDER BY version",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .map_err(|_| failed("not a harborshield database".to_string()))?;
+    let _ = conn.close().await;
+
+    let ours = schema_version();
+    for (version, checksum) in &applied {
+        match super::MIGRATOR
+            .iter()
+            .find(|migration| migration.version == *version)
+        {
+            Some(migration) if migration.checksum.as_ref() == checksum.as_slice() => {}
+            Some(_) => {
+                return Err(failed(format!(
+                    "migration {} differs from this build's",
+                    version
+                )));
+            }
+            None if *version > ours => {
+                return Err(failed(format!(
+                    "schema version {} is newer than this build's {}; restore with a newer harborshield",
+                    version, ours
+                )));
+            }
+            None => return Err(failed(format!("unknown migration {}", version))),
+        }
+    }
+    let version = applied.last().map_or(0, |(version, _)| *version);
+    let pending = super::MIGRATOR
+        .iter()
+        .filter(|migration| migration.version > version)
+        .count();
+    Ok((version, pending))
+}
+
+/// Replace the database at `db_path` with the backup at `source`, keeping
+/// the current one beside it. Refused while the daemon holds the database
+pub async fn restore(source: &Path, db_path: &Path) -> Result<RestoreReport> {
+    let (schema_version, pending_migrations) = check_backup(source).await?;
+    let _lock = DbLock::acquire(db_path).await?;
+    let failed = |reason: String| {
+        Error::from(DatabaseError::RestoreFailed {
+            source_path: source.to_path_buf(),
+            reason,
+            tables_restored: 0,
+            tables_failed: 0,
+        })
+    };
+
+    // Copied next to the database first, so the swap is a rename
+    let staged = db_path.with_extension("sqlite.restoring");
+    let _ = std::fs::remove_file(&staged);
+    std::fs::copy(source, &staged).map_err(|e| failed(e.to_string()))?;
+
+    let previous = if db_path.exists() {
+        let previous = db_path.with_extension(format!("sqlite.{}", PRE_RESTORE_SUFFIX));
+        std::fs::rename(db_path, &previous).map_err(|e| failed(e.to_string()))?;
+        Some(previous)
+    } else {
+        None
+    };
+    // The old write-ahead log would otherwise be replayed into the backup
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = db_path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        let _ = std::fs::remove_file(PathBuf::from(sidecar));
+    }
+    std::fs::rename(&staged, db_path).map_err(|e| failed(e.to_string()))?;
+
+    Ok(RestoreReport {
+        source: source.to_path_buf(),
+        schema_version,
+        pending_migrations,
+        previous,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{ContainerIdentifiers, DbOp};
+
+    #[tokio::test]
+    async fn test_backup_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("db.sqlite");
+        let db = DB::builder().db_path(&db_path).build().await.unwrap();
+        db.execute(&DbOp::InsertContainer(&ContainerIdentifiers {
+            id: "abc".to_string(),
+            name: "web".to_string(),
+        }))
+        .await
+        .unwrap();
+
+        let dest = dir.path().join("backup.sqlite");
+        let report = backup(&db, &dest).await.unwrap();
+        assert_eq!(report.schema_version, schema_version());
+        assert!(backup(&db, &dest).await.is_err());
+
+        let dump = export(&db).await.unwrap();
+        assert!(!dump.tables.contains_key("_sqlx_migrations"));
+        assert!(
+            dump.tables
+                .values()
+                .flatten()
+                .any(|row| row.values().any(|v| v == "web"))
+        );
+        db.close().await.unwrap();
+
+        // A backup from a newer release is refused
+        let newer = dir.path().join("newer.sqlite");
+        std::fs::copy(&dest, &newer).unwrap();
+        let mut conn = SqliteConnectOptions::new()
+            .filename(&newer)
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+             VALUES (99990101000001, 'future', 1, x'00', 0)",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        conn.close().await.unwrap();
+        let err = restore(&newer, &db_path).await.unwrap_err().to_string();
+        assert!(err.contains("newer than this build"), "{}", err);
+
+        // Refused while a running daemon holds the database
+        let lock = DbLock::try_acquire(&db_path).unwrap().unwrap();
+        let err = restore(&dest, &db_path).await.unwrap_err().to_string();
+        assert!(err.contains("in use"), "{}", err);
+        assert!(DbLock::try_acquire(&db_path).unwrap().is_none());
+        drop(lock);
+
+        let restored = restore(&dest, &db_path).await.unwrap();
+        assert_eq!(restored.pending_migrations, 0);
+        assert!(restored.previous.is_some_and(|previous| previous.exists()));
+        let db = DB::builder().db_path(&db_path).build().await.unwrap();
+        let dump = export(&db).await.unwrap();
+        assert!(
+            dump.tables
+                .values()
+                .flatten()
+                .any(|row| row.values().any(|v| v == "web"))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Address bans, behind `harborshield ban` and `POST /bans`.
+//!
+//! A ban is stored with its expiry and a running daemon adds the address to
+//! the timed set the harborshield chain drops traffic from and to (see
+//! [`crate::nftables::blocked`]) within seconds, again after a restart for
+//! bans still running. Banning an address again replaces its ban. Bans,
+//! lifted bans and expired ones are recorded in the audit log, so other
+//! tools, such as one watching the NFLOG drops, can react to abuse through
+//! it too.
+
+use crate::Result;
+use crate::database::{AuditEntry, Ban, DB, DbOp, DbOpResult, audit};
+use crate::output::{Column, Render, Table};
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+fn time(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0).map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+}
+
+impl Ban {
+    /// The banned address, `None` for a row not written by [`ban`]
+    pub fn addr(&self) -> Option<Ipv4Addr> {
+        self.ip.parse().ok()
+    }
+}
+
+/// Ban `ip` until `duration` from now, replacing a ban it already has, with
+/// its audit entry in the same transaction
+pub async fn ban(
+    db: &mut DB,
+    ip: Ipv4Addr,
+    duration: Duration,
+    reason: Option<&str>,
+) -> Result<Ban> {
+    let now = chrono::Utc::now().timestamp();
+    let ban = Ban {
+        id: 0,
+        ip: ip.to_string(),
+        reason: reason.map(str::to_string),
+        created_at: now,
+        expires_at: now + duration.as_secs() as i64,
+    };
+    let mut detail = format!("banned {} until {}", ip, time(ban.expires_at));
+    if let Some(reason) = reason {
+        detail.push_str(&format!(" ({})", reason));
+    }
+    let entry = AuditEntry::builder()
+        .ts(now)
+        .kind(audit::KIND_ADDRESS_BANNED)
+        .detail(detail)
+        .build();
+
+    let replaced: Vec<i64> = list(db)
+        .await?
+        .into_iter()
+        .filter(|b| b.ip == ban.ip)
+        .map(|b| b.id)
+        .collect();
+    let mut ops: Vec<DbOp> = replaced.into_iter().map(DbOp::DeleteBan).collect();
+    ops.push(DbOp::InsertBan(&ban));
+    ops.push(DbOp::InsertAuditEntry(&entry));
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(ban)
+}
+
+/// Lift the ban of `ip`, recording it; false when it had none
+pub async fn unban(db: &mut DB, ip: Ipv4Addr, reason: Option<&str>) -> Result<bool> {
+    let ip = ip.to_string();
+    let ids: Vec<i64> = list(db)
+        .await?
+        .into_iter()
+        .filter(|ban| ban.ip == ip)
+        .map(|ban| ban.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(false);
+    }
+
+    let mut detail = format!("lifted the ban of {}", ip);
+    if let Some(reason) = reason {
+        detail.push_str(&format!(" ({})", reason));
+    }
+    let entry = AuditEntry::builder()
+        .ts(chrono::Utc::now().timestamp())
+        .kind(audit::KIND_ADDRESS_UNBANNED)
+        .detail(detail)
+        .build();
+    let mut ops: Vec<DbOp> = ids.into_iter().map(DbOp::DeleteBan).collect();
+    ops.push(DbOp::InsertAuditEntry(&entry));
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(true)
+}
+
+/// Every stored ban, expired ones included until [`expire`] removes them
+pub async fn list(db: &DB) -> Result<Vec<Ban>> {
+    match db.execute(&DbOp::ListBans).await? {
+        DbOpResult::Bans(bans) => Ok(bans),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Delete the bans expired at `now`, recording each, and return those
+/// still running
+pub async fn expire(db: &mut DB, now: i64) -> Result<Vec<Ban>> {
+    let (active, expired): (Vec<Ban>, Vec<Ban>) = list(db)
+        .await?
+        .into_iter()
+        .partition(|ban| ban.expires_at > now);
+    if expired.is_empty() {
+        return Ok(active);
+    }
+
+    let entries: Vec<AuditEntry> = expired
+        .iter()
+        .map(|ban| {
+            AuditEntry::builder()
+                .ts(now)
+                .kind(audit::KIND_BAN_EXPIRED)
+                .detail(format!(
+                    "ban of {} from {} expired",
+                    ban.ip,
+                    time(ban.created_at)
+                ))
+                .build()
+        })
+        .collect();
+    let mut ops: Vec<DbOp> = expired.iter().map(|ban| DbOp::DeleteBan(ban.id)).collect();
+    ops.extend(entries.iter().map(DbOp::InsertAuditEntry));
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(&entries);
+    Ok(active)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BanReport {
+    pub bans: Vec<Ban>,
+}
+
+impl Render for BanReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("ADDRESS"),
+            Column::left("BANNED").wide(),
+            Column::left("EXPIRES"),
+            Column::left("REASON"),
+        ]);
+
+        for ban in &self.bans {
+            table.row(vec![
+                ban.ip.clone().into(),
+                time(ban.created_at).into(),
+                time(ban.expires_at).into(),
+                ban.reason.clone().unwrap_or_else(|| "-".to_string()).into(),
+            ]);
+        }
+
+        if self.bans.is_empty() {
+            table.footer("no addresses are banned");
+        }
+        table
+    }
+}
@@ -0,0 +1,226 @@
+//! Encryption at rest for database columns that carry rule and label
+//! content: waiting rules and audit log details.
+//!
+//! Values are sealed with AES-256-GCM under a key read from a file or the
+//! `HARBORSHIELD_DB_KEY` environment variable. The nonce is derived from the
+//! plaintext, so equal values seal to equal ciphertexts and the primary key
+//! of `waiting_container_rules` keeps deduplicating. Rows written without a
+//! key stay readable after one is configured.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hmac;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+use thiserror::Error;
+
+/// Environment variable holding the base64 key when no key file is given
+pub const KEY_ENV: &str = "HARBORSHIELD_DB_KEY";
+
+const KEY_LEN: usize = 32;
+
+/// Marks sealed BLOB values
+const BLOB_PREFIX: &[u8] = b"hs-enc1:";
+
+/// Marks sealed TEXT values, followed by the base64 of the sealed bytes
+const TEXT_PREFIX: &str = "enc1:";
+
+static COLUMN_KEY: LazyLock<RwLock<Option<Arc<ColumnKey>>>> = LazyLock::new(|| RwLock::new(None));
+
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("Invalid database key: {0}")]
+    InvalidKey(String),
+
+    #[error("Value is encrypted but no database key is configured")]
+    NoKey,
+
+    #[error("Value could not be decrypted; the database key does not match")]
+    Undecryptable,
+}
+
+pub struct ColumnKey {
+    aead: LessSafeKey,
+    nonce: hmac::Key,
+}
+
+impl ColumnKey {
+    pub fn from_bytes(key: &[u8]) -> Result<Self, CryptoError> {
+        if key.len() != KEY_LEN {
+            return Err(CryptoError::InvalidKey(format!(
+                "expected {} bytes, got {}",
+                KEY_LEN,
+                key.len()
+            )));
+        }
+
+        // Separate subkeys for sealing and nonce derivation
+        let master = hmac::Key::new(hmac::HMAC_SHA256, key);
+        let aead_key = hmac::sign(&master, b"harborshield column aead");
+        let nonce_key = hmac::sign(&master, b"harborshield column nonce");
+
+        let unbound = UnboundKey::new(&AES_256_GCM, aead_key.as_ref())
+            .map_err(|_| CryptoError::InvalidKey("rejected by AES-256-GCM".to_string()))?;
+        Ok(Self {
+            aead: LessSafeKey::new(unbound),
+            nonce: hmac::Key::new(hmac::HMAC_SHA256, nonce_key.as_ref()),
+        })
+    }
+
+    /// Key given as base64 text
+    pub fn parse(text: &str) -> Result<Self, CryptoError> {
+        let bytes = STANDARD
+            .decode(text.trim())
+            .map_err(|e| CryptoError::InvalidKey(format!("not base64: {}", e)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Key from `path`, holding either 32 raw bytes or their base64, or
+    /// from `HARBORSHIELD_DB_KEY` when no path is given
+    pub fn load(path: Option<&Path>) -> io::Result<Option<Self>> {
+        let invalid = |e: CryptoError| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        match path {
+            Some(path) => {
+                let bytes = std::fs::read(path)?;
+                let key = if bytes.len() == KEY_LEN {
+                    Self::from_bytes(&bytes)
+                } else {
+                    Self::parse(&String::from_utf8_lossy(&bytes))
+                };
+                key.map(Some).map_err(invalid)
+            }
+            None => match std::env::var(KEY_ENV) {
+                Ok(text) => Self::parse(&text).map(Some).map_err(invalid),
+                Err(_) => Ok(None),
+            },
+        }
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let tag = hmac::sign(&self.nonce, plaintext);
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&tag.as_ref()[..NONCE_LEN]);
+
+        let mut sealed = plaintext.to_vec();
+        self.aead
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .expect("plaintext within AES-GCM limits");
+
+        let mut out = Vec::with_capacity(BLOB_PREFIX.len() + NONCE_LEN + sealed.len());
+        out.extend_from_slice(BLOB_PREFIX);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        out
+    }
+
+    /// Open a value produced by [`ColumnKey::seal`], prefix included
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let body = sealed
+            .strip_prefix(BLOB_PREFIX)
+            .filter(|body| body.len() >= NONCE_LEN)
+            .ok_or(CryptoError::Undecryptable)?;
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let nonce =
+            Nonce::try_assume_unique_for_key(nonce).map_err(|_| CryptoError::Undecryptable)?;
+
+        let mut buf = ciphertext.to_vec();
+        let plaintext = self
+            .aead
+            .open_in_place(nonce, Aad::empty(), &mut buf)
+            .map_err(|_| CryptoError::Undecryptable)?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// Set the key used for sealed columns; `None` writes plaintext
+pub fn set_column_key(key: Option<ColumnKey>) {
+    *COLUMN_KEY.write().unwrap() = key.map(Arc::new);
+}
+
+fn column_key() -> Option<Arc<ColumnKey>> {
+    COLUMN_KEY.read().unwrap().clone()
+}
+
+pub fn seal_blob(value: &[u8]) -> Vec<u8> {
+    match column_key() {
+        Some(key) => key.seal(value),
+        None => value.to_vec(),
+    }
+}
+
+pub fn open_blob(value: Vec<u8>) -> Result<Vec<u8>, CryptoError> {
+    if !value.starts_with(BLOB_PREFIX) {
+        return Ok(value);
+    }
+    column_key().ok_or(CryptoError::NoKey)?.open(&value)
+}
+
+pub fn seal_text(value: &str) -> String {
+    match column_key() {
+        Some(key) => format!(
+            "{}{}",
+            TEXT_PREFIX,
+            STANDARD.encode(key.seal(value.as_bytes()))
+        ),
+        None => value.to_string(),
+    }
+}
+
+pub fn open_text(value: String) -> Result<String, CryptoError> {
+    let Some(encoded) = value.strip_prefix(TEXT_PREFIX) else {
+        return Ok(value);
+    };
+    let key = column_key().ok_or(CryptoError::NoKey)?;
+    let sealed = STANDARD
+        .decode(encoded)
+        .map_err(|_| CryptoError::Undecryptable)?;
+    String::from_utf8(key.open(&sealed)?).map_err(|_| CryptoError::Undecryptable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = ColumnKey::from_bytes(&[7u8; KEY_LEN]).unwrap();
+        let sealed = key.seal(b"allow tcp 443");
+
+        assert!(sealed.starts_with(BLOB_PREFIX));
+        assert!(!sealed.windows(3).any(|w| w == b"443"));
+        // Deterministic, so unique constraints still hold
+        assert_eq!(sealed, key.seal(b"allow tcp 443"));
+        assert_ne!(sealed, key.seal(b"allow tcp 80"));
+        assert_eq!(key.open(&sealed).unwrap(), b"allow tcp 443");
+
+        let other = ColumnKey::from_bytes(&[8u8; KEY_LEN]).unwrap();
+        assert!(matches!(
+            other.open(&sealed),
+            Err(CryptoError::Undecryptable)
+        ));
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert!(ColumnKey::parse(&STANDARD.encode([1u8; KEY_LEN])).is_ok());
+        assert!(ColumnKey::parse(&STANDARD.encode([1u8; 16])).is_err());
+        assert!(ColumnKey::parse("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_plaintext_passes_through() {
+        // No key is set in tests; unsealed values are returned unchanged
+        assert_eq!(open_blob(b"rule".to_vec()).unwrap(), b"rule");
+        assert_eq!(open_text("detail".to_string()).unwrap(), "detail");
+    }
+}
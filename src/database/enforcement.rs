@@ -0,0 +1,95 @@
+//! Per-container enforcement overrides, shared by the CLI and the admin API.
+
+use crate::Result;
+use crate::database::{
+    AuditEntry, ContainerEnforcement, DB, DbOp, DbOpResult, EnforcementMode, audit,
+};
+use crate::output::{Cell, Color, Column, Render, Table};
+use serde::Serialize;
+
+/// All containers with a non-default enforcement mode
+pub async fn list_overrides(db: &DB) -> Result<Vec<ContainerEnforcement>> {
+    match db.execute(&DbOp::ListEnforcementModes).await? {
+        DbOpResult::EnforcementModes(modes) => Ok(modes),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Persist `mode` for `container_name`; the daemon picks the change up and
+/// rebuilds the container's chain
+pub async fn set_mode(db: &DB, container_name: &str, mode: EnforcementMode) -> Result<()> {
+    db.execute(&DbOp::SetEnforcementMode {
+        container_name,
+        mode,
+        updated_at: chrono::Utc::now().timestamp(),
+    })
+    .await?;
+    Ok(())
+}
+
+/// Persist `mode` as [`set_mode`] does, with an audit entry naming who set
+/// it, such as "the command line", in the same transaction
+pub async fn change_mode(
+    db: &mut DB,
+    container_name: &str,
+    mode: EnforcementMode,
+    by: &str,
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let entry = AuditEntry::builder()
+        .ts(now)
+        .kind(audit::KIND_ENFORCEMENT_CHANGED)
+        .container_name(container_name.to_string())
+        .detail(format!("enforcement mode set to {} by {}", mode, by))
+        .build();
+    let ops = [
+        DbOp::SetEnforcementMode {
+            container_name,
+            mode,
+            updated_at: now,
+        },
+        DbOp::InsertAuditEntry(&entry),
+    ];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnforcementReport {
+    pub containers: Vec<ContainerEnforcement>,
+}
+
+impl Render for EnforcementReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::left("MODE"),
+            Column::left("SINCE").wide(),
+        ]);
+
+        for entry in &self.containers {
+            let mode = match entry.mode {
+                EnforcementMode::Enforce => Cell::from(entry.mode.as_str()),
+                EnforcementMode::Permissive => Cell::colored(entry.mode.as_str(), Color::Yellow),
+                EnforcementMode::Disabled | EnforcementMode::Quarantined => {
+                    Cell::colored(entry.mode.as_str(), Color::Red)
+                }
+            };
+            let since = (entry.updated_at > 0)
+                .then(|| chrono::DateTime::from_timestamp(entry.updated_at, 0))
+                .flatten()
+                .map_or_else(|| "-".to_string(), |t| t.to_rfc3339());
+            table.row(vec![
+                entry.container_name.clone().into(),
+                mode,
+                since.into(),
+            ]);
+        }
+
+        if self.containers.is_empty() {
+            table.footer("all containers are enforced");
+        }
+        table
+    }
+}
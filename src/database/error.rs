@@ -137,6 +137,9 @@ pub enum DatabaseError {
         tables_restored: usize,
         tables_failed: usize,
     },
+
+    #[error("Database at {path} is in use by a running harborshield")]
+    Locked { path: PathBuf },
 }
 
 impl DatabaseError {
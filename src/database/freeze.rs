@@ -0,0 +1,229 @@
+//! Change freezes, behind `harborshield freeze` and `unfreeze`.
+//!
+//! While a freeze lasts, a running daemon changes no container rules: Docker
+//! events are held back, one row per container, and enforcement switches,
+//! releases, learning and ad-hoc rules are refused. Emergency blocks still
+//! go through: quarantines by the daemon, and switching a container to
+//! `quarantined` by hand.
+//!
+//! `unfreeze` lists the held containers and the ruleset diff applying them
+//! would make; with `--confirm` it ends the freeze, and the daemon resyncs
+//! every container within seconds. A freeze given `--until` ends by itself
+//! then. Both ends are recorded in the audit log.
+
+use crate::Result;
+use crate::database::{AuditEntry, DB, DbOp, DbOpResult, Freeze, HeldEvent, audit};
+use crate::output::{Column, Render, Table};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the daemon is holding changes back
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_frozen(frozen: bool) {
+    FROZEN.store(frozen, Ordering::Relaxed);
+}
+
+pub fn is_frozen() -> bool {
+    FROZEN.load(Ordering::Relaxed)
+}
+
+fn time(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0).map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+}
+
+/// Unix seconds for an `--until` value given at `now`: a duration from now,
+/// unix seconds or an RFC 3339 time, which must be in the future
+pub fn parse_until(value: &str, now: i64) -> std::result::Result<i64, String> {
+    let value = value.trim();
+    let until = if let Ok(secs) = value.parse::<i64>() {
+        secs
+    } else if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        time.timestamp()
+    } else {
+        crate::parse_duration(value)
+            .map(|window| now + window.as_secs() as i64)
+            .map_err(|_| {
+                format!(
+                    "invalid until '{}': expected a duration, unix seconds or RFC 3339",
+                    value
+                )
+            })?
+    };
+    if until <= now {
+        return Err(format!("until '{}' has already passed", value));
+    }
+    Ok(until)
+}
+
+/// `parse_until` against the current time, for command line arguments
+pub fn parse_until_arg(value: &str) -> std::result::Result<i64, String> {
+    parse_until(value, chrono::Utc::now().timestamp())
+}
+
+impl Freeze {
+    /// Whether the freeze still holds at `now`
+    pub fn active(&self, now: i64) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+/// Start a freeze until `until`, or replace the current one, with its audit
+/// entry in the same transaction
+pub async fn freeze(db: &mut DB, until: Option<i64>, reason: Option<&str>) -> Result<Freeze> {
+    let freeze = Freeze {
+        reason: reason.map(str::to_string),
+        started_at: chrono::Utc::now().timestamp(),
+        until,
+    };
+    let mut detail = match until {
+        Some(until) => format!("rule changes frozen until {}", time(until)),
+        None => "rule changes frozen until unfrozen".to_string(),
+    };
+    if let Some(reason) = reason {
+        detail.push_str(&format!(" ({})", reason));
+    }
+    let entry = AuditEntry::builder()
+        .ts(freeze.started_at)
+        .kind(audit::KIND_FROZEN)
+        .detail(detail)
+        .build();
+
+    let ops = [DbOp::SetFreeze(&freeze), DbOp::InsertAuditEntry(&entry)];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(freeze)
+}
+
+/// The stored freeze, even one past its `until` that the daemon hasn't
+/// ended yet
+pub async fn stored(db: &DB) -> Result<Option<Freeze>> {
+    match db.execute(&DbOp::GetFreeze).await? {
+        DbOpResult::Freeze(freeze) => Ok(freeze),
+        _ => Ok(None),
+    }
+}
+
+/// The freeze holding at `now`, if any
+pub async fn current(db: &DB, now: i64) -> Result<Option<Freeze>> {
+    Ok(stored(db).await?.filter(|freeze| freeze.active(now)))
+}
+
+/// End the freeze, recording `detail`
+pub async fn lift(db: &mut DB, now: i64, detail: &str) -> Result<()> {
+    let entry = AuditEntry::builder()
+        .ts(now)
+        .kind(audit::KIND_UNFROZEN)
+        .detail(detail)
+        .build();
+    let ops = [DbOp::ClearFreeze, DbOp::InsertAuditEntry(&entry)];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(())
+}
+
+/// Note an event held back for a container
+pub async fn hold(db: &DB, held: &HeldEvent) -> Result<()> {
+    db.execute(&DbOp::HoldEvent(held)).await?;
+    Ok(())
+}
+
+/// The containers with held events, longest held first
+pub async fn held(db: &DB) -> Result<Vec<HeldEvent>> {
+    match db.execute(&DbOp::ListHeldEvents).await? {
+        DbOpResult::HeldEvents(held) => Ok(held),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Forget the held events once they are applied
+pub async fn clear_held(db: &DB) -> Result<()> {
+    db.execute(&DbOp::ClearHeldEvents).await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FreezeReport {
+    pub freeze: Option<Freeze>,
+    pub held: Vec<HeldEvent>,
+}
+
+impl Render for FreezeReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::left("ID").wide(),
+            Column::left("LAST EVENT"),
+            Column::right("EVENTS"),
+            Column::left("HELD SINCE"),
+        ]);
+
+        for held in &self.held {
+            table.row(vec![
+                held.container_name.clone().into(),
+                held.container_id
+                    .chars()
+                    .take(12)
+                    .collect::<String>()
+                    .into(),
+                held.action.clone().into(),
+                held.events.to_string().into(),
+                time(held.first_held).into(),
+            ]);
+        }
+
+        match &self.freeze {
+            Some(freeze) => {
+                let until = freeze.until.map_or_else(
+                    || "until unfrozen".to_string(),
+                    |until| format!("until {}", time(until)),
+                );
+                let reason = freeze
+                    .reason
+                    .as_ref()
+                    .map(|reason| format!(": {}", reason))
+                    .unwrap_or_default();
+                table.footer(format!(
+                    "frozen since {} {}{}",
+                    time(freeze.started_at),
+                    until,
+                    reason
+                ));
+            }
+            None => {
+                table.footer("not frozen");
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_until() {
+        let now = 1_700_000_000;
+        assert_eq!(parse_until("2h", now), Ok(now + 7200));
+        assert_eq!(parse_until("1700003600", now), Ok(1_700_003_600));
+        assert_eq!(parse_until("2023-11-14T23:13:20Z", now), Ok(1_700_003_600));
+        assert!(parse_until("1699999999", now).is_err());
+        assert!(parse_until("soon", now).is_err());
+
+        let freeze = Freeze {
+            reason: None,
+            started_at: now,
+            until: Some(now + 60),
+        };
+        assert!(freeze.active(now + 59));
+        assert!(!freeze.active(now + 60));
+        assert!(
+            Freeze {
+                until: None,
+                ..freeze
+            }
+            .active(i64::MAX)
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Learning mode, behind `harborshield learn` and `harborshield suggest`.
+//!
+//! A learning container runs in permissive mode while the daemon records the
+//! flows it sees in conntrack. Once the session ends the container is back to
+//! being enforced, and the recorded flows can be turned into a proposed rule
+//! set for review.
+
+use crate::Result;
+use crate::database::{DB, DbOp, DbOpResult, EnforcementMode, LearningSession, ObservedFlow};
+use crate::host::conntrack::Direction;
+use crate::output::{Column, Render, Table};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Sources listed in a suggested external rule before it is left open to all
+const MAX_LISTED_SOURCES: usize = 16;
+
+/// Switch `container_name` to permissive mode and record its flows for
+/// `duration`
+pub async fn start(db: &mut DB, container_name: &str, duration: Duration) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let session = LearningSession {
+        container_name: container_name.to_string(),
+        started_at: now,
+        ends_at: now + duration.as_secs() as i64,
+    };
+    let ops = [
+        DbOp::StartLearningSession(&session),
+        DbOp::SetEnforcementMode {
+            container_name,
+            mode: EnforcementMode::Permissive,
+            updated_at: now,
+        },
+    ];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    Ok(())
+}
+
+/// End the session of `container_name` and enforce its rules again; the
+/// recorded flows are kept for `suggest`
+pub async fn finish(db: &mut DB, container_name: &str) -> Result<()> {
+    let ops = [
+        DbOp::EndLearningSession(container_name),
+        DbOp::SetEnforcementMode {
+            container_name,
+            mode: EnforcementMode::Enforce,
+            updated_at: chrono::Utc::now().timestamp(),
+        },
+    ];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    Ok(())
+}
+
+pub async fn sessions(db: &DB) -> Result<Vec<LearningSession>> {
+    match db.execute(&DbOp::ListLearningSessions).await? {
+        DbOpResult::LearningSessions(sessions) => Ok(sessions),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Add one sample's flows in a single transaction
+pub async fn record(db: &mut DB, flows: &[ObservedFlow]) -> Result<()> {
+    let ops: Vec<DbOp> = flows.iter().map(DbOp::RecordObservedFlow).collect();
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    Ok(())
+}
+
+pub async fn flows(db: &DB, container_name: &str) -> Result<Vec<ObservedFlow>> {
+    match db.execute(&DbOp::ListObservedFlows(container_name)).await? {
+        DbOpResult::ObservedFlows(flows) => Ok(flows),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LearningReport {
+    pub sessions: Vec<LearningSession>,
+}
+
+impl Render for LearningReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::left("STARTED").wide(),
+            Column::left("UNTIL"),
+        ]);
+
+        let time = |ts: i64| {
+            chrono::DateTime::from_timestamp(ts, 0)
+                .map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+        };
+        for session in &self.sessions {
+            table.row(vec![
+                session.container_name.clone().into(),
+                time(session.started_at).into(),
+                time(session.ends_at).into(),
+            ]);
+        }
+
+        if self.sessions.is_empty() {
+            table.footer("no containers are learning");
+        }
+        table
+    }
+}
+
+/// Proposed rules for a container, built from its recorded flows
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    pub container_name: String,
+    /// Value for the rules label
+    pub rules: String,
+    pub flows: Vec<ObservedFlow>,
+}
+
+impl Suggestion {
+    pub fn new(container_name: &str, flows: Vec<ObservedFlow>) -> Self {
+        Self {
+            container_name: container_name.to_string(),
+            rules: suggest_rules(&flows),
+            flows,
+        }
+    }
+}
+
+fn yaml_addr(addr: &str) -> String {
+    // Keep IPv6 addresses from being read as mappings
+    if addr.contains(':') {
+        format!("\"{}\"", addr)
+    } else {
+        addr.to_string()
+    }
+}
+
+fn is_loopback(addr: &str) -> bool {
+    addr.parse::<std::net::IpAddr>()
+        .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The smallest rule set allowing every recorded flow, as rules label YAML
+pub fn suggest_rules(flows: &[ObservedFlow]) -> String {
+    let inbound = Direction::Inbound.as_str();
+    let outbound = Direction::Outbound.as_str();
+
+    // Ports to other containers, by network, container and protocol
+    let mut to_containers: BTreeMap<(&str, &str, &str), BTreeSet<i64>> = BTreeMap::new();
+    // Peers by protocol and port, regrouped below into one rule per peer set
+    let mut to_addrs: BTreeMap<(&str, i64), BTreeSet<&str>> = BTreeMap::new();
+    let mut sources: BTreeSet<&str> = BTreeSet::new();
+    let mut from_localhost = false;
+    let mut from_containers: BTreeSet<(&str, &str, i64, &str)> = BTreeSet::new();
+
+    for flow in flows {
+        let peer_container = flow
+            .peer_container
+            .as_deref()
+            .zip(flow.peer_network.as_deref());
+        if flow.direction == outbound {
+            match peer_container {
+                Some((container, network)) => {
+                    to_containers
+                        .entry((network, container, flow.proto.as_str()))
+                        .or_default()
+                        .insert(flow.port);
+                }
+                None => {
+                    to_addrs
+                        .entry((flow.proto.as_str(), flow.port))
+                        .or_default()
+                        .insert(flow.peer.as_str());
+                }
+            }
+        } else if flow.direction == inbound {
+            match peer_container {
+                Some((container, network)) => {
+                    from_containers.insert((container, network, flow.port, flow.proto.as_str()));
+                }
+                None if is_loopback(&flow.peer) => from_localhost = true,
+                None => {
+                    sources.insert(flow.peer.as_str());
+                }
+            }
+        }
+    }
+
+    let mut by_peers: BTreeMap<(&str, BTreeSet<&str>), BTreeSet<i64>> = BTreeMap::new();
+    for ((proto, port), peers) in to_addrs {
+        by_peers.entry((proto, peers)).or_default().insert(port);
+    }
+
+    let mut out = String::new();
+    if flows.is_empty() {
+        out.push_str("# No traffic was observed; nothing is allowed\n");
+        return out;
+    }
+
+    if from_localhost || !sources.is_empty() {
+        out.push_str("mapped_ports:\n");
+        if from_localhost {
+            out.push_str("  localhost:\n    allow: true\n");
+        }
+        if !sources.is_empty() {
+            out.push_str("  external:\n    allow: true\n");
+            if sources.len() > MAX_LISTED_SOURCES {
+                let _ = writeln!(
+                    out,
+                    "    # {} different sources were seen, so none are listed",
+                    sources.len()
+                );
+            } else {
+                out.push_str("    ips:\n");
+                for source in &sources {
+                    let _ = writeln!(out, "      - {}", yaml_addr(source));
+                }
+            }
+        }
+    }
+
+    if !to_containers.is_empty() || !by_peers.is_empty() {
+        out.push_str("output:\n");
+    }
+    for ((network, container, proto), ports) in &to_containers {
+        let _ = writeln!(
+            out,
+            "  - network: {}\n    container: {}\n    proto: {}\n    dst_ports:",
+            network, container, proto
+        );
+        for port in ports {
+            let _ = writeln!(out, "      - {}", port);
+        }
+    }
+    for ((proto, peers), ports) in &by_peers {
+        let _ = writeln!(out, "  - proto: {}\n    ips:", proto);
+        for peer in peers {
+            let _ = writeln!(out, "      - {}", yaml_addr(peer));
+        }
+        out.push_str("    dst_ports:\n");
+        for port in ports {
+            let _ = writeln!(out, "      - {}", port);
+        }
+    }
+
+    for (container, network, port, proto) in &from_containers {
+        let _ = writeln!(
+            out,
+            "# {} on {} connects to port {}/{}; allow it with an output rule on {}",
+            container, network, port, proto, container
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::config::Config;
+
+    fn flow(direction: Direction, proto: &str, peer: &str, port: i64) -> ObservedFlow {
+        ObservedFlow::builder()
+            .container_name("web")
+            .direction(direction.as_str())
+            .proto(proto)
+            .peer(peer)
+            .port(port)
+            .first_seen(0)
+            .last_seen(0)
+            .build()
+    }
+
+    #[test]
+    fn test_suggest_rules() {
+        let mut db_flow = flow(Direction::Outbound, "tcp", "172.18.0.3", 5432);
+        db_flow.peer_container = Some("db".to_string());
+        db_flow.peer_network = Some("backend".to_string());
+        let flows = vec![
+            flow(Direction::Outbound, "tcp", "93.184.216.34", 443),
+            flow(Direction::Outbound, "tcp", "93.184.216.34", 80),
+            flow(Direction::Outbound, "udp", "2001:db8::53", 53),
+            db_flow,
+            flow(Direction::Inbound, "tcp", "203.0.113.9", 8080),
+            flow(Direction::Inbound, "tcp", "127.0.0.1", 8080),
+        ];
+
+        let rules = suggest_rules(&flows);
+        let config: Config = serde_yaml::from_str(&rules).unwrap();
+        assert!(config.mapped_ports.localhost.allow);
+        assert!(config.mapped_ports.external.allow);
+        assert_eq!(config.mapped_ports.external.ips.len(), 1);
+
+        // One rule per container, then one per protocol and peer set
+        assert_eq!(config.output.len(), 3);
+        assert_eq!(config.output[0].container, "db");
+        assert_eq!(config.output[0].network, "backend");
+        assert_eq!(config.output[1].dst_ports.len(), 2);
+        assert_eq!(config.output[2].ips.len(), 1);
+
+        assert!(suggest_rules(&[]).starts_with('#'));
+    }
+}
@@ -1,11 +1,23 @@
+pub mod activity;
+pub mod adhoc;
+pub mod audit;
+pub mod backup;
+pub mod bans;
+pub mod crypto;
+pub mod enforcement;
 pub mod error;
+pub mod freeze;
+pub mod learning;
 pub mod models;
 pub mod operations;
+pub mod panic;
+pub mod schema;
+pub mod stats;
 
 #[cfg(test)]
 mod tests;
 
-use crate::{Error, Result};
+use crate::{Error, Result, low_memory};
 use bon::{Builder, bon};
 use sqlx::{Sqlite, SqlitePool, Transaction};
 use std::path::Path;
@@ -13,6 +25,9 @@ use std::path::Path;
 pub use models::*;
 pub use operations::{DbOp, DbOpResult, execute_op};
 
+/// The schema migrations, applied whenever the database is opened
+pub(crate) static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
 /// Database connection pool
 pub struct DB {
     pool: SqlitePool,
@@ -25,21 +40,33 @@ impl DB {
         // Build database URL
         let db_url = format!("sqlite:{}?mode=rwc", db_path.display());
 
+        let mut options = db_url
+            .parse::<sqlx::sqlite::SqliteConnectOptions>()
+            .map_err(|e| Error::Database(format!("Failed to parse database URL: {}", e)))?
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .busy_timeout(std::time::Duration::from_secs(1))
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+        let mut pool_options = sqlx::sqlite::SqlitePoolOptions::new();
+        if low_memory::enabled() {
+            // A negative cache_size is in KiB rather than pages
+            options = options
+                .statement_cache_capacity(0)
+                .pragma("cache_size", format!("-{}", low_memory::SQLITE_CACHE_KIB));
+            pool_options = pool_options
+                .max_connections(low_memory::DB_MAX_CONNECTIONS)
+                .min_connections(0)
+                .idle_timeout(low_memory::DB_IDLE_TIMEOUT);
+        }
+
         // Create pool with configuration
-        let pool = SqlitePool::connect_with(
-            db_url
-                .parse::<sqlx::sqlite::SqliteConnectOptions>()
-                .map_err(|e| Error::Database(format!("Failed to parse database URL: {}", e)))?
-                .create_if_missing(true)
-                .foreign_keys(true)
-                .busy_timeout(std::time::Duration::from_secs(1))
-                .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal),
-        )
-        .await
-        .map_err(|e| Error::Database(format!("Failed to create database pool: {}", e)))?;
+        let pool = pool_options
+            .connect_with(options)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to create database pool: {}", e)))?;
 
         // Run migrations
-        sqlx::migrate!("./migrations")
+        MIGRATOR
             .run(&pool)
             .await
             .map_err(|e| Error::Database(format!("Failed to run migrations: {}", e)))?;
@@ -2,6 +2,8 @@ use bon::Builder;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 
+pub use harborshield_core::EnforcementMode;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Builder)]
 pub struct ContainerIdentifiers {
     pub id: String,
@@ -58,3 +60,193 @@ pub struct WaitingContainerRule {
     pub dst_container_name: String,
     pub rule: Vec<u8>,
 }
+
+/// Kind of a raw stats event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatKind {
+    Drop,
+    Accept,
+    RuleChange,
+}
+
+impl StatKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatKind::Drop => "drop",
+            StatKind::Accept => "accept",
+            StatKind::RuleChange => "rule_change",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Builder)]
+pub struct StatEvent {
+    /// Unix seconds
+    pub ts: i64,
+    pub container_name: String,
+    pub kind: StatKind,
+    #[builder(default)]
+    pub packets: i64,
+    #[builder(default)]
+    pub bytes: i64,
+}
+
+/// One row of `stats_hourly` or `stats_daily`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsBucket {
+    pub bucket: i64,
+    pub container_name: String,
+    pub dropped_packets: i64,
+    pub dropped_bytes: i64,
+    pub accepted_bytes: i64,
+    pub rule_changes: i64,
+}
+
+/// Ruleset size at the start of an hour
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapacitySample {
+    pub bucket: i64,
+    pub chains: i64,
+    pub rules: i64,
+    pub set_elements: i64,
+    pub memory_bytes: i64,
+}
+
+/// Enforcement override stored for a container name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerEnforcement {
+    pub container_name: String,
+    pub mode: EnforcementMode,
+    /// Unix seconds
+    pub updated_at: i64,
+}
+
+/// One row of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Builder)]
+pub struct AuditEntry {
+    /// Assigned by the database
+    #[builder(default)]
+    pub id: i64,
+    /// Unix seconds
+    pub ts: i64,
+    #[builder(into)]
+    pub kind: String,
+    pub container_name: Option<String>,
+    #[builder(into)]
+    pub detail: String,
+    #[builder(default)]
+    pub repaired: bool,
+}
+
+/// A container recording its traffic in permissive mode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LearningSession {
+    pub container_name: String,
+    /// Unix seconds
+    pub started_at: i64,
+    /// Unix seconds; the container returns to enforce mode afterwards
+    pub ends_at: i64,
+}
+
+/// Rules layered over a container's rules label until they expire
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdhocRule {
+    /// Assigned by the database
+    pub id: i64,
+    pub container_name: String,
+    /// Rules label YAML
+    pub rules: String,
+    pub reason: Option<String>,
+    /// Unix seconds
+    pub created_at: i64,
+    /// Unix seconds
+    pub expires_at: i64,
+}
+
+/// An address dropped by the harborshield chain until it expires
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    /// Assigned by the database
+    pub id: i64,
+    /// IPv4 address
+    pub ip: String,
+    pub reason: Option<String>,
+    /// Unix seconds
+    pub created_at: i64,
+    /// Unix seconds
+    pub expires_at: i64,
+}
+
+/// Traffic seen for a container during learning, one row per peer and port
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Builder)]
+pub struct ObservedFlow {
+    #[builder(into)]
+    pub container_name: String,
+    #[builder(into)]
+    pub direction: String,
+    #[builder(into)]
+    pub proto: String,
+    #[builder(into)]
+    pub peer: String,
+    /// Container port for inbound flows, remote port for outbound ones
+    pub port: i64,
+    pub peer_container: Option<String>,
+    pub peer_network: Option<String>,
+    /// Unix seconds
+    pub first_seen: i64,
+    /// Unix seconds
+    pub last_seen: i64,
+    /// Samples the flow showed up in
+    #[builder(default = 1)]
+    pub sightings: i64,
+}
+
+/// When an allow rule of a container was installed and last matched traffic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleActivity {
+    pub container_name: String,
+    /// What the rule was built from, such as `Output rule 2`
+    pub rule: String,
+    /// Unix seconds
+    pub first_seen: i64,
+    /// The last sample the rule was installed in, unix seconds
+    pub last_seen: i64,
+    /// Unix seconds; `None` while it has matched nothing
+    pub last_hit: Option<i64>,
+}
+
+/// A change freeze; rule changes wait until it ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Freeze {
+    pub reason: Option<String>,
+    /// Unix seconds
+    pub started_at: i64,
+    /// Unix seconds; lasts until unfrozen when unset
+    pub until: Option<i64>,
+}
+
+/// The engaged kill switch; tracked containers are cut off until released
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Panic {
+    pub reason: Option<String>,
+    /// Unix seconds
+    pub started_at: i64,
+    /// Comma-separated IPv4 networks still let through
+    pub allow: String,
+}
+
+/// A container whose Docker events were held back by a freeze
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeldEvent {
+    pub container_id: String,
+    pub container_name: String,
+    /// The last event held, such as `start`
+    pub action: String,
+    /// Events held for the container
+    pub events: i64,
+    /// Unix seconds
+    pub first_held: i64,
+    /// Unix seconds
+    pub last_held: i64,
+}
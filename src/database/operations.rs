@@ -2,7 +2,12 @@ use sqlx::{Sqlite, Transaction, query, query_as};
 
 use crate::{
     Error, Result,
-    database::{Addr, ContainerAlias, ContainerIdentifiers, EstContainer, WaitingContainerRule},
+    database::{
+        Addr, AdhocRule, AuditEntry, Ban, CapacitySample, ContainerAlias, ContainerEnforcement,
+        ContainerIdentifiers, EnforcementMode, EstContainer, Freeze, HeldEvent, LearningSession,
+        ObservedFlow, Panic, RuleActivity, StatEvent, StatsBucket, WaitingContainerRule, crypto,
+        stats::StatsGranularity,
+    },
 };
 
 /// Database operations that can be executed
@@ -22,6 +27,8 @@ pub enum DbOp<'a> {
     // Address operations
     InsertAddr(&'a Addr),
     GetAddrsByContainer(&'a str),
+    /// The addresses of every container
+    ListAddrs,
     DeleteAddrsByContainer(&'a str),
 
     // ContainerIdentifiers alias operations
@@ -41,6 +48,96 @@ pub enum DbOp<'a> {
         src_container_id: &'a str,
         dst_container_name: &'a str,
     },
+    /// Forget every tracked container with its addresses, aliases and
+    /// waiting rules. History (stats, audit, overrides, learning) is kept
+    ResetContainerState,
+
+    // Stats operations
+    InsertStatEvent(&'a StatEvent),
+    /// Fold raw events up to `until` into the hourly and daily rollups
+    RollupStats {
+        until: i64,
+    },
+    PruneStats {
+        hourly_before: i64,
+        daily_before: i64,
+    },
+    QueryStats {
+        granularity: StatsGranularity,
+        since: i64,
+    },
+    InsertCapacitySample(&'a CapacitySample),
+    /// Latest capacity sample taken at or before the given time
+    GetCapacitySampleAt(i64),
+
+    // Enforcement mode operations
+    /// Setting `Enforce` removes the override
+    SetEnforcementMode {
+        container_name: &'a str,
+        mode: EnforcementMode,
+        updated_at: i64,
+    },
+    GetEnforcementMode(&'a str),
+    ListEnforcementModes,
+
+    // Audit log operations
+    InsertAuditEntry(&'a AuditEntry),
+    /// Entries of the given kinds for one container, oldest first
+    ListContainerAuditEntries {
+        container_name: &'a str,
+        kinds: &'a [&'a str],
+    },
+    /// Entries at or after the given time, oldest first
+    ListAuditEntries {
+        since: i64,
+    },
+
+    // Learning mode operations
+    /// Replaces a running session for the same container
+    StartLearningSession(&'a LearningSession),
+    EndLearningSession(&'a str),
+    ListLearningSessions,
+    /// Adds to the sightings of a flow already recorded
+    RecordObservedFlow(&'a ObservedFlow),
+    /// Flows of a container, busiest first
+    ListObservedFlows(&'a str),
+
+    // Ad-hoc rule operations
+    InsertAdhocRule(&'a AdhocRule),
+    /// Every stored rule set, expired ones included, oldest first
+    ListAdhocRules,
+    DeleteAdhocRule(i64),
+
+    // Ban operations
+    InsertBan(&'a Ban),
+    /// Every stored ban, expired ones included, oldest first
+    ListBans,
+    DeleteBan(i64),
+
+    // Rule activity operations
+    /// Keeps the first sighting and the last hit already recorded
+    RecordRuleActivity(&'a RuleActivity),
+    ListRuleActivity,
+    /// Forget rules not installed since the given time
+    PruneRuleActivity {
+        before: i64,
+    },
+
+    // Change freeze operations
+    /// Replaces a freeze already in place
+    SetFreeze(&'a Freeze),
+    GetFreeze,
+    ClearFreeze,
+    /// Adds to the events already held for the container
+    HoldEvent(&'a HeldEvent),
+    ListHeldEvents,
+    ClearHeldEvents,
+
+    // Kill switch operations
+    /// Replaces the kill switch already engaged
+    SetPanic(&'a Panic),
+    GetPanic,
+    ClearPanic,
 }
 
 /// Result of a database operation
@@ -51,6 +148,19 @@ pub enum DbOpResult {
     ContainerIdentifiers(Option<ContainerIdentifiers>),
     Addrs(Vec<Addr>),
     WaitingRules(Vec<WaitingContainerRule>),
+    Stats(Vec<StatsBucket>),
+    CapacitySample(Option<CapacitySample>),
+    EnforcementMode(EnforcementMode),
+    EnforcementModes(Vec<ContainerEnforcement>),
+    AuditEntries(Vec<AuditEntry>),
+    LearningSessions(Vec<LearningSession>),
+    ObservedFlows(Vec<ObservedFlow>),
+    AdhocRules(Vec<AdhocRule>),
+    Bans(Vec<Ban>),
+    RuleActivity(Vec<RuleActivity>),
+    Freeze(Option<Freeze>),
+    HeldEvents(Vec<HeldEvent>),
+    Panic(Option<Panic>),
 }
 
 /// Execute a database operation
@@ -142,6 +252,14 @@ pub async fn execute_op(tx: &mut Transaction<'_, Sqlite>, op: &DbOp<'_>) -> Resu
             Ok(DbOpResult::Addrs(addrs))
         }
 
+        DbOp::ListAddrs => {
+            let addrs = query_as!(Addr, "SELECT addr, container_id FROM addrs")
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to list addresses: {}", e)))?;
+            Ok(DbOpResult::Addrs(addrs))
+        }
+
         DbOp::DeleteAddrsByContainer(container_id) => {
             query!("DELETE FROM addrs WHERE container_id = ?", container_id)
                 .execute(&mut **tx)
@@ -220,11 +338,12 @@ pub async fn execute_op(tx: &mut Transaction<'_, Sqlite>, op: &DbOp<'_>) -> Resu
 
         // Waiting rule operations
         DbOp::InsertWaitingRule(rule) => {
+            let sealed = crypto::seal_blob(&rule.rule);
             query!(
                 "INSERT OR IGNORE INTO waiting_container_rules (src_container_id, dst_container_name, rule) VALUES (?, ?, ?)",
                 rule.src_container_id,
                 rule.dst_container_name,
-                rule.rule
+                sealed
             )
             .execute(&mut **tx)
             .await
@@ -241,6 +360,14 @@ pub async fn execute_op(tx: &mut Transaction<'_, Sqlite>, op: &DbOp<'_>) -> Resu
             .fetch_all(&mut **tx)
             .await
             .map_err(|e| Error::Database(format!("Failed to get waiting rules: {}", e)))?;
+            let rules = rules
+                .into_iter()
+                .map(|mut rule| {
+                    rule.rule = crypto::open_blob(rule.rule)?;
+                    Ok(rule)
+                })
+                .collect::<std::result::Result<Vec<_>, crypto::CryptoError>>()
+                .map_err(|e| Error::Database(format!("Failed to read waiting rule: {}", e)))?;
             Ok(DbOpResult::WaitingRules(rules))
         }
 
@@ -269,5 +396,591 @@ pub async fn execute_op(tx: &mut Transaction<'_, Sqlite>, op: &DbOp<'_>) -> Resu
             .map_err(|e| Error::Database(format!("Failed to delete waiting rule: {}", e)))?;
             Ok(DbOpResult::Unit)
         }
+
+        DbOp::ResetContainerState => {
+            let reset =
+                |e: sqlx::Error| Error::Database(format!("Failed to reset container state: {}", e));
+            query!("DELETE FROM waiting_container_rules")
+                .execute(&mut **tx)
+                .await
+                .map_err(reset)?;
+            query!("DELETE FROM est_containers")
+                .execute(&mut **tx)
+                .await
+                .map_err(reset)?;
+            query!("DELETE FROM container_aliases")
+                .execute(&mut **tx)
+                .await
+                .map_err(reset)?;
+            query!("DELETE FROM addrs")
+                .execute(&mut **tx)
+                .await
+                .map_err(reset)?;
+            query!("DELETE FROM containers")
+                .execute(&mut **tx)
+                .await
+                .map_err(reset)?;
+            Ok(DbOpResult::Unit)
+        }
+
+        // Stats operations
+        DbOp::InsertStatEvent(event) => {
+            let kind = event.kind.as_str();
+            query!(
+                "INSERT INTO stat_events (ts, container_name, kind, packets, bytes) VALUES (?, ?, ?, ?, ?)",
+                event.ts,
+                event.container_name,
+                kind,
+                event.packets,
+                event.bytes
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to insert stat event: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::RollupStats { until } => {
+            let oldest = query!(
+                r#"SELECT MIN(ts) AS "min_ts: i64" FROM stat_events WHERE ts <= ?"#,
+                until
+            )
+            .fetch_one(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to read stat events: {}", e)))?
+            .min_ts;
+
+            let Some(oldest) = oldest else {
+                return Ok(DbOpResult::Unit);
+            };
+
+            query!(
+                r#"INSERT INTO stats_hourly
+                       (bucket, container_name, dropped_packets, dropped_bytes, accepted_bytes, rule_changes)
+                   SELECT ts - ts % 3600, container_name,
+                       SUM(CASE WHEN kind = 'drop' THEN packets ELSE 0 END),
+                       SUM(CASE WHEN kind = 'drop' THEN bytes ELSE 0 END),
+                       SUM(CASE WHEN kind = 'accept' THEN bytes ELSE 0 END),
+                       SUM(CASE WHEN kind = 'rule_change' THEN 1 ELSE 0 END)
+                   FROM stat_events
+                   WHERE ts <= ?
+                   GROUP BY 1, 2
+                   ON CONFLICT(bucket, container_name) DO UPDATE SET
+                       dropped_packets = dropped_packets + excluded.dropped_packets,
+                       dropped_bytes = dropped_bytes + excluded.dropped_bytes,
+                       accepted_bytes = accepted_bytes + excluded.accepted_bytes,
+                       rule_changes = rule_changes + excluded.rule_changes"#,
+                until
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to update hourly stats: {}", e)))?;
+
+            query!("DELETE FROM stat_events WHERE ts <= ?", until)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to drain stat events: {}", e)))?;
+
+            // Recompute every day touched by this rollup from its hourly rows
+            let first_day = oldest - oldest % 86400;
+            query!(
+                r#"INSERT OR REPLACE INTO stats_daily
+                       (bucket, container_name, dropped_packets, dropped_bytes, accepted_bytes, rule_changes)
+                   SELECT bucket - bucket % 86400, container_name,
+                       SUM(dropped_packets), SUM(dropped_bytes), SUM(accepted_bytes), SUM(rule_changes)
+                   FROM stats_hourly
+                   WHERE bucket >= ?
+                   GROUP BY 1, 2"#,
+                first_day
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to update daily stats: {}", e)))?;
+
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::PruneStats {
+            hourly_before,
+            daily_before,
+        } => {
+            query!("DELETE FROM stats_hourly WHERE bucket < ?", hourly_before)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to prune hourly stats: {}", e)))?;
+            query!("DELETE FROM stats_daily WHERE bucket < ?", daily_before)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to prune daily stats: {}", e)))?;
+            query!(
+                "DELETE FROM capacity_samples WHERE bucket < ?",
+                daily_before
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to prune capacity samples: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::QueryStats { granularity, since } => {
+            let buckets = match granularity {
+                StatsGranularity::Hourly => query_as!(
+                    StatsBucket,
+                    "SELECT bucket, container_name, dropped_packets, dropped_bytes, accepted_bytes, rule_changes FROM stats_hourly WHERE bucket >= ? ORDER BY bucket, container_name",
+                    since
+                )
+                .fetch_all(&mut **tx)
+                .await,
+                StatsGranularity::Daily => query_as!(
+                    StatsBucket,
+                    "SELECT bucket, container_name, dropped_packets, dropped_bytes, accepted_bytes, rule_changes FROM stats_daily WHERE bucket >= ? ORDER BY bucket, container_name",
+                    since
+                )
+                .fetch_all(&mut **tx)
+                .await,
+            }
+            .map_err(|e| Error::Database(format!("Failed to query stats: {}", e)))?;
+            Ok(DbOpResult::Stats(buckets))
+        }
+
+        DbOp::InsertCapacitySample(sample) => {
+            query!(
+                "INSERT OR REPLACE INTO capacity_samples (bucket, chains, rules, set_elements, memory_bytes) VALUES (?, ?, ?, ?, ?)",
+                sample.bucket,
+                sample.chains,
+                sample.rules,
+                sample.set_elements,
+                sample.memory_bytes
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to insert capacity sample: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::GetCapacitySampleAt(at) => {
+            let sample = query_as!(
+                CapacitySample,
+                "SELECT bucket, chains, rules, set_elements, memory_bytes FROM capacity_samples WHERE bucket <= ? ORDER BY bucket DESC LIMIT 1",
+                at
+            )
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to get capacity sample: {}", e)))?;
+            Ok(DbOpResult::CapacitySample(sample))
+        }
+
+        DbOp::SetEnforcementMode {
+            container_name,
+            mode,
+            updated_at,
+        } => {
+            if *mode == EnforcementMode::Enforce {
+                query!(
+                    "DELETE FROM enforcement_modes WHERE container_name = ?",
+                    container_name
+                )
+                .execute(&mut **tx)
+                .await
+            } else {
+                let mode = mode.as_str();
+                query!(
+                    "INSERT OR REPLACE INTO enforcement_modes (container_name, mode, updated_at) VALUES (?, ?, ?)",
+                    container_name,
+                    mode,
+                    updated_at
+                )
+                .execute(&mut **tx)
+                .await
+            }
+            .map_err(|e| Error::Database(format!("Failed to set enforcement mode: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::GetEnforcementMode(container_name) => {
+            let mode = query!(
+                "SELECT mode FROM enforcement_modes WHERE container_name = ?",
+                container_name
+            )
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to get enforcement mode: {}", e)))?
+            .and_then(|row| row.mode.parse().ok())
+            .unwrap_or_default();
+            Ok(DbOpResult::EnforcementMode(mode))
+        }
+
+        DbOp::ListEnforcementModes => {
+            let modes = query!(
+                "SELECT container_name, mode, updated_at FROM enforcement_modes ORDER BY container_name"
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list enforcement modes: {}", e)))?
+            .into_iter()
+            .filter_map(|row| {
+                Some(ContainerEnforcement {
+                    mode: row.mode.parse().ok()?,
+                    container_name: row.container_name,
+                    updated_at: row.updated_at,
+                })
+            })
+            .collect();
+            Ok(DbOpResult::EnforcementModes(modes))
+        }
+
+        DbOp::InsertAuditEntry(entry) => {
+            let detail = crypto::seal_text(&entry.detail);
+            query!(
+                "INSERT INTO audit_log (ts, kind, container_name, detail, repaired) VALUES (?, ?, ?, ?, ?)",
+                entry.ts,
+                entry.kind,
+                entry.container_name,
+                detail,
+                entry.repaired
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to insert audit entry: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListAuditEntries { since } => {
+            let entries = query_as!(
+                AuditEntry,
+                r#"SELECT id, ts, kind, container_name, detail, repaired AS "repaired: bool" FROM audit_log WHERE ts >= ? ORDER BY id"#,
+                since
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list audit entries: {}", e)))?;
+            let entries = entries
+                .into_iter()
+                .map(|mut entry| {
+                    entry.detail = crypto::open_text(entry.detail)?;
+                    Ok(entry)
+                })
+                .collect::<std::result::Result<Vec<_>, crypto::CryptoError>>()
+                .map_err(|e| Error::Database(format!("Failed to read audit entry: {}", e)))?;
+            Ok(DbOpResult::AuditEntries(entries))
+        }
+
+        DbOp::ListContainerAuditEntries {
+            container_name,
+            kinds,
+        } => {
+            let entries = query_as!(
+                AuditEntry,
+                r#"SELECT id, ts, kind, container_name, detail, repaired AS "repaired: bool" FROM audit_log WHERE container_name = ? ORDER BY id"#,
+                container_name
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list audit entries: {}", e)))?;
+            let entries = entries
+                .into_iter()
+                .filter(|entry| kinds.contains(&entry.kind.as_str()))
+                .map(|mut entry| {
+                    entry.detail = crypto::open_text(entry.detail)?;
+                    Ok(entry)
+                })
+                .collect::<std::result::Result<Vec<_>, crypto::CryptoError>>()
+                .map_err(|e| Error::Database(format!("Failed to read audit entry: {}", e)))?;
+            Ok(DbOpResult::AuditEntries(entries))
+        }
+
+        DbOp::StartLearningSession(session) => {
+            query!(
+                "INSERT OR REPLACE INTO learning_sessions (container_name, started_at, ends_at) VALUES (?, ?, ?)",
+                session.container_name,
+                session.started_at,
+                session.ends_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to start learning session: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::EndLearningSession(container_name) => {
+            query!(
+                "DELETE FROM learning_sessions WHERE container_name = ?",
+                container_name
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to end learning session: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListLearningSessions => {
+            let sessions = query_as!(
+                LearningSession,
+                "SELECT container_name, started_at, ends_at FROM learning_sessions ORDER BY container_name"
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list learning sessions: {}", e)))?;
+            Ok(DbOpResult::LearningSessions(sessions))
+        }
+
+        DbOp::RecordObservedFlow(flow) => {
+            query!(
+                r#"INSERT INTO observed_flows
+                     (container_name, direction, proto, peer, port, peer_container, peer_network, first_seen, last_seen, sightings)
+                   VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                   ON CONFLICT(container_name, direction, proto, peer, port) DO UPDATE SET
+                     peer_container = excluded.peer_container,
+                     peer_network = excluded.peer_network,
+                     last_seen = excluded.last_seen,
+                     sightings = sightings + excluded.sightings"#,
+                flow.container_name,
+                flow.direction,
+                flow.proto,
+                flow.peer,
+                flow.port,
+                flow.peer_container,
+                flow.peer_network,
+                flow.first_seen,
+                flow.last_seen,
+                flow.sightings
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to record observed flow: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListObservedFlows(container_name) => {
+            let flows = query_as!(
+                ObservedFlow,
+                r#"SELECT container_name, direction, proto, peer, port, peer_container, peer_network, first_seen, last_seen, sightings
+                   FROM observed_flows WHERE container_name = ? ORDER BY sightings DESC, direction, proto, port, peer"#,
+                container_name
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list observed flows: {}", e)))?;
+            Ok(DbOpResult::ObservedFlows(flows))
+        }
+
+        DbOp::InsertAdhocRule(rule) => {
+            let rules = crypto::seal_text(&rule.rules);
+            query!(
+                "INSERT INTO adhoc_rules (container_name, rules, reason, created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+                rule.container_name,
+                rules,
+                rule.reason,
+                rule.created_at,
+                rule.expires_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to insert ad-hoc rules: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListAdhocRules => {
+            let rules = query_as!(
+                AdhocRule,
+                "SELECT id, container_name, rules, reason, created_at, expires_at FROM adhoc_rules ORDER BY id"
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list ad-hoc rules: {}", e)))?;
+            let rules = rules
+                .into_iter()
+                .map(|mut rule| {
+                    rule.rules = crypto::open_text(rule.rules)?;
+                    Ok(rule)
+                })
+                .collect::<std::result::Result<Vec<_>, crypto::CryptoError>>()
+                .map_err(|e| Error::Database(format!("Failed to read ad-hoc rules: {}", e)))?;
+            Ok(DbOpResult::AdhocRules(rules))
+        }
+
+        DbOp::DeleteAdhocRule(id) => {
+            query!("DELETE FROM adhoc_rules WHERE id = ?", id)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to delete ad-hoc rules: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::InsertBan(ban) => {
+            query!(
+                "INSERT INTO bans (ip, reason, created_at, expires_at) VALUES (?, ?, ?, ?)",
+                ban.ip,
+                ban.reason,
+                ban.created_at,
+                ban.expires_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to insert ban: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListBans => {
+            let bans = query_as!(
+                Ban,
+                "SELECT id, ip, reason, created_at, expires_at FROM bans ORDER BY id"
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list bans: {}", e)))?;
+            Ok(DbOpResult::Bans(bans))
+        }
+
+        DbOp::DeleteBan(id) => {
+            query!("DELETE FROM bans WHERE id = ?", id)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to delete ban: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::RecordRuleActivity(activity) => {
+            query!(
+                r#"INSERT INTO rule_activity (container_name, rule, first_seen, last_seen, last_hit)
+                   VALUES (?, ?, ?, ?, ?)
+                   ON CONFLICT(container_name, rule) DO UPDATE SET
+                     last_seen = excluded.last_seen,
+                     last_hit = COALESCE(excluded.last_hit, last_hit)"#,
+                activity.container_name,
+                activity.rule,
+                activity.first_seen,
+                activity.last_seen,
+                activity.last_hit
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to record rule activity: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListRuleActivity => {
+            let activity = query_as!(
+                RuleActivity,
+                "SELECT container_name, rule, first_seen, last_seen, last_hit FROM rule_activity ORDER BY container_name, rule"
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list rule activity: {}", e)))?;
+            Ok(DbOpResult::RuleActivity(activity))
+        }
+
+        DbOp::PruneRuleActivity { before } => {
+            query!("DELETE FROM rule_activity WHERE last_seen < ?", before)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to prune rule activity: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::SetFreeze(freeze) => {
+            query!(
+                "INSERT OR REPLACE INTO freeze (id, reason, started_at, until) VALUES (1, ?, ?, ?)",
+                freeze.reason,
+                freeze.started_at,
+                freeze.until
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to store freeze: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::GetFreeze => {
+            let freeze = query_as!(
+                Freeze,
+                "SELECT reason, started_at, until FROM freeze WHERE id = 1"
+            )
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to read freeze: {}", e)))?;
+            Ok(DbOpResult::Freeze(freeze))
+        }
+
+        DbOp::ClearFreeze => {
+            query!("DELETE FROM freeze")
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to clear freeze: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::HoldEvent(held) => {
+            query!(
+                r#"INSERT INTO held_events (container_id, container_name, action, events, first_held, last_held)
+                   VALUES (?, ?, ?, ?, ?, ?)
+                   ON CONFLICT(container_id) DO UPDATE SET
+                     container_name = excluded.container_name,
+                     action = excluded.action,
+                     events = events + excluded.events,
+                     last_held = excluded.last_held"#,
+                held.container_id,
+                held.container_name,
+                held.action,
+                held.events,
+                held.first_held,
+                held.last_held
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to hold event: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::ListHeldEvents => {
+            let held = query_as!(
+                HeldEvent,
+                "SELECT container_id, container_name, action, events, first_held, last_held FROM held_events ORDER BY first_held, container_name"
+            )
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list held events: {}", e)))?;
+            Ok(DbOpResult::HeldEvents(held))
+        }
+
+        DbOp::ClearHeldEvents => {
+            query!("DELETE FROM held_events")
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to clear held events: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::SetPanic(panic) => {
+            query!(
+                "INSERT OR REPLACE INTO panic (id, reason, started_at, allow) VALUES (1, ?, ?, ?)",
+                panic.reason,
+                panic.started_at,
+                panic.allow
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to store kill switch: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
+
+        DbOp::GetPanic => {
+            let panic = query_as!(
+                Panic,
+                "SELECT reason, started_at, allow FROM panic WHERE id = 1"
+            )
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to read kill switch: {}", e)))?;
+            Ok(DbOpResult::Panic(panic))
+        }
+
+        DbOp::ClearPanic => {
+            query!("DELETE FROM panic")
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to clear kill switch: {}", e)))?;
+            Ok(DbOpResult::Unit)
+        }
     }
 }
@@ -0,0 +1,151 @@
+//! The host kill switch, behind `harborshield panic`.
+//!
+//! Engaging it installs a table of its own (see [`crate::nftables::panic`])
+//! that drops all traffic from and to the addresses of tracked containers,
+//! except with the `--allow` networks, right away from the command line and
+//! again by a running daemon as containers come and go. It stays until
+//! `harborshield panic --release`, across daemon restarts and
+//! `harborshield flush`; both ends are recorded in the audit log. Change
+//! freezes don't hold it back.
+
+use crate::Result;
+use crate::database::{AuditEntry, DB, DbOp, DbOpResult, Panic, audit};
+use crate::output::{Column, Render, Table};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::net::IpAddr;
+
+fn time(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0).map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+}
+
+/// An `--allow` value: a network, or an address on its own
+pub fn parse_allow_arg(value: &str) -> std::result::Result<IpNet, String> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .map(|net| net.trunc())
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| {
+            format!(
+                "invalid network '{}': expected an IP address or CIDR",
+                value
+            )
+        })
+}
+
+impl Panic {
+    /// The networks still let through; unreadable entries are left out
+    pub fn allowlist(&self) -> Vec<IpNet> {
+        self.allow
+            .split(',')
+            .filter_map(|net| net.trim().parse().ok())
+            .collect()
+    }
+}
+
+/// Engage the kill switch, or replace the one engaged, with its audit entry
+/// in the same transaction
+pub async fn engage(db: &mut DB, allow: &[IpNet], reason: Option<&str>) -> Result<Panic> {
+    let panic = Panic {
+        reason: reason.map(str::to_string),
+        started_at: chrono::Utc::now().timestamp(),
+        allow: allow
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+    };
+    let mut detail = "kill switch engaged: all container traffic dropped".to_string();
+    if !allow.is_empty() {
+        detail.push_str(&format!(" except with {}", panic.allow));
+    }
+    if let Some(reason) = reason {
+        detail.push_str(&format!(" ({})", reason));
+    }
+    let entry = AuditEntry::builder()
+        .ts(panic.started_at)
+        .kind(audit::KIND_PANIC_ENGAGED)
+        .detail(detail)
+        .build();
+
+    let ops = [DbOp::SetPanic(&panic), DbOp::InsertAuditEntry(&entry)];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(panic)
+}
+
+/// The engaged kill switch, if any
+pub async fn current(db: &DB) -> Result<Option<Panic>> {
+    match db.execute(&DbOp::GetPanic).await? {
+        DbOpResult::Panic(panic) => Ok(panic),
+        _ => Ok(None),
+    }
+}
+
+/// Release the kill switch, recording it; false when it wasn't engaged
+pub async fn release(db: &mut DB, reason: Option<&str>) -> Result<bool> {
+    let Some(panic) = current(db).await? else {
+        return Ok(false);
+    };
+    let mut detail = format!("kill switch engaged at {} released", time(panic.started_at));
+    if let Some(reason) = reason {
+        detail.push_str(&format!(" ({})", reason));
+    }
+    let entry = AuditEntry::builder()
+        .ts(chrono::Utc::now().timestamp())
+        .kind(audit::KIND_PANIC_RELEASED)
+        .detail(detail)
+        .build();
+    let ops = [DbOp::ClearPanic, DbOp::InsertAuditEntry(&entry)];
+    db.transaction().execute_ops(&ops).await?.commit().await?;
+    crate::bus::publish_audit(std::slice::from_ref(&entry));
+    Ok(true)
+}
+
+/// The addresses stored for containers, for cutting them off without the
+/// daemon
+pub async fn addresses(db: &DB) -> Result<Vec<IpAddr>> {
+    let addrs = match db.execute(&DbOp::ListAddrs).await? {
+        DbOpResult::Addrs(addrs) => addrs,
+        _ => Vec::new(),
+    };
+    let mut ips: Vec<IpAddr> = addrs.iter().filter_map(|addr| addr.to_ip()).collect();
+    ips.sort();
+    ips.dedup();
+    Ok(ips)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PanicReport {
+    pub panic: Option<Panic>,
+}
+
+impl Render for PanicReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("ENGAGED"),
+            Column::left("ALLOWED"),
+            Column::left("REASON"),
+        ]);
+
+        match &self.panic {
+            Some(panic) => table.row(vec![
+                time(panic.started_at).into(),
+                if panic.allow.is_empty() {
+                    "-".to_string()
+                } else {
+                    panic.allow.replace(',', ", ")
+                }
+                .into(),
+                panic
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string())
+                    .into(),
+            ]),
+            None => table.footer("the kill switch is released"),
+        };
+        table
+    }
+}
@@ -0,0 +1,406 @@
+//! `harborshield db schema`: the tables this build's migrations leave, for
+//! integrators writing read-only reports against the database.
+//!
+//! The migrations are applied to an in-memory database and its tables
+//! introspected, so the schema is the one a daemon of this build runs
+//! with. What tables and columns mean comes from the migrations'
+//! comments: the comment block above a `CREATE TABLE` describes the table,
+//! a trailing `--` comment on a column or an `ALTER TABLE ... ADD COLUMN`
+//! describes the column. New migrations should annotate their columns the
+//! same way.
+
+use crate::{Error, Result};
+use serde::Serialize;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, Connection, Row};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SchemaFormat {
+    #[default]
+    Markdown,
+    Sql,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Schema {
+    pub schema_version: i64,
+    pub tables: Vec<TableDoc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableDoc {
+    pub name: String,
+    pub description: Option<String>,
+    /// The migration that created the table
+    pub since: Option<i64>,
+    pub columns: Vec<ColumnDoc>,
+    /// `CREATE INDEX` statements
+    pub indexes: Vec<String>,
+    /// The `CREATE TABLE` statement as SQLite keeps it
+    pub sql: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnDoc {
+    pub name: String,
+    pub sql_type: String,
+    pub not_null: bool,
+    pub default: Option<String>,
+    pub primary_key: bool,
+    pub meaning: Option<String>,
+}
+
+/// The comments of the migrations, for one table
+#[derive(Debug, Default, PartialEq)]
+struct Notes {
+    description: Option<String>,
+    since: Option<i64>,
+    columns: HashMap<String, String>,
+}
+
+/// `name` of `CREATE TABLE [IF NOT EXISTS] name (`
+fn created_table(line: &str) -> Option<String> {
+    let upper = line.to_ascii_uppercase();
+    let rest = upper.strip_prefix("CREATE TABLE ")?;
+    let skip = line.len() - rest.len()
+        + if rest.starts_with("IF NOT EXISTS ") {
+            "IF NOT EXISTS ".len()
+        } else {
+            0
+        };
+    let name = line[skip..]
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .next()?;
+    Some(name.trim_matches('"').to_string()).filter(|name| !name.is_empty())
+}
+
+/// `name` of `DROP TABLE [IF EXISTS] name`
+fn dropped_table(line: &str) -> Option<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let upper: Vec<String> = words.iter().map(|word| word.to_ascii_uppercase()).collect();
+    if upper.first()? != "DROP" || upper.get(1)? != "TABLE" {
+        return None;
+    }
+    let name = if upper.get(2)? == "IF" {
+        words.get(4)?
+    } else {
+        words.get(2)?
+    };
+    Some(name.trim_end_matches(';').trim_matches('"').to_string())
+}
+
+/// Table and column of `ALTER TABLE t ADD [COLUMN] c ...`
+fn added_column(line: &str) -> Option<(String, String)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let upper: Vec<String> = words
+        .iter()
+        .take(5)
+        .map(|word| word.to_ascii_uppercase())
+        .collect();
+    if upper.first()? != "ALTER" || upper.get(1)? != "TABLE" || upper.get(3)? != "ADD" {
+        return None;
+    }
+    let column = if upper.get(4)? == "COLUMN" {
+        words.get(5)?
+    } else {
+        words.get(4)?
+    };
+    Some((
+        words[2].trim_matches('"').to_string(),
+        column.trim_matches('"').to_string(),
+    ))
+}
+
+/// Table constraints, as opposed to columns, in a `CREATE TABLE` body
+fn is_constraint(word: &str) -> bool {
+    ["PRIMARY", "FOREIGN", "UNIQUE", "CHECK", "CONSTRAINT"]
+        .contains(&word.to_ascii_uppercase().as_str())
+}
+
+/// Gather the comments of migrations `(version, sql)`, the later ones
+/// winning
+fn annotations<'a>(migrations: impl Iterator<Item = (i64, &'a str)>) -> HashMap<String, Notes> {
+    let mut notes: HashMap<String, Notes> = HashMap::new();
+    for (version, sql) in migrations {
+        let mut pending: Vec<&str> = Vec::new();
+        let mut table: Option<String> = None;
+        for line in sql.lines() {
+            let line = line.trim();
+            let (code, comment) = match line.split_once("--") {
+                Some((code, comment)) => (code.trim(), Some(comment.trim())),
+                None => (line, None),
+            };
+
+            if let Some(name) = &table {
+                if code.starts_with(')') {
+                    table = None;
+                } else if let (Some(column), Some(comment)) =
+                    (code.split([' ', '\t', '(']).next(), comment)
+                    && !is_constraint(column)
+                    && !comment.is_empty()
+                {
+                    notes
+                        .entry(name.clone())
+                        .or_default()
+                        .columns
+                        .insert(column.trim_matches('"').to_string(), comment.to_string());
+                }
+                continue;
+            }
+
+            if code.is_empty() {
+                // Blank lines may part a description from its table
+                if let Some(comment) = comment {
+                    pending.push(comment);
+                }
+                continue;
+            }
+            if let Some(name) = created_table(code) {
+                let entry = notes.entry(name.clone()).or_default();
+                let description = pending.join(" ");
+                if !description.is_empty() {
+                    entry.description = Some(description);
+                }
+                entry.since = entry.since.or(Some(version));
+                // A one-line `CREATE TABLE t (...)` has no body to read
+                if !code.contains(')') {
+                    table = Some(name);
+                }
+            } else if let Some(name) = dropped_table(code) {
+                notes.remove(&name);
+            } else if let Some((name, column)) = added_column(code)
+                && let Some(comment) = comment.filter(|comment| !comment.is_empty())
+            {
+                notes
+                    .entry(name)
+                    .or_default()
+                    .columns
+                    .insert(column, comment.to_string());
+            }
+            pending.clear();
+        }
+    }
+    notes
+}
+
+/// The schema this build's migrations leave
+pub async fn describe() -> Result<Schema> {
+    let failed = |e: sqlx::Error| Error::Database(format!("Failed to describe the schema: {}", e));
+    let mut conn = "sqlite::memory:"
+        .parse::<SqliteConnectOptions>()
+        .map_err(failed)?
+        .connect()
+        .await
+        .map_err(failed)?;
+    super::MIGRATOR
+        .run(&mut conn)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to describe the schema: {}", e)))?;
+
+    let mut notes = annotations(
+        super::MIGRATOR
+            .iter()
+            .map(|migration| (migration.version, migration.sql.as_ref())),
+    );
+    let tables: Vec<(String, String)> = sqlx::query_as(
+        "SELECT name, sql FROM sqlite_master WHERE type = 'table' \
+         AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations' ORDER BY name",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .map_err(failed)?;
+
+    let mut docs = Vec::new();
+    for (name, sql) in tables {
+        let mut table_notes = notes.remove(&name).unwrap_or_default();
+        let columns = sqlx::query(
+            "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid",
+        )
+        .bind(&name)
+        .fetch_all(&mut conn)
+        .await
+        .map_err(failed)?
+        .iter()
+        .map(|row| {
+            let column: String = row.get(0);
+            let sql_type: String = row.get(1);
+            let primary_key = row.get::<i64, _>(4) != 0;
+            ColumnDoc {
+                meaning: table_notes.columns.remove(&column),
+                // An INTEGER PRIMARY KEY is the rowid, never NULL
+                not_null: row.get::<i64, _>(2) != 0
+                    || (primary_key && sql_type.eq_ignore_ascii_case("INTEGER")),
+                sql_type,
+                default: row.get(3),
+                primary_key,
+                name: column,
+            }
+        })
+        .collect();
+        let indexes: Vec<String> = sqlx::query_scalar(
+            "SELECT sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ? \
+             AND sql IS NOT NULL ORDER BY name",
+        )
+        .bind(&name)
+        .fetch_all(&mut conn)
+        .await
+        .map_err(failed)?;
+        docs.push(TableDoc {
+            name,
+            description: table_notes.description,
+            since: table_notes.since,
+            columns,
+            indexes,
+            sql,
+        });
+    }
+    let _ = conn.close().await;
+
+    Ok(Schema {
+        schema_version: super::backup::schema_version(),
+        tables: docs,
+    })
+}
+
+/// `|` would end a Markdown table cell
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+impl Schema {
+    pub fn render(&self, format: SchemaFormat) -> String {
+        match format {
+            SchemaFormat::Markdown => self.markdown(),
+            SchemaFormat::Sql => self.sql(),
+        }
+    }
+
+    fn markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# harborshield database schema\n");
+        let _ = writeln!(out, "Schema version {}.\n", self.schema_version);
+        for table in &self.tables {
+            let _ = writeln!(out, "## `{}`\n", table.name);
+            if let Some(description) = &table.description {
+                let _ = writeln!(out, "{}\n", description);
+            }
+            if let Some(since) = table.since {
+                let _ = writeln!(out, "Added in migration {}.\n", since);
+            }
+            let _ = writeln!(out, "| Column | Type | Null | Default | Meaning |");
+            let _ = writeln!(out, "|---|---|---|---|---|");
+            for column in &table.columns {
+                let mut meaning = column.meaning.as_deref().map(cell).unwrap_or_default();
+                if column.primary_key {
+                    meaning = if meaning.is_empty() {
+                        "Primary key".to_string()
+                    } else {
+                        format!("Primary key; {}", meaning)
+                    };
+                }
+                let _ = writeln!(
+                    out,
+                    "| `{}` | {} | {} | {} | {} |",
+                    column.name,
+                    column.sql_type,
+                    if column.not_null { "no" } else { "yes" },
+                    column
+                        .default
+                        .as_deref()
+                        .map(|default| format!("`{}`", cell(default)))
+                        .unwrap_or_default(),
+                    meaning
+                );
+            }
+            if !table.indexes.is_empty() {
+                let _ = writeln!(out, "\nIndexes:\n");
+                for index in &table.indexes {
+                    let _ = writeln!(out, "- `{}`", index);
+                }
+            }
+            out.push('\n');
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+        out
+    }
+
+    fn sql(&self) -> String {
+        let mut out = format!(
+            "-- harborshield database schema, version {}\n",
+            self.schema_version
+        );
+        for table in &self.tables {
+            out.push('\n');
+            if let Some(description) = &table.description {
+                let _ = writeln!(out, "-- {}", description);
+            }
+            let _ = writeln!(out, "{};", table.sql);
+            for index in &table.indexes {
+                let _ = writeln!(out, "{};", index);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_describe() {
+        let notes = annotations(
+            [
+                (
+                    1,
+                    "-- Things\n-- and more\n\nCREATE TABLE things (\n  id INTEGER PRIMARY KEY, -- row id\n  name TEXT NOT NULL,\n\n  UNIQUE(name) -- not a column\n) STRICT;\n-- Lookups\nCREATE INDEX idx ON things(name);\nCREATE TABLE bare (id INTEGER);",
+                ),
+                (2, "ALTER TABLE things ADD COLUMN seen INTEGER; -- unix seconds"),
+            ]
+            .into_iter(),
+        );
+        let things = &notes["things"];
+        assert_eq!(things.description.as_deref(), Some("Things and more"));
+        assert_eq!(things.since, Some(1));
+        assert_eq!(things.columns["id"], "row id");
+        assert_eq!(things.columns["seen"], "unix seconds");
+        assert_eq!(things.columns.len(), 2);
+        assert_eq!(notes["bare"].description, None);
+
+        let schema = describe().await.unwrap();
+        assert_eq!(
+            schema.schema_version,
+            super::super::backup::schema_version()
+        );
+        let bans = schema.tables.iter().find(|t| t.name == "bans").unwrap();
+        assert!(
+            bans.description
+                .as_deref()
+                .unwrap()
+                .starts_with("Addresses banned")
+        );
+        let ip = bans.columns.iter().find(|c| c.name == "ip").unwrap();
+        assert_eq!(ip.meaning.as_deref(), Some("IPv4 address"));
+        assert!(ip.not_null && !ip.primary_key);
+        assert_eq!(bans.indexes.len(), 1);
+        // Dropped by a later migration
+        assert!(!schema.tables.iter().any(|t| t.name == "persistent_rules"));
+
+        let markdown = schema.render(SchemaFormat::Markdown);
+        assert!(
+            markdown.contains("| `ip` | TEXT | no |  | IPv4 address |"),
+            "{}",
+            markdown
+        );
+        let sql = schema.render(SchemaFormat::Sql);
+        assert!(sql.contains("CREATE TABLE bans ("), "{}", sql);
+        assert!(
+            sql.contains("CREATE INDEX idx_bans_ip ON bans(ip);"),
+            "{}",
+            sql
+        );
+    }
+}
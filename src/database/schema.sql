@@ -36,3 +36,133 @@ CREATE TABLE waiting_container_rules (
   FOREIGN KEY (src_container_id) REFERENCES containers(id)
 ) STRICT;
 
+CREATE TABLE stat_events (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  ts              INTEGER NOT NULL,
+  container_name  TEXT NOT NULL,
+  kind            TEXT NOT NULL,
+  packets         INTEGER NOT NULL DEFAULT 0,
+  bytes           INTEGER NOT NULL DEFAULT 0
+) STRICT;
+
+CREATE TABLE stats_hourly (
+  bucket           INTEGER NOT NULL,
+  container_name   TEXT NOT NULL,
+  dropped_packets  INTEGER NOT NULL DEFAULT 0,
+  dropped_bytes    INTEGER NOT NULL DEFAULT 0,
+  accepted_bytes   INTEGER NOT NULL DEFAULT 0,
+  rule_changes     INTEGER NOT NULL DEFAULT 0,
+
+  PRIMARY KEY(bucket, container_name)
+) STRICT;
+
+CREATE TABLE stats_daily (
+  bucket           INTEGER NOT NULL,
+  container_name   TEXT NOT NULL,
+  dropped_packets  INTEGER NOT NULL DEFAULT 0,
+  dropped_bytes    INTEGER NOT NULL DEFAULT 0,
+  accepted_bytes   INTEGER NOT NULL DEFAULT 0,
+  rule_changes     INTEGER NOT NULL DEFAULT 0,
+
+  PRIMARY KEY(bucket, container_name)
+) STRICT;
+
+CREATE TABLE capacity_samples (
+  bucket          INTEGER PRIMARY KEY,
+  chains          INTEGER NOT NULL,
+  rules           INTEGER NOT NULL,
+  set_elements    INTEGER NOT NULL,
+  memory_bytes    INTEGER NOT NULL
+) STRICT;
+
+CREATE TABLE enforcement_modes (
+  container_name  TEXT PRIMARY KEY,
+  mode            TEXT NOT NULL,
+  updated_at      INTEGER NOT NULL
+) STRICT;
+
+CREATE TABLE audit_log (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  ts              INTEGER NOT NULL,
+  kind            TEXT NOT NULL,
+  container_name  TEXT,
+  detail          TEXT NOT NULL,
+  repaired        INTEGER NOT NULL DEFAULT 0
+) STRICT;
+
+CREATE INDEX idx_audit_log_ts ON audit_log(ts);
+
+CREATE TABLE learning_sessions (
+  container_name  TEXT PRIMARY KEY,
+  started_at      INTEGER NOT NULL,
+  ends_at         INTEGER NOT NULL
+) STRICT;
+
+CREATE TABLE observed_flows (
+  container_name  TEXT NOT NULL,
+  direction       TEXT NOT NULL,
+  proto           TEXT NOT NULL,
+  peer            TEXT NOT NULL,
+  port            INTEGER NOT NULL,
+  peer_container  TEXT,
+  peer_network    TEXT,
+  first_seen      INTEGER NOT NULL,
+  last_seen       INTEGER NOT NULL,
+  sightings       INTEGER NOT NULL DEFAULT 1,
+
+  PRIMARY KEY(container_name, direction, proto, peer, port)
+) STRICT;
+
+CREATE TABLE adhoc_rules (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  container_name  TEXT NOT NULL,
+  rules           TEXT NOT NULL,
+  reason          TEXT,
+  created_at      INTEGER NOT NULL,
+  expires_at      INTEGER NOT NULL
+) STRICT;
+
+CREATE INDEX idx_adhoc_rules_container ON adhoc_rules(container_name);
+
+CREATE TABLE rule_activity (
+  container_name  TEXT NOT NULL,
+  rule            TEXT NOT NULL,
+  first_seen      INTEGER NOT NULL,
+  last_seen       INTEGER NOT NULL,
+  last_hit        INTEGER,
+
+  PRIMARY KEY(container_name, rule)
+) STRICT;
+
+CREATE TABLE freeze (
+  id              INTEGER PRIMARY KEY CHECK (id = 1),
+  reason          TEXT,
+  started_at      INTEGER NOT NULL,
+  until           INTEGER
+) STRICT;
+
+CREATE TABLE held_events (
+  container_id    TEXT PRIMARY KEY,
+  container_name  TEXT NOT NULL,
+  action          TEXT NOT NULL,
+  events          INTEGER NOT NULL DEFAULT 1,
+  first_held      INTEGER NOT NULL,
+  last_held       INTEGER NOT NULL
+) STRICT;
+
+CREATE TABLE bans (
+  id              INTEGER PRIMARY KEY AUTOINCREMENT,
+  ip              TEXT NOT NULL,
+  reason          TEXT,
+  created_at      INTEGER NOT NULL,
+  expires_at      INTEGER NOT NULL
+) STRICT;
+
+CREATE INDEX idx_bans_ip ON bans(ip);
+
+CREATE TABLE panic (
+  id              INTEGER PRIMARY KEY CHECK (id = 1),
+  reason          TEXT,
+  started_at      INTEGER NOT NULL,
+  allow           TEXT NOT NULL DEFAULT ''
+) STRICT;
@@ -0,0 +1,133 @@
+//! Rollup queries behind `harborshield stats`.
+//!
+//! Raw [`StatEvent`](super::StatEvent)s are drained into `stats_hourly` and
+//! `stats_daily` by the aggregation job, so reads only ever touch the rollup
+//! tables.
+
+use super::{DB, DbOp, DbOpResult, StatsBucket};
+use crate::Result;
+use crate::output::{Cell, Color, Column, Render, Table, human_bytes};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Hourly rows are kept this long before only daily rows remain
+pub const HOURLY_RETENTION: Duration = Duration::from_secs(30 * 86400);
+
+/// Daily rows are kept this long
+pub const DAILY_RETENTION: Duration = Duration::from_secs(400 * 86400);
+
+/// Windows up to this length are answered from the hourly table
+const HOURLY_WINDOW_LIMIT: Duration = Duration::from_secs(2 * 86400);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsGranularity {
+    Hourly,
+    Daily,
+}
+
+impl StatsGranularity {
+    pub fn for_window(window: Duration) -> Self {
+        if window <= HOURLY_WINDOW_LIMIT {
+            Self::Hourly
+        } else {
+            Self::Daily
+        }
+    }
+
+    pub fn bucket_secs(&self) -> i64 {
+        match self {
+            Self::Hourly => 3600,
+            Self::Daily => 86400,
+        }
+    }
+}
+
+/// Per-container totals for the last `window` before `now` (unix seconds).
+///
+/// The window start is rounded down to a bucket boundary, so the result can
+/// include up to one bucket more than asked for.
+pub async fn stats_since(db: &DB, window: Duration, now: i64) -> Result<Vec<StatsBucket>> {
+    let granularity = StatsGranularity::for_window(window);
+    let start = now - window.as_secs() as i64;
+    let since = start - start.rem_euclid(granularity.bucket_secs());
+
+    let buckets = match db.execute(&DbOp::QueryStats { granularity, since }).await? {
+        DbOpResult::Stats(buckets) => buckets,
+        _ => Vec::new(),
+    };
+
+    Ok(summarize(buckets, since))
+}
+
+/// Collapse buckets into one row per container, busiest droppers first
+pub fn summarize(buckets: Vec<StatsBucket>, since: i64) -> Vec<StatsBucket> {
+    let mut totals: HashMap<String, StatsBucket> = HashMap::new();
+    for bucket in buckets {
+        let total = totals
+            .entry(bucket.container_name.clone())
+            .or_insert_with(|| StatsBucket {
+                bucket: since,
+                container_name: bucket.container_name.clone(),
+                ..Default::default()
+            });
+        total.dropped_packets += bucket.dropped_packets;
+        total.dropped_bytes += bucket.dropped_bytes;
+        total.accepted_bytes += bucket.accepted_bytes;
+        total.rule_changes += bucket.rule_changes;
+    }
+
+    let mut totals: Vec<_> = totals.into_values().collect();
+    totals.sort_by(|a, b| {
+        b.dropped_packets
+            .cmp(&a.dropped_packets)
+            .then_with(|| a.container_name.cmp(&b.container_name))
+    });
+    totals
+}
+
+/// Totals for a reporting window, as printed by `harborshield stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    pub since_seconds: u64,
+    pub containers: Vec<StatsBucket>,
+}
+
+impl Render for StatsReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::right("DROPPED PKTS"),
+            Column::right("DROPPED"),
+            Column::right("DROPPED BYTES").wide(),
+            Column::right("ACCEPTED"),
+            Column::right("ACCEPTED BYTES").wide(),
+            Column::right("RULE CHANGES"),
+        ]);
+
+        for row in &self.containers {
+            let drops = row.dropped_packets.to_string();
+            table.row(vec![
+                row.container_name.clone().into(),
+                if row.dropped_packets > 0 {
+                    Cell::colored(drops, Color::Red)
+                } else {
+                    drops.into()
+                },
+                human_bytes(row.dropped_bytes.max(0) as u64).into(),
+                row.dropped_bytes.to_string().into(),
+                human_bytes(row.accepted_bytes.max(0) as u64).into(),
+                row.accepted_bytes.to_string().into(),
+                row.rule_changes.to_string().into(),
+            ]);
+        }
+
+        if self.containers.is_empty() {
+            table.footer(Cell::colored(
+                "No stats recorded in this window",
+                Color::Dim,
+            ));
+        }
+        table
+    }
+}
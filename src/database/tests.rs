@@ -386,3 +386,481 @@ async fn test_foreign_key_constraint() {
     let result = db.execute(&DbOp::InsertAddr(&addr)).await;
     assert!(result.is_err());
 }
+
+#[tokio::test]
+async fn test_stats_rollup() {
+    use crate::database::stats::{StatsGranularity, stats_since};
+    use crate::database::{DbOp, StatEvent, StatKind};
+
+    let (_temp, db) = setup_test_db().await.unwrap();
+
+    // Two events in the same hour, one in the next day
+    let day = 20_000 * 86400;
+    let events = [
+        StatEvent::builder()
+            .ts(day + 60)
+            .container_name("web".to_string())
+            .kind(StatKind::Drop)
+            .packets(3)
+            .bytes(180)
+            .build(),
+        StatEvent::builder()
+            .ts(day + 120)
+            .container_name("web".to_string())
+            .kind(StatKind::RuleChange)
+            .build(),
+        StatEvent::builder()
+            .ts(day + 86400 + 60)
+            .container_name("web".to_string())
+            .kind(StatKind::Drop)
+            .packets(2)
+            .bytes(120)
+            .build(),
+    ];
+    for event in &events {
+        db.execute(&DbOp::InsertStatEvent(event)).await.unwrap();
+    }
+
+    db.execute(&DbOp::RollupStats {
+        until: day + 86400 + 60,
+    })
+    .await
+    .unwrap();
+
+    let DbOpResult::Stats(hourly) = db
+        .execute(&DbOp::QueryStats {
+            granularity: StatsGranularity::Hourly,
+            since: 0,
+        })
+        .await
+        .unwrap()
+    else {
+        panic!("Expected Stats result");
+    };
+    assert_eq!(hourly.len(), 2);
+    assert_eq!(hourly[0].bucket, day);
+    assert_eq!(hourly[0].dropped_packets, 3);
+    assert_eq!(hourly[0].rule_changes, 1);
+
+    let DbOpResult::Stats(daily) = db
+        .execute(&DbOp::QueryStats {
+            granularity: StatsGranularity::Daily,
+            since: 0,
+        })
+        .await
+        .unwrap()
+    else {
+        panic!("Expected Stats result");
+    };
+    assert_eq!(daily.len(), 2);
+
+    // Raw events were drained, so a second rollup must not double count
+    db.execute(&DbOp::RollupStats {
+        until: day + 2 * 86400,
+    })
+    .await
+    .unwrap();
+
+    let totals = stats_since(
+        &db,
+        std::time::Duration::from_secs(7 * 86400),
+        day + 2 * 86400,
+    )
+    .await
+    .unwrap();
+    assert_eq!(totals.len(), 1);
+    assert_eq!(totals[0].dropped_packets, 5);
+    assert_eq!(totals[0].dropped_bytes, 300);
+    assert_eq!(totals[0].rule_changes, 1);
+}
+
+#[test]
+fn test_render_stats_table() {
+    use crate::database::StatsBucket;
+    use crate::database::stats::StatsReport;
+    use crate::output::{OutputFormat, format};
+
+    let report = StatsReport {
+        since_seconds: 86400,
+        containers: vec![StatsBucket {
+            container_name: "web".to_string(),
+            dropped_packets: 12,
+            dropped_bytes: 2048,
+            accepted_bytes: 100,
+            rule_changes: 1,
+            ..Default::default()
+        }],
+    };
+
+    let table = format(&report, OutputFormat::Table, false);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("CONTAINER"));
+    assert!(lines[1].starts_with("web"));
+    assert!(lines[1].contains("2.0 KiB"));
+    assert!(lines[1].contains("100 B"));
+
+    let wide = format(&report, OutputFormat::Wide, false);
+    assert!(wide.contains("DROPPED BYTES"));
+    assert!(wide.lines().nth(1).unwrap().contains("2048"));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&format(&report, OutputFormat::Json, false)).unwrap();
+    assert_eq!(json["containers"][0]["dropped_packets"], 12);
+}
+
+#[tokio::test]
+async fn test_capacity_sample_lookup() {
+    use crate::database::{CapacitySample, DbOp};
+
+    let (_temp, db) = setup_test_db().await.unwrap();
+    for (bucket, rules) in [(3600, 10), (7200, 20)] {
+        let sample = CapacitySample {
+            bucket,
+            rules,
+            ..Default::default()
+        };
+        db.execute(&DbOp::InsertCapacitySample(&sample))
+            .await
+            .unwrap();
+    }
+
+    match db.execute(&DbOp::GetCapacitySampleAt(7000)).await.unwrap() {
+        DbOpResult::CapacitySample(Some(sample)) => assert_eq!(sample.rules, 10),
+        other => panic!("Expected capacity sample, got {:?}", other),
+    }
+    match db.execute(&DbOp::GetCapacitySampleAt(100)).await.unwrap() {
+        DbOpResult::CapacitySample(sample) => assert!(sample.is_none()),
+        other => panic!("Expected capacity sample, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_enforcement_modes() {
+    use crate::database::{DbOp, EnforcementMode, enforcement};
+
+    let (_temp, db) = setup_test_db().await.unwrap();
+    let mode_of = |name| {
+        let db = &db;
+        async move {
+            match db.execute(&DbOp::GetEnforcementMode(name)).await.unwrap() {
+                DbOpResult::EnforcementMode(mode) => mode,
+                other => panic!("Expected enforcement mode, got {:?}", other),
+            }
+        }
+    };
+
+    assert_eq!(mode_of("web").await, EnforcementMode::Enforce);
+
+    enforcement::set_mode(&db, "web", EnforcementMode::Permissive)
+        .await
+        .unwrap();
+    enforcement::set_mode(&db, "db", EnforcementMode::Disabled)
+        .await
+        .unwrap();
+    assert_eq!(mode_of("web").await, EnforcementMode::Permissive);
+
+    let overrides = enforcement::list_overrides(&db).await.unwrap();
+    let names: Vec<_> = overrides
+        .iter()
+        .map(|o| o.container_name.as_str())
+        .collect();
+    assert_eq!(names, ["db", "web"]);
+
+    // Back to enforce removes the override
+    enforcement::set_mode(&db, "web", EnforcementMode::Enforce)
+        .await
+        .unwrap();
+    assert_eq!(mode_of("web").await, EnforcementMode::Enforce);
+    assert_eq!(enforcement::list_overrides(&db).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_reset_container_state() {
+    use crate::database::{DbOp, EnforcementMode, enforcement};
+
+    let (_temp, db) = setup_test_db().await.unwrap();
+    let container = ContainerIdentifiers {
+        id: "reset123".to_string(),
+        name: "web".to_string(),
+    };
+    db.execute(&DbOp::InsertContainer(&container))
+        .await
+        .unwrap();
+    let addr = Addr::from_ip(
+        IpAddr::V4(Ipv4Addr::new(172, 17, 0, 2)),
+        "reset123".to_string(),
+    );
+    db.execute(&DbOp::InsertAddr(&addr)).await.unwrap();
+    db.execute(&DbOp::InsertContainerAlias(&ContainerAlias {
+        container_id: "reset123".to_string(),
+        container_alias: "frontend".to_string(),
+    }))
+    .await
+    .unwrap();
+    enforcement::set_mode(&db, "web", EnforcementMode::Permissive)
+        .await
+        .unwrap();
+
+    db.execute(&DbOp::ResetContainerState).await.unwrap();
+
+    match db.execute(&DbOp::ListContainers).await.unwrap() {
+        DbOpResult::Containers(containers) => assert!(containers.is_empty()),
+        other => panic!("Expected Containers result, got {:?}", other),
+    }
+    match db
+        .execute(&DbOp::GetAddrsByContainer("reset123"))
+        .await
+        .unwrap()
+    {
+        DbOpResult::Addrs(addrs) => assert!(addrs.is_empty()),
+        other => panic!("Expected Addrs result, got {:?}", other),
+    }
+    // Overrides are history, not container state
+    assert_eq!(enforcement::list_overrides(&db).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    use crate::database::{AuditEntry, audit};
+
+    let (_temp, mut db) = setup_test_db().await.unwrap();
+
+    let entries = [
+        AuditEntry::builder()
+            .ts(100)
+            .kind(audit::KIND_RECONCILE)
+            .detail("checked 2 chains, found 1 drift")
+            .build(),
+        AuditEntry::builder()
+            .ts(100)
+            .kind(audit::KIND_DRIFT)
+            .container_name("web".to_string())
+            .detail("missing chain hs-web-abc")
+            .repaired(true)
+            .build(),
+        AuditEntry::builder()
+            .ts(50)
+            .kind(audit::KIND_RECONCILE)
+            .detail("old run")
+            .build(),
+    ];
+    audit::record(&mut db, &entries).await.unwrap();
+
+    let recent = audit::list_since(&db, 100).await.unwrap();
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[1].container_name.as_deref(), Some("web"));
+    assert!(recent[1].repaired);
+    assert!(recent[0].id < recent[1].id);
+}
+
+#[tokio::test]
+async fn test_learning_sessions_and_flows() {
+    use crate::database::{EnforcementMode, ObservedFlow, enforcement, learning};
+
+    let (_temp, mut db) = setup_test_db().await.unwrap();
+
+    learning::start(&mut db, "web", std::time::Duration::from_secs(3600))
+        .await
+        .unwrap();
+    let sessions = learning::sessions(&db).await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].ends_at - sessions[0].started_at, 3600);
+    assert_eq!(
+        enforcement::list_overrides(&db).await.unwrap()[0].mode,
+        EnforcementMode::Permissive
+    );
+
+    let flow = |last_seen| {
+        ObservedFlow::builder()
+            .container_name("web")
+            .direction("outbound")
+            .proto("tcp")
+            .peer("93.184.216.34")
+            .port(443)
+            .first_seen(last_seen)
+            .last_seen(last_seen)
+            .build()
+    };
+    learning::record(&mut db, &[flow(100)]).await.unwrap();
+    learning::record(&mut db, &[flow(200)]).await.unwrap();
+
+    let flows = learning::flows(&db, "web").await.unwrap();
+    assert_eq!(flows.len(), 1);
+    assert_eq!(flows[0].first_seen, 100);
+    assert_eq!(flows[0].last_seen, 200);
+    assert_eq!(flows[0].sightings, 2);
+
+    // Ending the session enforces again but keeps the flows
+    learning::finish(&mut db, "web").await.unwrap();
+    assert!(learning::sessions(&db).await.unwrap().is_empty());
+    assert!(enforcement::list_overrides(&db).await.unwrap().is_empty());
+    assert_eq!(learning::flows(&db, "web").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_rule_activity() {
+    use crate::database::{RuleActivity, activity};
+
+    let (_temp, mut db) = setup_test_db().await.unwrap();
+
+    let sample = |rule: &str, now, last_hit| RuleActivity {
+        container_name: "web".to_string(),
+        rule: rule.to_string(),
+        first_seen: now,
+        last_seen: now,
+        last_hit,
+    };
+    activity::record(&mut db, &[sample("Output rule 1", 100, Some(100))], 0)
+        .await
+        .unwrap();
+    activity::record(
+        &mut db,
+        &[
+            sample("Output rule 1", 200, None),
+            sample("Output rule 2", 200, None),
+        ],
+        0,
+    )
+    .await
+    .unwrap();
+
+    // The first sighting and last hit are kept
+    let recorded = activity::list(&db).await.unwrap();
+    assert_eq!(recorded.len(), 2);
+    assert_eq!(recorded[0].first_seen, 100);
+    assert_eq!(recorded[0].last_seen, 200);
+    assert_eq!(recorded[0].last_hit, Some(100));
+
+    activity::record(&mut db, &[sample("Output rule 2", 300, None)], 250)
+        .await
+        .unwrap();
+    let recorded = activity::list(&db).await.unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].rule, "Output rule 2");
+}
+
+#[tokio::test]
+async fn test_freeze_and_held_events() {
+    use crate::database::{HeldEvent, freeze};
+
+    let (_temp, mut db) = setup_test_db().await.unwrap();
+    assert!(freeze::stored(&db).await.unwrap().is_none());
+
+    let started = freeze::freeze(&mut db, Some(i64::MAX), Some("release week"))
+        .await
+        .unwrap();
+    let now = started.started_at;
+    assert_eq!(freeze::current(&db, now).await.unwrap(), Some(started));
+
+    let held = |action: &str, at| HeldEvent {
+        container_id: "abc".to_string(),
+        container_name: "web".to_string(),
+        action: action.to_string(),
+        events: 1,
+        first_held: at,
+        last_held: at,
+    };
+    freeze::hold(&db, &held("start", 100)).await.unwrap();
+    freeze::hold(&db, &held("die", 200)).await.unwrap();
+    let held = freeze::held(&db).await.unwrap();
+    assert_eq!(held.len(), 1);
+    assert_eq!((held[0].action.as_str(), held[0].events), ("die", 2));
+    assert_eq!((held[0].first_held, held[0].last_held), (100, 200));
+
+    // Lifting keeps the held events for the daemon to apply
+    freeze::lift(&mut db, now, "unfrozen").await.unwrap();
+    assert!(freeze::current(&db, now).await.unwrap().is_none());
+    assert_eq!(freeze::held(&db).await.unwrap().len(), 1);
+    freeze::clear_held(&db).await.unwrap();
+    assert!(freeze::held(&db).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_bans() {
+    use crate::database::bans;
+    use std::time::Duration;
+
+    let (_temp, mut db) = setup_test_db().await.unwrap();
+    let ip = Ipv4Addr::new(203, 0, 113, 7);
+    let first = bans::ban(&mut db, ip, Duration::from_secs(60), None)
+        .await
+        .unwrap();
+    // Banning again replaces the ban
+    let second = bans::ban(
+        &mut db,
+        ip,
+        Duration::from_secs(3600),
+        Some("ssh brute force"),
+    )
+    .await
+    .unwrap();
+    bans::ban(
+        &mut db,
+        Ipv4Addr::new(198, 51, 100, 1),
+        Duration::from_secs(60),
+        None,
+    )
+    .await
+    .unwrap();
+    let stored = bans::list(&db).await.unwrap();
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored[0].reason.as_deref(), Some("ssh brute force"));
+    assert_eq!(stored[0].addr(), Some(ip));
+
+    // Only the ban past its expiry goes
+    let active = bans::expire(&mut db, first.expires_at).await.unwrap();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].expires_at, second.expires_at);
+
+    assert!(bans::unban(&mut db, ip, None).await.unwrap());
+    assert!(!bans::unban(&mut db, ip, None).await.unwrap());
+    assert!(bans::list(&db).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_panic() {
+    use crate::database::{DbOp, panic};
+
+    let (_temp, mut db) = setup_test_db().await.unwrap();
+    let container = ContainerIdentifiers {
+        id: "web123".to_string(),
+        name: "web".to_string(),
+    };
+    db.execute(&DbOp::InsertContainer(&container))
+        .await
+        .unwrap();
+    for ip in [
+        IpAddr::V4(Ipv4Addr::new(172, 18, 0, 2)),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ] {
+        db.execute(&DbOp::InsertAddr(&Addr::from_ip(ip, "web123".to_string())))
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        panic::addresses(&db).await.unwrap(),
+        [
+            IpAddr::V4(Ipv4Addr::new(172, 18, 0, 2)),
+            IpAddr::V6(Ipv6Addr::LOCALHOST)
+        ]
+    );
+
+    assert!(panic::current(&db).await.unwrap().is_none());
+    let allow = [
+        panic::parse_allow_arg("10.0.9.7/24").unwrap(),
+        panic::parse_allow_arg("192.0.2.1").unwrap(),
+        panic::parse_allow_arg("fd00:9::1/64").unwrap(),
+    ];
+    panic::engage(&mut db, &allow, Some("under attack"))
+        .await
+        .unwrap();
+    let engaged = panic::current(&db).await.unwrap().unwrap();
+    assert_eq!(engaged.allow, "10.0.9.0/24,192.0.2.1/32,fd00:9::/64");
+    assert_eq!(engaged.allowlist(), allow);
+    assert_eq!(engaged.reason.as_deref(), Some("under attack"));
+
+    assert!(panic::release(&mut db, None).await.unwrap());
+    assert!(!panic::release(&mut db, None).await.unwrap());
+    assert!(panic::current(&db).await.unwrap().is_none());
+}
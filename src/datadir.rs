@@ -0,0 +1,179 @@
+//! Setting up the data and runtime directories before the daemon trusts
+//! them.
+//!
+//! The database in the data directory decides what traffic containers get,
+//! so whoever can write to the directory controls the firewall. A missing
+//! directory is created with mode 0700. An existing one is refused unless
+//! it is a directory owned by the daemon's user or root, other users can't
+//! write to it, and no directory above it lets other users swap it out
+//! (world-writable without the sticky bit). A group-writable directory is
+//! only warned about.
+//!
+//! Persistent state (the database and its write-ahead log) is all that goes
+//! in the data directory. Scratch state, such as the database copy a plan
+//! works on, goes in the runtime directory: `--runtime-dir`, systemd's
+//! `$RUNTIME_DIRECTORY`, or `/run/harborshield`. On a read-only root
+//! filesystem, the data directory needs a writable volume and the runtime
+//! directory a tmpfs.
+
+use std::fs::DirBuilder;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Runtime directory when neither `--runtime-dir` nor systemd names one
+pub const DEFAULT_RUNTIME_DIR: &str = "/run/harborshield";
+
+const WORLD_WRITABLE: u32 = 0o002;
+const GROUP_WRITABLE: u32 = 0o020;
+const STICKY: u32 = 0o1000;
+
+fn refuse(dir: &Path, reason: &str) -> Error {
+    Error::new(
+        ErrorKind::PermissionDenied,
+        format!("{} {}", dir.display(), reason),
+    )
+}
+
+pub(crate) fn euid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() }
+}
+
+/// Check that `dir` (canonical) can be trusted by a process running as
+/// `uid`
+pub fn check(dir: &Path, uid: u32) -> Result<()> {
+    let metadata = std::fs::metadata(dir)?;
+    if !metadata.is_dir() {
+        return Err(refuse(dir, "is not a directory"));
+    }
+    if metadata.uid() != uid && metadata.uid() != 0 {
+        return Err(refuse(
+            dir,
+            &format!(
+                "is owned by uid {}, not by uid {} or root",
+                metadata.uid(),
+                uid
+            ),
+        ));
+    }
+    let mode = metadata.permissions().mode();
+    if mode & WORLD_WRITABLE != 0 {
+        return Err(refuse(dir, "is writable by every user"));
+    }
+    if mode & GROUP_WRITABLE != 0 {
+        warn!(
+            "{} is group-writable; members of its group can change the firewall rules",
+            dir.display()
+        );
+    }
+
+    for parent in dir.ancestors().skip(1) {
+        let mode = std::fs::metadata(parent)?.permissions().mode();
+        if mode & WORLD_WRITABLE != 0 && mode & STICKY == 0 {
+            return Err(refuse(
+                dir,
+                &format!(
+                    "is inside {}, which every user can write to",
+                    parent.display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Create `dir` if it is missing and check it, returning it canonical
+pub fn prepare(dir: &Path) -> Result<PathBuf> {
+    if !dir.exists() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    let dir = dir.canonicalize()?;
+    check(&dir, euid())?;
+    Ok(dir)
+}
+
+/// Prepare the data directory, which must also be writable
+pub fn prepare_data_dir(dir: &Path) -> Result<PathBuf> {
+    let dir = prepare(dir)?;
+    // Writing a probe also catches a read-only mount, which permissions don't
+    let probe = dir.join(format!(".harborshield-probe-{}", std::process::id()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            std::fs::remove_file(&probe)?;
+            Ok(dir)
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EROFS) => Err(refuse(
+            &dir,
+            "is on a read-only filesystem; give --data-dir a writable volume",
+        )),
+        Err(e) => Err(Error::new(
+            e.kind(),
+            format!("{} is not writable: {}", dir.display(), e),
+        )),
+    }
+}
+
+/// The runtime directory to use: `explicit`, then systemd's, then the
+/// default
+pub fn runtime_dir(explicit: Option<&Path>) -> PathBuf {
+    if let Some(dir) = explicit {
+        return dir.to_path_buf();
+    }
+    // systemd lists one directory per RuntimeDirectory= entry
+    std::env::var("RUNTIME_DIRECTORY")
+        .ok()
+        .and_then(|dirs| dirs.split(':').next().map(PathBuf::from))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_RUNTIME_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let root = tempfile::tempdir().unwrap();
+        let uid = std::fs::metadata(root.path()).unwrap().uid();
+        let set_mode = |path: &Path, mode| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+
+        let dir = prepare(&root.path().join("state/harborshield")).unwrap();
+        assert_eq!(
+            std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        assert!(check(&dir, uid).is_ok());
+        // Owned by someone else, unless that is root
+        if uid != 0 {
+            assert!(check(&dir, uid + 1).is_err());
+        }
+
+        set_mode(&dir, 0o777);
+        assert!(check(&dir, uid).is_err());
+        set_mode(&dir, 0o700);
+
+        // A parent other users can rename it out of
+        set_mode(&root.path().join("state"), 0o777);
+        let err = check(&dir, uid).unwrap_err();
+        assert!(
+            err.to_string().contains("every user can write to"),
+            "{}",
+            err
+        );
+        set_mode(&root.path().join("state"), 0o1777);
+        assert!(check(&dir, uid).is_ok());
+
+        let file = root.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(check(&file, uid).is_err());
+
+        assert_eq!(
+            runtime_dir(Some(Path::new("/tmp/hs"))),
+            PathBuf::from("/tmp/hs")
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Keeping the sets of `host: <name>` output rules in line with DNS.
+//!
+//! Every chain built with hostname rules registers one target per rule and
+//! family (see [`harborshield_core::dns::hostname`]), and its sets are
+//! filled from the last answer recorded for each name. The refresher looks
+//! each name up once per its shortest refresh interval and, when the answer
+//! changed, replaces the contents of the sets matching it in a single
+//! transaction. A failed lookup keeps the addresses already in place.
+
+use super::LookupPolicy;
+use super::{Resolve, lookup};
+use crate::Result;
+use crate::nftables::hostname as nft_hostname;
+pub use harborshield_core::dns::hostname::{HostTarget, Query, parse_hostname};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// How soon a failed lookup is retried, unless the refresh interval is shorter
+const RETRY: Duration = Duration::from_secs(30);
+/// How often the refresher checks for due names
+const TICK: Duration = Duration::from_secs(1);
+
+/// Woken when a name without an answer yet is registered
+static REGISTERED: Notify = Notify::const_new();
+/// Registered targets, by family (true for IPv6) and set name
+static TARGETS: LazyLock<Mutex<BTreeMap<(bool, String), HostTarget>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+/// The last answer for each name and policy
+static RESOLVED: LazyLock<Mutex<HashMap<Query, Vec<IpAddr>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Keep the answer to `query`, returning the one before it
+pub fn record(query: Query, addrs: Vec<IpAddr>) -> Option<Vec<IpAddr>> {
+    RESOLVED
+        .lock()
+        .ok()
+        .and_then(|mut resolved| resolved.insert(query, addrs))
+}
+
+/// The last addresses `hostname` resolved to under `policy`
+pub fn resolved(hostname: &str, policy: LookupPolicy) -> Vec<IpAddr> {
+    RESOLVED
+        .lock()
+        .ok()
+        .and_then(|resolved| resolved.get(&(hostname.to_string(), policy)).cloned())
+        .unwrap_or_default()
+}
+
+/// Replace the targets of `chain` in one family, returning the sets no
+/// longer used
+pub fn register_chain(ipv6: bool, chain: &str, targets: Vec<HostTarget>) -> Vec<String> {
+    let Ok(mut registered) = TARGETS.lock() else {
+        return Vec::new();
+    };
+    let dropped: Vec<String> = registered
+        .extract_if(.., |(family, _), target| {
+            *family == ipv6 && target.chain == chain && !targets.iter().any(|t| t.set == target.set)
+        })
+        .map(|((_, set), _)| set)
+        .collect();
+
+    let unresolved = targets.iter().any(|target| {
+        RESOLVED
+            .lock()
+            .is_ok_and(|resolved| !resolved.contains_key(&target.query()))
+    });
+    for target in targets {
+        registered.insert((ipv6, target.set.clone()), target);
+    }
+    if unresolved {
+        REGISTERED.notify_one();
+    }
+    dropped
+}
+
+/// Forget the targets of a chain being deleted, returning their sets
+pub fn unregister_chain(ipv6: bool, chain: &str) -> Vec<String> {
+    register_chain(ipv6, chain, Vec::new())
+}
+
+/// Every registered target
+pub fn targets() -> Vec<HostTarget> {
+    TARGETS
+        .lock()
+        .map(|targets| targets.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Forget the target of one set
+pub fn unregister_set(ipv6: bool, set: &str) {
+    if let Ok(mut targets) = TARGETS.lock() {
+        targets.remove(&(ipv6, set.to_string()));
+    }
+}
+
+/// Re-resolves registered names as they fall due
+pub struct HostRefresher {
+    resolver: Arc<dyn Resolve>,
+    /// When each name is looked up next
+    due: HashMap<Query, Instant>,
+}
+
+impl HostRefresher {
+    pub fn new(resolver: Arc<dyn Resolve>) -> Self {
+        Self {
+            resolver,
+            due: HashMap::new(),
+        }
+    }
+
+    /// Look up every due name, updating the sets whose addresses changed.
+    /// Returns how many sets were updated
+    pub async fn run_once(&mut self) -> Result<usize> {
+        let now = Instant::now();
+        let targets = targets();
+
+        // A name shared by several rules follows the most eager of them
+        let mut intervals: HashMap<Query, u32> = HashMap::new();
+        for target in &targets {
+            let interval = intervals.entry(target.query()).or_insert(target.refresh);
+            *interval = (*interval).min(target.refresh);
+        }
+        self.due.retain(|query, _| intervals.contains_key(query));
+
+        let mut updated = 0;
+        for (query, refresh) in intervals {
+            if self.due.get(&query).is_some_and(|due| *due > now) {
+                continue;
+            }
+            let refresh = Duration::from_secs(refresh as u64);
+            let (hostname, policy) = &query;
+
+            let mut addrs = match lookup(self.resolver.as_ref(), hostname, *policy).await {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    warn!(
+                        "Failed to resolve {}, keeping its previous addresses: {}",
+                        hostname, e
+                    );
+                    self.due.insert(query, now + refresh.min(RETRY));
+                    continue;
+                }
+            };
+            addrs.sort();
+            addrs.dedup();
+            self.due.insert(query.clone(), now + refresh);
+
+            let previous = record(query.clone(), addrs.clone());
+            if previous.as_ref() == Some(&addrs) {
+                continue;
+            }
+            match &previous {
+                Some(previous) => info!(
+                    "{} now resolves to {:?} (was {:?})",
+                    hostname, addrs, previous
+                ),
+                None => debug!("{} resolves to {:?}", hostname, addrs),
+            }
+
+            for target in targets.iter().filter(|t| t.query() == query) {
+                let family: Vec<IpAddr> = addrs
+                    .iter()
+                    .copied()
+                    .filter(|addr| addr.is_ipv6() == target.ipv6)
+                    .collect();
+                match nft_hostname::replace_elements(target.ipv6, &target.set, &family).await {
+                    Ok(()) => updated += 1,
+                    Err(e) => {
+                        // The chain went away without being unregistered
+                        debug!("Dropping hostname set {}: {}", target.set, e);
+                        unregister_set(target.ipv6, &target.set);
+                    }
+                }
+            }
+        }
+
+        Ok(updated)
+    }
+}
+
+/// Run the refresher until `cancel` fires
+pub async fn run_refresher(resolver: Arc<dyn Resolve>, cancel: CancellationToken) {
+    let mut refresher = HostRefresher::new(resolver);
+    let mut ticker = tokio::time::interval(TICK);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = ticker.tick() => {}
+            _ = REGISTERED.notified() => {}
+        }
+        if let Err(e) = refresher.run_once().await {
+            warn!("Hostname refresh pass failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(ipv6: bool, chain: &str, set: &str) -> HostTarget {
+        HostTarget {
+            ipv6,
+            chain: chain.to_string(),
+            set: set.to_string(),
+            hostname: "api.stripe.com".to_string(),
+            policy: LookupPolicy::default(),
+            refresh: 300,
+        }
+    }
+
+    #[test]
+    fn test_register_chain() {
+        let chain = "hs-test-register";
+        let sets = |ipv6| -> Vec<String> {
+            targets()
+                .into_iter()
+                .filter(|t| t.chain == chain && t.ipv6 == ipv6)
+                .map(|t| t.set)
+                .collect()
+        };
+        let first = format!("{}-host-1", chain);
+        let second = format!("{}-host-2", chain);
+
+        let dropped = register_chain(
+            false,
+            chain,
+            vec![target(false, chain, &first), target(false, chain, &second)],
+        );
+        assert!(dropped.is_empty());
+        register_chain(true, chain, vec![target(true, chain, &first)]);
+
+        // A rebuild without the second rule hands its set back for deletion
+        let dropped = register_chain(false, chain, vec![target(false, chain, &first)]);
+        assert_eq!(dropped, vec![second]);
+        assert_eq!(sets(false), vec![first.clone()]);
+        assert_eq!(sets(true), vec![first.clone()]);
+
+        assert_eq!(unregister_chain(true, chain), vec![first.clone()]);
+        assert!(sets(true).is_empty());
+        assert_eq!(sets(false), vec![first]);
+        unregister_chain(false, chain);
+    }
+}
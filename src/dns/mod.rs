@@ -0,0 +1,157 @@
+//! DNS lookups used by hostname-based firewall rules and the sinkhole.
+
+pub mod hostname;
+pub mod rdns;
+pub mod sinkhole;
+
+use crate::{Error, Result};
+use async_trait::async_trait;
+use hickory_resolver::TokioResolver;
+use hickory_resolver::proto::rr::RData;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+pub use harborshield_core::dns::{DEFAULT_REFRESH, LookupFamily, LookupPolicy};
+
+static FAMILY: AtomicU8 = AtomicU8::new(LookupFamily::Both as u8);
+static REQUIRE_BOTH: AtomicBool = AtomicBool::new(false);
+static REFRESH: AtomicU32 = AtomicU32::new(DEFAULT_REFRESH);
+
+/// Set the policy of rules that don't choose their own
+pub fn set_default_policy(policy: LookupPolicy) {
+    FAMILY.store(policy.family as u8, Ordering::Relaxed);
+    REQUIRE_BOTH.store(policy.require_both, Ordering::Relaxed);
+}
+
+/// The policy given by `--dns-family` and `--dns-require-both`
+pub fn default_policy() -> LookupPolicy {
+    let family = match FAMILY.load(Ordering::Relaxed) {
+        0 => LookupFamily::A,
+        1 => LookupFamily::Aaaa,
+        _ => LookupFamily::Both,
+    };
+    LookupPolicy {
+        family,
+        require_both: REQUIRE_BOTH.load(Ordering::Relaxed),
+    }
+}
+
+/// Set the refresh interval of hostname rules that don't choose their own
+pub fn set_default_refresh(seconds: u32) {
+    REFRESH.store(seconds.max(1), Ordering::Relaxed);
+}
+
+/// The interval given by `--dns-refresh`, in seconds
+pub fn default_refresh() -> u32 {
+    REFRESH.load(Ordering::Relaxed)
+}
+
+/// Minimal resolver interface so lookups can be faked in tests
+#[async_trait]
+pub trait Resolve: Send + Sync {
+    /// PTR lookup, returning hostnames without the trailing dot
+    async fn reverse(&self, ip: IpAddr) -> Result<Vec<String>>;
+
+    /// A lookup, or AAAA with `ipv6`
+    async fn forward(&self, host: &str, ipv6: bool) -> Result<Vec<IpAddr>>;
+}
+
+/// Resolve `host` to the address families `policy` asks for. With both
+/// families, one failing is only an error when `require_both` is set or
+/// the other failed too
+pub async fn lookup(
+    resolver: &dyn Resolve,
+    host: &str,
+    policy: LookupPolicy,
+) -> Result<Vec<IpAddr>> {
+    let families: &[bool] = match policy.family {
+        LookupFamily::A => &[false],
+        LookupFamily::Aaaa => &[true],
+        LookupFamily::Both => &[false, true],
+    };
+
+    let mut addrs = Vec::new();
+    let mut failure = None;
+    for &ipv6 in families {
+        match resolver.forward(host, ipv6).await {
+            Ok(found) if !found.is_empty() => addrs.extend(found),
+            Ok(_) => {
+                failure = Some(Error::network_with_endpoint(
+                    format!("no {} records", if ipv6 { "AAAA" } else { "A" }),
+                    host,
+                ))
+            }
+            Err(e) => failure = Some(e),
+        }
+    }
+
+    match failure {
+        Some(e) if addrs.is_empty() || policy.require_both => Err(e),
+        _ => Ok(addrs),
+    }
+}
+
+/// Resolver backed by the host's `/etc/resolv.conf`
+#[derive(Clone)]
+pub struct SystemResolver {
+    inner: TokioResolver,
+}
+
+impl SystemResolver {
+    pub fn new() -> Result<Self> {
+        let mut builder = TokioResolver::builder_tokio()
+            .map_err(|e| Error::network(format!("Failed to read resolver configuration: {}", e)))?;
+        if crate::low_memory::enabled() {
+            builder.options_mut().cache_size = 0;
+        }
+        let inner = builder
+            .build()
+            .map_err(|e| Error::network(format!("Failed to build DNS resolver: {}", e)))?;
+
+        Ok(Self { inner })
+    }
+
+    pub fn shared() -> Result<Arc<dyn Resolve>> {
+        Ok(Arc::new(Self::new()?))
+    }
+}
+
+#[async_trait]
+impl Resolve for SystemResolver {
+    async fn reverse(&self, ip: IpAddr) -> Result<Vec<String>> {
+        let lookup = self
+            .inner
+            .reverse_lookup(ip)
+            .await
+            .map_err(|e| Error::network_with_endpoint(e.to_string(), ip.to_string()))?;
+
+        Ok(lookup
+            .answers()
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::PTR(name) => Some(name.to_string().trim_end_matches('.').to_lowercase()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn forward(&self, host: &str, ipv6: bool) -> Result<Vec<IpAddr>> {
+        let lookup = if ipv6 {
+            self.inner.ipv6_lookup(host).await
+        } else {
+            self.inner.ipv4_lookup(host).await
+        }
+        .map_err(|e| Error::network_with_endpoint(e.to_string(), host))?;
+
+        Ok(lookup
+            .answers()
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::A(addr) => Some(IpAddr::V4(addr.0)),
+                RData::AAAA(addr) => Some(IpAddr::V6(addr.0)),
+                _ => None,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,536 @@
+//! Forward-confirmed reverse DNS (FCrDNS) verification for inbound rules.
+//!
+//! Rules with `rdns` patterns don't know their allowed sources up front. The
+//! container chain instead records unknown sources hitting a mapped port in a
+//! short-lived "pending" set; this module periodically drains those sets,
+//! checks each address (PTR lookup, pattern match, then A/AAAA lookup of the
+//! returned name must contain the address again) and adds verified sources to
+//! a "verified" set whose element timeout acts as the cache TTL. Which records
+//! the forward lookup asks for follows `rdns_family` and `rdns_require_both`,
+//! or `--dns-family` and `--dns-require-both` when the rule doesn't set them.
+//!
+//! At most [`CONCURRENCY`] sources are verified at once. Sources that fail
+//! verification are remembered for `rdns_negative_ttl` seconds before being
+//! checked again, and kept in the chain's rejected set meanwhile so the packet
+//! path doesn't queue them again. When the lookups themselves fail,
+//! because the resolver is down or times out, `rdns_on_failure` decides what
+//! happens to the source:
+//!
+//! - `closed` (default): it is refused like an unverified source
+//! - `keep`: sources verified before stay allowed, new ones are refused
+//! - `open`: it is allowed
+//!
+//! Sources allowed on a failure only stay for the negative TTL, so they are
+//! verified properly once DNS answers again.
+
+use crate::Result;
+use crate::dns::{LookupPolicy, Resolve};
+use crate::nftables::rdns as nft_rdns;
+use futures::StreamExt;
+pub use harborshield_core::dns::rdns::{
+    DEFAULT_RDNS_NEGATIVE_TTL, DEFAULT_RDNS_TTL, HostnamePattern, RdnsFailurePolicy,
+};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Verifications in flight at once
+pub const CONCURRENCY: usize = 16;
+
+/// Perform FCrDNS for `ip`, returning the verified hostname on success
+pub async fn verify(
+    resolver: &dyn Resolve,
+    ip: IpAddr,
+    patterns: &[HostnamePattern],
+    policy: LookupPolicy,
+) -> Result<Option<String>> {
+    for hostname in resolver.reverse(ip).await? {
+        if !patterns.iter().any(|p| p.matches(&hostname)) {
+            debug!(
+                "PTR {} for {} does not match any rdns pattern",
+                hostname, ip
+            );
+            continue;
+        }
+
+        match super::lookup(resolver, &hostname, policy).await {
+            Ok(addrs) if addrs.contains(&ip) => return Ok(Some(hostname)),
+            Ok(_) => debug!("Forward lookup of {} does not confirm {}", hostname, ip),
+            Err(e) => debug!("Forward lookup of {} failed: {}", hostname, e),
+        }
+    }
+
+    Ok(None)
+}
+
+/// A container chain with rdns-gated inbound rules
+#[derive(Debug, Clone)]
+pub struct RdnsTarget {
+    pub chain: String,
+    pub patterns: Vec<HostnamePattern>,
+    pub ttl: u32,
+    pub negative_ttl: u32,
+    pub on_failure: RdnsFailurePolicy,
+    pub lookup: LookupPolicy,
+}
+
+/// Chains whose pending sets the verifier should drain
+#[derive(Debug, Default)]
+pub struct RdnsRegistry {
+    targets: Mutex<HashMap<String, RdnsTarget>>,
+}
+
+impl RdnsRegistry {
+    pub fn register(&self, target: RdnsTarget) {
+        if let Ok(mut targets) = self.targets.lock() {
+            targets.insert(target.chain.clone(), target);
+        }
+    }
+
+    pub fn unregister(&self, chain: &str) -> Option<RdnsTarget> {
+        self.targets.lock().ok()?.remove(chain)
+    }
+
+    pub fn targets(&self) -> Vec<RdnsTarget> {
+        self.targets
+            .lock()
+            .map(|t| t.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Outcome of checking one pending source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Allowed for the rule's TTL
+    Verified,
+    /// Allowed for the negative TTL because DNS failed
+    Fallback,
+    /// Refused until the negative TTL has passed
+    Rejected,
+}
+
+/// Drains pending sets and promotes verified sources
+pub struct RdnsVerifier {
+    registry: Arc<RdnsRegistry>,
+    resolver: Arc<dyn Resolve>,
+    /// Negative cache: when each source may be checked again, per chain
+    rejected: HashMap<(String, IpAddr), Instant>,
+    /// Sources each chain has verified, for the `keep` policy
+    known: HashSet<(String, IpAddr)>,
+    /// Chains whose lookups are currently failing
+    failing: HashSet<String>,
+}
+
+impl RdnsVerifier {
+    pub fn new(registry: Arc<RdnsRegistry>, resolver: Arc<dyn Resolve>) -> Self {
+        Self {
+            registry,
+            resolver,
+            rejected: HashMap::new(),
+            known: HashSet::new(),
+            failing: HashSet::new(),
+        }
+    }
+
+    fn is_rejected(&self, chain: &str, ip: IpAddr) -> bool {
+        self.rejected
+            .get(&(chain.to_string(), ip))
+            .is_some_and(|until| *until > Instant::now())
+    }
+
+    /// Apply the outcome of verifying `ip` for `target`, then decide
+    pub fn decide(
+        &mut self,
+        target: &RdnsTarget,
+        ip: IpAddr,
+        outcome: &Result<Option<String>>,
+    ) -> Decision {
+        let key = (target.chain.clone(), ip);
+        let retry_at = Instant::now() + Duration::from_secs(target.negative_ttl as u64);
+        match outcome {
+            Ok(Some(hostname)) => {
+                info!("Verified {} as {} for chain {}", ip, hostname, target.chain);
+                self.known.insert(key);
+                Decision::Verified
+            }
+            Ok(None) => {
+                debug!("{} failed FCrDNS for chain {}", ip, target.chain);
+                self.known.remove(&key);
+                self.rejected.insert(key, retry_at);
+                Decision::Rejected
+            }
+            Err(e) => {
+                debug!("Lookups for {} failed: {}", ip, e);
+                crate::server::increment_rdns_lookup_failures(target.on_failure.as_str());
+                let allow = match target.on_failure {
+                    RdnsFailurePolicy::Open => true,
+                    RdnsFailurePolicy::Keep => self.known.contains(&key),
+                    RdnsFailurePolicy::Closed => false,
+                };
+                if allow {
+                    Decision::Fallback
+                } else {
+                    self.rejected.insert(key, retry_at);
+                    Decision::Rejected
+                }
+            }
+        }
+    }
+
+    /// Warn when a chain's lookups start failing and note when they recover
+    fn track_failures(&mut self, target: &RdnsTarget, failures: usize, answered: usize) {
+        if failures > 0 && self.failing.insert(target.chain.clone()) {
+            warn!(
+                "DNS lookups for rdns rules of chain {} are failing, applying the '{}' failure policy",
+                target.chain,
+                target.on_failure.as_str()
+            );
+        } else if failures == 0 && answered > 0 && self.failing.remove(&target.chain) {
+            info!(
+                "DNS lookups for rdns rules of chain {} recovered",
+                target.chain
+            );
+        }
+        crate::server::set_rdns_failing_chains(self.failing.len() as u64);
+    }
+
+    /// Verify the `pending` sources of `target` not in the negative cache, at
+    /// most [`CONCURRENCY`] at once
+    pub async fn verify_pending(
+        &self,
+        target: &RdnsTarget,
+        pending: Vec<IpAddr>,
+    ) -> Vec<(IpAddr, Result<Option<String>>)> {
+        let unchecked: Vec<IpAddr> = pending
+            .into_iter()
+            .filter(|ip| !self.is_rejected(&target.chain, *ip))
+            .collect();
+        futures::stream::iter(unchecked)
+            .map(|ip| {
+                let resolver = self.resolver.clone();
+                async move {
+                    let outcome =
+                        verify(resolver.as_ref(), ip, &target.patterns, target.lookup).await;
+                    (ip, outcome)
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// One verification pass over all registered chains
+    pub async fn run_once(&mut self) -> Result<usize> {
+        let now = Instant::now();
+        self.rejected.retain(|_, until| *until > now);
+
+        let targets = self.registry.targets();
+        let chains: HashSet<&str> = targets.iter().map(|t| t.chain.as_str()).collect();
+        self.known
+            .retain(|(chain, _)| chains.contains(chain.as_str()));
+        self.failing.retain(|chain| chains.contains(chain.as_str()));
+
+        let mut verified = 0;
+        for target in &targets {
+            let pending = match nft_rdns::list_pending(&target.chain).await {
+                Ok(pending) => pending,
+                Err(e) => {
+                    debug!("Cannot list pending rdns set for {}: {}", target.chain, e);
+                    continue;
+                }
+            };
+
+            let outcomes = self.verify_pending(target, pending).await;
+            let mut accepted = Vec::new();
+            let mut fallback = Vec::new();
+            let mut rejected = Vec::new();
+            let (mut failures, mut answered) = (0, 0);
+            for (ip, outcome) in outcomes {
+                if outcome.is_err() {
+                    failures += 1;
+                } else {
+                    answered += 1;
+                }
+                match self.decide(target, ip, &outcome) {
+                    Decision::Verified => accepted.push(ip),
+                    Decision::Fallback => fallback.push(ip),
+                    Decision::Rejected => rejected.push(ip),
+                }
+            }
+            self.track_failures(target, failures, answered);
+
+            if !accepted.is_empty() {
+                nft_rdns::add_verified(&target.chain, &accepted, target.ttl).await?;
+                verified += accepted.len();
+            }
+            if !fallback.is_empty() {
+                nft_rdns::add_verified(&target.chain, &fallback, target.negative_ttl.max(1))
+                    .await?;
+            }
+            if !rejected.is_empty() {
+                nft_rdns::add_rejected(&target.chain, &rejected, target.negative_ttl.max(1))
+                    .await?;
+            }
+        }
+
+        Ok(verified)
+    }
+}
+
+/// Run the verifier every `interval` until `cancel` fires
+pub async fn run_verifier(
+    registry: Arc<RdnsRegistry>,
+    resolver: Arc<dyn Resolve>,
+    interval: Duration,
+    cancel: CancellationToken,
+) {
+    let mut verifier = RdnsVerifier::new(registry, resolver);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = ticker.tick() => {
+                if let Err(e) = verifier.run_once().await {
+                    warn!("rdns verification pass failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use async_trait::async_trait;
+
+    struct FakeResolver {
+        ptr: HashMap<IpAddr, Vec<String>>,
+        a: HashMap<String, Vec<IpAddr>>,
+    }
+
+    #[async_trait]
+    impl Resolve for FakeResolver {
+        async fn reverse(&self, ip: IpAddr) -> Result<Vec<String>> {
+            Ok(self.ptr.get(&ip).cloned().unwrap_or_default())
+        }
+
+        async fn forward(&self, host: &str, ipv6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self
+                .a
+                .get(host)
+                .into_iter()
+                .flatten()
+                .filter(|ip| ip.is_ipv6() == ipv6)
+                .copied()
+                .collect())
+        }
+    }
+
+    fn googlebot() -> Vec<HostnamePattern> {
+        vec!["*.googlebot.com".parse().unwrap()]
+    }
+
+    fn target(on_failure: RdnsFailurePolicy) -> RdnsTarget {
+        RdnsTarget {
+            chain: "hs-web-abc".to_string(),
+            patterns: googlebot(),
+            ttl: DEFAULT_RDNS_TTL,
+            negative_ttl: DEFAULT_RDNS_NEGATIVE_TTL,
+            on_failure,
+            lookup: LookupPolicy::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_forward_confirmed() {
+        let ip: IpAddr = "66.249.66.1".parse().unwrap();
+        let host = "crawl-66-249-66-1.googlebot.com".to_string();
+        let resolver = FakeResolver {
+            ptr: HashMap::from([(ip, vec![host.clone()])]),
+            a: HashMap::from([(host.clone(), vec![ip])]),
+        };
+
+        assert_eq!(
+            verify(&resolver, ip, &googlebot(), LookupPolicy::default())
+                .await
+                .unwrap(),
+            Some(host)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_spoofed_ptr() {
+        // PTR claims googlebot but the forward lookup points elsewhere
+        let ip: IpAddr = "203.0.113.9".parse().unwrap();
+        let host = "crawl-1.googlebot.com".to_string();
+        let resolver = FakeResolver {
+            ptr: HashMap::from([(ip, vec![host.clone()])]),
+            a: HashMap::from([(host, vec!["66.249.66.1".parse().unwrap()])]),
+        };
+
+        assert_eq!(
+            verify(&resolver, ip, &googlebot(), LookupPolicy::default())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_unmatched_hostname() {
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+        let host = "crawler.example.net".to_string();
+        let resolver = FakeResolver {
+            ptr: HashMap::from([(ip, vec![host.clone()])]),
+            a: HashMap::from([(host, vec![ip])]),
+        };
+
+        assert_eq!(
+            verify(&resolver, ip, &googlebot(), LookupPolicy::default())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_lookup_policy() {
+        use crate::dns::LookupFamily;
+
+        // Only an A record behind the PTR name
+        let ip: IpAddr = "66.249.66.1".parse().unwrap();
+        let host = "crawl-66-249-66-1.googlebot.com".to_string();
+        let resolver = FakeResolver {
+            ptr: HashMap::from([(ip, vec![host.clone()])]),
+            a: HashMap::from([(host.clone(), vec![ip])]),
+        };
+        let policy = |family, require_both| LookupPolicy {
+            family,
+            require_both,
+        };
+
+        let patterns = googlebot();
+        let verified = |policy| verify(&resolver, ip, &patterns, policy);
+        assert_eq!(
+            verified(policy(LookupFamily::Both, false)).await.unwrap(),
+            Some(host.clone())
+        );
+        assert_eq!(
+            verified(policy(LookupFamily::A, true)).await.unwrap(),
+            Some(host)
+        );
+        assert_eq!(
+            verified(policy(LookupFamily::Aaaa, false)).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            verified(policy(LookupFamily::Both, true)).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = RdnsRegistry::default();
+        registry.register(target(RdnsFailurePolicy::Closed));
+        assert_eq!(registry.targets().len(), 1);
+        assert!(registry.unregister("hs-web-abc").is_some());
+        assert!(registry.targets().is_empty());
+    }
+
+    #[test]
+    fn test_failure_policies() {
+        let verifier = || {
+            RdnsVerifier::new(
+                Arc::new(RdnsRegistry::default()),
+                Arc::new(FakeResolver {
+                    ptr: HashMap::new(),
+                    a: HashMap::new(),
+                }),
+            )
+        };
+        let ip: IpAddr = "66.249.66.1".parse().unwrap();
+        let other: IpAddr = "66.249.66.2".parse().unwrap();
+        let verified = Ok(Some("crawl-1.googlebot.com".to_string()));
+        let failed = || Err(Error::network("SERVFAIL"));
+
+        let closed = target(RdnsFailurePolicy::Closed);
+        let mut v = verifier();
+        assert_eq!(v.decide(&closed, ip, &verified), Decision::Verified);
+        assert_eq!(v.decide(&closed, ip, &failed()), Decision::Rejected);
+        assert!(v.is_rejected(&closed.chain, ip));
+
+        // Only sources verified before survive an outage
+        let keep = target(RdnsFailurePolicy::Keep);
+        let mut v = verifier();
+        v.decide(&keep, ip, &verified);
+        assert_eq!(v.decide(&keep, ip, &failed()), Decision::Fallback);
+        assert_eq!(v.decide(&keep, other, &failed()), Decision::Rejected);
+        assert!(!v.is_rejected(&keep.chain, ip));
+
+        let open = target(RdnsFailurePolicy::Open);
+        let mut v = verifier();
+        assert_eq!(v.decide(&open, other, &failed()), Decision::Fallback);
+        // A definite answer is refused whatever the policy
+        assert_eq!(v.decide(&open, other, &Ok(None)), Decision::Rejected);
+        assert!(v.is_rejected(&open.chain, other));
+    }
+
+    /// Counts the reverse lookups in flight
+    #[derive(Default)]
+    struct CountingResolver {
+        in_flight: std::sync::atomic::AtomicUsize,
+        peak: std::sync::atomic::AtomicUsize,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Resolve for CountingResolver {
+        async fn reverse(&self, _ip: IpAddr) -> Result<Vec<String>> {
+            use std::sync::atomic::Ordering;
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(Vec::new())
+        }
+
+        async fn forward(&self, _host: &str, _ipv6: bool) -> Result<Vec<IpAddr>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_pending_bounded() {
+        use std::sync::atomic::Ordering;
+
+        let resolver = Arc::new(CountingResolver::default());
+        let mut v = RdnsVerifier::new(Arc::new(RdnsRegistry::default()), resolver.clone());
+        let target = target(RdnsFailurePolicy::Closed);
+        let pending: Vec<IpAddr> = (0..200u32)
+            .map(|i| IpAddr::from(std::net::Ipv4Addr::from(0xc000_0200 + i)))
+            .collect();
+
+        // Recently rejected sources aren't looked up again
+        v.decide(&target, pending[0], &Ok(None));
+        v.decide(&target, pending[1], &Ok(None));
+
+        let outcomes = v.verify_pending(&target, pending).await;
+        assert_eq!(outcomes.len(), 198);
+        assert!(
+            outcomes
+                .iter()
+                .all(|(ip, _)| !v.is_rejected(&target.chain, *ip))
+        );
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 198);
+        assert!(resolver.peak.load(Ordering::SeqCst) <= CONCURRENCY);
+    }
+}
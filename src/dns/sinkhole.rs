@@ -0,0 +1,226 @@
+//! `--sinkhole`: domain blocklists enforced for every container.
+//!
+//! Lists are hosts files (`0.0.0.0 tracker.example`) or adblock filters
+//! (`||tracker.example^`), or plain one-name-per-line; other lines, such
+//! as cosmetic filters, are skipped and `@@` exceptions take a name out.
+//! The daemon resolves each listed name to its IPv4 addresses and keeps
+//! those in a set whose members the harborshield chain drops as
+//! destinations of forwarded traffic (see [`crate::nftables::sinkhole`]).
+//!
+//! This is coarse: only names in the lists are looked up, not their
+//! subdomains, and an address shared with an unlisted name (a CDN) is
+//! blocked for both. Lists are re-read, and their names resolved again,
+//! every [`REFRESH`]. A name that fails to resolve keeps its addresses from
+//! the previous pass.
+
+use super::hostname::parse_hostname;
+use super::{LookupFamily, LookupPolicy, Resolve, lookup};
+use crate::nftables::sinkhole as nft_sinkhole;
+use futures::StreamExt;
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// How often the lists are re-read and resolved
+pub const REFRESH: Duration = Duration::from_secs(3600);
+
+/// Lookups in flight at once
+const CONCURRENCY: usize = 32;
+
+/// The names a list blocks. Exceptions (`@@`) are removed later, so they
+/// apply across lists
+fn parse_line(line: &str) -> Option<(bool, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['#', '!', '[']) {
+        return None;
+    }
+    let (exception, line) = match line.strip_prefix("@@") {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let name = if let Some(rule) = line.strip_prefix("||") {
+        // Only whole-domain filters, not paths or options
+        rule.strip_suffix('^')?
+    } else {
+        // A `#` opening a word starts a comment; one inside a word is an
+        // adblock element filter
+        let line = match line.find('#') {
+            Some(i) if line[..i].ends_with(char::is_whitespace) => &line[..i],
+            Some(_) => return None,
+            None => line,
+        };
+        let mut words = line.split_whitespace();
+        let first = words.next()?;
+        match first.parse::<IpAddr>() {
+            // A hosts file line; later names on it are aliases
+            Ok(_) => words.next()?,
+            Err(_) if words.next().is_none() => first,
+            Err(_) => return None,
+        }
+    };
+    parse_hostname(name).map(|name| (exception, name))
+}
+
+/// The names blocked by `text`, less those it makes exceptions of
+pub fn parse_list(text: &str) -> BTreeSet<String> {
+    let mut blocked = BTreeSet::new();
+    let mut allowed = BTreeSet::new();
+    for (exception, name) in text.lines().filter_map(parse_line) {
+        if exception {
+            allowed.insert(name);
+        } else {
+            blocked.insert(name);
+        }
+    }
+    &blocked - &allowed
+}
+
+/// Read a list
+pub fn load(path: &Path) -> std::io::Result<BTreeSet<String>> {
+    Ok(parse_list(&std::fs::read_to_string(path)?))
+}
+
+/// Resolves the listed names and keeps the set in line with them
+pub struct Sinkhole {
+    lists: Vec<PathBuf>,
+    resolver: Arc<dyn Resolve>,
+    /// The last names read from each list, kept when it can't be read
+    names: HashMap<PathBuf, BTreeSet<String>>,
+    /// The last addresses of each name
+    resolved: HashMap<String, Vec<Ipv4Addr>>,
+}
+
+impl Sinkhole {
+    pub fn new(lists: Vec<PathBuf>, resolver: Arc<dyn Resolve>) -> Self {
+        Self {
+            lists,
+            resolver,
+            names: HashMap::new(),
+            resolved: HashMap::new(),
+        }
+    }
+
+    /// Re-read the lists and resolve their names, returning the addresses
+    /// to block
+    pub async fn resolve(&mut self) -> BTreeSet<Ipv4Addr> {
+        for list in &self.lists {
+            match load(list) {
+                Ok(names) => {
+                    self.names.insert(list.clone(), names);
+                }
+                Err(e) => warn!(
+                    "Failed to read sinkhole list {}, keeping its previous names: {}",
+                    list.display(),
+                    e
+                ),
+            }
+        }
+        let names: BTreeSet<String> = self.names.values().flatten().cloned().collect();
+        self.resolved.retain(|name, _| names.contains(name));
+
+        let policy = LookupPolicy {
+            family: LookupFamily::A,
+            require_both: false,
+        };
+        let mut lookups = futures::stream::iter(names)
+            .map(|name| {
+                let resolver = self.resolver.clone();
+                async move {
+                    let result = lookup(resolver.as_ref(), &name, policy).await;
+                    (name, result)
+                }
+            })
+            .buffer_unordered(CONCURRENCY);
+        while let Some((name, result)) = lookups.next().await {
+            match result {
+                Ok(addrs) => {
+                    let addrs = addrs
+                        .into_iter()
+                        .filter_map(|addr| match addr {
+                            // Names already sinkholed upstream answer 0.0.0.0
+                            IpAddr::V4(addr) if !addr.is_unspecified() && !addr.is_loopback() => {
+                                Some(addr)
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    self.resolved.insert(name, addrs);
+                }
+                Err(e) => debug!("Failed to resolve sinkholed {}: {}", name, e),
+            }
+        }
+
+        self.resolved.values().flatten().copied().collect()
+    }
+}
+
+/// Resolve the lists every [`REFRESH`] until `cancel` fires
+pub async fn run_sinkhole(
+    lists: Vec<PathBuf>,
+    resolver: Arc<dyn Resolve>,
+    cancel: CancellationToken,
+) {
+    let mut sinkhole = Sinkhole::new(lists, resolver);
+    let mut ticker = tokio::time::interval(REFRESH);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut previous = None;
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        let addrs = sinkhole.resolve().await;
+        if previous.as_ref() == Some(&addrs) {
+            continue;
+        }
+        let addrs_list: Vec<Ipv4Addr> = addrs.iter().copied().collect();
+        match nft_sinkhole::replace_elements(&addrs_list).await {
+            Ok(()) => {
+                info!(
+                    "Sinkholing {} addresses of {} names",
+                    addrs.len(),
+                    sinkhole.resolved.len()
+                );
+                previous = Some(addrs);
+            }
+            Err(e) => warn!("Failed to update the sinkhole set: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        let names = parse_list(
+            "# hosts\n\
+             0.0.0.0 tracker.example.com alias.example.com\n\
+             127.0.0.1 localhost\n\
+             ::1 ip6-localhost\n\
+             ! adblock\n\
+             [Adblock Plus 2.0]\n\
+             ||Ads.Example.net^\n\
+             ||cdn.example.net^$third-party\n\
+             example.com##.banner\n\
+             telemetry.example.org # inline\n\
+             ||allowed.example.com^\n\
+             @@||allowed.example.com^\n",
+        );
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            [
+                "ads.example.net",
+                "telemetry.example.org",
+                "tracker.example.com"
+            ]
+        );
+    }
+}
@@ -4,8 +4,7 @@ use std::collections::HashMap;
 use tracing::warn;
 
 /// Docker Compose label constants
-pub const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
-pub const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+pub use harborshield_core::{COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL};
 pub const COMPOSE_CONTAINER_NUMBER_LABEL: &str = "com.docker.compose.container-number";
 pub const COMPOSE_DEPENDS_ON_LABEL: &str = "com.docker.compose.depends_on";
 pub const COMPOSE_VERSION_LABEL: &str = "com.docker.compose.version";
@@ -0,0 +1,17 @@
+//! The sensitive ports of `--sensitive-ports`, which the checks in
+//! [`harborshield_core::config::exposure`] are run against.
+
+use std::sync::{LazyLock, RwLock};
+
+pub use harborshield_core::config::exposure::*;
+
+static SENSITIVE_PORTS: LazyLock<RwLock<Vec<u16>>> =
+    LazyLock::new(|| RwLock::new(DEFAULT_SENSITIVE_PORTS.to_vec()));
+
+pub fn set_sensitive_ports(ports: Vec<u16>) {
+    *SENSITIVE_PORTS.write().unwrap() = ports;
+}
+
+pub fn sensitive_ports() -> Vec<u16> {
+    SENSITIVE_PORTS.read().unwrap().clone()
+}
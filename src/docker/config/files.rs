@@ -0,0 +1,183 @@
+//! Rule sets from `--rules-dir`: `<name>.yml` files keyed by container name
+//! or Compose service.
+//!
+//! A container's file is found by its name first, then its service. Its rule
+//! set is laid under the rules label, so the label wins where both set
+//! something (see [`super::profiles::merge`]), and ad-hoc rule sets go over
+//! both. A file alone is enough to manage an enabled container.
+//!
+//! The directory is read once at startup and again whenever a file in it
+//! changes, which the daemon learns of from inotify.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// The contents of each file, by stem
+static FILES: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+
+/// Read every `.yml` and `.yaml` file in `dir`, by stem
+pub fn load_dir(dir: &Path) -> io::Result<HashMap<String, String>> {
+    let mut files = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_yaml = path
+            .extension()
+            .is_some_and(|ext| ext == "yml" || ext == "yaml");
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        // Editors' swap and backup files start with a dot
+        if !is_yaml || stem.starts_with('.') || !path.is_file() {
+            continue;
+        }
+        files.insert(stem.to_string(), std::fs::read_to_string(&path)?);
+    }
+    Ok(files)
+}
+
+/// Replace the known files, returning the previous ones
+pub fn set_files(files: HashMap<String, String>) -> HashMap<String, String> {
+    FILES
+        .write()
+        .ok()
+        .and_then(|mut current| current.replace(files))
+        .unwrap_or_default()
+}
+
+/// The rule set file of a container called `name` in `service`
+pub fn lookup(name: &str, service: Option<&str>) -> Option<String> {
+    let files = FILES.read().ok()?;
+    find(files.as_ref()?, name, service).cloned()
+}
+
+fn find<'a>(
+    files: &'a HashMap<String, String>,
+    name: &str,
+    service: Option<&str>,
+) -> Option<&'a String> {
+    files
+        .get(name.trim_start_matches('/'))
+        .or_else(|| service.and_then(|service| files.get(service)))
+}
+
+/// The stems whose contents differ between `old` and `new`
+pub fn changed(old: &HashMap<String, String>, new: &HashMap<String, String>) -> Vec<String> {
+    let mut stems: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|stem| old.get(*stem) != new.get(*stem))
+        .cloned()
+        .collect();
+    stems.sort();
+    stems.dedup();
+    stems
+}
+
+/// inotify watch on the rules directory
+#[cfg(target_os = "linux")]
+pub struct DirEvents {
+    fd: tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+}
+
+#[cfg(target_os = "linux")]
+impl DirEvents {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::os::unix::ffi::OsStrExt;
+
+        // SAFETY: plain inotify_init1(2) call; the result is checked before use
+        let raw = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: raw is a freshly created descriptor nobody else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // Editors save by writing in place or by renaming a new file over
+        // the old one
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_MOVED_TO
+            | libc::IN_MOVED_FROM
+            | libc::IN_CREATE
+            | libc::IN_DELETE;
+        // SAFETY: path is a valid NUL-terminated string
+        let rc = unsafe { libc::inotify_add_watch(raw, path.as_ptr(), mask) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: tokio::io::unix::AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait until a file in the directory changes
+    pub async fn changed(&self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let read = guard.try_io(|fd| {
+                // SAFETY: buf is valid for writes of buf.len() bytes
+                let n = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n)
+                }
+            });
+            match read {
+                // Which file changed doesn't matter, the directory is re-read
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("web.yml"), "output: []\n").unwrap();
+        std::fs::write(dir.path().join("db.yaml"), "inbound: []\n").unwrap();
+        std::fs::write(dir.path().join(".web.yml.swp"), "").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        let files = load_dir(dir.path()).unwrap();
+        let mut stems: Vec<&String> = files.keys().collect();
+        stems.sort();
+        assert_eq!(stems, ["db", "web"]);
+
+        let mut edited = files.clone();
+        edited.insert("web".to_string(), "output: [{}]\n".to_string());
+        edited.remove("db");
+        edited.insert("cache".to_string(), String::new());
+        assert_eq!(changed(&files, &edited), ["cache", "db", "web"]);
+
+        assert_eq!(
+            find(&files, "/web", None).map(String::as_str),
+            Some("output: []\n")
+        );
+        assert_eq!(
+            find(&files, "project-db-1", Some("db")).map(String::as_str),
+            Some("inbound: []\n")
+        );
+        assert_eq!(find(&files, "project-cache-1", Some("cache")), None);
+    }
+}
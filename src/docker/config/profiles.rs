@@ -0,0 +1,54 @@
+//! The profiles of `--profiles`, which rules labels, `--rules-dir` files
+//! and guests are parsed against. Profiles themselves are
+//! [`harborshield_core::config::profiles`].
+
+use super::{Config, exposure};
+use harborshield_core::Result;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::sync::{LazyLock, RwLock};
+
+pub use harborshield_core::config::profiles::*;
+
+static PROFILES: LazyLock<RwLock<Profiles>> = LazyLock::new(|| RwLock::new(Profiles::default()));
+
+/// Make `profiles` available to rule sets parsed from now on
+pub fn set_profiles(profiles: Profiles) {
+    if let Ok(mut current) = PROFILES.write() {
+        *current = profiles;
+    }
+}
+
+fn current() -> Profiles {
+    PROFILES.read().map(|p| p.clone()).unwrap_or_default()
+}
+
+/// Parse a rule set against the configured profiles and sensitive ports
+pub fn parse_rules(yaml: &str) -> Result<Config> {
+    let config = current().parse_rules(yaml)?;
+    config.check_exposure(&exposure::sensitive_ports())?;
+    Ok(config)
+}
+
+/// [`Profiles::parse_layered`] against the configured profiles and
+/// sensitive ports
+pub fn parse_layered(yaml: &str, layers: &[String]) -> Result<Config> {
+    let config = current().parse_layered(yaml, layers)?;
+    config.check_exposure(&exposure::sensitive_ports())?;
+    Ok(config)
+}
+
+/// `deserialize_with` for rule sets embedded in other files
+pub fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Config, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    let config = current()
+        .parse_value(value)
+        .map_err(serde::de::Error::custom)?;
+    config
+        .check_exposure(&exposure::sensitive_ports())
+        .map_err(serde::de::Error::custom)?;
+    Ok(config)
+}
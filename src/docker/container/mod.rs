@@ -1,6 +1,6 @@
 use crate::docker::compose::ComposeInfo;
 use crate::docker::config::Config;
-use crate::{ENABLED_LABEL, RULES_LABEL};
+use crate::docker::labels::{self, ALIASES_KEY, IPV6_KEY, RULES_KEY};
 use crate::{Error, Result};
 use bon::Builder;
 use std::collections::{HashMap, HashSet};
@@ -30,6 +30,13 @@ pub struct Container {
     pub uses_host_network: bool,
     #[builder(default = false)]
     pub paused: bool,
+    /// Why the container looks like it runs behind a mesh sidecar
+    #[builder(default)]
+    pub sidecar_hints: Vec<String>,
+    /// NFS and SMB shares behind its volumes, read when its rules set
+    /// `storage_egress`
+    #[builder(default)]
+    pub storage_backends: Vec<crate::docker::volumes::StorageBackend>,
 }
 
 #[derive(Debug, Clone, Builder)]
@@ -57,6 +64,8 @@ pub struct Tracker {
 pub struct PortMapping {
     pub container_port: u16,
     pub host_port: Option<u16>,
+    /// Host address the port is published on, unless bound to all addresses
+    pub host_ip: Option<IpAddr>,
     pub protocol: String,
 }
 
@@ -174,6 +183,17 @@ impl Tracker {
         self.containers.lock().unwrap().len()
     }
 
+    /// Tracked containers whose state is kept under `identity`
+    pub fn find_by_identity(&self, identity: &str) -> Vec<Container> {
+        self.containers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|c| c.identity() == identity)
+            .cloned()
+            .collect()
+    }
+
     pub fn find_container(&self, identifier: &str) -> Option<Container> {
         // Try ID first
         if let Some(container) = self.get_container(identifier) {
@@ -290,7 +310,29 @@ impl Tracker {
 }
 
 impl Container {
+    /// Key for the state kept about this container across recreations
+    pub fn identity(&self) -> String {
+        crate::docker::identity::identity(&self.name, &self.labels)
+    }
+
+    /// Addresses on every network, IPv4 or IPv6
+    pub fn ip_addresses(&self, ipv6: bool) -> Vec<IpAddr> {
+        self.networks
+            .values()
+            .flat_map(|network| network.ip_addresses.iter().copied())
+            .filter(|ip| ip.is_ipv6() == ipv6)
+            .collect()
+    }
+
+    /// Whether the container's IPv6 traffic is filtered too; opted out with
+    /// a `<prefix>.ipv6: "false"` label
+    pub fn ipv6_enabled(&self) -> bool {
+        labels::get(&self.labels, IPV6_KEY).is_none_or(|v| v != "false")
+    }
+
     pub fn from_inspect(inspect: bollard::models::ContainerInspectResponse) -> Result<Self> {
+        let sidecar_hints = crate::docker::mesh::sidecar_hints(&inspect);
+
         let id = inspect
             .id
             .ok_or_else(|| Error::invalid_state("Container missing ID", "has ID", "missing"))?;
@@ -321,11 +363,13 @@ impl Container {
         if let Some(network_settings) = inspect.network_settings {
             if let Some(networks_map) = network_settings.networks {
                 for (net_name, net_info) in networks_map {
-                    let ip_addresses: Vec<IpAddr> = net_info
-                        .ip_address
-                        .and_then(|ip| ip.parse().ok())
-                        .map(|ip| vec![ip])
-                        .unwrap_or_else(Vec::new);
+                    // Docker reports an empty string for a missing address
+                    let ip_addresses: Vec<IpAddr> =
+                        [net_info.ip_address, net_info.global_ipv6_address]
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|ip| ip.parse().ok())
+                            .collect();
 
                     // Extract network aliases
                     let mut network_aliases = Vec::new();
@@ -355,6 +399,7 @@ impl Container {
                             ports.push(PortMapping {
                                 container_port: port_num,
                                 host_port: None, // Will be filled from port bindings
+                                host_ip: None,
                                 protocol: parts[1].to_string(),
                             });
                         }
@@ -378,6 +423,11 @@ impl Container {
                                             for port in &mut ports {
                                                 if port.container_port == container_port {
                                                     port.host_port = Some(host_port);
+                                                    port.host_ip = first_binding
+                                                        .host_ip
+                                                        .as_deref()
+                                                        .and_then(|ip| ip.parse::<IpAddr>().ok())
+                                                        .filter(|ip| !ip.is_unspecified());
                                                     break;
                                                 }
                                             }
@@ -413,8 +463,10 @@ impl Container {
             all_aliases.push(clean_name.to_string());
         }
 
+        labels::warn_shadowed(&name, &labels);
+
         // Extract custom aliases from harborshield labels
-        if let Some(custom_aliases) = labels.get("harborshield.aliases") {
+        if let Some(custom_aliases) = labels::get(&labels, ALIASES_KEY) {
             for alias in custom_aliases.split(',') {
                 let alias = alias.trim().to_string();
                 if !alias.is_empty() && !all_aliases.contains(&alias) {
@@ -439,15 +491,32 @@ impl Container {
             .collect();
 
         // Extract enabled and rules_yaml from labels
-        let enabled = labels
-            .get(ENABLED_LABEL)
-            .map(|v| v == "true")
-            .unwrap_or(false);
+        let enabled = labels::is_enabled(&labels);
 
         // Parse and validate config during container creation
-        let config = if let Some(rules_yaml) = labels.get(RULES_LABEL) {
-            match serde_yaml::from_str::<Config>(rules_yaml) {
-                Ok(config) => Some(config),
+        // Containers without rules stay unmanaged, ad-hoc rules or not
+        // A --rules-dir file is laid under the label, so the label wins
+        let file = crate::docker::config::files::lookup(&name, compose_info.service.as_deref());
+        let rules = match (file, labels::get(&labels, RULES_KEY)) {
+            (Some(file), Some(label)) => Some((file, vec![label.clone()])),
+            (Some(file), None) => Some((file, Vec::new())),
+            (None, Some(label)) => Some((label.clone(), Vec::new())),
+            (None, None) => None,
+        };
+        let config = if let Some((rules_yaml, mut layers)) = rules {
+            layers.extend(crate::database::adhoc::layers(
+                &crate::docker::identity::identity(&name, &labels),
+            ));
+            match crate::docker::config::profiles::parse_layered(&rules_yaml, &layers) {
+                Ok(config) => {
+                    for group in config.duplicate_output_rules() {
+                        warn!(
+                            "Container {} has identical output rules {:?}; they are applied once",
+                            name, group
+                        );
+                    }
+                    Some(config)
+                }
                 Err(e) => {
                     warn!(
                         "Failed to parse/validate rules for container {}: {}. Container will be created without rules.",
@@ -460,6 +529,25 @@ impl Container {
             None
         };
 
+        // Forwarded ports are filtered like published ones
+        for forward in config.iter().flat_map(|config| &config.forward) {
+            let protocol = forward.proto.to_string();
+            let mapping = PortMapping {
+                container_port: forward.container_port(),
+                host_port: Some(forward.host_port),
+                host_ip: forward.host_ip.map(IpAddr::V4),
+                protocol,
+            };
+            match ports.iter_mut().find(|port| {
+                port.host_port.is_none()
+                    && port.container_port == mapping.container_port
+                    && port.protocol == mapping.protocol
+            }) {
+                Some(port) => *port = mapping,
+                None => ports.push(mapping),
+            }
+        }
+
         Ok(Container {
             id,
             name,
@@ -471,14 +559,13 @@ impl Container {
             enabled,
             config,
             paused: false, // Containers are not paused when starting/inspecting
+            sidecar_hints,
+            storage_backends: Vec::new(),
         })
     }
 
     /// Check if harborshield is enabled for a container
     pub fn is_harborshield_enabled(&self) -> bool {
-        self.labels
-            .get(ENABLED_LABEL)
-            .map(|v| v == "true")
-            .unwrap_or(false)
+        labels::is_enabled(&self.labels)
     }
 }
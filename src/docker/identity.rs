@@ -0,0 +1,105 @@
+//! Stable container identities.
+//!
+//! Enforcement overrides, learning sessions, audit entries and traffic stats
+//! are kept under a container's identity rather than its ID, so they survive
+//! the container being recreated. By default the identity is the container
+//! name. Deployments that rename containers (scaling, blue/green suffixes)
+//! can key state on something steadier instead:
+//!
+//! - `compose`: `<project>.<service>` from the compose labels, so every
+//!   replica of a service shares one history
+//! - `label`: the value of `<prefix>.identity`
+//!
+//! Containers without the labels a mode needs fall back to their name.
+
+use crate::docker::compose::ComposeInfo;
+use crate::docker::labels::{self, IDENTITY_KEY};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IdentityMode {
+    /// The container name
+    #[default]
+    Name,
+    /// Compose project and service
+    Compose,
+    /// The `<prefix>.identity` label
+    Label,
+}
+
+static MODE: RwLock<IdentityMode> = RwLock::new(IdentityMode::Name);
+
+/// Key container state on `mode` from now on
+pub fn set_identity_mode(mode: IdentityMode) {
+    if let Ok(mut current) = MODE.write() {
+        *current = mode;
+    }
+}
+
+pub fn identity_mode() -> IdentityMode {
+    MODE.read().map(|mode| *mode).unwrap_or_default()
+}
+
+/// The identity of a container named `name` carrying `container_labels`
+pub fn identity_for(
+    mode: IdentityMode,
+    name: &str,
+    container_labels: &HashMap<String, String>,
+) -> String {
+    let identity = match mode {
+        IdentityMode::Name => None,
+        IdentityMode::Compose => {
+            let compose = ComposeInfo::from_labels(container_labels);
+            compose
+                .project
+                .zip(compose.service)
+                .map(|(project, service)| format!("{}.{}", project, service))
+        }
+        IdentityMode::Label => labels::get(container_labels, IDENTITY_KEY)
+            .filter(|identity| !identity.is_empty())
+            .cloned(),
+    };
+    identity.unwrap_or_else(|| name.to_string())
+}
+
+/// The identity of a container under the configured mode
+pub fn identity(name: &str, container_labels: &HashMap<String, String>) -> String {
+    identity_for(identity_mode(), name, container_labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::compose::{COMPOSE_PROJECT_LABEL, COMPOSE_SERVICE_LABEL};
+
+    #[test]
+    fn test_identity_modes() {
+        let compose = HashMap::from([
+            (COMPOSE_PROJECT_LABEL.to_string(), "shop".to_string()),
+            (COMPOSE_SERVICE_LABEL.to_string(), "web".to_string()),
+            ("harborshield.enabled".to_string(), "true".to_string()),
+            (
+                "harborshield.identity".to_string(),
+                "storefront".to_string(),
+            ),
+        ]);
+        let plain = HashMap::new();
+
+        assert_eq!(
+            identity_for(IdentityMode::Name, "shop-web-2", &compose),
+            "shop-web-2"
+        );
+        assert_eq!(
+            identity_for(IdentityMode::Compose, "shop-web-2", &compose),
+            "shop.web"
+        );
+        assert_eq!(
+            identity_for(IdentityMode::Label, "shop-web-2", &compose),
+            "storefront"
+        );
+        // Without the labels a mode relies on, the name is used
+        assert_eq!(identity_for(IdentityMode::Compose, "db", &plain), "db");
+        assert_eq!(identity_for(IdentityMode::Label, "db", &plain), "db");
+    }
+}
@@ -0,0 +1,112 @@
+//! Container inspect over a local Docker socket for `--low-memory`.
+//!
+//! bollard collects a response body before deserializing it, so an inspect
+//! briefly holds the JSON and the parsed response at once. Here the body is
+//! parsed straight off the socket instead. HTTP/1.0 keeps the daemon from
+//! chunking it, and the connection closing marks its end.
+
+use crate::{Error, Result};
+use bollard::models::ContainerInspectResponse;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where bollard connects without DOCKER_HOST
+pub const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Read buffer between the socket and the parser
+const BUFFER_SIZE: usize = 8 * 1024;
+
+fn invalid(message: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Parse a raw HTTP/1.0 inspect response as it is read
+fn read_response(mut reader: impl BufRead) -> Result<ContainerInspectResponse> {
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    let code = status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("malformed status line '{}'", status.trim())))?;
+
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid("truncated response".to_string()));
+        }
+        if header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    if code != 200 {
+        // Errors carry a short {"message": ...} body
+        let body: serde_json::Value = serde_json::from_reader(reader).unwrap_or_default();
+        return Err(Error::Docker(
+            bollard::errors::Error::DockerResponseServerError {
+                status_code: code,
+                message: body["message"].as_str().unwrap_or_default().to_string(),
+            },
+        ));
+    }
+    serde_json::from_reader(reader).map_err(|e| invalid(e.to_string()))
+}
+
+fn inspect_blocking(
+    socket: &PathBuf,
+    path: &str,
+    timeout: Duration,
+) -> Result<ContainerInspectResponse> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(format!("GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path).as_bytes())?;
+    read_response(BufReader::with_capacity(BUFFER_SIZE, stream))
+}
+
+/// Inspect container `id` through the Docker socket at `socket`
+pub async fn inspect(
+    socket: PathBuf,
+    api_version: String,
+    id: &str,
+    timeout: Duration,
+) -> Result<ContainerInspectResponse> {
+    let path = format!("/v{}/containers/{}/json", api_version, id);
+    tokio::task::spawn_blocking(move || inspect_blocking(&socket, &path, timeout))
+        .await
+        .map_err(|e| invalid(format!("inspect task failed: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_response() {
+        let raw = b"HTTP/1.0 200 OK\r\nApi-Version: 1.45\r\nContent-Type: application/json\r\n\r\n{\"Id\":\"abc123\",\"Name\":\"/web\",\"Config\":{\"Labels\":{\"harborshield.enabled\":\"true\"}}}\n";
+        let inspect = read_response(&raw[..]).unwrap();
+        assert_eq!(inspect.id.as_deref(), Some("abc123"));
+        assert_eq!(inspect.name.as_deref(), Some("/web"));
+        assert_eq!(
+            inspect.config.and_then(|c| c.labels).unwrap()["harborshield.enabled"],
+            "true"
+        );
+
+        let missing = b"HTTP/1.0 404 Not Found\r\n\r\n{\"message\":\"No such container: gone\"}";
+        match read_response(&missing[..]) {
+            Err(Error::Docker(bollard::errors::Error::DockerResponseServerError {
+                status_code,
+                message,
+            })) => {
+                assert_eq!(status_code, 404);
+                assert_eq!(message, "No such container: gone");
+            }
+            other => panic!("{:?}", other.map(|i| i.id)),
+        }
+        assert!(read_response(&b"HTTP/1.0 200 OK\r\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Container label namespaces.
+//!
+//! Rules are read from `<prefix>.enabled`, `<prefix>.rules` and
+//! `<prefix>.aliases`. Besides `harborshield`, fleets can register the
+//! prefixes they already use. A container is read from exactly one
+//! namespace: the first prefix, in precedence order, that it carries any of
+//! these labels for.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+use tracing::warn;
+
+pub const DEFAULT_LABEL_PREFIX: &str = "harborshield";
+
+pub const ENABLED_KEY: &str = "enabled";
+pub const RULES_KEY: &str = "rules";
+pub const ALIASES_KEY: &str = "aliases";
+/// `"false"` leaves the container's IPv6 traffic unfiltered
+pub const IPV6_KEY: &str = "ipv6";
+/// Stable identity for `--identity label`, see [`crate::docker::identity`]
+pub const IDENTITY_KEY: &str = "identity";
+
+static PREFIXES: LazyLock<RwLock<Vec<String>>> =
+    LazyLock::new(|| RwLock::new(vec![DEFAULT_LABEL_PREFIX.to_string()]));
+
+/// Set the label prefixes to read, highest precedence first. `harborshield`
+/// comes first unless it is listed explicitly.
+pub fn set_label_prefixes(extra: &[String]) {
+    let mut prefixes = Vec::new();
+    if !extra.iter().any(|p| p == DEFAULT_LABEL_PREFIX) {
+        prefixes.push(DEFAULT_LABEL_PREFIX.to_string());
+    }
+    for prefix in extra {
+        let prefix = prefix.trim_end_matches('.').to_string();
+        if !prefix.is_empty() && !prefixes.contains(&prefix) {
+            prefixes.push(prefix);
+        }
+    }
+
+    if let Ok(mut current) = PREFIXES.write() {
+        *current = prefixes;
+    }
+}
+
+/// Configured label prefixes, highest precedence first
+pub fn label_prefixes() -> Vec<String> {
+    PREFIXES
+        .read()
+        .map(|p| p.clone())
+        .unwrap_or_else(|_| vec![DEFAULT_LABEL_PREFIX.to_string()])
+}
+
+fn has_namespace(labels: &HashMap<String, String>, prefix: &str) -> bool {
+    [ENABLED_KEY, RULES_KEY, ALIASES_KEY]
+        .iter()
+        .any(|key| labels.contains_key(&format!("{}.{}", prefix, key)))
+}
+
+/// Namespaces the labels use, highest precedence first
+pub fn namespaces_in(labels: &HashMap<String, String>, prefixes: &[String]) -> Vec<String> {
+    prefixes
+        .iter()
+        .filter(|p| has_namespace(labels, p))
+        .cloned()
+        .collect()
+}
+
+/// The namespace a container's labels are read from
+pub fn namespace_in(labels: &HashMap<String, String>, prefixes: &[String]) -> Option<String> {
+    namespaces_in(labels, prefixes).into_iter().next()
+}
+
+/// Warn when a container carries labels under more than one namespace, since
+/// only the highest precedence one is read
+pub fn warn_shadowed(container_name: &str, labels: &HashMap<String, String>) {
+    let namespaces = namespaces_in(labels, &label_prefixes());
+    if let [chosen, shadowed @ ..] = namespaces.as_slice()
+        && !shadowed.is_empty()
+    {
+        warn!(
+            "Container {} has labels under {:?} as well as '{}'; only '{}' labels are used",
+            container_name, shadowed, chosen, chosen
+        );
+    }
+}
+
+/// `<namespace>.<key>` from the container's namespace
+pub fn get<'a>(labels: &'a HashMap<String, String>, key: &str) -> Option<&'a String> {
+    let prefix = namespace_in(labels, &label_prefixes())?;
+    labels.get(&format!("{}.{}", prefix, key))
+}
+
+/// Whether the container opted in to harborshield under any namespace
+pub fn is_enabled(labels: &HashMap<String, String>) -> bool {
+    get(labels, ENABLED_KEY).is_some_and(|v| v == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_namespace_precedence() {
+        let prefixes = vec!["harborshield".to_string(), "acme.fw".to_string()];
+
+        let legacy = labels(&[("acme.fw.enabled", "true"), ("acme.fw.rules", "output: []")]);
+        assert_eq!(namespace_in(&legacy, &prefixes).as_deref(), Some("acme.fw"));
+
+        // Both present: the higher precedence namespace wins outright
+        let both = labels(&[
+            ("acme.fw.enabled", "true"),
+            ("harborshield.enabled", "false"),
+        ]);
+        assert_eq!(
+            namespace_in(&both, &prefixes).as_deref(),
+            Some("harborshield")
+        );
+
+        assert_eq!(namespaces_in(&both, &prefixes).len(), 2);
+
+        let other = labels(&[("com.example.enabled", "true")]);
+        assert_eq!(namespace_in(&other, &prefixes), None);
+    }
+}
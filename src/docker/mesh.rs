@@ -0,0 +1,227 @@
+//! Service mesh sidecar detection.
+//!
+//! Istio and Linkerd style sidecars install `REDIRECT` rules inside the pod's
+//! network namespace, so traffic reaching a published port is handed to the
+//! proxy before the application ever sees it. Port-based container rules then
+//! describe ports the application no longer answers on directly.
+
+use bollard::models::ContainerInspectResponse;
+use std::io;
+use tokio::process::Command;
+
+/// Label prefixes set by mesh injectors and compose setups that mimic them
+const MESH_LABEL_PREFIXES: &[&str] = &[
+    "sidecar.istio.io",
+    "istio.io",
+    "linkerd.io",
+    "consul.hashicorp.com/connect",
+];
+
+/// Image name fragments of well-known sidecar proxies and their init containers
+const PROXY_IMAGES: &[&str] = &[
+    "istio/proxyv2",
+    "istio/proxy_init",
+    "linkerd/proxy",
+    "linkerd2-proxy",
+    "envoyproxy/envoy",
+];
+
+/// Reasons to suspect a container runs behind a sidecar, from inspect data
+/// alone
+pub fn sidecar_hints(inspect: &ContainerInspectResponse) -> Vec<String> {
+    let mut hints = Vec::new();
+
+    if let Some(labels) = inspect.config.as_ref().and_then(|c| c.labels.as_ref()) {
+        let mut keys: Vec<&String> = labels
+            .keys()
+            .filter(|k| MESH_LABEL_PREFIXES.iter().any(|p| k.starts_with(p)))
+            .collect();
+        keys.sort();
+        if let Some(key) = keys.first() {
+            hints.push(format!("mesh label {}", key));
+        }
+    }
+
+    if let Some(image) = inspect.config.as_ref().and_then(|c| c.image.as_deref())
+        && PROXY_IMAGES.iter().any(|p| image.contains(p))
+    {
+        hints.push(format!("proxy image {}", image));
+    }
+
+    // Sidecars share the application's namespace, or the other way round
+    if let Some(mode) = inspect
+        .host_config
+        .as_ref()
+        .and_then(|hc| hc.network_mode.as_deref())
+        && let Some(owner) = mode.strip_prefix("container:")
+    {
+        hints.push(format!("shares network namespace of {}", owner));
+    }
+
+    hints
+}
+
+/// A transparent redirect found in a container's network namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub chain: String,
+    pub to_port: Option<u16>,
+}
+
+/// `REDIRECT` targets in `iptables-save -t nat` output
+pub fn parse_iptables_redirects(output: &str) -> Vec<Redirect> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("-A") {
+                return None;
+            }
+            let chain = words.next()?.to_string();
+            let rest: Vec<&str> = words.collect();
+            if !rest.windows(2).any(|w| w == ["-j", "REDIRECT"]) {
+                return None;
+            }
+            let to_port = rest
+                .windows(2)
+                .find(|w| w[0] == "--to-ports")
+                .and_then(|w| w[1].split(['-', ':']).next()?.parse().ok());
+            Some(Redirect { chain, to_port })
+        })
+        .collect()
+}
+
+/// `redirect` statements in `nft list ruleset` output
+pub fn parse_nft_redirects(output: &str) -> Vec<Redirect> {
+    let mut chain = String::new();
+    let mut redirects = Vec::new();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix("chain ") {
+            chain = name.trim_end_matches(" {").trim().to_string();
+        } else if let Some(pos) = line.find("redirect") {
+            let to_port = line[pos..]
+                .split_once("to :")
+                .and_then(|(_, port)| port.split_whitespace().next()?.parse().ok());
+            redirects.push(Redirect {
+                chain: chain.clone(),
+                to_port,
+            });
+        }
+    }
+
+    redirects
+}
+
+async fn run_in_netns(pid: i64, args: &[&str]) -> io::Result<Option<String>> {
+    let pid = pid.to_string();
+    let output = Command::new("nsenter")
+        .args(["-t", &pid, "-n"])
+        .args(args)
+        .output()
+        .await?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+/// Redirects installed in the network namespace of process `pid`, read with
+/// iptables and, failing that, nft
+pub async fn netns_redirects(pid: i64) -> io::Result<Vec<Redirect>> {
+    if let Some(out) = run_in_netns(pid, &["iptables-save", "-t", "nat"]).await? {
+        let redirects = parse_iptables_redirects(&out);
+        if !redirects.is_empty() {
+            return Ok(redirects);
+        }
+    }
+    match run_in_netns(pid, &["nft", "list", "ruleset"]).await? {
+        Some(out) => Ok(parse_nft_redirects(&out)),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::{ContainerConfig, HostConfig};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_sidecar_hints() {
+        let inspect = ContainerInspectResponse {
+            config: Some(ContainerConfig {
+                image: Some("docker.io/istio/proxyv2:1.22".to_string()),
+                labels: Some(HashMap::from([(
+                    "sidecar.istio.io/inject".to_string(),
+                    "true".to_string(),
+                )])),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                network_mode: Some("container:web".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            sidecar_hints(&inspect),
+            vec![
+                "mesh label sidecar.istio.io/inject",
+                "proxy image docker.io/istio/proxyv2:1.22",
+                "shares network namespace of web",
+            ]
+        );
+        assert!(sidecar_hints(&ContainerInspectResponse::default()).is_empty());
+    }
+
+    #[test]
+    fn test_parse_iptables_redirects() {
+        let output = "\
+*nat
+:PREROUTING ACCEPT [0:0]
+:ISTIO_INBOUND - [0:0]
+-A PREROUTING -p tcp -j ISTIO_INBOUND
+-A ISTIO_INBOUND -p tcp -m tcp --dport 15008 -j RETURN
+-A ISTIO_IN_REDIRECT -p tcp -j REDIRECT --to-ports 15006
+-A PROXY_INIT_REDIRECT -p tcp -j REDIRECT --to-ports 4143
+COMMIT
+";
+        assert_eq!(
+            parse_iptables_redirects(output),
+            vec![
+                Redirect {
+                    chain: "ISTIO_IN_REDIRECT".to_string(),
+                    to_port: Some(15006),
+                },
+                Redirect {
+                    chain: "PROXY_INIT_REDIRECT".to_string(),
+                    to_port: Some(4143),
+                },
+            ]
+        );
+        assert!(parse_iptables_redirects("-A OUTPUT -j ACCEPT").is_empty());
+    }
+
+    #[test]
+    fn test_parse_nft_redirects() {
+        let output = "\
+table ip nat {
+	chain PROXY_INIT_REDIRECT {
+		meta l4proto tcp tcp dport != 4190 counter packets 0 bytes 0 redirect to :4143
+	}
+	chain OUTPUT {
+		type nat hook output priority -100; policy accept;
+	}
+}
+";
+        assert_eq!(
+            parse_nft_redirects(output),
+            vec![Redirect {
+                chain: "PROXY_INIT_REDIRECT".to_string(),
+                to_port: Some(4143),
+            }]
+        );
+    }
+}
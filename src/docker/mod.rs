@@ -2,7 +2,15 @@ pub mod compose;
 pub mod config;
 pub mod container;
 pub mod error;
+pub mod identity;
+pub mod inspect;
+pub mod labels;
+pub mod mesh;
 pub mod network;
+pub mod ports;
+pub mod reconnect;
+pub mod rootless;
+pub mod volumes;
 
 use crate::docker::container::{Container, Tracker};
 use crate::docker::network::NetworkGatewayInfo;
@@ -13,12 +21,12 @@ use bon::bon;
 use futures::stream::StreamExt;
 use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::time::timeout;
-use tracing::debug;
+use tracing::{debug, warn};
 
 #[cfg(test)]
 mod tests;
@@ -49,12 +57,16 @@ impl DockerClient {
     #[builder]
     pub fn new(
         #[builder(default = Duration::from_secs(30))] timeout_duration: Duration,
+        /// Daemon address used instead of DOCKER_HOST, e.g. a Podman socket
+        host: Option<String>,
     ) -> Result<Self> {
         // Check for API version override first
         let api_version_override = env::var("DOCKER_API_VERSION").ok();
 
         // Check DOCKER_HOST environment variable
-        let (client, connection_info) = if let Ok(docker_host) = env::var("DOCKER_HOST") {
+        let (client, connection_info) = if let Some(docker_host) =
+            host.or_else(|| env::var("DOCKER_HOST").ok())
+        {
             // Check if TLS is required
             let tls_verify = env::var("DOCKER_TLS_VERIFY")
                 .unwrap_or_default()
@@ -67,7 +79,7 @@ impl DockerClient {
                 // Unix socket connection
                 let client =
                     Docker::connect_with_socket(&docker_host, 120, bollard::API_DEFAULT_VERSION)
-                        .map_err(|e| Error::Docker(e))?;
+                        .map_err(Error::Docker)?;
                 (client, ConnectionInfo::Socket(docker_host))
             } else if tls_verify || docker_host.starts_with("tcp://") {
                 // TCP connection - check if TLS is needed
@@ -101,19 +113,19 @@ impl DockerClient {
                         docker_host.replace("tcp://", "http://")
                     };
                     let client = Docker::connect_with_http(&url, 120, bollard::API_DEFAULT_VERSION)
-                        .map_err(|e| Error::Docker(e))?;
+                        .map_err(Error::Docker)?;
                     (client, ConnectionInfo::Http(url))
                 }
             } else {
                 // HTTP connection
                 let client =
                     Docker::connect_with_http(&docker_host, 120, bollard::API_DEFAULT_VERSION)
-                        .map_err(|e| Error::Docker(e))?;
+                        .map_err(Error::Docker)?;
                 (client, ConnectionInfo::Http(docker_host))
             }
         } else {
             // Default connection
-            let client = Docker::connect_with_socket_defaults().map_err(|e| Error::Docker(e))?;
+            let client = Docker::connect_with_socket_defaults().map_err(Error::Docker)?;
             (client, ConnectionInfo::Default)
         };
 
@@ -360,7 +372,59 @@ impl DockerClient {
     }
 
     pub async fn try_get_container_by_id(&self, id: &str) -> Result<Container> {
-        Ok(Container::from_inspect(self.inspect_container(id).await?)?)
+        let inspect = self.inspect_container(id).await?;
+        let volumes = volumes::volume_names(&inspect);
+        let mut container = Container::from_inspect(inspect)?;
+        if container.config.as_ref().is_some_and(|c| c.storage_egress) {
+            container.storage_backends = self.storage_backends(&volumes).await;
+        }
+        Ok(container)
+    }
+
+    /// The NFS and SMB shares behind `volumes`, with their servers resolved.
+    /// Volumes that cannot be inspected or resolved are left out
+    pub async fn storage_backends(&self, volumes: &[String]) -> Vec<volumes::StorageBackend> {
+        let mut backends = Vec::new();
+        for name in volumes {
+            let volume =
+                match timeout(self.timeout_duration, self.client.inspect_volume(name)).await {
+                    Ok(Ok(volume)) => volume,
+                    Ok(Err(e)) => {
+                        warn!("Failed to inspect volume {}: {}", name, e);
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Timed out inspecting volume {}", name);
+                        continue;
+                    }
+                };
+            let Some(mut backend) = volumes::backend_from_volume(&volume) else {
+                continue;
+            };
+            match volumes::resolve_server(&backend.server).await {
+                Ok(addrs) if !addrs.is_empty() => backend.addrs = addrs,
+                Ok(_) | Err(_) => {
+                    warn!(
+                        "Storage server {} of volume {} does not resolve; no egress is allowed to it",
+                        backend.server, name
+                    );
+                    continue;
+                }
+            }
+            backends.push(backend);
+        }
+        backends
+    }
+
+    /// The daemon's unix socket, when it is reached through one
+    fn local_socket(&self) -> Option<PathBuf> {
+        match &self.connection_info {
+            ConnectionInfo::Socket(host) => {
+                Some(PathBuf::from(host.strip_prefix("unix://").unwrap_or(host)))
+            }
+            ConnectionInfo::Default => Some(PathBuf::from(inspect::DEFAULT_SOCKET)),
+            ConnectionInfo::Http(_) | ConnectionInfo::Ssl { .. } => None,
+        }
     }
 
     pub async fn inspect_container(
@@ -369,6 +433,18 @@ impl DockerClient {
     ) -> Result<bollard::models::ContainerInspectResponse> {
         use bollard::query_parameters::InspectContainerOptionsBuilder;
 
+        if crate::low_memory::enabled()
+            && let Some(socket) = self.local_socket()
+        {
+            let version = self.client.client_version().to_string();
+            return timeout(
+                self.timeout_duration,
+                inspect::inspect(socket, version, id, self.timeout_duration),
+            )
+            .await
+            .map_err(|_| Error::timeout(self.timeout_duration, "inspect container"))?;
+        }
+
         let options = InspectContainerOptionsBuilder::default().build();
 
         timeout(
@@ -449,6 +525,15 @@ impl DockerClient {
             .map_err(|e| Error::Docker(e))
     }
 
+    /// Security options the daemon runs with, e.g. `name=rootless`
+    pub async fn security_options(&self) -> Result<Vec<String>> {
+        let info = timeout(self.timeout_duration, self.client.info())
+            .await
+            .map_err(|_| Error::timeout(self.timeout_duration, "get system info"))?
+            .map_err(Error::Docker)?;
+        Ok(info.security_options.unwrap_or_default())
+    }
+
     /// Check if the Docker daemon supports a specific API endpoint
     /// This is useful for gracefully handling newer features
     pub async fn check_api_endpoint(&self, endpoint: &str) -> bool {
@@ -1,17 +1,30 @@
 use crate::{Error, Result};
 use bollard::models::Network;
+use ipnet::IpNet;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::{LazyLock, RwLock};
 use tracing::{debug, warn};
 
-/// Information about a Docker network including gateway IPs
-#[derive(Debug, Clone)]
-pub struct NetworkGatewayInfo {
-    pub network_id: String,
-    pub network_name: String,
-    pub gateway_ips: Vec<IpAddr>,
-    pub subnet: Option<String>,
+pub use harborshield_core::network::NetworkGatewayInfo;
+
+static KNOWN_NETWORKS: LazyLock<RwLock<HashMap<String, NetworkGatewayInfo>>> =
+    LazyLock::new(Default::default);
+
+/// Keep `networks`, by name, for rules resolved without the engine
+pub fn remember_networks(networks: HashMap<String, NetworkGatewayInfo>) {
+    if let Ok(mut known) = KNOWN_NETWORKS.write() {
+        *known = networks;
+    }
+}
+
+/// The networks last kept by [`remember_networks`]
+pub fn known_networks() -> HashMap<String, NetworkGatewayInfo> {
+    KNOWN_NETWORKS
+        .read()
+        .map(|known| known.clone())
+        .unwrap_or_default()
 }
 
 /// Extract gateway information from Docker network inspect response
@@ -30,6 +43,7 @@ pub fn extract_network_gateway(network: &Network) -> Result<NetworkGatewayInfo>
 
     let mut gateway_ips = Vec::new();
     let mut subnet = None;
+    let mut subnets = Vec::new();
 
     // Extract gateway IPs from IPAM configuration
     if let Some(ipam) = &network.ipam {
@@ -55,6 +69,15 @@ pub fn extract_network_gateway(network: &Network) -> Result<NetworkGatewayInfo>
                 if subnet.is_none() {
                     subnet = config.subnet.clone();
                 }
+                if let Some(subnet_str) = &config.subnet {
+                    match IpNet::from_str(subnet_str) {
+                        Ok(net) => subnets.push(net),
+                        Err(e) => warn!(
+                            "Failed to parse subnet '{}' for network {}: {}",
+                            subnet_str, network_name, e
+                        ),
+                    }
+                }
             }
         }
     }
@@ -71,6 +94,7 @@ pub fn extract_network_gateway(network: &Network) -> Result<NetworkGatewayInfo>
         network_name,
         gateway_ips,
         subnet,
+        subnets,
     })
 }
 
@@ -116,6 +140,7 @@ mod tests {
             IpAddr::from_str("192.168.1.1").unwrap()
         );
         assert_eq!(result.subnet, Some("192.168.1.0/24".to_string()));
+        assert_eq!(result.subnets, ["192.168.1.0/24".parse::<IpNet>().unwrap()]);
     }
 
     #[test]
@@ -0,0 +1,214 @@
+//! Host ports published by more than one container.
+//!
+//! Docker lets containers share a host port as long as they publish it on
+//! different addresses, and DNAT sends each client to whichever container
+//! owns the address it connected to. When those containers restrict
+//! external sources differently, the same port is open to one client and
+//! closed to another depending only on the host address used.
+
+use crate::docker::config::ExternalRules;
+use crate::docker::container::{Container, PortMapping};
+use serde::Serialize;
+use std::fmt;
+use std::net::IpAddr;
+
+/// A port two containers publish on different host addresses with
+/// different external source restrictions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortOverlap {
+    pub port: u16,
+    pub protocol: String,
+    pub container: String,
+    pub host_ip: Option<IpAddr>,
+    pub restriction: String,
+    pub other: String,
+    pub other_host_ip: Option<IpAddr>,
+    pub other_restriction: String,
+}
+
+fn address(ip: Option<IpAddr>) -> String {
+    ip.map_or_else(|| "all addresses".to_string(), |ip| ip.to_string())
+}
+
+impl fmt::Display for PortOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "port {}/{} is published by {} on {} ({}) and by {} on {} ({}); \
+             clients reach one or the other depending on the host address they connect to, \
+             so the same port accepts different sources",
+            self.port,
+            self.protocol,
+            self.container,
+            address(self.host_ip),
+            self.restriction,
+            self.other,
+            address(self.other_host_ip),
+            self.other_restriction
+        )
+    }
+}
+
+/// Who may reach a container's published ports from outside, in words.
+/// Containers with the same description are treated alike
+pub fn describe_restriction(external: Option<&ExternalRules>) -> String {
+    let Some(external) = external else {
+        return "no harborshield rules".to_string();
+    };
+    if !external.allow {
+        return "external access blocked".to_string();
+    }
+
+    let mut sources: Vec<String> = external.ips.iter().map(|ip| ip.to_string()).collect();
+    sources.sort();
+    if external.host {
+        sources.push("the host's addresses".to_string());
+    }
+    if !external.rdns.is_empty() {
+        let patterns = serde_json::to_value(&external.rdns)
+            .ok()
+            .and_then(|v| v.as_array().cloned())
+            .unwrap_or_default();
+        let names: Vec<String> = patterns
+            .iter()
+            .map(|p| p.as_str().map_or_else(|| p.to_string(), str::to_string))
+            .collect();
+        sources.push(format!("sources resolving to {}", names.join(", ")));
+    }
+
+    let mut description = if sources.is_empty() {
+        "open to any source".to_string()
+    } else {
+        format!("allowed from {}", sources.join(", "))
+    };
+    if let Some(window) = &external.time {
+        let window = serde_json::to_string(window).unwrap_or_default();
+        description.push_str(&format!(" during {}", window));
+    }
+    description
+}
+
+fn published(container: &Container) -> impl Iterator<Item = &PortMapping> {
+    container
+        .ports
+        .iter()
+        .filter(|port| port.host_port.is_some())
+}
+
+fn restriction(container: &Container) -> String {
+    describe_restriction(
+        container
+            .config
+            .as_ref()
+            .map(|config| &config.mapped_ports.external),
+    )
+}
+
+/// Ports `container` shares with `others` on a different host address under
+/// a different restriction. Only containers with harborshield enabled are
+/// compared, and each port is reported once per other container
+pub fn overlapping_ports(container: &Container, others: &[Container]) -> Vec<PortOverlap> {
+    let mut overlaps = Vec::new();
+    if !container.enabled {
+        return overlaps;
+    }
+    let ours = restriction(container);
+
+    for other in others {
+        if other.id == container.id || !other.enabled {
+            continue;
+        }
+        let theirs = restriction(other);
+        if theirs == ours {
+            continue;
+        }
+        for port in published(container) {
+            let shared = published(other).find(|candidate| {
+                candidate.host_port == port.host_port
+                    && candidate.protocol == port.protocol
+                    && candidate.host_ip != port.host_ip
+            });
+            let Some(shared) = shared else {
+                continue;
+            };
+            let overlap = PortOverlap {
+                port: port.host_port.unwrap_or_default(),
+                protocol: port.protocol.clone(),
+                container: container.name.clone(),
+                host_ip: port.host_ip,
+                restriction: ours.clone(),
+                other: other.name.clone(),
+                other_host_ip: shared.host_ip,
+                other_restriction: theirs.clone(),
+            };
+            // IPv4 and IPv6 bindings of one port are a single publication
+            let seen = overlaps.iter().any(|o: &PortOverlap| {
+                o.port == overlap.port && o.protocol == overlap.protocol && o.other == overlap.other
+            });
+            if !seen {
+                overlaps.push(overlap);
+            }
+        }
+    }
+    overlaps
+}
+
+/// Every overlap among `containers`, each pair reported once
+pub fn all_overlaps(containers: &[Container]) -> Vec<PortOverlap> {
+    let mut overlaps = Vec::new();
+    for (i, container) in containers.iter().enumerate() {
+        overlaps.extend(overlapping_ports(container, &containers[i + 1..]));
+    }
+    overlaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::config::Config;
+
+    fn container(name: &str, host_ip: Option<&str>, rules: &str) -> Container {
+        Container::builder()
+            .id(name.to_string())
+            .name(name.to_string())
+            .ports(vec![
+                PortMapping::builder()
+                    .container_port(8443)
+                    .host_port(443)
+                    .maybe_host_ip(host_ip.map(|ip| ip.parse().unwrap()))
+                    .protocol("tcp".to_string())
+                    .build(),
+            ])
+            .config(serde_yaml::from_str::<Config>(rules).unwrap())
+            .build()
+    }
+
+    #[test]
+    fn test_overlapping_ports() {
+        let office = container(
+            "intranet",
+            Some("10.0.0.5"),
+            "mapped_ports:\n  external:\n    allow: true\n    ips: [192.168.10.0/24]",
+        );
+        let public = container("shop", None, "mapped_ports:\n  external:\n    allow: true");
+        let overlaps = all_overlaps(&[office.clone(), public.clone()]);
+        assert_eq!(overlaps.len(), 1);
+        let overlap = &overlaps[0];
+        assert_eq!((overlap.port, overlap.other.as_str()), (443, "shop"));
+        assert_eq!(overlap.restriction, "allowed from 192.168.10.0/24");
+        assert_eq!(overlap.other_restriction, "open to any source");
+        assert!(overlap.to_string().contains("on all addresses"));
+
+        // The same restriction behind both addresses behaves consistently
+        let mirror = container(
+            "intranet-b",
+            Some("10.0.0.6"),
+            "mapped_ports:\n  external:\n    allow: true\n    ips: [192.168.10.0/24]",
+        );
+        assert!(overlapping_ports(&office, &[mirror]).is_empty());
+
+        let mut disabled = public;
+        disabled.enabled = false;
+        assert!(overlapping_ports(&office, &[disabled]).is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+//! Getting the event stream back after the Docker daemon restarts.
+//!
+//! The stream only fails once it is read, so a daemon that is down shows up
+//! as a stream that ends straight away. Before opening another, the listener
+//! waits here for the daemon to answer a ping, with the delay between pings
+//! doubling up to [`MAX_DELAY`]. Once back, containers may have restarted
+//! with other addresses, so the listener has everything resynced.
+
+use crate::runtime::ContainerRuntime;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+pub const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How long a stream has to stay up before the delay starts over
+pub const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Exponential backoff between reconnect attempts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    attempt: u32,
+    initial: Duration,
+    max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(INITIAL_DELAY, MAX_DELAY)
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            attempt: 0,
+            initial,
+            max,
+        }
+    }
+
+    /// The delay before the next attempt
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        delay
+    }
+
+    /// Attempts made since the last reset
+    pub fn attempts(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Wait until `runtime` answers a ping, backing off between tries. False
+/// when `cancel` fires first
+pub async fn wait_for_daemon(
+    runtime: &dyn ContainerRuntime,
+    backoff: &mut Backoff,
+    cancel: &CancellationToken,
+) -> bool {
+    loop {
+        let delay = backoff.next_delay();
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return false,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        match runtime.ping().await {
+            Ok(()) => {
+                info!(
+                    "Docker daemon is back after {} attempts",
+                    backoff.attempts()
+                );
+                return true;
+            }
+            Err(e) => warn!(
+                "Docker daemon still unavailable after {} attempts: {}",
+                backoff.attempts(),
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(backoff.attempts(), 6);
+
+        // Far past the cap nothing overflows
+        for _ in 0..100 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), Duration::from_secs(10));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+
+        let runtime = crate::runtime::fake::FakeRuntime::default();
+        let mut backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1));
+        let cancel = CancellationToken::new();
+        assert!(wait_for_daemon(&runtime, &mut backoff, &cancel).await);
+        cancel.cancel();
+        assert!(!wait_for_daemon(&runtime, &mut backoff, &cancel).await);
+    }
+}
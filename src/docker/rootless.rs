@@ -0,0 +1,180 @@
+//! Rootless Docker, where RootlessKit runs containers in its own network
+//! namespace behind slirp4netns or pasta.
+//!
+//! The host's filter table never sees container traffic then: there is no
+//! DNAT, no bridge forwarding and no DOCKER-USER chain. What it does see is
+//! the host side of published ports, which RootlessKit's port driver accepts
+//! like any local service. Harborshield enforces `mapped_ports` there and
+//! reports every other label feature as unavailable in `/status` rather
+//! than rendering rules that never match.
+
+use crate::docker::config::{Config, ConfigVerdict};
+use crate::docker::container::Container;
+use serde::Serialize;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ROOTLESS: AtomicBool = AtomicBool::new(false);
+
+static STATUS: RwLock<Vec<ContainerSupport>> = RwLock::new(Vec::new());
+
+/// Whether the daemon's security options include `name=rootless`
+pub fn detect(security_options: &[String]) -> bool {
+    security_options
+        .iter()
+        .any(|option| option.split(',').any(|field| field == "name=rootless"))
+}
+
+pub fn set_rootless(rootless: bool) {
+    ROOTLESS.store(rootless, Ordering::Relaxed);
+}
+
+pub fn is_rootless() -> bool {
+    ROOTLESS.load(Ordering::Relaxed)
+}
+
+/// What harborshield can enforce for one container under rootless Docker
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContainerSupport {
+    pub container: String,
+    /// Published host ports, as `port/proto`
+    pub ports: Vec<String>,
+    pub enforced: Vec<&'static str>,
+    pub unavailable: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RootlessStatus {
+    pub containers: Vec<ContainerSupport>,
+}
+
+fn custom_verdict(verdict: &ConfigVerdict) -> bool {
+    !verdict.chain.is_empty() || verdict.queue != 0
+}
+
+/// Split the features `config` uses into those enforced on the host side
+/// of `container`'s published ports and those that cannot be
+pub fn support(container: &Container, config: &Config) -> ContainerSupport {
+    let ports: Vec<String> = container
+        .ports
+        .iter()
+        .filter_map(|port| Some(format!("{}/{}", port.host_port?, port.protocol)))
+        .collect();
+
+    let mut enforced = Vec::new();
+    if !ports.is_empty() {
+        enforced.push("mapped_ports.external");
+        enforced.push("mapped_ports.localhost");
+    }
+
+    let external = &config.mapped_ports.external;
+    let localhost = &config.mapped_ports.localhost;
+    let mut unavailable = Vec::new();
+    for (used, feature) in [
+        (!config.output.is_empty(), "output"),
+        (config.storage_egress, "storage_egress"),
+        (config.quarantine.is_some(), "quarantine"),
+        (!external.rdns.is_empty(), "mapped_ports.external.rdns"),
+        (
+            !external.log_prefix.is_empty(),
+            "mapped_ports.external.log_prefix",
+        ),
+        (
+            custom_verdict(&external.verdict),
+            "mapped_ports.external.verdict",
+        ),
+        (
+            !localhost.log_prefix.is_empty(),
+            "mapped_ports.localhost.log_prefix",
+        ),
+        (
+            custom_verdict(&localhost.verdict),
+            "mapped_ports.localhost.verdict",
+        ),
+        (
+            localhost.include_gateway_ips,
+            "mapped_ports.localhost.include_gateway_ips",
+        ),
+        (localhost.enable_nat, "mapped_ports.localhost.enable_nat"),
+    ] {
+        if used {
+            unavailable.push(feature);
+        }
+    }
+
+    ContainerSupport {
+        container: container.name.clone(),
+        ports,
+        enforced,
+        unavailable,
+    }
+}
+
+pub fn set_status(containers: Vec<ContainerSupport>) {
+    if let Ok(mut status) = STATUS.write() {
+        *status = containers;
+    }
+}
+
+/// Per-container support for `/status`; `None` unless Docker is rootless
+pub fn status() -> Option<RootlessStatus> {
+    if !is_rootless() {
+        return None;
+    }
+    let containers = STATUS.read().map(|s| s.clone()).unwrap_or_default();
+    Some(RootlessStatus { containers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::PortMapping;
+
+    #[test]
+    fn test_support() {
+        assert!(detect(&[
+            "name=seccomp,profile=builtin".to_string(),
+            "name=rootless".to_string(),
+        ]));
+        assert!(!detect(&["name=seccomp,profile=builtin".to_string()]));
+
+        let config: Config = serde_yaml::from_str(
+            r#"
+mapped_ports:
+  external:
+    allow: true
+    ips: ["192.168.1.0/24"]
+    rdns: ["*.example.com"]
+output:
+  - ips: ["10.0.0.1"]
+    proto: tcp
+    dst_ports: [5432]
+"#,
+        )
+        .unwrap();
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("web".to_string())
+            .ports(vec![
+                PortMapping::builder()
+                    .container_port(80)
+                    .host_port(8080)
+                    .protocol("tcp".to_string())
+                    .build(),
+                PortMapping::builder()
+                    .container_port(9000)
+                    .protocol("tcp".to_string())
+                    .build(),
+            ])
+            .config(config.clone())
+            .build();
+
+        let support = support(&container, &config);
+        assert_eq!(support.ports, vec!["8080/tcp"]);
+        assert!(support.enforced.contains(&"mapped_ports.external"));
+        assert_eq!(
+            support.unavailable,
+            vec!["output", "mapped_ports.external.rdns"]
+        );
+    }
+}
@@ -84,6 +84,43 @@ mod tests {
         assert!(!info.uses_host_network);
     }
 
+    #[test]
+    fn test_container_info_dual_stack() {
+        let mut inspect = create_test_inspect_response("test123", "test-container");
+        let networks = inspect.network_settings.as_mut().unwrap().networks.as_mut();
+        let bridge = networks.unwrap().get_mut("bridge").unwrap();
+        bridge.global_ipv6_address = Some("fd00::2".to_string());
+        let info = Container::from_inspect(inspect.clone()).unwrap();
+        assert_eq!(
+            info.ip_addresses(false),
+            vec![IpAddr::V4(Ipv4Addr::new(172, 17, 0, 2))]
+        );
+        assert_eq!(
+            info.ip_addresses(true),
+            vec!["fd00::2".parse::<IpAddr>().unwrap()]
+        );
+        assert!(info.ipv6_enabled());
+
+        // Without IPv6 Docker leaves the address empty
+        let networks = inspect.network_settings.as_mut().unwrap().networks.as_mut();
+        networks
+            .unwrap()
+            .get_mut("bridge")
+            .unwrap()
+            .global_ipv6_address = Some(String::new());
+        inspect
+            .config
+            .as_mut()
+            .unwrap()
+            .labels
+            .as_mut()
+            .unwrap()
+            .insert("harborshield.ipv6".to_string(), "false".to_string());
+        let info = Container::from_inspect(inspect).unwrap();
+        assert!(info.ip_addresses(true).is_empty());
+        assert!(!info.ipv6_enabled());
+    }
+
     #[test]
     fn test_container_info_missing_id() {
         let mut inspect = create_test_inspect_response("test", "test");
@@ -0,0 +1,184 @@
+//! Storage servers behind network-backed volumes.
+//!
+//! Docker's `local` driver mounts NFS and SMB/CIFS shares from its volume
+//! options, e.g.
+//! `docker volume create --opt type=nfs --opt o=addr=10.0.0.5,nfsvers=4 --opt device=:/export data`.
+//! The server is read from `addr=` in `o`, or from the host part of
+//! `device` when `addr` is missing.
+
+use crate::docker::config::Protocol;
+use bollard::models::{ContainerInspectResponse, MountPointTypeEnum, Volume};
+use std::net::IpAddr;
+
+const NFS_PORT: u16 = 2049;
+/// rpcbind, which NFSv2 and v3 clients ask for the mount daemon's port
+const RPCBIND_PORT: u16 = 111;
+const SMB_PORT: u16 = 445;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageProtocol {
+    /// `v4` when the options pin NFSv4, which needs nothing but port 2049
+    Nfs {
+        v4: bool,
+    },
+    Smb,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageBackend {
+    pub volume: String,
+    pub protocol: StorageProtocol,
+    /// Host name or address, as written in the volume options
+    pub server: String,
+    /// Addresses `server` resolved to when the container was inspected
+    pub addrs: Vec<IpAddr>,
+}
+
+impl StorageBackend {
+    /// Ports the share is reached on
+    pub fn ports(&self) -> Vec<(Protocol, u16)> {
+        match self.protocol {
+            StorageProtocol::Nfs { v4: true } => vec![(Protocol::Tcp, NFS_PORT)],
+            StorageProtocol::Nfs { v4: false } => vec![
+                (Protocol::Tcp, NFS_PORT),
+                (Protocol::Udp, NFS_PORT),
+                (Protocol::Tcp, RPCBIND_PORT),
+                (Protocol::Udp, RPCBIND_PORT),
+            ],
+            StorageProtocol::Smb => vec![(Protocol::Tcp, SMB_PORT)],
+        }
+    }
+}
+
+/// Names of the named volumes a container mounts
+pub fn volume_names(inspect: &ContainerInspectResponse) -> Vec<String> {
+    inspect
+        .mounts
+        .iter()
+        .flatten()
+        .filter(|mount| mount.typ == Some(MountPointTypeEnum::VOLUME))
+        .filter_map(|mount| mount.name.clone())
+        .collect()
+}
+
+/// Value of `key` in a comma separated mount option string
+fn mount_option<'a>(options: &'a str, key: &str) -> Option<&'a str> {
+    options.split(',').find_map(|option| {
+        option
+            .split_once('=')
+            .filter(|(name, _)| name.trim() == key)
+            .map(|(_, value)| value.trim())
+    })
+}
+
+/// The share a volume mounts, if it is an NFS or SMB one. `addrs` is left
+/// for the caller to resolve
+pub fn backend_from_volume(volume: &Volume) -> Option<StorageBackend> {
+    if volume.driver != "local" {
+        return None;
+    }
+    let kind = volume.options.get("type")?.to_ascii_lowercase();
+    let options = volume.options.get("o").map(String::as_str).unwrap_or("");
+    let device = volume
+        .options
+        .get("device")
+        .map(String::as_str)
+        .unwrap_or("");
+
+    let (protocol, device_host) = match kind.as_str() {
+        "nfs" | "nfs4" => {
+            let version = mount_option(options, "nfsvers").or(mount_option(options, "vers"));
+            let v4 = kind == "nfs4" || version.is_some_and(|v| v.starts_with('4'));
+            // "server:/export"
+            let host = device.split_once(":/").map(|(host, _)| host);
+            (StorageProtocol::Nfs { v4 }, host)
+        }
+        "cifs" | "smb3" => {
+            // "//server/share"
+            let host = device
+                .strip_prefix("//")
+                .and_then(|rest| rest.split('/').next());
+            (StorageProtocol::Smb, host)
+        }
+        _ => return None,
+    };
+
+    let server = mount_option(options, "addr")
+        .or(device_host)
+        .filter(|server| !server.is_empty())?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    Some(StorageBackend {
+        volume: volume.name.clone(),
+        protocol,
+        server,
+        addrs: Vec::new(),
+    })
+}
+
+/// Addresses of a storage server given by name or address
+pub async fn resolve_server(server: &str) -> std::io::Result<Vec<IpAddr>> {
+    if let Ok(addr) = server.parse::<IpAddr>() {
+        return Ok(vec![addr]);
+    }
+    let mut addrs: Vec<IpAddr> = tokio::net::lookup_host((server, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect();
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn volume(options: &[(&str, &str)]) -> Volume {
+        Volume {
+            name: "data".to_string(),
+            driver: "local".to_string(),
+            options: options
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backend_from_volume() {
+        let nfs = backend_from_volume(&volume(&[
+            ("type", "nfs"),
+            ("o", "addr=10.0.0.5,rw,nfsvers=4.1"),
+            ("device", ":/export/data"),
+        ]))
+        .unwrap();
+        assert_eq!(nfs.server, "10.0.0.5");
+        assert_eq!(nfs.protocol, StorageProtocol::Nfs { v4: true });
+        assert_eq!(nfs.ports(), vec![(Protocol::Tcp, 2049)]);
+
+        let nfs3 = backend_from_volume(&volume(&[
+            ("type", "nfs"),
+            ("device", "filer.lan:/export/data"),
+        ]))
+        .unwrap();
+        assert_eq!(nfs3.server, "filer.lan");
+        assert_eq!(nfs3.ports().len(), 4);
+
+        let smb = backend_from_volume(&volume(&[
+            ("type", "cifs"),
+            ("o", "username=app,vers=3.0"),
+            ("device", "//fileserver/share"),
+        ]))
+        .unwrap();
+        assert_eq!(smb.server, "fileserver");
+        assert_eq!(smb.ports(), vec![(Protocol::Tcp, 445)]);
+
+        assert!(backend_from_volume(&volume(&[])).is_none());
+        assert!(backend_from_volume(&volume(&[("type", "tmpfs"), ("device", "tmpfs")])).is_none());
+        assert!(backend_from_volume(&volume(&[("type", "nfs"), ("device", "/export")])).is_none());
+    }
+}
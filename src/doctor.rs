@@ -0,0 +1,225 @@
+//! Environment checks for `harborshield doctor`.
+
+use crate::database::{AuditEntry, DB, audit};
+use crate::docker::{labels, mesh, ports};
+use crate::output::{Cell, Color, Column, Render, Table};
+use crate::runtime::ContainerRuntime;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Warn,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub check: &'static str,
+    pub subject: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub findings: Vec<Finding>,
+}
+
+impl DoctorReport {
+    pub fn has_warnings(&self) -> bool {
+        self.findings.iter().any(|f| f.status == Status::Warn)
+    }
+}
+
+impl Render for DoctorReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CHECK"),
+            Column::left("SUBJECT"),
+            Column::left("STATUS"),
+            Column::left("DETAIL"),
+        ]);
+        for finding in &self.findings {
+            let status = match finding.status {
+                Status::Ok => Cell::colored("ok", Color::Green),
+                Status::Warn => Cell::colored("warn", Color::Yellow),
+            };
+            table.row(vec![
+                finding.check.into(),
+                finding.subject.clone().into(),
+                status,
+                finding.detail.clone().into(),
+            ]);
+        }
+        if self.findings.is_empty() {
+            table.footer("no harborshield-enabled containers are running");
+        }
+        table
+    }
+}
+
+/// Describe how a sidecar interacts with a container's rules
+pub fn mesh_finding(name: &str, hints: &[String], redirects: &[mesh::Redirect]) -> Finding {
+    let (status, detail) = if !redirects.is_empty() {
+        let ports: Vec<String> = redirects
+            .iter()
+            .filter_map(|r| r.to_port)
+            .map(|p| p.to_string())
+            .collect();
+        (
+            Status::Warn,
+            format!(
+                "traffic is transparently redirected to a proxy (ports {}); rules on published ports see proxy traffic, not the application",
+                if ports.is_empty() {
+                    "unknown".to_string()
+                } else {
+                    ports.join(", ")
+                }
+            ),
+        )
+    } else if !hints.is_empty() {
+        (
+            Status::Warn,
+            format!(
+                "looks sidecar-proxied ({}); no redirect rules could be read",
+                hints.join(", ")
+            ),
+        )
+    } else {
+        (Status::Ok, "no sidecar redirection".to_string())
+    };
+
+    Finding {
+        check: "service-mesh",
+        subject: name.to_string(),
+        status,
+        detail,
+    }
+}
+
+/// Look for mesh sidecars redirecting traffic of harborshield-enabled
+/// containers
+pub async fn check_service_mesh(docker: &dyn ContainerRuntime) -> crate::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+
+    for summary in docker.list_containers().await? {
+        let Some(id) = summary.id else {
+            continue;
+        };
+        let inspect = docker.inspect_container(&id).await?;
+        let enabled = inspect
+            .config
+            .as_ref()
+            .and_then(|c| c.labels.as_ref())
+            .is_some_and(labels::is_enabled);
+        if !enabled {
+            continue;
+        }
+
+        let name = inspect
+            .name
+            .as_deref()
+            .unwrap_or(&id)
+            .trim_start_matches('/')
+            .to_string();
+        let hints = mesh::sidecar_hints(&inspect);
+        let redirects = match inspect.state.as_ref().and_then(|s| s.pid) {
+            Some(pid) if pid > 0 => mesh::netns_redirects(pid).await.unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        findings.push(mesh_finding(&name, &hints, &redirects));
+    }
+
+    Ok(findings)
+}
+
+/// Look for host ports that harborshield-enabled containers share on
+/// different addresses under different source restrictions
+pub async fn check_published_ports(docker: &dyn ContainerRuntime) -> crate::Result<Vec<Finding>> {
+    let mut containers = Vec::new();
+    for summary in docker.list_containers().await? {
+        let Some(id) = summary.id else {
+            continue;
+        };
+        let container = docker.try_get_container_by_id(&id).await?;
+        if container.enabled && container.ports.iter().any(|p| p.host_port.is_some()) {
+            containers.push(container);
+        }
+    }
+
+    let mut findings: Vec<Finding> = ports::all_overlaps(&containers)
+        .into_iter()
+        .map(|overlap| Finding {
+            check: "published-ports",
+            subject: format!("{}/{}", overlap.port, overlap.protocol),
+            status: Status::Warn,
+            detail: overlap.to_string(),
+        })
+        .collect();
+    if findings.is_empty() && !containers.is_empty() {
+        findings.push(Finding {
+            check: "published-ports",
+            subject: "all".to_string(),
+            status: Status::Ok,
+            detail: "no host port is shared under different source restrictions".to_string(),
+        });
+    }
+    Ok(findings)
+}
+
+/// Allow rules the `--validate-rules-every` sampler found letting flows
+/// through uncounted since `since`, once per container and rule
+pub async fn check_sampled_rules(db: &DB, since: i64) -> crate::Result<Vec<Finding>> {
+    Ok(sampled_rule_findings(&suspect_rules(db, since).await?))
+}
+
+/// `rule_suspect` audit entries since `since`, the latest per container
+/// and detail
+pub async fn suspect_rules(db: &DB, since: i64) -> crate::Result<Vec<AuditEntry>> {
+    let mut suspects: Vec<AuditEntry> = Vec::new();
+    for entry in audit::list_since(db, since).await?.into_iter().rev() {
+        let seen = suspects
+            .iter()
+            .any(|s| s.container_name == entry.container_name && s.detail == entry.detail);
+        if entry.kind == audit::KIND_RULE_SUSPECT && !seen {
+            suspects.push(entry);
+        }
+    }
+    suspects.reverse();
+    Ok(suspects)
+}
+
+pub fn sampled_rule_findings(suspects: &[AuditEntry]) -> Vec<Finding> {
+    suspects
+        .iter()
+        .map(|entry| Finding {
+            check: "rule-sampling",
+            subject: entry.container_name.clone().unwrap_or_default(),
+            status: Status::Warn,
+            detail: entry.detail.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mesh_finding() {
+        let redirect = mesh::Redirect {
+            chain: "ISTIO_IN_REDIRECT".to_string(),
+            to_port: Some(15006),
+        };
+        let finding = mesh_finding("web", &[], &[redirect]);
+        assert_eq!(finding.status, Status::Warn);
+        assert!(finding.detail.contains("15006"));
+
+        let finding = mesh_finding("web", &["mesh label linkerd.io/inject".to_string()], &[]);
+        assert_eq!(finding.status, Status::Warn);
+
+        assert_eq!(mesh_finding("db", &[], &[]).status, Status::Ok);
+    }
+}
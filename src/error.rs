@@ -171,6 +171,29 @@ pub enum Error {
     NftablesModule(#[from] crate::nftables::error::NftablesError),
 }
 
+impl From<harborshield_core::Error> for Error {
+    fn from(e: harborshield_core::Error) -> Self {
+        use harborshield_core::Error as Core;
+        match e {
+            Core::Config {
+                message,
+                location,
+                suggestion,
+            } => Self::Config {
+                message,
+                location,
+                suggestion,
+            },
+            Core::InvalidIpAddress { input, reason } => Self::InvalidIpAddress { input, reason },
+            Core::Validation(e) => Self::ValidationModule(e),
+            Core::Yaml(e) => Self::Yaml(e),
+            Core::Json(e) => Self::Json(e),
+            Core::Nftables(e) => Self::nftables(e.to_string()),
+            e @ Core::Rules(_) => Self::config(e.to_string()),
+        }
+    }
+}
+
 // Helper methods for creating errors with context
 impl Error {
     // NFTables error constructors
@@ -0,0 +1,364 @@
+//! The JSON events harborshield sends to other systems: event bus messages
+//! and webhook posts.
+//!
+//! Every event carries `schema_version` and `event` next to its own fields.
+//! [`EVENTS`] lists each event and its fields, and [`schema`] turns that into
+//! the JSON Schema served at `/schema/events` and printed by
+//! `harborshield export event-schema`.
+//!
+//! Within a schema version, events and fields are only ever added: none is
+//! removed or renamed, and no field changes type. Consumers should ignore
+//! events and fields they don't know. A change that breaks this bumps
+//! [`SCHEMA_VERSION`].
+
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An event as sent: its own fields next to `schema_version` and `event`
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a, T> {
+    pub schema_version: u32,
+    pub event: &'a str,
+    #[serde(flatten)]
+    pub data: &'a T,
+}
+
+pub fn envelope<'a, T: Serialize>(event: &'a str, data: &'a T) -> Envelope<'a, T> {
+    Envelope {
+        schema_version: SCHEMA_VERSION,
+        event,
+        data,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Integer,
+    Boolean,
+    Object,
+    Array,
+}
+
+impl FieldType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::String => "string",
+            FieldType::Integer => "integer",
+            FieldType::Boolean => "boolean",
+            FieldType::Object => "object",
+            FieldType::Array => "array",
+        }
+    }
+}
+
+/// One field of an event
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    pub kind: FieldType,
+    /// Whether the field may be `null`; every field is always present
+    pub nullable: bool,
+    pub description: &'static str,
+}
+
+const fn field(name: &'static str, kind: FieldType, description: &'static str) -> FieldSpec {
+    FieldSpec {
+        name,
+        kind,
+        nullable: false,
+        description,
+    }
+}
+
+const fn nullable(name: &'static str, kind: FieldType, description: &'static str) -> FieldSpec {
+    FieldSpec {
+        name,
+        kind,
+        nullable: true,
+        description,
+    }
+}
+
+/// One kind of event
+#[derive(Debug, Clone, Copy)]
+pub struct EventSpec {
+    /// Value of the `event` field
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Where the event is sent
+    pub sent_to: &'static str,
+    pub fields: &'static [FieldSpec],
+}
+
+pub const EVENT_AUDIT: &str = "audit";
+pub const EVENT_STATS: &str = "stats";
+pub const EVENT_QUARANTINED: &str = "quarantined";
+pub const EVENT_RECONCILE: &str = "reconcile";
+pub const EVENT_REPORT: &str = "report";
+pub const EVENT_UPDATE_AVAILABLE: &str = "update_available";
+
+/// Every event harborshield sends. New events and fields are added here,
+/// and never removed within a schema version
+pub const EVENTS: &[EventSpec] = &[
+    EventSpec {
+        name: EVENT_AUDIT,
+        description: "An entry written to the audit log",
+        sent_to: "event bus, <prefix>.audit.<kind>[.<container>]",
+        fields: &[
+            field("id", FieldType::Integer, "Audit log row, 0 when not stored"),
+            field("ts", FieldType::Integer, "Unix seconds"),
+            field(
+                "kind",
+                FieldType::String,
+                "What happened, such as drift, quarantined or unused_allow",
+            ),
+            nullable(
+                "container_name",
+                FieldType::String,
+                "Container the entry is about",
+            ),
+            field("detail", FieldType::String, "Human-readable description"),
+            field(
+                "repaired",
+                FieldType::Boolean,
+                "Whether harborshield fixed what it found",
+            ),
+        ],
+    },
+    EventSpec {
+        name: EVENT_STATS,
+        description: "Traffic counted for a container in one stats interval, or a rule change",
+        sent_to: "event bus, <prefix>.stats.<kind>.<container>",
+        fields: &[
+            field("ts", FieldType::Integer, "Unix seconds"),
+            field("container_name", FieldType::String, "Container counted"),
+            field("kind", FieldType::String, "drop, accept or rule_change"),
+            field("packets", FieldType::Integer, "Packets in the interval"),
+            field("bytes", FieldType::Integer, "Bytes in the interval"),
+        ],
+    },
+    EventSpec {
+        name: EVENT_QUARANTINED,
+        description: "A container was quarantined for exceeding its drop threshold",
+        sent_to: "--quarantine-webhook",
+        fields: &[
+            field("container", FieldType::String, "Container identity"),
+            field("drops", FieldType::Integer, "Packets dropped in the window"),
+            field("window_seconds", FieldType::Integer, "Policy window"),
+            field("quarantined_at", FieldType::Integer, "Unix seconds"),
+            field("release", FieldType::String, "Command that lifts it"),
+        ],
+    },
+    EventSpec {
+        name: EVENT_RECONCILE,
+        description: "Outcome of a nightly reconcile",
+        sent_to: "--reconcile-webhook",
+        fields: &[
+            field("started_at", FieldType::Integer, "Unix seconds"),
+            field("finished_at", FieldType::Integer, "Unix seconds"),
+            field(
+                "chains_checked",
+                FieldType::Integer,
+                "Container chains compared",
+            ),
+            field("repair", FieldType::Boolean, "Whether drift was repaired"),
+            field(
+                "drift",
+                FieldType::Array,
+                "Missing and orphaned chains, with kind, chain, container_id, container_name and repaired",
+            ),
+        ],
+    },
+    EventSpec {
+        name: EVENT_REPORT,
+        description: "The daily consistency report",
+        sent_to: "--report-webhook",
+        fields: &[
+            field("subject", FieldType::String, "One-line summary"),
+            field("body", FieldType::String, "The rendered report"),
+            field("content_type", FieldType::String, "text/plain or text/html"),
+            field(
+                "report",
+                FieldType::Object,
+                "The report as data, as with harborshield report --output json",
+            ),
+        ],
+    },
+    EventSpec {
+        name: EVENT_UPDATE_AVAILABLE,
+        description: "The release manifest lists a newer version",
+        sent_to: "--update-webhook",
+        fields: &[field(
+            "update",
+            FieldType::Object,
+            "The update status, as served at /about",
+        )],
+    },
+];
+
+fn property(kind: FieldType, nullable: bool, description: &str) -> Value {
+    let kind = if nullable {
+        json!([kind.as_str(), "null"])
+    } else {
+        json!(kind.as_str())
+    };
+    json!({ "type": kind, "description": description })
+}
+
+/// JSON Schema (draft 2020-12) of every event
+pub fn schema() -> Value {
+    let events: Vec<Value> = EVENTS
+        .iter()
+        .map(|spec| {
+            let mut properties = Map::new();
+            properties.insert(
+                "schema_version".to_string(),
+                json!({ "const": SCHEMA_VERSION }),
+            );
+            properties.insert("event".to_string(), json!({ "const": spec.name }));
+            for field in spec.fields {
+                properties.insert(
+                    field.name.to_string(),
+                    property(field.kind, field.nullable, field.description),
+                );
+            }
+            let required: Vec<&str> = ["schema_version", "event"]
+                .into_iter()
+                .chain(spec.fields.iter().map(|f| f.name))
+                .collect();
+            json!({
+                "title": spec.name,
+                "description": format!("{} (sent to {})", spec.description, spec.sent_to),
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                // Fields may be added within a version
+                "additionalProperties": true,
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "harborshield events",
+        "description": "Within a schema_version events and fields are only added; ignore unknown ones",
+        "schema_version": SCHEMA_VERSION,
+        "oneOf": events,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{AuditEntry, StatEvent, StatKind};
+    use crate::docker::config::QuarantinePolicy;
+    use crate::handlers::quarantine::QuarantineNotice;
+    use crate::handlers::reconcile::ReconcileReport;
+    use crate::handlers::report::ReportNotice;
+    use crate::report::ConsistencyReport;
+
+    fn matches(kind: FieldType, value: &Value) -> bool {
+        match kind {
+            FieldType::String => value.is_string(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Boolean => value.is_boolean(),
+            FieldType::Object => value.is_object(),
+            FieldType::Array => value.is_array(),
+        }
+    }
+
+    /// Fields of an event sent as `name` that its spec doesn't describe
+    fn check(name: &str, event: impl Serialize) -> Vec<String> {
+        let spec = EVENTS.iter().find(|spec| spec.name == name).unwrap();
+        let event = serde_json::to_value(envelope(name, &event)).unwrap();
+        let object = event.as_object().unwrap();
+        let mut problems = Vec::new();
+        for field in spec.fields {
+            match object.get(field.name) {
+                Some(Value::Null) if field.nullable => {}
+                Some(value) if matches(field.kind, value) => {}
+                other => problems.push(format!("{}.{}: {:?}", name, field.name, other)),
+            }
+        }
+        for key in object.keys() {
+            if key != "schema_version"
+                && key != "event"
+                && !spec.fields.iter().any(|f| f.name == key)
+            {
+                problems.push(format!("{}.{} is not in EVENTS", name, key));
+            }
+        }
+        problems
+    }
+
+    #[test]
+    fn test_events_match_schema() {
+        let report = ConsistencyReport::new(Vec::new(), Vec::new(), Vec::new(), 0, 86400);
+        let policy: QuarantinePolicy = serde_yaml::from_str("max_drops: 100").unwrap();
+        let problems: Vec<String> = [
+            check(
+                EVENT_AUDIT,
+                AuditEntry::builder()
+                    .ts(1)
+                    .kind("drift")
+                    .detail("missing chain hs-web-abc")
+                    .build(),
+            ),
+            check(
+                EVENT_STATS,
+                StatEvent::builder()
+                    .ts(1)
+                    .container_name("web".to_string())
+                    .kind(StatKind::Drop)
+                    .build(),
+            ),
+            check(
+                EVENT_QUARANTINED,
+                QuarantineNotice::new("web", 120, &policy, 1),
+            ),
+            check(
+                EVENT_RECONCILE,
+                ReconcileReport {
+                    started_at: 1,
+                    finished_at: 2,
+                    chains_checked: 3,
+                    repair: false,
+                    drift: Vec::new(),
+                },
+            ),
+            check(
+                EVENT_REPORT,
+                ReportNotice {
+                    subject: "harborshield".to_string(),
+                    body: String::new(),
+                    content_type: "text/plain",
+                    report: &report,
+                },
+            ),
+            check(
+                EVENT_UPDATE_AVAILABLE,
+                json!({ "update": crate::update::UpdateStatus {
+                    current: "1.0.0".to_string(),
+                    latest: Some("1.1.0".to_string()),
+                    update_available: true,
+                    security: false,
+                    release_url: None,
+                    checked_at: 1,
+                    error: None,
+                } }),
+            ),
+        ]
+        .concat();
+        assert!(problems.is_empty(), "{:#?}", problems);
+
+        let schema = schema();
+        assert_eq!(schema["oneOf"].as_array().unwrap().len(), EVENTS.len());
+        assert_eq!(
+            schema["oneOf"][0]["properties"]["container_name"]["type"],
+            json!(["string", "null"])
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! `harborshield events export`: drop statistics and the audit log as CSV or
+//! Parquet, for pandas, DuckDB and the like.
+//!
+//! Drops come from the stats rollups, so each row covers a container for an
+//! hour, or a day once the window reaches past the hourly rows kept.
+//! Columns are named and typed here rather than after the database's
+//! tables, which may change; times are UTC, in RFC 3339 in CSV and as
+//! millisecond timestamps in Parquet.
+
+pub mod parquet;
+
+use crate::Result;
+use crate::database::stats::{HOURLY_RETENTION, StatsGranularity};
+use crate::database::{DB, DbOp, DbOpResult, audit};
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExportKind {
+    /// Dropped and accepted traffic per container and hour or day
+    #[default]
+    Drops,
+    /// Audit log entries
+    Audit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    /// Unix seconds
+    Timestamp,
+    Integer,
+    Text,
+    /// Text that may be missing
+    OptionalText,
+    Boolean,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnKind,
+}
+
+impl Column {
+    pub fn new(name: &'static str, kind: ColumnKind) -> Self {
+        Self { name, kind }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Integer(i64),
+    Text(String),
+    Boolean(bool),
+    Null,
+}
+
+/// Exported rows, each with a field per column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Field>>,
+}
+
+/// Drop statistics from `since` (unix seconds) to `now`
+pub async fn drops(db: &DB, since: i64, now: i64) -> Result<Frame> {
+    let granularity = if now - since <= HOURLY_RETENTION.as_secs() as i64 {
+        StatsGranularity::Hourly
+    } else {
+        StatsGranularity::Daily
+    };
+    let start = since - since.rem_euclid(granularity.bucket_secs());
+    let buckets = match db
+        .execute(&DbOp::QueryStats {
+            granularity,
+            since: start,
+        })
+        .await?
+    {
+        DbOpResult::Stats(buckets) => buckets,
+        _ => Vec::new(),
+    };
+
+    Ok(Frame {
+        columns: vec![
+            Column::new("time", ColumnKind::Timestamp),
+            Column::new("period_secs", ColumnKind::Integer),
+            Column::new("container", ColumnKind::Text),
+            Column::new("dropped_packets", ColumnKind::Integer),
+            Column::new("dropped_bytes", ColumnKind::Integer),
+            Column::new("accepted_bytes", ColumnKind::Integer),
+            Column::new("rule_changes", ColumnKind::Integer),
+        ],
+        rows: buckets
+            .into_iter()
+            .map(|bucket| {
+                vec![
+                    Field::Integer(bucket.bucket),
+                    Field::Integer(granularity.bucket_secs()),
+                    Field::Text(bucket.container_name),
+                    Field::Integer(bucket.dropped_packets),
+                    Field::Integer(bucket.dropped_bytes),
+                    Field::Integer(bucket.accepted_bytes),
+                    Field::Integer(bucket.rule_changes),
+                ]
+            })
+            .collect(),
+    })
+}
+
+/// Audit log entries from `since` (unix seconds)
+pub async fn audit(db: &DB, since: i64) -> Result<Frame> {
+    let entries = audit::list_since(db, since).await?;
+    Ok(Frame {
+        columns: vec![
+            Column::new("id", ColumnKind::Integer),
+            Column::new("time", ColumnKind::Timestamp),
+            Column::new("kind", ColumnKind::Text),
+            Column::new("container", ColumnKind::OptionalText),
+            Column::new("detail", ColumnKind::Text),
+            Column::new("repaired", ColumnKind::Boolean),
+        ],
+        rows: entries
+            .into_iter()
+            .map(|entry| {
+                vec![
+                    Field::Integer(entry.id),
+                    Field::Integer(entry.ts),
+                    Field::Text(entry.kind),
+                    entry.container_name.map_or(Field::Null, Field::Text),
+                    Field::Text(entry.detail),
+                    Field::Boolean(entry.repaired),
+                ]
+            })
+            .collect(),
+    })
+}
+
+/// A CSV field, quoted when it has to be
+fn csv_field(column: &Column, field: &Field) -> String {
+    let text = match field {
+        Field::Integer(ts) if column.kind == ColumnKind::Timestamp => {
+            chrono::DateTime::from_timestamp(*ts, 0)
+                .map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+        }
+        Field::Integer(value) => value.to_string(),
+        Field::Text(text) => text.clone(),
+        Field::Boolean(value) => value.to_string(),
+        Field::Null => String::new(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// `frame` as RFC 4180 CSV with a header line
+pub fn csv(frame: &Frame) -> String {
+    let mut out = frame
+        .columns
+        .iter()
+        .map(|column| column.name)
+        .collect::<Vec<_>>()
+        .join(",");
+    out.push_str("\r\n");
+    for row in &frame.rows {
+        let line: Vec<String> = frame
+            .columns
+            .iter()
+            .zip(row)
+            .map(|(column, field)| csv_field(column, field))
+            .collect();
+        out.push_str(&line.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// `frame` in `format`
+pub fn encode(frame: &Frame, format: ExportFormat) -> Vec<u8> {
+    match format {
+        ExportFormat::Csv => csv(frame).into_bytes(),
+        ExportFormat::Parquet => {
+            parquet::write(frame, &format!("harborshield version {}", crate::VERSION))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv() {
+        let frame = Frame {
+            columns: vec![
+                Column::new("time", ColumnKind::Timestamp),
+                Column::new("container", ColumnKind::OptionalText),
+                Column::new("detail", ColumnKind::Text),
+            ],
+            rows: vec![
+                vec![
+                    Field::Integer(1_700_000_000),
+                    Field::Text("web".to_string()),
+                    Field::Text("rules changed, \"output\" rule 2".to_string()),
+                ],
+                vec![
+                    Field::Integer(1_700_003_600),
+                    Field::Null,
+                    Field::Text("frozen".to_string()),
+                ],
+            ],
+        };
+        assert_eq!(
+            csv(&frame),
+            "time,container,detail\r\n\
+             2023-11-14T22:13:20+00:00,web,\"rules changed, \"\"output\"\" rule 2\"\r\n\
+             2023-11-14T23:13:20+00:00,,frozen\r\n"
+        );
+    }
+}
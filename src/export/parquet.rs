@@ -0,0 +1,390 @@
+//! A minimal Parquet writer for [`Frame`]s: one row group, one uncompressed
+//! PLAIN data page per column, and the footer in Thrift's compact protocol.
+//! That is all pandas, DuckDB and Spark need to read the file.
+
+use super::{ColumnKind, Field, Frame};
+
+const MAGIC: &[u8] = b"PAR1";
+
+// parquet.thrift enums
+const TYPE_BOOLEAN: i32 = 0;
+const TYPE_INT64: i32 = 2;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const CONVERTED_UTF8: i32 = 0;
+const CONVERTED_TIMESTAMP_MILLIS: i32 = 9;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_DATA: i32 = 0;
+
+/// A Thrift value, encoded with the compact protocol
+enum Thrift {
+    I32(i32),
+    I64(i64),
+    Binary(Vec<u8>),
+    List(Vec<Thrift>),
+    Struct(Vec<(i16, Thrift)>),
+}
+
+impl Thrift {
+    fn string(value: &str) -> Self {
+        Thrift::Binary(value.as_bytes().to_vec())
+    }
+
+    fn type_id(&self) -> u8 {
+        match self {
+            Thrift::I32(_) => 5,
+            Thrift::I64(_) => 6,
+            Thrift::Binary(_) => 8,
+            Thrift::List(_) => 9,
+            Thrift::Struct(_) => 12,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Thrift::I32(value) => varint(out, zigzag(i64::from(*value))),
+            Thrift::I64(value) => varint(out, zigzag(*value)),
+            Thrift::Binary(bytes) => {
+                varint(out, bytes.len() as u64);
+                out.extend_from_slice(bytes);
+            }
+            Thrift::List(items) => {
+                // Lists here are never empty, but an element type is needed
+                let element = items.first().map_or(12, Thrift::type_id);
+                if items.len() < 15 {
+                    out.push(((items.len() as u8) << 4) | element);
+                } else {
+                    out.push(0xf0 | element);
+                    varint(out, items.len() as u64);
+                }
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Thrift::Struct(fields) => {
+                let mut last = 0;
+                for (id, value) in fields {
+                    let delta = id - last;
+                    if (1..=15).contains(&delta) {
+                        out.push(((delta as u8) << 4) | value.type_id());
+                    } else {
+                        out.push(value.type_id());
+                        varint(out, zigzag(i64::from(*id)));
+                    }
+                    value.encode(out);
+                    last = *id;
+                }
+                out.push(0);
+            }
+        }
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Physical type and converted type of a column
+fn physical(kind: ColumnKind) -> (i32, Option<i32>) {
+    match kind {
+        ColumnKind::Timestamp => (TYPE_INT64, Some(CONVERTED_TIMESTAMP_MILLIS)),
+        ColumnKind::Integer => (TYPE_INT64, None),
+        ColumnKind::Text | ColumnKind::OptionalText => (TYPE_BYTE_ARRAY, Some(CONVERTED_UTF8)),
+        ColumnKind::Boolean => (TYPE_BOOLEAN, None),
+    }
+}
+
+/// Definition levels as one RLE run per stretch of equal levels, behind
+/// their length as data pages v1 want
+fn definition_levels(fields: &[&Field]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < fields.len() {
+        let defined = !matches!(fields[i], Field::Null);
+        let run = fields[i..]
+            .iter()
+            .take_while(|field| !matches!(field, Field::Null) == defined)
+            .count();
+        varint(&mut runs, (run as u64) << 1);
+        runs.push(defined as u8);
+        i += run;
+    }
+    let mut out = (runs.len() as u32).to_le_bytes().to_vec();
+    out.extend(runs);
+    out
+}
+
+/// The PLAIN encoding of the non-null values of a column
+fn values(kind: ColumnKind, fields: &[&Field]) -> Vec<u8> {
+    let mut out = Vec::new();
+    if kind == ColumnKind::Boolean {
+        let bits: Vec<bool> = fields
+            .iter()
+            .map(|field| matches!(field, Field::Boolean(true)))
+            .collect();
+        for byte in bits.chunks(8) {
+            out.push(
+                byte.iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (bit, set)| acc | ((*set as u8) << bit)),
+            );
+        }
+        return out;
+    }
+    for field in fields {
+        match field {
+            Field::Integer(value) if kind == ColumnKind::Timestamp => {
+                out.extend_from_slice(&value.saturating_mul(1000).to_le_bytes())
+            }
+            Field::Integer(value) => out.extend_from_slice(&value.to_le_bytes()),
+            Field::Text(text) => {
+                out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                out.extend_from_slice(text.as_bytes());
+            }
+            Field::Boolean(_) | Field::Null => {}
+        }
+    }
+    out
+}
+
+/// `frame` as a Parquet file
+pub fn write(frame: &Frame, created_by: &str) -> Vec<u8> {
+    let mut file = MAGIC.to_vec();
+    let num_rows = frame.rows.len() as i64;
+
+    let mut chunks = Vec::new();
+    let mut total_size = 0;
+    if num_rows > 0 {
+        for (index, column) in frame.columns.iter().enumerate() {
+            let fields: Vec<&Field> = frame.rows.iter().map(|row| &row[index]).collect();
+            let mut page = Vec::new();
+            if column.kind == ColumnKind::OptionalText {
+                page.extend(definition_levels(&fields));
+            }
+            page.extend(values(column.kind, &fields));
+
+            let mut header = Vec::new();
+            Thrift::Struct(vec![
+                (1, Thrift::I32(PAGE_DATA)),
+                (2, Thrift::I32(page.len() as i32)),
+                (3, Thrift::I32(page.len() as i32)),
+                (
+                    5,
+                    Thrift::Struct(vec![
+                        (1, Thrift::I32(num_rows as i32)),
+                        (2, Thrift::I32(ENCODING_PLAIN)),
+                        (3, Thrift::I32(ENCODING_RLE)),
+                        (4, Thrift::I32(ENCODING_RLE)),
+                    ]),
+                ),
+            ])
+            .encode(&mut header);
+
+            let offset = file.len() as i64;
+            let size = (header.len() + page.len()) as i64;
+            file.extend(header);
+            file.extend(page);
+            total_size += size;
+
+            chunks.push(Thrift::Struct(vec![
+                (2, Thrift::I64(offset)),
+                (
+                    3,
+                    Thrift::Struct(vec![
+                        (1, Thrift::I32(physical(column.kind).0)),
+                        (
+                            2,
+                            Thrift::List(vec![
+                                Thrift::I32(ENCODING_PLAIN),
+                                Thrift::I32(ENCODING_RLE),
+                            ]),
+                        ),
+                        (3, Thrift::List(vec![Thrift::string(column.name)])),
+                        (4, Thrift::I32(CODEC_UNCOMPRESSED)),
+                        (5, Thrift::I64(num_rows)),
+                        (6, Thrift::I64(size)),
+                        (7, Thrift::I64(size)),
+                        (9, Thrift::I64(offset)),
+                    ]),
+                ),
+            ]));
+        }
+    }
+
+    let mut schema = vec![Thrift::Struct(vec![
+        (4, Thrift::string("schema")),
+        (5, Thrift::I32(frame.columns.len() as i32)),
+    ])];
+    for column in &frame.columns {
+        let (physical, converted) = physical(column.kind);
+        let repetition = match column.kind {
+            ColumnKind::OptionalText => OPTIONAL,
+            _ => REQUIRED,
+        };
+        let mut element = vec![
+            (1, Thrift::I32(physical)),
+            (3, Thrift::I32(repetition)),
+            (4, Thrift::string(column.name)),
+        ];
+        if let Some(converted) = converted {
+            element.push((6, Thrift::I32(converted)));
+        }
+        schema.push(Thrift::Struct(element));
+    }
+
+    let mut metadata = vec![
+        (1, Thrift::I32(1)),
+        (2, Thrift::List(schema)),
+        (3, Thrift::I64(num_rows)),
+    ];
+    let row_groups = if chunks.is_empty() {
+        Vec::new()
+    } else {
+        vec![Thrift::Struct(vec![
+            (1, Thrift::List(chunks)),
+            (2, Thrift::I64(total_size)),
+            (3, Thrift::I64(num_rows)),
+        ])]
+    };
+    metadata.push((4, Thrift::List(row_groups)));
+    metadata.push((6, Thrift::string(created_by)));
+
+    let mut footer = Vec::new();
+    Thrift::Struct(metadata).encode(&mut footer);
+    file.extend_from_slice(&footer);
+    file.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::Column;
+
+    #[test]
+    fn test_write() {
+        let mut out = Vec::new();
+        Thrift::Struct(vec![(1, Thrift::I32(-1)), (20, Thrift::string("ab"))]).encode(&mut out);
+        assert_eq!(out, [0x15, 0x01, 0x08, 0x28, 0x02, b'a', b'b', 0x00]);
+
+        let levels = definition_levels(&[
+            &Field::Text("a".to_string()),
+            &Field::Text("b".to_string()),
+            &Field::Null,
+        ]);
+        assert_eq!(levels, [4, 0, 0, 0, 0x04, 1, 0x02, 0]);
+
+        let frame = Frame {
+            columns: vec![
+                Column::new("time", ColumnKind::Timestamp),
+                Column::new("container", ColumnKind::OptionalText),
+            ],
+            rows: vec![
+                vec![
+                    Field::Integer(1_700_000_000),
+                    Field::Text("web".to_string()),
+                ],
+                vec![Field::Integer(1_700_003_600), Field::Null],
+            ],
+        };
+        let file = write(&frame, "harborshield");
+        assert!(file.starts_with(MAGIC) && file.ends_with(MAGIC));
+        let footer_len =
+            u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        assert!((footer_len as usize) < file.len() - 12);
+        // Timestamps are stored in milliseconds
+        let millis = 1_700_000_000_000i64.to_le_bytes();
+        assert!(file.windows(8).any(|window| window == millis));
+    }
+
+    #[test]
+    #[ignore = "Requires python3 with pyarrow"]
+    fn test_read_back() {
+        let frame = Frame {
+            columns: vec![
+                Column::new("time", ColumnKind::Timestamp),
+                Column::new("drops", ColumnKind::Integer),
+                Column::new("kind", ColumnKind::Text),
+                Column::new("container", ColumnKind::OptionalText),
+                Column::new("repaired", ColumnKind::Boolean),
+            ],
+            rows: vec![
+                vec![
+                    Field::Integer(1_700_000_000),
+                    Field::Integer(42),
+                    Field::Text("drop".to_string()),
+                    Field::Text("web".to_string()),
+                    Field::Boolean(true),
+                ],
+                vec![
+                    Field::Integer(1_700_003_600),
+                    Field::Integer(-1),
+                    Field::Text("drift".to_string()),
+                    Field::Null,
+                    Field::Boolean(false),
+                ],
+            ],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.parquet");
+        std::fs::write(&path, write(&frame, "harborshield")).unwrap();
+
+        let output = std::process::Command::new("python3")
+            .args([
+                "-c",
+                "import json, sys, pyarrow.parquet as pq\n\
+                 table = pq.read_table(sys.argv[1])\n\
+                 print(json.dumps({'schema': [str(f.type) for f in table.schema], \
+                 'rows': table.to_pylist()}, default=str))",
+            ])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let mut read: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+        // pyarrow may mark the timestamps as UTC, as the converted type implies
+        let schema = read["schema"].as_array().unwrap();
+        assert!(
+            schema[0].as_str().unwrap().starts_with("timestamp[ms"),
+            "{}",
+            read
+        );
+        assert_eq!(schema[1..], ["int64", "string", "string", "bool"]);
+        let rows = read["rows"].as_array_mut().unwrap();
+        for (row, time) in rows
+            .iter_mut()
+            .zip(["2023-11-14 22:13:20", "2023-11-14 23:13:20"])
+        {
+            let read_time = row["time"].take();
+            assert!(
+                read_time.as_str().unwrap().starts_with(time),
+                "{}",
+                read_time
+            );
+        }
+        assert_eq!(
+            read["rows"],
+            serde_json::json!([
+                {"time": null, "drops": 42, "kind": "drop", "container": "web", "repaired": true},
+                {"time": null, "drops": -1, "kind": "drift", "container": null, "repaired": false},
+            ])
+        );
+    }
+}
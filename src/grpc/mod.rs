@@ -0,0 +1,474 @@
+//! Admin gRPC service, `harborshield.admin.v1.Admin` (`proto/admin.proto`),
+//! served with `--grpc` on a unix socket by default:
+//!
+//! - `ListContainers`: tracked containers with their addresses and modes
+//! - `GetRulesForContainer`: a container's resolved rules and its chain
+//! - `TriggerReconcile`: a reconcile run, optionally repairing drift
+//! - `BlockIP`: drop all traffic from and to an address, optionally for a
+//!   while; see [`crate::nftables::blocked`]
+//!
+//! Calls are unary and spoken over cleartext HTTP/2, which `grpcurl
+//! -plaintext -proto proto/admin.proto -unix` reaches. With `--api-tokens`
+//! set every call needs `authorization: Bearer <token>` metadata. Container
+//! calls only reach the containers the token covers, and reconciling or
+//! blocking needs a token covering all of them. Without tokens those two
+//! are only served over a unix socket file, or to root or the daemon's user
+//! over an abstract socket: TCP and vsock have no permissions to rely on.
+
+pub mod wire;
+
+use crate::access::{TokenScope, Tokens};
+use crate::handlers::admin::{ContainerRules, ContainerSummary};
+use crate::handlers::reconcile::ReconcileReport;
+use crate::server::{ListenAddr, Listener, Peer};
+use crate::{Harborshield, Result};
+use bytes::Bytes;
+use h2::server::SendResponse;
+use http::{HeaderMap, HeaderValue, Request};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{debug, error, info};
+use wire::{Encoder, MAX_MESSAGE_SIZE, Value};
+
+pub const SERVICE: &str = "harborshield.admin.v1.Admin";
+
+/// Where `--grpc` listens when given no address
+pub const DEFAULT_LISTEN_ADDR: &str = "unix:/run/harborshield/admin.sock";
+
+/// gRPC status codes used by the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    NotFound = 5,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    Unimplemented = 12,
+    Internal = 13,
+    Unauthenticated = 16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Status {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// `grpc-status` and `grpc-message`, the latter percent-encoded
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", HeaderValue::from(self.code as u16));
+        if !self.message.is_empty() {
+            let encoded: String = self
+                .message
+                .bytes()
+                .map(|b| match b {
+                    b' '..=b'~' if b != b'%' => (b as char).to_string(),
+                    _ => format!("%{:02X}", b),
+                })
+                .collect();
+            if let Ok(value) = HeaderValue::from_str(&encoded) {
+                headers.insert("grpc-message", value);
+            }
+        }
+        headers
+    }
+}
+
+type Fields<'a> = [(u32, Value<'a>)];
+
+fn string_field<'a>(fields: &Fields<'a>, field: u32) -> &'a str {
+    fields
+        .iter()
+        .rev()
+        .find(|(number, _)| *number == field)
+        .and_then(|(_, value)| value.as_str())
+        .unwrap_or_default()
+}
+
+fn uint_field(fields: &Fields<'_>, field: u32) -> u64 {
+    fields
+        .iter()
+        .rev()
+        .find(|(number, _)| *number == field)
+        .and_then(|(_, value)| value.as_u64())
+        .unwrap_or_default()
+}
+
+pub fn encode_containers(containers: &[ContainerSummary]) -> Vec<u8> {
+    let mut response = Encoder::new();
+    for container in containers {
+        let mut message = Encoder::new();
+        message
+            .string(1, &container.id)
+            .string(2, &container.name)
+            .string(3, container.project.as_deref().unwrap_or_default())
+            .strings(4, &container.ips)
+            .string(5, container.mode.as_str())
+            .bool(6, container.managed)
+            .uint64(7, container.output_rules as u64);
+        response.message(1, message);
+    }
+    response.finish()
+}
+
+pub fn encode_rules(rules: &ContainerRules) -> Vec<u8> {
+    Encoder::new()
+        .string(1, &rules.name)
+        .string(2, &rules.chain)
+        .string(3, &rules.config)
+        .strings(4, &rules.chain_rules)
+        .finish()
+}
+
+pub fn encode_reconcile(report: &ReconcileReport) -> Vec<u8> {
+    Encoder::new()
+        .uint64(1, report.chains_checked as u64)
+        .uint64(2, report.drift.len() as u64)
+        .uint64(3, report.drift.iter().filter(|d| d.repaired).count() as u64)
+        .string(4, &report.summary())
+        .finish()
+}
+
+/// What the caller's token grants, when tokens are configured
+struct Caller<'a> {
+    scope: Option<&'a TokenScope>,
+    peer: Peer,
+}
+
+impl Caller<'_> {
+    fn covers(&self, name: &str, project: Option<&str>) -> bool {
+        self.scope.is_none_or(|scope| scope.covers(name, project))
+    }
+
+    fn require_unrestricted(&self, method: &str) -> std::result::Result<(), Status> {
+        match self.scope {
+            Some(scope) if !scope.unrestricted() => Err(Status::new(
+                Code::PermissionDenied,
+                format!("token {} may not call {}", scope.name, method),
+            )),
+            None if !self.peer.trusted() => Err(Status::new(
+                Code::PermissionDenied,
+                format!("{} needs --api-tokens over this listener", method),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn call(
+    handlers: &Harborshield,
+    caller: &Caller<'_>,
+    method: &str,
+    message: &[u8],
+) -> std::result::Result<Vec<u8>, Status> {
+    let fields =
+        wire::decode(message).map_err(|e| Status::new(Code::InvalidArgument, e.to_string()))?;
+    let internal = |e: crate::Error| Status::new(Code::Internal, e.to_string());
+
+    match method {
+        "ListContainers" => {
+            let mut containers = handlers.admin_containers().await;
+            containers.retain(|c| caller.covers(&c.name, c.project.as_deref()));
+            Ok(encode_containers(&containers))
+        }
+        "GetRulesForContainer" => {
+            let name = string_field(&fields, 1);
+            if name.is_empty() {
+                return Err(Status::new(Code::InvalidArgument, "container is required"));
+            }
+            let not_found =
+                || Status::new(Code::NotFound, format!("container {} is not tracked", name));
+            let rules = handlers.admin_rules(name).await.ok_or_else(not_found)?;
+            if !caller.covers(&rules.name, rules.project.as_deref()) {
+                // Not admitting the container exists to a token without it
+                return Err(not_found());
+            }
+            Ok(encode_rules(&rules))
+        }
+        "TriggerReconcile" => {
+            caller.require_unrestricted(method)?;
+            let report = handlers
+                .reconcile(uint_field(&fields, 1) != 0)
+                .await
+                .map_err(internal)?;
+            info!("Reconcile requested over gRPC: {}", report.summary());
+            Ok(encode_reconcile(&report))
+        }
+        "BlockIP" => {
+            caller.require_unrestricted(method)?;
+            let ip: Ipv4Addr = string_field(&fields, 1).parse().map_err(|_| {
+                Status::new(
+                    Code::InvalidArgument,
+                    format!("not an IPv4 address: {:?}", string_field(&fields, 1)),
+                )
+            })?;
+            let ttl = Some(uint_field(&fields, 2))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs);
+            let reason = Some(string_field(&fields, 3)).filter(|r| !r.is_empty());
+            let expires_at = handlers
+                .block_address(ip, ttl, reason)
+                .await
+                .map_err(internal)?;
+            Ok(Encoder::new()
+                .string(1, &ip.to_string())
+                .int64(2, expires_at.unwrap_or_default())
+                .finish())
+        }
+        _ => Err(Status::new(
+            Code::Unimplemented,
+            format!("unknown method {}", method),
+        )),
+    }
+}
+
+async fn read_message(
+    request: Request<h2::RecvStream>,
+) -> std::result::Result<(HeaderMap, Vec<u8>), Status> {
+    let (parts, mut body) = request.into_parts();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| Status::new(Code::Internal, e.to_string()))?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        buf.extend_from_slice(&chunk);
+        if buf.len() > MAX_MESSAGE_SIZE + 5 {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!("message larger than {} bytes", MAX_MESSAGE_SIZE),
+            ));
+        }
+    }
+    match wire::unframe(&buf) {
+        Some(Ok(message)) => Ok((parts.headers, message.to_vec())),
+        Some(Err(e)) => Err(Status::new(Code::InvalidArgument, e.to_string())),
+        None => Err(Status::new(Code::InvalidArgument, "incomplete message")),
+    }
+}
+
+fn send(
+    mut respond: SendResponse<Bytes>,
+    result: std::result::Result<Vec<u8>, Status>,
+) -> std::result::Result<(), h2::Error> {
+    let mut response = http::Response::new(());
+    response
+        .headers_mut()
+        .insert("content-type", HeaderValue::from_static("application/grpc"));
+    match result {
+        Ok(message) => {
+            let mut stream = respond.send_response(response, false)?;
+            stream.send_data(Bytes::from(wire::frame(&message)), false)?;
+            stream.send_trailers(Status::new(Code::Ok, "").headers())
+        }
+        // Trailers-only: the status goes out with the headers
+        Err(status) => {
+            response.headers_mut().extend(status.headers());
+            respond.send_response(response, true).map(drop)
+        }
+    }
+}
+
+async fn handle(
+    handlers: Harborshield,
+    tokens: Option<Arc<Tokens>>,
+    peer: Peer,
+    request: Request<h2::RecvStream>,
+    respond: SendResponse<Bytes>,
+) {
+    let path = request.uri().path().to_string();
+    let result = async {
+        let method = path
+            .strip_prefix('/')
+            .and_then(|path| path.strip_prefix(SERVICE))
+            .and_then(|path| path.strip_prefix('/'))
+            .ok_or_else(|| Status::new(Code::Unimplemented, format!("unknown service {}", path)))?;
+        let (headers, message) = read_message(request).await?;
+        let scope = match &tokens {
+            Some(tokens) => {
+                let authorization = headers
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok());
+                Some(
+                    tokens
+                        .authorize(authorization)
+                        .ok_or_else(|| Status::new(Code::Unauthenticated, "Unauthorized"))?,
+                )
+            }
+            None => None,
+        };
+        call(&handlers, &Caller { scope, peer }, method, &message).await
+    }
+    .await;
+
+    if let Err(status) = &result {
+        debug!("gRPC {} failed: {:?}", path, status);
+    }
+    if let Err(e) = send(respond, result) {
+        debug!("Failed to answer gRPC {}: {}", path, e);
+    }
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    handlers: Harborshield,
+    tokens: Option<Arc<Tokens>>,
+    peer: Peer,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut connection = match h2::server::handshake(stream).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            debug!("gRPC handshake failed: {}", e);
+            return;
+        }
+    };
+    while let Some(accepted) = connection.accept().await {
+        match accepted {
+            Ok((request, respond)) => {
+                tokio::spawn(handle(
+                    handlers.clone(),
+                    tokens.clone(),
+                    peer,
+                    request,
+                    respond,
+                ));
+            }
+            Err(e) => {
+                debug!("gRPC connection closed: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// The service's socket, bound before the process is sandboxed
+pub struct AdminListener(Listener);
+
+impl AdminListener {
+    pub async fn bind(addr: &ListenAddr) -> Result<Self> {
+        if let ListenAddr::Unix(path) = addr
+            && let Some(dir) = path.parent()
+        {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self(Listener::bind(addr).await?))
+    }
+}
+
+pub struct AdminServer {
+    listener: Listener,
+    handlers: Harborshield,
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl AdminServer {
+    pub fn new(listener: AdminListener, handlers: Harborshield, tokens: Option<Tokens>) -> Self {
+        Self {
+            listener: listener.0,
+            handlers,
+            tokens: tokens.map(Arc::new),
+        }
+    }
+
+    pub async fn serve(self) {
+        info!(
+            "Serving the {} gRPC service on {}",
+            SERVICE,
+            self.listener.describe()
+        );
+        let cancelled = self.handlers.cancellation_token.clone();
+        loop {
+            let accepted = tokio::select! {
+                _ = cancelled.cancelled() => break,
+                accepted = self.accept() => accepted,
+            };
+            if let Err(e) = accepted {
+                error!("Error accepting gRPC connection: {}", e);
+            }
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<()> {
+        let (handlers, tokens) = (self.handlers.clone(), self.tokens.clone());
+        match &self.listener {
+            Listener::Tcp(l) => {
+                let (stream, _) = l.accept().await?;
+                tokio::spawn(serve_connection(stream, handlers, tokens, Peer::Network));
+            }
+            Listener::Unix(l) => {
+                let (stream, _) = l.accept().await?;
+                let peer = Peer::of_unix(&stream);
+                tokio::spawn(serve_connection(stream, handlers, tokens, peer));
+            }
+            #[cfg(target_os = "linux")]
+            Listener::Vsock(l) => {
+                let stream = l.accept().await?;
+                tokio::spawn(serve_connection(stream, handlers, tokens, Peer::Network));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::EnforcementMode;
+
+    #[test]
+    fn test_caller_changes() {
+        let caller = |peer| Caller { scope: None, peer };
+        for trusted in [Peer::SocketFile, Peer::Abstract(Some(0))] {
+            assert!(caller(trusted).require_unrestricted("BlockIP").is_ok());
+        }
+        for untrusted in [
+            Peer::Network,
+            Peer::Abstract(None),
+            Peer::Abstract(Some(65534)),
+        ] {
+            let refused = caller(untrusted)
+                .require_unrestricted("BlockIP")
+                .unwrap_err();
+            assert_eq!(refused.code, Code::PermissionDenied);
+        }
+    }
+
+    #[test]
+    fn test_encode_containers() {
+        let message = encode_containers(&[ContainerSummary {
+            id: "0123456789ab".to_string(),
+            name: "web".to_string(),
+            project: None,
+            ips: vec!["172.17.0.2".to_string()],
+            mode: EnforcementMode::Permissive,
+            managed: true,
+            output_rules: 2,
+        }]);
+        let fields = wire::decode(&message).unwrap();
+        let Value::Bytes(container) = fields[0].1 else {
+            panic!("{:?}", fields);
+        };
+        let container = wire::decode(container).unwrap();
+        assert_eq!(string_field(&container, 2), "web");
+        assert_eq!(string_field(&container, 3), "");
+        assert_eq!(string_field(&container, 4), "172.17.0.2");
+        assert_eq!(string_field(&container, 5), "permissive");
+        assert_eq!(uint_field(&container, 7), 2);
+
+        let status = Status::new(Code::NotFound, "container 100% gone");
+        let headers = status.headers();
+        assert_eq!(headers["grpc-status"], "5");
+        assert_eq!(headers["grpc-message"], "container 100%25 gone");
+    }
+}
@@ -0,0 +1,218 @@
+//! Enough of the protobuf wire format for the admin messages: varints,
+//! strings and nested messages, plus the 5-byte gRPC message framing.
+
+/// Largest request message accepted
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Builds a message field by field; default values are left out, as
+/// protobuf encoders do
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        put_varint(&mut self.buf, ((field as u64) << 3) | wire_type);
+    }
+
+    pub fn uint64(&mut self, field: u32, value: u64) -> &mut Self {
+        if value != 0 {
+            self.key(field, VARINT);
+            put_varint(&mut self.buf, value);
+        }
+        self
+    }
+
+    pub fn int64(&mut self, field: u32, value: i64) -> &mut Self {
+        self.uint64(field, value as u64)
+    }
+
+    pub fn bool(&mut self, field: u32, value: bool) -> &mut Self {
+        self.uint64(field, value as u64)
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.key(field, LENGTH_DELIMITED);
+        put_varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Self {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+        self
+    }
+
+    /// Each element of a `repeated string`, empty ones included
+    pub fn strings<S: AsRef<str>>(&mut self, field: u32, values: &[S]) -> &mut Self {
+        for value in values {
+            self.bytes(field, value.as_ref().as_bytes());
+        }
+        self
+    }
+
+    pub fn message(&mut self, field: u32, message: Encoder) -> &mut Self {
+        self.bytes(field, &message.buf)
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+}
+
+/// A decoded field value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// Fixed-width values, which the admin messages don't use
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Varint(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            Value::Bytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("malformed protobuf message")]
+pub struct DecodeError;
+
+fn take_varint(buf: &mut &[u8]) -> Result<u64, DecodeError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first().ok_or(DecodeError)?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError)
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if buf.len() < len {
+        return Err(DecodeError);
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(value)
+}
+
+/// The fields of a message, in the order they were written
+pub fn decode(mut buf: &[u8]) -> Result<Vec<(u32, Value<'_>)>, DecodeError> {
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let key = take_varint(&mut buf)?;
+        let field = u32::try_from(key >> 3).map_err(|_| DecodeError)?;
+        let value = match key & 0x7 {
+            VARINT => Value::Varint(take_varint(&mut buf)?),
+            LENGTH_DELIMITED => {
+                let len = take_varint(&mut buf)? as usize;
+                Value::Bytes(take(&mut buf, len)?)
+            }
+            FIXED64 => {
+                take(&mut buf, 8)?;
+                Value::Fixed
+            }
+            FIXED32 => {
+                take(&mut buf, 4)?;
+                Value::Fixed
+            }
+            _ => return Err(DecodeError),
+        };
+        fields.push((field, value));
+    }
+    Ok(fields)
+}
+
+/// A message as sent on a gRPC stream: uncompressed flag and big-endian
+/// length first
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(message.len() + 5);
+    framed.push(0);
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// The single message of a unary request body, or `None` until it is
+/// complete. Compressed messages are refused, as none is ever negotiated
+pub fn unframe(body: &[u8]) -> Option<Result<&[u8], DecodeError>> {
+    let (header, rest) = body.split_at_checked(5)?;
+    if header[0] != 0 {
+        return Some(Err(DecodeError));
+    }
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    rest.get(..len).map(Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut inner = Encoder::new();
+        inner.string(1, "web").uint64(2, 300);
+        let message = Encoder::new()
+            .bool(1, true)
+            .string(2, "")
+            .strings(3, &["a", ""])
+            .message(4, inner)
+            .int64(5, -1)
+            .finish();
+
+        let fields = decode(&message).unwrap();
+        assert_eq!(fields.len(), 5);
+        assert_eq!(fields[0], (1, Value::Varint(1)));
+        assert_eq!(fields[1].1.as_str(), Some("a"));
+        assert_eq!(fields[2].1.as_str(), Some(""));
+        assert_eq!(fields[4], (5, Value::Varint(u64::MAX)));
+        let Value::Bytes(inner) = fields[3].1 else {
+            panic!("{:?}", fields[3]);
+        };
+        assert_eq!(
+            decode(inner).unwrap(),
+            vec![(1, Value::Bytes(b"web")), (2, Value::Varint(300))]
+        );
+
+        let framed = frame(&message);
+        assert_eq!(&framed[..5], &[0, 0, 0, 0, message.len() as u8]);
+        assert_eq!(unframe(&framed), Some(Ok(&message[..])));
+        assert_eq!(unframe(&framed[..framed.len() - 1]), None);
+        assert!(decode(&[0x0a, 0x05, b'a']).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! libvirt/QEMU guests protected alongside containers.
+//!
+//! Guests are declared in a YAML file instead of labels. Each one becomes a
+//! [`Container`] with a stable synthetic id, so it gets its own chain and can
+//! be referenced by name from container rules. Guests given only a MAC
+//! address are located through the DHCP leases of libvirt's dnsmasq.
+//!
+//! Only routed traffic passes the chains: guests on libvirt's NAT or routed
+//! networks are covered, but purely bridged traffic between guests stays on
+//! layer 2 unless `br_netfilter` is loaded.
+
+use crate::docker::config::Config;
+use crate::docker::container::{Container, Network, PortMapping};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Where libvirt's dnsmasq keeps its `<bridge>.status` lease files
+pub const LIBVIRT_LEASE_DIR: &str = "/var/lib/libvirt/dnsmasq";
+
+/// Network name used for guests that don't name one
+const DEFAULT_NETWORK: &str = "default";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuestSpec {
+    pub name: String,
+    /// Looked up in the DHCP leases when `ips` is empty
+    #[serde(default)]
+    pub mac: Option<String>,
+    #[serde(default)]
+    pub ips: Vec<IpAddr>,
+    /// libvirt network the guest is attached to
+    #[serde(default = "default_network")]
+    pub network: String,
+    /// Ports the guest serves, as `22` or `53/udp`
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// Rules in the label format, which may `extends` profiles
+    #[serde(
+        default = "Config::new",
+        deserialize_with = "crate::docker::config::profiles::deserialize"
+    )]
+    pub rules: Config,
+}
+
+fn default_network() -> String {
+    DEFAULT_NETWORK.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct GuestFile {
+    #[serde(default)]
+    guests: Vec<GuestSpec>,
+}
+
+/// Read and check a guests file
+pub fn load(path: &Path) -> std::io::Result<Vec<GuestSpec>> {
+    let text = std::fs::read_to_string(path)?;
+    parse(&text).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse(text: &str) -> std::result::Result<Vec<GuestSpec>, String> {
+    let file: GuestFile = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+
+    let mut seen = std::collections::HashSet::new();
+    for guest in &file.guests {
+        if guest.name.is_empty() {
+            return Err("guest without a name".to_string());
+        }
+        if !seen.insert(guest.name.as_str()) {
+            return Err(format!("guest {} is listed twice", guest.name));
+        }
+        if guest.ips.is_empty() && guest.mac.is_none() {
+            return Err(format!("guest {} needs ips or a mac", guest.name));
+        }
+        for port in &guest.ports {
+            parse_port(port)
+                .ok_or_else(|| format!("guest {}: invalid port {}", guest.name, port))?;
+        }
+    }
+
+    Ok(file.guests)
+}
+
+fn parse_port(port: &str) -> Option<(u16, String)> {
+    let (number, proto) = port.split_once('/').unwrap_or((port, "tcp"));
+    let proto = proto.to_lowercase();
+    if proto != "tcp" && proto != "udp" {
+        return None;
+    }
+    Some((number.parse().ok().filter(|p| *p != 0)?, proto))
+}
+
+/// Stable id for a guest, so its chain keeps its name across restarts
+pub fn guest_id(name: &str) -> String {
+    // FNV-1a; only needs to be stable and spread out
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Lease addresses by lowercase MAC, from libvirt's `*.status` files
+pub fn read_leases(dir: &Path) -> std::io::Result<HashMap<String, Vec<IpAddr>>> {
+    #[derive(Deserialize)]
+    struct Lease {
+        #[serde(rename = "ip-address")]
+        ip_address: IpAddr,
+        #[serde(rename = "mac-address")]
+        mac_address: String,
+    }
+
+    let mut leases: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "status") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path)?;
+        // dnsmasq leaves an empty file when there are no leases
+        let Ok(entries) = serde_json::from_str::<Vec<Lease>>(&text) else {
+            continue;
+        };
+        for lease in entries {
+            leases
+                .entry(lease.mac_address.to_lowercase())
+                .or_default()
+                .push(lease.ip_address);
+        }
+    }
+    Ok(leases)
+}
+
+impl GuestSpec {
+    /// Configured addresses, or those leased to the guest's MAC
+    pub fn addresses(&self, leases: &HashMap<String, Vec<IpAddr>>) -> Vec<IpAddr> {
+        if !self.ips.is_empty() {
+            return self.ips.clone();
+        }
+        self.mac
+            .as_ref()
+            .and_then(|mac| leases.get(&mac.to_lowercase()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn to_container(&self, addrs: Vec<IpAddr>) -> Container {
+        let network = Network::builder()
+            .name(self.network.clone())
+            .ip_addresses(addrs)
+            .build();
+        let ports = self
+            .ports
+            .iter()
+            .filter_map(|port| parse_port(port))
+            .map(|(port, protocol)| {
+                PortMapping::builder()
+                    .container_port(port)
+                    .protocol(protocol)
+                    .build()
+            })
+            .collect();
+
+        Container::builder()
+            .id(guest_id(&self.name))
+            .name(self.name.clone())
+            .labels(HashMap::from([(
+                crate::ENABLED_LABEL.to_string(),
+                "true".to_string(),
+            )]))
+            .networks(HashMap::from([(self.network.clone(), network)]))
+            .ports(ports)
+            .config(self.rules.clone())
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_guests() {
+        let guests = parse(
+            r#"
+guests:
+  - name: nas
+    ips: [192.168.122.10]
+    ports: ["22", "2049/udp"]
+    rules:
+      mapped_ports:
+        external:
+          allow: true
+  - name: ha
+    mac: "52:54:00:AB:CD:EF"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(guests.len(), 2);
+        let nas = guests[0].to_container(guests[0].ips.clone());
+        assert_eq!(nas.id, guest_id("nas"));
+        assert!(nas.is_harborshield_enabled());
+        assert_eq!(nas.ports.len(), 2);
+        assert_eq!(nas.ports[1].protocol, "udp");
+        assert!(nas.config.unwrap().mapped_ports.external.allow);
+
+        let leases = HashMap::from([(
+            "52:54:00:ab:cd:ef".to_string(),
+            vec!["192.168.122.20".parse().unwrap()],
+        )]);
+        assert_eq!(
+            guests[1].addresses(&leases),
+            vec!["192.168.122.20".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(guests[1].network, "default");
+
+        assert!(parse("guests: [{name: a}]").is_err());
+        assert!(parse("guests: [{name: a, ips: [10.0.0.1]}, {name: a, ips: [10.0.0.2]}]").is_err());
+        assert!(parse("guests: [{name: a, ips: [10.0.0.1], ports: [\"22/sctp\"]}]").is_err());
+    }
+
+    #[test]
+    fn test_read_leases() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("virbr0.status"),
+            r#"[{"ip-address": "192.168.122.20", "mac-address": "52:54:00:ab:cd:ef", "hostname": "ha", "expiry-time": 1700000000}]"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("virbr1.status"), "").unwrap();
+        std::fs::write(dir.path().join("virbr0.macs"), "[]").unwrap();
+
+        let leases = read_leases(dir.path()).unwrap();
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases["52:54:00:ab:cd:ef"].len(), 1);
+    }
+}
@@ -0,0 +1,109 @@
+//! Recording when allow rules last matched traffic, and with
+//! `--notify-unused-after` a notice once one has gone unmatched that long.
+//!
+//! Notices are `unused_allow` audit entries, which the event bus publishes
+//! as well. Each idle rule is reported once; it is reported again only after
+//! it matched something and went idle anew.
+
+use crate::database::{AuditEntry, activity, audit};
+use crate::nftables::counters::list_rule_counters;
+use crate::status::CountersReport;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tracing::{debug, info};
+
+use super::Harborshield;
+
+/// Idle allow rules already reported, by container and rule
+pub type Notified = HashSet<(String, String)>;
+
+impl Harborshield {
+    /// Container identities by chain name
+    fn chain_names(&self) -> HashMap<String, String> {
+        self.docker_client
+            .container_tracker()
+            .list_containers()
+            .into_iter()
+            .map(|container| {
+                let chain = format!(
+                    "hs-{}-{}",
+                    container.name.replace(['_', '.', '/'], "-"),
+                    &container.id[..12.min(container.id.len())]
+                );
+                (chain, container.identity())
+            })
+            .collect()
+    }
+
+    /// Record the activity of every installed allow rule, then notify of
+    /// those idle for `notify_after`
+    pub(super) async fn track_rule_activity(
+        &self,
+        sampler: &mut activity::ActivitySampler,
+        notified: &mut Notified,
+        notify_after: Option<Duration>,
+        now: i64,
+    ) {
+        let counters = match list_rule_counters().await {
+            Ok(counters) => counters,
+            Err(e) => {
+                debug!("Skipping rule activity sample: {}", e);
+                return;
+            }
+        };
+        let report = CountersReport::new(counters, &self.chain_names());
+        let sample = sampler.sample(&report.rules, now);
+
+        let mut db = self.db.lock().await;
+        let prune_before = now - activity::PRUNE_AFTER.as_secs() as i64;
+        if let Err(e) = activity::record(&mut db, &sample, prune_before).await {
+            debug!("Failed to record rule activity: {}", e);
+            return;
+        }
+        let Some(after) = notify_after else {
+            return;
+        };
+
+        let idle = match activity::list(&db).await {
+            Ok(recorded) => activity::unused(&recorded, now, after),
+            Err(e) => {
+                debug!("Failed to read rule activity: {}", e);
+                return;
+            }
+        };
+        let current: Notified = idle
+            .iter()
+            .map(|rule| (rule.container_name.clone(), rule.rule.clone()))
+            .collect();
+        let entries: Vec<AuditEntry> = idle
+            .into_iter()
+            .filter(|rule| !notified.contains(&(rule.container_name.clone(), rule.rule.clone())))
+            .map(|rule| {
+                let detail = match rule.last_hit {
+                    Some(_) => format!(
+                        "{} matched no traffic for {} days",
+                        rule.rule, rule.idle_days
+                    ),
+                    None => format!(
+                        "{} matched no traffic in the {} days since it was installed",
+                        rule.rule, rule.idle_days
+                    ),
+                };
+                info!("Container {}: {}", rule.container_name, detail);
+                AuditEntry::builder()
+                    .ts(now)
+                    .kind(audit::KIND_UNUSED_ALLOW)
+                    .container_name(rule.container_name)
+                    .detail(detail)
+                    .build()
+            })
+            .collect();
+        *notified = current;
+        if entries.is_empty() {
+            return;
+        }
+        if let Err(e) = audit::record(&mut db, &entries).await {
+            debug!("Failed to record unused allow rules: {}", e);
+        }
+    }
+}
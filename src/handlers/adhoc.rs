@@ -0,0 +1,133 @@
+//! Applying and expiring `apply-adhoc` rule sets as they are stored; see
+//! [`crate::database::adhoc`].
+
+use crate::database::{AdhocRule, adhoc};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// The ids of the rule sets active for each identity
+pub fn active_ids(rules: &[AdhocRule]) -> HashMap<String, BTreeSet<i64>> {
+    let mut ids: HashMap<String, BTreeSet<i64>> = HashMap::new();
+    for rule in rules {
+        ids.entry(rule.container_name.clone())
+            .or_default()
+            .insert(rule.id);
+    }
+    ids
+}
+
+impl Harborshield {
+    /// Load the stored rule sets, expiring those past their TTL, so that
+    /// containers synced at startup are parsed with them
+    pub(crate) async fn load_adhoc_rules(&self) -> HashMap<String, BTreeSet<i64>> {
+        let mut db = self.db.lock().await;
+        match adhoc::expire(&mut db, chrono::Utc::now().timestamp()).await {
+            Ok(active) => {
+                adhoc::set_active(&active);
+                active_ids(&active)
+            }
+            Err(e) => {
+                warn!("Failed to load ad-hoc rules: {}", e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Watch the stored ad-hoc rule sets and rebuild the chains of
+    /// containers whose sets were added or expired, every `interval` until
+    /// shutdown
+    pub(crate) fn spawn_adhoc_watcher(
+        &self,
+        mut known: HashMap<String, BTreeSet<i64>>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        // Rule sets expire once the freeze ends
+                        if crate::database::freeze::is_frozen() {
+                            continue;
+                        }
+                        let active = {
+                            let mut db = handlers.db.lock().await;
+                            adhoc::expire(&mut db, chrono::Utc::now().timestamp()).await
+                        };
+                        let active = match active {
+                            Ok(active) => active,
+                            Err(e) => {
+                                warn!("Failed to read ad-hoc rules: {}", e);
+                                continue;
+                            }
+                        };
+                        let current = active_ids(&active);
+                        if current != known {
+                            adhoc::set_active(&active);
+                            handlers.apply_changed_adhoc(&known, &current).await;
+                            known = current;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    async fn apply_changed_adhoc(
+        &self,
+        known: &HashMap<String, BTreeSet<i64>>,
+        current: &HashMap<String, BTreeSet<i64>>,
+    ) {
+        let identities: HashSet<&String> = known.keys().chain(current.keys()).collect();
+        for identity in identities {
+            if known.get(identity) == current.get(identity) {
+                continue;
+            }
+
+            // Stopped containers pick the rules up when they start
+            let tracker = self.docker_client.container_tracker();
+            let mut containers = tracker.find_by_identity(identity);
+            if containers.is_empty() {
+                containers.extend(tracker.find_container(identity));
+            }
+            for container in containers {
+                // Re-inspect to parse the rules label with the new layers
+                let container = match self
+                    .docker_client
+                    .try_get_container_by_id(&container.id)
+                    .await
+                {
+                    Ok(container) => container,
+                    Err(e) => {
+                        warn!(
+                            "Failed to re-read container {} for ad-hoc rules: {}",
+                            container.name, e
+                        );
+                        continue;
+                    }
+                };
+                if let Err(e) = tracker.update_container(container.clone()) {
+                    warn!("Failed to update container {}: {}", container.name, e);
+                    continue;
+                }
+                let mode = self.enforcement_mode(&container.identity()).await;
+                match self.rebuild_container_rules(&container, mode).await {
+                    Ok(()) => info!("Updated ad-hoc rules for container {}", container.name),
+                    Err(e) => warn!(
+                        "Failed to update ad-hoc rules for container {}: {}",
+                        container.name, e
+                    ),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Runtime state and actions behind the admin gRPC service; see
+//! [`crate::grpc`].
+
+use crate::database::{AuditEntry, EnforcementMode, audit};
+use crate::docker::compose::COMPOSE_PROJECT_LABEL;
+use crate::docker::container::Container;
+use crate::nftables::{FILTER_TABLE, blocked, runner};
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tracing::info;
+
+use super::Harborshield;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerSummary {
+    pub id: String,
+    pub name: String,
+    pub project: Option<String>,
+    pub ips: Vec<String>,
+    pub mode: EnforcementMode,
+    /// Whether harborshield is enabled for it and it has rules
+    pub managed: bool,
+    pub output_rules: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerRules {
+    pub name: String,
+    pub project: Option<String>,
+    pub chain: String,
+    /// The rules as rendered, references resolved, in YAML
+    pub config: String,
+    /// The rules of the chain as nft lists them
+    pub chain_rules: Vec<String>,
+}
+
+pub(crate) fn chain_name(container: &Container) -> String {
+    format!(
+        "hs-{}-{}",
+        container.name.replace(['_', '.', '/'], "-"),
+        &container.id[..12.min(container.id.len())]
+    )
+}
+
+/// The rule lines of `nft list chain` output, without the table and chain
+/// around them
+pub fn chain_rule_lines(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            !line.is_empty()
+                && *line != "}"
+                && !line.starts_with("table ")
+                && !line.starts_with("chain ")
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+impl Harborshield {
+    pub async fn admin_containers(&self) -> Vec<ContainerSummary> {
+        let mut summaries = Vec::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            summaries.push(ContainerSummary {
+                mode: self.enforcement_mode(&container.identity()).await,
+                managed: container.is_harborshield_enabled() && container.config.is_some(),
+                output_rules: container.config.as_ref().map_or(0, |c| c.output.len()),
+                ips: container
+                    .ip_addresses(true)
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect(),
+                project: container.labels.get(COMPOSE_PROJECT_LABEL).cloned(),
+                id: container.id,
+                name: container.name,
+            });
+        }
+        summaries
+    }
+
+    /// The rules of a tracked container, found by name or id
+    pub async fn admin_rules(&self, name: &str) -> Option<ContainerRules> {
+        let container = self
+            .docker_client
+            .container_tracker()
+            .find_container(name)?;
+        let chain = chain_name(&container);
+        let config = container
+            .config
+            .as_ref()
+            .map(|config| self.resolve_container_references(&container, config))
+            .and_then(|config| serde_yaml::to_string(&config).ok())
+            .unwrap_or_default();
+        // A container without rules has no chain to list
+        let chain_rules = match runner::run_nft(
+            "admin_list_chain",
+            &["list", "chain", "ip", FILTER_TABLE, &chain],
+        )
+        .await
+        {
+            Ok(output) if output.status.success() => {
+                chain_rule_lines(&String::from_utf8_lossy(&output.stdout))
+            }
+            _ => Vec::new(),
+        };
+        Some(ContainerRules {
+            project: container.labels.get(COMPOSE_PROJECT_LABEL).cloned(),
+            name: container.name,
+            chain,
+            config,
+            chain_rules,
+        })
+    }
+
+    /// Drop all traffic from and to `ip` for `ttl`, or until the ruleset is
+    /// reinstalled, returning when the block expires
+    pub async fn block_address(
+        &self,
+        ip: Ipv4Addr,
+        ttl: Option<Duration>,
+        reason: Option<&str>,
+    ) -> crate::Result<Option<i64>> {
+        blocked::block(ip, ttl).await?;
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = ttl.map(|ttl| now + ttl.as_secs() as i64);
+
+        let mut detail = match ttl {
+            Some(ttl) => format!("blocked {} for {}s", ip, ttl.as_secs()),
+            None => format!("blocked {}", ip),
+        };
+        if let Some(reason) = reason {
+            detail.push_str(&format!(" ({})", reason));
+        }
+        info!("{}", detail);
+        let entry = AuditEntry::builder()
+            .ts(now)
+            .kind(audit::KIND_ADDRESS_BLOCKED)
+            .detail(detail)
+            .build();
+        let mut db = self.db.lock().await;
+        audit::record(&mut db, std::slice::from_ref(&entry)).await?;
+        Ok(expires_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_rule_lines() {
+        let listing = "table ip filter {\n\tchain hs-web-0123456789ab {\n\t\tip daddr 10.0.0.8 tcp dport 5432 counter packets 0 bytes 0 accept\n\n\t\tcounter packets 3 bytes 180 drop\n\t}\n}\n";
+        assert_eq!(
+            chain_rule_lines(listing),
+            vec![
+                "ip daddr 10.0.0.8 tcp dport 5432 counter packets 0 bytes 0 accept",
+                "counter packets 3 bytes 180 drop",
+            ]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Applying stored bans to the blocked set; see [`crate::database::bans`].
+
+use crate::database::{Ban, bans};
+use crate::nftables::blocked;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// The expiry of each banned address
+pub fn ban_expiries(bans: &[Ban]) -> HashMap<Ipv4Addr, i64> {
+    let mut expiries = HashMap::new();
+    for ban in bans {
+        match ban.addr() {
+            Some(ip) => {
+                let expires_at = expiries.entry(ip).or_insert(ban.expires_at);
+                *expires_at = (*expires_at).max(ban.expires_at);
+            }
+            None => warn!("Ignoring ban of invalid address {:?}", ban.ip),
+        }
+    }
+    expiries
+}
+
+impl Harborshield {
+    /// Watch the stored bans and add addresses to the blocked set as they
+    /// are banned, or drop them as their ban is lifted, every `interval`
+    /// until shutdown. Bans still running at startup are added on the first
+    /// pass; expired ones leave the set by its own timeouts
+    pub(crate) fn spawn_ban_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut known: HashMap<Ipv4Addr, i64> = HashMap::new();
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        let now = chrono::Utc::now().timestamp();
+                        let active = {
+                            let mut db = handlers.db.lock().await;
+                            bans::expire(&mut db, now).await
+                        };
+                        let current = match active {
+                            Ok(active) => ban_expiries(&active),
+                            Err(e) => {
+                                warn!("Failed to read bans: {}", e);
+                                continue;
+                            }
+                        };
+                        if current != known {
+                            blocked::set_banned(current.iter().map(|(ip, at)| (*ip, *at)).collect());
+                            known = apply_changed_bans(&known, current, now).await;
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Bring the set in line with `current`, returning the bans now applied;
+/// those that failed are left out to be tried again on the next pass
+async fn apply_changed_bans(
+    known: &HashMap<Ipv4Addr, i64>,
+    current: HashMap<Ipv4Addr, i64>,
+    now: i64,
+) -> HashMap<Ipv4Addr, i64> {
+    for (ip, expires_at) in known {
+        // Expired bans are gone from the set already
+        if !current.contains_key(ip) && *expires_at > now {
+            match blocked::unban(*ip).await {
+                Ok(()) => info!("Lifted the ban of {}", ip),
+                Err(e) => warn!("Failed to lift the ban of {}: {}", ip, e),
+            }
+        }
+    }
+    let mut applied = HashMap::new();
+    for (ip, expires_at) in current {
+        if known.get(&ip) == Some(&expires_at) {
+            applied.insert(ip, expires_at);
+            continue;
+        }
+        let ttl = Duration::from_secs((expires_at - now).max(1) as u64);
+        match blocked::ban(ip, ttl).await {
+            Ok(()) => {
+                info!("Banned {} for {}s", ip, ttl.as_secs());
+                applied.insert(ip, expires_at);
+            }
+            Err(e) => warn!("Failed to ban {}: {}", ip, e),
+        }
+    }
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban_expiries() {
+        let ban = |id, ip: &str, expires_at| Ban {
+            id,
+            ip: ip.to_string(),
+            reason: None,
+            created_at: 0,
+            expires_at,
+        };
+        let expiries = ban_expiries(&[
+            ban(1, "203.0.113.7", 100),
+            ban(2, "203.0.113.7", 300),
+            ban(3, "198.51.100.1", 200),
+            ban(4, "2001:db8::1", 400),
+        ]);
+        assert_eq!(expiries.len(), 2);
+        assert_eq!(expiries[&Ipv4Addr::new(203, 0, 113, 7)], 300);
+        assert_eq!(expiries[&Ipv4Addr::new(198, 51, 100, 1)], 200);
+    }
+}
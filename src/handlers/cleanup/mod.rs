@@ -257,7 +257,9 @@ fn matches_resource(a: &CleanupResource, b: &CleanupResource) -> bool {
             CleanupResource::DatabaseContainer { id: id1 },
             CleanupResource::DatabaseContainer { id: id2 },
         ) => id1 == id2,
-        (CleanupResource::HarborshieldFilterRules, CleanupResource::HarborshieldFilterRules) => true,
+        (CleanupResource::HarborshieldFilterRules, CleanupResource::HarborshieldFilterRules) => {
+            true
+        }
         _ => false,
     }
 }
@@ -478,14 +480,16 @@ async fn cleanup_resource(resource: &CleanupResource, db: &Arc<Mutex<DB>>) -> Re
             warn!("Cleaning up all Harborshield rules from filter table");
 
             // First, get a list of all Harborshield chains (hs-* chains)
-            let list_output = std::process::Command::new("nft")
-                .args(&["-j", "list", "table", "ip", "filter"])
-                .output()
-                .map_err(|e| crate::Error::Config {
-                    message: format!("Failed to list filter table: {}", e),
-                    location: "cleanup_harborshield_filter_rules".to_string(),
-                    suggestion: Some("Check nftables permissions".to_string()),
-                })?;
+            let list_output = crate::nftables::runner::run_nft(
+                "list_filter_table",
+                &["-j", "list", "table", "ip", "filter"],
+            )
+            .await
+            .map_err(|e| crate::Error::Config {
+                message: format!("Failed to list filter table: {}", e),
+                location: "cleanup_harborshield_filter_rules".to_string(),
+                suggestion: Some("Check nftables permissions".to_string()),
+            })?;
 
             if !list_output.status.success() {
                 warn!(
@@ -520,18 +524,22 @@ async fn cleanup_resource(resource: &CleanupResource, db: &Arc<Mutex<DB>>) -> Re
                     // Delete each container chain
                     for chain_name in chains_to_delete {
                         // First flush the chain
-                        let flush_result = std::process::Command::new("nft")
-                            .args(&["flush", "chain", "ip", "filter", &chain_name])
-                            .output();
+                        let flush_result = crate::nftables::runner::run_nft(
+                            "flush_chain",
+                            &["flush", "chain", "ip", "filter", &chain_name],
+                        )
+                        .await;
 
                         if let Err(e) = flush_result {
                             warn!("Failed to flush chain {}: {}", chain_name, e);
                         }
 
                         // Then delete the chain
-                        let delete_result = std::process::Command::new("nft")
-                            .args(&["delete", "chain", "ip", "filter", &chain_name])
-                            .output();
+                        let delete_result = crate::nftables::runner::run_nft(
+                            "delete_chain",
+                            &["delete", "chain", "ip", "filter", &chain_name],
+                        )
+                        .await;
 
                         match delete_result {
                             Ok(output) => {
@@ -554,9 +562,11 @@ async fn cleanup_resource(resource: &CleanupResource, db: &Arc<Mutex<DB>>) -> Re
             }
 
             // Also flush the main harborshield chain
-            let flush_harborshield = std::process::Command::new("nft")
-                .args(&["flush", "chain", "ip", "filter", "harborshield"])
-                .output();
+            let flush_harborshield = crate::nftables::runner::run_nft(
+                "flush_chain",
+                &["flush", "chain", "ip", "filter", "harborshield"],
+            )
+            .await;
 
             if let Err(e) = flush_harborshield {
                 warn!("Failed to flush harborshield chain: {}", e);
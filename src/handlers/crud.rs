@@ -10,7 +10,7 @@ use crate::{
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 impl Harborshield {
     /// Remove container data from database
@@ -61,7 +61,7 @@ impl Harborshield {
             // Update container if it's being tracked
             if self
                 .docker_client
-                .container_tracker
+                .container_tracker()
                 .get_container(container_id)
                 .is_some()
             {
@@ -82,7 +82,7 @@ impl Harborshield {
             Ok(container_info) => {
                 // Update container container
                 self.docker_client
-                    .container_tracker
+                    .container_tracker()
                     .update_container(container_info.clone())?;
 
                 // Update in database
@@ -164,10 +164,18 @@ impl Harborshield {
                 "Network event"
             );
 
+            // A network created since the last sync, for address templates
+            if action == "connect"
+                && !crate::docker::network::known_networks().contains_key(network_name)
+                && let Err(e) = self.refresh_networks().await
+            {
+                warn!("Failed to refresh network information: {}", e);
+            }
+
             // If this is a tracked container, update its network information
             if self
                 .docker_client
-                .container_tracker
+                .container_tracker()
                 .get_container(actual_container_id)
                 .is_some()
             {
@@ -189,7 +197,7 @@ impl Harborshield {
             Ok(container_info) => {
                 // Update container container with new network info
                 self.docker_client
-                    .container_tracker
+                    .container_tracker()
                     .update_container(container_info.clone())?;
 
                 // Update IP addresses and aliases in database
@@ -305,7 +313,7 @@ impl Harborshield {
         );
 
         // Get all containers that might have rules referencing this container
-        let all_containers = self.docker_client.container_tracker.list_containers();
+        let all_containers = self.docker_client.container_tracker().list_containers();
         let mut rules_to_update = Vec::new();
 
         // Check each container for rules that reference the changed container
@@ -0,0 +1,216 @@
+//! Output rules switched off in place with `enabled: false`.
+//!
+//! A disabled rule stays in the config with the `reason` it was turned off,
+//! is skipped when rules are built, and shows up in `harborshield audit`
+//! when it is disabled, re-enabled or deleted from the config.
+
+use crate::database::AuditEntry;
+use crate::database::audit::{self, KIND_RULE_DISABLED, KIND_RULE_ENABLED, KIND_RULE_REMOVED};
+use crate::docker::config::{Config, RuleConfig};
+use std::collections::BTreeMap;
+use tracing::{debug, info};
+
+use super::Harborshield;
+
+const KINDS: &[&str] = &[KIND_RULE_DISABLED, KIND_RULE_ENABLED, KIND_RULE_REMOVED];
+
+/// Skip the rules the config disables
+pub fn skip_disabled_rules(config: &mut Config) {
+    for rule in config.output.iter_mut().filter(|rule| !rule.enabled) {
+        debug!(
+            "Output rule disabled: {}",
+            rule.reason.as_deref().unwrap_or_default()
+        );
+        rule.skip = true;
+    }
+}
+
+/// How a rule is named in the audit log: protocol, destination and ports,
+/// so it is recognised again after rules around it are added or removed
+pub fn rule_key(rule: &RuleConfig) -> String {
+    let target = if !rule.container.is_empty() {
+        format!("container {}", rule.container)
+    } else if let Some(from) = &rule.from {
+        format!("from {}", from)
+    } else if rule.host {
+        "host".to_string()
+    } else if let Some(hostname) = &rule.hostname {
+        hostname.clone()
+    } else if rule.ips.is_empty() {
+        "any".to_string()
+    } else {
+        rule.ips
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    if rule.proto.is_icmp() {
+        let types = if rule.icmp_types.is_empty() {
+            "any type".to_string()
+        } else {
+            rule.icmp_types.join(",")
+        };
+        return format!("{} to {} {}", rule.proto, target, types);
+    }
+    let ports = rule
+        .dst_ports
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{} to {} port {}", rule.proto, target, ports)
+}
+
+/// Rules disabled according to `history`, with their reasons
+fn disabled_in(history: &[AuditEntry]) -> BTreeMap<String, String> {
+    let mut disabled = BTreeMap::new();
+    for entry in history {
+        if entry.kind == KIND_RULE_DISABLED {
+            let (key, reason) = entry.detail.split_once(": ").unwrap_or((&entry.detail, ""));
+            disabled.insert(key.to_string(), reason.to_string());
+        } else {
+            disabled.remove(&entry.detail);
+        }
+    }
+    disabled
+}
+
+/// Audit entries recording how `config` changed what is disabled since
+/// `history`
+pub fn disabled_changes(
+    container_name: &str,
+    config: &Config,
+    history: &[AuditEntry],
+    now: i64,
+) -> Vec<AuditEntry> {
+    let before = disabled_in(history);
+    let now_disabled: BTreeMap<String, String> = config
+        .output
+        .iter()
+        .filter(|rule| !rule.enabled)
+        .map(|rule| (rule_key(rule), rule.reason.clone().unwrap_or_default()))
+        .collect();
+    let entry = |kind: &str, detail: String| {
+        AuditEntry::builder()
+            .ts(now)
+            .kind(kind)
+            .container_name(container_name.to_string())
+            .detail(detail)
+            .build()
+    };
+
+    let mut entries = Vec::new();
+    for (key, reason) in &now_disabled {
+        if before.get(key) != Some(reason) {
+            entries.push(entry(KIND_RULE_DISABLED, format!("{}: {}", key, reason)));
+        }
+    }
+    for key in before.keys().filter(|key| !now_disabled.contains_key(*key)) {
+        let still_configured = config.output.iter().any(|rule| rule_key(rule) == *key);
+        let kind = if still_configured {
+            KIND_RULE_ENABLED
+        } else {
+            KIND_RULE_REMOVED
+        };
+        entries.push(entry(kind, key.clone()));
+    }
+    entries
+}
+
+impl Harborshield {
+    /// Record rules of `identity` that were disabled, re-enabled or deleted
+    /// while disabled since the last time its rules were applied
+    pub(super) async fn audit_disabled_rules(&self, identity: &str, config: &Config) {
+        let mut db = self.db.lock().await;
+        let history = match audit::list_for_container(&db, identity, KINDS).await {
+            Ok(history) => history,
+            Err(e) => {
+                debug!("Failed to read rule history of {}: {}", identity, e);
+                return;
+            }
+        };
+
+        let entries = disabled_changes(identity, config, &history, chrono::Utc::now().timestamp());
+        if entries.is_empty() {
+            return;
+        }
+        for entry in &entries {
+            info!("{} for {}: {}", entry.kind, identity, entry.detail);
+        }
+        if let Err(e) = audit::record(&mut db, &entries).await {
+            debug!("Failed to record disabled rules of {}: {}", identity, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_disabled_rule_history() {
+        let disabled = config(
+            r#"
+output:
+  - ips: ["10.0.0.5"]
+    proto: tcp
+    dst_ports: [5432]
+    enabled: false
+    reason: "migration window, INC-42"
+  - ips: ["10.0.0.6"]
+    proto: tcp
+    dst_ports: [443]
+"#,
+        );
+        let mut skipped = disabled.clone();
+        skip_disabled_rules(&mut skipped);
+        assert!(skipped.output[0].skip);
+        assert!(!skipped.output[1].skip);
+
+        let first = disabled_changes("web", &disabled, &[], 100);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].kind, KIND_RULE_DISABLED);
+        assert_eq!(
+            first[0].detail,
+            "tcp to 10.0.0.5 port 5432: migration window, INC-42"
+        );
+        // Applying the same config again records nothing
+        assert!(disabled_changes("web", &disabled, &first, 200).is_empty());
+
+        let enabled = config(
+            r#"
+output:
+  - ips: ["10.0.0.5"]
+    proto: tcp
+    dst_ports: [5432]
+"#,
+        );
+        let second = disabled_changes("web", &enabled, &first, 300);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].kind, KIND_RULE_ENABLED);
+        assert_eq!(second[0].detail, "tcp to 10.0.0.5 port 5432");
+
+        let removed = disabled_changes("web", &config("output: []"), &first, 300);
+        assert_eq!(removed[0].kind, KIND_RULE_REMOVED);
+    }
+
+    #[test]
+    fn test_disabled_rule_needs_reason() {
+        let err = serde_yaml::from_str::<Config>(
+            r#"
+output:
+  - ips: ["10.0.0.5"]
+    proto: tcp
+    dst_ports: [5432]
+    enabled: false
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("reason"), "{}", err);
+    }
+}
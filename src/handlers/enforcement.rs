@@ -0,0 +1,177 @@
+use crate::{
+    Result,
+    database::{DbOp, DbOpResult, EnforcementMode, enforcement},
+    docker::container::Container,
+};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::Harborshield;
+
+impl Harborshield {
+    /// Persisted enforcement mode for a container, `Enforce` if unset or
+    /// unreadable
+    pub(super) async fn enforcement_mode(&self, container_name: &str) -> EnforcementMode {
+        let db = self.db.lock().await;
+        match db.execute(&DbOp::GetEnforcementMode(container_name)).await {
+            Ok(DbOpResult::EnforcementMode(mode)) => mode,
+            Ok(_) => EnforcementMode::Enforce,
+            Err(e) => {
+                warn!(
+                    "Failed to read enforcement mode for {}, enforcing: {}",
+                    container_name, e
+                );
+                EnforcementMode::Enforce
+            }
+        }
+    }
+
+    /// Rebuild a running container's chain from its current config for `mode`
+    pub async fn rebuild_container_rules(
+        &self,
+        container: &Container,
+        mode: EnforcementMode,
+    ) -> Result<()> {
+        // Without rules the chain has nothing to enforce
+        let Some(config) = &container.config else {
+            debug!(
+                "Container {} has no rules, enforcement mode {} has no effect",
+                container.name, mode
+            );
+            return Ok(());
+        };
+        if container.uses_host_network {
+            return Ok(());
+        }
+        if crate::docker::rootless::is_rootless() {
+            self.sync_rootless().await;
+            return Ok(());
+        }
+
+        let container_ips = container.ip_addresses(false);
+        let container_ports: Vec<(u16, String)> = container
+            .ports
+            .iter()
+            .map(|p| (p.container_port, p.protocol.clone()))
+            .collect();
+        let resolved_config = self.resolve_container_references(container, config);
+
+        let mut nftables = self.nftables_client.lock().await;
+        nftables
+            .rebuild_container_chain(
+                &container.id,
+                &container.name,
+                &container_ips,
+                &container_ports,
+                &resolved_config,
+                mode,
+            )
+            .await?;
+        drop(nftables);
+        self.sync_offload().await;
+        self.sync_forwards().await;
+        self.sync_tproxy().await;
+        self.sync_ipv6().await;
+
+        debug!(
+            "Rebuilt rules for container {} in {} mode",
+            container.name, mode
+        );
+        self.record_rule_change(&container.identity()).await;
+        Ok(())
+    }
+
+    /// Watch the stored enforcement modes and rebuild the chains of running
+    /// containers whose mode changed, every `interval` until shutdown
+    pub(crate) fn spawn_enforcement_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            // Containers synced at startup already honour the stored modes
+            let mut known = handlers
+                .stored_enforcement_modes()
+                .await
+                .unwrap_or_default();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        let current = match handlers.stored_enforcement_modes().await {
+                            Ok(current) => current,
+                            Err(e) => {
+                                warn!("Failed to read enforcement modes: {}", e);
+                                continue;
+                            }
+                        };
+                        let current = if crate::database::freeze::is_frozen() {
+                            frozen_modes(&known, current)
+                        } else {
+                            current
+                        };
+                        handlers.apply_changed_modes(&known, &current).await;
+                        known = current;
+                    }
+                }
+            }
+        })
+    }
+
+    async fn stored_enforcement_modes(&self) -> Result<HashMap<String, EnforcementMode>> {
+        let db = self.db.lock().await;
+        Ok(enforcement::list_overrides(&db)
+            .await?
+            .into_iter()
+            .map(|e| (e.container_name, e.mode))
+            .collect())
+    }
+
+    async fn apply_changed_modes(
+        &self,
+        known: &HashMap<String, EnforcementMode>,
+        current: &HashMap<String, EnforcementMode>,
+    ) {
+        let names: HashSet<&String> = known.keys().chain(current.keys()).collect();
+        for name in names {
+            let mode = current.get(name).copied().unwrap_or_default();
+            if known.get(name).copied().unwrap_or_default() == mode {
+                continue;
+            }
+
+            // Stopped containers pick the mode up when they start
+            let tracker = self.docker_client.container_tracker();
+            let mut containers = tracker.find_by_identity(name);
+            if containers.is_empty() {
+                containers.extend(tracker.find_container(name));
+            }
+            for container in containers {
+                match self.rebuild_container_rules(&container, mode).await {
+                    Ok(()) => info!("Container {} switched to {} mode", container.name, mode),
+                    Err(e) => warn!(
+                        "Failed to switch container {} to {} mode: {}",
+                        container.name, mode, e
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// The modes to apply during a change freeze: quarantines go through, other
+/// switches wait for it to end
+fn frozen_modes(
+    known: &HashMap<String, EnforcementMode>,
+    current: HashMap<String, EnforcementMode>,
+) -> HashMap<String, EnforcementMode> {
+    let mut modes = known.clone();
+    modes.extend(
+        current
+            .into_iter()
+            .filter(|(_, mode)| *mode == EnforcementMode::Quarantined),
+    );
+    modes
+}
@@ -0,0 +1,178 @@
+//! Keeping the nat table in line with every container's `forward` rules;
+//! see [`crate::nftables::forward`]. When containers forward the same host
+//! port, the first by name keeps it and the others' forwards of it are
+//! left out with a warning.
+
+use crate::database::EnforcementMode;
+use crate::docker::config::ForwardRule;
+use crate::docker::container::Container;
+use crate::nftables::forward::ForwardTarget;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, warn};
+
+use super::Harborshield;
+
+/// Whether the nat table may hold forwards; a table left by an earlier run
+/// is cleared by the first sync
+static FORWARDING: AtomicBool = AtomicBool::new(true);
+
+/// A container's forwards to its first IPv4 address, if it has one
+pub fn forward_target(container: &Container) -> Option<ForwardTarget<'_>> {
+    let forwards = &container.config.as_ref()?.forward;
+    if forwards.is_empty() {
+        return None;
+    }
+    let mut ips: Vec<_> = container
+        .ip_addresses(false)
+        .into_iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+    ips.sort();
+    Some(ForwardTarget {
+        container_name: &container.name,
+        ip: *ips.first()?,
+        forwards: forwards.iter().collect(),
+    })
+}
+
+/// Keep only the first claim on each host port, going through the
+/// containers by name so the same one wins on every sync
+pub fn claim_host_ports(mut targets: Vec<ForwardTarget<'_>>) -> Vec<ForwardTarget<'_>> {
+    targets.sort_by_key(|target| target.container_name);
+    let mut claimed: Vec<(&ForwardRule, &str)> = Vec::new();
+    for target in &mut targets {
+        target.forwards.retain(|forward| {
+            match claimed.iter().find(|(other, _)| other.overlaps(forward)) {
+                Some((_, owner)) => {
+                    warn!(
+                        "Not forwarding {}/{} to {}: {} already forwards it",
+                        forward.host_port, forward.proto, target.container_name, owner
+                    );
+                    false
+                }
+                None => {
+                    claimed.push((forward, target.container_name));
+                    true
+                }
+            }
+        });
+    }
+    targets.retain(|target| !target.forwards.is_empty());
+    targets
+}
+
+impl Harborshield {
+    /// Rebuild the forwards of every tracked container. Quarantined and
+    /// disabled containers forward nothing
+    pub(super) async fn sync_forwards(&self) {
+        if crate::docker::rootless::is_rootless() {
+            return;
+        }
+
+        let mut containers = Vec::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            if !container.enabled || container.uses_host_network || container.paused {
+                continue;
+            }
+            if container
+                .config
+                .as_ref()
+                .is_none_or(|config| config.forward.is_empty())
+            {
+                continue;
+            }
+            let mode = self.enforcement_mode(&container.identity()).await;
+            if !matches!(mode, EnforcementMode::Enforce | EnforcementMode::Permissive) {
+                continue;
+            }
+            containers.push(container);
+        }
+
+        let targets = claim_host_ports(containers.iter().filter_map(forward_target).collect());
+        if targets.is_empty() && !FORWARDING.load(Ordering::Relaxed) {
+            return;
+        }
+        let nftables = self.nftables_client.lock().await;
+        match nftables.rebuild_forwards(&targets).await {
+            Ok(()) => {
+                FORWARDING.store(!targets.is_empty(), Ordering::Relaxed);
+                debug!("Forwarding ports of {} containers", targets.len());
+            }
+            Err(e) => warn!("Failed to rebuild port forwards: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::Network;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_forward_target() {
+        let config = serde_yaml::from_str("forward:\n  - host_port: 8080\n    port: 80\n").unwrap();
+        let network = |ips: &[&str]| {
+            Network::builder()
+                .name("bridge".to_string())
+                .ip_addresses(ips.iter().map(|ip| ip.parse().unwrap()).collect())
+                .aliases(Vec::new())
+                .build()
+        };
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("web".to_string())
+            .config(config)
+            .networks(HashMap::from([(
+                "bridge".to_string(),
+                network(&["fd00::2", "172.17.0.2"]),
+            )]))
+            .build();
+
+        let target = forward_target(&container).unwrap();
+        assert_eq!(
+            target.ip,
+            "172.17.0.2".parse::<std::net::Ipv4Addr>().unwrap()
+        );
+        assert_eq!(target.forwards[0].container_port(), 80);
+
+        // Nothing to forward to without an IPv4 address
+        let unaddressed = Container {
+            networks: HashMap::from([("bridge".to_string(), network(&["fd00::2"]))]),
+            ..container
+        };
+        assert!(forward_target(&unaddressed).is_none());
+    }
+
+    #[test]
+    fn test_claim_host_ports() {
+        let forwards: Vec<ForwardRule> = serde_yaml::from_str(
+            "- host_port: 8080\n- host_port: 8080\n  proto: udp\n- host_port: 9090\n  host_ip: 192.0.2.10\n",
+        )
+        .unwrap();
+        let target = |name, forwards| ForwardTarget {
+            container_name: name,
+            ip: "172.17.0.2".parse().unwrap(),
+            forwards,
+        };
+
+        // Whatever order the tracker lists them in, "api" claims 8080/tcp
+        let targets = claim_host_ports(vec![
+            target("web", vec![&forwards[0], &forwards[2]]),
+            target("api", vec![&forwards[0], &forwards[1]]),
+            target("db", vec![&forwards[1]]),
+        ]);
+        let kept: Vec<(&str, Vec<u16>)> = targets
+            .iter()
+            .map(|target| {
+                let ports = target.forwards.iter().map(|f| f.host_port).collect();
+                (target.container_name, ports)
+            })
+            .collect();
+        assert_eq!(kept, [("api", vec![8080, 8080]), ("web", vec![9090])]);
+    }
+}
@@ -0,0 +1,179 @@
+//! Holding back rule changes while a change freeze lasts; see
+//! [`crate::database::freeze`].
+
+use crate::database::HeldEvent;
+use crate::database::freeze::{self, is_frozen, set_frozen};
+use bollard::models::EventMessage;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// The container a Docker event is about, and its name if the event says
+fn event_container(event: &EventMessage) -> Option<(String, Option<String>)> {
+    let actor = event.actor.as_ref()?;
+    let attributes = actor.attributes.as_ref();
+    let attribute = |key: &str| attributes.and_then(|a| a.get(key)).cloned();
+    match event.action.as_deref()? {
+        // The actor of a network event is the network
+        "connect" | "disconnect" => Some((attribute("container")?, None)),
+        _ => Some((actor.id.clone()?, attribute("name"))),
+    }
+}
+
+impl Harborshield {
+    /// Pick up a freeze started while the daemon was down
+    pub(crate) async fn load_freeze(&self) {
+        let db = self.db.lock().await;
+        match freeze::current(&db, chrono::Utc::now().timestamp()).await {
+            Ok(current) => {
+                if current.is_some() {
+                    warn!("A change freeze is in place; Docker events are held until it ends");
+                }
+                set_frozen(current.is_some());
+            }
+            Err(e) => warn!("Failed to read the change freeze: {}", e),
+        }
+    }
+
+    /// Follow the stored freeze every `interval` until shutdown, ending one
+    /// past its time and applying what it held once it ends
+    pub(crate) fn spawn_freeze_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => handlers.check_freeze().await,
+                }
+            }
+        })
+    }
+
+    async fn check_freeze(&self) {
+        let now = chrono::Utc::now().timestamp();
+        let mut db = self.db.lock().await;
+        let stored = match freeze::stored(&db).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("Failed to read the change freeze: {}", e);
+                return;
+            }
+        };
+        match stored {
+            Some(current) if current.active(now) => {
+                if !is_frozen() {
+                    info!("Change freeze started; holding back rule changes");
+                    set_frozen(true);
+                }
+                return;
+            }
+            Some(_) => {
+                if let Err(e) = freeze::lift(&mut db, now, "freeze reached its end time").await {
+                    warn!("Failed to end the change freeze: {}", e);
+                    return;
+                }
+            }
+            None => {}
+        }
+        drop(db);
+
+        if is_frozen() {
+            set_frozen(false);
+            self.apply_held().await;
+        }
+    }
+
+    /// Bring every container in line after a freeze, then forget what it
+    /// held
+    async fn apply_held(&self) {
+        let held = {
+            let db = self.db.lock().await;
+            freeze::held(&db).await.unwrap_or_default()
+        };
+        info!(
+            "Change freeze ended; applying the events held for {} containers",
+            held.len()
+        );
+        // Containers whose events were shed or held are all covered
+        self.resync_all().await;
+
+        let db = self.db.lock().await;
+        if let Err(e) = freeze::clear_held(&db).await {
+            warn!("Failed to clear held events: {}", e);
+        }
+    }
+
+    /// Keep `event` for the end of the freeze instead of handling it
+    pub(super) async fn hold_event(&self, event: &EventMessage) {
+        crate::web::record_event(event);
+        let Some((container_id, name)) = event_container(event) else {
+            return;
+        };
+        let name = name
+            .or_else(|| {
+                self.docker_client
+                    .container_tracker()
+                    .get_container(&container_id)
+                    .map(|container| container.name)
+            })
+            .unwrap_or_else(|| container_id.clone());
+        let now = chrono::Utc::now().timestamp();
+        let held = HeldEvent {
+            container_id,
+            container_name: name,
+            action: event.action.clone().unwrap_or_default(),
+            events: 1,
+            first_held: now,
+            last_held: now,
+        };
+
+        info!(
+            "Holding {} event of container {} for the end of the change freeze",
+            held.action, held.container_name
+        );
+        let db = self.db.lock().await;
+        if let Err(e) = freeze::hold(&db, &held).await {
+            warn!("Failed to note held event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::EventActor;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_event_container() {
+        let event = |action: &str, attributes: &[(&str, &str)]| EventMessage {
+            action: Some(action.to_string()),
+            actor: Some(EventActor {
+                id: Some("abc".to_string()),
+                attributes: Some(
+                    attributes
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect::<HashMap<_, _>>(),
+                ),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            event_container(&event("start", &[("name", "web")])),
+            Some(("abc".to_string(), Some("web".to_string())))
+        );
+        assert_eq!(
+            event_container(&event("connect", &[("container", "def")])),
+            Some(("def".to_string(), None))
+        );
+        assert_eq!(event_container(&event("disconnect", &[])), None);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    guests::{self, GuestSpec},
+    nftables::transaction::NftablesTransaction,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::Harborshield;
+
+impl Harborshield {
+    /// The configured guests, as of the last reload
+    pub(crate) fn guests(&self) -> Vec<GuestSpec> {
+        self.guests
+            .read()
+            .map(|guests| guests.clone())
+            .unwrap_or_default()
+    }
+
+    fn guest_leases(&self) -> HashMap<String, Vec<IpAddr>> {
+        if self.guests().iter().all(|guest| !guest.ips.is_empty()) {
+            return HashMap::new();
+        }
+        guests::read_leases(Path::new(guests::LIBVIRT_LEASE_DIR)).unwrap_or_else(|e| {
+            warn!(
+                "Failed to read libvirt leases from {}: {}",
+                guests::LIBVIRT_LEASE_DIR,
+                e
+            );
+            HashMap::new()
+        })
+    }
+
+    /// Chains of configured guests, as `(chain, id, name)`
+    pub(crate) fn guest_chains(&self) -> Vec<(String, String, String)> {
+        self.guests()
+            .iter()
+            .map(|guest| {
+                let id = guests::guest_id(&guest.name);
+                let chain = format!(
+                    "hs-{}-{}",
+                    guest.name.replace(['_', '.', '/'], "-"),
+                    &id[..12]
+                );
+                (chain, id, guest.name.clone())
+            })
+            .collect()
+    }
+
+    /// Create the chains of all configured guests, returning the addresses
+    /// each was set up with
+    pub(crate) async fn apply_guests(&self) -> HashMap<String, Vec<IpAddr>> {
+        let leases = self.guest_leases();
+        let mut applied = HashMap::new();
+
+        for guest in self.guests().iter() {
+            let addrs = guest.addresses(&leases);
+            if addrs.is_empty() {
+                warn!(
+                    "Guest {} has no lease yet, its rules are applied once it gets an address",
+                    guest.name
+                );
+            } else if let Err(e) = self.apply_guest(guest, addrs.clone()).await {
+                warn!("Failed to apply rules for guest {}: {}", guest.name, e);
+                continue;
+            }
+            applied.insert(guest.name.clone(), addrs);
+        }
+
+        applied
+    }
+
+    /// Recreate a guest's chain with the addresses it is tracked with
+    pub(crate) async fn reapply_guest(&self, guest: &GuestSpec) -> crate::Result<()> {
+        let addrs = self
+            .docker_client
+            .container_tracker()
+            .get_container(&guests::guest_id(&guest.name))
+            .map(|c| {
+                c.networks
+                    .into_values()
+                    .flat_map(|n| n.ip_addresses)
+                    .collect()
+            })
+            .unwrap_or_else(|| guest.addresses(&self.guest_leases()));
+        self.apply_guest(guest, addrs).await
+    }
+
+    async fn apply_guest(&self, guest: &GuestSpec, addrs: Vec<IpAddr>) -> crate::Result<()> {
+        let container = guest.to_container(addrs);
+        let tracker = self.docker_client.container_tracker();
+        if tracker.remove_container(&container.id)?.is_some() {
+            let mut transaction = NftablesTransaction::builder().build();
+            transaction.remove_container_rules(&container.id, &container.name)?;
+            if let Err(e) = transaction.commit().await {
+                debug!("Failed to remove old rules of guest {}: {}", guest.name, e);
+            }
+        }
+        tracker.add_container(container.clone())?;
+
+        self.create_container_rules(&container, None).await?;
+        info!(
+            "Applied firewall rules for guest {} at {:?}",
+            guest.name,
+            container
+                .networks
+                .values()
+                .flat_map(|n| &n.ip_addresses)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    /// Follow lease changes of guests located by MAC until shutdown
+    pub(crate) fn spawn_guest_lease_watcher(
+        &self,
+        mut applied: HashMap<String, Vec<IpAddr>>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        let leases = handlers.guest_leases();
+                        for guest in handlers.guests().iter().filter(|g| g.ips.is_empty()) {
+                            let addrs = guest.addresses(&leases);
+                            if addrs.is_empty() || applied.get(&guest.name) == Some(&addrs) {
+                                continue;
+                            }
+                            info!("Guest {} now leases {:?}", guest.name, addrs);
+                            match handlers.apply_guest(guest, addrs.clone()).await {
+                                Ok(()) => {
+                                    applied.insert(guest.name.clone(), addrs);
+                                }
+                                Err(e) => warn!(
+                                    "Failed to update rules for guest {}: {}",
+                                    guest.name, e
+                                ),
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
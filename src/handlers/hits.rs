@@ -0,0 +1,218 @@
+//! Notices for output rules marked `notify_on_hit: true`.
+//!
+//! Each stats pass reads the counters of the rules in container chains and
+//! compares them with the previous pass. When a watched rule counted new
+//! packets, a `rule_hit` audit entry is recorded, which the event bus
+//! publishes as well. Hits are sampled, so a burst within one interval is
+//! one notice; the first pass after startup only sets the baseline.
+
+use crate::database::{AuditEntry, audit};
+use crate::nftables::counters::{RuleCounter, list_rule_counters};
+use std::collections::HashMap;
+use tracing::{debug, info};
+
+use super::Harborshield;
+
+/// The output rule numbers named in a rule comment such as
+/// `Output rules 1, 3 for web`
+pub fn rule_numbers(comment: &str) -> Vec<usize> {
+    let Some(rest) = comment
+        .strip_prefix("Output rules ")
+        .or_else(|| comment.strip_prefix("Output rule "))
+    else {
+        return Vec::new();
+    };
+    let numbers = rest
+        .split_once(" for ")
+        .map_or(rest, |(numbers, _)| numbers);
+    numbers.split(", ").filter_map(|n| n.parse().ok()).collect()
+}
+
+/// A watched rule that matched traffic since the last sample
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub chain: String,
+    /// The watched output rules merged into the nft rule
+    pub rules: Vec<usize>,
+    pub packets: u64,
+}
+
+/// Packet counts of watched rules at the last sample, by chain and comment
+#[derive(Debug, Default)]
+pub struct HitSampler {
+    last: HashMap<(String, String), u64>,
+    /// Set after the first sample; rules seen later start from zero
+    primed: bool,
+}
+
+impl HitSampler {
+    /// Hits of the rules in `watched`, output rule numbers by chain. A
+    /// counter that went backwards belongs to a rebuilt chain, and one not
+    /// seen before to a new chain, so all of it is new
+    pub fn hits(
+        &mut self,
+        counters: Vec<RuleCounter>,
+        watched: &HashMap<String, Vec<usize>>,
+    ) -> Vec<Hit> {
+        let mut last = HashMap::new();
+        let mut hits = Vec::new();
+        for counter in counters {
+            let (Some(numbers), Some(comment)) = (watched.get(&counter.chain), counter.comment)
+            else {
+                continue;
+            };
+            let rules: Vec<usize> = rule_numbers(&comment)
+                .into_iter()
+                .filter(|n| numbers.contains(n))
+                .collect();
+            if rules.is_empty() {
+                continue;
+            }
+            let key = (counter.chain, comment);
+            if self.primed {
+                let before = self.last.get(&key).copied().unwrap_or_default();
+                let packets = counter
+                    .packets
+                    .checked_sub(before)
+                    .unwrap_or(counter.packets);
+                if packets > 0 {
+                    hits.push(Hit {
+                        chain: key.0.clone(),
+                        rules,
+                        packets,
+                    });
+                }
+            }
+            last.insert(key, counter.packets);
+        }
+        self.last = last;
+        self.primed = true;
+        hits
+    }
+}
+
+impl Harborshield {
+    /// Output rules with `notify_on_hit`, by container chain, with the
+    /// container names
+    fn watched_rules(&self) -> (HashMap<String, Vec<usize>>, HashMap<String, String>) {
+        let mut watched = HashMap::new();
+        let mut names = HashMap::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            let Some(config) = &container.config else {
+                continue;
+            };
+            let numbers: Vec<usize> = config
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.notify_on_hit && !rule.skip)
+                .map(|(i, _)| i + 1)
+                .collect();
+            if numbers.is_empty() {
+                continue;
+            }
+            let chain = format!(
+                "hs-{}-{}",
+                container.name.replace(['_', '.', '/'], "-"),
+                &container.id[..12.min(container.id.len())]
+            );
+            names.insert(chain.clone(), container.identity());
+            watched.insert(chain, numbers);
+        }
+        (watched, names)
+    }
+
+    /// Record a notice for every watched rule that matched since the last
+    /// call
+    pub(super) async fn notify_rule_hits(&self, sampler: &mut HitSampler, now: i64) {
+        let (watched, names) = self.watched_rules();
+        if watched.is_empty() {
+            return;
+        }
+        let counters = match list_rule_counters().await {
+            Ok(counters) => counters,
+            Err(e) => {
+                debug!("Skipping rule hit sample: {}", e);
+                return;
+            }
+        };
+
+        let entries: Vec<AuditEntry> = sampler
+            .hits(counters, &watched)
+            .into_iter()
+            .map(|hit| {
+                let name = names.get(&hit.chain).cloned().unwrap_or(hit.chain);
+                let rules = hit
+                    .rules
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let detail = format!("output rule {} matched {} packets", rules, hit.packets);
+                info!("Container {}: {}", name, detail);
+                AuditEntry::builder()
+                    .ts(now)
+                    .kind(audit::KIND_RULE_HIT)
+                    .container_name(name)
+                    .detail(detail)
+                    .build()
+            })
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        let mut db = self.db.lock().await;
+        if let Err(e) = audit::record(&mut db, &entries).await {
+            debug!("Failed to record rule hits: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(chain: &str, comment: &str, packets: u64) -> RuleCounter {
+        RuleCounter {
+            chain: chain.to_string(),
+            position: 1,
+            comment: Some(comment.to_string()),
+            verdict: Some("accept"),
+            packets,
+            bytes: packets * 60,
+        }
+    }
+
+    #[test]
+    fn test_hits() {
+        assert_eq!(rule_numbers("Output rule 2 for web"), vec![2]);
+        assert_eq!(rule_numbers("Output rules 1, 3 for web"), vec![1, 3]);
+        assert!(rule_numbers("Allow tcp port 80 from external for web").is_empty());
+
+        let watched = HashMap::from([("hs-admin-1".to_string(), vec![3])]);
+        let sample = |ssh, other| {
+            vec![
+                counter("hs-admin-1", "Output rules 1, 3 for admin", ssh),
+                counter("hs-admin-1", "Output rule 2 for admin", other),
+                counter("hs-web-2", "Output rule 3 for web", ssh),
+            ]
+        };
+        let mut sampler = HitSampler::default();
+        // The first sample is the baseline
+        assert!(sampler.hits(sample(5, 0), &watched).is_empty());
+        assert!(sampler.hits(sample(5, 9), &watched).is_empty());
+        assert_eq!(
+            sampler.hits(sample(8, 9), &watched),
+            vec![Hit {
+                chain: "hs-admin-1".to_string(),
+                rules: vec![3],
+                packets: 3,
+            }]
+        );
+        // The chain was rebuilt
+        assert_eq!(sampler.hits(sample(2, 0), &watched)[0].packets, 2);
+        // A container that started since counts from zero
+        let watched = HashMap::from([("hs-web-2".to_string(), vec![3])]);
+        assert_eq!(sampler.hits(sample(2, 0), &watched)[0].packets, 2);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::docker::{
+    config::{AddrOrRange, Config},
+    container::Container,
+};
+use std::net::IpAddr;
+use tracing::debug;
+#[cfg(target_os = "linux")]
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// Let a burst of address events (e.g. a DHCP renewal) settle before
+/// re-reading the host's addresses
+#[cfg(target_os = "linux")]
+const HOST_ADDR_SETTLE: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Whether a container's rules depend on the host's addresses
+pub fn uses_host_addresses(container: &Container) -> bool {
+    container.config.as_ref().is_some_and(|config| {
+        config.mapped_ports.external.host || config.output.iter().any(|rule| rule.host)
+    })
+}
+
+/// Replace `host` in a config with the given addresses. Rules that only
+/// matched the host are dropped rather than widened when it has none.
+pub fn expand_host_addresses(config: &mut Config, host_addrs: &[IpAddr]) {
+    // Container chains only match IPv4 addresses
+    let addrs = || {
+        host_addrs
+            .iter()
+            .filter(|ip| ip.is_ipv4())
+            .map(|ip| AddrOrRange::Addr(*ip))
+    };
+
+    let external = &mut config.mapped_ports.external;
+    if external.host {
+        external.ips.extend(addrs());
+        external.host = false;
+        if external.ips.is_empty() && external.rdns.is_empty() {
+            debug!("Host has no addresses, external rule allowing only the host disabled");
+            external.allow = false;
+        }
+    }
+
+    for rule in config.output.iter_mut().filter(|rule| rule.host) {
+        rule.ips.extend(addrs());
+        rule.host = false;
+        if rule.ips.is_empty() {
+            debug!("Host has no addresses, output rule targeting only the host skipped");
+            rule.skip = true;
+        }
+    }
+}
+
+impl Harborshield {
+    /// Watch for host address changes and rebuild the rules that depend on
+    /// them until shutdown
+    #[cfg(target_os = "linux")]
+    pub(crate) fn spawn_host_address_watcher(
+        &self,
+    ) -> std::io::Result<tokio::task::JoinHandle<()>> {
+        let events = crate::host::netlink::AddressEvents::open()?;
+        let handlers = self.clone();
+
+        Ok(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    changed = events.changed() => {
+                        if let Err(e) = changed {
+                            warn!("Stopped watching host addresses: {}", e);
+                            break;
+                        }
+                        tokio::time::sleep(HOST_ADDR_SETTLE).await;
+                        handlers.refresh_host_addresses().await;
+                    }
+                }
+            }
+        }))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn refresh_host_addresses(&self) {
+        let current = match crate::host::netlink::list_addresses() {
+            Ok(current) => current,
+            Err(e) => {
+                warn!("Failed to list host addresses: {}", e);
+                return;
+            }
+        };
+        let Some(previous) = self.host_addrs.replace(current.clone()) else {
+            return;
+        };
+        info!(
+            "Host addresses changed from {:?} to {:?}",
+            previous, current
+        );
+
+        for container in self.docker_client.container_tracker().list_containers() {
+            // Docker keeps forwarding to the address the port was published
+            // on, so only recreating the container helps
+            for port in &container.ports {
+                if let Some(ip) = port.host_ip
+                    && previous.contains(&ip)
+                    && !current.contains(&ip)
+                {
+                    warn!(
+                        "Container {} publishes port {} on {}, which the host no longer has; recreate it to publish on the new address",
+                        container.name,
+                        port.host_port.unwrap_or(port.container_port),
+                        ip
+                    );
+                }
+            }
+
+            if !uses_host_addresses(&container) {
+                continue;
+            }
+            let mode = self.enforcement_mode(&container.identity()).await;
+            match self.rebuild_container_rules(&container, mode).await {
+                Ok(()) => info!(
+                    "Updated host addresses in rules for container {}",
+                    container.name
+                ),
+                Err(e) => warn!(
+                    "Failed to update host addresses for container {}: {}",
+                    container.name, e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::config::{ExternalRules, MappedPorts, Protocol, RuleConfig, RulePorts};
+
+    fn config(external_ips: Vec<AddrOrRange>) -> Config {
+        Config::builder()
+            .mapped_ports(
+                MappedPorts::builder()
+                    .external(
+                        ExternalRules::builder()
+                            .allow(true)
+                            .host(true)
+                            .ips(external_ips)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .output(vec![
+                RuleConfig::builder()
+                    .host(true)
+                    .proto(Protocol::Tcp)
+                    .dst_ports(vec![RulePorts::Single(5432)])
+                    .build(),
+            ])
+            .build()
+    }
+
+    #[test]
+    fn test_expand_host_addresses() {
+        let mut config = config(vec!["10.0.0.0/8".parse().unwrap()]);
+        let host: Vec<IpAddr> = vec![
+            "192.168.1.20".parse().unwrap(),
+            "2001:db8::20".parse().unwrap(),
+        ];
+        expand_host_addresses(&mut config, &host);
+
+        let external = &config.mapped_ports.external;
+        assert!(external.allow && !external.host);
+        let ips: Vec<String> = external.ips.iter().map(|ip| ip.to_string()).collect();
+        assert_eq!(ips, ["10.0.0.0/8", "192.168.1.20"]);
+        assert_eq!(config.output[0].ips.len(), 1);
+        assert!(!config.output[0].skip);
+    }
+
+    #[test]
+    fn test_expand_without_host_addresses_never_widens() {
+        let mut config = config(vec![]);
+        expand_host_addresses(&mut config, &[]);
+
+        assert!(!config.mapped_ports.external.allow);
+        assert!(config.output[0].skip);
+    }
+}
@@ -0,0 +1,139 @@
+//! Keeping the `ip6 filter` container chains in line with tracked
+//! containers; see [`crate::nftables::ipv6`].
+
+use crate::docker::config::Config;
+use crate::docker::container::Container;
+use crate::nftables::ipv6::Ipv6Target;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, warn};
+
+use super::Harborshield;
+use super::subnet::{unexpected_addresses, withdraw_allow_rules};
+use super::utils::resolve_config;
+
+/// Whether the missing `ip6 filter` table was already reported
+static WARNED_NO_TABLE: AtomicBool = AtomicBool::new(false);
+
+/// A container's chain in the `ip6 filter` table, or `None` when it has no
+/// IPv6 address to dispatch on or opted out
+pub fn ipv6_target<'a>(
+    container: &'a Container,
+    config: &'a Config,
+    mode: crate::database::EnforcementMode,
+) -> Option<Ipv6Target<'a>> {
+    if !container.ipv6_enabled() {
+        return None;
+    }
+    let ips = container.ip_addresses(true);
+    if ips.is_empty() {
+        return None;
+    }
+    Some(Ipv6Target {
+        container_id: &container.id,
+        container_name: &container.name,
+        ips,
+        ports: container
+            .ports
+            .iter()
+            .map(|p| (p.container_port, p.protocol.clone()))
+            .collect(),
+        config,
+        mode,
+    })
+}
+
+impl Harborshield {
+    /// Rebuild the IPv6 chains of every tracked container with rules
+    pub(super) async fn sync_ipv6(&self) {
+        if crate::docker::rootless::is_rootless() {
+            return;
+        }
+
+        let host_addrs = self.host_addrs.get();
+        let now = chrono::Utc::now().timestamp();
+        let mut resolved = Vec::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            let Some(config) = &container.config else {
+                continue;
+            };
+            if !container.enabled || container.uses_host_network {
+                continue;
+            }
+            let mut config = resolve_config(
+                &container,
+                config,
+                self.docker_client.container_tracker(),
+                &host_addrs,
+                now,
+            );
+            // Reported when the IPv4 chain was built
+            if let Some(subnet) = &config.expected_subnet {
+                let addrs: Vec<IpAddr> = container
+                    .networks
+                    .values()
+                    .flat_map(|network| network.ip_addresses.iter().copied())
+                    .collect();
+                if !unexpected_addresses(subnet, &addrs).is_empty() {
+                    withdraw_allow_rules(&mut config);
+                }
+            }
+            let mode = self.enforcement_mode(&container.identity()).await;
+            resolved.push((container, config, mode));
+        }
+
+        let targets: Vec<Ipv6Target> = resolved
+            .iter()
+            .filter_map(|(container, config, mode)| ipv6_target(container, config, *mode))
+            .collect();
+        let nftables = self.nftables_client.lock().await;
+        match nftables.rebuild_ipv6(&targets).await {
+            Ok(true) => debug!("Rebuilt IPv6 chains of {} containers", targets.len()),
+            Ok(false) => {
+                if !targets.is_empty() && !WARNED_NO_TABLE.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "Containers have IPv6 addresses but there is no ip6 filter table; enable ip6tables in Docker's daemon.json to have their IPv6 traffic filtered"
+                    );
+                }
+            }
+            Err(e) => warn!("Failed to rebuild the IPv6 chains: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::EnforcementMode;
+    use crate::docker::container::Network;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_ipv6_target() {
+        let network = Network::builder()
+            .name("bridge".to_string())
+            .ip_addresses(vec![
+                "172.17.0.2".parse().unwrap(),
+                "fd00::2".parse().unwrap(),
+            ])
+            .build();
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("web".to_string())
+            .networks(HashMap::from([("bridge".to_string(), network)]))
+            .build();
+        let config = Config::builder().build();
+
+        let target = ipv6_target(&container, &config, EnforcementMode::Enforce).unwrap();
+        assert_eq!(target.ips, vec!["fd00::2".parse::<IpAddr>().unwrap()]);
+
+        let opted_out = Container {
+            labels: HashMap::from([
+                ("harborshield.enabled".to_string(), "true".to_string()),
+                ("harborshield.ipv6".to_string(), "false".to_string()),
+            ]),
+            ..container
+        };
+        assert!(ipv6_target(&opted_out, &config, EnforcementMode::Enforce).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+//! `--ha-lock`: active/standby for two hosts sharing a VIP, such as a
+//! keepalived pair.
+//!
+//! Both hosts run harborshield with the same lock file on storage they both
+//! see, such as an NFS export. The one holding an exclusive lock on it
+//! enforces; the other tracks containers and stores their rules without
+//! changing its ruleset (see [`crate::nftables::standby`]) and tries for the
+//! lock every few seconds. The lock goes with the holder's process, or with
+//! its host once the storage notices it's gone, and the standby then takes
+//! over with a full sync. The holder re-checks the lock just as often: when
+//! it can't confirm it still holds it and its name is still in the file,
+//! as when NFS hands the lock on during a partition, it drops back to
+//! standby rather than enforce alongside the new holder.
+
+use serde::Serialize;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// How often a standby tries for the lock, and the holder re-checks it
+pub const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+static STATUS: RwLock<Option<LeaderStatus>> = RwLock::new(None);
+
+/// This host's part in the pair, for `/status`
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderStatus {
+    pub lock: PathBuf,
+    /// `active` or `standby`
+    pub role: &'static str,
+    /// Unix seconds the role was taken
+    pub since: i64,
+}
+
+fn set_status(lock: &Path, active: bool) {
+    if let Ok(mut status) = STATUS.write() {
+        *status = Some(LeaderStatus {
+            lock: lock.to_path_buf(),
+            role: if active { "active" } else { "standby" },
+            since: chrono::Utc::now().timestamp(),
+        });
+    }
+}
+
+/// This host's role, when `--ha-lock` is given
+pub fn status() -> Option<LeaderStatus> {
+    STATUS.read().ok().and_then(|status| status.clone())
+}
+
+/// The lock file, opened ahead of the sandbox so trying for it needs no
+/// further file access
+pub struct LeaderLock {
+    path: PathBuf,
+    file: File,
+    /// Written to the file once held: hostname and process ID
+    holder: String,
+}
+
+impl LeaderLock {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            holder: format!("{} {}\n", hostname, std::process::id()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Take the lock if nobody holds it, noting this host in the file
+    pub fn try_acquire(&self) -> std::io::Result<bool> {
+        match self.file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(false),
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        let mut file = &self.file;
+        file.set_len(0)?;
+        file.write_all(self.holder.as_bytes())?;
+        Ok(true)
+    }
+
+    /// Whether a lock taken earlier is still this host's: locking again
+    /// still succeeds and the file still names this host
+    pub fn confirm(&self) -> std::io::Result<bool> {
+        match self.file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(false),
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        let mut file = &self.file;
+        let mut holder = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut holder)?;
+        Ok(holder == self.holder)
+    }
+}
+
+/// Enforce or withhold ruleset changes by `active`
+fn set_role(lock: &LeaderLock, active: bool) {
+    crate::nftables::standby::set_standby(!active);
+    crate::server::set_leader(active);
+    set_status(lock.path(), active);
+}
+
+/// Start out enforcing if the lock is free, or else as a standby that
+/// withholds ruleset changes from here on
+pub fn start(lock: &LeaderLock) {
+    let active = match lock.try_acquire() {
+        Ok(active) => active,
+        Err(e) => {
+            warn!("Failed to lock {}: {}", lock.path().display(), e);
+            false
+        }
+    };
+    if active {
+        info!("Holding {}, enforcing rules", lock.path().display());
+    } else {
+        info!(
+            "Standby: another host holds {}, tracking containers without changing the ruleset",
+            lock.path().display()
+        );
+    }
+    set_role(lock, active);
+}
+
+impl Harborshield {
+    /// Every `interval`, try for the lock while standing by and re-check it
+    /// while active, switching roles as it's taken or lost
+    pub(crate) fn spawn_leader_election(
+        &self,
+        lock: Arc<LeaderLock>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            let mut active = !crate::nftables::standby::is_standby();
+            // Whether the rules went in since taking over; retried until they do
+            let mut installed = active;
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {}
+                }
+
+                if active {
+                    let held = lock.confirm().unwrap_or_else(|e| {
+                        warn!("Failed to check {}: {}", lock.path().display(), e);
+                        false
+                    });
+                    if !held {
+                        warn!(
+                            "Lost {}, standing by without changing the ruleset",
+                            lock.path().display()
+                        );
+                        active = false;
+                        installed = false;
+                        set_role(&lock, false);
+                        continue;
+                    }
+                } else {
+                    match lock.try_acquire() {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(e) => {
+                            warn!("Failed to lock {}: {}", lock.path().display(), e);
+                            continue;
+                        }
+                    }
+                    info!(
+                        "Took over {}, installing every container's rules",
+                        lock.path().display()
+                    );
+                    active = true;
+                    set_role(&lock, true);
+                }
+
+                if !installed {
+                    match handlers.reinstall().await {
+                        Ok(()) => installed = true,
+                        Err(e) => {
+                            warn!("Failed to install rules after taking over, retrying: {}", e)
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leader_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("harborshield.lock");
+
+        let active = LeaderLock::open(&path).unwrap();
+        let standby = LeaderLock::open(&path).unwrap();
+        assert!(active.try_acquire().unwrap());
+        assert!(!standby.try_acquire().unwrap());
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .ends_with(&format!(" {}\n", std::process::id()))
+        );
+
+        assert!(active.confirm().unwrap());
+
+        // Released with the holder
+        drop(active);
+        assert!(standby.try_acquire().unwrap());
+        assert!(standby.confirm().unwrap());
+
+        // Another host's name in the file means the lock went to it
+        std::fs::write(&path, "other-host 1\n").unwrap();
+        assert!(!standby.confirm().unwrap());
+    }
+}
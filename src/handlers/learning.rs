@@ -0,0 +1,186 @@
+//! Learning mode sampling. While a container's session runs, conntrack is
+//! sampled and the flows it shows for the container are recorded; expired
+//! sessions put the container back into enforce mode.
+
+use crate::database::{LearningSession, ObservedFlow, learning};
+use crate::docker::container::Container;
+use crate::host::conntrack;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::Harborshield;
+
+/// Flows among `entries` that involve `container`, naming peers that are
+/// other containers on a shared network
+pub fn observed_flows(
+    container: &Container,
+    containers: &[Container],
+    entries: &[conntrack::Entry],
+    now: i64,
+) -> Vec<ObservedFlow> {
+    let ips: Vec<IpAddr> = container
+        .networks
+        .values()
+        .flat_map(|network| network.ip_addresses.iter().copied())
+        .collect();
+
+    let mut sightings: HashMap<conntrack::Flow, i64> = HashMap::new();
+    for flow in entries.iter().filter_map(|entry| entry.flow_for(&ips)) {
+        *sightings.entry(flow).or_default() += 1;
+    }
+
+    sightings
+        .into_iter()
+        .map(|(flow, count)| {
+            let peer = containers.iter().find_map(|other| {
+                let network = other.networks.values().find(|network| {
+                    network.ip_addresses.contains(&flow.peer)
+                        && container.networks.contains_key(&network.name)
+                })?;
+                Some((other.name.clone(), network.name.clone()))
+            });
+            ObservedFlow {
+                container_name: container.identity(),
+                direction: flow.direction.as_str().to_string(),
+                proto: flow.proto,
+                peer: flow.peer.to_string(),
+                port: flow.port as i64,
+                peer_container: peer.as_ref().map(|(name, _)| name.clone()),
+                peer_network: peer.map(|(_, network)| network),
+                first_seen: now,
+                last_seen: now,
+                sightings: count,
+            }
+        })
+        .collect()
+}
+
+impl Harborshield {
+    /// Record the flows of learning containers every `interval` until
+    /// shutdown
+    pub(crate) fn spawn_learning_job(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => handlers.sample_learning_containers().await,
+                }
+            }
+        })
+    }
+
+    async fn sample_learning_containers(&self) {
+        let sessions = {
+            let db = self.db.lock().await;
+            match learning::sessions(&db).await {
+                Ok(sessions) => sessions,
+                Err(e) => {
+                    warn!("Failed to read learning sessions: {}", e);
+                    return;
+                }
+            }
+        };
+        if sessions.is_empty() {
+            return;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let (expired, active): (Vec<LearningSession>, Vec<LearningSession>) =
+            sessions.into_iter().partition(|s| s.ends_at <= now);
+
+        for session in expired {
+            let mut db = self.db.lock().await;
+            match learning::finish(&mut db, &session.container_name).await {
+                Ok(()) => info!(
+                    "Learning for container {} ended, enforcing its rules again",
+                    session.container_name
+                ),
+                Err(e) => warn!(
+                    "Failed to end learning for container {}: {}",
+                    session.container_name, e
+                ),
+            }
+        }
+        if active.is_empty() {
+            return;
+        }
+
+        let entries = match conntrack::sample().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to sample conntrack for learning mode: {}", e);
+                return;
+            }
+        };
+        let containers = self.docker_client.container_tracker().list_containers();
+
+        let mut flows = Vec::new();
+        for session in &active {
+            // Stopped containers are picked up again once they run
+            for container in containers
+                .iter()
+                .filter(|c| c.identity() == session.container_name)
+            {
+                flows.extend(observed_flows(container, &containers, &entries, now));
+            }
+        }
+        if flows.is_empty() {
+            return;
+        }
+
+        let mut db = self.db.lock().await;
+        match learning::record(&mut db, &flows).await {
+            Ok(()) => debug!("Recorded {} flows for learning containers", flows.len()),
+            Err(e) => warn!("Failed to record observed flows: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::Network;
+
+    fn container(name: &str, network: &str, ip: &str) -> Container {
+        Container::builder()
+            .id(format!("{}-id", name))
+            .name(name.to_string())
+            .networks(HashMap::from([(
+                network.to_string(),
+                Network::builder()
+                    .name(network.to_string())
+                    .ip_addresses(vec![ip.parse().unwrap()])
+                    .build(),
+            )]))
+            .build()
+    }
+
+    #[test]
+    fn test_observed_flows_name_peer_containers() {
+        let web = container("web", "backend", "172.18.0.2");
+        let db = container("db", "backend", "172.18.0.3");
+        let entries = conntrack::parse(
+            "\
+tcp 6 300 ESTABLISHED src=172.18.0.2 dst=172.18.0.3 sport=40000 dport=5432 src=172.18.0.3 dst=172.18.0.2 sport=5432 dport=40000
+tcp 6 300 ESTABLISHED src=172.18.0.2 dst=172.18.0.3 sport=40001 dport=5432 src=172.18.0.3 dst=172.18.0.2 sport=5432 dport=40001
+tcp 6 300 ESTABLISHED src=172.18.0.2 dst=93.184.216.34 sport=40002 dport=443 src=93.184.216.34 dst=192.168.1.5 sport=443 dport=40002
+",
+        );
+
+        let mut flows = observed_flows(&web, &[web.clone(), db], &entries, 100);
+        flows.sort_by_key(|flow| flow.port);
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].peer_container, None);
+        assert_eq!(flows[1].peer_container.as_deref(), Some("db"));
+        assert_eq!(flows[1].peer_network.as_deref(), Some("backend"));
+        assert_eq!(flows[1].sightings, 2);
+    }
+}
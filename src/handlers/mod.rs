@@ -1,90 +1,135 @@
+pub mod activity;
+pub mod adhoc;
+pub mod admin;
+pub mod bans;
 pub mod cleanup;
 pub mod crud;
+pub mod disabled;
+pub mod enforcement;
 pub mod error;
+pub mod forward;
+pub mod freeze;
+pub mod guests;
+pub mod hits;
+pub mod host;
+pub mod ipv6;
+pub mod leader;
+pub mod learning;
+pub mod networks;
+#[cfg(target_os = "linux")]
+pub mod nflog;
+pub mod offload;
+pub mod panic;
+pub mod peers;
+pub mod pipeline;
+pub mod quarantine;
+pub mod readiness;
+pub mod reconcile;
+pub mod reload;
+pub mod report;
+pub mod restarts;
+pub mod revoke;
+pub mod rootless;
+pub mod rulesdir;
+pub mod sampling;
+pub mod schedule;
+pub mod selfheal;
+pub mod stage;
+pub mod stats;
+pub mod storage;
+pub mod subnet;
 #[cfg(test)]
 mod tests;
+pub mod tproxy;
 pub mod utils;
 
 use crate::{
     Result,
     database::{ContainerIdentifiers, DbOp},
+    docker::reconnect,
     nftables::transaction::NftablesTransaction,
 };
 use bollard::models::EventMessage;
-use futures::StreamExt;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::Instant;
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info};
 
-use super::{ENABLED_LABEL, Harborshield};
+use super::Harborshield;
+use stage::Stage;
 
 impl Harborshield {
     pub(super) fn spawn_event_listener(&self, handlers: Arc<Harborshield>) -> JoinHandle<()> {
         let handlers = Arc::clone(&handlers);
+        let (queue, rx) = pipeline::EventQueue::new(self.event_queue_capacity);
+
+        let worker = {
+            let handlers = Arc::clone(&handlers);
+            let queue = queue.clone();
+            tokio::spawn(async move { handlers.run_event_worker(queue, rx).await })
+        };
 
         tokio::spawn(async move {
-            let mut retry_count = 0;
-            const MAX_RETRIES: u32 = 5;
-            const RETRY_DELAY: Duration = Duration::from_secs(2);
+            let mut backoff = reconnect::Backoff::default();
+            let mut lost = false;
 
             loop {
                 let mut event_stream = match handlers.docker_client.events().await {
-                    Ok(stream) => {
-                        retry_count = 0; // Reset retry count on success
-                        stream
-                    }
+                    Ok(stream) => stream,
                     Err(e) => {
                         error!("Failed to create Docker event stream: {}", e);
-
-                        if retry_count >= MAX_RETRIES {
-                            error!("Max retries reached for Docker event stream, exiting");
-                            return;
+                        if !reconnect::wait_for_daemon(
+                            &*handlers.docker_client,
+                            &mut backoff,
+                            &handlers.cancellation_token,
+                        )
+                        .await
+                        {
+                            break;
                         }
-
-                        retry_count += 1;
-                        error!(
-                            "Retrying Docker event stream connection in {:?} (attempt {})",
-                            RETRY_DELAY, retry_count
-                        );
-                        tokio::time::sleep(RETRY_DELAY).await;
                         continue;
                     }
                 };
+                // Events from while the stream was down are gone
+                if lost {
+                    crate::server::increment_docker_reconnects();
+                    queue.request_reconnect_resync();
+                }
 
+                let opened = Instant::now();
                 let mut shutdown_rx = handlers.shutdown_rx.lock().await;
+                pipeline::set_stream_connected(true);
+                let shutdown = pipeline::forward(&mut event_stream, &queue, &mut shutdown_rx).await;
+                pipeline::set_stream_connected(false);
+                drop(shutdown_rx);
+                if shutdown {
+                    break;
+                }
 
-                loop {
-                    tokio::select! {
-                        Some(event_result) = event_stream.next() => {
-                            match event_result {
-                                Ok(event) => {
-                                    if let Err(e) = handlers.handle_event(
-                                        event
-                                    ).await {
-                                        error!("Error handling Docker event: {}", e);
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Error receiving Docker event: {}", e);
-                                    // Break inner loop to retry connection
-                                    break;
-                                }
-                            }
-                        }
-                        _ = shutdown_rx.recv() => {
-                            info!("Event listener received shutdown signal");
-                            return;
-                        }
-                    }
+                lost = true;
+                if opened.elapsed() >= reconnect::STABLE_AFTER {
+                    backoff.reset();
+                }
+                if !reconnect::wait_for_daemon(
+                    &*handlers.docker_client,
+                    &mut backoff,
+                    &handlers.cancellation_token,
+                )
+                .await
+                {
+                    break;
                 }
             }
+
+            drop(queue);
+            let _ = worker.await;
         })
     }
 
     pub(super) async fn handle_event(&self, event: EventMessage) -> Result<()> {
         debug!("Handling event: {:#?}", event);
+        crate::web::record_event(&event);
 
         let Some(ref actor) = event.actor else {
             return Ok(());
@@ -125,11 +170,7 @@ impl Harborshield {
         {
             Ok(container) => {
                 // Check if harborshield is enabled
-                let enabled = container
-                    .labels
-                    .get(ENABLED_LABEL)
-                    .map(|v| v == "true")
-                    .unwrap_or(false);
+                let enabled = container.is_harborshield_enabled();
 
                 if enabled {
                     info!(
@@ -165,36 +206,42 @@ impl Harborshield {
 
     /// Handle container start event
     pub(super) async fn handle_container_start(&self, container_id: &str) -> Result<()> {
-        let container = self
-            .docker_client
-            .try_get_container_by_id(container_id)
-            .await?;
+        let container = stage::run(
+            Stage::Inspect,
+            self.docker_client.try_get_container_by_id(container_id),
+        )
+        .await?;
 
         info!("Container starting: {:#?}", container);
 
         // Always track the container for C2C rule resolution
         self.docker_client
-            .container_tracker
+            .container_tracker()
             .add_container(container.clone())?;
 
         // Check if harborshield is enabled
-        let enabled = container
-            .labels
-            .get(ENABLED_LABEL)
-            .map(|v| v == "true")
-            .unwrap_or(false);
+        let enabled = container.is_harborshield_enabled();
 
         if enabled {
-            // Store in database
-            super::Harborshield::store_container_in_database(&container, &self.db).await?;
-
-            // Apply firewall rules using direct config translation
-            self.create_container_rules(
-                &container, None, // cancellation_token
-            )
-            .await?;
+            match self.restart_started(&container) {
+                restarts::OnStart::Render => {
+                    // Store in database
+                    super::Harborshield::store_container_in_database(&container, &self.db).await?;
+
+                    // Apply firewall rules using direct config translation
+                    self.create_container_rules(
+                        &container, None, // cancellation_token
+                    )
+                    .await?;
+                    self.restart_rendered(&container);
+                }
+                restarts::OnStart::Skip => {}
+                restarts::OnStart::Defer => self.redirect_addresses(&container).await?,
+            }
         }
 
+        self.sync_service_peers(&container).await;
+
         // ALWAYS process waiting rules for this container
         // This is needed for containers that receive C2C rules from other containers
         self.process_waiting_rules_for_container(&container.name, &container.id)
@@ -241,7 +288,7 @@ impl Harborshield {
                                 // Check if the referenced container has a chain
                                 if let Some(ref_container) = self
                                     .docker_client
-                                    .container_tracker
+                                    .container_tracker()
                                     .find_container(&output_rule.container)
                                 {
                                     let chain_name = format!(
@@ -293,20 +340,47 @@ impl Harborshield {
             }
         }
 
+        // A container in a restart loop keeps its rules for the next start
+        if let Some(tracked) = self
+            .docker_client
+            .container_tracker()
+            .get_container(container_id)
+            && tracked.is_harborshield_enabled()
+            && self.restart_died(&tracked) == restarts::OnDie::Hold
+        {
+            return Ok(());
+        }
+
+        self.remove_stopped_container(container_id).await
+    }
+
+    /// Untrack a stopped container and remove its rules
+    pub(super) async fn remove_stopped_container(&self, container_id: &str) -> Result<()> {
+        readiness::clear(container_id);
         if let Some(details) = self
             .docker_client
-            .container_tracker
+            .container_tracker()
             .remove_container(container_id)?
         {
             // Container IPs will be automatically removed when verdict maps are rebuilt
 
             // Remove from database
             self.remove_container_from_database(container_id).await?;
+            self.sync_service_peers(&details).await;
+
+            if crate::docker::rootless::is_rootless() {
+                self.sync_rootless().await;
+                return Ok(());
+            }
 
             // Now we can safely remove the container chain
             let mut transaction = NftablesTransaction::builder().build();
             transaction.remove_container_rules(container_id, &details.name)?;
             transaction.commit().await?;
+            self.sync_offload().await;
+            self.sync_forwards().await;
+            self.sync_tproxy().await;
+            self.sync_ipv6().await;
         }
         Ok(())
     }
@@ -318,12 +392,12 @@ impl Harborshield {
         // Mark container as paused and disable its rules
         if let Some(mut details) = self
             .docker_client
-            .container_tracker
+            .container_tracker()
             .get_container(container_id)
         {
             details.paused = true;
             self.docker_client
-                .container_tracker
+                .container_tracker()
                 .update_container(details.clone())?;
 
             // Disable firewall rules for paused container
@@ -344,12 +418,12 @@ impl Harborshield {
         // Mark container as unpaused and re-enable its rules
         if let Some(mut details) = self
             .docker_client
-            .container_tracker
+            .container_tracker()
             .get_container(container_id)
         {
             details.paused = false;
             self.docker_client
-                .container_tracker
+                .container_tracker()
                 .update_container(details.clone())?;
 
             // Only re-enable rules if the container is enabled
@@ -406,7 +480,7 @@ impl Harborshield {
             // Get rules for any aliases
             if let Some(container) = self
                 .docker_client
-                .container_tracker
+                .container_tracker()
                 .find_container(container_id)
             {
                 for alias in &container.aliases {
@@ -433,7 +507,7 @@ impl Harborshield {
             // Get the target container's details
             let target_container = self
                 .docker_client
-                .container_tracker
+                .container_tracker()
                 .find_container(container_id)
                 .ok_or_else(|| {
                     crate::Error::invalid_state(
@@ -466,7 +540,7 @@ impl Harborshield {
                 // Get the source container
                 if let Some(src_container) = self
                     .docker_client
-                    .container_tracker
+                    .container_tracker()
                     .find_container(&waiting_rule.src_container_id)
                 {
                     // Only process if the source container has harborshield enabled
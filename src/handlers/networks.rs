@@ -0,0 +1,127 @@
+//! Output rules scoped to one network of a container on several.
+//!
+//! A rule's `network:` names the attachment it is for. The rule then only
+//! matches traffic of the container's address on that network, and a
+//! `container:` reference only reaches the target's address there, not
+//! those it has on networks the two don't share. A rule for a network the
+//! container isn't attached to is left out until it is.
+
+use crate::docker::config::Config;
+use crate::docker::container::Container;
+use std::net::IpAddr;
+use tracing::debug;
+
+/// Tie each rule naming a network to the container's addresses on it
+pub fn scope_to_networks(container: &Container, config: &mut Config) {
+    for (idx, rule) in config.output.iter_mut().enumerate() {
+        if rule.network.is_empty() {
+            continue;
+        }
+        match container.networks.get(&rule.network) {
+            Some(network) if !network.ip_addresses.is_empty() => {
+                rule.attachment = network.ip_addresses.clone();
+            }
+            _ => {
+                debug!(
+                    "{} has no address on network '{}', skipping output rule {}",
+                    container.name,
+                    rule.network,
+                    idx + 1
+                );
+                rule.skip = true;
+            }
+        }
+    }
+}
+
+/// The addresses of `target` a rule for `network` reaches; all of them
+/// when the rule names no network
+pub fn target_addresses(target: &Container, network: &str) -> Vec<IpAddr> {
+    if network.is_empty() {
+        return target
+            .networks
+            .values()
+            .flat_map(|network| network.ip_addresses.iter().copied())
+            .collect();
+    }
+    target
+        .networks
+        .get(network)
+        .map(|network| network.ip_addresses.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::config::ToNftablesRule;
+    use crate::docker::container::Network;
+    use std::collections::HashMap;
+
+    fn container(name: &str, networks: &[(&str, &str)], rules: Option<&str>) -> Container {
+        Container::builder()
+            .id(format!("{}0123456789", name))
+            .name(name.to_string())
+            .networks(
+                networks
+                    .iter()
+                    .map(|(network, ip)| {
+                        (
+                            network.to_string(),
+                            Network::builder()
+                                .name(network.to_string())
+                                .ip_addresses(vec![ip.parse().unwrap()])
+                                .build(),
+                        )
+                    })
+                    .collect::<HashMap<_, _>>(),
+            )
+            .maybe_config(rules.map(|rules| serde_yaml::from_str(rules).unwrap()))
+            .build()
+    }
+
+    #[test]
+    fn test_scope_to_networks() {
+        let rules = "output:\n  \
+            - {proto: tcp, network: frontend, ips: [10.9.0.1], dst_ports: [443]}\n  \
+            - {proto: tcp, network: backend, container: db, dst_ports: [5432]}\n  \
+            - {proto: udp, network: storage, ips: [10.9.0.2], dst_ports: [2049]}\n  \
+            - {proto: udp, ips: [10.9.0.3], dst_ports: [53]}\n";
+        let app = container(
+            "app",
+            &[("frontend", "172.20.0.2"), ("backend", "172.21.0.2")],
+            Some(rules),
+        );
+        let db = container(
+            "db",
+            &[("backend", "172.21.0.3"), ("admin", "172.22.0.3")],
+            None,
+        );
+
+        let mut config = app.config.clone().unwrap();
+        scope_to_networks(&app, &mut config);
+        assert_eq!(
+            config.output[0].attachment,
+            ["172.20.0.2".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            config.output[1].attachment,
+            ["172.21.0.2".parse::<IpAddr>().unwrap()]
+        );
+        // Not attached to storage
+        assert!(config.output[2].skip);
+        // No network, every attachment
+        assert!(config.output[3].attachment.is_empty() && !config.output[3].skip);
+
+        let statements =
+            serde_json::to_string(&config.output[0].to_nftables_statements().unwrap()).unwrap();
+        assert!(statements.contains("\"saddr\"") && statements.contains("172.20.0.2"));
+
+        assert_eq!(
+            target_addresses(&db, "backend"),
+            ["172.21.0.3".parse::<IpAddr>().unwrap()]
+        );
+        assert!(target_addresses(&db, "frontend").is_empty());
+        assert_eq!(target_addresses(&db, "").len(), 2);
+    }
+}
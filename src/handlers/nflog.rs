@@ -0,0 +1,136 @@
+//! With `--nflog-group`, counting and logging the packets container chains
+//! drop, by the container they were meant for.
+//!
+//! A drop belongs to the container whose address it was sent to, or else to
+//! the one that sent it. Packets neither address attributes, such as those of
+//! a container that just went away, fall back to the chain in the log prefix.
+
+use crate::host::nflog::LoggedPacket;
+use crate::nftables::nflog::parse_prefix;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// Container names by address and by chain
+#[derive(Debug, Default)]
+pub struct Attribution {
+    pub by_addr: HashMap<IpAddr, String>,
+    pub by_chain: HashMap<String, String>,
+}
+
+impl Attribution {
+    /// Name of the container a dropped packet is counted against
+    pub fn container(&self, packet: &LoggedPacket) -> Option<&str> {
+        self.by_addr
+            .get(&packet.dst)
+            .or_else(|| self.by_addr.get(&packet.src))
+            .or_else(|| {
+                let (chain, _) = parse_prefix(&packet.prefix)?;
+                self.by_chain.get(chain)
+            })
+            .map(String::as_str)
+    }
+}
+
+impl Harborshield {
+    fn drop_attribution(&self) -> Attribution {
+        let mut attribution = Attribution::default();
+        for container in self.docker_client.container_tracker().list_containers() {
+            let chain = format!(
+                "hs-{}-{}",
+                container.name.replace(['_', '.', '/'], "-"),
+                &container.id[..12.min(container.id.len())]
+            );
+            for ip in container
+                .ip_addresses(false)
+                .into_iter()
+                .chain(container.ip_addresses(true))
+            {
+                attribution.by_addr.insert(ip, container.name.clone());
+            }
+            attribution.by_chain.insert(chain, container.name);
+        }
+        attribution
+    }
+
+    /// Count and log the packets logged to `group` until shutdown
+    pub(crate) fn spawn_drop_listener(
+        &self,
+        group: u16,
+    ) -> std::io::Result<tokio::task::JoinHandle<()>> {
+        let socket = crate::host::nflog::NflogSocket::open(group)?;
+        let handlers = self.clone();
+
+        Ok(tokio::spawn(async move {
+            loop {
+                let packets = tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    packets = socket.packets() => match packets {
+                        Ok(packets) => packets,
+                        Err(e) => {
+                            warn!("Stopped reading NFLOG group {}: {}", group, e);
+                            break;
+                        }
+                    },
+                };
+                if packets.is_empty() {
+                    continue;
+                }
+
+                let attribution = handlers.drop_attribution();
+                for packet in packets {
+                    let container = attribution.container(&packet).unwrap_or("unknown");
+                    crate::server::increment_dropped_packets(container);
+                    info!(
+                        container = %container,
+                        src = %packet.src,
+                        dst = %packet.dst,
+                        proto = %packet.proto,
+                        dport = ?packet.dport,
+                        prefix = %packet.prefix.trim_end(),
+                        "Dropped packet"
+                    );
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_attribution() {
+        let attribution = Attribution {
+            by_addr: HashMap::from([
+                ("172.17.0.2".parse().unwrap(), "nginx".to_string()),
+                ("172.17.0.3".parse().unwrap(), "worker".to_string()),
+            ]),
+            by_chain: HashMap::from([("hs-db-000111222333".to_string(), "db".to_string())]),
+        };
+        let packet = |src: &str, dst: &str| LoggedPacket {
+            prefix: "hs-db-000111222333 DROP: ".to_string(),
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            proto: "tcp".to_string(),
+            dport: Some(443),
+        };
+
+        // Inbound to nginx, outbound from worker, then by chain
+        assert_eq!(
+            attribution.container(&packet("203.0.113.9", "172.17.0.2")),
+            Some("nginx")
+        );
+        assert_eq!(
+            attribution.container(&packet("172.17.0.3", "198.51.100.1")),
+            Some("worker")
+        );
+        assert_eq!(
+            attribution.container(&packet("203.0.113.9", "172.17.0.9")),
+            Some("db")
+        );
+    }
+}
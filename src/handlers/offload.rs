@@ -0,0 +1,114 @@
+//! Keeping the fastpath chain in line with the output rules marked
+//! `offload: true`; see [`crate::nftables::flowtable`].
+
+use crate::database::EnforcementMode;
+use crate::docker::config::Config;
+use crate::docker::container::Container;
+use crate::nftables::flowtable::{self, OffloadTarget};
+use tracing::{debug, warn};
+
+use super::Harborshield;
+use super::utils::resolve_config;
+
+/// A container's offloaded rules as numbered in its chain
+pub fn offload_target<'a>(container: &'a Container, config: &'a Config) -> OffloadTarget<'a> {
+    OffloadTarget {
+        container_name: &container.name,
+        ips: container
+            .networks
+            .values()
+            .flat_map(|network| network.ip_addresses.iter().copied())
+            .collect(),
+        rules: config
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.offload && !rule.skip && rule.container.is_empty())
+            .map(|(i, rule)| (i + 1, rule))
+            .collect(),
+    }
+}
+
+impl Harborshield {
+    /// Rebuild the fastpath chain from every tracked container. Quarantined
+    /// and disabled containers offload nothing
+    pub(super) async fn sync_offload(&self) {
+        if !flowtable::enabled() {
+            return;
+        }
+
+        let host_addrs = self.host_addrs.get();
+        let now = chrono::Utc::now().timestamp();
+        let mut resolved: Vec<(Container, Config)> = Vec::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            let Some(config) = &container.config else {
+                continue;
+            };
+            if !container.enabled || !config.output.iter().any(|rule| rule.offload) {
+                continue;
+            }
+            let mode = self.enforcement_mode(&container.identity()).await;
+            if !matches!(mode, EnforcementMode::Enforce | EnforcementMode::Permissive) {
+                continue;
+            }
+            let config = resolve_config(
+                &container,
+                config,
+                self.docker_client.container_tracker(),
+                &host_addrs,
+                now,
+            );
+            resolved.push((container, config));
+        }
+
+        let targets: Vec<OffloadTarget> = resolved
+            .iter()
+            .map(|(container, config)| offload_target(container, config))
+            .filter(|target| !target.rules.is_empty())
+            .collect();
+        let nftables = self.nftables_client.lock().await;
+        match nftables.rebuild_fastpath(&targets).await {
+            Ok(()) => debug!("Offloading flows for {} containers", targets.len()),
+            Err(e) => warn!("Failed to rebuild the fastpath chain: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offload_target() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+output:
+  - ips: ["10.0.5.20"]
+    proto: tcp
+    dst_ports: [443]
+  - ips: ["10.0.5.30"]
+    proto: tcp
+    dst_ports: [2049]
+    offload: true
+  - container: backup
+    network: storage
+    proto: tcp
+    dst_ports: [873]
+    offload: true
+"#,
+        )
+        .unwrap();
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("nas".to_string())
+            .build();
+
+        let target = offload_target(&container, &config);
+        assert_eq!(target.container_name, "nas");
+        // The unresolved container reference waits like it does in the chain
+        assert_eq!(
+            target.rules.iter().map(|(n, _)| *n).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
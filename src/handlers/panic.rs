@@ -0,0 +1,128 @@
+//! Keeping the kill switch table in line with the tracked containers; see
+//! [`crate::database::panic`].
+
+use crate::database::panic;
+use crate::docker::container::Container;
+use crate::nftables::panic as table;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use super::Harborshield;
+
+/// The containers' and allowed addresses last installed
+type Installed = (Vec<IpAddr>, Vec<IpNet>);
+
+/// The addresses of `containers`, sorted
+pub fn container_addresses(containers: &[Container]) -> Vec<IpAddr> {
+    let mut ips: Vec<IpAddr> = containers
+        .iter()
+        .flat_map(|container| {
+            let mut ips = container.ip_addresses(false);
+            ips.extend(container.ip_addresses(true));
+            ips
+        })
+        .collect();
+    ips.sort();
+    ips.dedup();
+    ips
+}
+
+impl Harborshield {
+    /// Follow the stored kill switch every `interval` until shutdown,
+    /// cutting off containers as they start while it is engaged and
+    /// removing the table once it is released
+    pub(crate) fn spawn_panic_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut installed: Option<Installed> = None;
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => installed = handlers.check_panic(installed).await,
+                }
+            }
+        })
+    }
+
+    async fn check_panic(&self, installed: Option<Installed>) -> Option<Installed> {
+        let stored = {
+            let db = self.db.lock().await;
+            panic::current(&db).await
+        };
+        let engaged = match stored {
+            Ok(engaged) => engaged,
+            Err(e) => {
+                warn!("Failed to read the kill switch: {}", e);
+                return installed;
+            }
+        };
+
+        let Some(engaged) = engaged else {
+            if installed.is_some() {
+                if let Err(e) = table::uninstall().await {
+                    warn!("Failed to remove the kill switch table: {}", e);
+                    return installed;
+                }
+                info!("Kill switch released; container traffic flows again");
+            }
+            return None;
+        };
+
+        let wanted = (
+            container_addresses(&self.docker_client.container_tracker().list_containers()),
+            engaged.allowlist(),
+        );
+        if installed.as_ref() == Some(&wanted) {
+            return installed;
+        }
+        if let Err(e) = table::install(&wanted.0, &wanted.1).await {
+            warn!("Failed to install the kill switch table: {}", e);
+            return installed;
+        }
+        if installed.is_none() {
+            error!(
+                containers = wanted.0.len(),
+                "Kill switch engaged; all container traffic is dropped until `harborshield panic --release`"
+            );
+        }
+        Some(wanted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::Network;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_container_addresses() {
+        let container = |name: &str, ips: &[&str]| {
+            Container::builder()
+                .id(format!("{}-id", name))
+                .name(name.to_string())
+                .networks(HashMap::from([(
+                    "backend".to_string(),
+                    Network::builder()
+                        .name("backend".to_string())
+                        .ip_addresses(ips.iter().map(|ip| ip.parse().unwrap()).collect())
+                        .build(),
+                )]))
+                .build()
+        };
+        let ips = container_addresses(&[
+            container("web", &["172.18.0.3", "fd00::3"]),
+            container("db", &["172.18.0.2"]),
+            container("web-again", &["172.18.0.3"]),
+        ]);
+        let ips: Vec<String> = ips.iter().map(ToString::to_string).collect();
+        assert_eq!(ips, ["172.18.0.2", "172.18.0.3", "fd00::3"]);
+    }
+}
@@ -0,0 +1,188 @@
+//! Output rules that allow traffic `from` the containers of a compose
+//! service.
+//!
+//! A `from` rule matches the source addresses of every running replica of
+//! the service, filled in whenever the container's rules are built. Traffic
+//! a managed replica sends runs through its own chain first, so each
+//! replica also gets an output rule towards the container. Both sides are
+//! rebuilt as replicas start and stop, keeping the addresses in step with
+//! the service as it scales.
+
+use crate::docker::compose::COMPOSE_PROJECT_LABEL;
+use crate::docker::config::{AddrOrRange, Config, RuleConfig, ServiceRef};
+use crate::docker::container::Container;
+use tracing::{debug, warn};
+
+use super::Harborshield;
+
+fn project(container: &Container) -> Option<&str> {
+    container
+        .labels
+        .get(COMPOSE_PROJECT_LABEL)
+        .map(String::as_str)
+}
+
+/// Container chains only match IPv4 addresses
+fn addrs(container: &Container) -> Vec<AddrOrRange> {
+    container
+        .ip_addresses(false)
+        .into_iter()
+        .filter(|ip| ip.is_ipv4())
+        .map(AddrOrRange::Addr)
+        .collect()
+}
+
+/// The `from` rules of `container` that select `peer`
+fn rules_from<'a>(container: &'a Container, peer: &Container) -> Vec<&'a RuleConfig> {
+    let Some(config) = &container.config else {
+        return Vec::new();
+    };
+    config
+        .output
+        .iter()
+        .filter(|rule| rule.enabled)
+        .filter(|rule| {
+            rule.from
+                .as_ref()
+                .is_some_and(|from| from.matches(&peer.labels, project(container)))
+        })
+        .collect()
+}
+
+fn replicas<'a>(
+    from: &ServiceRef,
+    container: &Container,
+    containers: &'a [Container],
+) -> impl Iterator<Item = &'a Container> {
+    let own_project = project(container).map(str::to_string);
+    let own_id = container.id.clone();
+    let from = from.clone();
+    containers
+        .iter()
+        .filter(move |c| c.id != own_id && from.matches(&c.labels, own_project.as_deref()))
+}
+
+/// Fill in the replica addresses of the `from` rules in `config`, and add
+/// the rules that let `container` reach the containers whose `from` rules
+/// select it, out of the running `containers`
+pub fn add_service_peers(container: &Container, config: &mut Config, containers: &[Container]) {
+    for rule in config.output.iter_mut() {
+        let Some(from) = &rule.from else {
+            continue;
+        };
+        rule.ips = replicas(from, container, containers)
+            .flat_map(addrs)
+            .collect();
+        if rule.ips.is_empty() {
+            debug!(
+                "No running containers of {}, output rule of {} skipped",
+                from, container.name
+            );
+            rule.skip = true;
+        }
+    }
+
+    for peer in containers.iter().filter(|c| c.id != container.id) {
+        let ips = addrs(peer);
+        if ips.is_empty() {
+            continue;
+        }
+        // Queue and chain verdicts belong to the peer's chain
+        let grants = rules_from(peer, container).into_iter().filter(|rule| {
+            rule.verdict.chain.is_empty() && rule.verdict.queue == 0 && !rule.verdict.drop
+        });
+        for rule in grants {
+            config.output.push(
+                RuleConfig::builder()
+                    .ips(ips.clone())
+                    .proto(rule.proto)
+                    .dst_ports(rule.dst_ports.clone())
+                    .build(),
+            );
+        }
+    }
+}
+
+impl Harborshield {
+    /// Rebuild the containers whose rules involve `changed` through `from`,
+    /// after it started or stopped
+    pub(super) async fn sync_service_peers(&self, changed: &Container) {
+        let containers = self.docker_client.container_tracker().list_containers();
+        for container in containers.iter().filter(|c| {
+            c.id != changed.id
+                && c.is_harborshield_enabled()
+                && (!rules_from(c, changed).is_empty() || !rules_from(changed, c).is_empty())
+        }) {
+            debug!(
+                "Rebuilding rules of {} as {} changed",
+                container.name, changed.name
+            );
+            if let Err(e) = self.create_container_rules(container, None).await {
+                warn!(
+                    "Failed to rebuild rules of {} after {} changed: {}",
+                    container.name, changed.name, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::compose::COMPOSE_SERVICE_LABEL;
+    use crate::docker::container::Network;
+    use std::collections::HashMap;
+
+    fn container(id: &str, service: &str, ip: &str, rules: Option<&str>) -> Container {
+        Container::builder()
+            .id(id.to_string())
+            .name(format!("myapp-{}-{}", service, id))
+            .labels(HashMap::from([
+                (COMPOSE_PROJECT_LABEL.to_string(), "myapp".to_string()),
+                (COMPOSE_SERVICE_LABEL.to_string(), service.to_string()),
+            ]))
+            .networks(HashMap::from([(
+                "default".to_string(),
+                Network::builder()
+                    .name("default".to_string())
+                    .ip_addresses(vec![ip.parse().unwrap()])
+                    .build(),
+            )]))
+            .maybe_config(rules.map(|rules| serde_yaml::from_str(rules).unwrap()))
+            .build()
+    }
+
+    #[test]
+    fn test_service_peers() {
+        let rules = "output:\n  - proto: tcp\n    from: {service: web}\n    dst_ports: [5432]\n";
+        let db = container("1", "db", "172.20.0.2", Some(rules));
+        let web1 = container("2", "web", "172.20.0.3", None);
+        let web2 = container("3", "web", "172.20.0.4", Some("output: []\n"));
+        let worker = container("4", "worker", "172.20.0.5", None);
+        let containers = vec![db.clone(), web1, web2.clone(), worker];
+
+        let mut config = db.config.clone().unwrap();
+        add_service_peers(&db, &mut config, &containers);
+        let ips: Vec<String> = config.output[0]
+            .ips
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect();
+        assert_eq!(ips, ["172.20.0.3", "172.20.0.4"]);
+        assert!(!config.output[0].skip);
+        assert_eq!(config.output.len(), 1);
+
+        // A replica gets its way to the database
+        let mut config = web2.config.clone().unwrap();
+        add_service_peers(&web2, &mut config, &containers);
+        assert_eq!(config.output.len(), 1);
+        assert_eq!(config.output[0].ips[0].to_string(), "172.20.0.2");
+        assert!(config.output[0].from.is_none());
+
+        // Scaled down to nothing, the rule matches no one rather than anyone
+        let mut config = db.config.clone().unwrap();
+        add_service_peers(&db, &mut config, &containers[..1]);
+        assert!(config.output[0].skip);
+    }
+}
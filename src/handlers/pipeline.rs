@@ -0,0 +1,510 @@
+//! Bounded queue between the Docker event stream and the event handlers.
+//!
+//! The reader pushes events into a fixed-size queue. When the queue is full
+//! it waits up to [`BACKPRESSURE_WAIT`] for the handlers to catch up, which
+//! in turn stops it from reading the Docker socket. If there is still no
+//! room the event is shed: it is dropped and only the container ID is kept,
+//! so memory stays bounded no matter how fast a restart loop produces events.
+//!
+//! Once the queue has drained, shed containers are resynced from a fresh
+//! inspect instead of replaying their events. If more than
+//! [`MAX_PENDING_RESYNCS`] distinct containers were shed, a full sync of all
+//! containers runs instead.
+//!
+//! The worker also watches how old the events it gets are. Once it is more
+//! than [`MAX_EVENT_LAG`] behind with more events still queued, replaying the
+//! backlog one by one would only apply outdated states, so the queue is
+//! dropped and all containers are listed and diffed against the tracked
+//! rules instead.
+//!
+//! When the event stream had to be reopened, say after dockerd restarted,
+//! the reader asks the worker for a full resync, which it runs between
+//! events followed by a repairing reconcile (see [`crate::docker::reconnect`]).
+//!
+//! During a change freeze (see [`crate::database::freeze`]) events are
+//! only noted per container, and everything is resynced once it ends.
+//!
+//! Both sides report their state for [`event_loop_healthy`], which the
+//! systemd watchdog consults.
+
+use bollard::models::EventMessage;
+use futures::stream::{BoxStream, StreamExt};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{self, error::SendTimeoutError};
+use tracing::{debug, error, info, warn};
+
+use super::Harborshield;
+use super::stage::{self, Stage};
+
+pub const DEFAULT_EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// How long the reader waits for room in a full queue before shedding
+pub const BACKPRESSURE_WAIT: Duration = Duration::from_millis(100);
+
+/// Distinct shed containers remembered before falling back to a full sync
+pub const MAX_PENDING_RESYNCS: usize = 1024;
+
+/// Event age past which a queued backlog is dropped for a full resync
+pub const MAX_EVENT_LAG: Duration = Duration::from_secs(30);
+
+/// Whether the reader has a Docker event stream open
+static STREAM_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// When the worker started on the event it is handling, in unix seconds;
+/// 0 while it waits
+static HANDLING_SINCE: AtomicI64 = AtomicI64::new(0);
+
+pub(super) fn set_stream_connected(connected: bool) {
+    STREAM_CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+/// Whether the event stream is connected and the worker has not spent
+/// `stuck_after` or longer on one event, as of `now` (unix seconds)
+pub fn event_loop_healthy(now: i64, stuck_after: Duration) -> bool {
+    let since = HANDLING_SINCE.load(Ordering::Relaxed);
+    STREAM_CONNECTED.load(Ordering::Relaxed)
+        && (since == 0 || now - since < stuck_after.as_secs() as i64)
+}
+
+/// How long ago Docker emitted `event`, if it carries a timestamp
+pub fn event_lag(event: &EventMessage, now: SystemTime) -> Option<Duration> {
+    let emitted = match (event.time_nano, event.time) {
+        (Some(nanos), _) => {
+            SystemTime::UNIX_EPOCH + Duration::from_nanos(u64::try_from(nanos).ok()?)
+        }
+        (None, Some(secs)) => {
+            SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?)
+        }
+        (None, None) => return None,
+    };
+    // A timestamp from the future means no lag
+    Some(now.duration_since(emitted).unwrap_or_default())
+}
+
+/// What to do about events that were shed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resync {
+    None,
+    Containers(Vec<String>),
+    All,
+}
+
+/// Containers whose events were dropped, bounded by `limit`
+#[derive(Debug)]
+pub struct ShedSet {
+    ids: HashSet<String>,
+    overflowed: bool,
+    limit: usize,
+}
+
+impl ShedSet {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            ids: HashSet::new(),
+            overflowed: false,
+            limit,
+        }
+    }
+
+    pub fn record(&mut self, container_id: Option<&str>) {
+        if self.overflowed {
+            return;
+        }
+        match container_id {
+            Some(id) if self.ids.len() < self.limit || self.ids.contains(id) => {
+                self.ids.insert(id.to_string());
+            }
+            // Too many to track, or nothing to resync by
+            _ => {
+                self.ids.clear();
+                self.overflowed = true;
+            }
+        }
+    }
+
+    pub fn take(&mut self) -> Resync {
+        if std::mem::take(&mut self.overflowed) {
+            return Resync::All;
+        }
+        if self.ids.is_empty() {
+            return Resync::None;
+        }
+        let mut ids: Vec<String> = self.ids.drain().collect();
+        ids.sort();
+        Resync::Containers(ids)
+    }
+}
+
+/// Sending half of the event queue
+#[derive(Clone)]
+pub struct EventQueue {
+    tx: mpsc::Sender<EventMessage>,
+    capacity: usize,
+    shed: Arc<StdMutex<ShedSet>>,
+    reconnected: Arc<Notify>,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<EventMessage>) {
+        let capacity = capacity.max(1);
+        let (tx, rx) = mpsc::channel(capacity);
+        let queue = Self {
+            tx,
+            capacity,
+            shed: Arc::new(StdMutex::new(ShedSet::new(MAX_PENDING_RESYNCS))),
+            reconnected: Arc::new(Notify::new()),
+        };
+        (queue, rx)
+    }
+
+    /// Queue an event, shedding it if the handlers stay behind. Returns
+    /// false once the receiving side is gone.
+    pub async fn push(&self, event: EventMessage) -> bool {
+        let event = match self.tx.try_send(event) {
+            Ok(()) => {
+                self.report_depth();
+                return true;
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            Err(mpsc::error::TrySendError::Full(event)) => event,
+        };
+
+        crate::server::increment_event_backpressure();
+        match self.tx.send_timeout(event, BACKPRESSURE_WAIT).await {
+            Ok(()) => {}
+            Err(SendTimeoutError::Closed(_)) => return false,
+            Err(SendTimeoutError::Timeout(event)) => {
+                let id = event.actor.as_ref().and_then(|a| a.id.as_deref());
+                debug!("Event queue full, shedding event for {:?}", id);
+                crate::server::increment_events_shed();
+                stage::record_queue_error();
+                if let Ok(mut shed) = self.shed.lock() {
+                    shed.record(id);
+                }
+            }
+        }
+        self.report_depth();
+        true
+    }
+
+    pub fn depth(&self) -> usize {
+        self.capacity - self.tx.capacity()
+    }
+
+    fn report_depth(&self) {
+        crate::server::set_event_queue_depth(self.depth() as u64);
+        stage::set_queue_depth(self.depth());
+    }
+
+    /// Have the worker resync everything; events may have been missed
+    /// while the stream was down
+    pub fn request_reconnect_resync(&self) {
+        self.reconnected.notify_one();
+    }
+
+    /// Shed events still waiting for a resync
+    pub fn take_shed(&self) -> Resync {
+        self.shed
+            .lock()
+            .map(|mut s| s.take())
+            .unwrap_or(Resync::None)
+    }
+}
+
+/// Queue events from `stream` until it fails or ends, the queue closes or
+/// `shutdown` fires. Returns true when the reader should stop rather than
+/// reconnect
+pub async fn forward(
+    stream: &mut BoxStream<'_, crate::Result<EventMessage>>,
+    queue: &EventQueue,
+    shutdown: &mut mpsc::Receiver<()>,
+) -> bool {
+    loop {
+        tokio::select! {
+            event = stream.next() => match event {
+                // Waits while the queue is full, which holds back reads
+                // from the Docker socket
+                Some(Ok(event)) => {
+                    if !queue.push(event).await {
+                        return true;
+                    }
+                }
+                Some(Err(e)) => {
+                    error!("Error receiving Docker event: {}", e);
+                    return false;
+                }
+                None => {
+                    warn!("Docker event stream ended, reconnecting");
+                    return false;
+                }
+            },
+            _ = shutdown.recv() => {
+                info!("Event listener received shutdown signal");
+                return true;
+            }
+        }
+    }
+}
+
+/// Discard everything currently queued, returning how many events that was
+fn drain(rx: &mut mpsc::Receiver<EventMessage>) -> usize {
+    let mut count = 0;
+    while rx.try_recv().is_ok() {
+        count += 1;
+    }
+    count
+}
+
+impl Harborshield {
+    /// Handle queued events until shutdown, resyncing shed containers
+    /// whenever the queue runs dry
+    pub(super) async fn run_event_worker(
+        &self,
+        queue: EventQueue,
+        mut rx: mpsc::Receiver<EventMessage>,
+    ) {
+        loop {
+            let event = tokio::select! {
+                biased;
+                _ = self.cancellation_token.cancelled() => break,
+                _ = queue.reconnected.notified() => {
+                    // A freeze ends with a full resync anyway
+                    if !crate::database::freeze::is_frozen() {
+                        self.resync_after_reconnect(&queue).await;
+                    }
+                    continue;
+                }
+                event = rx.recv() => event,
+            };
+            let Some(event) = event else {
+                break;
+            };
+
+            queue.report_depth();
+            let lag = event_lag(&event, SystemTime::now());
+            if let Some(lag) = lag {
+                stage::record_queue_wait(lag);
+            }
+            let frozen = crate::database::freeze::is_frozen();
+            // During a freeze each event is only noted, which keeps up
+            if let Some(lag) = lag
+                && lag > MAX_EVENT_LAG
+                && !rx.is_empty()
+                && !frozen
+            {
+                let skipped = drain(&mut rx) + 1;
+                queue.report_depth();
+                warn!(
+                    "Event handling is {:?} behind, skipping {} queued events for a full resync",
+                    lag, skipped
+                );
+                crate::server::increment_event_lag_resyncs();
+                // The full resync covers anything shed so far
+                queue.take_shed();
+                self.resync_all().await;
+                continue;
+            }
+
+            HANDLING_SINCE.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+            if frozen {
+                self.hold_event(&event).await;
+            } else if let Err(e) = self.handle_event(event).await {
+                tracing::error!("Error handling Docker event: {}", e);
+            }
+
+            // Shed containers are resynced with the rest after a freeze
+            if rx.is_empty() && !frozen {
+                self.resync_shed(queue.take_shed()).await;
+            }
+            HANDLING_SINCE.store(0, Ordering::Relaxed);
+        }
+    }
+
+    async fn resync_shed(&self, resync: Resync) {
+        match resync {
+            Resync::None => {}
+            Resync::Containers(ids) => {
+                info!("Resyncing {} containers with shed events", ids.len());
+                crate::server::increment_event_resyncs("containers");
+                for id in ids {
+                    if let Err(e) = self.resync_container(&id).await {
+                        warn!("Failed to resync container {}: {}", id, e);
+                    }
+                }
+            }
+            Resync::All => {
+                warn!("Too many shed events to track, resyncing all containers");
+                crate::server::increment_event_resyncs("all");
+                self.resync_all().await;
+            }
+        }
+    }
+
+    /// Resync every container after the event stream came back, then
+    /// repair chains the daemon restart left missing or orphaned
+    async fn resync_after_reconnect(&self, queue: &EventQueue) {
+        info!("Event stream reopened, resyncing all containers");
+        crate::server::increment_event_resyncs("reconnect");
+        // The full resync covers anything shed so far
+        queue.take_shed();
+        self.resync_all().await;
+        if crate::nftables::standby::is_standby() {
+            return;
+        }
+        match self.reconcile(true).await {
+            Ok(report) => info!("Reconcile after reconnect: {}", report.summary()),
+            Err(e) => warn!("Reconcile after reconnect failed: {}", e),
+        }
+    }
+
+    /// List all containers and bring their rules in line with what runs
+    pub(super) async fn resync_all(&self) {
+        let result = async {
+            let stopped = self
+                .sync_containers(self.get_database_containers().await?)
+                .await?;
+            self.cleanup_stopped_containers(stopped).await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Full resync of all containers failed: {}", e);
+        }
+    }
+
+    /// Bring one container's rules in line with its current state
+    async fn resync_container(&self, container_id: &str) -> crate::Result<()> {
+        let inspect = stage::run(
+            Stage::Inspect,
+            self.docker_client.inspect_container(container_id),
+        );
+        let running = match inspect.await {
+            Ok(inspect) => inspect.state.and_then(|s| s.running).unwrap_or(false),
+            // Already removed
+            Err(_) => false,
+        };
+
+        if running {
+            self.handle_container_start(container_id).await
+        } else {
+            self.handle_container_stop(container_id).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::EventActor;
+
+    fn event(id: &str) -> EventMessage {
+        EventMessage {
+            action: Some("start".to_string()),
+            actor: Some(EventActor {
+                id: Some(id.to_string()),
+                attributes: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shed_set_overflow() {
+        let mut shed = ShedSet::new(2);
+        assert_eq!(shed.take(), Resync::None);
+
+        shed.record(Some("b"));
+        shed.record(Some("a"));
+        shed.record(Some("a"));
+        assert_eq!(
+            shed.take(),
+            Resync::Containers(vec!["a".to_string(), "b".to_string()])
+        );
+
+        for id in ["a", "b", "c"] {
+            shed.record(Some(id));
+        }
+        assert_eq!(shed.take(), Resync::All);
+        assert_eq!(shed.take(), Resync::None);
+
+        shed.record(None);
+        assert_eq!(shed.take(), Resync::All);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_sheds() {
+        let (queue, mut rx) = EventQueue::new(1);
+
+        assert!(queue.push(event("first")).await);
+        assert_eq!(queue.depth(), 1);
+        // Nobody is reading, so this one waits out the backpressure and is shed
+        assert!(queue.push(event("second")).await);
+        assert_eq!(queue.depth(), 1);
+
+        let queued = rx.recv().await.unwrap();
+        assert_eq!(queued.actor.unwrap().id.as_deref(), Some("first"));
+        assert_eq!(
+            queue.take_shed(),
+            Resync::Containers(vec!["second".to_string()])
+        );
+
+        drop(rx);
+        assert!(!queue.push(event("third")).await);
+    }
+
+    #[tokio::test]
+    async fn test_event_lag() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut old = event("a");
+        old.time = Some(900);
+        assert_eq!(event_lag(&old, now), Some(Duration::from_secs(100)));
+
+        // Nanoseconds are preferred when both are set
+        old.time_nano = Some(999_500_000_000);
+        assert_eq!(event_lag(&old, now), Some(Duration::from_millis(500)));
+
+        let mut future = event("b");
+        future.time = Some(2_000);
+        assert_eq!(event_lag(&future, now), Some(Duration::ZERO));
+        assert_eq!(event_lag(&event("c"), now), None);
+
+        let (queue, mut rx) = EventQueue::new(4);
+        for id in ["a", "b", "c"] {
+            assert!(queue.push(event(id)).await);
+        }
+        assert_eq!(drain(&mut rx), 3);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_forward_from_runtime() {
+        use crate::runtime::ContainerRuntime;
+        use crate::runtime::fake::{self, FakeContainer, FakeRuntime};
+
+        let runtime = FakeRuntime::default();
+        runtime.add_container(FakeContainer::builder().id("abc").name("web").build());
+        let (queue, mut rx) = EventQueue::new(4);
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
+
+        // A failing stream has the reader reconnect
+        let mut stream = runtime.events().await.unwrap();
+        runtime.stop("abc").unwrap();
+        runtime.emit(fake::event("rename", "abc", &[("name", "api")]));
+        runtime.fail_streams("daemon restarted");
+        assert!(!forward(&mut stream, &queue, &mut shutdown_rx).await);
+        let actions: Vec<String> = [rx.recv().await, rx.recv().await]
+            .into_iter()
+            .map(|event| event.unwrap().action.unwrap())
+            .collect();
+        assert_eq!(actions, vec!["die", "rename"]);
+
+        // Shutdown stops it for good
+        let mut stream = runtime.events().await.unwrap();
+        shutdown_tx.send(()).await.unwrap();
+        assert!(forward(&mut stream, &queue, &mut shutdown_rx).await);
+        assert!(rx.is_empty());
+    }
+}
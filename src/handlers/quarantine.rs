@@ -0,0 +1,225 @@
+//! Automatic quarantine of containers whose drops pass the threshold of
+//! their `quarantine` policy.
+//!
+//! Drops are taken from the counter samples of the stats job. A container
+//! over its threshold is switched to the `quarantined` enforcement mode,
+//! which the enforcement watcher applies like any other mode change, and the
+//! switch is logged, audited, counted and posted to `--quarantine-webhook`.
+//! Connections established before the switch keep flowing until they close,
+//! unless `--flush-revoked-flows` cuts them.
+//! `harborshield release <container>` puts it back to enforcing.
+
+use crate::database::{AuditEntry, EnforcementMode, audit, enforcement};
+use crate::docker::config::QuarantinePolicy;
+use crate::events;
+use crate::server;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tracing::{error, warn};
+
+use super::Harborshield;
+
+/// Time allowed for the quarantine webhook to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Recent drop counts per container identity
+#[derive(Debug, Default)]
+pub struct DropWindows {
+    samples: HashMap<String, VecDeque<(i64, u64)>>,
+}
+
+impl DropWindows {
+    /// Add `drops` seen at `now` and return the total within `window`
+    pub fn record(&mut self, identity: &str, now: i64, drops: u64, window: Duration) -> u64 {
+        let samples = self.samples.entry(identity.to_string()).or_default();
+        samples.push_back((now, drops));
+        let since = now - window.as_secs() as i64;
+        while samples.front().is_some_and(|(ts, _)| *ts <= since) {
+            samples.pop_front();
+        }
+        samples.iter().map(|(_, drops)| drops).sum()
+    }
+
+    pub fn forget(&mut self, identity: &str) {
+        self.samples.remove(identity);
+    }
+
+    /// Drop the windows of containers no longer tracked
+    pub fn retain(&mut self, identities: &[String]) {
+        self.samples
+            .retain(|identity, _| identities.contains(identity));
+    }
+}
+
+/// Posted to the quarantine webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantineNotice {
+    pub container: String,
+    pub drops: u64,
+    pub window_seconds: u64,
+    /// Unix seconds
+    pub quarantined_at: i64,
+    pub release: String,
+}
+
+impl QuarantineNotice {
+    pub fn new(identity: &str, drops: u64, policy: &QuarantinePolicy, now: i64) -> Self {
+        Self {
+            container: identity.to_string(),
+            drops,
+            window_seconds: policy.window.as_secs(),
+            quarantined_at: now,
+            release: format!("harborshield release {}", identity),
+        }
+    }
+}
+
+async fn post_notice(url: &str, notice: &QuarantineNotice) -> reqwest::Result<()> {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()?
+        .post(url)
+        .json(&events::envelope(events::EVENT_QUARANTINED, notice))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+impl Harborshield {
+    /// Quarantine enforced containers whose drops within their policy's
+    /// window reached its threshold. `drops` holds the packets dropped per
+    /// identity since the previous sample
+    pub(super) async fn check_quarantine(
+        &self,
+        windows: &mut DropWindows,
+        drops: &HashMap<String, u64>,
+        now: i64,
+    ) {
+        let mut policies: Vec<(String, QuarantinePolicy)> = self
+            .docker_client
+            .container_tracker()
+            .list_containers()
+            .into_iter()
+            .filter_map(|container| {
+                let policy = container.config.as_ref()?.quarantine.clone()?;
+                Some((container.identity(), policy))
+            })
+            .collect();
+        policies.sort_by(|a, b| a.0.cmp(&b.0));
+        policies.dedup_by(|a, b| a.0 == b.0);
+        windows.retain(
+            &policies
+                .iter()
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>(),
+        );
+
+        for (identity, policy) in policies {
+            // Drops of a quarantined container only show the quarantine
+            // working, and a permissive or disabled one is deliberately let
+            // through
+            if self.enforcement_mode(&identity).await != EnforcementMode::Enforce {
+                windows.forget(&identity);
+                continue;
+            }
+            let seen = drops.get(&identity).copied().unwrap_or(0);
+            let total = windows.record(&identity, now, seen, policy.window);
+            if total < policy.max_drops {
+                continue;
+            }
+            windows.forget(&identity);
+            self.quarantine(&identity, total, &policy, now).await;
+        }
+    }
+
+    async fn quarantine(&self, identity: &str, drops: u64, policy: &QuarantinePolicy, now: i64) {
+        let mut db = self.db.lock().await;
+        if let Err(e) = enforcement::set_mode(&db, identity, EnforcementMode::Quarantined).await {
+            warn!("Failed to quarantine {}: {}", identity, e);
+            return;
+        }
+        error!(
+            container = %identity,
+            drops,
+            window_seconds = policy.window.as_secs(),
+            "Container quarantined after exceeding its drop threshold; release it with `harborshield release {}`",
+            identity
+        );
+        server::increment_quarantines(identity);
+
+        let entry = AuditEntry::builder()
+            .ts(now)
+            .kind(audit::KIND_QUARANTINED)
+            .container_name(identity.to_string())
+            .detail(format!(
+                "{} drops in {}s (threshold {})",
+                drops,
+                policy.window.as_secs(),
+                policy.max_drops
+            ))
+            .build();
+        if let Err(e) = audit::record(&mut db, &[entry]).await {
+            warn!("Failed to record quarantine of {}: {}", identity, e);
+        }
+        drop(db);
+
+        if let Some(url) = &self.quarantine_webhook {
+            let notice = QuarantineNotice::new(identity, drops, policy, now);
+            if let Err(e) = post_notice(url, &notice).await {
+                warn!("Failed to post quarantine notice to {}: {}", url, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_windows() {
+        let window = Duration::from_secs(300);
+        let mut windows = DropWindows::default();
+        assert_eq!(windows.record("web", 0, 100, window), 100);
+        assert_eq!(windows.record("web", 60, 150, window), 250);
+        // The first sample falls out of the window
+        assert_eq!(windows.record("web", 300, 10, window), 160);
+        assert_eq!(windows.record("db", 300, 5, window), 5);
+
+        windows.forget("web");
+        assert_eq!(windows.record("web", 360, 1, window), 1);
+        windows.retain(&["web".to_string()]);
+        assert_eq!(windows.record("db", 360, 0, window), 0);
+
+        let policy: QuarantinePolicy =
+            serde_yaml::from_str("max_drops: 500\nwindow: 10m\nmanagement: [10.0.9.0/24]").unwrap();
+        assert_eq!(policy.window, Duration::from_secs(600));
+        let notice = QuarantineNotice::new("web", 512, &policy, 1000);
+        assert_eq!(notice.release, "harborshield release web");
+        assert_eq!(notice.window_seconds, 600);
+
+        let config: crate::docker::config::Config = serde_yaml::from_str(
+            r#"
+output:
+  - ips: ["192.0.2.10"]
+    proto: tcp
+    dst_ports: [443]
+quarantine:
+  max_drops: 500
+  management: ["10.0.9.0/24"]
+"#,
+        )
+        .unwrap();
+        let quarantined = config.quarantined();
+        assert!(quarantined.output.is_empty());
+        assert!(!quarantined.mapped_ports.localhost.allow);
+        assert!(quarantined.mapped_ports.external.allow);
+        assert_eq!(quarantined.mapped_ports.external.ips.len(), 1);
+        assert!(
+            serde_yaml::from_str::<crate::docker::config::Config>("quarantine: {max_drops: 0}")
+                .is_err()
+        );
+    }
+}
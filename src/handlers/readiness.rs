@@ -0,0 +1,77 @@
+//! Which containers have their rules in place, answered by
+//! `GET /ready/<container>` for `harborshield-wait`.
+//!
+//! A container counts as ready once its chain and dispatch were committed,
+//! and stops counting when it is removed. Containers on the host network
+//! have no rules to wait for and never become ready.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+static READY: LazyLock<Mutex<HashMap<String, Ready>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// A container whose rules are active
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Ready {
+    pub id: String,
+    pub name: String,
+    /// Unix seconds of the last time its rules were applied
+    pub since: i64,
+}
+
+/// Record that the rules of container `id` were applied
+pub fn mark_ready(id: &str, name: &str) {
+    if let Ok(mut ready) = READY.lock() {
+        ready.insert(
+            id.to_string(),
+            Ready {
+                id: id.to_string(),
+                name: name.to_string(),
+                since: chrono::Utc::now().timestamp(),
+            },
+        );
+    }
+}
+
+pub fn clear(id: &str) {
+    if let Ok(mut ready) = READY.lock() {
+        ready.remove(id);
+    }
+}
+
+/// `container` by name, full ID or an ID prefix of at least 12
+/// characters, which is the hostname Docker gives a container
+pub fn lookup(container: &str) -> Option<Ready> {
+    let ready = READY.lock().ok()?;
+    ready
+        .get(container)
+        .or_else(|| ready.values().find(|r| r.name == container))
+        .or_else(|| {
+            (container.len() >= 12)
+                .then(|| ready.values().find(|r| r.id.starts_with(container)))
+                .flatten()
+        })
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_lookup() {
+        let id = "3f4e5d6c7b8a9f0e1d2c3b4a5f6e7d8c9b0a1f2e3d4c5b6a7f8e9d0c1b2a3f4e";
+        assert_eq!(lookup("ready-web"), None);
+
+        mark_ready(id, "ready-web");
+        assert_eq!(lookup("ready-web").map(|r| r.id), Some(id.to_string()));
+        assert!(lookup(&id[..12]).is_some());
+        // Too short to tell containers apart
+        assert!(lookup(&id[..6]).is_none());
+
+        clear(id);
+        assert_eq!(lookup(id), None);
+    }
+}
@@ -0,0 +1,295 @@
+//! Scheduled reconcile that compares the live ruleset with what running
+//! containers should have, records any drift in the audit log and optionally
+//! repairs it and posts the report to a webhook.
+
+use crate::{
+    database::{AuditEntry, audit},
+    guests,
+    nftables::counters::list_chain_rule_counts,
+};
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::Serialize;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+pub use harborshield_core::{Drift, DriftKind, ExpectedChain, find_drift};
+
+/// Time allowed for the report webhook to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+static LAST: RwLock<Option<ReconcileReport>> = RwLock::new(None);
+
+/// The report of the last reconcile since startup
+pub fn last() -> Option<ReconcileReport> {
+    LAST.read().ok().and_then(|last| last.clone())
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconcileSchedule {
+    /// Time of day (UTC) to run at
+    pub at: NaiveTime,
+    /// Fix drift instead of only reporting it
+    pub repair: bool,
+    /// URL the JSON report is POSTed to after each run
+    pub webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconcileReport {
+    /// Unix seconds
+    pub started_at: i64,
+    /// Unix seconds
+    pub finished_at: i64,
+    pub chains_checked: usize,
+    pub repair: bool,
+    pub drift: Vec<Drift>,
+}
+
+impl ReconcileReport {
+    pub fn summary(&self) -> String {
+        let repaired = self.drift.iter().filter(|d| d.repaired).count();
+        format!(
+            "checked {} chains, found {} drift, repaired {}",
+            self.chains_checked,
+            self.drift.len(),
+            repaired
+        )
+    }
+
+    pub fn audit_entries(&self) -> Vec<AuditEntry> {
+        let ts = self.finished_at;
+        std::iter::once(
+            AuditEntry::builder()
+                .ts(ts)
+                .kind(audit::KIND_RECONCILE)
+                .detail(self.summary())
+                .build(),
+        )
+        .chain(self.drift.iter().map(|d| {
+            AuditEntry::builder()
+                .ts(ts)
+                .kind(audit::KIND_DRIFT)
+                .maybe_container_name(d.container_name.clone())
+                .detail(format!("{} {}", d.kind.as_str(), d.chain))
+                .repaired(d.repaired)
+                .build()
+        }))
+        .collect()
+    }
+}
+
+/// Time until the next `at` (UTC) strictly after `now`
+pub fn next_run_delay(now: DateTime<Utc>, at: NaiveTime) -> Duration {
+    let today = now.date_naive().and_time(at).and_utc();
+    let next = if today > now {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    };
+    (next - now).to_std().unwrap_or_default()
+}
+
+impl Harborshield {
+    /// Run a reconcile every day at the scheduled time until shutdown
+    pub(crate) fn spawn_reconcile_job(&self, schedule: ReconcileSchedule) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let delay = next_run_delay(Utc::now(), schedule.at);
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+                // The active host's ruleset is the one to reconcile
+                if crate::nftables::standby::is_standby() {
+                    continue;
+                }
+
+                match handlers.reconcile(schedule.repair).await {
+                    Ok(report) => {
+                        info!("Nightly reconcile: {}", report.summary());
+                        if let Some(url) = &schedule.webhook
+                            && let Err(e) = post_report(url, &report).await
+                        {
+                            warn!("Failed to post reconcile report to {}: {}", url, e);
+                        }
+                    }
+                    Err(e) => warn!("Nightly reconcile failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Compare the live ruleset with running containers, repair drift if
+    /// asked to, and record the outcome in the audit log
+    pub async fn reconcile(&self, repair: bool) -> crate::Result<ReconcileReport> {
+        let started_at = Utc::now().timestamp();
+
+        let mut expected = Vec::new();
+        for summary in self.docker_client.list_containers().await? {
+            let Some(id) = summary.id else {
+                continue;
+            };
+            let Ok(container) = self.docker_client.try_get_container_by_id(&id).await else {
+                continue;
+            };
+            // Chains are only created for containers with rules
+            let required = container.is_harborshield_enabled()
+                && !container.uses_host_network
+                && container.config.is_some();
+            expected.push(ExpectedChain {
+                chain: format!(
+                    "hs-{}-{}",
+                    container.name.replace(['_', '.', '/'], "-"),
+                    &id[..12.min(id.len())]
+                ),
+                container_id: id,
+                container_name: container.identity(),
+                required,
+            });
+        }
+
+        for (chain, container_id, container_name) in self.guest_chains() {
+            let running = self
+                .docker_client
+                .container_tracker()
+                .get_container(&container_id)
+                .is_some();
+            expected.push(ExpectedChain {
+                chain,
+                container_id,
+                container_name,
+                required: running,
+            });
+        }
+
+        let actual = list_chain_rule_counts()
+            .await
+            .map_err(|e| crate::Error::config(format!("Failed to list chains: {}", e)))?;
+        let mut drift = find_drift(&expected, &actual);
+
+        if repair {
+            self.repair_drift(&mut drift).await;
+        }
+
+        let report = ReconcileReport {
+            started_at,
+            finished_at: Utc::now().timestamp(),
+            chains_checked: actual.len(),
+            repair,
+            drift,
+        };
+
+        let mut db = self.db.lock().await;
+        audit::record(&mut db, &report.audit_entries()).await?;
+        if let Ok(mut last) = LAST.write() {
+            *last = Some(report.clone());
+        }
+        Ok(report)
+    }
+
+    async fn repair_drift(&self, drift: &mut [Drift]) {
+        let has_orphans = drift.iter().any(|d| d.kind == DriftKind::OrphanedChain);
+        let orphans_removed = if has_orphans {
+            match self.cleanup_orphaned_rules().await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to remove orphaned chains: {}", e);
+                    false
+                }
+            }
+        } else {
+            false
+        };
+
+        for d in drift.iter_mut() {
+            d.repaired = match (d.kind, &d.container_id) {
+                (DriftKind::OrphanedChain, _) => orphans_removed,
+                (DriftKind::MissingChain, Some(id)) => {
+                    let result = match self
+                        .guests()
+                        .iter()
+                        .find(|g| guests::guest_id(&g.name) == *id)
+                    {
+                        Some(guest) => self.reapply_guest(guest).await,
+                        None => self.handle_container_start(id).await,
+                    };
+                    match result {
+                        Ok(()) => true,
+                        Err(e) => {
+                            warn!("Failed to recreate chain {}: {}", d.chain, e);
+                            false
+                        }
+                    }
+                }
+                (DriftKind::MissingChain, None) => false,
+            };
+        }
+    }
+}
+
+async fn post_report(url: &str, report: &ReconcileReport) -> reqwest::Result<()> {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()?
+        .post(url)
+        .json(&crate::events::envelope(
+            crate::events::EVENT_RECONCILE,
+            report,
+        ))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_audit_entries() {
+        let report = ReconcileReport {
+            started_at: 100,
+            finished_at: 160,
+            chains_checked: 2,
+            repair: true,
+            drift: vec![Drift {
+                kind: DriftKind::MissingChain,
+                chain: "hs-db-def".to_string(),
+                container_id: Some("def".to_string()),
+                container_name: Some("db".to_string()),
+                repaired: true,
+            }],
+        };
+
+        let entries = report.audit_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].kind, audit::KIND_RECONCILE);
+        assert_eq!(
+            entries[0].detail,
+            "checked 2 chains, found 1 drift, repaired 1"
+        );
+        assert_eq!(entries[1].detail, "missing chain hs-db-def");
+        assert!(entries[1].repaired);
+    }
+
+    #[test]
+    fn test_next_run_delay() {
+        let at = NaiveTime::from_hms_opt(3, 0, 0).unwrap();
+        let before = DateTime::parse_from_rfc3339("2024-05-01T02:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(next_run_delay(before, at), Duration::from_secs(30 * 60));
+
+        let exactly = DateTime::parse_from_rfc3339("2024-05-01T03:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(next_run_delay(exactly, at), Duration::from_secs(24 * 3600));
+    }
+}
@@ -0,0 +1,57 @@
+//! Reload on SIGHUP: re-read the guests file, re-inspect every container
+//! and reconcile the ruleset, without restarting the daemon.
+//!
+//! The ruleset is rebuilt the way [`start`](Harborshield::start) builds it,
+//! orphaned chains included, then checked against the running containers
+//! by a repairing [`reconcile`](Harborshield::reconcile) whose report lands
+//! in the audit log. Command-line flags only take effect on restart.
+
+use crate::guests::{self, GuestSpec};
+use tracing::info;
+
+use super::Harborshield;
+use super::reconcile::ReconcileReport;
+
+impl Harborshield {
+    /// Swap in `guests` when given, re-sync all containers and guests, and
+    /// repair what is still out of line
+    pub async fn reload(&self, guests: Option<Vec<GuestSpec>>) -> crate::Result<ReconcileReport> {
+        info!("Reloading: re-syncing containers and reconciling the ruleset");
+
+        if let Some(guests) = guests {
+            let tracker = self.docker_client.container_tracker();
+            for removed in self
+                .guests()
+                .iter()
+                .filter(|old| !guests.iter().any(|new| new.name == old.name))
+            {
+                info!("Guest {} is no longer configured", removed.name);
+                tracker.remove_container(&guests::guest_id(&removed.name))?;
+            }
+            if let Ok(mut current) = self.guests.write() {
+                *current = guests;
+            }
+        }
+
+        // Chains of removed guests are orphaned now and go with the others
+        self.sync_all().await?;
+
+        let report = self.reconcile(true).await?;
+        self.update_metrics().await;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ReloadSignal;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_reload_signal() {
+        let mut reloads = ReloadSignal::new().unwrap();
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGHUP).unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), reloads.recv()).await;
+        assert_eq!(received, Ok(Some(())));
+    }
+}
@@ -0,0 +1,130 @@
+//! Daily consistency report, built at `--report-at` and written to a
+//! directory, posted to a webhook, or both.
+
+use crate::report::{ConsistencyReport, ReportFormat, rule_warnings};
+use chrono::{NaiveTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+use super::reconcile::next_run_delay;
+
+/// Period each report covers
+pub const REPORT_WINDOW: Duration = Duration::from_secs(86400);
+
+/// Time allowed for the report webhook to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct ReportSchedule {
+    /// Time of day (UTC) to build the report at
+    pub at: NaiveTime,
+    pub format: ReportFormat,
+    /// Directory a dated file is written to, for mail cron jobs to pick up
+    pub dir: Option<PathBuf>,
+    /// URL the report is POSTed to as JSON
+    pub webhook: Option<String>,
+}
+
+/// Posted to the report webhook
+#[derive(Debug, Serialize)]
+pub struct ReportNotice<'a> {
+    pub subject: String,
+    /// The rendered report, in `content_type`
+    pub body: String,
+    pub content_type: &'static str,
+    pub report: &'a ConsistencyReport,
+}
+
+/// Write `body` to `name` in `dir`, returning the path
+pub fn write_report(dir: &Path, name: &str, body: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(name);
+    // Written aside and renamed so a cron job never mails half a report
+    let partial = dir.join(format!(".{}.partial", name));
+    std::fs::write(&partial, body)?;
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+async fn post_report(url: &str, notice: &ReportNotice<'_>) -> reqwest::Result<()> {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()?
+        .post(url)
+        .json(&crate::events::envelope(
+            crate::events::EVENT_REPORT,
+            notice,
+        ))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+impl Harborshield {
+    /// Build and deliver a report every day at the scheduled time until
+    /// shutdown
+    pub(crate) fn spawn_report_job(&self, schedule: ReportSchedule) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let delay = next_run_delay(Utc::now(), schedule.at);
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+
+                match handlers.consistency_report().await {
+                    Ok(report) => handlers.deliver_report(&schedule, &report).await,
+                    Err(e) => warn!("Failed to build the daily report: {}", e),
+                }
+            }
+        })
+    }
+
+    /// The report for the last day, with the rule sets of running containers
+    /// validated
+    pub async fn consistency_report(&self) -> crate::Result<ConsistencyReport> {
+        let containers = self.docker_client.container_tracker().list_containers();
+        let db = self.db.lock().await;
+        ConsistencyReport::collect(
+            &db,
+            REPORT_WINDOW,
+            Utc::now().timestamp(),
+            rule_warnings(&containers),
+        )
+        .await
+    }
+
+    async fn deliver_report(&self, schedule: &ReportSchedule, report: &ConsistencyReport) {
+        info!("Daily report: {}", report.summary());
+        let body = report.render(schedule.format);
+
+        if let Some(dir) = &schedule.dir {
+            match write_report(dir, &report.file_name(schedule.format), &body) {
+                Ok(path) => info!("Wrote daily report to {}", path.display()),
+                Err(e) => warn!("Failed to write daily report to {}: {}", dir.display(), e),
+            }
+        }
+
+        if let Some(url) = &schedule.webhook {
+            let notice = ReportNotice {
+                subject: format!("harborshield: {}", report.summary()),
+                body,
+                content_type: match schedule.format {
+                    ReportFormat::Text => "text/plain",
+                    ReportFormat::Html => "text/html",
+                },
+                report,
+            };
+            if let Err(e) = post_report(url, &notice).await {
+                warn!("Failed to post daily report to {}: {}", url, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Damping rule churn of containers stuck in a restart loop.
+//!
+//! Starts are counted per container over [`LOOP_WINDOW`]; from
+//! [`LOOP_STARTS`] of them on, the container is looping. A looping container
+//! that dies keeps its chain, dispatch and tracking instead of having them
+//! torn down, so its next start finds the rules still installed and skips
+//! the re-render unless its addresses, ports or rules changed. Renders that
+//! are still needed are held to one per [`RERENDER_INTERVAL`]: in between,
+//! only its addresses are pointed at the existing chain and the restart-loop
+//! watcher renders it once the interval is up. A held container that stays
+//! down for [`HOLD`] has its rules removed as on any stop, and the loop ends
+//! after a window without starts. Looping containers show in `/status`.
+
+use crate::docker::container::Container;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::Harborshield;
+
+/// Window starts are counted over
+pub const LOOP_WINDOW: Duration = Duration::from_secs(120);
+
+/// Starts within the window that make a loop
+pub const LOOP_STARTS: usize = 3;
+
+/// Least time between renders of a looping container's rules
+pub const RERENDER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the rules of a looping container that died are kept
+pub const HOLD: Duration = LOOP_WINDOW;
+
+static LOOPS: LazyLock<Mutex<RestartLoops>> = LazyLock::new(|| Mutex::new(RestartLoops::default()));
+
+/// What to do with a container that started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnStart {
+    /// Render its rules as usual
+    Render,
+    /// Its rules are still installed as they would be rendered
+    Skip,
+    /// Point its addresses at the chain and render later
+    Defer,
+}
+
+/// What to do with a container that died
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDie {
+    Remove,
+    /// Keep its rules for the restart to come
+    Hold,
+}
+
+/// A looping container, as listed in `/status`
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RestartLoop {
+    pub container: String,
+    /// Starts within the window
+    pub starts: usize,
+    /// Whether it is down with its rules kept
+    pub held: bool,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    name: String,
+    starts: VecDeque<i64>,
+    /// When the rules were last rendered, and from what
+    rendered: Option<(i64, u64)>,
+    /// When it died with its rules kept
+    held_since: Option<i64>,
+    /// A render was put off by the rate limit
+    deferred: bool,
+}
+
+impl History {
+    fn looping(&mut self, now: i64) -> bool {
+        let since = now - LOOP_WINDOW.as_secs() as i64;
+        while self.starts.front().is_some_and(|at| *at <= since) {
+            self.starts.pop_front();
+        }
+        self.starts.len() >= LOOP_STARTS
+    }
+}
+
+/// Start and render history of containers, by ID
+#[derive(Debug, Default)]
+pub struct RestartLoops {
+    containers: HashMap<String, History>,
+}
+
+impl RestartLoops {
+    /// Record a start of a container whose rules would render from
+    /// `fingerprint`
+    pub fn started(&mut self, id: &str, name: &str, fingerprint: u64, now: i64) -> OnStart {
+        let history = self.containers.entry(id.to_string()).or_default();
+        history.name = name.to_string();
+        history.starts.push_back(now);
+        let held = history.held_since.take().is_some();
+        if !history.looping(now) || !held {
+            return OnStart::Render;
+        }
+        match history.rendered {
+            Some((_, rendered)) if rendered == fingerprint && !history.deferred => OnStart::Skip,
+            Some((at, _)) if now - at < RERENDER_INTERVAL.as_secs() as i64 => {
+                history.deferred = true;
+                OnStart::Defer
+            }
+            _ => OnStart::Render,
+        }
+    }
+
+    /// Record that the container's rules were rendered from `fingerprint`
+    pub fn rendered(&mut self, id: &str, fingerprint: u64, now: i64) {
+        let history = self.containers.entry(id.to_string()).or_default();
+        history.rendered = Some((now, fingerprint));
+        history.deferred = false;
+    }
+
+    /// Record that the container died
+    pub fn died(&mut self, id: &str, now: i64) -> OnDie {
+        let Some(history) = self.containers.get_mut(id) else {
+            return OnDie::Remove;
+        };
+        if history.looping(now) && history.rendered.is_some() {
+            history.held_since = Some(now);
+            OnDie::Hold
+        } else {
+            history.rendered = None;
+            history.deferred = false;
+            OnDie::Remove
+        }
+    }
+
+    /// Containers held longer than [`HOLD`], whose rules should now go, and
+    /// running ones with a deferred render that may now happen
+    pub fn due(&mut self, now: i64) -> (Vec<String>, Vec<String>) {
+        let mut remove = Vec::new();
+        let mut render = Vec::new();
+        for (id, history) in self.containers.iter_mut() {
+            match history.held_since {
+                Some(since) if now - since >= HOLD.as_secs() as i64 => {
+                    history.held_since = None;
+                    history.rendered = None;
+                    history.deferred = false;
+                    remove.push(id.clone());
+                }
+                Some(_) => {}
+                None if history.deferred
+                    && history
+                        .rendered
+                        .is_none_or(|(at, _)| now - at >= RERENDER_INTERVAL.as_secs() as i64) =>
+                {
+                    render.push(id.clone());
+                }
+                None => {}
+            }
+        }
+        // Quiet containers with nothing pending are forgotten
+        self.containers.retain(|_, history| {
+            history.looping(now) || history.held_since.is_some() || history.deferred
+        });
+        (remove, render)
+    }
+
+    /// Every looping container, by name
+    pub fn looping(&mut self, now: i64) -> Vec<RestartLoop> {
+        let mut loops: Vec<RestartLoop> = self
+            .containers
+            .values_mut()
+            .filter_map(|history| {
+                history.looping(now).then(|| RestartLoop {
+                    container: history.name.clone(),
+                    starts: history.starts.len(),
+                    held: history.held_since.is_some(),
+                })
+            })
+            .collect();
+        loops.sort_by(|a, b| a.container.cmp(&b.container));
+        loops
+    }
+}
+
+/// What a container's rules render from: its rules, addresses and ports
+pub fn fingerprint(container: &Container) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&container.config)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    container.ip_addresses(false).hash(&mut hasher);
+    container.ip_addresses(true).hash(&mut hasher);
+    for port in &container.ports {
+        (
+            port.container_port,
+            &port.protocol,
+            port.host_port,
+            port.host_ip,
+        )
+            .hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn with_loops<T>(f: impl FnOnce(&mut RestartLoops) -> T) -> Option<T> {
+    LOOPS.lock().ok().map(|mut loops| f(&mut loops))
+}
+
+/// Looping containers for `/status`
+pub fn status() -> Vec<RestartLoop> {
+    with_loops(|loops| loops.looping(chrono::Utc::now().timestamp())).unwrap_or_default()
+}
+
+impl Harborshield {
+    /// Note a start of `container`, deciding whether its rules need a render
+    pub(super) fn restart_started(&self, container: &Container) -> OnStart {
+        let now = chrono::Utc::now().timestamp();
+        let action = with_loops(|loops| {
+            loops.started(&container.id, &container.name, fingerprint(container), now)
+        })
+        .unwrap_or(OnStart::Render);
+        match action {
+            OnStart::Render => {}
+            OnStart::Skip => info!(
+                "Container {} is in a restart loop, its rules are still in place",
+                container.name
+            ),
+            OnStart::Defer => info!(
+                "Container {} is in a restart loop, rendering its changed rules within {:?}",
+                container.name, RERENDER_INTERVAL
+            ),
+        }
+        action
+    }
+
+    pub(super) fn restart_rendered(&self, container: &Container) {
+        let now = chrono::Utc::now().timestamp();
+        with_loops(|loops| loops.rendered(&container.id, fingerprint(container), now));
+    }
+
+    /// Note that a tracked container died, deciding whether its rules stay
+    pub(super) fn restart_died(&self, container: &Container) -> OnDie {
+        let now = chrono::Utc::now().timestamp();
+        let action = with_loops(|loops| loops.died(&container.id, now)).unwrap_or(OnDie::Remove);
+        if action == OnDie::Hold {
+            info!(
+                "Container {} is in a restart loop, keeping its rules for {:?}",
+                container.name, HOLD
+            );
+        }
+        action
+    }
+
+    /// Point a restarted container's addresses at its existing chain
+    pub(super) async fn redirect_addresses(&self, container: &Container) -> crate::Result<()> {
+        let ips: Vec<String> = container
+            .ip_addresses(false)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        if !ips.is_empty() {
+            self.nftables_client
+                .lock()
+                .await
+                .update_container_verdict_maps(&[(
+                    container.id.clone(),
+                    container.name.clone(),
+                    ips,
+                )])
+                .await?;
+        }
+        self.sync_ipv6().await;
+        Ok(())
+    }
+
+    /// Remove the rules of held containers that stayed down, and render
+    /// deferred ones, every `interval` until shutdown
+    pub(crate) fn spawn_restart_loop_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => handlers.damp_restart_loops().await,
+                }
+            }
+        })
+    }
+
+    async fn damp_restart_loops(&self) {
+        let now = chrono::Utc::now().timestamp();
+        let Some((remove, render)) = with_loops(|loops| loops.due(now)) else {
+            return;
+        };
+
+        for id in remove {
+            debug!("Container {} stayed down, removing its held rules", id);
+            if let Err(e) = self.remove_stopped_container(&id).await {
+                warn!("Failed to remove rules of stopped container {}: {}", id, e);
+            }
+        }
+        for id in render {
+            let Some(container) = self.docker_client.container_tracker().get_container(&id) else {
+                continue;
+            };
+            match self.create_container_rules(&container, None).await {
+                Ok(()) => self.restart_rendered(&container),
+                Err(e) => warn!(
+                    "Failed to render deferred rules of container {}: {}",
+                    container.name, e
+                ),
+            }
+        }
+
+        let looping = with_loops(|loops| loops.looping(now).len()).unwrap_or_default();
+        crate::server::set_restart_loops(looping as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_loop_damping() {
+        let mut loops = RestartLoops::default();
+        let step = 10;
+        let mut now = 1_000;
+
+        // The first starts and deaths churn as usual
+        for _ in 0..2 {
+            assert_eq!(loops.started("abc", "web", 1, now), OnStart::Render);
+            loops.rendered("abc", 1, now);
+            assert_eq!(loops.died("abc", now + 1), OnDie::Remove);
+            now += step;
+        }
+        assert_eq!(loops.started("abc", "web", 1, now), OnStart::Render);
+        loops.rendered("abc", 1, now);
+        assert_eq!(loops.looping(now)[0].starts, 3);
+
+        // From the third start on, deaths keep the rules
+        assert_eq!(loops.died("abc", now + 1), OnDie::Hold);
+        assert!(loops.looping(now + 1)[0].held);
+        now += step;
+        assert_eq!(loops.started("abc", "web", 1, now), OnStart::Skip);
+
+        // A change within the rate limit is deferred, then rendered
+        assert_eq!(loops.died("abc", now + 1), OnDie::Hold);
+        now += step;
+        assert_eq!(loops.started("abc", "web", 2, now), OnStart::Defer);
+        assert_eq!(loops.due(now), (vec![], vec![]));
+        let later = now + RERENDER_INTERVAL.as_secs() as i64;
+        assert_eq!(loops.due(later), (vec![], vec!["abc".to_string()]));
+        loops.rendered("abc", 2, later);
+
+        // Staying down past the hold removes the rules, and the loop ends
+        assert_eq!(loops.died("abc", later), OnDie::Hold);
+        let gone = later + HOLD.as_secs() as i64;
+        assert_eq!(loops.due(gone), (vec!["abc".to_string()], vec![]));
+        assert!(loops.looping(gone).is_empty());
+        assert!(loops.containers.is_empty());
+        assert_eq!(loops.died("abc", gone), OnDie::Remove);
+    }
+}
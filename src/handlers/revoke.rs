@@ -0,0 +1,298 @@
+//! Cutting established connections the rules no longer allow.
+//!
+//! A connection opened under a rule that is since removed or narrowed keeps
+//! flowing as established until it idles out. With `--flush-revoked-flows`,
+//! every rebuild of a container's chain checks its connections against the
+//! rules just applied and deletes the conntrack entries of those no rule
+//! allows any more, so their next packet is dropped.
+//!
+//! Connections the container opened are checked against its output rules,
+//! and those made to its published or forwarded ports against
+//! `mapped_ports`, through the reply tuple that names the container after
+//! DNAT. Connections from other containers and Docker's gateways are left
+//! to the sender's chain. Whatever the rules can't tell is kept: nothing is
+//! cut while `raw_rules` may accept it, and external access restricted to
+//! interfaces or verified hostnames keeps every inbound connection. Limits
+//! and blocklists only ever drop, so they never keep a connection.
+
+use crate::database::EnforcementMode;
+use crate::docker::config::{Config, RuleConfig};
+use crate::docker::container::Container;
+use crate::host::conntrack::{self, Direction, Entry, Flow};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, info, warn};
+
+use super::Harborshield;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether `rule` decides the outbound connection of `entry`. Rules whose
+/// destinations are only known to nft, by hostname or a container that
+/// isn't running yet, are taken to match any destination
+fn decides(rule: &RuleConfig, entry: &Entry) -> bool {
+    let tuple = &entry.original;
+    if rule.skip || rule.from.is_some() || rule.proto.to_string() != entry.proto {
+        return false;
+    }
+    let ports = |ports: &[crate::docker::config::RulePorts], port| {
+        ports.is_empty() || ports.iter().any(|p| p.contains(port))
+    };
+    if !ports(&rule.src_ports, tuple.sport) || !ports(&rule.dst_ports, tuple.dport) {
+        return false;
+    }
+    if rule.hostname.is_some() || !rule.container.is_empty() {
+        return !rule.verdict.drop;
+    }
+    rule.ips.is_empty() || rule.ips.iter().any(|ip| ip.contains(&tuple.dst))
+}
+
+/// Whether `config` admits the inbound `flow` to one of `ports`
+fn admits(config: &Config, ports: &[u16], flow: &Flow) -> bool {
+    if !ports.contains(&flow.port) {
+        return false;
+    }
+    // Localhost rules match loopback sources, external ones everything else
+    if flow.peer.is_loopback() {
+        return config.mapped_ports.localhost.allow;
+    }
+    let external = &config.mapped_ports.external;
+    external.allow
+        && (external.ips.is_empty()
+            || !external.rdns.is_empty()
+            || !external.iif_in.is_empty()
+            || external.ips.iter().any(|ip| ip.contains(&flow.peer)))
+}
+
+/// Who a container's connections are checked against
+pub struct Scope<'a> {
+    /// The container's addresses
+    pub ips: &'a [IpAddr],
+    /// Its published and forwarded container ports
+    pub ports: &'a [u16],
+    /// Addresses whose connections to it their own chain decides: other
+    /// containers and Docker's gateways
+    pub exempt: &'a [IpAddr],
+}
+
+/// The connections in `entries` of the container in `scope` that its
+/// resolved `config` no longer allows in `mode`
+pub fn revoked<'a>(
+    entries: &'a [Entry],
+    scope: &Scope<'_>,
+    config: &Config,
+    mode: EnforcementMode,
+) -> Vec<&'a Entry> {
+    let undecidable = !config.raw_rules.is_empty() && mode != EnforcementMode::Quarantined;
+    if undecidable
+        || matches!(
+            mode,
+            EnforcementMode::Permissive | EnforcementMode::Disabled
+        )
+    {
+        return Vec::new();
+    }
+    entries
+        .iter()
+        .filter(|entry| {
+            let Some(flow) = entry.flow_for(scope.ips) else {
+                return false;
+            };
+            match flow.direction {
+                Direction::Outbound => {
+                    mode == EnforcementMode::Quarantined
+                        || config
+                            .output
+                            .iter()
+                            .find(|rule| decides(rule, entry))
+                            .is_none_or(|rule| rule.verdict.drop)
+                }
+                Direction::Inbound => {
+                    !scope.exempt.contains(&flow.peer)
+                        && (mode == EnforcementMode::Quarantined
+                            || !admits(config, scope.ports, &flow))
+                }
+            }
+        })
+        .collect()
+}
+
+impl Harborshield {
+    /// Delete the conntrack entries of connections `container` opened that
+    /// its rebuilt rules no longer allow
+    pub(super) async fn flush_revoked_flows(
+        &self,
+        container: &Container,
+        ips: &[IpAddr],
+        config: &Config,
+        mode: EnforcementMode,
+    ) {
+        if !enabled() {
+            return;
+        }
+        let entries = match conntrack::sample().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(
+                    "Failed to read conntrack for revoked flows of {}: {}",
+                    container.name, e
+                );
+                return;
+            }
+        };
+
+        let ports: Vec<u16> = container
+            .ports
+            .iter()
+            .map(|port| port.container_port)
+            .chain(
+                config
+                    .forward
+                    .iter()
+                    .map(|forward| forward.container_port()),
+            )
+            .collect();
+        let mut exempt: Vec<IpAddr> = self
+            .docker_client
+            .container_tracker()
+            .list_containers()
+            .iter()
+            .filter(|other| other.id != container.id)
+            .flat_map(|other| other.networks.values())
+            .flat_map(|network| network.ip_addresses.iter().copied())
+            .collect();
+        exempt.extend(
+            crate::docker::network::known_networks()
+                .into_values()
+                .flat_map(|network| network.gateway_ips),
+        );
+        let scope = Scope {
+            ips,
+            ports: &ports,
+            exempt: &exempt,
+        };
+
+        let mut cut = 0;
+        for entry in revoked(&entries, &scope, config, mode) {
+            let tuple = &entry.original;
+            match conntrack::delete(entry).await {
+                Ok(()) => {
+                    debug!(
+                        "Cut {} connection of {} from {}:{} to {}:{}",
+                        entry.proto, container.name, tuple.src, tuple.sport, tuple.dst, tuple.dport
+                    );
+                    cut += 1;
+                }
+                Err(e) => warn!(
+                    "Failed to cut {} connection of {} from {}:{} to {}:{}: {}",
+                    entry.proto, container.name, tuple.src, tuple.sport, tuple.dst, tuple.dport, e
+                ),
+            }
+        }
+        if cut > 0 {
+            info!(
+                "Cut {} established connection(s) of {} no longer allowed by its rules",
+                cut, container.name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoked() {
+        let entries = conntrack::parse(
+            "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=172.17.0.2 dst=93.184.216.34 sport=51234 dport=443 src=93.184.216.34 dst=192.168.1.5 sport=443 dport=51234 [ASSURED] mark=0 use=2
+ipv4     2 tcp      6 431999 ESTABLISHED src=172.17.0.2 dst=10.0.0.8 sport=40100 dport=5432 src=10.0.0.8 dst=172.17.0.2 sport=5432 dport=40100 [ASSURED] mark=0 use=2
+ipv4     2 udp      17 28 src=172.17.0.2 dst=1.1.1.1 sport=40000 dport=53 src=1.1.1.1 dst=172.17.0.2 sport=53 dport=40000 mark=0 use=1
+ipv4     2 tcp      6 431999 ESTABLISHED src=172.17.0.3 dst=10.0.0.8 sport=40200 dport=5432 src=10.0.0.8 dst=172.17.0.3 sport=5432 dport=40200 [ASSURED] mark=0 use=2
+",
+        );
+        let ips: Vec<IpAddr> = vec!["172.17.0.2".parse().unwrap()];
+        let scope = Scope {
+            ips: &ips,
+            ports: &[],
+            exempt: &[],
+        };
+        let config: Config = serde_yaml::from_str(
+            "output:\n  - proto: tcp\n    dst_ports: [443]\n  - proto: udp\n    ips: [1.1.1.1]\n    dst_ports: [53]\n",
+        )
+        .unwrap();
+
+        // The database rule was dropped; the other container isn't ours
+        let cut = revoked(&entries, &scope, &config, EnforcementMode::Enforce);
+        assert_eq!(cut, vec![&entries[1]]);
+
+        assert!(revoked(&entries, &scope, &config, EnforcementMode::Permissive).is_empty());
+        assert_eq!(
+            revoked(&entries, &scope, &config, EnforcementMode::Quarantined).len(),
+            3
+        );
+
+        // A raw rule may be what still accepts it
+        let raw: Config = serde_yaml::from_str(
+            "raw_rules: [\"ip daddr 10.0.0.8 accept\"]\noutput:\n  - proto: tcp\n    dst_ports: [443]\n",
+        )
+        .unwrap();
+        assert!(revoked(&entries, &scope, &raw, EnforcementMode::Enforce).is_empty());
+    }
+
+    #[test]
+    fn test_revoked_inbound() {
+        // Published 8080 DNATed to port 80, from the internet, the office,
+        // a peer container and loopback
+        let entries = conntrack::parse(
+            "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=198.51.100.7 dst=192.168.1.5 sport=50000 dport=8080 src=172.17.0.2 dst=198.51.100.7 sport=80 dport=50000 [ASSURED] mark=0 use=2
+ipv4     2 tcp      6 431999 ESTABLISHED src=192.0.2.10 dst=192.168.1.5 sport=50001 dport=8080 src=172.17.0.2 dst=192.0.2.10 sport=80 dport=50001 [ASSURED] mark=0 use=2
+ipv4     2 tcp      6 431999 ESTABLISHED src=172.17.0.3 dst=172.17.0.2 sport=50002 dport=80 src=172.17.0.2 dst=172.17.0.3 sport=80 dport=50002 [ASSURED] mark=0 use=2
+ipv4     2 tcp      6 431999 ESTABLISHED src=127.0.0.1 dst=127.0.0.1 sport=50003 dport=8080 src=172.17.0.2 dst=127.0.0.1 sport=80 dport=50003 [ASSURED] mark=0 use=2
+",
+        );
+        let ips: Vec<IpAddr> = vec!["172.17.0.2".parse().unwrap()];
+        let exempt: Vec<IpAddr> = vec!["172.17.0.3".parse().unwrap()];
+        let scope = Scope {
+            ips: &ips,
+            ports: &[80],
+            exempt: &exempt,
+        };
+        let config = |yaml: &str| -> Config { serde_yaml::from_str(yaml).unwrap() };
+
+        // External access narrowed to the office, localhost access dropped
+        let office =
+            config("mapped_ports:\n  external:\n    allow: true\n    ips: [192.0.2.0/24]\n");
+        let cut = revoked(&entries, &scope, &office, EnforcementMode::Enforce);
+        assert_eq!(cut, vec![&entries[0], &entries[3]]);
+
+        let open =
+            config("mapped_ports:\n  localhost:\n    allow: true\n  external:\n    allow: true\n");
+        assert!(revoked(&entries, &scope, &open, EnforcementMode::Enforce).is_empty());
+
+        // Verified hostnames change without a rebuild
+        let verified =
+            config("mapped_ports:\n  external:\n    allow: true\n    rdns: [\"*.example.com\"]\n");
+        assert_eq!(
+            revoked(&entries, &scope, &verified, EnforcementMode::Enforce),
+            vec![&entries[3]]
+        );
+
+        // The peer's connection is its own chain's to decide
+        let closed = config("{}");
+        assert_eq!(
+            revoked(&entries, &scope, &closed, EnforcementMode::Quarantined).len(),
+            3
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Keeping the rootless published-port chain and its `/status` report in
+//! line with tracked containers; see [`crate::docker::rootless`].
+
+use crate::database::EnforcementMode;
+use crate::docker::config::Config;
+use crate::docker::container::Container;
+use crate::docker::rootless::{self, ContainerSupport};
+use crate::nftables::rootless::PublishedTarget;
+use tracing::{debug, warn};
+
+use super::Harborshield;
+use super::utils::resolve_config;
+
+/// A container's published host ports under `config`
+pub fn published_target<'a>(container: &'a Container, config: &'a Config) -> PublishedTarget<'a> {
+    let mut ports: Vec<(u16, &str)> = container
+        .ports
+        .iter()
+        .filter_map(|port| Some((port.host_port?, port.protocol.as_str())))
+        .collect();
+    // IPv4 and IPv6 bindings of a port share one rule
+    ports.sort_unstable();
+    ports.dedup();
+    PublishedTarget {
+        container_name: &container.name,
+        ports,
+        config,
+    }
+}
+
+impl Harborshield {
+    /// Rebuild the rootless chain from every tracked container and record
+    /// what each one gets enforced
+    pub(super) async fn sync_rootless(&self) {
+        if !rootless::is_rootless() {
+            return;
+        }
+
+        let host_addrs = self.host_addrs.get();
+        let now = chrono::Utc::now().timestamp();
+        let mut resolved: Vec<(Container, Config)> = Vec::new();
+        let mut supports: Vec<ContainerSupport> = Vec::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            let Some(config) = &container.config else {
+                continue;
+            };
+            if !container.enabled || container.uses_host_network {
+                continue;
+            }
+            let mut support = rootless::support(&container, config);
+            let config = match self.enforcement_mode(&container.identity()).await {
+                EnforcementMode::Enforce => resolve_config(
+                    &container,
+                    config,
+                    self.docker_client.container_tracker(),
+                    &host_addrs,
+                    now,
+                ),
+                EnforcementMode::Quarantined => config.quarantined(),
+                EnforcementMode::Permissive | EnforcementMode::Disabled => {
+                    support.enforced.clear();
+                    supports.push(support);
+                    continue;
+                }
+            };
+            supports.push(support);
+            resolved.push((container, config));
+        }
+        rootless::set_status(supports);
+
+        let targets: Vec<PublishedTarget> = resolved
+            .iter()
+            .map(|(container, config)| published_target(container, config))
+            .filter(|target| !target.ports.is_empty())
+            .collect();
+        let nftables = self.nftables_client.lock().await;
+        match nftables.rebuild_rootless(&targets).await {
+            Ok(()) => debug!("Guarding published ports of {} containers", targets.len()),
+            Err(e) => warn!("Failed to rebuild the rootless chain: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::PortMapping;
+
+    #[test]
+    fn test_published_target() {
+        let mapping = |host_ip: &str, host_port: Option<u16>| {
+            PortMapping::builder()
+                .container_port(80)
+                .maybe_host_port(host_port)
+                .host_ip(host_ip.parse().unwrap())
+                .protocol("tcp".to_string())
+                .build()
+        };
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("web".to_string())
+            .ports(vec![
+                mapping("0.0.0.0", Some(8080)),
+                mapping("::", Some(8080)),
+                mapping("0.0.0.0", None),
+            ])
+            .build();
+        let config = Config::builder().build();
+
+        let target = published_target(&container, &config);
+        assert_eq!(target.container_name, "web");
+        assert_eq!(target.ports, vec![(8080, "tcp")]);
+    }
+}
@@ -0,0 +1,135 @@
+//! Rebuilding the chains of containers whose `--rules-dir` file changed; see
+//! [`crate::docker::config::files`].
+
+use crate::docker::compose::ComposeInfo;
+use crate::docker::config::files;
+use crate::docker::container::Container;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Harborshield;
+
+/// Wait after a change for the rest of an editor's save to land
+const RULES_DIR_SETTLE: Duration = Duration::from_millis(500);
+
+/// Whether one of `stems` is `container`'s name or service, so its rules may
+/// have changed
+fn uses_file(container: &Container, stems: &[String]) -> bool {
+    let name = container.name.trim_start_matches('/');
+    let service = ComposeInfo::from_labels(&container.labels).service;
+    stems
+        .iter()
+        .any(|stem| stem == name || service.as_deref() == Some(stem.as_str()))
+}
+
+impl Harborshield {
+    /// Re-read `dir` whenever a file in it changes and rebuild the chains of
+    /// containers whose file was added, edited or removed
+    #[cfg(target_os = "linux")]
+    pub(crate) fn spawn_rules_dir_watcher(&self, dir: PathBuf) -> std::io::Result<JoinHandle<()>> {
+        let events = files::DirEvents::open(&dir)?;
+        let handlers = self.clone();
+
+        Ok(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    changed = events.changed() => {
+                        if let Err(e) = changed {
+                            warn!("Stopped watching rules directory {}: {}", dir.display(), e);
+                            break;
+                        }
+                        tokio::time::sleep(RULES_DIR_SETTLE).await;
+                        handlers.reload_rules_dir(&dir).await;
+                    }
+                }
+            }
+        }))
+    }
+
+    async fn reload_rules_dir(&self, dir: &std::path::Path) {
+        let current = match files::load_dir(dir) {
+            Ok(current) => current,
+            Err(e) => {
+                warn!(
+                    "Failed to read rules directory {}, keeping its previous files: {}",
+                    dir.display(),
+                    e
+                );
+                return;
+            }
+        };
+        let previous = files::set_files(current.clone());
+        let stems = files::changed(&previous, &current);
+        if stems.is_empty() {
+            return;
+        }
+        info!("Rule set files changed: {}", stems.join(", "));
+        // Every container is resynced once the freeze ends
+        if crate::database::freeze::is_frozen() {
+            info!("Holding the changed rule set files for the end of the change freeze");
+            return;
+        }
+
+        // Stopped containers pick the files up when they start
+        let tracker = self.docker_client.container_tracker();
+        for container in tracker.list_containers() {
+            if !uses_file(&container, &stems) {
+                continue;
+            }
+            // Re-inspect to parse the rules with the new file
+            let container = match self
+                .docker_client
+                .try_get_container_by_id(&container.id)
+                .await
+            {
+                Ok(container) => container,
+                Err(e) => {
+                    warn!(
+                        "Failed to re-read container {} for its rule set file: {}",
+                        container.name, e
+                    );
+                    continue;
+                }
+            };
+            if let Err(e) = tracker.update_container(container.clone()) {
+                warn!("Failed to update container {}: {}", container.name, e);
+                continue;
+            }
+            let mode = self.enforcement_mode(&container.identity()).await;
+            match self.rebuild_container_rules(&container, mode).await {
+                Ok(()) => info!(
+                    "Updated rules of container {} from its file",
+                    container.name
+                ),
+                Err(e) => warn!(
+                    "Failed to update rules of container {} from its file: {}",
+                    container.name, e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_uses_file() {
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("/shop-web-1".to_string())
+            .labels(HashMap::from([(
+                "com.docker.compose.service".to_string(),
+                "web".to_string(),
+            )]))
+            .build();
+        assert!(uses_file(&container, &["web".to_string()]));
+        assert!(uses_file(&container, &["shop-web-1".to_string()]));
+        assert!(!uses_file(&container, &["db".to_string()]));
+    }
+}
@@ -0,0 +1,400 @@
+//! `--validate-rules-every`: spot checks that allow rules still see the
+//! traffic they were written for.
+//!
+//! Each pass takes a baseline of up to [`SAMPLE_SIZE`] output allow rules
+//! picked at random: their counters and the conntrack flows they match. The
+//! next pass compares. A flow that showed up in between had its first packet
+//! go through the container's chain, where this rule or one before it should
+//! have counted it. When new flows appeared and none of those counters
+//! moved, the traffic went around the chain, typically because the container
+//! moved to another network or interface that its jump no longer matches.
+//! Such rules are recorded as `rule_suspect` audit entries, which `doctor`
+//! and `status` show. Only IPv4 flows are checked, as only the `ip` table's
+//! counters are read.
+
+use crate::database::{AuditEntry, audit};
+use crate::docker::config::{Protocol, RuleConfig};
+use crate::docker::container::Container;
+use crate::host::conntrack::{self, Direction, Flow};
+use crate::nftables::counters::{RuleCounter, list_rule_counters};
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::Harborshield;
+use super::admin::chain_name;
+use super::hits::rule_numbers;
+
+/// Rules checked per pass
+pub const SAMPLE_SIZE: usize = 8;
+
+/// How far back `doctor` and `status` look for suspect rules
+pub const REPORT_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// A container's allow rules that can be checked against conntrack
+#[derive(Debug, Clone)]
+pub struct SampleTarget {
+    pub container_name: String,
+    pub chain: String,
+    pub ips: Vec<IpAddr>,
+    /// Output rules, numbered from 1
+    pub rules: Vec<(usize, RuleConfig)>,
+}
+
+/// Whether conntrack can tell the flows `rule` lets through: a plain
+/// accept with peers and ports it can compare
+fn checkable(rule: &RuleConfig) -> bool {
+    let verdict = &rule.verdict;
+    let accepts = verdict.chain.is_empty() && verdict.queue == 0 && !verdict.drop;
+    accepts
+        && !rule.skip
+        && !rule.proto.is_icmp()
+        && rule.src_ports.is_empty()
+        && rule.hostname.is_none()
+        && rule.tproxy.is_none()
+        && rule.container.is_empty()
+        && rule.network.is_empty()
+        && rule.ips.iter().any(|ip| !ip.is_ipv6())
+}
+
+fn matches(rule: &RuleConfig, flow: &Flow) -> bool {
+    let proto = match rule.proto {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+        Protocol::Icmp | Protocol::Icmpv6 => return false,
+    };
+    flow.direction == Direction::Outbound
+        && flow.proto == proto
+        && rule.ips.iter().any(|ip| ip.contains(&flow.peer))
+        && (rule.dst_ports.is_empty() || rule.dst_ports.iter().any(|p| p.contains(flow.port)))
+}
+
+/// The checkable output rules of a container, or none without IPv4
+/// addresses
+pub fn sample_target(container: &Container) -> Option<SampleTarget> {
+    let config = container.config.as_ref()?;
+    let ips = container.ip_addresses(false);
+    let rules: Vec<(usize, RuleConfig)> = config
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, rule)| checkable(rule))
+        .map(|(i, rule)| (i + 1, rule.clone()))
+        .collect();
+    if ips.is_empty() || rules.is_empty() {
+        return None;
+    }
+    Some(SampleTarget {
+        container_name: container.identity(),
+        chain: chain_name(container),
+        ips,
+        rules,
+    })
+}
+
+/// What a sampled rule looked like at its baseline
+#[derive(Debug, Clone)]
+struct Baseline {
+    container_name: String,
+    chain: String,
+    number: usize,
+    ips: Vec<IpAddr>,
+    rule: RuleConfig,
+    packets: u64,
+    flows: HashSet<FlowKey>,
+}
+
+/// A rule that let new flows through without counting them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suspect {
+    pub container_name: String,
+    /// Output rule number
+    pub rule: usize,
+    pub new_flows: usize,
+}
+
+impl Suspect {
+    pub fn detail(&self) -> String {
+        format!(
+            "output rule {} counted none of {} new matching flows; the chain may not see the container's traffic, for example after a network change",
+            self.rule, self.new_flows
+        )
+    }
+}
+
+/// Packets counted by output rule `number`, and every numbered output rule
+/// ahead of it, in `chain`. None when the rule isn't installed
+fn counted(counters: &[RuleCounter], chain: &str, number: usize) -> Option<u64> {
+    let chain_rules: Vec<(&RuleCounter, Vec<usize>)> = counters
+        .iter()
+        .filter(|counter| counter.chain == chain)
+        .map(|counter| {
+            let numbers = counter.comment.as_deref().map(rule_numbers);
+            (counter, numbers.unwrap_or_default())
+        })
+        .collect();
+    let position = chain_rules
+        .iter()
+        .find(|(_, numbers)| numbers.contains(&number))?
+        .0
+        .position;
+    Some(
+        chain_rules
+            .iter()
+            .filter(|(counter, numbers)| !numbers.is_empty() && counter.position <= position)
+            .map(|(counter, _)| counter.packets)
+            .sum(),
+    )
+}
+
+/// A connection by its original tuple, which tells apart flows to one peer
+type FlowKey = (IpAddr, u16, IpAddr, u16);
+
+fn flows(rule: &RuleConfig, ips: &[IpAddr], entries: &[conntrack::Entry]) -> HashSet<FlowKey> {
+    entries
+        .iter()
+        .filter(|entry| entry.flow_for(ips).is_some_and(|flow| matches(rule, &flow)))
+        .map(|entry| {
+            let tuple = entry.original;
+            (tuple.src, tuple.sport, tuple.dst, tuple.dport)
+        })
+        .collect()
+}
+
+/// Baselines of the rules picked last pass
+#[derive(Debug, Default)]
+pub struct RuleSampler {
+    pending: Vec<Baseline>,
+}
+
+impl RuleSampler {
+    /// Check the rules picked last pass, then pick up to `size` of
+    /// `targets` for the next. `rank` orders the candidates; the lowest
+    /// are picked
+    pub fn pass(
+        &mut self,
+        targets: &[SampleTarget],
+        counters: &[RuleCounter],
+        entries: &[conntrack::Entry],
+        size: usize,
+        mut rank: impl FnMut(&str, usize) -> u64,
+    ) -> Vec<Suspect> {
+        let mut suspects = Vec::new();
+        for baseline in std::mem::take(&mut self.pending) {
+            // Counters that grew saw the traffic, and ones that went
+            // backwards belong to a rebuilt chain
+            let Some(packets) = counted(counters, &baseline.chain, baseline.number) else {
+                continue;
+            };
+            if packets != baseline.packets {
+                continue;
+            }
+            // Addresses that changed make for other flows, not new ones
+            let still_there = targets
+                .iter()
+                .any(|target| target.chain == baseline.chain && target.ips == baseline.ips);
+            if !still_there {
+                continue;
+            }
+            let new_flows = flows(&baseline.rule, &baseline.ips, entries)
+                .difference(&baseline.flows)
+                .count();
+            if new_flows > 0 {
+                suspects.push(Suspect {
+                    container_name: baseline.container_name,
+                    rule: baseline.number,
+                    new_flows,
+                });
+            }
+        }
+
+        let mut candidates: Vec<(u64, &SampleTarget, &(usize, RuleConfig))> = targets
+            .iter()
+            .flat_map(|target| target.rules.iter().map(move |rule| (target, rule)))
+            .map(|(target, rule)| (rank(&target.chain, rule.0), target, rule))
+            .collect();
+        candidates.sort_by_key(|(rank, _, _)| *rank);
+        for (_, target, (number, rule)) in candidates.into_iter().take(size) {
+            let Some(packets) = counted(counters, &target.chain, *number) else {
+                continue;
+            };
+            self.pending.push(Baseline {
+                container_name: target.container_name.clone(),
+                chain: target.chain.clone(),
+                number: *number,
+                ips: target.ips.clone(),
+                rule: rule.clone(),
+                packets,
+                flows: flows(rule, &target.ips, entries),
+            });
+        }
+        suspects
+    }
+}
+
+impl Harborshield {
+    /// Check a random sample of allow rules every `interval` until shutdown
+    pub(crate) fn spawn_rule_sampler(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut sampler = RuleSampler::default();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => handlers.sample_rules(&mut sampler).await,
+                }
+            }
+        })
+    }
+
+    async fn sample_rules(&self, sampler: &mut RuleSampler) {
+        if crate::nftables::standby::is_standby() {
+            return;
+        }
+        let targets: Vec<SampleTarget> = self
+            .docker_client
+            .container_tracker()
+            .list_containers()
+            .iter()
+            .filter(|container| {
+                container.enabled && !container.uses_host_network && !container.paused
+            })
+            .filter_map(sample_target)
+            .collect();
+        let counters = match list_rule_counters().await {
+            Ok(counters) => counters,
+            Err(e) => {
+                debug!("Skipping rule sample: {}", e);
+                return;
+            }
+        };
+        let entries = match conntrack::sample().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Skipping rule sample, conntrack unavailable: {}", e);
+                return;
+            }
+        };
+
+        // A fresh seed per pass gives another random pick
+        let seed = RandomState::new();
+        let suspects = sampler.pass(&targets, &counters, &entries, SAMPLE_SIZE, |chain, n| {
+            seed.hash_one((chain, n))
+        });
+        if suspects.is_empty() {
+            return;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let entries: Vec<AuditEntry> = suspects
+            .into_iter()
+            .map(|suspect| {
+                warn!("Container {}: {}", suspect.container_name, suspect.detail());
+                AuditEntry::builder()
+                    .ts(now)
+                    .kind(audit::KIND_RULE_SUSPECT)
+                    .detail(suspect.detail())
+                    .container_name(suspect.container_name)
+                    .build()
+            })
+            .collect();
+        let mut db = self.db.lock().await;
+        if let Err(e) = audit::record(&mut db, &entries).await {
+            debug!("Failed to record suspect rules: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nftables::counters::parse_rule_counters;
+    use serde_json::json;
+
+    fn counters(packets: u64) -> Vec<RuleCounter> {
+        parse_rule_counters(&json!({
+            "nftables": [
+                {"rule": {"chain": "hs-web-abc", "comment": "Output rule 1 for web", "expr": [
+                    {"counter": {"packets": packets, "bytes": 0}}, {"accept": null}
+                ]}},
+                {"rule": {"chain": "hs-web-abc", "comment": "Default DROP for container web", "expr": [
+                    {"counter": {"packets": 0, "bytes": 0}}, {"drop": null}
+                ]}}
+            ]
+        }))
+    }
+
+    #[test]
+    fn test_rule_sampler() {
+        let rule: RuleConfig =
+            serde_yaml::from_str("ips: [10.0.5.0/24]\nproto: tcp\ndst_ports: [443]").unwrap();
+        assert!(checkable(&rule));
+        let target = SampleTarget {
+            container_name: "web".to_string(),
+            chain: "hs-web-abc".to_string(),
+            ips: vec!["172.17.0.2".parse().unwrap()],
+            rules: vec![(1, rule)],
+        };
+        let entry = |sport: u16, dport: u16| {
+            conntrack::parse_line(&format!(
+                "ipv4 2 tcp 6 431999 ESTABLISHED src=172.17.0.2 dst=10.0.5.20 sport={sport} dport={dport} \
+                 src=10.0.5.20 dst=172.17.0.2 sport={dport} dport={sport} [ASSURED] mark=0 use=1"
+            ))
+            .unwrap()
+        };
+        let targets = [target];
+        let mut sampler = RuleSampler::default();
+
+        // The first pass only takes the baseline
+        let old = [entry(40000, 443)];
+        assert!(
+            sampler
+                .pass(&targets, &counters(5), &old, 8, |_, _| 0)
+                .is_empty()
+        );
+
+        // A new flow the rule counted is fine
+        let newer = [entry(40000, 443), entry(40001, 443)];
+        assert!(
+            sampler
+                .pass(&targets, &counters(6), &newer, 8, |_, _| 0)
+                .is_empty()
+        );
+
+        // So is a flow to a port the rule doesn't cover
+        let other = [entry(40000, 443), entry(40001, 443), entry(40002, 80)];
+        assert!(
+            sampler
+                .pass(&targets, &counters(6), &other, 8, |_, _| 0)
+                .is_empty()
+        );
+
+        // A new flow the counter missed went around the chain
+        let bypassed = [entry(40000, 443), entry(40001, 443), entry(40003, 443)];
+        assert_eq!(
+            sampler.pass(&targets, &counters(6), &bypassed, 8, |_, _| 0),
+            vec![Suspect {
+                container_name: "web".to_string(),
+                rule: 1,
+                new_flows: 1,
+            }]
+        );
+
+        // Nothing is picked with a sample size of zero
+        sampler.pass(&targets, &counters(6), &bypassed, 0, |_, _| 0);
+        let more = [entry(40004, 443)];
+        assert!(
+            sampler
+                .pass(&targets, &counters(6), &more, 8, |_, _| 0)
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Time-window rules. Windows are evaluated against the current instant in
+//! each rule's zone, and a watcher rebuilds a container's chain whenever one
+//! of its windows opens or closes.
+
+use crate::docker::{config::Config, container::Container};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::Harborshield;
+
+/// Which of a config's windows are open at `now`, external rule first
+pub fn window_states(config: &Config, now: i64) -> Vec<bool> {
+    std::iter::once(&config.mapped_ports.external.time)
+        .chain(config.output.iter().map(|rule| &rule.time))
+        .flatten()
+        .map(|window| window.is_active(now))
+        .collect()
+}
+
+/// Disable the rules whose window is closed at `now`
+pub fn apply_time_windows(config: &mut Config, now: i64) {
+    let external = &mut config.mapped_ports.external;
+    if let Some(window) = &external.time
+        && !window.is_active(now)
+    {
+        debug!("External rule outside its time window, disabled");
+        external.allow = false;
+    }
+
+    for rule in config.output.iter_mut() {
+        if let Some(window) = &rule.time
+            && !window.is_active(now)
+        {
+            debug!("Output rule outside its time window, skipped");
+            rule.skip = true;
+        }
+    }
+}
+
+fn container_window_states(container: &Container, now: i64) -> Option<Vec<bool>> {
+    let states = window_states(container.config.as_ref()?, now);
+    (!states.is_empty()).then_some(states)
+}
+
+impl Harborshield {
+    /// Rebuild the chains of containers whose time windows opened or closed,
+    /// checking every `interval` until shutdown
+    pub(crate) fn spawn_time_window_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut known: HashMap<String, Vec<bool>> = HashMap::new();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => handlers.apply_window_changes(&mut known).await,
+                }
+            }
+        })
+    }
+
+    async fn apply_window_changes(&self, known: &mut HashMap<String, Vec<bool>>) {
+        let now = chrono::Utc::now().timestamp();
+        let mut current = HashMap::new();
+
+        for container in self.docker_client.container_tracker().list_containers() {
+            let Some(states) = container_window_states(&container, now) else {
+                continue;
+            };
+            // Rules of newly seen containers were built with the current
+            // windows already
+            let changed = known.get(&container.id).is_some_and(|prev| *prev != states);
+            current.insert(container.id.clone(), states);
+            if !changed {
+                continue;
+            }
+
+            let mode = self.enforcement_mode(&container.identity()).await;
+            match self.rebuild_container_rules(&container, mode).await {
+                Ok(()) => info!("Time window changed for container {}", container.name),
+                Err(e) => warn!(
+                    "Failed to apply time window change for container {}: {}",
+                    container.name, e
+                ),
+            }
+        }
+
+        *known = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(s).unwrap().timestamp()
+    }
+
+    #[test]
+    fn test_apply_time_windows() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+mapped_ports:
+  external:
+    allow: true
+    time: {start: "09:00", end: "17:00", tz: UTC}
+output:
+  - proto: tcp
+    dst_ports: [443]
+    time: {days: [sat, sun], start: "00:00", end: "23:59", tz: UTC}
+  - proto: tcp
+    dst_ports: [53]
+"#,
+        )
+        .unwrap();
+
+        // Monday mid-morning: external open, weekend rule closed
+        let now = ts("2024-05-06T10:00:00Z");
+        assert_eq!(window_states(&config, now), vec![true, false]);
+
+        apply_time_windows(&mut config, now);
+        assert!(config.mapped_ports.external.allow);
+        assert!(config.output[0].skip);
+        assert!(!config.output[1].skip);
+
+        apply_time_windows(&mut config, ts("2024-05-06T20:00:00Z"));
+        assert!(!config.mapped_ports.external.allow);
+    }
+}
@@ -0,0 +1,228 @@
+//! Reinstalling our rules after someone else removed or changed them.
+//!
+//! The filter table is checked every few seconds. A missing harborshield
+//! chain or jump into it reinstalls everything; a missing container chain
+//! re-renders that container. A chain whose rules changed between two
+//! checks while this process wrote nothing was changed by someone else and
+//! is re-rendered too.
+
+use crate::nftables::HARBORSHIELD_CHAIN;
+use crate::nftables::integrity::{self, TableState};
+use crate::nftables::runner;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::Harborshield;
+
+/// What a check found to reinstall
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Heal {
+    Nothing,
+    /// The base chains, and every container's rules
+    Full(String),
+    /// The chains of these names
+    Chains(Vec<String>),
+}
+
+/// Checksums of the last check, to tell changes apart over the next
+#[derive(Debug, Default)]
+pub struct SelfHeal {
+    /// Our write generation then, and the checksum of each chain
+    baseline: Option<(u64, HashMap<String, u64>)>,
+}
+
+impl SelfHeal {
+    /// Compare `state`, listed with the write generation at `before` and
+    /// `after` the listing, with the chains running containers should have
+    pub fn check(
+        &mut self,
+        state: &TableState,
+        expected: &[String],
+        before: u64,
+        after: u64,
+    ) -> Heal {
+        let baseline = self.baseline.take();
+        let heal = if !state.table {
+            Heal::Full("the filter table is gone".to_string())
+        } else if !state.has_base_chain() {
+            Heal::Full(format!("the {} chain is gone", HARBORSHIELD_CHAIN))
+        } else if !state.missing_jumps.is_empty() {
+            Heal::Full(format!(
+                "the jumps from {} are gone",
+                state.missing_jumps.join(", ")
+            ))
+        } else {
+            let mut chains: Vec<String> = expected
+                .iter()
+                .filter(|chain| !state.chains.contains_key(*chain))
+                .cloned()
+                .collect();
+            // Only a span without writes of ours tells whose a change is
+            if let Some((generation, sums)) = &baseline
+                && *generation == before
+                && before == after
+            {
+                let changed = state
+                    .chains
+                    .iter()
+                    .filter(|(chain, sum)| sums.get(*chain).is_some_and(|old| old != *sum));
+                for (chain, _) in changed {
+                    if chain == HARBORSHIELD_CHAIN {
+                        return Heal::Full(format!("the {} chain was changed", chain));
+                    }
+                    chains.push(chain.clone());
+                }
+            }
+            chains.sort();
+            chains.dedup();
+            if chains.is_empty() {
+                Heal::Nothing
+            } else {
+                Heal::Chains(chains)
+            }
+        };
+
+        if heal == Heal::Nothing && before == after {
+            self.baseline = Some((after, state.chains.clone()));
+        }
+        heal
+    }
+}
+
+impl Harborshield {
+    /// Check the filter table for changes by others and reinstall what they
+    /// removed, every `interval` until shutdown
+    pub(crate) fn spawn_self_heal_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut heal = SelfHeal::default();
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => handlers.self_heal(&mut heal).await,
+                }
+            }
+        })
+    }
+
+    /// Chains of tracked containers by name, with the containers' IDs
+    fn expected_chains(&self) -> HashMap<String, String> {
+        self.docker_client
+            .container_tracker()
+            .list_containers()
+            .into_iter()
+            .filter(|c| {
+                c.is_harborshield_enabled()
+                    && c.config.is_some()
+                    && !c.uses_host_network
+                    && !c.paused
+            })
+            .map(|c| {
+                let chain = format!(
+                    "hs-{}-{}",
+                    c.name.replace(['_', '.', '/'], "-"),
+                    &c.id[..12.min(c.id.len())]
+                );
+                (chain, c.id)
+            })
+            .collect()
+    }
+
+    async fn self_heal(&self, heal: &mut SelfHeal) {
+        if crate::docker::rootless::is_rootless() || crate::nftables::standby::is_standby() {
+            return;
+        }
+
+        let expected = self.expected_chains();
+        let before = runner::write_generation();
+        let state = match integrity::table_state().await {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Failed to check the filter table: {}", e);
+                return;
+            }
+        };
+        let after = runner::write_generation();
+        let names: Vec<String> = expected.keys().cloned().collect();
+
+        match heal.check(&state, &names, before, after) {
+            Heal::Nothing => {}
+            Heal::Full(reason) => {
+                warn!("{}, reinstalling all rules", reason);
+                crate::server::increment_self_heals("full");
+                if let Err(e) = self.reinstall().await {
+                    warn!("Failed to reinstall rules: {}", e);
+                }
+            }
+            Heal::Chains(chains) => {
+                let tracker = self.docker_client.container_tracker();
+                for chain in chains {
+                    let Some(container) = expected
+                        .get(&chain)
+                        .and_then(|id| tracker.get_container(id))
+                    else {
+                        continue;
+                    };
+                    warn!(
+                        "Chain {} of {} was removed or changed, re-rendering it",
+                        chain, container.name
+                    );
+                    crate::server::increment_self_heals("chain");
+                    if let Err(e) = self.create_container_rules(&container, None).await {
+                        warn!("Failed to re-render rules of {}: {}", container.name, e);
+                    }
+                }
+            }
+        }
+    }
+
+    pub(super) async fn reinstall(&self) -> crate::Result<()> {
+        self.nftables_client.lock().await.init_base_chains().await?;
+        self.sync_all().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_heal_check() {
+        let state = |sum: u64| TableState {
+            table: true,
+            missing_jumps: Vec::new(),
+            chains: HashMap::from([
+                (HARBORSHIELD_CHAIN.to_string(), 1),
+                ("hs-web-abc".to_string(), sum),
+            ]),
+        };
+        let expected = vec!["hs-web-abc".to_string(), "hs-db-def".to_string()];
+        let mut heal = SelfHeal::default();
+
+        assert_eq!(
+            heal.check(&state(1), &expected, 0, 0),
+            Heal::Chains(vec!["hs-db-def".to_string()])
+        );
+        let expected = &expected[..1];
+        assert_eq!(heal.check(&state(1), expected, 0, 0), Heal::Nothing);
+
+        // Changed by us, then by someone else
+        assert_eq!(heal.check(&state(2), expected, 2, 2), Heal::Nothing);
+        assert_eq!(
+            heal.check(&state(3), expected, 2, 2),
+            Heal::Chains(vec!["hs-web-abc".to_string()])
+        );
+
+        assert!(matches!(
+            heal.check(&TableState::default(), expected, 2, 2),
+            Heal::Full(_)
+        ));
+    }
+}
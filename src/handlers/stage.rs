@@ -0,0 +1,143 @@
+//! Named stages of the event pipeline.
+//!
+//! A Docker event passes through four stages before its rules are live:
+//!
+//! - `queue`: waiting in the [`EventQueue`](super::pipeline::EventQueue)
+//! - `inspect`: asking Docker for the container's current state
+//! - `render`: resolving references and building the nft batch
+//! - `apply`: running the batch and updating the verdict maps
+//!
+//! Each stage reports how many items are in it, how long it took and how
+//! often it failed, and runs inside a `pipeline_stage` tracing span, so a
+//! slow pipeline can be pinned to Docker, rule rendering or nft.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Queue,
+    Inspect,
+    Render,
+    Apply,
+}
+
+static IN_FLIGHT: [AtomicI64; 4] = [
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+];
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Queue => "queue",
+            Stage::Inspect => "inspect",
+            Stage::Render => "render",
+            Stage::Apply => "apply",
+        }
+    }
+
+    fn counter(&self) -> &'static AtomicI64 {
+        &IN_FLIGHT[*self as usize]
+    }
+
+    /// Items currently in the stage. The queue stage is reported by the
+    /// queue itself, see [`set_queue_depth`]
+    pub fn depth(&self) -> u64 {
+        self.counter().load(Ordering::Relaxed).max(0) as u64
+    }
+
+    fn add(&self, delta: i64) {
+        let depth = self.counter().fetch_add(delta, Ordering::Relaxed) + delta;
+        crate::server::set_stage_depth(self.as_str(), depth.max(0) as u64);
+    }
+}
+
+/// Counts an item in the stage until dropped, so cancelled futures leave too
+struct InStage(Stage);
+
+impl InStage {
+    fn enter(stage: Stage) -> Self {
+        stage.add(1);
+        Self(stage)
+    }
+}
+
+impl Drop for InStage {
+    fn drop(&mut self) {
+        self.0.add(-1);
+    }
+}
+
+/// Run `work` as part of `stage`, recording its latency and failures
+pub async fn run<T, E, F>(stage: Stage, work: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let _in_stage = InStage::enter(stage);
+    let started = Instant::now();
+    let result = work
+        .instrument(tracing::debug_span!(
+            "pipeline_stage",
+            stage = stage.as_str()
+        ))
+        .await;
+    let elapsed = started.elapsed();
+
+    crate::server::record_stage_duration(stage.as_str(), elapsed);
+    match &result {
+        Ok(_) => tracing::trace!(stage = stage.as_str(), ?elapsed, "Stage finished"),
+        Err(e) => {
+            crate::server::increment_stage_errors(stage.as_str());
+            tracing::debug!(stage = stage.as_str(), ?elapsed, "Stage failed: {}", e);
+        }
+    }
+    result
+}
+
+/// Report the events waiting in the queue stage
+pub fn set_queue_depth(depth: usize) {
+    Stage::Queue
+        .counter()
+        .store(depth as i64, Ordering::Relaxed);
+    crate::server::set_stage_depth(Stage::Queue.as_str(), depth as u64);
+}
+
+/// Record how long an event waited before a worker picked it up
+pub fn record_queue_wait(wait: Duration) {
+    crate::server::record_stage_duration(Stage::Queue.as_str(), wait);
+}
+
+/// Record an event that never made it through the queue
+pub fn record_queue_error() {
+    crate::server::increment_stage_errors(Stage::Queue.as_str());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_tracks_stage() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let work = tokio::spawn(run(Stage::Render, async move {
+            rx.await.map_err(|e| e.to_string())?;
+            Ok::<_, String>(7)
+        }));
+        while Stage::Render.depth() == 0 {
+            tokio::task::yield_now().await;
+        }
+        tx.send(()).unwrap();
+        assert_eq!(work.await.unwrap(), Ok(7));
+        assert_eq!(Stage::Render.depth(), 0);
+
+        let failed: Result<(), String> = run(Stage::Apply, async { Err("nft".to_string()) }).await;
+        assert_eq!(failed, Err("nft".to_string()));
+        assert_eq!(Stage::Apply.depth(), 0);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    database::{
+        DbOp, DbOpResult, StatEvent, StatKind,
+        activity::ActivitySampler,
+        stats::{DAILY_RETENTION, HOURLY_RETENTION},
+    },
+    nftables::{
+        capacity::list_ruleset_counts,
+        counters::{CounterSampler, list_chain_counters},
+    },
+};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::Harborshield;
+use super::activity::Notified;
+use super::hits::HitSampler;
+use super::quarantine::DropWindows;
+
+impl Harborshield {
+    /// Sample chain counters and fold raw stats into the rollup tables every
+    /// `interval` until shutdown
+    pub(crate) fn spawn_stats_job(&self, interval: Duration) -> JoinHandle<()> {
+        let handlers = self.clone();
+
+        tokio::spawn(async move {
+            let mut sampler = CounterSampler::default();
+            let mut drop_windows = DropWindows::default();
+            let mut hit_sampler = HitSampler::default();
+            let mut activity_sampler = ActivitySampler::default();
+            let mut notified_unused = Notified::default();
+            let mut last_capacity_bucket = None;
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        if let Err(e) = handlers.run_stats_pass(&mut sampler, &mut drop_windows).await {
+                            warn!("Stats aggregation pass failed: {}", e);
+                        }
+                        let now = chrono::Utc::now().timestamp();
+                        handlers.notify_rule_hits(&mut hit_sampler, now).await;
+                        handlers
+                            .track_rule_activity(
+                                &mut activity_sampler,
+                                &mut notified_unused,
+                                handlers.unused_allow_after,
+                                now,
+                            )
+                            .await;
+                        handlers.sample_capacity(&mut last_capacity_bucket).await;
+                    }
+                }
+            }
+        })
+    }
+
+    async fn run_stats_pass(
+        &self,
+        sampler: &mut CounterSampler,
+        drop_windows: &mut DropWindows,
+    ) -> crate::Result<()> {
+        let now = chrono::Utc::now().timestamp();
+
+        let deltas = match list_chain_counters().await {
+            Ok(current) => sampler.deltas(current),
+            Err(e) => {
+                debug!("Skipping counter sample: {}", e);
+                HashMap::new()
+            }
+        };
+
+        let mut db = self.db.lock().await;
+
+        // Chains are named after containers; map them back to the identity
+        // stats are kept under, so they carry over recreated containers
+        let tracker = self.docker_client.container_tracker();
+        let names: HashMap<String, String> = match db.execute(&DbOp::ListContainers).await? {
+            DbOpResult::Containers(containers) => containers
+                .into_iter()
+                .map(|c| {
+                    let chain = format!(
+                        "hs-{}-{}",
+                        c.name.replace(['_', '.', '/'], "-"),
+                        &c.id[..12.min(c.id.len())]
+                    );
+                    let identity = tracker
+                        .get_container(&c.id)
+                        .map_or(c.name, |tracked| tracked.identity());
+                    (chain, identity)
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        let events: Vec<StatEvent> = deltas
+            .into_iter()
+            .flat_map(|(chain, delta)| {
+                let name = names.get(&chain).cloned().unwrap_or(chain);
+                [
+                    (StatKind::Drop, delta.drop_packets, delta.drop_bytes),
+                    (StatKind::Accept, delta.accept_packets, delta.accept_bytes),
+                ]
+                .into_iter()
+                .filter(|(_, packets, _)| *packets > 0)
+                .map(move |(kind, packets, bytes)| {
+                    StatEvent::builder()
+                        .ts(now)
+                        .container_name(name.clone())
+                        .kind(kind)
+                        .packets(packets as i64)
+                        .bytes(bytes as i64)
+                        .build()
+                })
+            })
+            .collect();
+        let mut ops: Vec<DbOp> = events.iter().map(DbOp::InsertStatEvent).collect();
+        ops.push(DbOp::RollupStats { until: now });
+        ops.push(DbOp::PruneStats {
+            hourly_before: now - HOURLY_RETENTION.as_secs() as i64,
+            daily_before: now - DAILY_RETENTION.as_secs() as i64,
+        });
+
+        db.transaction().execute_ops(&ops).await?.commit().await?;
+        drop(db);
+        events.iter().for_each(crate::bus::publish_stat);
+
+        let mut drops: HashMap<String, u64> = HashMap::new();
+        for event in events.iter().filter(|e| e.kind == StatKind::Drop) {
+            *drops.entry(event.container_name.clone()).or_default() += event.packets as u64;
+        }
+        self.check_quarantine(drop_windows, &drops, now).await;
+        Ok(())
+    }
+
+    /// Snapshot ruleset size once per hour for `harborshield capacity`
+    async fn sample_capacity(&self, last_bucket: &mut Option<i64>) {
+        let now = chrono::Utc::now().timestamp();
+        let bucket = now - now % 3600;
+        if *last_bucket == Some(bucket) {
+            return;
+        }
+
+        match list_ruleset_counts().await {
+            Ok(counts) => {
+                let sample = counts.to_sample(bucket);
+                let db = self.db.lock().await;
+                match db.execute(&DbOp::InsertCapacitySample(&sample)).await {
+                    Ok(_) => *last_bucket = Some(bucket),
+                    Err(e) => debug!("Failed to store capacity sample: {}", e),
+                }
+            }
+            Err(e) => debug!("Skipping capacity sample: {}", e),
+        }
+    }
+
+    /// Record that a container's rules were created or removed
+    pub(super) async fn record_rule_change(&self, container_name: &str) {
+        let event = StatEvent::builder()
+            .ts(chrono::Utc::now().timestamp())
+            .container_name(container_name.to_string())
+            .kind(StatKind::RuleChange)
+            .build();
+
+        let db = self.db.lock().await;
+        if let Err(e) = db.execute(&DbOp::InsertStatEvent(&event)).await {
+            debug!("Failed to record rule change for {}: {}", container_name, e);
+        }
+        crate::bus::publish_stat(&event);
+    }
+}
@@ -0,0 +1,106 @@
+//! Egress to the storage servers behind a container's NFS and SMB volumes,
+//! added when its rules set `storage_egress: true`.
+//!
+//! Forgetting these carve-outs breaks the mount mid-run as soon as egress
+//! is locked down, long after the rules were written.
+
+use crate::docker::config::{AddrOrRange, Config, Protocol, RuleConfig, RulePorts};
+use crate::docker::container::Container;
+use crate::docker::volumes::StorageBackend;
+use tracing::debug;
+
+/// One rule per backend and protocol, allowing the share's ports
+pub fn storage_rules(backends: &[StorageBackend]) -> Vec<RuleConfig> {
+    let mut rules = Vec::new();
+    for backend in backends.iter().filter(|backend| !backend.addrs.is_empty()) {
+        let ports = backend.ports();
+        for proto in [Protocol::Tcp, Protocol::Udp] {
+            let dst_ports: Vec<RulePorts> = ports
+                .iter()
+                .filter(|(port_proto, _)| *port_proto == proto)
+                .map(|(_, port)| RulePorts::Single(*port))
+                .collect();
+            if dst_ports.is_empty() {
+                continue;
+            }
+            rules.push(
+                RuleConfig::builder()
+                    .ips(
+                        backend
+                            .addrs
+                            .iter()
+                            .copied()
+                            .map(AddrOrRange::Addr)
+                            .collect(),
+                    )
+                    .proto(proto)
+                    .dst_ports(dst_ports)
+                    .build(),
+            );
+        }
+    }
+    rules
+}
+
+/// Append the storage rules of `container` when its config asks for them
+pub fn add_storage_egress(container: &Container, config: &mut Config) {
+    if !config.storage_egress {
+        return;
+    }
+    for backend in &container.storage_backends {
+        debug!(
+            "Allowing {} to storage server {} of volume {}",
+            container.name, backend.server, backend.volume
+        );
+    }
+    config
+        .output
+        .extend(storage_rules(&container.storage_backends));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::volumes::StorageProtocol;
+
+    #[test]
+    fn test_storage_rules() {
+        let backend = |protocol, addrs: &[&str]| StorageBackend {
+            volume: "data".to_string(),
+            protocol,
+            server: "filer".to_string(),
+            addrs: addrs.iter().map(|a| a.parse().unwrap()).collect(),
+        };
+        let rules = storage_rules(&[
+            backend(StorageProtocol::Nfs { v4: false }, &["10.0.0.5"]),
+            backend(StorageProtocol::Smb, &["10.0.0.6", "fd00::6"]),
+            backend(StorageProtocol::Smb, &[]),
+        ]);
+
+        let summary: Vec<String> = rules
+            .iter()
+            .map(|rule| super::super::disabled::rule_key(rule))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "tcp to 10.0.0.5 port 2049,111",
+                "udp to 10.0.0.5 port 2049,111",
+                "tcp to 10.0.0.6,fd00::6 port 445",
+            ]
+        );
+
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("app".to_string())
+            .storage_backends(vec![backend(StorageProtocol::Smb, &["10.0.0.6"])])
+            .build();
+        let mut config = Config::new();
+        add_storage_egress(&container, &mut config);
+        assert!(config.output.is_empty());
+
+        config.storage_egress = true;
+        add_storage_egress(&container, &mut config);
+        assert_eq!(config.output.len(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{
+    docker::{config::Config, container::Container},
+    server,
+};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use tracing::error;
+
+use super::Harborshield;
+
+/// Addresses outside `subnet`. Addresses of the other IP family are not
+/// covered by the assertion and never count.
+pub fn unexpected_addresses(subnet: &IpNet, addrs: &[IpAddr]) -> Vec<IpAddr> {
+    addrs
+        .iter()
+        .filter(|ip| ip.is_ipv4() == matches!(subnet, IpNet::V4(_)) && !subnet.contains(*ip))
+        .copied()
+        .collect()
+}
+
+/// Turn off everything a config allows, leaving the chain's default drop
+pub fn withdraw_allow_rules(config: &mut Config) {
+    config.mapped_ports.localhost.allow = false;
+    config.mapped_ports.external.allow = false;
+    for rule in config.output.iter_mut() {
+        rule.skip = true;
+    }
+}
+
+impl Harborshield {
+    /// Withdraw a container's allow rules when it came up outside its
+    /// `expected_subnet`
+    pub(super) fn enforce_expected_subnet(&self, container: &Container, config: &mut Config) {
+        let Some(subnet) = &config.expected_subnet else {
+            return;
+        };
+        let addrs: Vec<IpAddr> = container
+            .networks
+            .values()
+            .flat_map(|network| network.ip_addresses.iter().copied())
+            .collect();
+        let unexpected = unexpected_addresses(subnet, &addrs);
+        if unexpected.is_empty() {
+            return;
+        }
+
+        error!(
+            container_name = %container.name,
+            expected_subnet = %subnet,
+            addresses = ?unexpected,
+            "Container has addresses outside its expected subnet; allow rules are not applied. Check which networks it is attached to"
+        );
+        server::increment_subnet_violations(&container.name);
+        withdraw_allow_rules(config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unexpected_addresses() {
+        let subnet: IpNet = "172.20.0.0/16".parse().unwrap();
+        let addrs: Vec<IpAddr> = ["172.20.0.5", "172.17.0.2", "fd00::2"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+
+        assert_eq!(
+            unexpected_addresses(&subnet, &addrs),
+            vec!["172.17.0.2".parse::<IpAddr>().unwrap()]
+        );
+        assert!(unexpected_addresses(&subnet, &addrs[..1]).is_empty());
+    }
+
+    #[test]
+    fn test_withdraw_allow_rules() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+expected_subnet: 172.20.0.0/16
+mapped_ports:
+  localhost:
+    allow: true
+  external:
+    allow: true
+output:
+  - proto: tcp
+    dst_ports: [443]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.expected_subnet,
+            Some("172.20.0.0/16".parse().unwrap())
+        );
+
+        withdraw_allow_rules(&mut config);
+        assert!(!config.mapped_ports.localhost.allow);
+        assert!(!config.mapped_ports.external.allow);
+        assert!(config.output[0].skip);
+
+        assert!(serde_yaml::from_str::<Config>("expected_subnet: 172.20.0.0").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Keeping the tproxy table in line with the output rules marked `tproxy`;
+//! see [`crate::nftables::tproxy`].
+
+use crate::database::EnforcementMode;
+use crate::docker::config::Config;
+use crate::docker::container::Container;
+use crate::nftables::tproxy::TproxyTarget;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, warn};
+
+use super::Harborshield;
+use super::utils::resolve_config;
+
+/// Whether the tproxy table may hold rules; a table left by an earlier run
+/// is cleared by the first sync
+static INTERCEPTING: AtomicBool = AtomicBool::new(true);
+
+/// A container's intercepted rules as numbered in its chain
+pub fn tproxy_target<'a>(container: &'a Container, config: &'a Config) -> TproxyTarget<'a> {
+    let mut ips: Vec<_> = container
+        .ip_addresses(false)
+        .into_iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+    ips.sort();
+    TproxyTarget {
+        container_name: &container.name,
+        ips,
+        rules: config
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.tproxy.is_some() && !rule.skip && rule.container.is_empty())
+            .map(|(i, rule)| (i + 1, rule))
+            .collect(),
+    }
+}
+
+impl Harborshield {
+    /// Rebuild the tproxy table from every tracked container. Quarantined
+    /// and disabled containers intercept nothing
+    pub(super) async fn sync_tproxy(&self) {
+        if crate::docker::rootless::is_rootless() {
+            return;
+        }
+
+        let host_addrs = self.host_addrs.get();
+        let now = chrono::Utc::now().timestamp();
+        let mut resolved: Vec<(Container, Config)> = Vec::new();
+        for container in self.docker_client.container_tracker().list_containers() {
+            let Some(config) = &container.config else {
+                continue;
+            };
+            if !container.enabled || container.uses_host_network || container.paused {
+                continue;
+            }
+            if !config.output.iter().any(|rule| rule.tproxy.is_some()) {
+                continue;
+            }
+            let mode = self.enforcement_mode(&container.identity()).await;
+            if !matches!(mode, EnforcementMode::Enforce | EnforcementMode::Permissive) {
+                continue;
+            }
+            let config = resolve_config(
+                &container,
+                config,
+                self.docker_client.container_tracker(),
+                &host_addrs,
+                now,
+            );
+            resolved.push((container, config));
+        }
+
+        let targets: Vec<TproxyTarget> = resolved
+            .iter()
+            .map(|(container, config)| tproxy_target(container, config))
+            .filter(|target| !target.rules.is_empty())
+            .collect();
+        if targets.is_empty() && !INTERCEPTING.load(Ordering::Relaxed) {
+            return;
+        }
+        let nftables = self.nftables_client.lock().await;
+        match nftables.rebuild_tproxy(&targets).await {
+            Ok(()) => {
+                INTERCEPTING.store(!targets.is_empty(), Ordering::Relaxed);
+                debug!("Intercepting flows of {} containers", targets.len());
+            }
+            Err(e) => warn!("Failed to rebuild the tproxy table: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::Network;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_tproxy_target() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+output:
+  - ips: ["10.0.5.20"]
+    proto: tcp
+    dst_ports: [443]
+  - ips: ["10.0.0.0/8"]
+    proto: tcp
+    dst_ports: [80]
+    tproxy: {port: 8080, mark: 1}
+"#,
+        )
+        .unwrap();
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("web".to_string())
+            .networks(HashMap::from([(
+                "bridge".to_string(),
+                Network::builder()
+                    .name("bridge".to_string())
+                    .ip_addresses(vec![
+                        "fd00::2".parse().unwrap(),
+                        "172.17.0.2".parse().unwrap(),
+                    ])
+                    .aliases(Vec::new())
+                    .build(),
+            )]))
+            .build();
+
+        let target = tproxy_target(&container, &config);
+        assert_eq!(
+            target.ips,
+            vec!["172.17.0.2".parse::<std::net::Ipv4Addr>().unwrap()]
+        );
+        assert_eq!(
+            target.rules.iter().map(|(n, _)| *n).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
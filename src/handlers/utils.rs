@@ -1,15 +1,21 @@
 use crate::{
     Result,
-    database::ContainerIdentifiers,
-    docker::{compose::ComposeInfo, config::RulePorts, container::Container},
+    database::{ContainerIdentifiers, EnforcementMode},
+    docker::{
+        compose::ComposeInfo,
+        config::RulePorts,
+        container::{Container, Tracker},
+    },
     nftables::transaction::NftablesTransaction,
     server,
 };
 use std::collections::HashMap;
+use std::net::IpAddr;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
 use super::Harborshield;
+use super::stage::{self, Stage};
 
 impl Harborshield {
     /// Create container rules using direct config translation (new approach)
@@ -28,12 +34,22 @@ impl Harborshield {
             return Ok(());
         }
 
-        // Get container IPs
-        let mut container_ips: Vec<std::net::IpAddr> = Vec::new();
-        for (_, network) in &container.networks {
-            container_ips.extend(&network.ip_addresses);
+        if !container.sidecar_hints.is_empty() && container.config.is_some() {
+            tracing::warn!(
+                container_name = %container.name,
+                hints = ?container.sidecar_hints,
+                "Container appears to run behind a service mesh sidecar; traffic may be redirected to the proxy, so port-based rules may not match what the application sees. Run `harborshield doctor` to check"
+            );
+        }
+
+        let tracked = self.docker_client.container_tracker().list_containers();
+        for overlap in crate::docker::ports::overlapping_ports(container, &tracked) {
+            tracing::warn!("Container {}: {}", container.name, overlap);
         }
 
+        // Get container IPs; IPv6 ones have their own chains
+        let container_ips = container.ip_addresses(false);
+
         // Check if container has rules defined
         if let Some(config) = &container.config {
             // Check for cancellation
@@ -47,6 +63,19 @@ impl Harborshield {
                 }
             }
 
+            let enforcement = self.enforcement_mode(&container.identity()).await;
+
+            // Rootless containers have no chain; only published ports are guarded
+            if crate::docker::rootless::is_rootless() {
+                self.sync_rootless().await;
+                info!(
+                    "Guarded published ports of container {} in {} mode (rootless Docker)",
+                    container.name, enforcement
+                );
+                super::readiness::mark_ready(&container.id, &container.name);
+                return Ok(());
+            }
+
             // Create container chain and apply rules using direct translation
             let mut nftables = self.nftables_client.lock().await;
             nftables
@@ -66,85 +95,70 @@ impl Harborshield {
                 container_ports
             );
 
-            // Resolve container references in output rules
-            let mut resolved_config = config.clone();
-            for (idx, output_rule) in resolved_config.output.iter_mut().enumerate() {
-                if !output_rule.container.is_empty() {
-                    let container_ref = output_rule.container.clone();
-                    // Find the target container
-                    if let Some(target_container) = self
-                        .docker_client
-                        .container_tracker
-                        .find_container(&container_ref)
-                    {
-                        // Get target container IPs
-                        let mut target_ips = Vec::new();
-                        for (_, network) in &target_container.networks {
-                            for ip in &network.ip_addresses {
-                                target_ips.push(crate::docker::config::AddrOrRange::Addr(*ip));
-                            }
-                        }
-
-                        if !target_ips.is_empty() {
-                            // Replace container reference with actual IPs
-                            output_rule.ips = target_ips;
-                            output_rule.container.clear(); // Clear the container reference
-                            debug!(
-                                "Resolved container reference '{}' to IPs for output rule {} in container {}",
-                                container_ref,
-                                idx + 1,
-                                container.name
-                            );
-                        } else {
-                            debug!(
-                                "Target container '{}' has no IPs yet, output rule {} will be handled as waiting rule",
-                                container_ref,
-                                idx + 1
-                            );
-                        }
-                    } else {
-                        debug!(
-                            "Target container '{}' not found, output rule {} will be handled as waiting rule",
-                            container_ref,
-                            idx + 1
-                        );
-                    }
-                }
-            }
+            let resolved_config = stage::run(Stage::Render, async {
+                // Resolve container references in output rules
+                let resolved_config = self.resolve_container_references(container, config);
 
-            // Apply rules directly from config
-            nftables
-                .add_rules_from_config(
-                    &container.id,
-                    &container.name,
-                    &container_ips,
-                    &container_ports,
-                    &resolved_config,
-                )
-                .await?;
+                // Apply rules directly from config
+                nftables
+                    .add_rules_from_config(
+                        &container.id,
+                        &container.name,
+                        &container_ips,
+                        &container_ports,
+                        &resolved_config,
+                    )
+                    .await?;
+                Ok::<_, crate::Error>(resolved_config)
+            })
+            .await?;
 
-            // Commit the batch
-            nftables.apply().await?;
+            stage::run(Stage::Apply, async {
+                // Commit the batch
+                nftables.apply().await?;
+
+                // Update verdict maps to include this container's IPs
+                // This creates the vmap rules in the harborshield chain that route traffic
+                // from container source IPs to their respective chains
+                let container_mappings = vec![(
+                    container.id.clone(),
+                    container.name.clone(),
+                    container_ips
+                        .iter()
+                        .map(|ip| ip.to_string())
+                        .collect::<Vec<_>>(),
+                )];
+                nftables
+                    .update_container_verdict_maps(&container_mappings)
+                    .await?;
 
-            // Update verdict maps to include this container's IPs
-            // This creates the vmap rules in the harborshield chain that route traffic
-            // from container source IPs to their respective chains
-            let container_mappings = vec![(
-                container.id.clone(),
-                container.name.clone(),
-                container_ips
-                    .iter()
-                    .map(|ip| ip.to_string())
-                    .collect::<Vec<_>>(),
-            )];
-            nftables
-                .update_container_verdict_maps(&container_mappings)
-                .await?;
+                if enforcement != EnforcementMode::Enforce {
+                    nftables
+                        .rebuild_container_chain(
+                            &container.id,
+                            &container.name,
+                            &container_ips,
+                            &container_ports,
+                            &resolved_config,
+                            enforcement,
+                        )
+                        .await?;
+                }
+                Ok::<_, crate::Error>(())
+            })
+            .await?;
 
             info!(
-                "Applied firewall rules for container {} using direct config translation",
-                container.name
+                "Applied firewall rules for container {} in {} mode using direct config translation",
+                container.name, enforcement
             );
+            drop(nftables);
+            self.flush_revoked_flows(container, &container_ips, &resolved_config, enforcement)
+                .await;
+            self.sync_offload().await;
+            self.sync_forwards().await;
+            self.sync_tproxy().await;
+            self.sync_ipv6().await;
 
             // Update metrics
             let rule_count = config.output.len()
@@ -162,20 +176,24 @@ impl Harborshield {
             for _ in 0..rule_count {
                 server::increment_rules_applied();
             }
+            self.record_rule_change(&container.identity()).await;
+            self.audit_disabled_rules(&container.identity(), config)
+                .await;
         } else {
             // No rules defined, just create the chain
             let mut nftables = self.nftables_client.lock().await;
             nftables
                 .create_container_chain(&container.id, &container.name)
                 .await?;
-            nftables.apply().await?;
+            stage::run(Stage::Apply, nftables.apply()).await?;
 
             // Even with no rules, we need to update verdict maps for the container's IPs
             // so traffic from/to this container can be routed to its chain
-            let mut container_ips = Vec::new();
-            for (_, network) in &container.networks {
-                container_ips.extend(network.ip_addresses.iter().map(|ip| ip.to_string()));
-            }
+            let container_ips: Vec<String> = container
+                .ip_addresses(false)
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect();
 
             if !container_ips.is_empty() {
                 let container_mappings =
@@ -186,8 +204,29 @@ impl Harborshield {
             }
         }
 
+        super::readiness::mark_ready(&container.id, &container.name);
         Ok(())
     }
+
+    /// Replace container references in output rules with the IPs of the
+    /// referenced containers that are currently known, and `host` with the
+    /// host's current addresses
+    pub(super) fn resolve_container_references(
+        &self,
+        container: &Container,
+        config: &crate::docker::config::Config,
+    ) -> crate::docker::config::Config {
+        let mut resolved_config = resolve_config(
+            container,
+            config,
+            self.docker_client.container_tracker(),
+            &self.host_addrs.get(),
+            chrono::Utc::now().timestamp(),
+        );
+        self.enforce_expected_subnet(container, &mut resolved_config);
+        resolved_config
+    }
+
     /// Log Docker Compose information if present
     pub fn log_compose_info(
         &self,
@@ -213,6 +252,94 @@ impl Harborshield {
 }
 
 // Helper functions for rule management
+
+/// The rules `config` amounts to for `container` at `now`: rules scoped to
+/// the network they name, container references resolved through `tracker`, `host` expanded to `host_addrs`,
+/// compose service peers and storage egress added, rules outside their time window or disabled
+/// skipped and external access withheld from sensitive ports it would open to everyone
+pub fn resolve_config(
+    container: &Container,
+    config: &crate::docker::config::Config,
+    tracker: &Tracker,
+    host_addrs: &[IpAddr],
+    now: i64,
+) -> crate::docker::config::Config {
+    let mut resolved_config = config.clone();
+    super::networks::scope_to_networks(container, &mut resolved_config);
+    for (idx, output_rule) in resolved_config.output.iter_mut().enumerate() {
+        if !output_rule.container.is_empty() {
+            let container_ref = output_rule.container.clone();
+            // Find the target container
+            if let Some(target_container) = tracker.find_container(&container_ref) {
+                // Get target container IPs
+                let target_ips: Vec<_> =
+                    super::networks::target_addresses(&target_container, &output_rule.network)
+                        .into_iter()
+                        .map(crate::docker::config::AddrOrRange::Addr)
+                        .collect();
+
+                if !target_ips.is_empty() {
+                    // Replace container reference with actual IPs
+                    output_rule.ips = target_ips;
+                    output_rule.container.clear(); // Clear the container reference
+                    debug!(
+                        "Resolved container reference '{}' to IPs for output rule {} in container {}",
+                        container_ref,
+                        idx + 1,
+                        container.name
+                    );
+                } else {
+                    debug!(
+                        "Target container '{}' has no IPs yet, output rule {} will be handled as waiting rule",
+                        container_ref,
+                        idx + 1
+                    );
+                }
+            } else {
+                debug!(
+                    "Target container '{}' not found, output rule {} will be handled as waiting rule",
+                    container_ref,
+                    idx + 1
+                );
+            }
+        }
+    }
+    super::peers::add_service_peers(container, &mut resolved_config, &tracker.list_containers());
+    super::storage::add_storage_egress(container, &mut resolved_config);
+    super::host::expand_host_addresses(&mut resolved_config, host_addrs);
+    crate::docker::config::expand_ip_templates(
+        &mut resolved_config,
+        &crate::docker::network::known_networks(),
+    );
+    super::schedule::apply_time_windows(&mut resolved_config, now);
+    super::disabled::skip_disabled_rules(&mut resolved_config);
+
+    let ports: Vec<u16> = container
+        .ports
+        .iter()
+        .flat_map(|port| [Some(port.container_port), port.host_port])
+        .flatten()
+        .chain(
+            resolved_config
+                .forward
+                .iter()
+                .flat_map(|forward| [forward.host_port, forward.container_port()]),
+        )
+        .collect();
+    let exposed = crate::docker::config::exposure::withhold_external(
+        &mut resolved_config,
+        &ports,
+        &crate::docker::config::exposure::sensitive_ports(),
+    );
+    if !exposed.is_empty() {
+        warn!(
+            "Container {}: external access left out, it would admit every source to sensitive ports {:?}; set 'expose_to_internet: true' if that is intended",
+            container.name, exposed
+        );
+    }
+    resolved_config
+}
+
 pub fn convert_rule_ports(ports: &[RulePorts]) -> Vec<u16> {
     ports
         .iter()
@@ -254,7 +381,7 @@ pub fn extract_mapped_ports(container: &Container) -> Option<(Vec<u16>, Vec<u16>
 impl Harborshield {
     /// Get gateway IPs for a container's connected networks
     pub async fn get_container_gateway_ips(&self, details: &Container) -> Vec<std::net::IpAddr> {
-        let gateway_cache = self.docker_client.network_gateway_cache.lock().await;
+        let gateway_cache = self.docker_client.network_gateway_cache().lock().await;
         let mut gateway_ips = Vec::new();
 
         for (network_name, _) in &details.networks {
@@ -265,6 +392,21 @@ impl Harborshield {
 
         gateway_ips
     }
+
+    /// Re-read every network's gateways and subnets, keeping them for
+    /// address templates
+    pub async fn refresh_networks(&self) -> Result<()> {
+        self.docker_client.refresh_network_gateways().await?;
+        let networks = self
+            .docker_client
+            .network_gateway_cache()
+            .lock()
+            .await
+            .clone();
+        crate::docker::network::remember_networks(networks);
+        Ok(())
+    }
+
     pub async fn sync_containers(
         &self,
         mut db_container_ids: HashMap<String, ContainerIdentifiers>,
@@ -272,7 +414,7 @@ impl Harborshield {
         info!("Syncing containers with current Docker state");
 
         // Refresh network gateway information first
-        if let Err(e) = self.docker_client.refresh_network_gateways().await {
+        if let Err(e) = self.refresh_networks().await {
             warn!("Failed to refresh network gateway information: {}", e);
         }
 
@@ -293,7 +435,7 @@ impl Harborshield {
                             // Add to container tracker so waiting rules can find it
                             if let Err(e) = self
                                 .docker_client
-                                .container_tracker
+                                .container_tracker()
                                 .add_container(container.clone())
                             {
                                 debug!(
@@ -373,7 +515,7 @@ impl Harborshield {
                                 // Add to container tracker so waiting rules can find it
                                 if let Err(e) = self
                                     .docker_client
-                                    .container_tracker
+                                    .container_tracker()
                                     .add_container(container.clone())
                                 {
                                     debug!(
@@ -511,7 +653,7 @@ impl Harborshield {
 
         // Process output rules to create waiting rules
         for rule_config in &config.output {
-            if !rule_config.container.is_empty() {
+            if rule_config.enabled && !rule_config.container.is_empty() {
                 let dst_ports: Vec<u16> = rule_config
                     .dst_ports
                     .iter()
@@ -607,7 +749,7 @@ impl Harborshield {
 
         // Add to tracker
         self.docker_client
-            .container_tracker
+            .container_tracker()
             .add_container(container.clone())?;
 
         // Store in database before creating rules to avoid foreign key issues
@@ -654,6 +796,13 @@ impl Harborshield {
         transaction.remove_container_rules(container_id, container_name)?;
         transaction.commit().await?;
 
+        let identity = self
+            .docker_client
+            .container_tracker()
+            .get_container(container_id)
+            .map_or_else(|| container_name.to_string(), |c| c.identity());
+        self.record_rule_change(&identity).await;
+
         let db = self.db.lock().await;
         use crate::database::DbOp;
         db.execute(&DbOp::DeleteContainer(container_id)).await?;
@@ -663,7 +812,7 @@ impl Harborshield {
 
     /// Update metrics for monitoring
     pub async fn update_metrics(&self) {
-        let container_count = self.docker_client.container_tracker.container_count();
+        let container_count = self.docker_client.container_tracker().container_count();
         crate::server::set_active_containers(container_count as u64);
 
         // In stateless mode, we don't track persistent rules
@@ -686,7 +835,7 @@ impl Harborshield {
             )),
         );
 
-        let container_count = self.docker_client.container_tracker.container_count();
+        let container_count = self.docker_client.container_tracker().container_count();
         stats.insert(
             "active_containers".to_string(),
             serde_json::Value::Number(serde_json::Number::from(container_count)),
@@ -0,0 +1,219 @@
+//! Connection tracking samples, used by learning mode to see which flows a
+//! container makes or receives.
+//!
+//! Entries come from `/proc/net/nf_conntrack` where the kernel still provides
+//! it, and from `conntrack -L` otherwise. Both print the same `key=value`
+//! tuples, once for the original direction and once for the reply.
+//! Entries of revoked flows are deleted with `conntrack -D`.
+
+use std::io;
+use std::net::IpAddr;
+
+const PROC_CONNTRACK: &str = "/proc/net/nf_conntrack";
+pub const CONNTRACK_PROGRAM: &str = "conntrack";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Connections opened towards the container
+    Inbound,
+    /// Connections the container opened
+    Outbound,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuple {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub sport: u16,
+    pub dport: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub proto: String,
+    pub original: Tuple,
+    pub reply: Tuple,
+}
+
+/// A flow as seen from one container
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Flow {
+    pub direction: Direction,
+    pub proto: String,
+    pub peer: IpAddr,
+    /// Container port for inbound flows, remote port for outbound ones
+    pub port: u16,
+}
+
+/// Parse one line of conntrack output; only TCP and UDP entries are kept
+pub fn parse_line(line: &str) -> Option<Entry> {
+    let mut tokens = line.split_whitespace();
+    let proto = tokens.find(|t| *t == "tcp" || *t == "udp")?.to_string();
+
+    let mut fields: Vec<(&str, &str)> = Vec::new();
+    for token in tokens {
+        if let Some((key, value)) = token.split_once('=')
+            && matches!(key, "src" | "dst" | "sport" | "dport")
+        {
+            fields.push((key, value));
+        }
+    }
+    // The original tuple comes first, the reply tuple second
+    let (original, reply) = fields.split_at_checked(4)?;
+
+    Some(Entry {
+        proto,
+        original: parse_tuple(original)?,
+        reply: parse_tuple(reply.get(..4)?)?,
+    })
+}
+
+fn parse_tuple(fields: &[(&str, &str)]) -> Option<Tuple> {
+    let value = |name: &str| fields.iter().find(|(key, _)| *key == name).map(|f| f.1);
+    Some(Tuple {
+        src: value("src")?.parse().ok()?,
+        dst: value("dst")?.parse().ok()?,
+        sport: value("sport")?.parse().ok()?,
+        dport: value("dport")?.parse().ok()?,
+    })
+}
+
+pub fn parse(text: &str) -> Vec<Entry> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+impl Entry {
+    /// The flow this entry represents for a container with `ips`, if any
+    pub fn flow_for(&self, ips: &[IpAddr]) -> Option<Flow> {
+        if ips.contains(&self.original.src) {
+            return Some(Flow {
+                direction: Direction::Outbound,
+                proto: self.proto.clone(),
+                peer: self.original.dst,
+                port: self.original.dport,
+            });
+        }
+        // Published ports are DNATed, so only the reply names the container
+        if ips.contains(&self.reply.src) {
+            return Some(Flow {
+                direction: Direction::Inbound,
+                proto: self.proto.clone(),
+                peer: self.original.src,
+                port: self.reply.sport,
+            });
+        }
+        None
+    }
+}
+
+/// Current conntrack table
+pub async fn sample() -> io::Result<Vec<Entry>> {
+    match tokio::fs::read_to_string(PROC_CONNTRACK).await {
+        Ok(text) => return Ok(parse(&text)),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let output = tokio::process::Command::new(CONNTRACK_PROGRAM)
+        .arg("-L")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} -L failed: {}",
+            CONNTRACK_PROGRAM,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `conntrack -D` arguments matching the connection of `entry` exactly
+fn delete_args(entry: &Entry) -> Vec<String> {
+    let tuple = &entry.original;
+    vec![
+        "-D".to_string(),
+        "-p".to_string(),
+        entry.proto.clone(),
+        "-s".to_string(),
+        tuple.src.to_string(),
+        "-d".to_string(),
+        tuple.dst.to_string(),
+        "--sport".to_string(),
+        tuple.sport.to_string(),
+        "--dport".to_string(),
+        tuple.dport.to_string(),
+    ]
+}
+
+/// Delete the connection of `entry`, so its packets are matched against the
+/// ruleset again instead of passing as established
+pub async fn delete(entry: &Entry) -> io::Result<()> {
+    let output = tokio::process::Command::new(CONNTRACK_PROGRAM)
+        .args(delete_args(entry))
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // The connection closing since the sample isn't a failure
+    if output.status.success() || stderr.contains("0 flow entries") {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "{} -D failed: {}",
+        CONNTRACK_PROGRAM,
+        stderr.trim()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_classify() {
+        let text = "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=172.17.0.2 dst=93.184.216.34 sport=51234 dport=443 src=93.184.216.34 dst=192.168.1.5 sport=443 dport=51234 [ASSURED] mark=0 zone=0 use=2
+udp      17 28 src=203.0.113.9 dst=192.168.1.5 sport=40000 dport=5353 [UNREPLIED] src=172.17.0.2 dst=203.0.113.9 sport=53 dport=40000 mark=0 use=1
+ipv4     2 icmp     1 29 src=172.17.0.2 dst=1.1.1.1 type=8 code=0 id=1 src=1.1.1.1 dst=172.17.0.2 type=0 code=0 id=1 mark=0 use=1
+conntrack v1.4.6 (conntrack-tools): 3 flow entries have been shown.
+";
+        let entries = parse(text);
+        assert_eq!(entries.len(), 2);
+
+        let ips: Vec<IpAddr> = vec!["172.17.0.2".parse().unwrap()];
+        assert_eq!(
+            entries[0].flow_for(&ips),
+            Some(Flow {
+                direction: Direction::Outbound,
+                proto: "tcp".to_string(),
+                peer: "93.184.216.34".parse().unwrap(),
+                port: 443,
+            })
+        );
+        // Published port 5353 maps to container port 53
+        assert_eq!(
+            entries[1].flow_for(&ips),
+            Some(Flow {
+                direction: Direction::Inbound,
+                proto: "udp".to_string(),
+                peer: "203.0.113.9".parse().unwrap(),
+                port: 53,
+            })
+        );
+        assert_eq!(entries[0].flow_for(&["10.0.0.1".parse().unwrap()]), None);
+
+        assert_eq!(
+            delete_args(&entries[0]).join(" "),
+            "-D -p tcp -s 172.17.0.2 -d 93.184.216.34 --sport 51234 --dport 443"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! The host's own addresses, for rules that allow or target `host`.
+//!
+//! Hosts on DHCP can change address at any time, so the current set is kept
+//! in [`HostAddrs`] and refreshed from netlink address events on Linux.
+
+#[cfg(target_os = "linux")]
+pub mod netlink;
+
+pub mod conntrack;
+
+#[cfg(target_os = "linux")]
+pub mod nflog;
+
+#[cfg(target_os = "linux")]
+pub mod vsock;
+
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+/// Interfaces whose addresses belong to Docker rather than the host
+const CONTAINER_INTERFACE_PREFIXES: [&str; 3] = ["docker", "br-", "veth"];
+
+/// Whether an address on `interface` counts as one of the host's own
+pub fn is_host_address(interface: &str, addr: &IpAddr) -> bool {
+    if CONTAINER_INTERFACE_PREFIXES
+        .iter()
+        .any(|prefix| interface.starts_with(prefix))
+    {
+        return false;
+    }
+
+    match addr {
+        IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_link_local() && !v4.is_unspecified(),
+        IpAddr::V6(v6) => !v6.is_loopback() && !v6.is_unspecified() && !v6.is_unicast_link_local(),
+    }
+}
+
+/// Last known set of host addresses
+#[derive(Debug, Default)]
+pub struct HostAddrs {
+    addrs: RwLock<Vec<IpAddr>>,
+}
+
+impl HostAddrs {
+    pub fn new(addrs: Vec<IpAddr>) -> Self {
+        let this = Self::default();
+        this.replace(addrs);
+        this
+    }
+
+    pub fn get(&self) -> Vec<IpAddr> {
+        self.addrs.read().map(|a| a.clone()).unwrap_or_default()
+    }
+
+    /// Store a new set, returning the previous one if it differs
+    pub fn replace(&self, mut addrs: Vec<IpAddr>) -> Option<Vec<IpAddr>> {
+        addrs.sort();
+        addrs.dedup();
+
+        let mut current = self.addrs.write().ok()?;
+        if *current == addrs {
+            return None;
+        }
+        Some(std::mem::replace(&mut *current, addrs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_host_address() {
+        let lan: IpAddr = "192.168.1.20".parse().unwrap();
+        assert!(is_host_address("eth0", &lan));
+        assert!(!is_host_address("docker0", &"172.17.0.1".parse().unwrap()));
+        assert!(!is_host_address(
+            "br-1a2b3c",
+            &"172.18.0.1".parse().unwrap()
+        ));
+        assert!(!is_host_address("lo", &"127.0.0.1".parse().unwrap()));
+        assert!(!is_host_address("eth0", &"fe80::1".parse().unwrap()));
+        assert!(is_host_address("eth0", &"2001:db8::20".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_replace_reports_changes() {
+        let addrs = HostAddrs::new(vec!["192.168.1.20".parse().unwrap()]);
+        assert!(
+            addrs
+                .replace(vec!["192.168.1.20".parse().unwrap()])
+                .is_none()
+        );
+
+        let previous = addrs.replace(vec!["192.168.1.31".parse().unwrap()]);
+        assert_eq!(previous, Some(vec!["192.168.1.20".parse().unwrap()]));
+        assert_eq!(addrs.get(), vec!["192.168.1.31".parse::<IpAddr>().unwrap()]);
+    }
+}
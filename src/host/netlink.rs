@@ -0,0 +1,189 @@
+//! Host address listing and rtnetlink address change notifications.
+
+use std::ffi::CStr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+use super::is_host_address;
+
+const NLMSG_HDRLEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+const RECV_BUFFER: usize = 16 * 1024;
+
+/// The host's own addresses, excluding loopback, link-local and Docker
+/// interfaces
+pub fn list_addresses() -> io::Result<Vec<IpAddr>> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs initialises the list on success; it is freed below
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addrs = Vec::new();
+    let mut cursor = ifaddrs;
+    while !cursor.is_null() {
+        // SAFETY: cursor walks the list returned by getifaddrs, which stays
+        // valid until freeifaddrs
+        let entry = unsafe { &*cursor };
+        cursor = entry.ifa_next;
+
+        if entry.ifa_addr.is_null() || entry.ifa_name.is_null() {
+            continue;
+        }
+        // SAFETY: ifa_name is a NUL-terminated interface name
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) }.to_string_lossy();
+        // SAFETY: ifa_addr is non-null and its family says which sockaddr
+        // variant it points to
+        let addr = unsafe {
+            match (*entry.ifa_addr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sin = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(entry.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            }
+        };
+
+        if is_host_address(&name, &addr) {
+            addrs.push(addr);
+        }
+    }
+
+    // SAFETY: ifaddrs came from a successful getifaddrs call
+    unsafe { libc::freeifaddrs(ifaddrs) };
+
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+/// Whether a netlink datagram carries an address being added or removed.
+/// An overrun means events were lost, so it counts as a change too.
+pub fn contains_address_event(buf: &[u8]) -> bool {
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= buf.len() {
+        let len = u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
+
+        if matches!(kind, libc::RTM_NEWADDR | libc::RTM_DELADDR)
+            || kind == libc::NLMSG_OVERRUN as u16
+        {
+            return true;
+        }
+        if len < NLMSG_HDRLEN {
+            break;
+        }
+        // Messages are 4-byte aligned
+        offset += (len + 3) & !3;
+    }
+    false
+}
+
+/// Subscription to IPv4 and IPv6 address changes
+pub struct AddressEvents {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl AddressEvents {
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: plain socket(2) call; the result is checked before use
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: raw is a freshly created descriptor nobody else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: sockaddr_nl is plain data, all-zero is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        // SAFETY: addr is a valid sockaddr_nl of the given length
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait until an address is added or removed on any interface
+    pub async fn changed(&self) -> io::Result<()> {
+        let mut buf = vec![0u8; RECV_BUFFER];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let received = guard.try_io(|fd| {
+                // SAFETY: buf is valid for writes of buf.len() bytes
+                let n = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+
+            match received {
+                Ok(Ok(n)) if contains_address_event(&buf[..n]) => return Ok(()),
+                Ok(Ok(_)) => {}
+                // The kernel dropped notifications; assume something changed
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: u16, payload: usize) -> Vec<u8> {
+        let len = (NLMSG_HDRLEN + payload) as u32;
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&len.to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.resize(NLMSG_HDRLEN + payload, 0);
+        msg.resize((msg.len() + 3) & !3, 0);
+        msg
+    }
+
+    #[test]
+    fn test_contains_address_event() {
+        assert!(contains_address_event(&message(libc::RTM_NEWADDR, 8)));
+        assert!(!contains_address_event(&message(libc::RTM_NEWLINK, 8)));
+
+        // Address event after an unrelated, unaligned message
+        let mut batch = message(libc::RTM_NEWLINK, 5);
+        batch.extend(message(libc::RTM_DELADDR, 8));
+        assert!(contains_address_event(&batch));
+
+        assert!(!contains_address_event(&[]));
+    }
+}
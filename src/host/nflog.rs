@@ -0,0 +1,366 @@
+//! Packets logged to an NFLOG group, read from a netfilter netlink socket.
+//!
+//! The socket binds the group and asks for the start of each packet, enough
+//! for its addresses and ports. Only one listener can bind a group, so a
+//! group already taken by e.g. ulogd fails to open.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+const NLMSG_HDRLEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+const NFGENMSG_LEN: usize = 4;
+const NLA_HDRLEN: usize = 4;
+/// Attribute types carry flags in their top bits
+const NLA_TYPE_MASK: u16 = 0x3fff;
+const RECV_BUFFER: usize = 64 * 1024;
+/// Bytes of each packet copied, enough for IPv6 and transport headers
+const COPY_RANGE: u32 = 128;
+
+const MSG_PACKET: u16 = ((libc::NFNL_SUBSYS_ULOG as u16) << 8) | libc::NFULNL_MSG_PACKET as u16;
+const MSG_CONFIG: u16 = ((libc::NFNL_SUBSYS_ULOG as u16) << 8) | libc::NFULNL_MSG_CONFIG as u16;
+
+/// A packet a rule logged to the group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedPacket {
+    /// Prefix of the log statement, e.g. `hs-web-abc123def456 DROP: `
+    pub prefix: String,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// `tcp`, `udp`, `icmp`, `icmpv6` or the protocol number
+    pub proto: String,
+    pub dport: Option<u16>,
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Netlink messages in a datagram, as (type, payload)
+fn messages(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while let (Some(len), Some(kind)) = (read_u32(buf, offset), read_u16(buf, offset + 4)) {
+        let len = len as usize;
+        if len < NLMSG_HDRLEN || offset + len > buf.len() {
+            break;
+        }
+        messages.push((kind, &buf[offset + NLMSG_HDRLEN..offset + len]));
+        offset += align(len);
+    }
+    messages
+}
+
+/// Addresses, protocol and destination port of an IPv4 or IPv6 packet
+fn parse_payload(payload: &[u8]) -> Option<(IpAddr, IpAddr, u8, Option<u16>)> {
+    let (src, dst, proto, transport) = match payload.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(payload[0] & 0x0f) * 4;
+            let src: [u8; 4] = payload.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = payload.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                *payload.get(9)?,
+                payload.get(header_len..),
+            )
+        }
+        6 => {
+            let src: [u8; 16] = payload.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = payload.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                *payload.get(6)?,
+                payload.get(40..),
+            )
+        }
+        _ => return None,
+    };
+    let dport = match proto {
+        6 | 17 => transport
+            .and_then(|t| t.get(2..4))
+            .map(|port| u16::from_be_bytes([port[0], port[1]])),
+        _ => None,
+    };
+    Some((src, dst, proto, dport))
+}
+
+fn protocol_name(proto: u8) -> String {
+    match proto {
+        1 => "icmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        58 => "icmpv6".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// The logged packets in a netlink datagram
+pub fn parse_packets(buf: &[u8]) -> Vec<LoggedPacket> {
+    let mut packets = Vec::new();
+    for (_, message) in messages(buf).into_iter().filter(|(k, _)| *k == MSG_PACKET) {
+        let mut prefix = String::new();
+        let mut payload = None;
+        let mut offset = NFGENMSG_LEN;
+        while let (Some(len), Some(kind)) =
+            (read_u16(message, offset), read_u16(message, offset + 2))
+        {
+            let len = usize::from(len);
+            if len < NLA_HDRLEN || offset + len > message.len() {
+                break;
+            }
+            let value = &message[offset + NLA_HDRLEN..offset + len];
+            match i32::from(kind & NLA_TYPE_MASK) {
+                libc::NFULA_PREFIX => {
+                    let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+                    prefix = String::from_utf8_lossy(&value[..end]).into_owned();
+                }
+                libc::NFULA_PAYLOAD => payload = Some(value),
+                _ => {}
+            }
+            offset += align(len);
+        }
+
+        if let Some((src, dst, proto, dport)) = payload.and_then(parse_payload) {
+            packets.push(LoggedPacket {
+                prefix,
+                src,
+                dst,
+                proto: protocol_name(proto),
+                dport,
+            });
+        }
+    }
+    packets
+}
+
+/// A config request for `group` carrying one attribute
+fn config_message(group: u16, seq: u32, attr: i32, value: &[u8]) -> Vec<u8> {
+    let attr_len = NLA_HDRLEN + value.len();
+    let len = NLMSG_HDRLEN + NFGENMSG_LEN + align(attr_len);
+    let mut msg = Vec::with_capacity(len);
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&MSG_CONFIG.to_ne_bytes());
+    msg.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16).to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // nfgenmsg: family, version, resource id (the group, big endian)
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    msg.extend_from_slice(&group.to_be_bytes());
+    msg.extend_from_slice(&(attr_len as u16).to_ne_bytes());
+    msg.extend_from_slice(&(attr as u16).to_ne_bytes());
+    msg.extend_from_slice(value);
+    msg.resize(len, 0);
+    msg
+}
+
+/// The error an acknowledgement reports, if any
+fn ack_error(buf: &[u8]) -> Option<io::Error> {
+    messages(buf)
+        .into_iter()
+        .filter(|(kind, _)| *kind == libc::NLMSG_ERROR as u16)
+        .find_map(|(_, payload)| {
+            let code = read_u32(payload, 0)? as i32;
+            (code != 0).then(|| io::Error::from_raw_os_error(-code))
+        })
+}
+
+/// Subscription to the packets logged to one NFLOG group
+pub struct NflogSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl NflogSocket {
+    pub fn open(group: u16) -> io::Result<Self> {
+        // Blocking until configured, so the acknowledgements can be awaited
+        // SAFETY: plain socket(2) call; the result is checked before use
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_NETFILTER,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: raw is a freshly created descriptor nobody else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: sockaddr_nl is plain data, all-zero is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // SAFETY: addr is a valid sockaddr_nl of the given length
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut mode = COPY_RANGE.to_be_bytes().to_vec();
+        mode.extend_from_slice(&[libc::NFULNL_COPY_PACKET as u8, 0]);
+        let requests = [
+            config_message(
+                group,
+                1,
+                libc::NFULA_CFG_CMD,
+                &[libc::NFULNL_CFG_CMD_BIND as u8],
+            ),
+            config_message(group, 2, libc::NFULA_CFG_MODE, &mode),
+        ];
+        let mut buf = vec![0u8; 4096];
+        for request in requests {
+            // SAFETY: request is valid for reads of request.len() bytes
+            let sent = unsafe {
+                libc::send(
+                    fd.as_raw_fd(),
+                    request.as_ptr() as *const libc::c_void,
+                    request.len(),
+                    0,
+                )
+            };
+            if sent < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: buf is valid for writes of buf.len() bytes
+            let n = unsafe {
+                libc::recv(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(e) = ack_error(&buf[..n as usize]) {
+                return Err(e);
+            }
+        }
+
+        // SAFETY: fcntl on a descriptor we own
+        let rc = unsafe {
+            let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK)
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait for the next batch of logged packets. Returns none when the
+    /// kernel dropped some because they weren't read fast enough
+    pub async fn packets(&self) -> io::Result<Vec<LoggedPacket>> {
+        let mut buf = vec![0u8; RECV_BUFFER];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let received = guard.try_io(|fd| {
+                // SAFETY: buf is valid for writes of buf.len() bytes
+                let n = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+
+            match received {
+                Ok(Ok(n)) => return Ok(parse_packets(&buf[..n])),
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => return Ok(Vec::new()),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(kind: i32, value: &[u8]) -> Vec<u8> {
+        let mut attr = Vec::new();
+        attr.extend_from_slice(&((NLA_HDRLEN + value.len()) as u16).to_ne_bytes());
+        attr.extend_from_slice(&(kind as u16).to_ne_bytes());
+        attr.extend_from_slice(value);
+        attr.resize(align(attr.len()), 0);
+        attr
+    }
+
+    fn packet_message(prefix: &str, payload: &[u8]) -> Vec<u8> {
+        let mut body = vec![libc::AF_INET as u8, 0, 0, 5];
+        body.extend(attr(libc::NFULA_PREFIX, format!("{}\0", prefix).as_bytes()));
+        body.extend(attr(libc::NFULA_PAYLOAD, payload));
+        let len = NLMSG_HDRLEN + body.len();
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&MSG_PACKET.to_ne_bytes());
+        msg.resize(NLMSG_HDRLEN, 0);
+        msg.extend(body);
+        msg
+    }
+
+    #[test]
+    fn test_parse_packets() {
+        // IPv4 TCP from 203.0.113.9:40000 to 172.17.0.2:8080
+        let mut ipv4 = vec![0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0];
+        ipv4.extend([203, 0, 113, 9, 172, 17, 0, 2]);
+        ipv4.extend([0x9c, 0x40, 0x1f, 0x90]);
+        let mut batch = packet_message("hs-web-abc123def456 DROP: ", &ipv4);
+
+        // IPv6 ICMP after it, with no destination port
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 8, 58, 64];
+        ipv6.extend(Ipv6Addr::LOCALHOST.octets());
+        ipv6.extend("fd00::2".parse::<Ipv6Addr>().unwrap().octets());
+        batch.extend(packet_message("hs-db-000111222333 QUARANTINE: ", &ipv6));
+
+        let packets = parse_packets(&batch);
+        assert_eq!(
+            packets[0],
+            LoggedPacket {
+                prefix: "hs-web-abc123def456 DROP: ".to_string(),
+                src: "203.0.113.9".parse().unwrap(),
+                dst: "172.17.0.2".parse().unwrap(),
+                proto: "tcp".to_string(),
+                dport: Some(8080),
+            }
+        );
+        assert_eq!(packets[1].proto, "icmpv6");
+        assert_eq!(packets[1].dst, "fd00::2".parse::<IpAddr>().unwrap());
+        assert_eq!(packets[1].dport, None);
+
+        // A config request for group 5 and a truncated datagram
+        let request = config_message(5, 1, libc::NFULA_CFG_CMD, &[1]);
+        assert_eq!(request.len(), 28);
+        assert_eq!(&request[18..20], &[0, 5]);
+        assert!(parse_packets(&request).is_empty());
+        assert!(parse_packets(&batch[..10]).is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! Minimal `AF_VSOCK` stream sockets for the admin listener.
+//!
+//! vsock connects a VM and its hypervisor without any network between them,
+//! so an instance running in a management VM can be administered from the
+//! host (`socat - VSOCK-CONNECT:<cid>:<port>`) without exposing TCP.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Accept connections from any CID
+pub const CID_ANY: u32 = libc::VMADDR_CID_ANY;
+
+/// Pending connections the kernel queues before `accept`
+const BACKLOG: libc::c_int = 128;
+
+fn check(rc: libc::c_int) -> io::Result<libc::c_int> {
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(rc)
+    }
+}
+
+pub struct VsockListener {
+    fd: AsyncFd<OwnedFd>,
+    cid: u32,
+    port: u32,
+}
+
+impl VsockListener {
+    pub fn bind(cid: u32, port: u32) -> io::Result<Self> {
+        // SAFETY: plain socket(2) call; the result is checked before use
+        let raw = check(unsafe {
+            libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        })?;
+        // SAFETY: raw is a freshly created descriptor nobody else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: sockaddr_vm is plain data, all-zero is a valid value
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_cid = cid;
+        addr.svm_port = port;
+        // SAFETY: addr is a valid sockaddr_vm of the given length
+        check(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        })?;
+        // SAFETY: fd is a bound stream socket
+        check(unsafe { libc::listen(fd.as_raw_fd(), BACKLOG) })?;
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            cid,
+            port,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        if self.cid == CID_ANY {
+            format!("vsock:{}", self.port)
+        } else {
+            format!("vsock:{}:{}", self.cid, self.port)
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<VsockStream> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let accepted = guard.try_io(|fd| {
+                // SAFETY: peer address is not requested, so null is allowed
+                check(unsafe {
+                    libc::accept4(
+                        fd.as_raw_fd(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                        libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    )
+                })
+            });
+            match accepted {
+                Ok(Ok(raw)) => {
+                    // SAFETY: accept4 returned a new descriptor we now own
+                    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+                    return Ok(VsockStream {
+                        fd: AsyncFd::new(fd)?,
+                    });
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+/// An accepted vsock connection
+pub struct VsockStream {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl AsyncRead for VsockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            let received = guard.try_io(|fd| {
+                // SAFETY: unfilled is valid for writes of its length
+                let n = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        unfilled.as_mut_ptr() as *mut libc::c_void,
+                        unfilled.len(),
+                        0,
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match received {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+impl AsyncWrite for VsockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.fd.poll_write_ready(cx))?;
+            let sent = guard.try_io(|fd| {
+                // SAFETY: buf is valid for reads of its length
+                let n = unsafe {
+                    libc::send(
+                        fd.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void,
+                        buf.len(),
+                        libc::MSG_NOSIGNAL,
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match sent {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => {}
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // SAFETY: the descriptor stays open until the stream is dropped
+        let rc = unsafe { libc::shutdown(self.fd.as_raw_fd(), libc::SHUT_WR) };
+        Poll::Ready(check(rc).map(|_| ()))
+    }
+}
@@ -0,0 +1,134 @@
+# Messages printed by the harborshield CLI, in Fluent syntax. A translation
+# copies this file, keeps each id and its { $placeholders }, and is loaded
+# with --messages. Table headers are translated by adding `header-<name>`
+# ids, e.g. `header-container = CONTENEUR`; untranslated ids stay English.
+
+## Shared by several commands
+
+db-open-failed = Failed to open database: { $error }
+docker-connect-failed = Failed to connect to { $runtime }: { $error }
+nft-ruleset-read-failed = Failed to read nftables ruleset: { $error }
+nft-counters-read-failed = Failed to read nftables counters: { $error }
+enforcement-read-failed = Failed to read enforcement mode: { $error }
+
+## stats, audit and capacity
+
+stats-query-failed = Failed to query stats: { $error }
+audit-project-lookup-failed = Failed to look up project { $project }: { $error }
+audit-more-entries = Showing { $shown } of { $total } entries, use --offset { $next } for more
+audit-read-failed = Failed to read audit log: { $error }
+capacity-trend-unavailable = Failed to open database, trend unavailable: { $error }
+
+## examples
+
+example-schema-mismatch = Example does not match the current rule schema: { $error }
+example-serialize-failed = Failed to serialize example: { $error }
+
+## enforcement, release, learn, apply-adhoc, suggest and bans
+
+enforcement-set-failed = Failed to set enforcement mode: { $error }
+enforcement-list-failed = Failed to list enforcement modes: { $error }
+release-not-quarantined = Container { $container } is not quarantined
+release-failed = Failed to release { $container }: { $error }
+release-record-failed = Failed to record the release: { $error }
+learn-update-failed = Failed to update learning mode: { $error }
+learn-list-failed = Failed to list learning sessions: { $error }
+adhoc-read-failed = Failed to read rules from { $source }: { $error }
+adhoc-invalid-rules = Invalid rules: { $error }
+adhoc-zero-ttl = --ttl must be longer than zero
+adhoc-apply-failed = Failed to store ad-hoc rules: { $error }
+ban-zero-duration = --duration must be longer than zero
+ban-failed = Failed to store the ban: { $error }
+ban-not-found = { $ip } is not banned
+unban-failed = Failed to lift the ban: { $error }
+bans-list-failed = Failed to list bans: { $error }
+panic-failed = Failed to engage the kill switch: { $error }
+panic-install-failed = Kill switch stored, but installing it failed: { $error }; traffic is not cut off until a running daemon installs it
+panic-not-engaged = The kill switch is not engaged
+panic-release-failed = Failed to release the kill switch: { $error }
+panic-uninstall-failed = Kill switch released, but removing its table failed: { $error }; a running daemon retries within a second
+frozen-refused = Rule changes are frozen; end the freeze with `harborshield unfreeze --confirm` first
+freeze-read-failed = Failed to read the change freeze: { $error }
+freeze-failed = Failed to start the change freeze: { $error }
+unfreeze-not-frozen = Rule changes are not frozen
+unfreeze-failed = Failed to end the change freeze: { $error }
+unfreeze-confirm-hint = Run `harborshield unfreeze --confirm` to end the freeze and apply these changes
+suggest-flows-failed = Failed to read observed flows: { $error }
+suggest-serialize-failed = Failed to serialize suggestion: { $error }
+suggest-still-learning = { $container } is still learning until { $until }
+
+## doctor, flush and top
+
+doctor-inspect-failed = Failed to inspect containers: { $error }
+flush-read-failed = Failed to read the filter tables: { $error }
+flush-remove-failed = Failed to remove harborshield objects: { $error }
+flush-reset-failed = Failed to reset container state: { $error }
+top-names-unavailable = Failed to open database, showing chain names: { $error }
+top-events-unavailable = Failed to connect to Docker, events unavailable: { $error }
+top-serialize-failed = Failed to serialize view: { $error }
+
+## harborshield-wait
+
+wait-no-container = No --container given and no hostname to use
+wait-connect-failed = Failed to reach harborshield at { $addr }: { $error }
+wait-timed-out = Rules of { $container } are not active after { $timeout }
+wait-exec-failed = Failed to run { $program }: { $error }
+
+## report
+
+report-containers-unavailable = Failed to list containers, rule sets not validated: { $error }
+report-failed = Failed to build the report: { $error }
+report-write-failed = Failed to write the report to { $dir }: { $error }
+events-export-failed = Failed to export events: { $error }
+events-export-terminal = Parquet is binary; pass --file or redirect stdout
+db-backup-failed = Failed to back up the database: { $error }
+db-export-failed = Failed to export the database: { $error }
+db-schema-failed = Failed to describe the database schema: { $error }
+db-restore-failed = Failed to restore the database: { $error }
+db-restore-checked = { $path } is restorable at schema version { $version } with { $pending } migrations to apply; pass --confirm to replace the database
+
+## unused
+
+unused-read-failed = Failed to read rule activity: { $error }
+
+## validate
+
+validate-read-failed = Failed to read { $source }: { $error }
+
+## adopt
+
+adopt-read-failed = Failed to read the ruleset { $source }: { $error }
+adopt-containers-failed = Failed to look up container addresses, give them with --container: { $error }
+
+## plan
+
+plan-database-failed = Failed to copy the database for the plan: { $error }
+plan-read-failed = Failed to read the live ruleset: { $error }
+plan-failed = Failed to plan the ruleset: { $error }
+plan-no-changes = No changes: the live ruleset already matches the current containers
+plan-unsimulated = not simulated, would run: { $call }
+
+## Starting the daemon
+
+env-load-failed = Error loading .env file: { $error }
+runtime-start-failed = Failed to start the async runtime: { $error }
+nft-thread-failed = Failed to start the nft thread: { $error }
+applier-given-to-applier = --applier cannot be given to the applier itself
+applier-failed = Applier failed on { $socket }: { $error }
+db-key-load-failed = Failed to load database key: { $error }
+profiles-load-failed = Failed to load profiles: { $error }
+capability-check-failed = Capability check failed: { $error }
+data-dir-failed = Cannot use the data directory: { $error }
+guests-load-failed = Failed to load guests: { $error }
+sinkhole-load-failed = Failed to read sinkhole list { $path }: { $error }
+blocklists-load-failed = Failed to load blocklists: { $error }
+rules-dir-load-failed = Failed to read rules directory { $path }: { $error }
+api-tokens-load-failed = Failed to load API tokens: { $error }
+update-check-failed = Failed to enable update checks: { $error }
+handlers-init-failed = Failed to initialize rule handlers: { $error }
+restrictions-failed = Failed to apply security restrictions: { $error }
+clear-failed = Failed to clear rules: { $error }
+handlers-start-failed = Failed to start rule handlers: { $error }
+reload-signal-failed = Failed to listen for SIGHUP, reloading is disabled: { $error }
+reload-guests-failed = Failed to reload guests, keeping the current ones: { $error }
+reload-failed = Reload failed: { $error }
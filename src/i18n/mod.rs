@@ -0,0 +1,240 @@
+//! Message catalog for CLI output.
+//!
+//! User-facing messages are looked up by id, so a translation can replace
+//! them without wrapping the binary. The built-in English catalog is
+//! `en.ftl`; `--messages` loads a translation in the same Fluent syntax
+//! (single messages with `{ $name }` placeables, no selectors or terms),
+//! and embedders can install their own [`Localizer`] instead. Ids a
+//! localizer doesn't know fall back to English, then to the id itself.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+use thiserror::Error;
+
+/// Falls back to this when `--messages` isn't given
+pub const MESSAGES_ENV: &str = "HARBORSHIELD_MESSAGES";
+
+static ENGLISH: LazyLock<Catalog> =
+    LazyLock::new(|| Catalog::parse(include_str!("en.ftl")).unwrap_or_default());
+
+static LOCALIZER: RwLock<Option<Box<dyn Localizer>>> = RwLock::new(None);
+
+/// Named values substituted into a message
+pub type Args<'a> = [(&'a str, &'a dyn Display)];
+
+/// Source of translated messages
+pub trait Localizer: Send + Sync {
+    /// The message for `id` with `args` filled in, or `None` to fall back
+    fn message(&self, id: &str, args: &Args<'_>) -> Option<String>;
+}
+
+#[derive(Error, Debug)]
+pub enum CatalogError {
+    #[error("Failed to read message catalog: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid message catalog at line {line}: {message}")]
+    Syntax { line: usize, message: String },
+}
+
+/// Messages parsed from a Fluent file
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+fn valid_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Catalog {
+    pub fn parse(source: &str) -> Result<Self, CatalogError> {
+        let mut messages: HashMap<String, String> = HashMap::new();
+        let mut current: Option<String> = None;
+
+        for (number, line) in source.lines().enumerate() {
+            let syntax = |message: &str| CatalogError::Syntax {
+                line: number + 1,
+                message: message.to_string(),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                current = None;
+                continue;
+            }
+            if line.starts_with([' ', '\t']) {
+                // An indented line continues the previous message
+                let id = current
+                    .as_ref()
+                    .ok_or_else(|| syntax("indented line outside a message"))?;
+                let value = messages.entry(id.clone()).or_default();
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+                continue;
+            }
+            let (id, value) = line
+                .split_once('=')
+                .ok_or_else(|| syntax("expected `id = message`"))?;
+            let id = id.trim();
+            if !valid_id(id) {
+                return Err(syntax(&format!("invalid message id '{}'", id)));
+            }
+            messages.insert(id.to_string(), value.trim().to_string());
+            current = Some(id.to_string());
+        }
+
+        Ok(Self { messages })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CatalogError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// `--messages`, or the file `HARBORSHIELD_MESSAGES` names
+    pub fn load_configured(path: Option<&Path>) -> Result<Option<Self>, CatalogError> {
+        match path {
+            Some(path) => Self::load(path).map(Some),
+            None => match std::env::var_os(MESSAGES_ENV) {
+                Some(path) => Self::load(Path::new(&path)).map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+/// Replace `{ $name }` with its argument and `{ "text" }` with the text.
+/// Unknown placeables are kept as written
+fn format(template: &str, args: &Args<'_>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let placeable = &rest[start..start + end + 1];
+        let inner = placeable[1..placeable.len() - 1].trim();
+        let argument = inner
+            .strip_prefix('$')
+            .and_then(|name| args.iter().find(|(arg, _)| *arg == name));
+        match argument {
+            Some((_, value)) => out.push_str(&value.to_string()),
+            None => match inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(literal) => out.push_str(literal),
+                None => out.push_str(placeable),
+            },
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Localizer for Catalog {
+    fn message(&self, id: &str, args: &Args<'_>) -> Option<String> {
+        self.messages.get(id).map(|template| format(template, args))
+    }
+}
+
+/// Use `localizer` for every later message; `None` restores English
+pub fn set_localizer(localizer: Option<Box<dyn Localizer>>) {
+    if let Ok(mut current) = LOCALIZER.write() {
+        *current = localizer;
+    }
+}
+
+fn localized(id: &str, args: &Args<'_>) -> Option<String> {
+    LOCALIZER
+        .read()
+        .ok()?
+        .as_ref()
+        .and_then(|localizer| localizer.message(id, args))
+}
+
+/// The message for `id` in the configured language
+pub fn message(id: &str, args: &Args<'_>) -> String {
+    localized(id, args)
+        .or_else(|| ENGLISH.message(id, args))
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// A table header, translated when the localizer has `header-<name>`
+pub fn header(text: &'static str) -> Cow<'static, str> {
+    let id = format!("header-{}", text.to_lowercase().replace([' ', '_'], "-"));
+    match localized(&id, &[]) {
+        Some(translated) => Cow::Owned(translated),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Look up a message with named arguments:
+/// `tr!("release-failed", container = name, error = e)`
+#[macro_export]
+macro_rules! tr {
+    ($id:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::message(
+            $id,
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),*],
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog() {
+        let catalog = Catalog::parse(
+            "# Deutsch\n\
+             release-failed = { $container } konnte nicht freigegeben werden: { $error }\n\
+             header-container = CONTAINER\n\
+             braces = { \"{\" }{ $missing }\n\
+             multi = Erste Zeile\n    zweite Zeile\n",
+        )
+        .unwrap();
+        assert_eq!(catalog.len(), 4);
+        assert_eq!(
+            catalog
+                .message("release-failed", &[("container", &"web"), ("error", &42)])
+                .as_deref(),
+            Some("web konnte nicht freigegeben werden: 42")
+        );
+        assert_eq!(
+            catalog.message("braces", &[]).as_deref(),
+            Some("{{ $missing }")
+        );
+        assert_eq!(
+            catalog.message("multi", &[]).as_deref(),
+            Some("Erste Zeile\nzweite Zeile")
+        );
+
+        assert!(matches!(
+            Catalog::parse("ok = fine\nnot a message"),
+            Err(CatalogError::Syntax { line: 2, .. })
+        ));
+
+        // Every English message parses and ids fall back to themselves
+        assert!(ENGLISH.len() > 40);
+        assert_eq!(
+            tr!("release-not-quarantined", container = "web"),
+            "Container web is not quarantined"
+        );
+        assert_eq!(message("no-such-message", &[]), "no-such-message");
+    }
+}
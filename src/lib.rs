@@ -1,23 +1,54 @@
+pub mod about;
+pub mod access;
+pub mod adopt;
+pub mod blocklist;
+pub mod bus;
+pub mod check;
+pub mod dashboard;
 pub mod database;
+pub mod datadir;
+pub mod dns;
 pub mod docker;
+pub mod doctor;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod grpc;
+pub mod guests;
 pub mod handlers;
+pub mod host;
+pub mod i18n;
+pub mod listing;
+pub mod low_memory;
 pub mod nftables;
+pub mod offline;
+pub mod output;
+pub mod report;
+pub mod runtime;
 #[cfg(target_os = "linux")]
 pub mod security;
 pub mod server;
+pub mod status;
+pub mod systemd;
+pub mod top;
+pub mod update;
+pub mod validate;
+pub mod web;
 
 use crate::{
     database::DB,
-    docker::DockerClient,
     handlers::cleanup::CleanupTracker,
     nftables::{FILTER_TABLE, NftablesClient},
+    runtime::{ContainerRuntime, RuntimeKind},
 };
 use bon::bon;
 pub use error::{Error, Result};
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex as StdMutex;
+use std::sync::RwLock as StdRwLock;
 use std::time::Duration;
 use tokio::signal;
 use tokio::sync::{Mutex, mpsc};
@@ -25,14 +56,57 @@ use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
-pub const ENABLED_LABEL: &str = "harborshield.enabled";
-pub const RULES_LABEL: &str = "harborshield.rules";
+pub use harborshield_core::{ENABLED_LABEL, RULES_LABEL, parse_duration, tz};
+
+/// How often the nft watchdog checks for overdue invocations
+const NFT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often chain counters are sampled and stats rolled up
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often pending rdns sources are verified
+const RDNS_VERIFY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often stored enforcement modes are checked for changes
+const ENFORCEMENT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often stored ad-hoc rules are checked for new or expired sets
+const ADHOC_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often stored bans are checked for new, lifted or expired ones
+const BAN_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the stored kill switch is checked, and the containers it cuts
+/// off for new ones
+const PANIC_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the stored change freeze is checked for a start or end
+const FREEZE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the filter table is checked for rules removed or changed by
+/// others
+const SELF_HEAL_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often time-window rules are checked for opening or closing
+const TIME_WINDOW_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often containers in a restart loop are checked for held rules to
+/// remove and deferred renders
+const RESTART_LOOP_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often libvirt leases are re-read for guests located by MAC
+const GUEST_LEASE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often conntrack is sampled for containers in learning mode
+const LEARNING_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
 pub struct Harborshield {
-    docker_client: Arc<DockerClient>,
+    docker_client: Arc<dyn ContainerRuntime>,
     nftables_client: Arc<Mutex<NftablesClient>>,
     db: Arc<Mutex<DB>>,
+    /// Exclusive lock on the database, see [`database::backup`]
+    _db_lock: Arc<database::backup::DbLock>,
     shutdown_tx: mpsc::Sender<()>,
     shutdown_rx: Arc<Mutex<mpsc::Receiver<()>>>,
     task_handles: Arc<StdMutex<Vec<JoinHandle<()>>>>,
@@ -40,6 +114,46 @@ pub struct Harborshield {
     start_time: chrono::DateTime<chrono::Utc>,
     cleanup_tracker: Arc<CleanupTracker>,
     cancellation_token: CancellationToken,
+    /// Addresses substituted for `host` in rules
+    host_addrs: Arc<host::HostAddrs>,
+    /// Docker events buffered between the reader and the handlers
+    event_queue_capacity: usize,
+    /// When to run the nightly reconcile; disabled when unset
+    reconcile_schedule: Option<handlers::reconcile::ReconcileSchedule>,
+    /// When and where to deliver the daily report; disabled when unset
+    report_schedule: Option<handlers::report::ReportSchedule>,
+    /// Release manifest to check for updates; disabled when unset
+    update_check: Option<update::UpdateCheck>,
+    /// URL a notice is POSTed to when a container is quarantined
+    quarantine_webhook: Option<String>,
+    /// NATS or MQTT broker that rule, traffic and audit events are published to
+    event_bus: Option<bus::BusConfig>,
+    /// libvirt guests protected alongside containers, replaced on reload
+    guests: Arc<StdRwLock<Vec<guests::GuestSpec>>>,
+    /// Socket of the admin gRPC service, taken when it starts serving
+    grpc_listener: Arc<StdMutex<Option<grpc::AdminListener>>>,
+    /// Taken by the web dashboard once started
+    web_listener: Arc<StdMutex<Option<web::WebListener>>>,
+    /// Required on admin API calls when set
+    api_tokens: Option<access::Tokens>,
+    /// How long an allow rule may match nothing before a notice is
+    /// recorded; disabled when unset
+    unused_allow_after: Option<Duration>,
+    /// How often a sample of allow rules is checked against conntrack;
+    /// disabled when unset
+    validate_rules_every: Option<Duration>,
+    /// NFLOG group container chains log dropped packets to, read back to
+    /// count them per container; dropped packets go to the kernel log when
+    /// unset
+    nflog_group: Option<u16>,
+    /// Lock held while this host enforces; always enforcing when unset
+    ha_lock: Option<Arc<handlers::leader::LeaderLock>>,
+    /// Domain blocklists whose addresses no container may reach
+    sinkhole: Vec<PathBuf>,
+    /// Threat feeds of networks no container may talk to
+    blocklists: Option<blocklist::Blocklists>,
+    /// Directory of per-container rule set files, watched for changes
+    rules_dir: Option<PathBuf>,
 }
 
 #[bon]
@@ -48,14 +162,106 @@ impl Harborshield {
     pub async fn new(
         db_path: &Path,
         timeout: Duration,
+        runtime: Option<RuntimeKind>,
+        /// Docker daemons watched next to the primary one
+        endpoints: Option<&[runtime::fleet::Endpoint]>,
         health_server_addr: Option<&str>,
+        admin_endpoints: Option<server::Endpoints>,
+        nft_timeout: Option<Duration>,
+        label_prefixes: Option<&[String]>,
+        flowtable_devices: Option<&[String]>,
+        /// Delete conntrack entries of connections a rule change revoked
+        flush_revoked_flows: Option<bool>,
+        identity_mode: Option<docker::identity::IdentityMode>,
+        event_queue_capacity: Option<usize>,
+        reconcile_schedule: Option<handlers::reconcile::ReconcileSchedule>,
+        report_schedule: Option<handlers::report::ReportSchedule>,
+        update_check: Option<update::UpdateCheck>,
+        quarantine_webhook: Option<String>,
+        event_bus: Option<bus::BusConfig>,
+        guests: Option<Vec<guests::GuestSpec>>,
+        api_tokens: Option<access::Tokens>,
+        grpc_addr: Option<server::ListenAddr>,
+        /// Where the read-only dashboard is served; see [`web`]
+        web_addr: Option<server::ListenAddr>,
+        unused_allow_after: Option<Duration>,
+        /// See [`handlers::sampling`]
+        validate_rules_every: Option<Duration>,
+        nflog_group: Option<u16>,
+        /// Lock file shared with a standby host; see [`handlers::leader`]
+        ha_lock: Option<&Path>,
+        /// Blocklists; see [`dns::sinkhole`]
+        sinkhole: Option<Vec<PathBuf>>,
+        /// Threat feeds; see [`blocklist`]
+        blocklists: Option<blocklist::Blocklists>,
+        /// Rule set files by container; see [`docker::config::files`]
+        rules_dir: Option<&Path>,
     ) -> Result<Self> {
-        let docker_client = Arc::new(DockerClient::builder().timeout_duration(timeout).build()?);
-        let mut nftables_client = NftablesClient::builder().build();
+        if let Some(nft_timeout) = nft_timeout {
+            nftables::runner::set_nft_timeout(nft_timeout);
+        }
+        if let Some(prefixes) = label_prefixes {
+            docker::labels::set_label_prefixes(prefixes);
+        }
+        if let Some(mode) = identity_mode {
+            docker::identity::set_identity_mode(mode);
+        }
+        if let Some(devices) = flowtable_devices {
+            nftables::flowtable::set_devices(devices.to_vec());
+        }
+        nftables::nflog::set_group(nflog_group);
+        if let Some(flush) = flush_revoked_flows {
+            handlers::revoke::set_enabled(flush);
+        }
+        let sinkhole = sinkhole.unwrap_or_default();
+        nftables::sinkhole::set_enabled(!sinkhole.is_empty());
+        nftables::blocklist::set_enabled(blocklists.is_some());
+        // Read before the first sync, which parses the containers' rules
+        if let Some(dir) = rules_dir {
+            docker::config::files::set_files(docker::config::files::load_dir(dir)?);
+        }
+
+        // A standby changes nothing, base chains included
+        let ha_lock = match ha_lock {
+            Some(path) => {
+                let lock = handlers::leader::LeaderLock::open(path)?;
+                handlers::leader::start(&lock);
+                Some(Arc::new(lock))
+            }
+            None => None,
+        };
+
+        let cancellation_token = CancellationToken::new();
+
+        let docker_client = runtime::connect_with_endpoints(
+            runtime.unwrap_or_default(),
+            timeout,
+            endpoints.unwrap_or_default(),
+        )?;
+        let mut nftables_client = NftablesClient::builder()
+            .cancellation_token(cancellation_token.clone())
+            .build();
+        match docker_client.security_options().await {
+            Ok(options) if docker::rootless::detect(&options) => {
+                warn!(
+                    "{} is running rootless: only published ports are filtered, other rule features are reported as unavailable in /status",
+                    docker_client.kind()
+                );
+                docker::rootless::set_rootless(true);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to read {} security options: {}",
+                docker_client.kind(),
+                e
+            ),
+        }
         // Enable NAT support for localhost mapped port gateway handling
         nftables_client.init_base_chains().await?;
         let nftables_client = Arc::new(Mutex::new(nftables_client));
 
+        // Held until exit, so `db restore` can't swap the database meanwhile
+        let db_lock = Arc::new(database::backup::DbLock::acquire(db_path).await?);
         let db = Arc::new(Mutex::new(DB::builder().db_path(db_path).build().await?));
 
         let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
@@ -64,11 +270,34 @@ impl Harborshield {
         // Setup metrics
         let prometheus_handle = server::setup_metrics()?;
 
+        #[cfg(target_os = "linux")]
+        let host_addrs = host::netlink::list_addresses().unwrap_or_else(|e| {
+            warn!(
+                "Failed to list host addresses, rules using host match nothing: {}",
+                e
+            );
+            Vec::new()
+        });
+        #[cfg(not(target_os = "linux"))]
+        let host_addrs = Vec::new();
+        let host_addrs = Arc::new(host::HostAddrs::new(host_addrs));
+
         // Start health server if requested
         let health_server_handle = if let Some(addr) = health_server_addr {
-            let health_server =
+            let mut health_server =
                 server::HealthServer::new(addr, prometheus_handle, crate::VERSION.to_string())
-                    .await?;
+                    .await?
+                    .with_db(db.clone())
+                    .with_endpoints(admin_endpoints.unwrap_or_default())
+                    .with_docker(docker_client.clone())
+                    .with_host_addrs(host_addrs.clone());
+            if let Some(tokens) = api_tokens.clone() {
+                info!(
+                    "Enforcement endpoint requires one of {} tokens",
+                    tokens.len()
+                );
+                health_server = health_server.with_tokens(tokens);
+            }
 
             let handle = tokio::spawn(async move {
                 if let Err(e) = health_server.serve().await {
@@ -80,14 +309,22 @@ impl Harborshield {
             None
         };
 
-        let cleanup_tracker = Arc::new(CleanupTracker::builder().db(db.clone()).build());
+        let grpc_listener = match &grpc_addr {
+            Some(addr) => Some(grpc::AdminListener::bind(addr).await?),
+            None => None,
+        };
+        let web_listener = match &web_addr {
+            Some(addr) => Some(web::WebListener::bind(addr).await?),
+            None => None,
+        };
 
-        let cancellation_token = CancellationToken::new();
+        let cleanup_tracker = Arc::new(CleanupTracker::builder().db(db.clone()).build());
 
         let handlers = Self {
             docker_client,
             nftables_client,
             db,
+            _db_lock: db_lock,
             shutdown_tx,
             shutdown_rx,
             task_handles: Arc::new(StdMutex::new(Vec::new())),
@@ -95,17 +332,41 @@ impl Harborshield {
             start_time: chrono::Utc::now(),
             cleanup_tracker,
             cancellation_token,
+            host_addrs,
+            event_queue_capacity: event_queue_capacity
+                .unwrap_or(handlers::pipeline::DEFAULT_EVENT_QUEUE_CAPACITY),
+            reconcile_schedule,
+            report_schedule,
+            update_check,
+            quarantine_webhook,
+            event_bus,
+            guests: Arc::new(StdRwLock::new(guests.unwrap_or_default())),
+            grpc_listener: Arc::new(StdMutex::new(grpc_listener)),
+            web_listener: Arc::new(StdMutex::new(web_listener)),
+            api_tokens,
+            unused_allow_after,
+            validate_rules_every,
+            nflog_group,
+            ha_lock,
+            sinkhole,
+            blocklists,
+            rules_dir: rules_dir.map(Path::to_path_buf),
         };
 
         Ok(handlers)
     }
 
-    pub async fn start(self) -> Result<Self> {
-        info!("Starting harborshield rule handlers");
-
+    /// Bring the ruleset in line with the current containers and guests,
+    /// returning the ad-hoc rule sets and guest addresses applied
+    async fn sync_all(
+        &self,
+    ) -> Result<(HashMap<String, BTreeSet<i64>>, HashMap<String, Vec<IpAddr>>)> {
         // Clean up orphaned rules from previous runs
         self.cleanup_orphaned_rules().await?;
 
+        // Parse synced containers with their unexpired ad-hoc rules
+        let adhoc_ids = self.load_adhoc_rules().await;
+
         // Sync existing containers
         let stopped_container_ids = self
             .sync_containers(self.get_database_containers().await?)
@@ -115,11 +376,223 @@ impl Harborshield {
         self.cleanup_stopped_containers(stopped_container_ids)
             .await?;
 
+        // Protect configured VM guests
+        let applied = if self.guests().is_empty() {
+            Default::default()
+        } else {
+            self.apply_guests().await
+        };
+        Ok((adhoc_ids, applied))
+    }
+
+    /// Process the current containers as [`start`](Self::start) does, with
+    /// the changes only simulated; see [`nftables::plan`]
+    pub async fn plan(&self) -> Result<nftables::plan::PlanReport> {
+        self.sync_all().await?;
+        Ok(nftables::plan::finish())
+    }
+
+    pub async fn start(self) -> Result<Self> {
+        info!("Starting harborshield rule handlers");
+
+        let (adhoc_ids, applied) = self.sync_all().await?;
+        self.load_freeze().await;
+
+        // Follow the leases of guests located by MAC, including those a
+        // reload adds
+        let lease_handle = self.spawn_guest_lease_watcher(applied, GUEST_LEASE_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(lease_handle);
+
         let handlers = Arc::new(self.clone());
         // Start event listener
         let event_handle = self.spawn_event_listener(handlers);
         self.task_handles.lock().unwrap().push(event_handle);
 
+        // Watch for hung nft invocations and re-apply timed-out transactions
+        let watchdog_handle = tokio::spawn(nftables::runner::run_watchdog(
+            NFT_WATCHDOG_INTERVAL,
+            self.cancellation_token.clone(),
+        ));
+        self.task_handles.lock().unwrap().push(watchdog_handle);
+
+        // Reinstall rules flushed or changed by others
+        let heal_handle = self.spawn_self_heal_watcher(SELF_HEAL_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(heal_handle);
+
+        // Rebuild chains when a container's enforcement mode is switched
+        let enforcement_handle = self.spawn_enforcement_watcher(ENFORCEMENT_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(enforcement_handle);
+
+        // Hold rule changes back while a change freeze lasts
+        let freeze_handle = self.spawn_freeze_watcher(FREEZE_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(freeze_handle);
+
+        // Apply ad-hoc rules as they are stored and drop them once expired
+        let adhoc_handle = self.spawn_adhoc_watcher(adhoc_ids, ADHOC_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(adhoc_handle);
+
+        // Add banned addresses to the blocked set and lift bans removed early
+        let ban_handle = self.spawn_ban_watcher(BAN_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(ban_handle);
+
+        // Keep the kill switch table covering every container while engaged
+        let panic_handle = self.spawn_panic_watcher(PANIC_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(panic_handle);
+
+        // Rebuild chains as time-window rules open and close
+        let window_handle = self.spawn_time_window_watcher(TIME_WINDOW_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(window_handle);
+
+        // Drop the held rules of looping containers that stayed down
+        let restarts_handle = self.spawn_restart_loop_watcher(RESTART_LOOP_POLL_INTERVAL);
+        self.task_handles.lock().unwrap().push(restarts_handle);
+
+        // Record the flows of containers in learning mode
+        let learning_handle = self.spawn_learning_job(LEARNING_SAMPLE_INTERVAL);
+        self.task_handles.lock().unwrap().push(learning_handle);
+
+        // Re-render rules using the host's addresses when they change
+        #[cfg(target_os = "linux")]
+        match self.spawn_host_address_watcher() {
+            Ok(handle) => self.task_handles.lock().unwrap().push(handle),
+            Err(e) => warn!("Host address changes will not be picked up: {}", e),
+        }
+
+        // Rebuild chains as their --rules-dir files change
+        #[cfg(target_os = "linux")]
+        if let Some(dir) = self.rules_dir.clone() {
+            match self.spawn_rules_dir_watcher(dir) {
+                Ok(handle) => self.task_handles.lock().unwrap().push(handle),
+                Err(e) => warn!("Rule set file changes will not be picked up: {}", e),
+            }
+        }
+
+        // Count dropped packets per container from their NFLOG group
+        #[cfg(target_os = "linux")]
+        if let Some(group) = self.nflog_group {
+            match self.spawn_drop_listener(group) {
+                Ok(handle) => self.task_handles.lock().unwrap().push(handle),
+                Err(e) => warn!(
+                    "Dropped packets in NFLOG group {} will not be counted: {}",
+                    group, e
+                ),
+            }
+        }
+
+        // Take over once the active host lets go of the lock, and stand by
+        // if this host loses it
+        if let Some(lock) = self.ha_lock.clone() {
+            let leader_handle =
+                self.spawn_leader_election(lock, handlers::leader::LOCK_POLL_INTERVAL);
+            self.task_handles.lock().unwrap().push(leader_handle);
+        }
+
+        // Keep the stats rollup tables current
+        let stats_handle = self.spawn_stats_job(STATS_INTERVAL);
+        self.task_handles.lock().unwrap().push(stats_handle);
+
+        // Record drift between the ruleset and running containers nightly
+        if let Some(schedule) = self.reconcile_schedule.clone() {
+            let reconcile_handle = self.spawn_reconcile_job(schedule);
+            self.task_handles.lock().unwrap().push(reconcile_handle);
+        }
+
+        // Spot check that allow rules still count the flows they let through
+        if let Some(interval) = self.validate_rules_every {
+            let sampler_handle = self.spawn_rule_sampler(interval);
+            self.task_handles.lock().unwrap().push(sampler_handle);
+        }
+
+        if let Some(schedule) = self.report_schedule.clone() {
+            let report_handle = self.spawn_report_job(schedule);
+            self.task_handles.lock().unwrap().push(report_handle);
+        }
+
+        // Mirror stats and audit writes to the event bus
+        if let Some(config) = self.event_bus.clone() {
+            let bus_handle = bus::start(config, self.cancellation_token.clone());
+            self.task_handles.lock().unwrap().push(bus_handle);
+        }
+
+        // Look for new releases, if asked to
+        if let Some(check) = self.update_check.clone() {
+            let update_handle = tokio::spawn(update::run_checker(
+                check,
+                VERSION.to_string(),
+                self.cancellation_token.clone(),
+            ));
+            self.task_handles.lock().unwrap().push(update_handle);
+        }
+
+        // Keep the threat feeds' networks blocked
+        if let Some(lists) = self.blocklists.clone() {
+            let blocklist_handle = tokio::spawn(blocklist::run_blocklists(
+                lists,
+                self.cancellation_token.clone(),
+            ));
+            self.task_handles.lock().unwrap().push(blocklist_handle);
+        }
+
+        // Verify sources queued by rdns-gated inbound rules
+        match dns::SystemResolver::shared() {
+            Ok(resolver) => {
+                let registry = self.nftables_client.lock().await.rdns_registry();
+                let rdns_handle = tokio::spawn(dns::rdns::run_verifier(
+                    registry,
+                    resolver.clone(),
+                    RDNS_VERIFY_INTERVAL,
+                    self.cancellation_token.clone(),
+                ));
+                self.task_handles.lock().unwrap().push(rdns_handle);
+
+                // Keep the sets of `host: <name>` output rules resolved
+                let hostname_handle = tokio::spawn(dns::hostname::run_refresher(
+                    resolver.clone(),
+                    self.cancellation_token.clone(),
+                ));
+                self.task_handles.lock().unwrap().push(hostname_handle);
+
+                // Block whatever the sinkhole lists resolve to
+                if !self.sinkhole.is_empty() {
+                    let sinkhole_handle = tokio::spawn(dns::sinkhole::run_sinkhole(
+                        self.sinkhole.clone(),
+                        resolver,
+                        self.cancellation_token.clone(),
+                    ));
+                    self.task_handles.lock().unwrap().push(sinkhole_handle);
+                }
+            }
+            Err(e) => warn!(
+                "rdns rules will not admit any sources and hostname rules will not match: {}",
+                e
+            ),
+        }
+
+        // Answer orchestration tooling on the admin gRPC service
+        let grpc_listener = self.grpc_listener.lock().unwrap().take();
+        if let Some(listener) = grpc_listener {
+            let grpc = grpc::AdminServer::new(listener, self.clone(), self.api_tokens.clone());
+            let grpc_handle = tokio::spawn(grpc.serve());
+            self.task_handles.lock().unwrap().push(grpc_handle);
+        }
+
+        // Show the daemon's state on the read-only dashboard
+        let web_listener = self.web_listener.lock().unwrap().take();
+        if let Some(listener) = web_listener {
+            let dashboard = web::WebDashboard::new(listener, self.clone(), self.api_tokens.clone());
+            let web_handle = tokio::spawn(dashboard.serve());
+            self.task_handles.lock().unwrap().push(web_handle);
+        }
+
+        // Keep the systemd watchdog fed while events are handled
+        if let Some(timeout) = systemd::watchdog_timeout() {
+            let watchdog_handle = tokio::spawn(systemd::run_watchdog(
+                timeout,
+                self.cancellation_token.clone(),
+            ));
+            self.task_handles.lock().unwrap().push(watchdog_handle);
+        }
+
         // Update metrics
         self.update_metrics().await;
 
@@ -221,7 +694,7 @@ impl Harborshield {
         drop(db);
 
         // Clear tracker
-        self.docker_client.container_tracker.clear();
+        self.docker_client.container_tracker().clear();
 
         Ok(())
     }
@@ -231,14 +704,16 @@ impl Harborshield {
         info!("Clearing all Harborshield container chains from filter table");
 
         // Get a list of all Harborshield chains (hs-* chains)
-        let list_output = std::process::Command::new("nft")
-            .args(&["-j", "list", "table", "ip", FILTER_TABLE])
-            .output()
-            .map_err(|e| Error::Config {
-                message: format!("Failed to list filter table: {}", e),
-                location: "clear_all_harborshield_chains".to_string(),
-                suggestion: Some("Check nftables permissions".to_string()),
-            })?;
+        let list_output = nftables::runner::run_nft(
+            "list_filter_table",
+            &["-j", "list", "table", "ip", FILTER_TABLE],
+        )
+        .await
+        .map_err(|e| Error::Config {
+            message: format!("Failed to list filter table: {}", e),
+            location: "clear_all_harborshield_chains".to_string(),
+            suggestion: Some("Check nftables permissions".to_string()),
+        })?;
 
         if !list_output.status.success() {
             warn!(
@@ -273,18 +748,22 @@ impl Harborshield {
                 // Delete each container chain
                 for chain_name in chains_to_delete {
                     // First flush the chain
-                    let flush_result = std::process::Command::new("nft")
-                        .args(&["flush", "chain", "ip", FILTER_TABLE, &chain_name])
-                        .output();
+                    let flush_result = nftables::runner::run_nft(
+                        "flush_chain",
+                        &["flush", "chain", "ip", FILTER_TABLE, &chain_name],
+                    )
+                    .await;
 
                     if let Err(e) = flush_result {
                         warn!("Failed to flush chain {}: {}", chain_name, e);
                     }
 
                     // Then delete the chain
-                    let delete_result = std::process::Command::new("nft")
-                        .args(&["delete", "chain", "ip", FILTER_TABLE, &chain_name])
-                        .output();
+                    let delete_result = nftables::runner::run_nft(
+                        "delete_chain",
+                        &["delete", "chain", "ip", FILTER_TABLE, &chain_name],
+                    )
+                    .await;
 
                     match delete_result {
                         Ok(output) => {
@@ -331,15 +810,21 @@ impl Harborshield {
             }
         }
 
+        for (chain_name, _, _) in self.guest_chains() {
+            valid_chain_names.insert(chain_name);
+        }
+
         // Get all chains in the filter table
-        let list_output = std::process::Command::new("nft")
-            .args(&["-j", "list", "table", "ip", FILTER_TABLE])
-            .output()
-            .map_err(|e| Error::Config {
-                message: format!("Failed to list filter table: {}", e),
-                location: "cleanup_orphaned_rules".to_string(),
-                suggestion: Some("Check nftables permissions".to_string()),
-            })?;
+        let list_output = nftables::runner::run_nft(
+            "list_filter_table",
+            &["-j", "list", "table", "ip", FILTER_TABLE],
+        )
+        .await
+        .map_err(|e| Error::Config {
+            message: format!("Failed to list filter table: {}", e),
+            location: "cleanup_orphaned_rules".to_string(),
+            suggestion: Some("Check nftables permissions".to_string()),
+        })?;
 
         if !list_output.status.success() {
             warn!(
@@ -379,18 +864,22 @@ impl Harborshield {
                         info!("Removing orphaned chain: {}", chain_name);
 
                         // First flush the chain
-                        let flush_result = std::process::Command::new("nft")
-                            .args(&["flush", "chain", "ip", FILTER_TABLE, &chain_name])
-                            .output();
+                        let flush_result = nftables::runner::run_nft(
+                            "flush_chain",
+                            &["flush", "chain", "ip", FILTER_TABLE, &chain_name],
+                        )
+                        .await;
 
                         if let Err(e) = flush_result {
                             warn!("Failed to flush orphaned chain {}: {}", chain_name, e);
                         }
 
                         // Then delete the chain
-                        let delete_result = std::process::Command::new("nft")
-                            .args(&["delete", "chain", "ip", FILTER_TABLE, &chain_name])
-                            .output();
+                        let delete_result = nftables::runner::run_nft(
+                            "delete_chain",
+                            &["delete", "chain", "ip", FILTER_TABLE, &chain_name],
+                        )
+                        .await;
 
                         match delete_result {
                             Ok(output) => {
@@ -419,32 +908,6 @@ impl Harborshield {
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
-    let s = s.trim();
-
-    if let Some(stripped) = s.strip_suffix("ms") {
-        stripped
-            .parse::<u64>()
-            .map(Duration::from_millis)
-            .map_err(|e| format!("Invalid milliseconds: {}", e))
-    } else if let Some(stripped) = s.strip_suffix('s') {
-        stripped
-            .parse::<u64>()
-            .map(Duration::from_secs)
-            .map_err(|e| format!("Invalid seconds: {}", e))
-    } else if let Some(stripped) = s.strip_suffix('m') {
-        stripped
-            .parse::<u64>()
-            .map(|m| Duration::from_secs(m * 60))
-            .map_err(|e| format!("Invalid minutes: {}", e))
-    } else {
-        // Default to seconds if no suffix
-        s.parse::<u64>()
-            .map(Duration::from_secs)
-            .map_err(|e| format!("Invalid duration: {}", e))
-    }
-}
-
 pub async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -469,6 +932,30 @@ pub async fn shutdown_signal() {
     }
 }
 
+/// SIGHUPs, asking the daemon to reload
+pub struct ReloadSignal {
+    #[cfg(unix)]
+    hangup: signal::unix::Signal,
+}
+
+impl ReloadSignal {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            hangup: signal::unix::signal(signal::unix::SignalKind::hangup())?,
+        })
+    }
+
+    /// Wait for the next SIGHUP
+    pub async fn recv(&mut self) -> Option<()> {
+        #[cfg(unix)]
+        return self.hangup.recv().await;
+
+        #[cfg(not(unix))]
+        std::future::pending().await
+    }
+}
+
 pub fn check_kernel_version() {
     use std::process::Command;
 
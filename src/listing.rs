@@ -0,0 +1,231 @@
+//! Paging, filtering and field selection for list responses.
+//!
+//! `GET /enforcement`, `GET /stats` and `harborshield audit` accept the same
+//! parameters, so a large host can be queried without returning every row:
+//!
+//! - `container=<name>`: one container; a trailing `*` matches by prefix
+//! - `project=<name>`: containers of one compose project
+//! - `since=<when>`: a duration back (`24h`), unix seconds or RFC 3339
+//! - `limit=<n>`, `offset=<n>`: one page of the matching items
+//! - `fields=a,b`: only these fields of each item
+//!
+//! JSON responses then carry `total`, the number of matching items, and
+//! `next_offset` while more pages remain.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Largest page one request may ask for
+pub const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListQuery {
+    pub container: Option<String>,
+    pub project: Option<String>,
+    /// Unix seconds
+    pub since: Option<i64>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    pub fields: Vec<String>,
+}
+
+/// Decode `%XX` escapes in a query value
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Unix seconds for a `since` value given at `now`
+pub fn parse_since(value: &str, now: i64) -> Result<i64, String> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<i64>() {
+        return Ok(secs);
+    }
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp());
+    }
+    crate::parse_duration(value)
+        .map(|window| now - window.as_secs() as i64)
+        .map_err(|_| {
+            format!(
+                "invalid since '{}': expected a duration, unix seconds or RFC 3339",
+                value
+            )
+        })
+}
+
+/// `parse_since` against the current time, for command line arguments
+pub fn parse_since_arg(value: &str) -> Result<i64, String> {
+    parse_since(value, chrono::Utc::now().timestamp())
+}
+
+fn parse_count(name: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} '{}': expected a number", name, value))
+}
+
+impl ListQuery {
+    /// Read the list parameters from a URL query string
+    pub fn parse(query: &str, now: i64) -> Result<Self, String> {
+        let mut list = Self::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value);
+            match key {
+                "container" => list.container = Some(value),
+                "project" => list.project = Some(value),
+                "since" => list.since = Some(parse_since(&value, now)?),
+                "limit" => list.limit = Some(parse_count(key, &value)?),
+                "offset" => list.offset = parse_count(key, &value)?,
+                "fields" => {
+                    list.fields = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|field| !field.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        list.validate()?;
+        Ok(list)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.limit {
+            Some(0) => Err("limit must be at least 1".to_string()),
+            Some(limit) if limit > MAX_LIMIT => Err(format!("limit must be at most {}", MAX_LIMIT)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a container passes the container and project filters.
+    /// `project` is only called when a project filter is set
+    pub fn matches(&self, container_name: &str, project: impl FnOnce() -> Option<String>) -> bool {
+        if let Some(pattern) = &self.container
+            && !crate::access::name_matches(pattern, container_name)
+        {
+            return false;
+        }
+        match &self.project {
+            Some(wanted) => project().is_some_and(|project| project == *wanted),
+            None => true,
+        }
+    }
+
+    /// Cut `items` down to the requested page, returning how many matched
+    pub fn paginate<T>(&self, items: &mut Vec<T>) -> usize {
+        let total = items.len();
+        items.drain(..self.offset.min(total));
+        if let Some(limit) = self.limit {
+            items.truncate(limit);
+        }
+        total
+    }
+
+    /// `report` as JSON, with the items under `key` trimmed to the selected
+    /// fields and the paging totals added
+    pub fn to_json<R: Serialize>(&self, report: &R, key: &str, total: usize) -> Value {
+        let mut value = serde_json::to_value(report).unwrap_or(Value::Null);
+        let Some(Value::Array(items)) = value.get_mut(key) else {
+            return value;
+        };
+        if !self.fields.is_empty() {
+            for item in items.iter_mut() {
+                if let Value::Object(map) = item {
+                    map.retain(|field, _| self.fields.iter().any(|f| f == field));
+                }
+            }
+        }
+        let returned = items.len();
+
+        if let Value::Object(map) = &mut value {
+            map.insert("total".to_string(), total.into());
+            if self.offset + returned < total {
+                map.insert("next_offset".to_string(), (self.offset + returned).into());
+            }
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Report {
+        rows: Vec<Value>,
+    }
+
+    #[test]
+    fn test_list_query() {
+        let now = 1_700_000_000;
+        let query = ListQuery::parse(
+            "container=web-*&since=2h&limit=2&offset=1&fields=name,%20mode",
+            now,
+        )
+        .unwrap();
+        assert_eq!(query.since, Some(now - 7200));
+        assert_eq!(query.fields, vec!["name", "mode"]);
+        assert!(query.matches("web-2", || None));
+        assert!(!query.matches("db", || None));
+
+        let by_project = ListQuery::parse("project=shop", now).unwrap();
+        assert!(by_project.matches("web", || Some("shop".to_string())));
+        assert!(!by_project.matches("web", || None));
+
+        assert_eq!(
+            ListQuery::parse("since=2023-11-14T22:13:20%2B00:00", now)
+                .unwrap()
+                .since,
+            Some(1_700_000_000)
+        );
+        assert!(ListQuery::parse("limit=0", now).is_err());
+        assert!(ListQuery::parse("limit=5000", now).is_err());
+        assert!(ListQuery::parse("offset=x", now).is_err());
+        assert!(ListQuery::parse("since=yesterday", now).is_err());
+
+        let mut rows: Vec<Value> = (0..5)
+            .map(|i| serde_json::json!({ "name": format!("web-{}", i), "mode": "enforce", "updated_at": i }))
+            .collect();
+        let total = query.paginate(&mut rows);
+        let json = query.to_json(&Report { rows }, "rows", total);
+        assert_eq!(json["total"], 5);
+        assert_eq!(json["next_offset"], 3);
+        assert_eq!(
+            json["rows"],
+            serde_json::json!([
+                { "name": "web-1", "mode": "enforce" },
+                { "name": "web-2", "mode": "enforce" }
+            ])
+        );
+
+        let mut rows = vec![Value::Null; 2];
+        let all = ListQuery::default();
+        let total = all.paginate(&mut rows);
+        let json = all.to_json(&Report { rows }, "rows", total);
+        assert_eq!(json["total"], 2);
+        assert!(json.get("next_offset").is_none());
+    }
+}
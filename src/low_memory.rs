@@ -0,0 +1,37 @@
+//! Profile for single-board computers running a handful of containers.
+//!
+//! With `--low-memory` harborshield trades throughput for a resident set
+//! under about 30 MB:
+//!
+//! - the DNS resolver keeps no answer cache, and SQLite no prepared
+//!   statement cache and only a small page cache;
+//! - the database pool holds a single connection, closed when idle;
+//! - container inspect responses from a local Docker socket are parsed as
+//!   they are read instead of being buffered whole first.
+//!
+//! Rules behave the same; lookups and queries just repeat more often.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Connections the database pool opens at most
+pub const DB_MAX_CONNECTIONS: u32 = 1;
+
+/// How long the pool keeps its connection open without queries
+pub const DB_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SQLite page cache per connection, in KiB
+pub const SQLITE_CACHE_KIB: u32 = 256;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Switch to the low-memory profile for the rest of the process; call
+/// before the database and clients are created
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the low-memory profile is active
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
@@ -1,6 +1,43 @@
-use clap::Parser;
-use harborshield::{Harborshield, VERSION, check_kernel_version, parse_duration, shutdown_signal};
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use futures::StreamExt;
+use harborshield::{
+    Harborshield, ReloadSignal, VERSION, adopt, check_kernel_version,
+    database::{
+        AuditEntry, ContainerEnforcement, DB, DbOp, DbOpResult, EnforcementMode, activity, adhoc,
+        audit, backup, bans,
+        crypto::{self, ColumnKey},
+        enforcement, freeze, learning, panic, schema,
+    },
+    dns::{self, LookupFamily, LookupPolicy},
+    docker::{
+        compose::COMPOSE_PROJECT_LABEL,
+        config::{
+            exposure, profiles,
+            templates::{self, Scenario, TemplateList},
+        },
+        identity::{self, IdentityMode},
+        labels,
+    },
+    doctor,
+    export::{self, ExportFormat, ExportKind},
+    handlers::{
+        reconcile::ReconcileSchedule,
+        report::{ReportSchedule, write_report},
+        sampling,
+    },
+    i18n::{self, Catalog},
+    listing::{self, ListQuery},
+    nftables::{applier, capacity, counters, dedicated, flush, plan, runner, tproxy},
+    output::{self, OutputFormat},
+    parse_duration,
+    report::{ConsistencyReport, ReportFormat, rule_warnings},
+    runtime::{self, RuntimeKind},
+    shutdown_signal, status, top, tr, validate,
+};
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
@@ -8,56 +45,2573 @@ use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Automate management of firewall rules for Docker containers", long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Output format for subcommands
+    #[arg(short = 'o', long, value_enum, default_value_t = OutputFormat::Table, global = true)]
+    output: OutputFormat,
+
     /// Remove all firewall rules created by harborshield
     #[arg(long)]
     clear: bool,
 
-    /// Directory to store state in
-    #[arg(short = 'd', long, default_value = ".")]
-    data_dir: PathBuf,
+    /// Directory to store state in; created 0700 when missing, and refused
+    /// when other users could write to it
+    #[arg(short = 'd', long, default_value = ".", global = true)]
+    data_dir: PathBuf,
+
+    /// Directory for scratch state, such as the database copy a plan works
+    /// on; defaults to $RUNTIME_DIRECTORY or /run/harborshield
+    #[arg(long, value_name = "DIR", global = true)]
+    runtime_dir: Option<PathBuf>,
+
+    /// Key for encrypting rule and audit detail columns in the database: 32
+    /// raw bytes or their base64. Falls back to HARBORSHIELD_DB_KEY; rows
+    /// written before a key was set stay in plaintext
+    #[arg(long, global = true)]
+    db_key_file: Option<PathBuf>,
+
+    /// YAML file of named rule profiles that rules labels and guests can
+    /// build on with `extends: <name>`
+    #[arg(long, global = true)]
+    profiles: Option<PathBuf>,
+
+    /// Fluent (.ftl) catalog translating CLI messages and table headers;
+    /// ids it leaves out stay English. Falls back to HARBORSHIELD_MESSAGES
+    #[arg(long, global = true)]
+    messages: Option<PathBuf>,
+
+    /// Enable debug logging
+    #[arg(long)]
+    debug: bool,
+
+    /// Process the current containers and print the resulting nftables
+    /// changes as a diff against the live ruleset, without applying them;
+    /// same as `harborshield plan`
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Path to log to (use "stdout" or "stderr" for console output)
+    #[arg(short = 'l', long, default_value = "stdout")]
+    log_path: String,
+
+    /// Container engine to watch. Podman is reached on CONTAINER_HOST or
+    /// its default socket, and must use netavark's iptables firewall driver
+    #[arg(long, value_enum, default_value_t = RuntimeKind::Docker, global = true)]
+    runtime: RuntimeKind,
+
+    /// Another Docker daemon to watch as NAME=HOST, e.g.
+    /// `box2=tcp://10.0.0.2:2376`; its containers are named `NAME/...`.
+    /// May be repeated
+    #[arg(long = "endpoint", value_name = "NAME=HOST")]
+    endpoints: Vec<runtime::fleet::Endpoint>,
+
+    /// Timeout for Docker API requests
+    #[arg(short = 't', long, default_value = "10s", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Timeout for a single nft invocation before it is killed and re-queued
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    nft_timeout: Duration,
+
+    /// Worker threads of the async runtime; one per CPU unless given
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    worker_threads: Option<u16>,
+
+    /// Most threads the runtime starts for blocking work such as file and
+    /// DNS lookups; 512 unless given
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    max_blocking_threads: Option<u16>,
+
+    /// Make nft calls from a dedicated thread, so rule changes don't wait
+    /// behind API and metrics requests on a loaded host
+    #[arg(long)]
+    nft_thread: bool,
+
+    /// Niceness of the `--nft-thread` thread, below 0 to run it ahead of
+    /// the rest of the process
+    #[arg(long, default_value_t = dedicated::DEFAULT_NICE, allow_hyphen_values = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nft_thread_nice: i32,
+
+    /// Hand every nft call to a `harborshield applier` listening on this
+    /// socket, so this process needs no CAP_NET_ADMIN
+    #[arg(long, value_name = "SOCKET", global = true)]
+    applier: Option<PathBuf>,
+
+    /// Offload established flows of output rules marked `offload: true` to
+    /// a flowtable on this interface (e.g. the uplink and a container
+    /// bridge). Repeatable; offload is off unless given
+    #[arg(long = "flowtable-device", value_name = "IFACE")]
+    flowtable_devices: Vec<String>,
+
+    /// When a rule change stops allowing a connection a container opened or
+    /// accepted, delete its conntrack entry so it is cut at once instead of
+    /// lasting until it idles out
+    #[arg(long)]
+    flush_revoked_flows: bool,
+
+    /// Docker events buffered while handlers are busy. When the queue stays
+    /// full, further events are dropped and the affected containers are
+    /// resynced from Docker once it drains
+    #[arg(long, default_value_t = harborshield::handlers::pipeline::DEFAULT_EVENT_QUEUE_CAPACITY)]
+    event_queue_size: usize,
+
+    /// Run a full reconcile every day at this UTC time (HH:MM) and record
+    /// any drift in the audit log
+    #[arg(long, value_parser = parse_time_of_day)]
+    reconcile_at: Option<chrono::NaiveTime>,
+
+    /// Repair drift found by the nightly reconcile instead of only
+    /// reporting it
+    #[arg(long, requires = "reconcile_at")]
+    reconcile_repair: bool,
+
+    /// POST each nightly reconcile report as JSON to this URL
+    #[arg(long, requires = "reconcile_at")]
+    reconcile_webhook: Option<String>,
+
+    /// Build a report of the last day's policy changes, drift, dropped
+    /// traffic and invalid rule sets every day at this UTC time (HH:MM)
+    #[arg(long, value_parser = parse_time_of_day)]
+    report_at: Option<chrono::NaiveTime>,
+
+    /// Format of the daily report
+    #[arg(long, value_enum, default_value_t, requires = "report_at")]
+    report_format: ReportFormat,
+
+    /// Write each daily report to a dated file in this directory, e.g. for
+    /// a mail cron job
+    #[arg(long, requires = "report_at")]
+    report_dir: Option<PathBuf>,
+
+    /// POST each daily report as JSON, rendered body included, to this URL
+    #[arg(long, requires = "report_at")]
+    report_webhook: Option<String>,
+
+    /// Check a signed release manifest daily and report newer versions in
+    /// /status, the harborshield_update_available metric and the log.
+    /// Never downloads or installs anything
+    #[arg(long)]
+    check_updates: bool,
+
+    /// Release manifest to check; its signature is read from the same URL
+    /// with ".sig" appended
+    #[arg(long, default_value = harborshield::update::DEFAULT_MANIFEST_URL, requires = "check_updates")]
+    update_manifest_url: String,
+
+    /// Base64 Ed25519 key the manifest must be signed with, instead of the
+    /// release key built into this binary
+    #[arg(long, requires = "check_updates")]
+    update_key: Option<String>,
+
+    /// POST a JSON notice to this URL when a newer release is first seen
+    #[arg(long, requires = "check_updates")]
+    update_webhook: Option<String>,
+
+    /// Record an `unused_allow` audit entry, published on the event bus,
+    /// once an allow rule has matched no traffic for this long (e.g. "30d")
+    #[arg(long, value_parser = parse_duration)]
+    notify_unused_after: Option<Duration>,
+
+    /// Check a random sample of allow rules this often (e.g. "5m") against
+    /// conntrack, and record a `rule_suspect` audit entry, shown by `doctor`
+    /// and `status`, for rules that let new flows through without counting
+    /// them
+    #[arg(long, value_parser = parse_duration)]
+    validate_rules_every: Option<Duration>,
+
+    /// Log packets dropped by container chains to this NFLOG group instead
+    /// of the kernel log, and count them per container in
+    /// `harborshield_dropped_packets_total`
+    #[arg(long, value_name = "GROUP")]
+    nflog_group: Option<u16>,
+
+    /// Run active/standby with another host sharing this lock file, e.g.
+    /// on NFS: only the host holding the lock changes its ruleset, and the
+    /// other takes over once the lock is released
+    #[arg(long, value_name = "PATH")]
+    ha_lock: Option<PathBuf>,
+
+    /// Domain blocklist, in hosts-file or adblock format, whose names'
+    /// addresses no container may reach; re-read hourly (repeatable)
+    #[arg(long, value_name = "FILE")]
+    sinkhole: Vec<PathBuf>,
+
+    /// YAML file listing threat feeds under `blocklists`, URLs such as
+    /// Spamhaus DROP or local files of networks, fetched every `refresh`
+    /// (6h by default); no container may talk to the networks they list
+    #[arg(long, value_name = "FILE")]
+    blocklists: Option<PathBuf>,
+
+    /// Directory of `<name>.yml` rule sets for the containers or Compose
+    /// services of that name, merged under their rules labels and reapplied
+    /// when a file changes
+    #[arg(long, value_name = "DIR")]
+    rules_dir: Option<PathBuf>,
+
+    /// POST a JSON notice to this URL whenever a container is quarantined
+    /// by its `quarantine` policy
+    #[arg(long)]
+    quarantine_webhook: Option<String>,
+
+    /// Publish rule changes, traffic samples and audit entries to a NATS
+    /// (nats://[user:pass@]host[:port]) or MQTT (mqtt://...) broker
+    #[arg(long, value_name = "URL")]
+    event_bus: Option<harborshield::bus::BusConfig>,
+
+    /// First level of every event bus subject or topic
+    #[arg(long, default_value = harborshield::bus::DEFAULT_PREFIX, requires = "event_bus")]
+    event_bus_prefix: String,
+
+    /// Also read rules from labels under this prefix (e.g. "com.acme.fw"
+    /// for "com.acme.fw.rules"). Repeatable; "harborshield" comes first
+    /// unless listed, and a container is read from the first prefix it uses
+    #[arg(long = "label-prefix")]
+    label_prefixes: Vec<String>,
+
+    /// What enforcement overrides, learning sessions, audit entries and
+    /// stats are kept under, so they carry over recreated containers: the
+    /// container name, its compose project and service, or its
+    /// `harborshield.identity` label
+    #[arg(long, value_enum, default_value_t = IdentityMode::Name)]
+    identity: IdentityMode,
+
+    /// Address records hostnames in rules are resolved to, unless a rule
+    /// picks its own: "a", "aaaa" or "both"
+    #[arg(long, value_enum, default_value_t = LookupFamily::Both)]
+    dns_family: LookupFamily,
+
+    /// With "--dns-family both", treat a hostname as unresolved unless it
+    /// has both A and AAAA records, rather than allowing whichever resolved
+    #[arg(long)]
+    dns_require_both: bool,
+
+    /// Seconds between re-resolving the names of `host: <name>` output
+    /// rules that don't set `dns_refresh`
+    #[arg(long, default_value_t = dns::DEFAULT_REFRESH, value_parser = clap::value_parser!(u32).range(1..))]
+    dns_refresh: u32,
+
+    /// Ports output rules may only open to every destination, and external
+    /// access to every source, with `expose_to_internet: true`
+    #[arg(long, value_delimiter = ',', default_values_t = exposure::DEFAULT_SENSITIVE_PORTS.to_vec())]
+    sensitive_ports: Vec<u16>,
+
+    /// YAML file listing libvirt/QEMU guests to protect like containers,
+    /// each with a name, `ips` or a `mac` to find in libvirt's leases,
+    /// served `ports` and `rules` in the label format
+    #[arg(long)]
+    guests: Option<PathBuf>,
+
+    /// YAML file of bearer tokens for the enforcement endpoint and the gRPC
+    /// service, each scoped to containers, compose projects and modes it may
+    /// set. Without it the REST API and gRPC service can be read by anyone
+    /// reaching their listener, but only changed over a unix socket file,
+    /// or by root or the daemon's user over an abstract socket
+    #[arg(long)]
+    api_tokens: Option<PathBuf>,
+
+    /// Enable the admin server serving health, metrics and the REST API on
+    /// one listener: "127.0.0.1:8080", "unix:/run/harborshield.sock",
+    /// "unix:@harborshield" for an abstract socket, "vsock:<port>" or
+    /// "vsock:<cid>:<port>" for a VM's host, or "systemd" to use a socket
+    /// passed by systemd socket activation
+    #[arg(long)]
+    health_server: Option<String>,
+
+    /// Serve the admin gRPC service (proto/admin.proto) on this address,
+    /// in the same forms as --health-server; on
+    /// unix:/run/harborshield/admin.sock when given without one
+    #[arg(long, value_name = "ADDR", num_args = 0..=1, default_missing_value = harborshield::grpc::DEFAULT_LISTEN_ADDR)]
+    grpc: Option<harborshield::server::ListenAddr>,
+
+    /// Serve a read-only web dashboard of containers, rules, counters and
+    /// recent events on this address, in the same forms as --health-server
+    #[arg(long, value_name = "ADDR")]
+    web_addr: Option<harborshield::server::ListenAddr>,
+
+    /// Don't serve /health and /ready on the admin server
+    #[arg(long)]
+    disable_health: bool,
+
+    /// Don't serve /metrics on the admin server
+    #[arg(long)]
+    disable_metrics: bool,
+
+    /// Don't serve /version, /about, /status and /stats on the admin server
+    #[arg(long)]
+    disable_api: bool,
+
+    /// Refuse outbound calls harborshield would start on its own (GeoIP
+    /// downloads, blocklist fetches, update checks). Always on in builds
+    /// with the `offline` feature
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Keep the resident set small for single-board computers: no DNS or
+    /// SQLite statement caches, a one-connection database pool, and Docker
+    /// inspect responses parsed as they are read
+    #[arg(long, global = true)]
+    low_memory: bool,
+
+    /// Print version and build information and exit
+    #[arg(long = "version-info")]
+    version_info: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the rules installed per container with the traffic they
+    /// accepted and dropped
+    Status {
+        /// List every rule with its counter, the container and rule it was
+        /// built from, and whether it has matched anything
+        #[arg(long)]
+        counters: bool,
+    },
+
+    /// Show dropped and accepted traffic and rule changes per container
+    Stats {
+        /// How far back to report (e.g. "90m", "24h", "7d")
+        #[arg(long, default_value = "24h", value_parser = parse_duration)]
+        since: Duration,
+
+        /// Only report this container
+        #[arg(long)]
+        container: Option<String>,
+    },
+
+    /// Report ruleset size against configured limits and its weekly trend
+    Capacity {
+        /// Chain count to plan against
+        #[arg(long, default_value_t = capacity::DEFAULT_MAX_CHAINS)]
+        max_chains: u64,
+
+        /// Rule count to plan against
+        #[arg(long, default_value_t = capacity::DEFAULT_MAX_RULES)]
+        max_rules: u64,
+
+        /// Set and map element count to plan against
+        #[arg(long, default_value_t = capacity::DEFAULT_MAX_SET_ELEMENTS)]
+        max_set_elements: u64,
+
+        /// Warn once usage reaches this percentage of a limit
+        #[arg(long, default_value_t = capacity::DEFAULT_WARN_PERCENT, value_parser = clap::value_parser!(u8).range(1..=100))]
+        warn_percent: u8,
+    },
+
+    /// Print ready-to-paste compose labels for a common kind of service
+    Examples {
+        /// Scenario to print; lists all scenarios when omitted
+        #[arg(value_enum)]
+        scenario: Option<Scenario>,
+    },
+
+    /// Show or switch per-container enforcement (enforce, permissive, disabled)
+    Enforcement {
+        /// Container to show or switch, by identity: its name unless the
+        /// daemon runs with `--identity`
+        container: Option<String>,
+
+        /// New mode; a running daemon applies it within a few seconds
+        #[arg(value_enum)]
+        mode: Option<EnforcementMode>,
+    },
+
+    /// Lift a container's quarantine and enforce its rules again
+    Release {
+        /// Quarantined container, by identity
+        container: String,
+    },
+
+    /// Run a container in permissive mode for a while and record the
+    /// traffic it makes, for `suggest`; lists learning containers when
+    /// omitted
+    Learn {
+        /// Container to start or stop learning (its identity, when the
+        /// daemon keys state on something other than names)
+        container: Option<String>,
+
+        /// How long to learn (e.g. "90m", "24h", "7d")
+        #[arg(long = "for", default_value = "24h", value_parser = parse_duration)]
+        duration: Duration,
+
+        /// End learning now and enforce the container's rules again
+        #[arg(long, requires = "container")]
+        stop: bool,
+    },
+
+    /// Layer a rules document over a container's rules label until a TTL
+    /// passes, without editing any files; a running daemon applies it within
+    /// a few seconds
+    ApplyAdhoc {
+        /// Rules document in the label format, or `-` to read it from stdin
+        source: PathBuf,
+
+        /// Container to apply the rules to, by identity
+        #[arg(long)]
+        container: String,
+
+        /// How long the rules stay applied (e.g. "30m", "2h")
+        #[arg(long, default_value = "1h", value_parser = parse_duration)]
+        ttl: Duration,
+
+        /// Why the rules are needed, recorded in the audit log
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Propose rules covering the traffic recorded while a container learned
+    Suggest {
+        /// Container to propose rules for
+        container: String,
+    },
+
+    /// Print a report of policy changes, drift, the containers dropping
+    /// the most traffic and invalid rule sets, as the daemon's
+    /// `--report-at` builds it
+    Report {
+        /// Period to cover, up to now
+        #[arg(long, default_value = "24h", value_parser = parse_duration)]
+        since: Duration,
+
+        /// Plain text or HTML; `-o json` prints the report's data instead
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+
+        /// Write the report to a dated file in this directory instead of
+        /// stdout
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+
+    /// Show reconcile runs and the drift they recorded
+    Audit {
+        /// How far back to report: a duration (e.g. "24h", "30d"), unix
+        /// seconds or an RFC 3339 time
+        #[arg(long, default_value = "7d", value_parser = listing::parse_since_arg)]
+        since: i64,
+
+        /// Only entries for this container; a trailing `*` matches by prefix
+        #[arg(long)]
+        container: Option<String>,
+
+        /// Only entries for containers of this compose project, looked up
+        /// in Docker
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Print at most this many entries
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Skip this many matching entries
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// Only these fields of each entry, for `--output json`
+        #[arg(long, value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// List allow rules that matched no traffic for a while, from the rule
+    /// activity the daemon records, exiting non-zero when there are any
+    Unused {
+        /// How long a rule must have matched nothing (e.g. "14d", "90d")
+        #[arg(long = "for", default_value = "30d", value_parser = parse_duration)]
+        idle: Duration,
+    },
+
+    /// Watch per-container drop and accept rates and Docker events live
+    Top {
+        /// Time between refreshes
+        #[arg(long, default_value = "2s", value_parser = parse_duration)]
+        interval: Duration,
+    },
+
+    /// Check running containers for setups that undermine their rules, such
+    /// as service mesh sidecars redirecting traffic or a host port shared
+    /// under different source restrictions
+    Doctor,
+
+    /// Check a rules document or a compose file for schema errors, unknown
+    /// keys and references to containers it doesn't define, exiting
+    /// non-zero when anything is found
+    Validate {
+        /// Rules document in the label format or compose file, or `-` to
+        /// read it from stdin
+        source: PathBuf,
+
+        /// Container defined elsewhere that rules may reference; repeatable
+        #[arg(long = "known", value_name = "NAME")]
+        known: Vec<String>,
+    },
+
+    /// Print the nftables changes starting the daemon would make, as a unified
+    /// diff against the live ruleset, without applying them or changing
+    /// the database
+    Plan,
+
+    /// Remove the nftables chains, sets and jump rules harborshield created
+    /// and reset its container state, leaving Docker's and other rules
+    /// alone. Stop the daemon first, or it recreates them
+    Flush {
+        /// Remove them; without it they are only listed
+        #[arg(long)]
+        confirm: bool,
+    },
+
+    /// Run the privileged half of a split deployment: apply the nft calls
+    /// of a daemon started with `--applier`, refusing anything that reaches
+    /// beyond harborshield's own chains, sets and jump rules
+    Applier {
+        /// Socket to listen on
+        #[arg(long, default_value = applier::DEFAULT_SOCKET)]
+        socket: PathBuf,
+
+        /// Only accept connections from this user (and root)
+        #[arg(long)]
+        controller_uid: Option<u32>,
+    },
+
+    /// Propose rules for containers from a hand-written nftables ruleset,
+    /// listing what it could and couldn't adopt
+    Adopt {
+        /// Ruleset as `nft -j list ruleset` prints it, or `-` to read it
+        /// from stdin
+        #[arg(long = "from-nft", value_name = "RULESET")]
+        from_nft: PathBuf,
+
+        /// Address of a container, as NAME=ADDR; repeatable. Running
+        /// containers are looked up in Docker when none are given
+        #[arg(long = "container", value_name = "NAME=ADDR", value_parser = adopt::parse_container_arg)]
+        containers: Vec<(String, std::net::IpAddr)>,
+    },
+
+    /// Hold back rule changes for a change-freeze window: a running daemon
+    /// notes Docker events without applying them, and enforcement switches,
+    /// releases, learning and ad-hoc rules are refused. Quarantines still
+    /// go through
+    Freeze {
+        /// When the freeze ends by itself: a duration (e.g. "4h"), unix
+        /// seconds or an RFC 3339 time. Lasts until `unfreeze --confirm`
+        /// when omitted
+        #[arg(long, value_parser = freeze::parse_until_arg)]
+        until: Option<i64>,
+
+        /// Why the freeze is needed, recorded in the audit log
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Review what a change freeze held back: the containers with held
+    /// events and the nftables diff applying them makes, as `plan` prints it
+    Unfreeze {
+        /// End the freeze; a running daemon applies the held changes
+        /// within a few seconds
+        #[arg(long)]
+        confirm: bool,
+    },
+
+    /// Drop traffic from and to an address until the ban expires; a
+    /// running daemon applies it within a few seconds. Banning an address
+    /// again replaces its ban
+    Ban {
+        ip: std::net::Ipv4Addr,
+
+        /// How long the ban lasts (e.g. "10m", "1h", "7d")
+        #[arg(long, default_value = "1h", value_parser = parse_duration)]
+        duration: Duration,
+
+        /// Why the address is banned, recorded in the audit log
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Lift the ban of an address before it expires
+    Unban {
+        ip: std::net::Ipv4Addr,
+
+        /// Why the ban is lifted, recorded in the audit log
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// List the addresses banned
+    Bans,
+
+    /// Kill switch: drop all traffic from and to every tracked container
+    /// straight away, except with the `--allow` networks, until
+    /// `panic --release`. A running daemon covers containers started
+    /// meanwhile
+    Panic {
+        /// A network still let through, such as the management subnet;
+        /// repeatable
+        #[arg(long, value_name = "NET", value_parser = panic::parse_allow_arg, conflicts_with = "release")]
+        allow: Vec<ipnet::IpNet>,
+
+        /// Why, recorded in the audit log
+        #[arg(long)]
+        reason: Option<String>,
+
+        /// Release the kill switch and let container traffic flow again
+        #[arg(long)]
+        release: bool,
+    },
+
+    /// Print generated artifacts for other tools
+    Export {
+        #[command(subcommand)]
+        what: Export,
+    },
+
+    /// Pull recorded drops or audit entries out for analysis
+    Events {
+        #[command(subcommand)]
+        what: Events,
+    },
+
+    /// Back up, dump or restore the database in the data directory
+    Db {
+        #[command(subcommand)]
+        what: Db,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Export {
+    /// Grafana dashboard JSON covering every exported metric
+    Dashboard,
+    /// JSON Schema of every event sent to the event bus and webhooks
+    EventSchema,
+}
+
+#[derive(Subcommand, Debug)]
+enum Events {
+    /// Write drop statistics or the audit log as CSV or Parquet, for
+    /// pandas, DuckDB and the like
+    Export {
+        /// How far back to export: a duration (e.g. "24h", "30d"), unix
+        /// seconds or an RFC 3339 time
+        #[arg(long, default_value = "30d", value_parser = listing::parse_since_arg)]
+        since: i64,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+
+        /// What to export
+        #[arg(long, value_enum, default_value_t)]
+        kind: ExportKind,
+
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Db {
+    /// Write a consistent snapshot of the database, safe while the daemon
+    /// runs; take one before upgrading
+    Backup {
+        /// File to write; must not exist yet
+        path: PathBuf,
+    },
+    /// Dump every table for inspection
+    Export {
+        #[arg(long, value_enum, default_value_t)]
+        format: DbExportFormat,
+
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Print the tables this build uses, with what their columns mean, for
+    /// reports reading the database
+    Schema {
+        #[arg(long, value_enum, default_value_t)]
+        format: schema::SchemaFormat,
+
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Replace the database with a backup, keeping the current one as
+    /// `db.sqlite.pre-restore`. Refused while the daemon is running
+    Restore {
+        /// Backup written by `db backup`
+        path: PathBuf,
+
+        /// Replace the database; without it the backup is only checked
+        #[arg(long)]
+        confirm: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum DbExportFormat {
+    #[default]
+    Json,
+}
+
+async fn open_db(data_dir: &Path) -> Option<DB> {
+    match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => Some(db),
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            None
+        }
+    }
+}
+
+async fn run_db_backup(data_dir: &Path, path: &Path, format: OutputFormat) -> i32 {
+    let Some(db) = open_db(data_dir).await else {
+        return 1;
+    };
+    match backup::backup(&db, path).await {
+        Ok(report) => {
+            output::emit(&report, format);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("db-backup-failed", error = e));
+            1
+        }
+    }
+}
+
+async fn run_db_export(data_dir: &Path, export_format: DbExportFormat, file: Option<&Path>) -> i32 {
+    let Some(db) = open_db(data_dir).await else {
+        return 1;
+    };
+    let dump = match backup::export(&db).await {
+        Ok(dump) => dump,
+        Err(e) => {
+            eprintln!("{}", tr!("db-export-failed", error = e));
+            return 1;
+        }
+    };
+    let mut body = match export_format {
+        DbExportFormat::Json => serde_json::to_string_pretty(&dump).unwrap_or_default(),
+    };
+    body.push('\n');
+    let written = match file {
+        Some(path) => std::fs::write(path, body),
+        None => std::io::stdout().write_all(body.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("{}", tr!("db-export-failed", error = e));
+        return 1;
+    }
+    0
+}
+
+async fn run_db_schema(format: schema::SchemaFormat, file: Option<&Path>) -> i32 {
+    let body = match schema::describe().await {
+        Ok(schema) => schema.render(format),
+        Err(e) => {
+            eprintln!("{}", tr!("db-schema-failed", error = e));
+            return 1;
+        }
+    };
+    let written = match file {
+        Some(path) => std::fs::write(path, body),
+        None => std::io::stdout().write_all(body.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("{}", tr!("db-schema-failed", error = e));
+        return 1;
+    }
+    0
+}
+
+async fn run_db_restore(data_dir: &Path, path: &Path, confirm: bool, format: OutputFormat) -> i32 {
+    if !confirm {
+        return match backup::check_backup(path).await {
+            Ok((version, pending)) => {
+                println!(
+                    "{}",
+                    tr!(
+                        "db-restore-checked",
+                        path = path.display(),
+                        version = version,
+                        pending = pending
+                    )
+                );
+                1
+            }
+            Err(e) => {
+                eprintln!("{}", tr!("db-restore-failed", error = e));
+                1
+            }
+        };
+    }
+    match backup::restore(path, &data_dir.join("db.sqlite")).await {
+        Ok(report) => {
+            output::emit(&report, format);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("db-restore-failed", error = e));
+            1
+        }
+    }
+}
+
+async fn run_events_export(
+    data_dir: &Path,
+    since: i64,
+    format: ExportFormat,
+    kind: ExportKind,
+    file: Option<&Path>,
+) -> i32 {
+    // Parquet is binary; a terminal would only show garbage
+    if file.is_none() && format == ExportFormat::Parquet && std::io::stdout().is_terminal() {
+        eprintln!("{}", tr!("events-export-terminal"));
+        return 1;
+    }
+
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let frame = match kind {
+        ExportKind::Drops => export::drops(&db, since, now).await,
+        ExportKind::Audit => export::audit(&db, since).await,
+    };
+    let frame = match frame {
+        Ok(frame) => frame,
+        Err(e) => {
+            eprintln!("{}", tr!("events-export-failed", error = e));
+            return 1;
+        }
+    };
+
+    let bytes = export::encode(&frame, format);
+    let written = match file {
+        Some(path) => std::fs::write(path, &bytes),
+        None => std::io::stdout().write_all(&bytes),
+    };
+    if let Err(e) = written {
+        eprintln!("{}", tr!("events-export-failed", error = e));
+        return 1;
+    }
+    0
+}
+
+async fn run_stats(
+    data_dir: &Path,
+    since: Duration,
+    container: Option<&str>,
+    format: OutputFormat,
+) -> i32 {
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let mut rows = match harborshield::database::stats::stats_since(&db, since, now).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("{}", tr!("stats-query-failed", error = e));
+            return 1;
+        }
+    };
+    if let Some(container) = container {
+        rows.retain(|row| row.container_name == container);
+    }
+
+    output::emit(
+        &harborshield::database::stats::StatsReport {
+            since_seconds: since.as_secs(),
+            containers: rows,
+        },
+        format,
+    );
+    0
+}
+
+/// Names and identities of the Docker containers in a compose project
+async fn project_members(
+    timeout: Duration,
+    identity_mode: IdentityMode,
+    project: &str,
+) -> harborshield::Result<HashSet<String>> {
+    let docker = runtime::connect(runtime::kind(), timeout)?;
+    let mut members = HashSet::new();
+    for summary in docker.list_all_containers().await? {
+        let container_labels = summary.labels.unwrap_or_default();
+        if container_labels
+            .get(COMPOSE_PROJECT_LABEL)
+            .map(String::as_str)
+            != Some(project)
+        {
+            continue;
+        }
+        for name in summary.names.unwrap_or_default() {
+            let name = name.trim_start_matches('/');
+            members.insert(identity::identity_for(
+                identity_mode,
+                name,
+                &container_labels,
+            ));
+            members.insert(name.to_string());
+        }
+    }
+    Ok(members)
+}
+
+async fn run_audit(
+    data_dir: &Path,
+    list: &ListQuery,
+    timeout: Duration,
+    identity_mode: IdentityMode,
+    format: OutputFormat,
+) -> i32 {
+    if let Err(e) = list.validate() {
+        eprintln!("{}", e);
+        return 2;
+    }
+    let members = match &list.project {
+        Some(project) => match project_members(timeout, identity_mode, project).await {
+            Ok(members) => members,
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    tr!("audit-project-lookup-failed", project = project, error = e)
+                );
+                return 1;
+            }
+        },
+        None => HashSet::new(),
+    };
+
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    match audit::list_since(&db, list.since.unwrap_or_default()).await {
+        Ok(mut entries) => {
+            if list.container.is_some() || list.project.is_some() {
+                entries.retain(|entry| {
+                    entry.container_name.as_deref().is_some_and(|name| {
+                        list.matches(name, || {
+                            members
+                                .contains(name)
+                                .then(|| list.project.clone())
+                                .flatten()
+                        })
+                    })
+                });
+            }
+            let total = list.paginate(&mut entries);
+            let shown = entries.len();
+            let report = audit::AuditReport { entries };
+            if format == OutputFormat::Json {
+                let json = list.to_json(&report, "entries", total);
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json).unwrap_or_default()
+                );
+            } else {
+                output::emit(&report, format);
+                if list.offset + shown < total {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "audit-more-entries",
+                            shown = shown,
+                            total = total,
+                            next = list.offset + shown
+                        )
+                    );
+                }
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("audit-read-failed", error = e));
+            1
+        }
+    }
+}
+
+async fn run_capacity(
+    data_dir: &Path,
+    limits: capacity::CapacityLimits,
+    format: OutputFormat,
+) -> i32 {
+    let counts = match capacity::list_ruleset_counts().await {
+        Ok(counts) => counts,
+        Err(e) => {
+            eprintln!("{}", tr!("nft-ruleset-read-failed", error = e));
+            return 1;
+        }
+    };
+
+    // The trend is best effort: a missing database just means no history yet
+    let week_ago = chrono::Utc::now().timestamp() - 7 * 86400;
+    let previous = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => match db.execute(&DbOp::GetCapacitySampleAt(week_ago)).await {
+            Ok(DbOpResult::CapacitySample(sample)) => sample,
+            _ => None,
+        },
+        Err(e) => {
+            eprintln!("{}", tr!("capacity-trend-unavailable", error = e));
+            None
+        }
+    };
+
+    let report = capacity::CapacityReport::new(counts, previous.as_ref(), &limits);
+    output::emit(&report, format);
+    0
+}
+
+fn run_examples(scenario: Option<Scenario>, format: OutputFormat) -> i32 {
+    let Some(scenario) = scenario else {
+        output::emit(&TemplateList::all(), format);
+        return 0;
+    };
+
+    let template = scenario.template();
+    if let Err(e) = template.config() {
+        eprintln!("{}", tr!("example-schema-mismatch", error = e));
+        return 1;
+    }
+    match format {
+        OutputFormat::Json => match serde_json::to_string_pretty(&template) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", tr!("example-serialize-failed", error = e));
+                return 1;
+            }
+        },
+        OutputFormat::Table | OutputFormat::Wide => {
+            print!("# {}\n{}", template.summary, template.labels_yaml())
+        }
+    }
+    0
+}
+
+async fn run_enforcement(
+    data_dir: &Path,
+    container: Option<&str>,
+    mode: Option<EnforcementMode>,
+    format: OutputFormat,
+) -> i32 {
+    let mut db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    if let (Some(container), Some(mode)) = (container, mode) {
+        // Quarantining is an emergency block, which a freeze lets through
+        if mode != EnforcementMode::Quarantined
+            && let Some(code) = refuse_if_frozen(&db).await
+        {
+            return code;
+        }
+        if let Err(e) = enforcement::change_mode(&mut db, container, mode, "the command line").await
+        {
+            eprintln!("{}", tr!("enforcement-set-failed", error = e));
+            return 1;
+        }
+    }
+
+    let mut containers = match enforcement::list_overrides(&db).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            eprintln!("{}", tr!("enforcement-list-failed", error = e));
+            return 1;
+        }
+    };
+    if let Some(container) = container {
+        containers.retain(|c| c.container_name == container);
+        // Containers without an override are enforced
+        if containers.is_empty() {
+            containers.push(ContainerEnforcement {
+                container_name: container.to_string(),
+                mode: EnforcementMode::Enforce,
+                updated_at: 0,
+            });
+        }
+    }
+
+    output::emit(&enforcement::EnforcementReport { containers }, format);
+    0
+}
+
+/// Refuse an override during a change freeze, with the exit code to return
+async fn refuse_if_frozen(db: &DB) -> Option<i32> {
+    match freeze::current(db, chrono::Utc::now().timestamp()).await {
+        Ok(None) => None,
+        Ok(Some(_)) => {
+            eprintln!("{}", tr!("frozen-refused"));
+            Some(1)
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("freeze-read-failed", error = e));
+            Some(1)
+        }
+    }
+}
+
+async fn run_freeze(
+    data_dir: &Path,
+    until: Option<i64>,
+    reason: Option<&str>,
+    format: OutputFormat,
+) -> i32 {
+    let mut db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    let current = match freeze::freeze(&mut db, until, reason).await {
+        Ok(current) => current,
+        Err(e) => {
+            eprintln!("{}", tr!("freeze-failed", error = e));
+            return 1;
+        }
+    };
+    let held = freeze::held(&db).await.unwrap_or_default();
+    output::emit(
+        &freeze::FreezeReport {
+            freeze: Some(current),
+            held,
+        },
+        format,
+    );
+    0
+}
+
+async fn run_ban(
+    data_dir: &Path,
+    ip: std::net::Ipv4Addr,
+    duration: Duration,
+    reason: Option<&str>,
+    format: OutputFormat,
+) -> i32 {
+    if duration.is_zero() {
+        eprintln!("{}", tr!("ban-zero-duration"));
+        return 2;
+    }
+    let Some(mut db) = open_db(data_dir).await else {
+        return 1;
+    };
+    match bans::ban(&mut db, ip, duration, reason).await {
+        Ok(ban) => {
+            output::emit(&bans::BanReport { bans: vec![ban] }, format);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("ban-failed", error = e));
+            1
+        }
+    }
+}
+
+async fn run_unban(data_dir: &Path, ip: std::net::Ipv4Addr, reason: Option<&str>) -> i32 {
+    let Some(mut db) = open_db(data_dir).await else {
+        return 1;
+    };
+    match bans::unban(&mut db, ip, reason).await {
+        Ok(true) => 0,
+        Ok(false) => {
+            eprintln!("{}", tr!("ban-not-found", ip = ip));
+            1
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("unban-failed", error = e));
+            1
+        }
+    }
+}
+
+async fn run_bans(data_dir: &Path, format: OutputFormat) -> i32 {
+    let Some(db) = open_db(data_dir).await else {
+        return 1;
+    };
+    let now = chrono::Utc::now().timestamp();
+    match bans::list(&db).await {
+        Ok(mut list) => {
+            list.retain(|ban| ban.expires_at > now);
+            output::emit(&bans::BanReport { bans: list }, format);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("bans-list-failed", error = e));
+            1
+        }
+    }
+}
+
+/// Engage the kill switch and cut off the stored container addresses
+/// without waiting for the daemon
+async fn run_panic(
+    data_dir: &Path,
+    allow: &[ipnet::IpNet],
+    reason: Option<&str>,
+    format: OutputFormat,
+) -> i32 {
+    let Some(mut db) = open_db(data_dir).await else {
+        return 1;
+    };
+    let engaged = match panic::engage(&mut db, allow, reason).await {
+        Ok(engaged) => engaged,
+        Err(e) => {
+            eprintln!("{}", tr!("panic-failed", error = e));
+            return 1;
+        }
+    };
+    let containers = panic::addresses(&db).await.unwrap_or_default();
+    let installed = harborshield::nftables::panic::install(&containers, allow).await;
+    output::emit(
+        &panic::PanicReport {
+            panic: Some(engaged),
+        },
+        format,
+    );
+    if let Err(e) = installed {
+        // Stored, so a running daemon installs it within a second, but
+        // without one nothing is cut off yet
+        eprintln!("{}", tr!("panic-install-failed", error = e));
+        return 1;
+    }
+    0
+}
+
+async fn run_panic_release(data_dir: &Path, reason: Option<&str>, format: OutputFormat) -> i32 {
+    let Some(mut db) = open_db(data_dir).await else {
+        return 1;
+    };
+    match panic::release(&mut db, reason).await {
+        Ok(true) => {}
+        Ok(false) => {
+            eprintln!("{}", tr!("panic-not-engaged"));
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("panic-release-failed", error = e));
+            return 1;
+        }
+    }
+    if let Err(e) = harborshield::nftables::panic::uninstall().await {
+        eprintln!("{}", tr!("panic-uninstall-failed", error = e));
+        return 1;
+    }
+    output::emit(&panic::PanicReport { panic: None }, format);
+    0
+}
+
+/// The freeze and what it held, with the diff applying it makes unless
+/// JSON was asked for
+async fn run_unfreeze_review(
+    args: &Args,
+    db_path: &Path,
+    guests: Option<Vec<harborshield::guests::GuestSpec>>,
+) -> i32 {
+    let db = match DB::builder().db_path(db_path).build().await {
+        Ok(db) => db,
+        Err(e) => {
+            error!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+    let report = match (freeze::current(&db, now).await, freeze::held(&db).await) {
+        (Ok(freeze), Ok(held)) => freeze::FreezeReport { freeze, held },
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", tr!("freeze-read-failed", error = e));
+            return 1;
+        }
+    };
+    drop(db);
+
+    output::emit(&report, args.output);
+    if args.output == OutputFormat::Json || report.freeze.is_none() {
+        return 0;
+    }
+    println!();
+    let code = run_plan(args, db_path, guests).await;
+    if code == 0 {
+        println!("{}", tr!("unfreeze-confirm-hint"));
+    }
+    code
+}
+
+async fn run_unfreeze(data_dir: &Path, format: OutputFormat) -> i32 {
+    let mut db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    match freeze::current(&db, now).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            eprintln!("{}", tr!("unfreeze-not-frozen"));
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("freeze-read-failed", error = e));
+            return 1;
+        }
+    }
+    let held = freeze::held(&db).await.unwrap_or_default();
+    if let Err(e) = freeze::lift(&mut db, now, "unfrozen from the command line").await {
+        eprintln!("{}", tr!("unfreeze-failed", error = e));
+        return 1;
+    }
+
+    output::emit(&freeze::FreezeReport { freeze: None, held }, format);
+    0
+}
+
+async fn run_release(data_dir: &Path, container: &str, format: OutputFormat) -> i32 {
+    let mut db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    if let Some(code) = refuse_if_frozen(&db).await {
+        return code;
+    }
+    match db.execute(&DbOp::GetEnforcementMode(container)).await {
+        Ok(DbOpResult::EnforcementMode(EnforcementMode::Quarantined)) => {}
+        Ok(_) => {
+            eprintln!("{}", tr!("release-not-quarantined", container = container));
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("enforcement-read-failed", error = e));
+            return 1;
+        }
+    }
+    if let Err(e) = enforcement::set_mode(&db, container, EnforcementMode::Enforce).await {
+        eprintln!(
+            "{}",
+            tr!("release-failed", container = container, error = e)
+        );
+        return 1;
+    }
+
+    let entry = AuditEntry::builder()
+        .ts(chrono::Utc::now().timestamp())
+        .kind(audit::KIND_QUARANTINE_RELEASED)
+        .container_name(container.to_string())
+        .detail("released from the command line".to_string())
+        .build();
+    if let Err(e) = audit::record(&mut db, &[entry]).await {
+        eprintln!("{}", tr!("release-record-failed", error = e));
+    }
+
+    output::emit(
+        &enforcement::EnforcementReport {
+            containers: vec![ContainerEnforcement {
+                container_name: container.to_string(),
+                mode: EnforcementMode::Enforce,
+                updated_at: chrono::Utc::now().timestamp(),
+            }],
+        },
+        format,
+    );
+    0
+}
+
+async fn run_learn(
+    data_dir: &Path,
+    container: Option<&str>,
+    duration: Duration,
+    stop: bool,
+    format: OutputFormat,
+) -> i32 {
+    let mut db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    if let Some(container) = container {
+        if let Some(code) = refuse_if_frozen(&db).await {
+            return code;
+        }
+        let result = if stop {
+            learning::finish(&mut db, container).await
+        } else {
+            learning::start(&mut db, container, duration).await
+        };
+        if let Err(e) = result {
+            eprintln!("{}", tr!("learn-update-failed", error = e));
+            return 1;
+        }
+    }
+
+    let mut sessions = match learning::sessions(&db).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("{}", tr!("learn-list-failed", error = e));
+            return 1;
+        }
+    };
+    if let Some(container) = container {
+        sessions.retain(|s| s.container_name == container);
+    }
+
+    output::emit(&learning::LearningReport { sessions }, format);
+    0
+}
+
+async fn run_apply_adhoc(
+    data_dir: &Path,
+    source: &Path,
+    container: &str,
+    ttl: Duration,
+    reason: Option<&str>,
+    format: OutputFormat,
+) -> i32 {
+    let read = if source == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(source)
+    };
+    let rules = match read {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                tr!("adhoc-read-failed", source = source.display(), error = e)
+            );
+            return 1;
+        }
+    };
+    if let Err(e) = profiles::parse_rules(&rules) {
+        eprintln!("{}", tr!("adhoc-invalid-rules", error = e));
+        return 1;
+    }
+    if ttl.is_zero() {
+        eprintln!("{}", tr!("adhoc-zero-ttl"));
+        return 2;
+    }
+
+    let mut db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    if let Some(code) = refuse_if_frozen(&db).await {
+        return code;
+    }
+    match adhoc::apply(&mut db, container, &rules, ttl, reason).await {
+        Ok(rule) => {
+            output::emit(&adhoc::AdhocReport { rules: vec![rule] }, format);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("adhoc-apply-failed", error = e));
+            1
+        }
+    }
+}
+
+async fn run_suggest(data_dir: &Path, container: &str, format: OutputFormat) -> i32 {
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    let flows = match learning::flows(&db, container).await {
+        Ok(flows) => flows,
+        Err(e) => {
+            eprintln!("{}", tr!("suggest-flows-failed", error = e));
+            return 1;
+        }
+    };
+    let learning_until = learning::sessions(&db)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .find(|s| s.container_name == container)
+        .map(|s| s.ends_at);
+
+    let suggestion = learning::Suggestion::new(container, flows);
+    match format {
+        OutputFormat::Json => match serde_json::to_string_pretty(&suggestion) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", tr!("suggest-serialize-failed", error = e));
+                return 1;
+            }
+        },
+        OutputFormat::Table | OutputFormat::Wide => {
+            print!(
+                "# Suggested from {} observed flows of {}; review before applying\n{}",
+                suggestion.flows.len(),
+                container,
+                templates::labels_yaml(&suggestion.rules)
+            )
+        }
+    }
+    if let Some(until) = learning_until {
+        let until = chrono::DateTime::from_timestamp(until, 0)
+            .map_or_else(|| until.to_string(), |t| t.to_rfc3339());
+        eprintln!(
+            "{}",
+            tr!(
+                "suggest-still-learning",
+                container = container,
+                until = until
+            )
+        );
+    }
+    0
+}
+
+async fn run_unused(data_dir: &Path, idle: Duration, format: OutputFormat) -> i32 {
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
+
+    let recorded = match activity::list(&db).await {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            eprintln!("{}", tr!("unused-read-failed", error = e));
+            return 1;
+        }
+    };
+    let report = activity::UnusedReport {
+        after: idle.as_secs(),
+        rules: activity::unused(&recorded, chrono::Utc::now().timestamp(), idle),
+    };
+    output::emit(&report, format);
+    if report.rules.is_empty() { 0 } else { 1 }
+}
+
+async fn run_doctor(
+    data_dir: &Path,
+    timeout: Duration,
+    label_prefixes: &[String],
+    format: OutputFormat,
+) -> i32 {
+    labels::set_label_prefixes(label_prefixes);
+
+    let docker = match runtime::connect(runtime::kind(), timeout) {
+        Ok(docker) => docker,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "docker-connect-failed",
+                    runtime = runtime::kind(),
+                    error = e
+                )
+            );
+            return 1;
+        }
+    };
+
+    let findings = match doctor::check_service_mesh(docker.as_ref()).await {
+        Ok(findings) => findings,
+        Err(e) => {
+            eprintln!("{}", tr!("doctor-inspect-failed", error = e));
+            return 1;
+        }
+    };
+
+    let findings: Vec<doctor::Finding> = match doctor::check_published_ports(docker.as_ref()).await
+    {
+        Ok(ports) => findings.into_iter().chain(ports).collect(),
+        Err(e) => {
+            eprintln!("{}", tr!("doctor-inspect-failed", error = e));
+            return 1;
+        }
+    };
+
+    // Sampling findings are kept by the daemon; none without its database
+    let since = chrono::Utc::now().timestamp() - sampling::REPORT_WINDOW.as_secs() as i64;
+    let findings = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => match doctor::check_sampled_rules(&db, since).await {
+            Ok(sampled) => findings.into_iter().chain(sampled).collect(),
+            Err(_) => findings,
+        },
+        Err(_) => findings,
+    };
+
+    let report = doctor::DoctorReport { findings };
+    output::emit(&report, format);
+    if report.has_warnings() { 1 } else { 0 }
+}
+
+async fn run_report(
+    data_dir: &Path,
+    since: Duration,
+    report_format: ReportFormat,
+    dir: Option<&Path>,
+    timeout: Duration,
+    label_prefixes: &[String],
+    format: OutputFormat,
+) -> i32 {
+    labels::set_label_prefixes(label_prefixes);
 
-    /// Enable debug logging
-    #[arg(long)]
-    debug: bool,
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("{}", tr!("db-open-failed", error = e));
+            return 1;
+        }
+    };
 
-    /// Path to log to (use "stdout" or "stderr" for console output)
-    #[arg(short = 'l', long, default_value = "stdout")]
-    log_path: String,
+    // Without Docker the report goes out without validation warnings
+    let mut containers = Vec::new();
+    match runtime::connect(runtime::kind(), timeout) {
+        Ok(docker) => match docker.list_containers().await {
+            Ok(summaries) => {
+                for id in summaries.into_iter().filter_map(|s| s.id) {
+                    if let Ok(container) = docker.try_get_container_by_id(&id).await {
+                        containers.push(container);
+                    }
+                }
+            }
+            Err(e) => eprintln!("{}", tr!("report-containers-unavailable", error = e)),
+        },
+        Err(e) => eprintln!("{}", tr!("report-containers-unavailable", error = e)),
+    }
 
-    /// Timeout for Docker API requests
-    #[arg(short = 't', long, default_value = "10s", value_parser = parse_duration)]
+    let now = chrono::Utc::now().timestamp();
+    let report = match ConsistencyReport::collect(&db, since, now, rule_warnings(&containers)).await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", tr!("report-failed", error = e));
+            return 1;
+        }
+    };
+
+    if format == OutputFormat::Json {
+        output::emit(&report, format);
+        return 0;
+    }
+    let body = report.render(report_format);
+    match dir {
+        Some(dir) => match write_report(dir, &report.file_name(report_format), &body) {
+            Ok(path) => println!("{}", path.display()),
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    tr!("report-write-failed", dir = dir.display(), error = e)
+                );
+                return 1;
+            }
+        },
+        None => print!("{}", body),
+    }
+    0
+}
+
+fn run_validate(
+    source: &Path,
+    known: &[String],
+    label_prefixes: &[String],
+    format: OutputFormat,
+) -> i32 {
+    labels::set_label_prefixes(label_prefixes);
+
+    let read = if source == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(source)
+    };
+    let text = match read {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                tr!("validate-read-failed", source = source.display(), error = e)
+            );
+            return 2;
+        }
+    };
+
+    let report = validate::check_document(&text, known);
+    output::emit(&report, format);
+    if report.is_valid() { 0 } else { 1 }
+}
+
+/// Addresses of the running containers, by the networks they are on
+async fn container_endpoints(
     timeout: Duration,
+) -> harborshield::Result<HashMap<std::net::IpAddr, adopt::Endpoint>> {
+    let docker = runtime::connect(runtime::kind(), timeout)?;
+    let mut endpoints = HashMap::new();
+    for summary in docker.list_containers().await? {
+        let Some(id) = summary.id else {
+            continue;
+        };
+        let container = docker.try_get_container_by_id(&id).await?;
+        for network in container.networks.values() {
+            for ip in &network.ip_addresses {
+                endpoints.insert(
+                    *ip,
+                    adopt::Endpoint {
+                        container: container.name.clone(),
+                        network: Some(network.name.clone()),
+                    },
+                );
+            }
+        }
+    }
+    Ok(endpoints)
+}
 
-    /// Enable health check server on specified address (e.g., "127.0.0.1:8080")
-    #[arg(long)]
-    health_server: Option<String>,
+async fn run_adopt(
+    source: &Path,
+    containers: &[(String, std::net::IpAddr)],
+    timeout: Duration,
+    label_prefixes: &[String],
+    format: OutputFormat,
+) -> i32 {
+    labels::set_label_prefixes(label_prefixes);
 
-    /// Print version and build information and exit
-    #[arg(long = "version-info")]
-    version_info: bool,
+    let read = if source == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(source)
+    };
+    let ruleset: serde_json::Value = match read
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+    {
+        Ok(ruleset) => ruleset,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                tr!("adopt-read-failed", source = source.display(), error = e)
+            );
+            return 2;
+        }
+    };
+
+    let known = if containers.is_empty() {
+        match container_endpoints(timeout).await {
+            Ok(known) => known,
+            Err(e) => {
+                eprintln!("{}", tr!("adopt-containers-failed", error = e));
+                return 1;
+            }
+        }
+    } else {
+        containers
+            .iter()
+            .map(|(name, ip)| {
+                (
+                    *ip,
+                    adopt::Endpoint {
+                        container: name.clone(),
+                        network: None,
+                    },
+                )
+            })
+            .collect()
+    };
+
+    let report = adopt::adopt(&ruleset, &known);
+    if format != OutputFormat::Json {
+        for suggestion in &report.containers {
+            print!(
+                "# Adopted from nftables rules for {}; review before applying\n{}\n",
+                suggestion.container_name,
+                templates::labels_yaml(&suggestion.rules)
+            );
+        }
+    }
+    output::emit(&report, format);
+    if report.has_skipped() { 1 } else { 0 }
+}
+
+/// Copy the database, with its write-ahead log, where a plan can change it
+fn scratch_database(db_path: &Path, runtime_dir: &Path) -> std::io::Result<PathBuf> {
+    let runtime_dir = harborshield::datadir::prepare(runtime_dir)?;
+    let dir = runtime_dir.join(format!("plan-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    for suffix in ["", "-wal", "-shm"] {
+        let from = PathBuf::from(format!("{}{}", db_path.display(), suffix));
+        if from.exists() {
+            std::fs::copy(&from, dir.join(format!("db.sqlite{}", suffix)))?;
+        }
+    }
+    Ok(dir)
+}
+
+async fn run_plan(
+    args: &Args,
+    db_path: &Path,
+    guests: Option<Vec<harborshield::guests::GuestSpec>>,
+) -> i32 {
+    let runtime_dir = harborshield::datadir::runtime_dir(args.runtime_dir.as_deref());
+    let scratch = match scratch_database(db_path, &runtime_dir) {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("{}", tr!("plan-database-failed", error = e));
+            return 1;
+        }
+    };
+    if let Err(e) = plan::begin().await {
+        error!("{}", tr!("plan-read-failed", error = e));
+        let _ = std::fs::remove_dir_all(&scratch);
+        return 1;
+    }
+
+    let report = match Harborshield::builder()
+        .db_path(&scratch.join("db.sqlite"))
+        .timeout(args.timeout)
+        .runtime(args.runtime)
+        .endpoints(&args.endpoints)
+        .nft_timeout(args.nft_timeout)
+        .label_prefixes(&args.label_prefixes)
+        .flowtable_devices(&args.flowtable_devices)
+        .identity_mode(args.identity)
+        .maybe_nflog_group(args.nflog_group)
+        .maybe_guests(guests)
+        .maybe_rules_dir(args.rules_dir.as_deref())
+        .build()
+        .await
+    {
+        Ok(harborshield) => harborshield.plan().await,
+        Err(e) => Err(e),
+    };
+    let _ = std::fs::remove_dir_all(&scratch);
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            error!("{}", tr!("plan-failed", error = e));
+            return 1;
+        }
+    };
+
+    if args.output == OutputFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).unwrap_or_default()
+        );
+    } else {
+        if report.diff.is_empty() {
+            println!("{}", tr!("plan-no-changes"));
+        } else {
+            print!("{}", output::paint_diff(&report.diff, output::use_color()));
+        }
+        for call in &report.unsimulated {
+            println!("{}", tr!("plan-unsimulated", call = call));
+        }
+    }
+    0
+}
+
+async fn run_flush(data_dir: &Path, confirm: bool, format: OutputFormat) -> i32 {
+    // Chains the database accounts for are ours even when nothing
+    // dispatches to them any more
+    let db = DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+        .ok();
+    let known = chain_names(db.as_ref()).await.into_keys().collect();
+
+    let plan = match flush::plan_current(&known).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}", tr!("flush-read-failed", error = e));
+            return 1;
+        }
+    };
+    if !confirm {
+        output::emit(
+            &flush::FlushReport {
+                plan,
+                applied: false,
+                database_reset: false,
+            },
+            format,
+        );
+        return 1;
+    }
+
+    if let Err(e) = flush::apply(&plan).await {
+        eprintln!("{}", tr!("flush-remove-failed", error = e));
+        return 1;
+    }
+    let database_reset = match &db {
+        Some(db) => match db.execute(&DbOp::ResetContainerState).await {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("{}", tr!("flush-reset-failed", error = e));
+                false
+            }
+        },
+        None => false,
+    };
+
+    output::emit(
+        &flush::FlushReport {
+            plan,
+            applied: true,
+            database_reset,
+        },
+        format,
+    );
+    if database_reset || db.is_none() { 0 } else { 1 }
+}
+
+/// Container chain names mapped to container names, from the database
+async fn chain_names(db: Option<&DB>) -> HashMap<String, String> {
+    let Some(db) = db else {
+        return HashMap::new();
+    };
+    match db.execute(&DbOp::ListContainers).await {
+        Ok(DbOpResult::Containers(containers)) => containers
+            .into_iter()
+            .map(|c| {
+                let chain = format!(
+                    "hs-{}-{}",
+                    c.name.replace(['_', '.', '/'], "-"),
+                    &c.id[..12.min(c.id.len())]
+                );
+                (chain, c.name)
+            })
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+async fn run_status(data_dir: &Path, per_rule: bool, format: OutputFormat) -> i32 {
+    let rules = match counters::list_rule_counters().await {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("{}", tr!("nft-counters-read-failed", error = e));
+            return 1;
+        }
+    };
+
+    // Chain names stand in for containers the database doesn't know
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => Some(db),
+        Err(e) => {
+            eprintln!("{}", tr!("top-names-unavailable", error = e));
+            None
+        }
+    };
+    let report = status::CountersReport::new(rules, &chain_names(db.as_ref()).await);
+    if per_rule {
+        output::emit(&report, format);
+    } else {
+        let mut status = status::StatusReport::from(&report);
+        status.interceptions = tproxy::list_interceptions().await.unwrap_or_default();
+        if let Some(db) = &db {
+            let since = chrono::Utc::now().timestamp() - sampling::REPORT_WINDOW.as_secs() as i64;
+            status.suspect_rules = doctor::suspect_rules(db, since).await.unwrap_or_default();
+        }
+        output::emit(&status, format);
+    }
+    0
+}
+
+async fn run_top(
+    data_dir: &Path,
+    interval: Duration,
+    timeout: Duration,
+    format: OutputFormat,
+) -> i32 {
+    let mut sampler = counters::CounterSampler::default();
+    match counters::list_chain_counters().await {
+        Ok(current) => {
+            sampler.deltas(current);
+        }
+        Err(e) => {
+            eprintln!("{}", tr!("nft-counters-read-failed", error = e));
+            return 1;
+        }
+    }
+
+    // Names and events are best effort: rates show without them
+    let db = match DB::builder()
+        .db_path(&data_dir.join("db.sqlite"))
+        .build()
+        .await
+    {
+        Ok(db) => Some(db),
+        Err(e) => {
+            eprintln!("{}", tr!("top-names-unavailable", error = e));
+            None
+        }
+    };
+    let events = Arc::new(Mutex::new(top::EventLog::default()));
+    match runtime::connect(runtime::kind(), timeout) {
+        Ok(docker) => {
+            let events = Arc::clone(&events);
+            tokio::spawn(async move {
+                let Ok(stream) = docker.events().await else {
+                    return;
+                };
+                futures::pin_mut!(stream);
+                while let Some(Ok(event)) = stream.next().await {
+                    if let (Some(event), Ok(mut log)) =
+                        (top::RecentEvent::from_event(&event), events.lock())
+                    {
+                        log.push(event);
+                    }
+                }
+            });
+        }
+        Err(e) => eprintln!("{}", tr!("top-events-unavailable", error = e)),
+    }
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let mut last = std::time::Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = ticker.tick() => {}
+        }
+
+        let deltas = match counters::list_chain_counters().await {
+            Ok(current) => sampler.deltas(current),
+            Err(e) => {
+                eprintln!("{}", tr!("nft-counters-read-failed", error = e));
+                return 1;
+            }
+        };
+        let elapsed = last.elapsed();
+        last = std::time::Instant::now();
+
+        let recent = events.lock().map(|log| log.snapshot()).unwrap_or_default();
+        let view = top::TopView::new(&deltas, &chain_names(db.as_ref()).await, elapsed, recent);
+        match format {
+            // One snapshot per line, for piping into other tools
+            OutputFormat::Json => match serde_json::to_string(&view) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    eprintln!("{}", tr!("top-serialize-failed", error = e));
+                    return 1;
+                }
+            },
+            OutputFormat::Table | OutputFormat::Wide => {
+                let mut stdout = std::io::stdout().lock();
+                let _ = write!(
+                    stdout,
+                    "{}{}",
+                    top::CLEAR_SCREEN,
+                    output::format(&view, format, output::use_color())
+                );
+                let _ = stdout.flush();
+            }
+        }
+    }
+    0
+}
+
+/// Re-read the guests file and reconcile, keeping the current guests if
+/// the file no longer loads
+async fn reload(harborshield: &Harborshield, guests_path: Option<&Path>) {
+    info!("Received SIGHUP, reloading");
+    let guests = match guests_path.map(harborshield::guests::load) {
+        Some(Ok(guests)) => Some(guests),
+        Some(Err(e)) => {
+            error!("{}", tr!("reload-guests-failed", error = e));
+            None
+        }
+        None => None,
+    };
+    match harborshield.reload(guests).await {
+        Ok(report) => info!("Reload finished: {}", report.summary()),
+        Err(e) => error!("{}", tr!("reload-failed", error = e)),
+    }
+}
+
+async fn run_applier(socket: &Path, controller_uid: Option<u32>, nft_timeout: Duration) -> i32 {
+    runner::set_nft_timeout(nft_timeout);
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let serving = applier::serve(socket, controller_uid, cancel.clone());
+    tokio::pin!(serving);
+    let result = tokio::select! {
+        result = &mut serving => result,
+        _ = shutdown_signal() => {
+            cancel.cancel();
+            serving.await
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            error!(
+                "{}",
+                tr!("applier-failed", socket = socket.display(), error = e)
+            );
+            1
+        }
+    }
+}
+
+fn parse_time_of_day(s: &str) -> Result<chrono::NaiveTime, String> {
+    chrono::NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|_| format!("Invalid time '{}', expected HH:MM", s))
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
     // Load .env file if it exists
     if let Err(e) = dotenvy::dotenv() {
         // It's ok if .env doesn't exist, but log other errors
         if e.not_found() {
             // Silent - .env is optional
         } else {
-            eprintln!("Error loading .env file: {}", e);
+            eprintln!("{}", tr!("env-load-failed", error = e));
         }
     }
 
     let args = Args::parse();
 
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(threads) = args.worker_threads {
+        builder.worker_threads(threads.into());
+    }
+    if let Some(threads) = args.max_blocking_threads {
+        builder.max_blocking_threads(threads.into());
+    }
+    match builder.build() {
+        Ok(runtime) => runtime.block_on(run(args)),
+        Err(e) => {
+            eprintln!("{}", tr!("runtime-start-failed", error = e));
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run(args: Args) {
     if args.version_info {
         println!("harborshield {}", VERSION);
         // Note: RUSTC_VERSION would need to be set at build time
         println!("harborshield-rust (Rust port)");
+        if harborshield::offline::COMPILED {
+            println!("offline build: automatic outbound calls are compiled out");
+        }
+        if harborshield::systemd::COMPILED {
+            println!("systemd build: readiness and watchdog are reported over sd_notify");
+        }
         return;
     }
 
+    harborshield::offline::set_offline(args.offline);
+    harborshield::low_memory::set_enabled(args.low_memory);
+    runtime::set_kind(args.runtime);
+    dns::set_default_policy(LookupPolicy {
+        family: args.dns_family,
+        require_both: args.dns_require_both,
+    });
+    dns::set_default_refresh(args.dns_refresh);
+    exposure::set_sensitive_ports(args.sensitive_ports.clone());
+
+    match Catalog::load_configured(args.messages.as_deref()) {
+        Ok(Some(catalog)) => i18n::set_localizer(Some(Box::new(catalog))),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(Command::Applier { .. }) = &args.command {
+        if args.applier.is_some() {
+            eprintln!("{}", tr!("applier-given-to-applier"));
+            std::process::exit(2);
+        }
+    } else {
+        applier::set_socket(args.applier.clone());
+    }
+
+    match ColumnKey::load(args.db_key_file.as_deref()) {
+        Ok(key) => crypto::set_column_key(key),
+        Err(e) => {
+            eprintln!("{}", tr!("db-key-load-failed", error = e));
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = &args.profiles {
+        match profiles::load(path) {
+            Ok(loaded) => profiles::set_profiles(loaded),
+            Err(e) => {
+                eprintln!("{}", tr!("profiles-load-failed", error = e));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match &args.command {
+        Some(Command::Status { counters }) => {
+            std::process::exit(run_status(&args.data_dir, *counters, args.output).await);
+        }
+        Some(Command::Stats { since, container }) => {
+            std::process::exit(
+                run_stats(&args.data_dir, *since, container.as_deref(), args.output).await,
+            );
+        }
+        Some(Command::Capacity {
+            max_chains,
+            max_rules,
+            max_set_elements,
+            warn_percent,
+        }) => {
+            let limits = capacity::CapacityLimits {
+                chains: *max_chains,
+                rules: *max_rules,
+                set_elements: *max_set_elements,
+                warn_percent: *warn_percent,
+            };
+            std::process::exit(run_capacity(&args.data_dir, limits, args.output).await);
+        }
+        Some(Command::Examples { scenario }) => {
+            std::process::exit(run_examples(*scenario, args.output));
+        }
+        Some(Command::Enforcement { container, mode }) => {
+            std::process::exit(
+                run_enforcement(&args.data_dir, container.as_deref(), *mode, args.output).await,
+            );
+        }
+        Some(Command::Release { container }) => {
+            std::process::exit(run_release(&args.data_dir, container, args.output).await);
+        }
+        Some(Command::Learn {
+            container,
+            duration,
+            stop,
+        }) => {
+            std::process::exit(
+                run_learn(
+                    &args.data_dir,
+                    container.as_deref(),
+                    *duration,
+                    *stop,
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::ApplyAdhoc {
+            source,
+            container,
+            ttl,
+            reason,
+        }) => {
+            std::process::exit(
+                run_apply_adhoc(
+                    &args.data_dir,
+                    source,
+                    container,
+                    *ttl,
+                    reason.as_deref(),
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::Unused { idle }) => {
+            std::process::exit(run_unused(&args.data_dir, *idle, args.output).await);
+        }
+        Some(Command::Suggest { container }) => {
+            std::process::exit(run_suggest(&args.data_dir, container, args.output).await);
+        }
+        Some(Command::Audit {
+            since,
+            container,
+            project,
+            limit,
+            offset,
+            fields,
+        }) => {
+            labels::set_label_prefixes(&args.label_prefixes);
+            let list = ListQuery {
+                container: container.clone(),
+                project: project.clone(),
+                since: Some(*since),
+                limit: *limit,
+                offset: *offset,
+                fields: fields.clone(),
+            };
+            std::process::exit(
+                run_audit(
+                    &args.data_dir,
+                    &list,
+                    args.timeout,
+                    args.identity,
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::Top { interval }) => {
+            std::process::exit(run_top(&args.data_dir, *interval, args.timeout, args.output).await);
+        }
+        Some(Command::Flush { confirm }) => {
+            std::process::exit(run_flush(&args.data_dir, *confirm, args.output).await);
+        }
+        Some(Command::Freeze { until, reason }) => {
+            std::process::exit(
+                run_freeze(&args.data_dir, *until, reason.as_deref(), args.output).await,
+            );
+        }
+        Some(Command::Unfreeze { confirm: true }) => {
+            std::process::exit(run_unfreeze(&args.data_dir, args.output).await);
+        }
+        Some(Command::Ban {
+            ip,
+            duration,
+            reason,
+        }) => {
+            std::process::exit(
+                run_ban(
+                    &args.data_dir,
+                    *ip,
+                    *duration,
+                    reason.as_deref(),
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::Unban { ip, reason }) => {
+            std::process::exit(run_unban(&args.data_dir, *ip, reason.as_deref()).await);
+        }
+        Some(Command::Bans) => {
+            std::process::exit(run_bans(&args.data_dir, args.output).await);
+        }
+        Some(Command::Panic {
+            release: false,
+            allow,
+            reason,
+        }) => {
+            std::process::exit(
+                run_panic(&args.data_dir, allow, reason.as_deref(), args.output).await,
+            );
+        }
+        Some(Command::Panic {
+            release: true,
+            reason,
+            ..
+        }) => {
+            std::process::exit(
+                run_panic_release(&args.data_dir, reason.as_deref(), args.output).await,
+            );
+        }
+        Some(Command::Export {
+            what: Export::Dashboard,
+        }) => {
+            let dashboard = harborshield::dashboard::grafana_dashboard(VERSION);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&dashboard).unwrap_or_default()
+            );
+            std::process::exit(0);
+        }
+        Some(Command::Export {
+            what: Export::EventSchema,
+        }) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&harborshield::events::schema()).unwrap_or_default()
+            );
+            std::process::exit(0);
+        }
+        Some(Command::Events {
+            what:
+                Events::Export {
+                    since,
+                    format,
+                    kind,
+                    file,
+                },
+        }) => {
+            std::process::exit(
+                run_events_export(&args.data_dir, *since, *format, *kind, file.as_deref()).await,
+            );
+        }
+        Some(Command::Db {
+            what: Db::Backup { path },
+        }) => {
+            std::process::exit(run_db_backup(&args.data_dir, path, args.output).await);
+        }
+        Some(Command::Db {
+            what: Db::Export { format, file },
+        }) => {
+            std::process::exit(run_db_export(&args.data_dir, *format, file.as_deref()).await);
+        }
+        Some(Command::Db {
+            what: Db::Schema { format, file },
+        }) => {
+            std::process::exit(run_db_schema(*format, file.as_deref()).await);
+        }
+        Some(Command::Db {
+            what: Db::Restore { path, confirm },
+        }) => {
+            std::process::exit(run_db_restore(&args.data_dir, path, *confirm, args.output).await);
+        }
+        // Run with the daemon's logging and capability check below
+        Some(Command::Applier { .. } | Command::Plan | Command::Unfreeze { confirm: false }) => {}
+        Some(Command::Doctor) => {
+            std::process::exit(
+                run_doctor(
+                    &args.data_dir,
+                    args.timeout,
+                    &args.label_prefixes,
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::Report { since, format, dir }) => {
+            std::process::exit(
+                run_report(
+                    &args.data_dir,
+                    *since,
+                    *format,
+                    dir.as_deref(),
+                    args.timeout,
+                    &args.label_prefixes,
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::Adopt {
+            from_nft,
+            containers,
+        }) => {
+            std::process::exit(
+                run_adopt(
+                    from_nft,
+                    containers,
+                    args.timeout,
+                    &args.label_prefixes,
+                    args.output,
+                )
+                .await,
+            );
+        }
+        Some(Command::Validate { source, known }) => {
+            std::process::exit(run_validate(
+                source,
+                known,
+                &args.label_prefixes,
+                args.output,
+            ));
+        }
+        None => {}
+    }
+
     // Initialize logging
     let env_filter = if args.debug {
         EnvFilter::new("debug")
@@ -67,7 +2621,17 @@ async fn main() {
 
     let subscriber = tracing_subscriber::registry().with(env_filter);
 
-    if args.log_path == "stdout" || args.log_path == "stderr" {
+    // A plan's diff goes to stdout, so its logs don't
+    let planning = args.dry_run
+        || matches!(
+            args.command,
+            Some(Command::Plan | Command::Unfreeze { confirm: false })
+        );
+    if args.log_path == "stderr" || (planning && args.log_path == "stdout") {
+        let subscriber = subscriber.with(fmt::layer().with_writer(std::io::stderr));
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("Failed to set tracing subscriber");
+    } else if args.log_path == "stdout" {
         let subscriber = subscriber.with(fmt::layer());
         tracing::subscriber::set_global_default(subscriber)
             .expect("Failed to set tracing subscriber");
@@ -84,9 +2648,14 @@ async fn main() {
 
     // Check for required capabilities
     #[cfg(target_os = "linux")]
-    {
+    if let Some(socket) = &args.applier {
+        info!(
+            "Applying nft calls through the applier on {}",
+            socket.display()
+        );
+    } else {
         if let Err(e) = harborshield::security::check_capabilities() {
-            error!("Capability check failed: {}", e);
+            error!("{}", tr!("capability-check-failed", error = e));
 
             // The error message already includes remediation information
             // from the SecurityError::MissingCapability Display implementation
@@ -96,28 +2665,187 @@ async fn main() {
         info!("All required capabilities are present");
     }
 
-    // Get absolute path for data directory
-    let data_dir = match args.data_dir.canonicalize() {
+    if args.nft_thread
+        && let Err(e) = dedicated::start(args.nft_thread_nice)
+    {
+        error!("{}", tr!("nft-thread-failed", error = e));
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Applier {
+        socket,
+        controller_uid,
+    }) = &args.command
+    {
+        std::process::exit(run_applier(socket, *controller_uid, args.nft_timeout).await);
+    }
+
+    // A plan only reads the database, so its directory may be read-only
+    let data_dir = if planning {
+        harborshield::datadir::prepare(&args.data_dir)
+    } else {
+        harborshield::datadir::prepare_data_dir(&args.data_dir)
+    };
+    let data_dir = match data_dir {
         Ok(path) => path,
         Err(e) => {
-            error!("Failed to get absolute path for data directory: {}", e);
+            error!("{}", tr!("data-dir-failed", error = e));
             std::process::exit(1);
         }
     };
 
     let db_path = data_dir.join("db.sqlite");
 
+    // Absolute, so the file is found again on reload
+    let guests_path = args
+        .guests
+        .as_ref()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+    let guests = match guests_path.as_deref().map(harborshield::guests::load) {
+        Some(Ok(guests)) => Some(guests),
+        Some(Err(e)) => {
+            error!("{}", tr!("guests-load-failed", error = e));
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    // Absolute and readable, as they are re-read once sandboxed
+    let sinkhole: Vec<PathBuf> = args
+        .sinkhole
+        .iter()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+        .collect();
+    for path in &sinkhole {
+        if let Err(e) = harborshield::dns::sinkhole::load(path) {
+            error!(
+                "{}",
+                tr!(
+                    "sinkhole-load-failed",
+                    path = path.display().to_string(),
+                    error = e
+                )
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let blocklists = match &args.blocklists {
+        Some(path) => match harborshield::blocklist::load(path) {
+            Ok(lists) => Some(lists.canonicalized()),
+            Err(e) => {
+                error!("{}", tr!("blocklists-load-failed", error = e));
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Absolute, as it is watched once sandboxed
+    let rules_dir = args
+        .rules_dir
+        .as_ref()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.clone()));
+    if let Some(dir) = &rules_dir
+        && let Err(e) = harborshield::docker::config::files::load_dir(dir)
+    {
+        error!(
+            "{}",
+            tr!(
+                "rules-dir-load-failed",
+                path = dir.display().to_string(),
+                error = e
+            )
+        );
+        std::process::exit(1);
+    }
+
+    if matches!(args.command, Some(Command::Unfreeze { confirm: false })) {
+        std::process::exit(run_unfreeze_review(&args, &db_path, guests).await);
+    }
+    if planning {
+        std::process::exit(run_plan(&args, &db_path, guests).await);
+    }
+
+    let api_tokens = match args.api_tokens.as_deref().map(harborshield::access::load) {
+        Some(Ok(tokens)) => Some(tokens),
+        Some(Err(e)) => {
+            error!("{}", tr!("api-tokens-load-failed", error = e));
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let update_check = if args.check_updates {
+        match harborshield::update::parse_public_key(args.update_key.as_deref()) {
+            Ok(public_key) => Some(harborshield::update::UpdateCheck {
+                manifest_url: args.update_manifest_url.clone(),
+                public_key,
+                interval: harborshield::update::DEFAULT_CHECK_INTERVAL,
+                webhook: args.update_webhook.clone(),
+            }),
+            Err(e) => {
+                error!("{}", tr!("update-check-failed", error = e));
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let event_bus = args.event_bus.clone().map(|mut config| {
+        config.prefix = args.event_bus_prefix.clone();
+        config
+    });
+
     // Create rule handlers with optional health server
     let harborshield = match Harborshield::builder()
         .db_path(&db_path)
         .timeout(args.timeout)
+        .runtime(args.runtime)
+        .nft_timeout(args.nft_timeout)
+        .label_prefixes(&args.label_prefixes)
+        .flowtable_devices(&args.flowtable_devices)
+        .flush_revoked_flows(args.flush_revoked_flows)
+        .identity_mode(args.identity)
+        .event_queue_capacity(args.event_queue_size)
+        .maybe_reconcile_schedule(args.reconcile_at.map(|at| ReconcileSchedule {
+            at,
+            repair: args.reconcile_repair,
+            webhook: args.reconcile_webhook.clone(),
+        }))
+        .maybe_report_schedule(args.report_at.map(|at| ReportSchedule {
+            at,
+            format: args.report_format,
+            dir: args.report_dir.clone(),
+            webhook: args.report_webhook.clone(),
+        }))
+        .maybe_update_check(update_check)
+        .maybe_quarantine_webhook(args.quarantine_webhook.clone())
+        .maybe_event_bus(event_bus)
+        .maybe_guests(guests)
+        .maybe_api_tokens(api_tokens)
+        .maybe_grpc_addr(args.grpc.clone())
+        .maybe_web_addr(args.web_addr.clone())
+        .maybe_unused_allow_after(args.notify_unused_after)
+        .maybe_validate_rules_every(args.validate_rules_every)
+        .maybe_nflog_group(args.nflog_group)
+        .maybe_ha_lock(args.ha_lock.as_deref())
+        .maybe_sinkhole((!sinkhole.is_empty()).then(|| sinkhole.clone()))
+        .maybe_blocklists(blocklists.clone())
+        .maybe_rules_dir(rules_dir.as_deref())
         .maybe_health_server_addr(args.health_server.as_deref())
+        .admin_endpoints(harborshield::server::Endpoints {
+            health: !args.disable_health,
+            metrics: !args.disable_metrics,
+            api: !args.disable_api,
+        })
         .build()
         .await
     {
         Ok(handlers) => handlers,
         Err(e) => {
-            error!("Failed to initialize rule handlers: {}", e);
+            error!("{}", tr!("handlers-init-failed", error = e));
             std::process::exit(1);
         }
     };
@@ -132,8 +2860,20 @@ async fn main() {
     #[cfg(target_os = "linux")]
     {
         // Apply security restrictions
-        if let Err(e) = harborshield::security::apply_restrictions(&db_path, log_path.as_deref()) {
-            error!("Failed to apply security restrictions: {}", e);
+        // The rules directory itself is needed, not its parent, so any name
+        // in it will do
+        let rules_file = rules_dir.as_ref().map(|dir| dir.join("rules.yml"));
+        let reloaded: Vec<&Path> = guests_path
+            .as_deref()
+            .into_iter()
+            .chain(sinkhole.iter().map(PathBuf::as_path))
+            .chain(blocklists.iter().flat_map(|lists| lists.files()))
+            .chain(rules_file.as_deref())
+            .collect();
+        if let Err(e) =
+            harborshield::security::apply_restrictions(&db_path, log_path.as_deref(), &reloaded)
+        {
+            error!("{}", tr!("restrictions-failed", error = e));
             std::process::exit(1);
         }
     }
@@ -142,7 +2882,7 @@ async fn main() {
     if args.clear {
         info!("Clearing all harborshield rules");
         if let Err(e) = harborshield.clear().await {
-            error!("Failed to clear rules: {}", e);
+            error!("{}", tr!("clear-failed", error = e));
             std::process::exit(1);
         }
         return;
@@ -150,19 +2890,41 @@ async fn main() {
 
     // Log version info
     info!("Starting harborshield v{}", VERSION);
+    if harborshield::offline::is_offline() {
+        info!("Offline: automatic outbound calls are disabled");
+    }
 
     // Start the rule handlers
     let harborshield = match harborshield.start().await {
         Ok(started_handlers) => started_handlers,
         Err(e) => {
-            error!("Failed to start rule handlers: {}", e);
+            error!("{}", tr!("handlers-start-failed", error = e));
             std::process::exit(1);
         }
     };
+    // The initial ruleset is in place
+    harborshield::systemd::ready();
 
-    // Wait for shutdown signal
-    shutdown_signal().await;
+    // Reload on SIGHUP until asked to shut down
+    let mut reloads = match ReloadSignal::new() {
+        Ok(reloads) => Some(reloads),
+        Err(e) => {
+            error!("{}", tr!("reload-signal-failed", error = e));
+            None
+        }
+    };
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some(()) = async { reloads.as_mut()?.recv().await }, if reloads.is_some() => {
+                reload(&harborshield, guests_path.as_deref()).await;
+            }
+        }
+    }
     info!("Shutting down");
+    harborshield::systemd::stopping();
 
     // Stop the rule handlers
     harborshield.stop().await;
@@ -0,0 +1,1033 @@
+//! The privileged half of a split deployment.
+//!
+//! `harborshield applier` is the only process that needs CAP_NET_ADMIN: it
+//! listens on a unix socket and runs `nft` for a daemon started with
+//! `--applier <socket>`, which keeps Docker events, rendering, the API and
+//! the database unprivileged. Each request is one JSON line naming the nft
+//! arguments and, for transactions, the JSON ruleset on stdin; the reply
+//! carries nft's exit status and output.
+//!
+//! The applier does not trust the controller. It accepts read-only `list`
+//! commands, emptying or deleting harborshield's own chains, `-j -f -`
+//! transactions that only touch the filter table's `harborshield`,
+//! `harborshield-fastpath`, `harborshield-sinkhole`, `harborshield-blocklist`,
+//! `harborshield-blocked` and `hs-*` objects, the kill switch's
+//! `inet harborshield-panic` table or the `harborshield-nat` and
+//! `harborshield-tproxy` tables, and `-f -` scripts that only insert raw
+//! rules into `hs-*` chains. Docker's chains may only gain the marked jump
+//! to `harborshield`, and only lose rules that are still that jump when the
+//! applier looks them up. The nat table may only hold its three base chains
+//! and DNAT and masquerade rules aimed at addresses the `harborshield`
+//! chain dispatches on when the applier looks; the tproxy table only its
+//! `prerouting` chain and rules handing flows to a local port.
+
+use crate::nftables::blocked::BLOCKED_SET;
+use crate::nftables::blocklist::BLOCKLIST_SET;
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::flowtable::{FASTPATH_CHAIN, FLOWTABLE};
+use crate::nftables::flush::JUMP_COMMENT;
+use crate::nftables::forward::{NAT_CHAINS, NAT_TABLE, POSTROUTING_CHAIN};
+use crate::nftables::panic::{self as kill_switch, PANIC_TABLE};
+use crate::nftables::rootless::ROOTLESS_CHAIN;
+use crate::nftables::runner::{self, NFT_PROGRAM};
+use crate::nftables::sinkhole::SINKHOLE_SET;
+use crate::nftables::tproxy::{self, TPROXY_TABLE};
+use crate::nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, counters};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::sync::RwLock;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_SOCKET: &str = "/run/harborshield/applier.sock";
+
+const FAMILIES: [&str; 2] = ["ip", "ip6"];
+
+/// Flags that only change how `list` output is printed
+const LIST_FLAGS: [&str; 4] = ["-j", "-a", "-n", "-s"];
+
+/// Families a listing may name, the panic table's `inet` included
+const LIST_FAMILIES: [&str; 3] = ["ip", "ip6", "inet"];
+
+static SOCKET: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Send every subsequent `nft` invocation to the applier on `socket`
+/// instead of running it here
+pub fn set_socket(socket: Option<PathBuf>) {
+    if let Ok(mut current) = SOCKET.write() {
+        *current = socket;
+    }
+}
+
+pub fn socket() -> Option<PathBuf> {
+    SOCKET.read().ok().and_then(|socket| socket.clone())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub operation: String,
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub stdin: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    /// Raw wait status of the nft process
+    #[serde(default)]
+    pub status: i32,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    /// Why the request was refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refused: Option<String>,
+    /// Why an accepted request could not be run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Why the applier refused a request
+#[derive(Error, Debug, PartialEq)]
+pub enum Rejected {
+    #[error("nft {0:?} is not an invocation the applier runs")]
+    Arguments(Vec<String>),
+
+    #[error("transaction is not valid nftables JSON: {0}")]
+    Json(String),
+
+    #[error("transaction touches {0}")]
+    Object(String),
+
+    #[error("script line {0:?} is not a raw rule in harborshield's chains")]
+    Script(String),
+
+    #[error("rule {handle} in chain {chain} is not harborshield's jump rule")]
+    ForeignRule { chain: String, handle: u64 },
+
+    #[error("{0} is not the address of a container harborshield dispatches on")]
+    UntrackedAddress(String),
+}
+
+/// A rule in a chain harborshield doesn't own, deleted by handle
+#[derive(Debug, Clone, PartialEq)]
+pub struct JumpDeletion {
+    pub family: String,
+    pub chain: String,
+    pub handle: u64,
+}
+
+/// What a transaction needs checked against the live ruleset before it runs
+#[derive(Debug, Default, PartialEq)]
+pub struct LiveChecks {
+    pub deletions: Vec<JumpDeletion>,
+    /// Addresses the nat table's rules are aimed at
+    pub nat_targets: Vec<String>,
+}
+
+/// Whether `name` is a plain table, chain or set name. nft joins its
+/// arguments into one script, so anything else could start a new command
+fn plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Whether an argument is free of what nft's parser treats as syntax: `;`
+/// and newlines start a command, `#` a comment, braces a block
+fn plain_arg(arg: &str) -> bool {
+    !arg.chars()
+        .any(|c| matches!(c, ';' | '#' | '{' | '}') || c.is_whitespace() || c.is_control())
+}
+
+/// The `list` invocations harborshield makes, flags aside
+fn listing(words: &[&str]) -> bool {
+    let family = |family: &str| LIST_FAMILIES.contains(&family);
+    match words {
+        ["list", "ruleset" | "tables"] => true,
+        ["list", "tables" | "chains", f] => family(f),
+        ["list", "chains" | "table", f, table] => family(f) && plain_name(table),
+        ["list", "chain" | "set", f, table, name] => {
+            family(f) && plain_name(table) && plain_name(name)
+        }
+        _ => false,
+    }
+}
+
+fn owned_chain(name: &str) -> bool {
+    plain_name(name)
+        && (name == HARBORSHIELD_CHAIN
+            || name == FASTPATH_CHAIN
+            || name == ROOTLESS_CHAIN
+            || name.starts_with("hs-"))
+}
+
+/// Check a request's arguments and transaction. Deleted rules outside
+/// harborshield's chains and the addresses forwarded to are returned so
+/// they can be checked against the live ruleset before the transaction runs
+pub fn validate(args: &[String], stdin: Option<&str>) -> std::result::Result<LiveChecks, Rejected> {
+    let refused = || Rejected::Arguments(args.to_vec());
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if !args.iter().all(|arg| plain_arg(arg)) {
+        return Err(refused());
+    }
+
+    match args.as_slice() {
+        ["-j", "-f", "-"] => {
+            let stdin = stdin.ok_or_else(refused)?;
+            return validate_transaction(stdin);
+        }
+        ["-f", "-"] | ["-c", "-f", "-"] => {
+            let stdin = stdin.ok_or_else(refused)?;
+            validate_script(stdin)?;
+            return Ok(LiveChecks::default());
+        }
+        ["--version"] => {}
+        ["flush" | "delete", "chain", family, table, chain]
+            if FAMILIES.contains(family) && *table == FILTER_TABLE && owned_chain(chain) => {}
+        _ => {
+            let words: Vec<&str> = args
+                .iter()
+                .copied()
+                .filter(|arg| !LIST_FLAGS.contains(arg))
+                .collect();
+            if !listing(&words) {
+                return Err(refused());
+            }
+        }
+    }
+
+    if stdin.is_some() {
+        return Err(refused());
+    }
+    Ok(LiveChecks::default())
+}
+
+fn field<'a>(object: &'a Value, key: &str) -> &'a str {
+    object.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+fn single_key(value: &Value) -> Option<(&str, &Value)> {
+    let object = value.as_object()?;
+    if object.len() != 1 {
+        return None;
+    }
+    object
+        .iter()
+        .next()
+        .map(|(key, value)| (key.as_str(), value))
+}
+
+fn validate_transaction(stdin: &str) -> std::result::Result<LiveChecks, Rejected> {
+    let json: Value = serde_json::from_str(stdin).map_err(|e| Rejected::Json(e.to_string()))?;
+    let items = json
+        .get("nftables")
+        .and_then(Value::as_array)
+        .ok_or_else(|| Rejected::Json("missing the nftables array".to_string()))?;
+
+    let mut checks = LiveChecks::default();
+    for item in items {
+        let (command, body) =
+            single_key(item).ok_or_else(|| Rejected::Json(format!("unexpected item {}", item)))?;
+        if command == "metainfo" {
+            continue;
+        }
+        if !matches!(
+            command,
+            "add" | "create" | "insert" | "replace" | "delete" | "flush"
+        ) {
+            return Err(Rejected::Object(format!("a {} command", command)));
+        }
+        let (kind, object) = single_key(body)
+            .ok_or_else(|| Rejected::Json(format!("unexpected {} body {}", command, body)))?;
+
+        let family = field(object, "family");
+        let table = match kind {
+            "table" => field(object, "name"),
+            _ => field(object, "table"),
+        };
+        if table == PANIC_TABLE && family == "inet" {
+            if !panic_object_allowed(kind, object) {
+                return Err(Rejected::Object(format!(
+                    "{} {} in {}",
+                    kind,
+                    field(object, "name"),
+                    PANIC_TABLE
+                )));
+            }
+            continue;
+        }
+        if !FAMILIES.contains(&family) {
+            return Err(Rejected::Object(format!("the {} family", family)));
+        }
+        // The nat and tproxy tables hold nothing but forwards and
+        // interceptions
+        if (table == NAT_TABLE || table == TPROXY_TABLE) && family == "ip" {
+            let allowed = if table == NAT_TABLE {
+                nat_object_allowed(command, kind, object, &mut checks.nat_targets)
+            } else {
+                tproxy_object_allowed(command, kind, object)
+            };
+            if !allowed {
+                return Err(Rejected::Object(format!(
+                    "{} {} in {}",
+                    kind,
+                    match kind {
+                        "rule" => field(object, "chain"),
+                        _ => field(object, "name"),
+                    },
+                    table
+                )));
+            }
+            continue;
+        }
+        if table != FILTER_TABLE {
+            return Err(Rejected::Object(format!("table {}", table)));
+        }
+
+        match kind {
+            // Adding the table is a no-op once Docker has created it
+            "table" if command == "add" => {}
+            // Only the fastpath chain hooks in, and only where flow offload works
+            "chain" if owned_chain(field(object, "name")) && base_chain_allowed(object) => {}
+            "rule" if owned_chain(field(object, "chain")) => {}
+            "rule" => {
+                let chain = field(object, "chain");
+                match command {
+                    "add" | "insert" if is_jump_rule(object) => {}
+                    "delete" => {
+                        let handle =
+                            object
+                                .get("handle")
+                                .and_then(Value::as_u64)
+                                .ok_or_else(|| {
+                                    Rejected::Object(format!(
+                                        "a rule in {} without a handle",
+                                        chain
+                                    ))
+                                })?;
+                        checks.deletions.push(JumpDeletion {
+                            family: family.to_string(),
+                            chain: chain.to_string(),
+                            handle,
+                        });
+                    }
+                    _ => return Err(Rejected::Object(format!("a {} rule in {}", command, chain))),
+                }
+            }
+            "flowtable" if field(object, "name") == FLOWTABLE => {}
+            "table" | "chain" | "flowtable" => {
+                return Err(Rejected::Object(format!(
+                    "{} {} {}",
+                    command,
+                    kind,
+                    field(object, "name")
+                )));
+            }
+            // Sets, maps, elements and named objects are all harborshield's
+            _ if field(object, "name").starts_with("hs-") => {}
+            _ if field(object, "name") == SINKHOLE_SET => {}
+            _ if field(object, "name") == BLOCKLIST_SET => {}
+            _ if field(object, "name") == BLOCKED_SET => {}
+            _ => {
+                return Err(Rejected::Object(format!(
+                    "{} {}",
+                    kind,
+                    field(object, "name")
+                )));
+            }
+        }
+    }
+    Ok(checks)
+}
+
+/// Raw rule scripts may only create the filter table and our chains and
+/// insert rules into those chains, one command per line
+fn validate_script(stdin: &str) -> std::result::Result<(), Rejected> {
+    for line in stdin.lines().filter(|line| !line.trim().is_empty()) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let allowed = !line.contains([';', '#'])
+            && match words.as_slice() {
+                ["add", "table", family, table] => {
+                    FAMILIES.contains(family) && *table == FILTER_TABLE
+                }
+                ["add", "chain", family, table, chain]
+                | ["insert", "rule", family, table, chain, _, ..] => {
+                    FAMILIES.contains(family) && *table == FILTER_TABLE && owned_chain(chain)
+                }
+                _ => false,
+            };
+        if !allowed {
+            return Err(Rejected::Script(line.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// The kill switch table holds its three base chains, each hooked where
+/// its name says, and rules in them that only match, count, accept or drop
+fn panic_object_allowed(kind: &str, object: &Value) -> bool {
+    let panic_chain = |name: &str| kill_switch::CHAINS.iter().any(|(chain, _)| *chain == name);
+    match kind {
+        "table" => true,
+        "chain" => {
+            let name = field(object, "name");
+            panic_chain(name)
+                && field(object, "hook") == name
+                && matches!(field(object, "policy"), "" | "accept")
+        }
+        "rule" => {
+            panic_chain(field(object, "chain"))
+                && object
+                    .get("expr")
+                    .and_then(Value::as_array)
+                    .is_some_and(|expr| {
+                        expr.iter().all(|statement| {
+                            matches!(
+                                single_key(statement),
+                                Some(("match" | "counter" | "accept" | "drop", _))
+                            )
+                        })
+                    })
+        }
+        _ => false,
+    }
+}
+
+/// A base chain named `name` hooked where its name says, accepting by
+/// default. Emptying or deleting it needs no hook
+fn named_base_chain(command: &str, object: &Value, name: &str) -> bool {
+    field(object, "name") == name
+        && match object.get("hook") {
+            None => matches!(command, "flush" | "delete"),
+            Some(_) => {
+                field(object, "hook") == name && matches!(field(object, "policy"), "" | "accept")
+            }
+        }
+}
+
+fn statements(rule: &Value) -> &[Value] {
+    rule.get("expr")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// The nat table holds prerouting and output DNATs and postrouting
+/// masquerades, each aimed at a container address collected in `targets`
+fn nat_object_allowed(
+    command: &str,
+    kind: &str,
+    object: &Value,
+    targets: &mut Vec<String>,
+) -> bool {
+    match kind {
+        "table" => true,
+        "chain" => NAT_CHAINS
+            .iter()
+            .any(|name| named_base_chain(command, object, name)),
+        "rule" => {
+            let chain = field(object, "chain");
+            if !NAT_CHAINS.contains(&chain) {
+                return false;
+            }
+            let masquerading = chain == POSTROUTING_CHAIN;
+            let mut translations = 0;
+            let mut aimed = None;
+            for statement in statements(object) {
+                match single_key(statement) {
+                    Some(("counter", _)) => {}
+                    Some(("match", matched)) => {
+                        let daddr = matched["left"]["payload"]["protocol"] == "ip"
+                            && matched["left"]["payload"]["field"] == "daddr";
+                        if daddr && masquerading {
+                            aimed = matched.get("right").and_then(Value::as_str);
+                        }
+                    }
+                    Some(("dnat", nat)) if !masquerading => {
+                        translations += 1;
+                        aimed = nat.get("addr").and_then(Value::as_str);
+                    }
+                    Some(("masquerade", _)) if masquerading => translations += 1,
+                    _ => return false,
+                }
+            }
+            match aimed {
+                Some(address) if translations == 1 => {
+                    targets.push(address.to_string());
+                    true
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// The tproxy table holds its prerouting chain and rules that mark flows
+/// and hand them to a local port
+fn tproxy_object_allowed(command: &str, kind: &str, object: &Value) -> bool {
+    match kind {
+        "table" => true,
+        "chain" => named_base_chain(command, object, tproxy::PREROUTING_CHAIN),
+        "rule" => {
+            field(object, "chain") == tproxy::PREROUTING_CHAIN
+                && statements(object)
+                    .iter()
+                    .all(|statement| match single_key(statement) {
+                        Some(("match" | "counter" | "mangle" | "accept", _)) => true,
+                        Some(("tproxy", tproxy)) => tproxy.get("addr").is_none(),
+                        _ => false,
+                    })
+        }
+        _ => false,
+    }
+}
+
+fn base_chain_allowed(chain: &Value) -> bool {
+    chain.get("hook").is_none()
+        || (field(chain, "name") == FASTPATH_CHAIN && field(chain, "hook") == "forward")
+        || (field(chain, "name") == ROOTLESS_CHAIN && field(chain, "hook") == "input")
+}
+
+/// The rule `create_jump_rules` adds to Docker's chains: counted, marked,
+/// and doing nothing but jump to `harborshield`
+fn is_jump_rule(rule: &Value) -> bool {
+    if field(rule, "comment") != JUMP_COMMENT {
+        return false;
+    }
+    let Some(expr) = rule.get("expr").and_then(Value::as_array) else {
+        return false;
+    };
+    let mut jumps = 0;
+    for statement in expr {
+        match single_key(statement) {
+            Some(("counter", _)) => {}
+            Some(("jump", target)) if field(target, "target") == HARBORSHIELD_CHAIN => jumps += 1,
+            _ => return false,
+        }
+    }
+    jumps == 1
+}
+
+/// Check each deletion names a rule that is currently harborshield's jump.
+/// `listing` holds the filter tables of every family deleted from
+pub fn verify_deletions(
+    listing: &Value,
+    deletions: &[JumpDeletion],
+) -> std::result::Result<(), Rejected> {
+    let rules: Vec<&Value> = listing
+        .get("nftables")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("rule"))
+        .collect();
+
+    for deletion in deletions {
+        let found = rules.iter().any(|rule| {
+            field(rule, "family") == deletion.family
+                && field(rule, "chain") == deletion.chain
+                && rule.get("handle").and_then(Value::as_u64) == Some(deletion.handle)
+                && is_jump_rule(rule)
+        });
+        if !found {
+            return Err(Rejected::ForeignRule {
+                chain: deletion.chain.clone(),
+                handle: deletion.handle,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check each nat target is an address the `ip` harborshield chain
+/// dispatches to a container chain on
+pub fn verify_nat_targets(
+    listing: &Value,
+    targets: &[String],
+) -> std::result::Result<(), Rejected> {
+    let mut dispatched = Vec::new();
+    for rule in listing
+        .get("nftables")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("rule"))
+        .filter(|rule| field(rule, "family") == "ip" && field(rule, "chain") == HARBORSHIELD_CHAIN)
+    {
+        for statement in statements(rule) {
+            let mappings = statement["vmap"]["data"]["set"].as_array();
+            dispatched.extend(
+                mappings
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|mapping| mapping.get(0)?.as_str()),
+            );
+        }
+    }
+    match targets
+        .iter()
+        .find(|target| !dispatched.contains(&target.as_str()))
+    {
+        Some(target) => Err(Rejected::UntrackedAddress(target.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Have the applier on `socket` run nft, as `run_program` would locally
+pub(crate) async fn forward(
+    socket: &Path,
+    operation: &str,
+    args: &[&str],
+    stdin: Option<String>,
+) -> Result<Output> {
+    let unreachable = |e| NftablesError::execution(socket.as_os_str(), e);
+    let request = Request {
+        operation: operation.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        stdin,
+    };
+    let mut line = serde_json::to_vec(&request).map_err(NftablesError::invalid_json)?;
+    line.push(b'\n');
+
+    let stream = UnixStream::connect(socket).await.map_err(unreachable)?;
+    let (read, mut write) = stream.into_split();
+    write.write_all(&line).await.map_err(unreachable)?;
+
+    let reply = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await
+        .map_err(unreachable)?
+        .ok_or_else(|| {
+            unreachable(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "applier closed the connection",
+            ))
+        })?;
+    let response: Response = serde_json::from_str(&reply).map_err(NftablesError::invalid_json)?;
+    if let Some(reason) = response.refused {
+        return Err(NftablesError::permission_denied(format!(
+            "applier refused {}: {}",
+            operation, reason
+        )));
+    }
+    if let Some(error) = response.error {
+        return Err(NftablesError::execution(
+            NFT_PROGRAM,
+            std::io::Error::other(format!("in the applier: {}", error)),
+        ));
+    }
+
+    Ok(Output {
+        status: ExitStatus::from_raw(response.status),
+        stdout: response.stdout.into_bytes(),
+        stderr: response.stderr.into_bytes(),
+    })
+}
+
+/// Serve the controller on `socket` until `cancel` fires. With
+/// `controller_uid`, connections from any other user but root are closed
+pub async fn serve(
+    socket: &Path,
+    controller_uid: Option<u32>,
+    cancel: CancellationToken,
+) -> std::io::Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if std::fs::symlink_metadata(socket).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o660))?;
+    info!("Applying nft transactions received on {}", socket.display());
+
+    loop {
+        let stream = tokio::select! {
+            _ = cancel.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept applier connection: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        let uid = stream.peer_cred().map(|cred| cred.uid()).ok();
+        let permitted = match (controller_uid, uid) {
+            (None, _) => true,
+            (Some(_), Some(0)) => true,
+            (Some(allowed), Some(uid)) => uid == allowed,
+            (Some(_), None) => false,
+        };
+        if !permitted {
+            warn!("Refusing applier connection from uid {:?}", uid);
+            continue;
+        }
+        tokio::spawn(async move {
+            if let Err(e) = connection(stream).await {
+                debug!("Applier connection ended: {}", e);
+            }
+        });
+    }
+
+    let _ = std::fs::remove_file(socket);
+    Ok(())
+}
+
+async fn connection(stream: UnixStream) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => handle(request).await,
+            Err(e) => Response {
+                refused: Some(format!("malformed request: {}", e)),
+                ..Response::default()
+            },
+        };
+        let mut reply = serde_json::to_vec(&response).map_err(std::io::Error::other)?;
+        reply.push(b'\n');
+        write.write_all(&reply).await?;
+    }
+    Ok(())
+}
+
+/// The filter tables `checks` need, as one listing
+async fn filter_tables(checks: &LiveChecks) -> std::result::Result<Value, String> {
+    let mut items = Vec::new();
+    for family in FAMILIES {
+        let needed = checks
+            .deletions
+            .iter()
+            .any(|deletion| deletion.family == family)
+            || (family == "ip" && !checks.nat_targets.is_empty());
+        if !needed {
+            continue;
+        }
+        let listing = if family == "ip" {
+            counters::list_filter_table("applier_verify")
+                .await
+                .map_err(|e| e.to_string())?
+        } else {
+            let output = runner::run_nft(
+                "applier_verify",
+                &["-j", "list", "table", family, FILTER_TABLE],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            if !output.status.success() {
+                return Err(format!("no {} {} table", family, FILTER_TABLE));
+            }
+            serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?
+        };
+        if let Some(Value::Array(listed)) = listing.get("nftables") {
+            items.extend(listed.iter().cloned());
+        }
+    }
+    Ok(serde_json::json!({ "nftables": items }))
+}
+
+async fn handle(request: Request) -> Response {
+    let refuse = |reason: String| {
+        warn!("Refused nft operation '{}': {}", request.operation, reason);
+        Response {
+            refused: Some(reason),
+            ..Response::default()
+        }
+    };
+
+    let checks = match validate(&request.args, request.stdin.as_deref()) {
+        Ok(checks) => checks,
+        Err(e) => return refuse(e.to_string()),
+    };
+    if checks != LiveChecks::default() {
+        let checked = filter_tables(&checks).await.and_then(|listing| {
+            verify_deletions(&listing, &checks.deletions)
+                .and_then(|()| verify_nat_targets(&listing, &checks.nat_targets))
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = checked {
+            return refuse(e);
+        }
+    }
+
+    let args: Vec<&str> = request.args.iter().map(String::as_str).collect();
+    debug!("Applying nft operation '{}'", request.operation);
+    // Timed-out transactions are re-queued by the controller's watchdog
+    let watchdog = runner::NftWatchdog::default();
+    let run = runner::run_program(
+        NFT_PROGRAM,
+        &request.operation,
+        &args,
+        request.stdin,
+        runner::nft_timeout(),
+        None,
+        &watchdog,
+    );
+    match run.await {
+        Ok(output) => Response {
+            status: output.status.into_raw(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            ..Response::default()
+        },
+        Err(e) => {
+            let cause = std::error::Error::source(&e)
+                .map(|cause| format!("{}: {}", e, cause))
+                .unwrap_or_else(|| e.to_string());
+            Response {
+                error: Some(cause),
+                ..Response::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn transaction(items: Value) -> String {
+        json!({ "nftables": items }).to_string()
+    }
+
+    #[test]
+    fn test_validate() {
+        for accepted in [
+            vec!["-j", "list", "table", "ip", "filter"],
+            vec!["-j", "list", "ruleset"],
+            vec!["-j", "list", "tables"],
+            vec!["-j", "list", "chains", "ip", "filter"],
+            vec!["-j", "list", "set", "ip", "filter", "hs-web-abc-rdns-p"],
+            vec!["-j", "list", "table", "inet", "harborshield-panic"],
+        ] {
+            assert!(validate(&args(&accepted), None).is_ok(), "{:?}", accepted);
+        }
+        assert!(
+            validate(
+                &args(&["list", "chain", "ip", "filter", "hs-web", "-j"]),
+                None
+            )
+            .is_ok()
+        );
+        assert!(
+            validate(
+                &args(&["delete", "chain", "ip", "filter", "hs-web-abc"]),
+                None
+            )
+            .is_ok()
+        );
+        for refused in [
+            vec!["delete", "chain", "ip", "filter", "DOCKER-USER"],
+            vec!["flush", "ruleset"],
+            vec!["-f", "/etc/nftables.conf"],
+            vec!["list", "ruleset", "-f", "-"],
+            vec!["list", "ruleset;", "flush", "ruleset"],
+            vec!["list", "ruleset", "flush", "ruleset"],
+            vec!["list", "ruleset\nflush", "ruleset"],
+            vec!["list", "table", "ip", "filter", "#"],
+            vec!["list", "chain", "ip", "filter", "hs-web{"],
+            vec!["list", "set", "bridge", "filter", "hs-web-abc-rdns-p"],
+            vec!["delete", "chain", "ip", "filter", "hs-x; flush ruleset"],
+            vec!["delete", "chain", "ip", "filter", "hs-x\nflush ruleset"],
+            vec!["flush", "chain", "ip", "filter", "hs-x.y"],
+        ] {
+            assert!(validate(&args(&refused), None).is_err(), "{:?}", refused);
+        }
+
+        let script = "add table ip filter\nadd chain ip filter hs-web-abc\ninsert rule ip filter hs-web-abc meta l4proto gre accept comment \"Raw rule 1 for web\"\n";
+        assert!(validate(&args(&["-c", "-f", "-"]), Some(script)).is_ok());
+        for refused in [
+            "insert rule ip filter DOCKER-USER accept",
+            "insert rule ip filter hs-web-abc accept; flush ruleset",
+            "include \"/etc/nftables.conf\"",
+            "add table ip nat",
+        ] {
+            assert!(
+                validate(&args(&["-f", "-"]), Some(refused)).is_err(),
+                "{}",
+                refused
+            );
+        }
+
+        let jump = json!({ "family": "ip", "table": "filter", "chain": "DOCKER-USER",
+            "comment": JUMP_COMMENT,
+            "expr": [{ "counter": null }, { "jump": { "target": "harborshield" } }] });
+        let ours = transaction(json!([
+            { "metainfo": { "json_schema_version": 1 } },
+            { "add": { "chain": { "family": "ip", "table": "filter", "name": "hs-web-abc" } } },
+            { "add": { "rule": { "family": "ip", "table": "filter", "chain": "hs-web-abc",
+                "expr": [{ "accept": null }] } } },
+            { "add": { "set": { "family": "ip", "table": "filter", "name": "hs-web-abc-pending" } } },
+            { "insert": { "rule": jump } },
+            { "delete": { "rule": { "family": "ip", "table": "filter", "chain": "INPUT",
+                "handle": 12, "expr": [] } } },
+        ]));
+        let deletions = validate(&args(&["-j", "-f", "-"]), Some(&ours))
+            .unwrap()
+            .deletions;
+        let interceptions = transaction(json!([
+            { "add": { "table": { "family": "ip", "name": "harborshield-tproxy" } } },
+            { "add": { "rule": { "family": "ip", "table": "harborshield-tproxy", "chain": "prerouting",
+                "expr": [{ "tproxy": { "port": 8443 } }, { "accept": null }] } } },
+        ]));
+        assert!(validate(&args(&["-j", "-f", "-"]), Some(&interceptions)).is_ok());
+        assert_eq!(
+            deletions,
+            vec![JumpDeletion {
+                family: "ip".to_string(),
+                chain: "INPUT".to_string(),
+                handle: 12
+            }]
+        );
+
+        for refused in [
+            json!([{ "flush": { "table": { "family": "ip", "name": "filter" } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "nat", "chain": "hs-web",
+                "expr": [] } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "filter", "chain": "DOCKER-USER",
+                "comment": JUMP_COMMENT, "expr": [{ "accept": null }] } } }]),
+            json!([{ "flush": { "chain": { "family": "ip", "table": "filter", "name": "FORWARD" } } }]),
+            json!([{ "add": { "set": { "family": "ip", "table": "filter", "name": "docker" } } }]),
+            json!([{ "list": { "ruleset": null } }]),
+            json!([{ "add": { "chain": { "family": "ip", "table": "filter", "name": "hs-web",
+                "type": "filter", "hook": "input", "prio": -500, "policy": "drop" } } }]),
+        ] {
+            let stdin = transaction(refused.clone());
+            assert!(
+                validate(&args(&["-j", "-f", "-"]), Some(&stdin)).is_err(),
+                "{}",
+                refused
+            );
+        }
+
+        // A deletion only goes ahead while the handle is still the jump
+        let listing = json!({ "nftables": [
+            { "rule": { "family": "ip", "table": "filter", "chain": "INPUT", "handle": 12,
+                "comment": JUMP_COMMENT,
+                "expr": [{ "counter": null }, { "jump": { "target": "harborshield" } }] } },
+            { "rule": { "family": "ip", "table": "filter", "chain": "INPUT", "handle": 13,
+                "expr": [{ "drop": null }] } },
+        ] });
+        assert!(verify_deletions(&listing, &deletions).is_ok());
+        let docker_rule = [JumpDeletion {
+            family: "ip".to_string(),
+            chain: "INPUT".to_string(),
+            handle: 13,
+        }];
+        assert!(verify_deletions(&listing, &docker_rule).is_err());
+        // The same handle in the ip6 table is a different rule
+        let ip6_rule = [JumpDeletion {
+            family: "ip6".to_string(),
+            ..deletions[0].clone()
+        }];
+        assert!(verify_deletions(&listing, &ip6_rule).is_err());
+    }
+
+    #[test]
+    fn test_validate_base_chains() {
+        use crate::nftables::{blocked, blocklist, docker, sinkhole};
+        use nftables::{batch::Batch, schema::NfListObject, types::NfFamily};
+
+        // The batch `init_base_chains` applies on a fresh host
+        let mut batch = Batch::new();
+        blocked::create_set(&mut batch, NfFamily::IP);
+        blocklist::create_set(&mut batch, NfFamily::IP);
+        sinkhole::create_set(&mut batch, NfFamily::IP);
+        docker::create_harborshield_chain(&mut batch, NfFamily::IP);
+        for rule in blocked::drop_rules(NfFamily::IP)
+            .into_iter()
+            .chain(blocklist::drop_rules(NfFamily::IP))
+            .chain(sinkhole::drop_rules(NfFamily::IP))
+        {
+            batch.add(NfListObject::Rule(rule));
+        }
+        docker::create_jump_rules(&mut batch, NfFamily::IP, true, true, true);
+
+        let stdin = serde_json::to_string(&batch.to_nftables()).unwrap();
+        let validated = validate(&args(&["-j", "-f", "-"]), Some(&stdin));
+        assert!(validated.is_ok(), "{:?}", validated);
+
+        let cut_off = ["172.18.0.2".parse().unwrap(), "fd00::2".parse().unwrap()];
+        let panic = kill_switch::rebuild(&cut_off, &["10.0.9.0/24".parse().unwrap()]);
+        let stdin = serde_json::to_string(&panic.to_nftables()).unwrap();
+        let validated = validate(&args(&["-j", "-f", "-"]), Some(&stdin));
+        assert!(validated.is_ok(), "{:?}", validated);
+        let hijack = transaction(json!([
+            { "add": { "chain": { "family": "inet", "table": "harborshield-panic",
+                "name": "prerouting", "type": "nat", "hook": "prerouting", "prio": -100 } } },
+        ]));
+        assert!(validate(&args(&["-j", "-f", "-"]), Some(&hijack)).is_err());
+    }
+
+    #[test]
+    fn test_validate_nat_tables() {
+        use crate::docker::config::ForwardRule;
+        use crate::nftables::forward::{self, ForwardTarget};
+
+        let forwards: Vec<ForwardRule> =
+            serde_yaml::from_str("- host_port: 8080\n  port: 80\n").unwrap();
+        let target = ForwardTarget {
+            container_name: "web",
+            ip: "172.17.0.2".parse().unwrap(),
+            forwards: forwards.iter().collect(),
+        };
+        let rebuild = serde_json::to_string(&forward::rebuild(&[target]).to_nftables()).unwrap();
+        let checks = validate(&args(&["-j", "-f", "-"]), Some(&rebuild)).unwrap();
+        assert_eq!(checks.nat_targets, ["172.17.0.2"; 3]);
+        let removal = serde_json::to_string(&forward::remove().to_nftables()).unwrap();
+        assert!(validate(&args(&["-j", "-f", "-"]), Some(&removal)).is_ok());
+
+        for refused in [
+            json!([{ "add": { "chain": { "family": "ip", "table": "harborshield-nat", "name": "input",
+                "type": "nat", "hook": "input", "prio": 0, "policy": "accept" } } }]),
+            json!([{ "add": { "chain": { "family": "ip", "table": "harborshield-nat", "name": "prerouting",
+                "type": "nat", "hook": "prerouting", "prio": -100, "policy": "drop" } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "harborshield-nat", "chain": "prerouting",
+                "expr": [{ "dnat": { "addr": "172.17.0.2", "port": 80 } }, { "drop": null }] } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "harborshield-nat", "chain": "postrouting",
+                "expr": [{ "masquerade": null }] } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "harborshield-nat", "chain": "prerouting",
+                "expr": [{ "snat": { "addr": "198.51.100.1" } }] } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "harborshield-tproxy", "chain": "prerouting",
+                "expr": [{ "tproxy": { "addr": "198.51.100.1", "port": 8443 } }] } } }]),
+            json!([{ "add": { "rule": { "family": "ip", "table": "harborshield-tproxy", "chain": "prerouting",
+                "expr": [{ "drop": null }] } } }]),
+        ] {
+            let stdin = transaction(refused.clone());
+            assert!(
+                validate(&args(&["-j", "-f", "-"]), Some(&stdin)).is_err(),
+                "{}",
+                refused
+            );
+        }
+
+        // Forwards may only reach containers the harborshield chain dispatches on
+        let listing = json!({ "nftables": [
+            { "rule": { "family": "ip", "table": "filter", "chain": "harborshield", "handle": 12,
+                "expr": [{ "vmap": { "key": { "payload": { "protocol": "ip", "field": "saddr" } },
+                    "data": { "set": [["172.17.0.2", { "jump": { "target": "hs-web-0123456789ab" } }]] } } }] } },
+        ] });
+        assert!(verify_nat_targets(&listing, &checks.nat_targets).is_ok());
+        assert_eq!(
+            verify_nat_targets(&listing, &["198.51.100.1".to_string()]),
+            Err(Rejected::UntrackedAddress("198.51.100.1".to_string()))
+        );
+    }
+}
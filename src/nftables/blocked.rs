@@ -0,0 +1,171 @@
+//! Addresses blocked at runtime through the admin API.
+//!
+//! A timed set next to the harborshield chain holds them, and the chain
+//! drops traffic from or to any of its members before dispatching to the
+//! container chains. Blocks last until their timeout or until the set is
+//! recreated, such as after the filter table is flushed.
+//!
+//! Bans stored with `harborshield ban` (see [`crate::database::bans`]) share
+//! the set. The last ones are kept so a recreated set is refilled with them
+//! straight away.
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, runner};
+use nftables::{
+    batch::Batch,
+    expr::{Elem, Expression, NamedExpression, Payload, PayloadField},
+    schema::{Element, NfListObject, Rule, Set, SetFlag, SetType, SetTypeValue},
+    stmt::{Counter, Match, Operator, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const BLOCKED_SET: &str = "harborshield-blocked";
+
+/// Banned addresses with their expiry in unix seconds
+static BANNED: Mutex<Vec<(Ipv4Addr, i64)>> = Mutex::new(Vec::new());
+
+fn blocked_set(family: NfFamily) -> Set<'static> {
+    Set {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(BLOCKED_SET),
+        handle: None,
+        set_type: SetTypeValue::Single(SetType::Ipv4Addr),
+        policy: None,
+        flags: Some(HashSet::from([SetFlag::Timeout])),
+        elem: None,
+        timeout: None,
+        gc_interval: None,
+        size: None,
+        comment: None,
+    }
+}
+
+fn element(family: NfFamily, ip: Ipv4Addr, ttl: Option<Duration>) -> NfListObject<'static> {
+    NfListObject::Element(Element {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(BLOCKED_SET),
+        elem: Cow::Owned(vec![Expression::Named(NamedExpression::Elem(Elem {
+            val: Box::new(Expression::String(Cow::Owned(ip.to_string()))),
+            timeout: ttl.map(|ttl| ttl.as_secs().max(1) as u32),
+            expires: None,
+            comment: None,
+            counter: None,
+        }))]),
+    })
+}
+
+/// Add the set, keeping its members if it already exists, with the bans
+/// still running
+pub fn create_set(batch: &mut Batch<'static>, family: NfFamily) {
+    batch.add(NfListObject::Set(Box::new(blocked_set(family))));
+    if family != NfFamily::IP {
+        return;
+    }
+    let now = chrono::Utc::now().timestamp();
+    let banned = BANNED.lock().map(|b| b.clone()).unwrap_or_default();
+    for (ip, expires_at) in banned {
+        if expires_at > now {
+            let ttl = Duration::from_secs((expires_at - now) as u64);
+            batch.add(element(family, ip, Some(ttl)));
+        }
+    }
+}
+
+/// Remember the bans to refill a recreated set with
+pub fn set_banned(banned: Vec<(Ipv4Addr, i64)>) {
+    if let Ok(mut current) = BANNED.lock() {
+        *current = banned;
+    }
+}
+
+/// Rules dropping traffic from and to blocked addresses, to head the
+/// harborshield chain
+pub fn drop_rules(family: NfFamily) -> Vec<Rule<'static>> {
+    ["saddr", "daddr"]
+        .into_iter()
+        .map(|field| Rule {
+            family,
+            table: Cow::Borrowed(FILTER_TABLE),
+            chain: Cow::Borrowed(HARBORSHIELD_CHAIN),
+            expr: Cow::Owned(vec![
+                Statement::Match(Match {
+                    left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                        PayloadField {
+                            protocol: Cow::Borrowed("ip"),
+                            field: Cow::Borrowed(field),
+                        },
+                    ))),
+                    right: Expression::String(Cow::Owned(format!("@{}", BLOCKED_SET))),
+                    op: Operator::EQ,
+                }),
+                Statement::Counter(Counter::Anonymous(None)),
+                Statement::Drop(None),
+            ]),
+            handle: None,
+            index: None,
+            comment: Some(Cow::Owned(format!("Drop blocked {}", field))),
+        })
+        .collect()
+}
+
+/// Block `ip` for `ttl`, or until the set is recreated without one
+pub async fn block(ip: Ipv4Addr, ttl: Option<Duration>) -> Result<()> {
+    let mut batch = Batch::new();
+    // Recreated in case the table was flushed since startup
+    create_set(&mut batch, NfFamily::IP);
+    batch.add(element(NfFamily::IP, ip, ttl));
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("block_address", json, None).await?;
+    Ok(())
+}
+
+/// Block `ip` for exactly `ttl`, replacing the timeout of an element
+/// already there, which adding it again would keep
+pub async fn ban(ip: Ipv4Addr, ttl: Duration) -> Result<()> {
+    let mut batch = Batch::new();
+    create_set(&mut batch, NfFamily::IP);
+    // Added first so the delete can't fail on a missing element
+    batch.add(element(NfFamily::IP, ip, None));
+    batch.delete(element(NfFamily::IP, ip, None));
+    batch.add(element(NfFamily::IP, ip, Some(ttl)));
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("ban_address", json, None).await?;
+    Ok(())
+}
+
+/// Drop `ip` from the set, whether banned or blocked through the admin API
+pub async fn unban(ip: Ipv4Addr) -> Result<()> {
+    let mut batch = Batch::new();
+    batch.add(NfListObject::Set(Box::new(blocked_set(NfFamily::IP))));
+    batch.add(element(NfFamily::IP, ip, None));
+    batch.delete(element(NfFamily::IP, ip, None));
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("unban_address", json, None).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_rules() {
+        let rules = drop_rules(NfFamily::IP);
+        assert_eq!(rules.len(), 2);
+        let json = serde_json::to_value(&rules[1]).unwrap();
+        let expr = json["expr"].as_array().unwrap();
+        assert_eq!(expr[0]["match"]["left"]["payload"]["field"], "daddr");
+        assert_eq!(expr[0]["match"]["right"], "@harborshield-blocked");
+        assert!(expr[2].get("drop").is_some(), "{}", json);
+    }
+}
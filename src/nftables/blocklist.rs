@@ -0,0 +1,166 @@
+//! The interval set of networks from `--blocklists` threat feeds, kept
+//! filled by [`crate::blocklist`].
+//!
+//! The harborshield chain drops traffic from or to any of them before
+//! dispatching to the container chains. The last networks are kept so a
+//! recreated set is filled again straight away.
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, runner};
+use ipnet::Ipv4Net;
+use nftables::{
+    batch::Batch,
+    expr::{Expression, NamedExpression, Payload, PayloadField, Prefix},
+    schema::{
+        Element, FlushObject, NfCmd, NfListObject, Rule, Set, SetFlag, SetType, SetTypeValue,
+    },
+    stmt::{Counter, Match, Operator, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const BLOCKLIST_SET: &str = "harborshield-blocklist";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NETWORKS: Mutex<Vec<Ipv4Net>> = Mutex::new(Vec::new());
+
+/// Install the set and its rules, when `--blocklists` is given
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn blocklist_set(family: NfFamily) -> Set<'static> {
+    Set {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(BLOCKLIST_SET),
+        handle: None,
+        set_type: SetTypeValue::Single(SetType::Ipv4Addr),
+        policy: None,
+        flags: Some(HashSet::from([SetFlag::Interval])),
+        elem: None,
+        timeout: None,
+        gc_interval: None,
+        size: None,
+        comment: None,
+    }
+}
+
+/// Add `networks`, which must not overlap: the set has no auto-merge
+fn fill(batch: &mut Batch<'static>, family: NfFamily, networks: &[Ipv4Net]) {
+    if networks.is_empty() {
+        return;
+    }
+    batch.add(NfListObject::Element(Element {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(BLOCKLIST_SET),
+        elem: Cow::Owned(
+            networks
+                .iter()
+                .map(|net| {
+                    Expression::Named(NamedExpression::Prefix(Prefix {
+                        addr: Box::new(Expression::String(Cow::Owned(net.network().to_string()))),
+                        len: net.prefix_len() as u32,
+                    }))
+                })
+                .collect(),
+        ),
+    }));
+}
+
+/// Add the set with the last blocked networks, if enabled
+pub fn create_set(batch: &mut Batch<'static>, family: NfFamily) {
+    if !enabled() {
+        return;
+    }
+    batch.add(NfListObject::Set(Box::new(blocklist_set(family))));
+    let networks = NETWORKS
+        .lock()
+        .map(|networks| networks.clone())
+        .unwrap_or_default();
+    fill(batch, family, &networks);
+}
+
+/// Rules dropping traffic from and to blocked networks, to head the
+/// harborshield chain, if enabled
+pub fn drop_rules(family: NfFamily) -> Vec<Rule<'static>> {
+    if !enabled() {
+        return Vec::new();
+    }
+    vec![drop_rule(family, "saddr"), drop_rule(family, "daddr")]
+}
+
+fn drop_rule(family: NfFamily, field: &'static str) -> Rule<'static> {
+    Rule {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        chain: Cow::Borrowed(HARBORSHIELD_CHAIN),
+        expr: Cow::Owned(vec![
+            Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                    PayloadField {
+                        protocol: Cow::Borrowed("ip"),
+                        field: Cow::Borrowed(field),
+                    },
+                ))),
+                right: Expression::String(Cow::Owned(format!("@{}", BLOCKLIST_SET))),
+                op: Operator::EQ,
+            }),
+            Statement::Counter(Counter::Anonymous(None)),
+            Statement::Drop(None),
+        ]),
+        handle: None,
+        index: None,
+        comment: Some(Cow::Owned(format!("Drop blocklisted {}", field))),
+    }
+}
+
+/// Swap the set's networks for `networks`, merged so none overlap, in one
+/// batch
+pub async fn replace_elements(networks: &[Ipv4Net]) -> Result<()> {
+    let mut batch = Batch::new();
+    // Recreated in case the table was flushed since startup
+    batch.add(NfListObject::Set(Box::new(blocklist_set(NfFamily::IP))));
+    batch.add_cmd(NfCmd::Flush(FlushObject::Set(Box::new(blocklist_set(
+        NfFamily::IP,
+    )))));
+    fill(&mut batch, NfFamily::IP, networks);
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("blocklist_replace_elements", json, None).await?;
+    if let Ok(mut current) = NETWORKS.lock() {
+        *current = networks.to_vec();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_rule() {
+        let json = serde_json::to_value(drop_rule(NfFamily::IP, "daddr")).unwrap();
+        let expr = json["expr"].as_array().unwrap();
+        assert_eq!(expr[0]["match"]["left"]["payload"]["field"], "daddr");
+        assert_eq!(expr[0]["match"]["right"], "@harborshield-blocklist");
+        assert!(expr[2].get("drop").is_some(), "{}", json);
+
+        let mut batch = Batch::new();
+        fill(&mut batch, NfFamily::IP, &["10.1.0.0/16".parse().unwrap()]);
+        let json = serde_json::to_string(&batch.to_nftables()).unwrap();
+        assert!(
+            json.contains("\"prefix\":{\"addr\":\"10.1.0.0\",\"len\":16}"),
+            "{}",
+            json
+        );
+    }
+}
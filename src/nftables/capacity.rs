@@ -0,0 +1,337 @@
+//! Ruleset size accounting for `harborshield capacity`.
+
+use crate::database::CapacitySample;
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::{HARBORSHIELD_CHAIN, runner};
+use crate::output::{Cell, Color, Column, Render, Table};
+use serde::Serialize;
+
+// Rough per-object kernel memory costs; nftables exposes no exact figure, so
+// these are only meant to show the order of magnitude and trend
+const CHAIN_BYTES: u64 = 512;
+const RULE_BYTES: u64 = 256;
+const SET_BYTES: u64 = 1024;
+const ELEMENT_BYTES: u64 = 128;
+
+pub const DEFAULT_MAX_CHAINS: u64 = 5_000;
+pub const DEFAULT_MAX_RULES: u64 = 50_000;
+pub const DEFAULT_MAX_SET_ELEMENTS: u64 = 500_000;
+pub const DEFAULT_WARN_PERCENT: u8 = 80;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RulesetCounts {
+    pub chains: u64,
+    pub rules: u64,
+    pub sets: u64,
+    pub set_elements: u64,
+    /// Chains owned by harborshield (`harborshield` and `hs-*`)
+    pub harborshield_chains: u64,
+    /// Rules in harborshield-owned chains
+    pub harborshield_rules: u64,
+}
+
+impl RulesetCounts {
+    pub fn estimated_memory_bytes(&self) -> u64 {
+        self.chains * CHAIN_BYTES
+            + self.rules * RULE_BYTES
+            + self.sets * SET_BYTES
+            + self.set_elements * ELEMENT_BYTES
+    }
+
+    pub fn to_sample(&self, bucket: i64) -> CapacitySample {
+        CapacitySample {
+            bucket,
+            chains: self.chains as i64,
+            rules: self.rules as i64,
+            set_elements: self.set_elements as i64,
+            memory_bytes: self.estimated_memory_bytes() as i64,
+        }
+    }
+}
+
+fn is_harborshield_chain(name: &str) -> bool {
+    name == HARBORSHIELD_CHAIN || name.starts_with("hs-")
+}
+
+/// Count objects in `nft -j list ruleset` output
+pub fn parse_ruleset_counts(json: &serde_json::Value) -> RulesetCounts {
+    let mut counts = RulesetCounts::default();
+
+    let items = json
+        .get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten();
+
+    for item in items {
+        if let Some(chain) = item.get("chain") {
+            counts.chains += 1;
+            if chain
+                .get("name")
+                .and_then(|n| n.as_str())
+                .is_some_and(is_harborshield_chain)
+            {
+                counts.harborshield_chains += 1;
+            }
+        } else if let Some(rule) = item.get("rule") {
+            counts.rules += 1;
+            if rule
+                .get("chain")
+                .and_then(|n| n.as_str())
+                .is_some_and(is_harborshield_chain)
+            {
+                counts.harborshield_rules += 1;
+            }
+        } else if let Some(set) = item.get("set").or_else(|| item.get("map")) {
+            counts.sets += 1;
+            counts.set_elements += set
+                .get("elem")
+                .and_then(|e| e.as_array())
+                .map_or(0, |e| e.len() as u64);
+        }
+    }
+
+    counts
+}
+
+/// Count objects in the host's whole ruleset
+pub async fn list_ruleset_counts() -> Result<RulesetCounts> {
+    let output = runner::run_nft("list_ruleset", &["-j", "list", "ruleset"]).await?;
+
+    if !output.status.success() {
+        return Err(NftablesError::command_failed(
+            runner::NFT_PROGRAM,
+            "list ruleset",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).map_err(NftablesError::invalid_json)?;
+    Ok(parse_ruleset_counts(&json))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityLimits {
+    pub chains: u64,
+    pub rules: u64,
+    pub set_elements: u64,
+    /// Warn once usage reaches this percentage of a limit
+    pub warn_percent: u8,
+}
+
+impl Default for CapacityLimits {
+    fn default() -> Self {
+        Self {
+            chains: DEFAULT_MAX_CHAINS,
+            rules: DEFAULT_MAX_RULES,
+            set_elements: DEFAULT_MAX_SET_ELEMENTS,
+            warn_percent: DEFAULT_WARN_PERCENT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapacityLine {
+    pub object: &'static str,
+    pub current: u64,
+    pub limit: Option<u64>,
+    pub week_ago: Option<u64>,
+}
+
+impl CapacityLine {
+    pub fn used_percent(&self) -> Option<f64> {
+        self.limit
+            .filter(|l| *l > 0)
+            .map(|l| self.current as f64 * 100.0 / l as f64)
+    }
+
+    pub fn change(&self) -> Option<i64> {
+        self.week_ago.map(|w| self.current as i64 - w as i64)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapacityReport {
+    pub counts: RulesetCounts,
+    pub lines: Vec<CapacityLine>,
+    pub warnings: Vec<String>,
+}
+
+impl CapacityReport {
+    pub fn new(
+        counts: RulesetCounts,
+        week_ago: Option<&CapacitySample>,
+        limits: &CapacityLimits,
+    ) -> Self {
+        let previous = |f: fn(&CapacitySample) -> i64| week_ago.map(|s| f(s).max(0) as u64);
+
+        let lines = vec![
+            CapacityLine {
+                object: "chains",
+                current: counts.chains,
+                limit: Some(limits.chains),
+                week_ago: previous(|s| s.chains),
+            },
+            CapacityLine {
+                object: "rules",
+                current: counts.rules,
+                limit: Some(limits.rules),
+                week_ago: previous(|s| s.rules),
+            },
+            CapacityLine {
+                object: "set elements",
+                current: counts.set_elements,
+                limit: Some(limits.set_elements),
+                week_ago: previous(|s| s.set_elements),
+            },
+            CapacityLine {
+                object: "memory (est. bytes)",
+                current: counts.estimated_memory_bytes(),
+                limit: None,
+                week_ago: previous(|s| s.memory_bytes),
+            },
+        ];
+
+        let warnings = lines
+            .iter()
+            .filter_map(|line| {
+                let used = line.used_percent()?;
+                (used >= limits.warn_percent as f64).then(|| {
+                    format!(
+                        "{} at {:.0}% of the configured limit ({} of {})",
+                        line.object,
+                        used,
+                        line.current,
+                        line.limit.unwrap_or_default()
+                    )
+                })
+            })
+            .collect();
+
+        Self {
+            counts,
+            lines,
+            warnings,
+        }
+    }
+}
+
+impl Render for CapacityReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("OBJECT"),
+            Column::right("CURRENT"),
+            Column::right("OWNED").wide(),
+            Column::right("LIMIT"),
+            Column::right("USED"),
+            Column::right("WEEK AGO"),
+            Column::right("CHANGE"),
+        ]);
+
+        let dash = || Cell::from("-");
+        for line in &self.lines {
+            let owned = match line.object {
+                "chains" => Some(self.counts.harborshield_chains),
+                "rules" => Some(self.counts.harborshield_rules),
+                _ => None,
+            };
+            let used = line.used_percent().map_or_else(dash, |u| {
+                let text = format!("{:.1}%", u);
+                if self.warnings.iter().any(|w| w.starts_with(line.object)) {
+                    Cell::colored(text, Color::Yellow)
+                } else {
+                    text.into()
+                }
+            });
+            table.row(vec![
+                line.object.into(),
+                line.current.to_string().into(),
+                owned.map_or_else(dash, |o| o.to_string().into()),
+                line.limit.map_or_else(dash, |l| l.to_string().into()),
+                used,
+                line.week_ago.map_or_else(dash, |w| w.to_string().into()),
+                line.change()
+                    .map_or_else(dash, |c| format!("{:+}", c).into()),
+            ]);
+        }
+
+        table.footer(format!(
+            "harborshield owns {} chains and {} rules",
+            self.counts.harborshield_chains, self.counts.harborshield_rules
+        ));
+        for warning in &self.warnings {
+            table.warning(warning);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ruleset() -> serde_json::Value {
+        json!({
+            "nftables": [
+                {"metainfo": {"json_schema_version": 1}},
+                {"table": {"family": "ip", "name": "filter"}},
+                {"chain": {"family": "ip", "table": "filter", "name": "DOCKER-USER"}},
+                {"chain": {"family": "ip", "table": "filter", "name": "harborshield"}},
+                {"chain": {"family": "ip", "table": "filter", "name": "hs-web-abc"}},
+                {"rule": {"chain": "DOCKER-USER", "expr": []}},
+                {"rule": {"chain": "hs-web-abc", "expr": []}},
+                {"rule": {"chain": "hs-web-abc", "expr": []}},
+                {"map": {"name": "hs-src-verdicts", "elem": [["172.17.0.2", {"jump": {"target": "hs-web-abc"}}]]}},
+                {"set": {"name": "hs-web-abc-rdns-ok", "elem": ["66.249.66.1", "66.249.66.2"]}}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_parse_ruleset_counts() {
+        let counts = parse_ruleset_counts(&ruleset());
+        assert_eq!(
+            counts,
+            RulesetCounts {
+                chains: 3,
+                rules: 3,
+                sets: 2,
+                set_elements: 3,
+                harborshield_chains: 2,
+                harborshield_rules: 2,
+            }
+        );
+        assert_eq!(
+            counts.estimated_memory_bytes(),
+            3 * CHAIN_BYTES + 3 * RULE_BYTES + 2 * SET_BYTES + 3 * ELEMENT_BYTES
+        );
+    }
+
+    #[test]
+    fn test_report_trend_and_warnings() {
+        let counts = parse_ruleset_counts(&ruleset());
+        let week_ago = CapacitySample {
+            bucket: 0,
+            chains: 1,
+            rules: 1,
+            set_elements: 0,
+            memory_bytes: 0,
+        };
+        let limits = CapacityLimits {
+            chains: 3,
+            rules: 100,
+            set_elements: 100,
+            warn_percent: 80,
+        };
+
+        let report = CapacityReport::new(counts, Some(&week_ago), &limits);
+        assert_eq!(report.lines[0].change(), Some(2));
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("chains at 100%"));
+        let rendered = crate::output::format(&report, crate::output::OutputFormat::Table, false);
+        assert!(rendered.contains("warning: chains"));
+    }
+}
@@ -1,7 +1,6 @@
 use nftables::{
     helper::{DEFAULT_NFT, get_current_ruleset_with_args},
     schema::{Chain, NfListObject},
-    types::NfFamily,
 };
 
 use crate::Error;
@@ -27,14 +26,3 @@ pub fn find_chain<'a>(table: &str, chain_name: &str) -> Result<Option<Chain<'a>>
             }),
     )
 }
-/// Convert NfFamily to string for nft command
-pub fn family_to_string(family: &NfFamily) -> &'static str {
-    match family {
-        NfFamily::IP => "ip",
-        NfFamily::IP6 => "ip6",
-        NfFamily::INet => "inet",
-        NfFamily::ARP => "arp",
-        NfFamily::Bridge => "bridge",
-        NfFamily::NetDev => "netdev",
-    }
-}
@@ -0,0 +1,108 @@
+//! The meters of `max_connections_per_ip` rules, which are built in
+//! [`harborshield_core::nftables::connlimit`]. Each chain's meters are kept
+//! here, so a rebuild deletes those of ports it no longer limits and
+//! deleting the chain deletes the rest.
+
+use crate::docker::config::{Config, RuleContext};
+use harborshield_core::nftables::connlimit::delete_meter;
+use nftables::{batch::Batch, types::NfFamily};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Meters of each chain, by family (true for IPv6) and chain name
+type Registry = HashMap<(bool, String), Vec<String>>;
+
+static METERS: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Replace the meters of `chain` in one family, returning those no longer
+/// used
+fn register_chain(ipv6: bool, chain: &str, meters: Vec<String>) -> Vec<String> {
+    let Ok(mut registered) = METERS.lock() else {
+        return Vec::new();
+    };
+    let key = (ipv6, chain.to_string());
+    let previous = if meters.is_empty() {
+        registered.remove(&key)
+    } else {
+        registered.insert(key, meters.clone())
+    };
+    previous
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|name| !meters.contains(name))
+        .collect()
+}
+
+/// Keep `meters` as those of `chain`, deleting the ones it had before and
+/// no longer uses
+pub fn register(batch: &mut Batch<'static>, family: NfFamily, chain: &str, meters: Vec<String>) {
+    for stale in register_chain(family == NfFamily::IP6, chain, meters) {
+        delete_meter(batch, family, stale);
+    }
+}
+
+/// Add the connection limit rules of the chain, and delete the meters of
+/// ports it no longer limits. Goes after the chain is flushed and before
+/// its external rules
+pub fn add_to_batch(batch: &mut Batch<'static>, ctx: &RuleContext, config: &Config) {
+    let meters = harborshield_core::nftables::connlimit::add_to_batch(batch, ctx, config);
+    register(batch, ctx.family, ctx.chain_name, meters);
+}
+
+/// Delete the meters of a container chain (the chain must be gone first)
+pub fn delete_sets(batch: &mut Batch<'static>, family: NfFamily, chain: &str) {
+    register(batch, family, chain, Vec::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nftables::FILTER_TABLE;
+    use harborshield_core::nftables::connlimit::meter_name;
+
+    #[test]
+    fn test_stale_meters() {
+        let limited: Config = serde_yaml::from_str(
+            "mapped_ports:\n  external:\n    allow: true\n    max_connections_per_ip: 10\n",
+        )
+        .unwrap();
+        let ports = [(443, "tcp".to_string())];
+        let chain = "hs-web-stalemeter12";
+        let ctx = RuleContext {
+            container_id: "stalemeter12",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &ports,
+            chain_name: chain,
+            table_name: FILTER_TABLE,
+            family: NfFamily::IP,
+        };
+        let mut batch = Batch::new();
+        add_to_batch(&mut batch, &ctx, &limited);
+
+        // Dropping the limit deletes the meter
+        let config: Config =
+            serde_yaml::from_str("mapped_ports:\n  external:\n    allow: true\n").unwrap();
+        let mut batch = Batch::new();
+        add_to_batch(&mut batch, &ctx, &config);
+        let json = serde_json::to_value(batch.to_nftables()).unwrap();
+        assert_eq!(
+            json["nftables"][0]["delete"]["set"]["name"],
+            meter_name(chain, "tcp", 443)
+        );
+        let mut batch = Batch::new();
+        delete_sets(&mut batch, NfFamily::IP, chain);
+        assert!(batch.to_nftables().objects.is_empty());
+
+        // A deleted chain's meters go with it
+        let mut batch = Batch::new();
+        add_to_batch(&mut batch, &ctx, &limited);
+        let mut batch = Batch::new();
+        delete_sets(&mut batch, NfFamily::IP, chain);
+        let json = serde_json::to_value(batch.to_nftables()).unwrap();
+        assert_eq!(
+            json["nftables"][0]["delete"]["set"]["name"],
+            meter_name(chain, "tcp", 443)
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Per-chain packet/byte counters read back from the kernel.
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::{FILTER_TABLE, runner};
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub use harborshield_core::parse_chain_rule_counts;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainCounters {
+    pub drop_packets: u64,
+    pub drop_bytes: u64,
+    pub accept_packets: u64,
+    pub accept_bytes: u64,
+}
+
+impl ChainCounters {
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// One counted rule of a container chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleCounter {
+    pub chain: String,
+    /// Position in the chain, from 1
+    pub position: usize,
+    pub comment: Option<String>,
+    /// `accept`, `drop`, `jump` or `queue`; `None` for rules that let
+    /// packets continue down the chain
+    pub verdict: Option<&'static str>,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+fn verdict_of(expr: &[serde_json::Value]) -> Option<&'static str> {
+    expr.iter().find_map(|stmt| {
+        ["accept", "drop", "jump", "goto", "queue"]
+            .into_iter()
+            .find(|verdict| stmt.get(verdict).is_some())
+    })
+}
+
+/// Every rule with a counter in harborshield container chains, in chain
+/// order, from `nft -j list table` output
+pub fn parse_rule_counters(json: &serde_json::Value) -> Vec<RuleCounter> {
+    let rules = json
+        .get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("rule"));
+
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut counted = Vec::new();
+    for rule in rules {
+        let Some(chain) = rule.get("chain").and_then(|c| c.as_str()) else {
+            continue;
+        };
+        if !chain.starts_with("hs-") {
+            continue;
+        }
+        let position = positions.entry(chain.to_string()).or_default();
+        *position += 1;
+        let Some(expr) = rule.get("expr").and_then(|e| e.as_array()) else {
+            continue;
+        };
+        let Some(counter) = expr.iter().find_map(|stmt| stmt.get("counter")) else {
+            continue;
+        };
+
+        counted.push(RuleCounter {
+            chain: chain.to_string(),
+            position: *position,
+            comment: rule
+                .get("comment")
+                .and_then(|c| c.as_str())
+                .map(str::to_string),
+            verdict: verdict_of(expr),
+            packets: counter.get("packets").and_then(|p| p.as_u64()).unwrap_or(0),
+            bytes: counter.get("bytes").and_then(|b| b.as_u64()).unwrap_or(0),
+        });
+    }
+    counted
+}
+
+/// Sum the anonymous counters of every rule in harborshield container chains
+/// from `nft -j list table` output. Rules ending in `drop` count as drops,
+/// other verdicts as accepted traffic. Rules without a verdict only see
+/// packets on their way to another rule, which counts them.
+pub fn parse_chain_counters(json: &serde_json::Value) -> HashMap<String, ChainCounters> {
+    let mut chains: HashMap<String, ChainCounters> = HashMap::new();
+
+    for rule in parse_rule_counters(json) {
+        let entry = chains.entry(rule.chain).or_default();
+        match rule.verdict {
+            Some("drop") => {
+                entry.drop_packets += rule.packets;
+                entry.drop_bytes += rule.bytes;
+            }
+            Some(_) => {
+                entry.accept_packets += rule.packets;
+                entry.accept_bytes += rule.bytes;
+            }
+            None => {}
+        }
+    }
+
+    chains
+}
+
+pub(crate) async fn list_filter_table(operation: &str) -> Result<serde_json::Value> {
+    let output = runner::run_nft(operation, &["-j", "list", "table", "ip", FILTER_TABLE]).await?;
+
+    if !output.status.success() {
+        return Err(NftablesError::command_failed(
+            runner::NFT_PROGRAM,
+            format!("list table ip {}", FILTER_TABLE),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    serde_json::from_slice(&output.stdout).map_err(NftablesError::invalid_json)
+}
+
+/// Current counters of all container chains
+pub async fn list_chain_counters() -> Result<HashMap<String, ChainCounters>> {
+    Ok(parse_chain_counters(
+        &list_filter_table("list_counters").await?,
+    ))
+}
+
+/// Current counters of every rule of all container chains
+pub async fn list_rule_counters() -> Result<Vec<RuleCounter>> {
+    Ok(parse_rule_counters(
+        &list_filter_table("list_rule_counters").await?,
+    ))
+}
+
+/// Current rule counts of all container chains
+pub async fn list_chain_rule_counts() -> Result<HashMap<String, u64>> {
+    Ok(parse_chain_rule_counts(
+        &list_filter_table("list_chains").await?,
+    ))
+}
+
+/// Turns absolute counter readings into increments since the previous reading
+#[derive(Debug, Default)]
+pub struct CounterSampler {
+    last: HashMap<String, ChainCounters>,
+}
+
+impl CounterSampler {
+    /// Increments since the last call. A counter that went backwards means
+    /// the chain was rebuilt, so its current value is the whole increment.
+    pub fn deltas(
+        &mut self,
+        current: HashMap<String, ChainCounters>,
+    ) -> HashMap<String, ChainCounters> {
+        let deltas = current
+            .iter()
+            .map(|(chain, now)| {
+                let before = self.last.get(chain).copied().unwrap_or_default();
+                let rebuilt = now.drop_packets < before.drop_packets
+                    || now.accept_packets < before.accept_packets;
+                let before = if rebuilt {
+                    ChainCounters::default()
+                } else {
+                    before
+                };
+                (
+                    chain.clone(),
+                    ChainCounters {
+                        drop_packets: now.drop_packets.saturating_sub(before.drop_packets),
+                        drop_bytes: now.drop_bytes.saturating_sub(before.drop_bytes),
+                        accept_packets: now.accept_packets.saturating_sub(before.accept_packets),
+                        accept_bytes: now.accept_bytes.saturating_sub(before.accept_bytes),
+                    },
+                )
+            })
+            .filter(|(_, d)| !d.is_zero())
+            .collect();
+
+        self.last = current;
+        deltas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_chain_counters() {
+        let output = json!({
+            "nftables": [
+                {"chain": {"family": "ip", "table": "filter", "name": "hs-web-abc"}},
+                {"rule": {"chain": "hs-web-abc", "expr": [
+                    {"match": {"op": "==", "left": {"meta": {"key": "l4proto"}}, "right": 6}},
+                    {"counter": {"packets": 10, "bytes": 1000}},
+                    {"accept": null}
+                ]}},
+                {"rule": {"chain": "hs-web-abc", "expr": [
+                    {"counter": {"packets": 3, "bytes": 180}},
+                    {"log": {"prefix": "hs-web-abc DROP: "}},
+                    {"drop": null}
+                ]}},
+                {"rule": {"chain": "DOCKER-USER", "expr": [
+                    {"counter": {"packets": 99, "bytes": 9999}},
+                    {"drop": null}
+                ]}}
+            ]
+        });
+
+        let counters = parse_chain_counters(&output);
+        assert_eq!(counters.len(), 1);
+        assert_eq!(
+            counters["hs-web-abc"],
+            ChainCounters {
+                drop_packets: 3,
+                drop_bytes: 180,
+                accept_packets: 10,
+                accept_bytes: 1000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sampler_deltas() {
+        let reading = |drop_packets, accept_packets| {
+            HashMap::from([(
+                "hs-web-abc".to_string(),
+                ChainCounters {
+                    drop_packets,
+                    drop_bytes: drop_packets * 60,
+                    accept_packets,
+                    accept_bytes: accept_packets * 100,
+                },
+            )])
+        };
+
+        let mut sampler = CounterSampler::default();
+        assert_eq!(sampler.deltas(reading(2, 5))["hs-web-abc"].drop_packets, 2);
+        assert_eq!(sampler.deltas(reading(7, 5))["hs-web-abc"].drop_packets, 5);
+        // Unchanged chains produce no delta
+        assert!(sampler.deltas(reading(7, 5)).is_empty());
+        // Chain rebuilt: counters restarted from zero
+        let after_rebuild = sampler.deltas(reading(1, 1));
+        assert_eq!(after_rebuild["hs-web-abc"].drop_packets, 1);
+        assert_eq!(after_rebuild["hs-web-abc"].accept_bytes, 100);
+    }
+}
@@ -0,0 +1,127 @@
+//! `nft` calls on a thread of their own, with `--nft-thread`.
+//!
+//! On a loaded host the calls otherwise share the runtime's workers with the
+//! API and metrics handlers, and rule application waits behind them. Here
+//! they run on a single-threaded runtime on a dedicated thread instead,
+//! scheduled ahead of the rest of the process by `--nft-thread-nice`.
+
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::Handle;
+use tokio::task::{JoinError, JoinHandle};
+use tracing::{info, warn};
+
+/// Niceness of the thread unless `--nft-thread-nice` says otherwise
+pub const DEFAULT_NICE: i32 = -5;
+
+pub const THREAD_NAME: &str = "hs-nft";
+
+static HANDLE: OnceLock<Handle> = OnceLock::new();
+
+/// Start the thread; later calls keep the first one
+pub fn start(nice: i32) -> std::io::Result<()> {
+    if HANDLE.get().is_some() {
+        return Ok(());
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let handle = runtime.handle().clone();
+    std::thread::Builder::new()
+        .name(THREAD_NAME.to_string())
+        .spawn(move || {
+            set_nice(nice);
+            runtime.block_on(std::future::pending::<()>());
+        })?;
+    let _ = HANDLE.set(handle);
+    info!("Running nft calls on a dedicated thread");
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    HANDLE.get().is_some()
+}
+
+/// Renice the calling thread; raising its priority takes CAP_SYS_NICE
+fn set_nice(nice: i32) {
+    #[cfg(target_os = "linux")]
+    {
+        // On Linux niceness is per thread, addressed by its id
+        // SAFETY: gettid has no preconditions and cannot fail
+        let tid = unsafe { libc::gettid() };
+        // SAFETY: plain setpriority(2) call on this thread; the result is
+        // checked
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) } != 0 {
+            warn!(
+                "Failed to set the nft thread's niceness to {}: {}",
+                nice,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    warn!(
+        "Thread niceness is only set on Linux, ignoring {} for the nft thread",
+        nice
+    );
+}
+
+/// Aborts the task when the caller gives up on it, so a timed-out `nft`
+/// process is killed as it would be on the caller's own runtime
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run `future` on the dedicated thread when started, right here otherwise
+pub async fn run<F>(future: F) -> Result<F::Output, JoinError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match HANDLE.get() {
+        Some(handle) => {
+            let mut task = AbortOnDrop(handle.spawn(future));
+            (&mut task.0).await
+        }
+        None => Ok(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run() {
+        let here = std::thread::current().id();
+        assert_eq!(run(async move { 2 }).await.unwrap(), 2);
+
+        start(0).unwrap();
+        assert!(enabled());
+        let name = run(async { std::thread::current().name().map(str::to_string) })
+            .await
+            .unwrap();
+        assert_eq!(name.as_deref(), Some(THREAD_NAME));
+        assert_ne!(
+            run(async { std::thread::current().id() }).await.unwrap(),
+            here
+        );
+
+        // Given up on, the task stops with it
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let pending = run(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await
+        });
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(10), pending)
+                .await
+                .is_err()
+        );
+        assert!(rx.await.is_err());
+    }
+}
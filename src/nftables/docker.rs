@@ -1,6 +1,7 @@
 use crate::{
     Error, Result,
-    nftables::{DOCKER_USER_CHAIN, FILTER_TABLE, HARBORSHIELD_CHAIN, INPUT_CHAIN, OUTPUT_CHAIN},
+    nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, INPUT_CHAIN, OUTPUT_CHAIN, runner},
+    runtime,
 };
 use nftables::{
     batch::Batch,
@@ -15,9 +16,8 @@ use tracing::{debug, info, warn};
 /// Check if Docker filter table and chains exist
 /// Returns: (has_filter_table, has_docker_user_chain, has_input_chain, has_output_chain)
 pub async fn check_docker_chains() -> Result<(bool, bool, bool, bool)> {
-    let output = std::process::Command::new("nft")
-        .args(&["-j", "list", "tables"])
-        .output()
+    let output = runner::run_nft("list_tables", &["-j", "list", "tables"])
+        .await
         .map_err(|e| Error::Config {
             message: format!("Failed to list nftables tables: {}", e),
             location: "check_docker_chains".to_string(),
@@ -58,14 +58,16 @@ pub async fn check_docker_chains() -> Result<(bool, bool, bool, bool)> {
     }
 
     // Check for specific chains
-    let output = std::process::Command::new("nft")
-        .args(&["-j", "list", "chains", "ip", "filter"])
-        .output()
-        .map_err(|e| Error::Config {
-            message: format!("Failed to list filter chains: {}", e),
-            location: "check_docker_chains".to_string(),
-            suggestion: Some("Ensure nftables is installed".to_string()),
-        })?;
+    let output = runner::run_nft(
+        "list_filter_chains",
+        &["-j", "list", "chains", "ip", "filter"],
+    )
+    .await
+    .map_err(|e| Error::Config {
+        message: format!("Failed to list filter chains: {}", e),
+        location: "check_docker_chains".to_string(),
+        suggestion: Some("Ensure nftables is installed".to_string()),
+    })?;
 
     if !output.status.success() {
         return Ok((true, false, false, false));
@@ -77,6 +79,7 @@ pub async fn check_docker_chains() -> Result<(bool, bool, bool, bool)> {
     let (has_docker_user, has_input, has_output) =
         if let Ok(json) = serde_json::from_str::<serde_json::Value>(&json_str) {
             if let Some(nftables) = json.get("nftables").and_then(|n| n.as_array()) {
+                let forward_chain = runtime::kind().forward_chain();
                 let mut docker_user = false;
                 let mut input = false;
                 let mut output = false;
@@ -85,7 +88,7 @@ pub async fn check_docker_chains() -> Result<(bool, bool, bool, bool)> {
                     if let Some(chain) = item.get("chain") {
                         if let Some(name) = chain.get("name").and_then(|n| n.as_str()) {
                             match name {
-                                DOCKER_USER_CHAIN => docker_user = true,
+                                name if name == forward_chain => docker_user = true,
                                 INPUT_CHAIN => input = true,
                                 OUTPUT_CHAIN => output = true,
                                 _ => {}
@@ -102,13 +105,18 @@ pub async fn check_docker_chains() -> Result<(bool, bool, bool, bool)> {
             (false, false, false)
         };
 
+    let forward_chain = runtime::kind().forward_chain();
     if !has_docker_user {
-        warn!("DOCKER-USER chain not found - Docker may not be running or not using nftables");
+        warn!(
+            "{} chain not found - {} may not be running or not using nftables",
+            forward_chain,
+            runtime::kind()
+        );
     }
 
     debug!(
-        "Docker chains check - filter: true, DOCKER-USER: {}, INPUT: {}, OUTPUT: {}",
-        has_docker_user, has_input, has_output
+        "Docker chains check - filter: true, {}: {}, INPUT: {}, OUTPUT: {}",
+        forward_chain, has_docker_user, has_input, has_output
     );
 
     Ok((true, has_docker_user, has_input, has_output))
@@ -154,19 +162,27 @@ pub fn create_jump_rules(
             ]),
             handle: None,
             index: None,
-            comment: Some(Cow::Borrowed("Jump to harborshield chain")),
+            comment: Some(Cow::Borrowed(super::flush::JUMP_COMMENT)),
         }
     };
 
-    // Always try to add jump from DOCKER-USER if it exists
+    // Always try to add jump from DOCKER-USER (FORWARD under Podman) if it exists
+    let forward_chain = runtime::kind().forward_chain();
     if has_docker_user {
-        info!("Adding jump rule from DOCKER-USER to harborshield chain");
+        info!(
+            "Adding jump rule from {} to harborshield chain",
+            forward_chain
+        );
         // Insert at the beginning of the chain
         batch.add_cmd(NfCmd::Insert(NfListObject::Rule(create_jump_rule(
-            DOCKER_USER_CHAIN.to_owned(),
+            forward_chain.to_owned(),
         ))));
     } else {
-        warn!("DOCKER-USER chain not found, Docker may not be running");
+        warn!(
+            "{} chain not found, {} may not be running",
+            forward_chain,
+            runtime::kind()
+        );
     }
 
     // Add jump from INPUT chain if it exists
@@ -190,14 +206,16 @@ pub fn create_jump_rules(
 
 /// Check if harborshield chain already exists in filter table
 pub async fn check_harborshield_chain_exists() -> Result<bool> {
-    let output = std::process::Command::new("nft")
-        .args(&["-j", "list", "chains", "ip", "filter"])
-        .output()
-        .map_err(|e| Error::Config {
-            message: format!("Failed to list filter chains: {}", e),
-            location: "check_harborshield_chain_exists".to_string(),
-            suggestion: Some("Ensure nftables is installed".to_string()),
-        })?;
+    let output = runner::run_nft(
+        "list_filter_chains",
+        &["-j", "list", "chains", "ip", "filter"],
+    )
+    .await
+    .map_err(|e| Error::Config {
+        message: format!("Failed to list filter chains: {}", e),
+        location: "check_harborshield_chain_exists".to_string(),
+        suggestion: Some("Ensure nftables is installed".to_string()),
+    })?;
 
     if !output.status.success() {
         // If we can't list chains, assume it doesn't exist
@@ -210,14 +228,16 @@ pub async fn check_harborshield_chain_exists() -> Result<bool> {
 
 /// Check if jump rules already exist
 pub async fn check_jump_rules_exist() -> Result<(bool, bool, bool)> {
-    let output = std::process::Command::new("nft")
-        .args(&["-j", "list", "table", "ip", "filter"])
-        .output()
-        .map_err(|e| Error::Config {
-            message: format!("Failed to list filter table: {}", e),
-            location: "check_jump_rules_exist".to_string(),
-            suggestion: Some("Ensure nftables is installed".to_string()),
-        })?;
+    let output = runner::run_nft(
+        "list_filter_table",
+        &["-j", "list", "table", "ip", "filter"],
+    )
+    .await
+    .map_err(|e| Error::Config {
+        message: format!("Failed to list filter table: {}", e),
+        location: "check_jump_rules_exist".to_string(),
+        suggestion: Some("Ensure nftables is installed".to_string()),
+    })?;
 
     if !output.status.success() {
         return Ok((false, false, false));
@@ -226,6 +246,7 @@ pub async fn check_jump_rules_exist() -> Result<(bool, bool, bool)> {
     let json_str = String::from_utf8_lossy(&output.stdout);
 
     // Parse JSON to check for jump rules more accurately
+    let forward_chain = runtime::kind().forward_chain();
     let mut docker_user_jump = false;
     let mut input_jump = false;
     let mut output_jump = false;
@@ -245,7 +266,9 @@ pub async fn check_jump_rules_exist() -> Result<(bool, bool, bool)> {
                                     if let Some(target) = jump.get("target") {
                                         if target.as_str() == Some(HARBORSHIELD_CHAIN) {
                                             match chain_name {
-                                                DOCKER_USER_CHAIN => docker_user_jump = true,
+                                                name if name == forward_chain => {
+                                                    docker_user_jump = true
+                                                }
                                                 INPUT_CHAIN => input_jump = true,
                                                 OUTPUT_CHAIN => output_jump = true,
                                                 _ => {}
@@ -262,8 +285,8 @@ pub async fn check_jump_rules_exist() -> Result<(bool, bool, bool)> {
     }
 
     debug!(
-        "Jump rules exist - DOCKER-USER: {}, INPUT: {}, OUTPUT: {}",
-        docker_user_jump, input_jump, output_jump
+        "Jump rules exist - {}: {}, INPUT: {}, OUTPUT: {}",
+        forward_chain, docker_user_jump, input_jump, output_jump
     );
 
     Ok((docker_user_jump, input_jump, output_jump))
@@ -0,0 +1,196 @@
+//! Fastpath offload through an nftables flowtable, enabled with
+//! `--flowtable-device`.
+//!
+//! `flow add` is only valid on the forward hook, and container chains are
+//! also reached from INPUT and OUTPUT, so offloading happens outside them:
+//! the `harborshield-fastpath` base chain hooks forward after Docker's
+//! filter chains and, for each output rule marked `offload: true`, adds the
+//! flows that rule's container opened to the `hs-fastpath` flowtable. Flows
+//! dropped by the filter chains never get that far. An offloaded flow skips
+//! the forward path entirely, so its later packets are not counted in the
+//! container's chain.
+
+use crate::docker::config::{RuleConfig, ToNftablesRule};
+use crate::nftables::FILTER_TABLE;
+use nftables::{
+    batch::Batch,
+    expr::{Expression, NamedExpression, Payload, PayloadField, SetItem},
+    schema::{Chain, FlowTable, FlushObject, NfCmd, NfListObject, Rule},
+    stmt::{Flow, Match, Operator, SetOp, Statement},
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook},
+};
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+pub const FLOWTABLE: &str = "hs-fastpath";
+pub const FASTPATH_CHAIN: &str = "harborshield-fastpath";
+
+/// After Docker's filter chains (priority 0), so only accepted flows arrive
+const FASTPATH_PRIORITY: i32 = 10;
+
+static DEVICES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Interfaces whose forwarded flows can be offloaded; none disables offload
+pub fn set_devices(devices: Vec<String>) {
+    if let Ok(mut current) = DEVICES.write() {
+        *current = devices;
+    }
+}
+
+pub fn devices() -> Vec<String> {
+    DEVICES.read().map(|d| d.clone()).unwrap_or_default()
+}
+
+pub fn enabled() -> bool {
+    DEVICES.read().is_ok_and(|d| !d.is_empty())
+}
+
+/// A container's offloaded output rules, numbered from 1
+pub struct OffloadTarget<'a> {
+    pub container_name: &'a str,
+    pub ips: Vec<IpAddr>,
+    pub rules: Vec<(usize, &'a RuleConfig)>,
+}
+
+/// Add the flowtable on `devices` and the forward chain feeding it
+pub fn create(batch: &mut Batch<'static>, family: NfFamily, devices: &[String]) {
+    batch.add(NfListObject::FlowTable(FlowTable {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(FLOWTABLE),
+        handle: None,
+        hook: Some(NfHook::Ingress),
+        prio: Some(0),
+        dev: Some(Cow::Owned(
+            devices.iter().cloned().map(Cow::Owned).collect::<Vec<_>>(),
+        )),
+    }));
+    batch.add(NfListObject::Chain(fastpath_chain(family)));
+}
+
+fn fastpath_chain(family: NfFamily) -> Chain<'static> {
+    Chain {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(FASTPATH_CHAIN),
+        newname: None,
+        handle: None,
+        _type: Some(NfChainType::Filter),
+        hook: Some(NfHook::Forward),
+        prio: Some(FASTPATH_PRIORITY),
+        dev: None,
+        policy: Some(NfChainPolicy::Accept),
+    }
+}
+
+/// `flow add` rules for `target`: the source is the container, the rest of
+/// the match is the rule's own. A rule that cannot be rendered is left out,
+/// as it is from the container chain
+pub fn offload_rules(family: NfFamily, target: &OffloadTarget<'_>) -> Vec<Rule<'static>> {
+    let ips: Vec<SetItem<'static>> = target
+        .ips
+        .iter()
+        .filter(|ip| ip.is_ipv4())
+        .map(|ip| SetItem::Element(Expression::String(Cow::Owned(ip.to_string()))))
+        .collect();
+    if ips.is_empty() {
+        return Vec::new();
+    }
+    let source = Statement::Match(Match {
+        left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+            PayloadField {
+                protocol: Cow::Borrowed("ip"),
+                field: Cow::Borrowed("saddr"),
+            },
+        ))),
+        right: Expression::Named(NamedExpression::Set(ips)),
+        op: Operator::EQ,
+    });
+
+    let mut rules = Vec::new();
+    for (number, rule) in &target.rules {
+        let Ok(statements) = rule.to_nftables_statements() else {
+            continue;
+        };
+        let mut expr = vec![source.clone()];
+        // Counters, logging and the verdict stay in the container chain
+        expr.extend(
+            statements
+                .into_iter()
+                .filter(|statement| matches!(statement, Statement::Match(_))),
+        );
+        expr.push(Statement::Flow(Flow {
+            op: SetOp::Add,
+            flowtable: Cow::Owned(format!("@{}", FLOWTABLE)),
+        }));
+        rules.push(Rule {
+            family,
+            table: Cow::Borrowed(FILTER_TABLE),
+            chain: Cow::Borrowed(FASTPATH_CHAIN),
+            expr: Cow::Owned(expr),
+            handle: None,
+            index: None,
+            comment: Some(Cow::Owned(format!(
+                "Offload output rule {} for {}",
+                number, target.container_name
+            ))),
+        });
+    }
+    rules
+}
+
+/// Replace the fastpath chain's rules with those of `targets`
+pub fn rebuild(family: NfFamily, targets: &[OffloadTarget<'_>]) -> Batch<'static> {
+    let mut batch = Batch::new();
+    batch.add(NfListObject::Chain(fastpath_chain(family)));
+    batch.add_cmd(NfCmd::Flush(FlushObject::Chain(fastpath_chain(family))));
+    for target in targets {
+        for rule in offload_rules(family, target) {
+            batch.add(NfListObject::Rule(rule));
+        }
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offload_rules() {
+        let rule: RuleConfig =
+            serde_yaml::from_str("ips: [10.0.5.20]\nproto: tcp\ndst_ports: [2049]\noffload: true")
+                .unwrap();
+        let target = OffloadTarget {
+            container_name: "nas",
+            ips: vec!["172.20.0.5".parse().unwrap(), "fd00::5".parse().unwrap()],
+            rules: vec![(2, &rule)],
+        };
+        let rules = offload_rules(NfFamily::IP, &target);
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            rules[0].comment.as_deref(),
+            Some("Offload output rule 2 for nas")
+        );
+        let json = serde_json::to_string(&rules[0]).unwrap();
+        assert!(json.contains(r#""flow":{"op":"add","flowtable":"@hs-fastpath"}"#));
+        assert!(json.contains("172.20.0.5"));
+        assert!(!json.contains("fd00::5"));
+        assert!(!json.contains("counter"));
+
+        // Without IPv4 addresses there is nothing to match the source on
+        let unaddressed = OffloadTarget {
+            ips: Vec::new(),
+            ..target
+        };
+        assert!(offload_rules(NfFamily::IP, &unaddressed).is_empty());
+
+        assert!(
+            serde_yaml::from_str::<RuleConfig>(
+                "ips: [10.0.5.20]\nproto: tcp\ndst_ports: [2049]\noffload: true\nverdict: {queue: 2}"
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,563 @@
+//! Removal of everything harborshield added to the filter tables, for
+//! `harborshield flush`.
+//!
+//! The `ip filter` and `ip6 filter` tables belong to Docker and are shared
+//! with whatever else the host runs, so an object is only removed when it is
+//! provably ours, in each table on its own:
+//!
+//! - the `harborshield` chain
+//! - container chains (`hs-*`) that the harborshield chain dispatches to or
+//!   that the database has a container for
+//! - the rdns sets of those chains, and the sets of blocked, blocklisted
+//!   and sinkholed addresses
+//! - the fastpath chain and its flowtable
+//! - the `harborshield-nat` table of port forwards and the
+//!   `harborshield-tproxy` table of interceptions, which are ours entirely
+//! - jump rules into the harborshield chain that carry our comment, removed
+//!   by handle
+//!
+//! Anything else that merely looks like ours is reported and left in place.
+//! The kill switch's `harborshield-panic` table is left for
+//! `harborshield panic --release`.
+
+use crate::nftables::blocked::BLOCKED_SET;
+use crate::nftables::blocklist::BLOCKLIST_SET;
+use crate::nftables::error::Result;
+use crate::nftables::family_to_string;
+use crate::nftables::flowtable::{FASTPATH_CHAIN, FLOWTABLE};
+use crate::nftables::forward::NAT_TABLE;
+use crate::nftables::rdns::{pending_set_name, rejected_set_name, verified_set_name};
+use crate::nftables::rootless::ROOTLESS_CHAIN;
+use crate::nftables::sinkhole::SINKHOLE_SET;
+use crate::nftables::tproxy::TPROXY_TABLE;
+use crate::nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, counters, runner};
+use crate::output::{Cell, Color, Column, Render, Table};
+use nftables::{
+    batch::Batch,
+    schema::{Chain, FlowTable, FlushObject, NfCmd, NfListObject, Rule},
+    types::NfFamily,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Comment on the rules that jump from Docker's chains into ours
+pub const JUMP_COMMENT: &str = "Jump to harborshield chain";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Rule,
+    Chain,
+    Set,
+    Flowtable,
+    Table,
+}
+
+impl ObjectKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rule => "rule",
+            Self::Chain => "chain",
+            Self::Set => "set",
+            Self::Flowtable => "flowtable",
+            Self::Table => "table",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlushItem {
+    pub kind: ObjectKind,
+    pub family: NfFamily,
+    /// Chain or set name; for rules, the chain holding the rule
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u64>,
+    /// Why a skipped object is left in place
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl FlushItem {
+    fn new(kind: ObjectKind, family: NfFamily, name: &str) -> Self {
+        Self {
+            kind,
+            family,
+            name: name.to_string(),
+            handle: None,
+            reason: None,
+        }
+    }
+
+    fn skipped(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FlushPlan {
+    pub remove: Vec<FlushItem>,
+    pub skipped: Vec<FlushItem>,
+}
+
+/// Jump and goto targets anywhere in a rule expression
+fn jump_targets<'a>(value: &'a Value, targets: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(map) => {
+            for (key, inner) in map {
+                if (key == "jump" || key == "goto")
+                    && let Some(target) = inner.get("target").and_then(Value::as_str)
+                {
+                    targets.push(target);
+                } else {
+                    jump_targets(inner, targets);
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| jump_targets(item, targets)),
+        _ => {}
+    }
+}
+
+fn rule_chain(rule: &Value) -> &str {
+    rule.get("chain").and_then(Value::as_str).unwrap_or("")
+}
+
+/// Work out what to remove from `nft -j list table <family> filter` output.
+/// `known_chains` are the container chains the database accounts for
+pub fn plan(listing: &Value, family: NfFamily, known_chains: &HashSet<String>) -> FlushPlan {
+    let items: Vec<&Value> = listing
+        .get("nftables")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .collect();
+    let chains: Vec<&str> = items
+        .iter()
+        .filter_map(|item| item.get("chain")?.get("name")?.as_str())
+        .collect();
+    let sets: HashSet<&str> = items
+        .iter()
+        .filter_map(|item| item.get("set")?.get("name")?.as_str())
+        .collect();
+    let rules: Vec<&Value> = items.iter().filter_map(|item| item.get("rule")).collect();
+
+    // Container chains our dispatch chain points at are ours
+    let mut dispatched = Vec::new();
+    for rule in rules
+        .iter()
+        .filter(|rule| rule_chain(rule) == HARBORSHIELD_CHAIN)
+    {
+        jump_targets(rule, &mut dispatched);
+    }
+
+    let mut plan = FlushPlan::default();
+    let mut owned = HashSet::new();
+    for chain in &chains {
+        let ours = *chain == HARBORSHIELD_CHAIN
+            || *chain == FASTPATH_CHAIN
+            || *chain == ROOTLESS_CHAIN
+            || (chain.starts_with("hs-")
+                && (dispatched.contains(chain) || known_chains.contains(*chain)));
+        if ours {
+            owned.insert(*chain);
+        } else if chain.starts_with("hs-") {
+            plan.skipped.push(
+                FlushItem::new(ObjectKind::Chain, family, chain)
+                    .skipped("not dispatched to by harborshield or known to its database"),
+            );
+        }
+    }
+
+    let mut foreign_jumps = false;
+    for rule in &rules {
+        let chain = rule_chain(rule);
+        if owned.contains(chain) {
+            continue;
+        }
+        let mut targets = Vec::new();
+        jump_targets(rule, &mut targets);
+        if !targets.contains(&HARBORSHIELD_CHAIN) {
+            continue;
+        }
+        let mut item = FlushItem::new(ObjectKind::Rule, family, chain);
+        item.handle = rule.get("handle").and_then(Value::as_u64);
+        let marked = rule.get("comment").and_then(Value::as_str) == Some(JUMP_COMMENT);
+        if marked && item.handle.is_some() {
+            plan.remove.push(item);
+        } else {
+            foreign_jumps = true;
+            plan.skipped
+                .push(item.skipped("jumps to harborshield but was not added by it"));
+        }
+    }
+
+    for chain in &chains {
+        if !owned.contains(chain) {
+            continue;
+        }
+        let item = FlushItem::new(ObjectKind::Chain, family, chain);
+        if *chain == HARBORSHIELD_CHAIN && foreign_jumps {
+            // Emptied, but deleting it would break the rules pointing at it
+            plan.skipped
+                .push(item.skipped("emptied but kept, other rules still jump to it"));
+        } else {
+            plan.remove.push(item);
+        }
+        for set in [
+            pending_set_name(chain),
+            verified_set_name(chain),
+            rejected_set_name(chain),
+        ] {
+            if sets.contains(set.as_str()) {
+                plan.remove
+                    .push(FlushItem::new(ObjectKind::Set, family, &set));
+            }
+        }
+    }
+
+    for set in [BLOCKED_SET, BLOCKLIST_SET, SINKHOLE_SET] {
+        if sets.contains(set) {
+            plan.remove
+                .push(FlushItem::new(ObjectKind::Set, family, set));
+        }
+    }
+
+    let has_flowtable = items
+        .iter()
+        .any(|item| item.get("flowtable").and_then(|f| f.get("name")?.as_str()) == Some(FLOWTABLE));
+    if has_flowtable {
+        plan.remove
+            .push(FlushItem::new(ObjectKind::Flowtable, family, FLOWTABLE));
+    }
+
+    plan
+}
+
+fn chain(family: NfFamily, name: &str) -> Chain<'static> {
+    Chain {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name.to_string()),
+        newname: None,
+        handle: None,
+        _type: None,
+        hook: None,
+        prio: None,
+        dev: None,
+        policy: None,
+    }
+}
+
+/// The batch carrying out `plan`: jump rules go first, then every owned
+/// chain is emptied so no rule still references another before the chains
+/// and sets are deleted. Each object is removed from its own family's table
+pub fn batch(plan: &FlushPlan) -> Batch<'static> {
+    let mut batch = Batch::new();
+    let owned = |kind| plan.remove.iter().filter(move |item| item.kind == kind);
+
+    for item in owned(ObjectKind::Rule) {
+        batch.delete(NfListObject::Rule(Rule {
+            family: item.family,
+            table: Cow::Borrowed(FILTER_TABLE),
+            chain: Cow::Owned(item.name.clone()),
+            expr: Cow::Owned(Vec::new()),
+            handle: item.handle.map(|handle| handle as u32),
+            index: None,
+            comment: None,
+        }));
+    }
+
+    for item in plan
+        .skipped
+        .iter()
+        .filter(|item| item.kind == ObjectKind::Chain && item.name == HARBORSHIELD_CHAIN)
+    {
+        batch.add_cmd(NfCmd::Flush(FlushObject::Chain(chain(
+            item.family,
+            HARBORSHIELD_CHAIN,
+        ))));
+    }
+    for item in owned(ObjectKind::Chain) {
+        batch.add_cmd(NfCmd::Flush(FlushObject::Chain(chain(
+            item.family,
+            &item.name,
+        ))));
+    }
+    for item in owned(ObjectKind::Chain) {
+        batch.delete(NfListObject::Chain(chain(item.family, &item.name)));
+    }
+    for item in owned(ObjectKind::Set) {
+        crate::nftables::rdns::delete_set(&mut batch, item.family, item.name.clone());
+    }
+    for item in owned(ObjectKind::Flowtable) {
+        batch.delete(NfListObject::FlowTable(FlowTable {
+            family: item.family,
+            table: Cow::Borrowed(FILTER_TABLE),
+            name: Cow::Owned(item.name.clone()),
+            handle: None,
+            hook: None,
+            prio: None,
+            dev: None,
+        }));
+    }
+    for item in owned(ObjectKind::Table) {
+        batch.delete(NfListObject::Table(nftables::schema::Table {
+            family: item.family,
+            name: Cow::Owned(item.name.clone()),
+            handle: None,
+        }));
+    }
+
+    batch
+}
+
+/// Read the filter tables and plan their flush. The `ip6 filter` table is
+/// only there when Docker manages IPv6
+pub async fn plan_current(known_chains: &HashSet<String>) -> Result<FlushPlan> {
+    let listing = counters::list_filter_table("flush_list").await?;
+    let mut plan = plan(&listing, NfFamily::IP, known_chains);
+
+    let output = runner::run_nft(
+        "flush_list_ip6",
+        &["-j", "list", "table", "ip6", FILTER_TABLE],
+    )
+    .await?;
+    if output.status.success() {
+        let listing: Value = serde_json::from_slice(&output.stdout)
+            .map_err(crate::nftables::error::NftablesError::invalid_json)?;
+        let ip6 = self::plan(&listing, NfFamily::IP6, known_chains);
+        plan.remove.extend(ip6.remove);
+        plan.skipped.extend(ip6.skipped);
+    }
+
+    let output = runner::run_nft("flush_list_tables", &["-j", "list", "tables"]).await?;
+    let tables: Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    for name in [NAT_TABLE, TPROXY_TABLE] {
+        let exists = tables
+            .get("nftables")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item.get("table"))
+            .any(|table| {
+                table.get("family").and_then(Value::as_str) == Some("ip")
+                    && table.get("name").and_then(Value::as_str) == Some(name)
+            });
+        if exists {
+            plan.remove
+                .push(FlushItem::new(ObjectKind::Table, NfFamily::IP, name));
+        }
+    }
+    Ok(plan)
+}
+
+/// Remove everything in `plan` in one transaction
+pub async fn apply(plan: &FlushPlan) -> Result<()> {
+    if plan.remove.is_empty() {
+        return Ok(());
+    }
+    let json = serde_json::to_string(&batch(plan).to_nftables())
+        .map_err(crate::nftables::error::NftablesError::invalid_json)?;
+    runner::apply_json("flush", json, None).await?;
+    Ok(())
+}
+
+/// What `harborshield flush` did or would do
+#[derive(Debug, Clone, Serialize)]
+pub struct FlushReport {
+    #[serde(flatten)]
+    pub plan: FlushPlan,
+    /// Whether the objects were actually removed
+    pub applied: bool,
+    /// Whether container state in the database was reset
+    pub database_reset: bool,
+}
+
+impl Render for FlushReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("KIND"),
+            Column::left("FAMILY"),
+            Column::left("NAME"),
+            Column::right("HANDLE").wide(),
+            Column::left("ACTION"),
+        ]);
+
+        let action = if self.applied { "removed" } else { "remove" };
+        let rows = self
+            .plan
+            .remove
+            .iter()
+            .map(|item| (item, Cell::colored(action, Color::Green)))
+            .chain(self.plan.skipped.iter().map(|item| {
+                let reason = item.reason.as_deref().unwrap_or_default();
+                (
+                    item,
+                    Cell::colored(format!("skip: {}", reason), Color::Yellow),
+                )
+            }));
+        for (item, action) in rows {
+            table.row(vec![
+                item.kind.as_str().into(),
+                family_to_string(&item.family).into(),
+                item.name.clone().into(),
+                item.handle
+                    .map_or_else(|| "-".to_string(), |h| h.to_string())
+                    .into(),
+                action,
+            ]);
+        }
+
+        if self.plan.remove.is_empty() && self.plan.skipped.is_empty() {
+            table.footer("no harborshield objects in the filter tables");
+        }
+        if !self.applied {
+            table.footer("nothing was changed, rerun with --confirm to remove these");
+        } else if self.database_reset {
+            table.footer("container state in the database was reset");
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn listing() -> Value {
+        json!({ "nftables": [
+            { "metainfo": { "version": "1.0.9" } },
+            { "table": { "family": "ip", "name": "filter", "handle": 1 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "DOCKER-USER", "handle": 2 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "INPUT", "handle": 3 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "harborshield", "handle": 4 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-web-0123456789ab", "handle": 5 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-db-ba9876543210", "handle": 6 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-mine", "handle": 7 } },
+            { "set": { "family": "ip", "table": "filter", "name": "hs-web-0123456789ab-rdns-p", "handle": 8 } },
+            { "set": { "family": "ip", "table": "filter", "name": "harborshield-blocked", "handle": 14 } },
+            { "flowtable": { "family": "ip", "table": "filter", "name": "hs-fastpath", "handle": 9 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "harborshield-fastpath", "handle": 13 } },
+            { "rule": { "family": "ip", "table": "filter", "chain": "DOCKER-USER", "handle": 10,
+                "comment": JUMP_COMMENT,
+                "expr": [{ "counter": { "packets": 0, "bytes": 0 } }, { "jump": { "target": "harborshield" } }] } },
+            { "rule": { "family": "ip", "table": "filter", "chain": "DOCKER-USER", "handle": 11,
+                "expr": [{ "match": { "op": "==", "left": { "meta": { "key": "iifname" } }, "right": "eth0" } },
+                         { "drop": null }] } },
+            { "rule": { "family": "ip", "table": "filter", "chain": "harborshield", "handle": 12,
+                "comment": "Container source IP verdict map",
+                "expr": [{ "vmap": { "key": { "payload": { "protocol": "ip", "field": "saddr" } },
+                    "data": { "set": [["172.17.0.2", { "jump": { "target": "hs-web-0123456789ab" } }]] } } }] } },
+        ]})
+    }
+
+    #[test]
+    fn test_plan_only_owned_objects() {
+        let known = HashSet::from(["hs-db-ba9876543210".to_string()]);
+        let plan = plan(&listing(), NfFamily::IP, &known);
+
+        let removed: Vec<(ObjectKind, &str)> = plan
+            .remove
+            .iter()
+            .map(|item| (item.kind, item.name.as_str()))
+            .collect();
+        assert_eq!(
+            removed,
+            vec![
+                (ObjectKind::Rule, "DOCKER-USER"),
+                (ObjectKind::Chain, "harborshield"),
+                (ObjectKind::Chain, "hs-web-0123456789ab"),
+                (ObjectKind::Set, "hs-web-0123456789ab-rdns-p"),
+                (ObjectKind::Chain, "hs-db-ba9876543210"),
+                (ObjectKind::Chain, "harborshield-fastpath"),
+                (ObjectKind::Set, "harborshield-blocked"),
+                (ObjectKind::Flowtable, "hs-fastpath"),
+            ]
+        );
+        assert_eq!(plan.remove[0].handle, Some(10));
+        // A chain that only looks like ours is left alone
+        assert_eq!(plan.skipped.len(), 1);
+        assert_eq!(plan.skipped[0].name, "hs-mine");
+    }
+
+    #[test]
+    fn test_plan_keeps_referenced_dispatch_chain() {
+        let mut listing = listing();
+        listing["nftables"].as_array_mut().unwrap().push(json!({
+            "rule": { "family": "ip", "table": "filter", "chain": "INPUT", "handle": 20,
+                "expr": [{ "jump": { "target": "harborshield" } }] }
+        }));
+        let plan = plan(&listing, NfFamily::IP, &HashSet::new());
+
+        assert!(
+            !plan
+                .remove
+                .iter()
+                .any(|item| item.name == HARBORSHIELD_CHAIN)
+        );
+        assert!(plan.skipped.iter().any(|item| item.name == "INPUT"));
+        assert!(
+            plan.skipped
+                .iter()
+                .any(|item| item.kind == ObjectKind::Chain && item.name == HARBORSHIELD_CHAIN)
+        );
+
+        let commands = serde_json::to_value(batch(&plan).to_nftables()).unwrap();
+        let commands = commands["nftables"].as_array().unwrap();
+        assert!(commands[1]["flush"]["chain"]["name"] == HARBORSHIELD_CHAIN);
+        assert!(
+            !commands
+                .iter()
+                .any(|cmd| cmd["delete"]["chain"]["name"] == HARBORSHIELD_CHAIN)
+        );
+    }
+
+    #[test]
+    fn test_plan_ip6_table() {
+        let listing = json!({ "nftables": [
+            { "table": { "family": "ip6", "name": "filter", "handle": 1 } },
+            { "chain": { "family": "ip6", "table": "filter", "name": "DOCKER-USER", "handle": 2 } },
+            { "chain": { "family": "ip6", "table": "filter", "name": "harborshield", "handle": 3 } },
+            { "chain": { "family": "ip6", "table": "filter", "name": "hs-web-0123456789ab", "handle": 4 } },
+            { "set": { "family": "ip6", "table": "filter", "name": "hs-web-0123456789ab-rdns-ok", "handle": 5 } },
+            { "rule": { "family": "ip6", "table": "filter", "chain": "DOCKER-USER", "handle": 6,
+                "comment": JUMP_COMMENT,
+                "expr": [{ "jump": { "target": "harborshield" } }] } },
+            { "rule": { "family": "ip6", "table": "filter", "chain": "harborshield", "handle": 7,
+                "expr": [{ "vmap": { "key": { "payload": { "protocol": "ip6", "field": "saddr" } },
+                    "data": { "set": [["fd00::2", { "jump": { "target": "hs-web-0123456789ab" } }]] } } }] } },
+        ]});
+        let mut plan = plan(&listing, NfFamily::IP6, &HashSet::new());
+        assert_eq!(plan.remove.len(), 4);
+        assert!(plan.remove.iter().all(|item| item.family == NfFamily::IP6));
+
+        // Merged with the ip table's plan, each object goes from its own table
+        plan.remove.push(FlushItem::new(
+            ObjectKind::Chain,
+            NfFamily::IP,
+            "hs-db-ba9876543210",
+        ));
+        let commands = serde_json::to_value(batch(&plan).to_nftables()).unwrap();
+        let commands = commands["nftables"].as_array().unwrap();
+        let deleted = |kind: &str, name: &str| {
+            commands
+                .iter()
+                .find(|cmd| cmd["delete"][kind]["name"] == name)
+                .map(|cmd| cmd["delete"][kind]["family"].clone())
+        };
+        assert_eq!(commands[0]["delete"]["rule"]["family"], "ip6");
+        assert_eq!(commands[0]["delete"]["rule"]["handle"], 6);
+        assert_eq!(deleted("chain", "harborshield"), Some(json!("ip6")));
+        assert_eq!(deleted("chain", "hs-web-0123456789ab"), Some(json!("ip6")));
+        assert_eq!(
+            deleted("set", "hs-web-0123456789ab-rdns-ok"),
+            Some(json!("ip6"))
+        );
+        assert_eq!(deleted("chain", "hs-db-ba9876543210"), Some(json!("ip")));
+    }
+}
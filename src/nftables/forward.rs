@@ -0,0 +1,258 @@
+//! `forward` rules: host ports DNATed to containers by harborshield, for
+//! containers run without published ports and so without docker-proxy.
+//!
+//! Everything lives in the `ip harborshield-nat` table, which is ours alone
+//! and rebuilt as a whole from every container's forwards. Its `prerouting`
+//! chain forwards connections arriving for a local address, and its `output`
+//! chain those the host opens itself. With `hairpin`, `postrouting`
+//! masquerades connections from loopback and from the container to itself,
+//! whose replies would otherwise skip the translation; loopback clients also
+//! need `net.ipv4.conf.all.route_localnet=1`, which harborshield doesn't set.
+//!
+//! Forwarded connections then reach the container's chain like those to a
+//! published port, and `mapped_ports` decides who gets through. Only IPv4
+//! is forwarded.
+
+use crate::docker::config::ForwardRule;
+use nftables::{
+    batch::Batch,
+    expr::{
+        CT, Expression, Fib, FibFlag, FibResult, NamedExpression, Payload, PayloadField, Prefix,
+        SetItem,
+    },
+    schema::{Chain, FlushObject, NfCmd, NfListObject, Rule, Table},
+    stmt::{Counter, Match, NAT, NATFamily, Operator, Statement},
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook},
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+
+pub const NAT_TABLE: &str = "harborshield-nat";
+
+const PREROUTING_CHAIN: &str = "prerouting";
+const OUTPUT_CHAIN: &str = "output";
+pub(crate) const POSTROUTING_CHAIN: &str = "postrouting";
+
+/// The table's base chains, each hooked where its name says
+pub(crate) const NAT_CHAINS: [&str; 3] = [PREROUTING_CHAIN, OUTPUT_CHAIN, POSTROUTING_CHAIN];
+
+/// nft's `dstnat` and `srcnat` priorities
+const DSTNAT_PRIORITY: i32 = -100;
+const SRCNAT_PRIORITY: i32 = 100;
+
+/// A container's forwards to its IPv4 address
+pub struct ForwardTarget<'a> {
+    pub container_name: &'a str,
+    pub ip: Ipv4Addr,
+    pub forwards: Vec<&'a ForwardRule>,
+}
+
+fn chain(name: &'static str, hook: NfHook, prio: i32) -> Chain<'static> {
+    Chain {
+        family: NfFamily::IP,
+        table: Cow::Borrowed(NAT_TABLE),
+        name: Cow::Borrowed(name),
+        newname: None,
+        handle: None,
+        _type: Some(NfChainType::NAT),
+        hook: Some(hook),
+        prio: Some(prio),
+        dev: None,
+        policy: Some(NfChainPolicy::Accept),
+    }
+}
+
+fn chains() -> [Chain<'static>; 3] {
+    [
+        chain(PREROUTING_CHAIN, NfHook::Prerouting, DSTNAT_PRIORITY),
+        chain(OUTPUT_CHAIN, NfHook::Output, DSTNAT_PRIORITY),
+        chain(POSTROUTING_CHAIN, NfHook::Postrouting, SRCNAT_PRIORITY),
+    ]
+}
+
+fn payload(protocol: &str, field: &str, right: Expression<'static>) -> Statement<'static> {
+    Statement::Match(Match {
+        left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+            PayloadField {
+                protocol: Cow::Owned(protocol.to_string()),
+                field: Cow::Owned(field.to_string()),
+            },
+        ))),
+        right,
+        op: Operator::EQ,
+    })
+}
+
+fn address(ip: Ipv4Addr) -> Expression<'static> {
+    Expression::String(Cow::Owned(ip.to_string()))
+}
+
+/// Match connections to `forward`'s host address, or to any local one
+fn host_destination(forward: &ForwardRule) -> Statement<'static> {
+    match forward.host_ip {
+        Some(ip) => payload("ip", "daddr", address(ip)),
+        None => Statement::Match(Match {
+            left: Expression::Named(NamedExpression::Fib(Fib {
+                result: FibResult::Type,
+                flags: HashSet::from([FibFlag::Daddr]),
+            })),
+            right: Expression::String(Cow::Borrowed("local")),
+            op: Operator::EQ,
+        }),
+    }
+}
+
+fn rule(chain: &'static str, expr: Vec<Statement<'static>>, comment: String) -> Rule<'static> {
+    Rule {
+        family: NfFamily::IP,
+        table: Cow::Borrowed(NAT_TABLE),
+        chain: Cow::Borrowed(chain),
+        expr: Cow::Owned(expr),
+        handle: None,
+        index: None,
+        comment: Some(Cow::Owned(comment)),
+    }
+}
+
+/// The DNAT and hairpin rules of `target`
+pub fn forward_rules(target: &ForwardTarget<'_>) -> Vec<Rule<'static>> {
+    let mut rules = Vec::new();
+    for forward in &target.forwards {
+        let proto = forward.proto.to_string();
+        let port = forward.container_port();
+        let comment = format!(
+            "Forward {}/{} to {}",
+            forward.host_port, proto, target.container_name
+        );
+
+        let dnat = vec![
+            host_destination(forward),
+            payload(
+                &proto,
+                "dport",
+                Expression::Number(forward.host_port.into()),
+            ),
+            Statement::Counter(Counter::Anonymous(None)),
+            Statement::DNAT(Some(NAT {
+                addr: Some(address(target.ip)),
+                family: Some(NATFamily::IP),
+                port: Some(Expression::Number(port.into())),
+                flags: None,
+            })),
+        ];
+        rules.push(rule(PREROUTING_CHAIN, dnat.clone(), comment.clone()));
+        rules.push(rule(OUTPUT_CHAIN, dnat, comment.clone()));
+
+        if forward.hairpin {
+            let sources = vec![
+                SetItem::Element(Expression::Named(NamedExpression::Prefix(Prefix {
+                    addr: Box::new(address(Ipv4Addr::new(127, 0, 0, 0))),
+                    len: 8,
+                }))),
+                SetItem::Element(address(target.ip)),
+            ];
+            let masquerade = vec![
+                payload(
+                    "ip",
+                    "saddr",
+                    Expression::Named(NamedExpression::Set(sources)),
+                ),
+                payload("ip", "daddr", address(target.ip)),
+                payload(&proto, "dport", Expression::Number(port.into())),
+                Statement::Match(Match {
+                    left: Expression::Named(NamedExpression::CT(CT {
+                        key: Cow::Borrowed("status"),
+                        family: None,
+                        dir: None,
+                    })),
+                    right: Expression::String(Cow::Borrowed("dnat")),
+                    op: Operator::IN,
+                }),
+                Statement::Masquerade(None),
+            ];
+            rules.push(rule(
+                POSTROUTING_CHAIN,
+                masquerade,
+                format!("Hairpin {}", comment),
+            ));
+        }
+    }
+    rules
+}
+
+/// Replace the table's rules with those of `targets`
+pub fn rebuild(targets: &[ForwardTarget<'_>]) -> Batch<'static> {
+    let mut batch = Batch::new();
+    batch.add(NfListObject::Table(Table {
+        family: NfFamily::IP,
+        name: Cow::Borrowed(NAT_TABLE),
+        handle: None,
+    }));
+    for chain in chains() {
+        batch.add(NfListObject::Chain(chain.clone()));
+        batch.add_cmd(NfCmd::Flush(FlushObject::Chain(chain)));
+    }
+    for target in targets {
+        for rule in forward_rules(target) {
+            batch.add(NfListObject::Rule(rule));
+        }
+    }
+    batch
+}
+
+/// Delete the table, once no container forwards anything
+pub fn remove() -> Batch<'static> {
+    let mut batch = Batch::new();
+    let table = Table {
+        family: NfFamily::IP,
+        name: Cow::Borrowed(NAT_TABLE),
+        handle: None,
+    };
+    // Adding first makes the delete succeed when the table is already gone
+    batch.add(NfListObject::Table(table.clone()));
+    batch.delete(NfListObject::Table(table));
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forward_rules() {
+        let forwards: Vec<ForwardRule> = serde_yaml::from_str(
+            "- host_port: 8080\n  port: 80\n- host_port: 5353\n  proto: udp\n  host_ip: 192.0.2.10\n  hairpin: false\n",
+        )
+        .unwrap();
+        let target = ForwardTarget {
+            container_name: "web",
+            ip: "172.17.0.2".parse().unwrap(),
+            forwards: forwards.iter().collect(),
+        };
+        let rules = forward_rules(&target);
+        let chains: Vec<&str> = rules.iter().map(|rule| rule.chain.as_ref()).collect();
+        assert_eq!(
+            chains,
+            [
+                "prerouting",
+                "output",
+                "postrouting",
+                "prerouting",
+                "output"
+            ]
+        );
+
+        let json = serde_json::to_string(&rules[0]).unwrap();
+        assert!(json.contains(r#""fib":{"result":"type","flags":["daddr"]}"#));
+        assert!(json.contains(r#""dnat":{"addr":"172.17.0.2","family":"ip","port":80}"#));
+        assert_eq!(rules[0].comment.as_deref(), Some("Forward 8080/tcp to web"));
+        let hairpin = serde_json::to_string(&rules[2]).unwrap();
+        assert!(hairpin.contains(r#"{"prefix":{"addr":"127.0.0.0","len":8}}"#));
+        assert!(hairpin.contains(r#""masquerade":null"#));
+
+        let json = serde_json::to_string(&rules[3]).unwrap();
+        assert!(json.contains(r#""right":"192.0.2.10""#));
+        assert!(json.contains(r#""port":5353"#));
+    }
+}
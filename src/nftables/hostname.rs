@@ -0,0 +1,110 @@
+//! The sets of `host: <name>` output rules, which are built in
+//! [`harborshield_core::nftables::hostname`]: filling them with the
+//! addresses last resolved, registering them for refresh and deleting
+//! those of rules a chain no longer has.
+
+use crate::dns::hostname::{self as dns_hostname, HostTarget};
+use crate::docker::config::{Config, RuleContext};
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::runner;
+use harborshield_core::nftables::hostname::delete_set;
+use nftables::{batch::Batch, types::NfFamily};
+use std::net::IpAddr;
+
+pub use harborshield_core::nftables::hostname::{daddr_match, hostname_rules, replace, set_name};
+
+/// Keep `targets` as those of `chain`, filling their sets from the last
+/// answers and deleting the sets it had before and no longer uses
+pub fn register(
+    batch: &mut Batch<'static>,
+    family: NfFamily,
+    chain: &str,
+    targets: Vec<HostTarget>,
+) {
+    for target in &targets {
+        let addrs: Vec<IpAddr> = dns_hostname::resolved(&target.hostname, target.policy)
+            .into_iter()
+            .filter(|addr| addr.is_ipv6() == target.ipv6)
+            .collect();
+        replace(batch, family, &target.set, &addrs);
+    }
+    for stale in dns_hostname::register_chain(family == NfFamily::IP6, chain, targets) {
+        delete_set(batch, family, stale);
+    }
+}
+
+/// Create and fill the sets of the chain's hostname rules, registering
+/// them for refresh, and delete those of rules it no longer has. Goes
+/// after the chain is flushed and before its rules are added
+pub fn add_to_batch(batch: &mut Batch<'static>, ctx: &RuleContext, config: &Config) {
+    let targets = harborshield_core::nftables::hostname::add_to_batch(
+        batch,
+        ctx,
+        config,
+        &super::rule_defaults(),
+    );
+    register(batch, ctx.family, ctx.chain_name, targets);
+}
+
+/// Delete the hostname sets of a container chain (the chain must be gone
+/// first)
+pub fn delete_sets(batch: &mut Batch<'static>, family: NfFamily, chain: &str) {
+    register(batch, family, chain, Vec::new());
+}
+
+/// Swap the addresses of a hostname set for `addrs`
+pub async fn replace_elements(ipv6: bool, name: &str, addrs: &[IpAddr]) -> Result<()> {
+    let family = if ipv6 { NfFamily::IP6 } else { NfFamily::IP };
+    let mut batch = Batch::new();
+    replace(&mut batch, family, name, addrs);
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("hostname_replace_elements", json, None).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nftables::FILTER_TABLE;
+
+    #[test]
+    fn test_hostname_sets() {
+        let config: Config = serde_yaml::from_str(
+            "output:\n  - {host: api.example.org, proto: tcp, dst_ports: [443]}\n",
+        )
+        .unwrap();
+        let chain = "hs-web-hostnamesets";
+        let ctx = RuleContext {
+            container_id: "hostnamesets",
+            container_name: "web",
+            container_ips: &[],
+            container_ports: &[],
+            chain_name: chain,
+            table_name: FILTER_TABLE,
+            family: NfFamily::IP,
+        };
+        let set = set_name(chain, 1);
+
+        let mut batch = Batch::new();
+        add_to_batch(&mut batch, &ctx, &config);
+        let json = serde_json::to_string(&batch.to_nftables()).unwrap();
+        assert!(json.contains(r#"{"flush":{"set""#), "{}", json);
+        assert!(
+            dns_hostname::targets()
+                .iter()
+                .any(|target| target.set == set)
+        );
+
+        // Dropping the rule deletes its set on the next build
+        let mut batch = Batch::new();
+        add_to_batch(&mut batch, &ctx, &Config::new());
+        let json = serde_json::to_string(&batch.to_nftables()).unwrap();
+        assert!(json.contains(r#"{"delete":{"set""#), "{}", json);
+        assert!(
+            !dns_hostname::targets()
+                .iter()
+                .any(|target| target.set == set)
+        );
+    }
+}
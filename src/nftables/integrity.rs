@@ -0,0 +1,149 @@
+//! What the filter table holds of ours, for noticing when someone else
+//! removed or changed it (`nft flush ruleset`, a firewalld reload).
+//!
+//! Chains are compared by a checksum of their rules, counters left out, so
+//! traffic alone never looks like a change.
+
+use crate::nftables::error::Result;
+use crate::nftables::flush::JUMP_COMMENT;
+use crate::nftables::{HARBORSHIELD_CHAIN, INPUT_CHAIN, OUTPUT_CHAIN, counters};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// Our part of the filter table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableState {
+    /// Whether the filter table exists at all
+    pub table: bool,
+    /// Docker chains present without their jump into ours
+    pub missing_jumps: Vec<String>,
+    /// Checksum of the rules of the harborshield chain and each container
+    /// chain
+    pub chains: HashMap<String, u64>,
+}
+
+impl TableState {
+    pub fn has_base_chain(&self) -> bool {
+        self.chains.contains_key(HARBORSHIELD_CHAIN)
+    }
+}
+
+fn ours(chain: &str) -> bool {
+    chain == HARBORSHIELD_CHAIN || chain.starts_with("hs-")
+}
+
+/// Drop counter values, which change with traffic
+fn without_counters(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| match key.as_str() {
+                "counter" if value.is_object() => (key.clone(), Value::Null),
+                _ => (key.clone(), without_counters(value)),
+            })
+            .collect(),
+        Value::Array(items) => items.iter().map(without_counters).collect(),
+        other => other.clone(),
+    }
+}
+
+/// Our chains and jumps from `nft -j list table` output, with
+/// `jump_sources` the Docker chains that should jump into ours
+pub fn parse_table_state(json: &Value, jump_sources: &[&str]) -> TableState {
+    let items = json
+        .get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten();
+
+    let mut present = BTreeSet::new();
+    let mut jumped_from = BTreeSet::new();
+    let mut hashers: HashMap<String, DefaultHasher> = HashMap::new();
+    for item in items {
+        if let Some(name) = item.pointer("/chain/name").and_then(|n| n.as_str()) {
+            present.insert(name.to_string());
+            if ours(name) {
+                hashers.entry(name.to_string()).or_default();
+            }
+            continue;
+        }
+        let Some(rule) = item.get("rule") else {
+            continue;
+        };
+        let Some(chain) = rule.get("chain").and_then(|c| c.as_str()) else {
+            continue;
+        };
+        if rule.get("comment").and_then(|c| c.as_str()) == Some(JUMP_COMMENT) {
+            jumped_from.insert(chain.to_string());
+        }
+        if ours(chain) {
+            let expr = rule.get("expr").map(without_counters).unwrap_or_default();
+            expr.to_string()
+                .hash(hashers.entry(chain.to_string()).or_default());
+        }
+    }
+
+    TableState {
+        table: true,
+        missing_jumps: jump_sources
+            .iter()
+            .filter(|source| present.contains(**source) && !jumped_from.contains(**source))
+            .map(|source| source.to_string())
+            .collect(),
+        chains: hashers
+            .into_iter()
+            .map(|(chain, hasher)| (chain, hasher.finish()))
+            .collect(),
+    }
+}
+
+/// The current state of our part of the filter table
+pub async fn table_state() -> Result<TableState> {
+    let forward_chain = crate::runtime::kind().forward_chain();
+    match counters::list_filter_table("list_integrity").await {
+        Ok(json) => Ok(parse_table_state(
+            &json,
+            &[forward_chain, INPUT_CHAIN, OUTPUT_CHAIN],
+        )),
+        // No such table
+        Err(e) if e.exit_code() == Some(2) => Ok(TableState::default()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_table_state() {
+        let table = |packets: u64, jump: bool| {
+            let mut items = vec![
+                serde_json::json!({ "chain": { "name": "DOCKER-USER" } }),
+                serde_json::json!({ "chain": { "name": "INPUT" } }),
+                serde_json::json!({ "chain": { "name": "harborshield" } }),
+                serde_json::json!({ "chain": { "name": "hs-web-abc" } }),
+                serde_json::json!({ "rule": { "chain": "hs-web-abc", "handle": packets,
+                    "expr": [{ "counter": { "packets": packets, "bytes": 10 } }, { "accept": null }] } }),
+            ];
+            if jump {
+                items.push(serde_json::json!({ "rule": { "chain": "DOCKER-USER",
+                    "comment": JUMP_COMMENT, "expr": [{ "jump": { "target": "harborshield" } }] } }));
+            }
+            serde_json::json!({ "nftables": items })
+        };
+        let sources = ["DOCKER-USER", "INPUT", "OUTPUT"];
+
+        let state = parse_table_state(&table(1, true), &sources);
+        assert!(state.table && state.has_base_chain());
+        assert_eq!(state.missing_jumps, vec!["INPUT"]);
+        assert_eq!(state.chains.len(), 2);
+
+        // Traffic leaves checksums alone
+        assert_eq!(parse_table_state(&table(500, true), &sources), state);
+        let state = parse_table_state(&table(1, false), &sources);
+        assert_eq!(state.missing_jumps, vec!["DOCKER-USER", "INPUT"]);
+    }
+}
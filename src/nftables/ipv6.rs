@@ -0,0 +1,311 @@
+//! Container chains for IPv6 addresses.
+//!
+//! Docker keeps its IPv6 forwarding rules in the `ip6 filter` table when
+//! `ip6tables` is enabled (the default since Docker 27). The chains built for
+//! a container's IPv4 addresses are mirrored there under the same `hs-*`
+//! names, from the same rules with only their IPv6 addresses kept (see
+//! [`Config::for_family`]), and dispatched to from an `ip6` harborshield
+//! chain. The table is rebuilt as a whole from the tracked containers, so
+//! chains of containers that went away are dropped with it.
+
+use crate::Result;
+use crate::database::EnforcementMode;
+use crate::docker::config::Config;
+use crate::nftables::docker::{create_harborshield_chain, create_jump_rules};
+use crate::nftables::transaction::NftablesTransaction;
+use crate::nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, INPUT_CHAIN, OUTPUT_CHAIN, runner};
+use nftables::{
+    expr::{Expression, NamedExpression, Payload, PayloadField, SetItem, Verdict},
+    schema::{Chain, NfListObject, Rule},
+    stmt::{JumpTarget, Statement, VerdictMap},
+    types::NfFamily,
+};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::net::IpAddr;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// A container's IPv6 addresses and the rules its chain is built from
+pub struct Ipv6Target<'a> {
+    pub container_id: &'a str,
+    pub container_name: &'a str,
+    pub ips: Vec<IpAddr>,
+    pub ports: Vec<(u16, String)>,
+    pub config: &'a Config,
+    pub mode: EnforcementMode,
+}
+
+/// What the `ip6 filter` table already holds
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Ip6Table {
+    /// Base chains there, by name
+    pub chains: Vec<String>,
+    /// Base chains already jumping to the harborshield chain
+    pub jumps: Vec<String>,
+    /// Container chains the harborshield chain dispatches to
+    pub dispatched: Vec<String>,
+}
+
+impl Ip6Table {
+    /// Read a `nft -j list table ip6 filter` listing
+    pub fn parse(listing: &Value) -> Self {
+        let items: Vec<&Value> = listing
+            .get("nftables")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut table = Self {
+            chains: items
+                .iter()
+                .filter_map(|item| item.get("chain")?.get("name")?.as_str())
+                .map(str::to_string)
+                .collect(),
+            ..Self::default()
+        };
+        for rule in items.iter().filter_map(|item| item.get("rule")) {
+            let chain = rule.get("chain").and_then(Value::as_str).unwrap_or("");
+            let targets = jump_targets(rule);
+            if chain == HARBORSHIELD_CHAIN {
+                table.dispatched.extend(targets);
+            } else if targets.iter().any(|target| target == HARBORSHIELD_CHAIN)
+                && !table.jumps.iter().any(|jump| jump == chain)
+            {
+                table.jumps.push(chain.to_string());
+            }
+        }
+        table
+    }
+
+    fn needs_jump(&self, chain: &str) -> bool {
+        self.chains.iter().any(|name| name == chain) && !self.jumps.iter().any(|j| j == chain)
+    }
+}
+
+fn jump_targets(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .flat_map(
+                |(key, inner)| match inner.get("target").and_then(Value::as_str) {
+                    Some(target) if key == "jump" || key == "goto" => vec![target.to_string()],
+                    _ => jump_targets(inner),
+                },
+            )
+            .collect(),
+        Value::Array(items) => items.iter().flat_map(jump_targets).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn container_chain(container_id: &str, container_name: &str) -> String {
+    format!(
+        "hs-{}-{}",
+        container_name.replace(['_', '.', '/'], "-"),
+        &container_id[..12.min(container_id.len())]
+    )
+}
+
+fn chain(name: &str) -> Chain<'static> {
+    Chain {
+        family: NfFamily::IP6,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(name.to_string()),
+        newname: None,
+        handle: None,
+        _type: None,
+        hook: None,
+        prio: None,
+        dev: None,
+        policy: None,
+    }
+}
+
+/// The harborshield chain's verdict maps from the targets' addresses to
+/// their chains
+pub fn dispatch_rules(targets: &[Ipv6Target<'_>]) -> Vec<Rule<'static>> {
+    let items: Vec<SetItem<'static>> = targets
+        .iter()
+        .flat_map(|target| {
+            let chain_name = container_chain(target.container_id, target.container_name);
+            target.ips.iter().map(move |ip| {
+                SetItem::Mapping(
+                    Expression::String(Cow::Owned(ip.to_string())),
+                    Expression::Verdict(Verdict::Jump(JumpTarget {
+                        target: Cow::Owned(chain_name.clone()),
+                    })),
+                )
+            })
+        })
+        .collect();
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let map = Expression::Named(NamedExpression::Set(items));
+    [("saddr", "source"), ("daddr", "destination")]
+        .into_iter()
+        .map(|(field, which)| Rule {
+            family: NfFamily::IP6,
+            table: Cow::Borrowed(FILTER_TABLE),
+            chain: Cow::Borrowed(HARBORSHIELD_CHAIN),
+            expr: Cow::Owned(vec![Statement::VerdictMap(VerdictMap {
+                key: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                    PayloadField {
+                        protocol: Cow::Borrowed("ip6"),
+                        field: Cow::Borrowed(field),
+                    },
+                ))),
+                data: map.clone(),
+            })]),
+            handle: None,
+            index: None,
+            comment: Some(Cow::Owned(format!("Container {} IPv6 verdict map", which))),
+        })
+        .collect()
+}
+
+/// Rebuild the `ip6 filter` chains of `targets`, removing those of
+/// containers no longer among them. Returns false, changing nothing, when
+/// Docker has no `ip6 filter` table
+pub async fn rebuild(
+    targets: &[Ipv6Target<'_>],
+    cancellation_token: Option<CancellationToken>,
+) -> Result<bool> {
+    let output = runner::run_nft(
+        "list_ip6_filter_table",
+        &["-j", "list", "table", "ip6", FILTER_TABLE],
+    )
+    .await?;
+    if !output.status.success() {
+        debug!(
+            "No ip6 filter table: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Ok(false);
+    }
+    let listing: Value = serde_json::from_slice(&output.stdout).map_err(crate::Error::Json)?;
+    let table = Ip6Table::parse(&listing);
+    let has_dispatch = table.chains.iter().any(|name| name == HARBORSHIELD_CHAIN);
+    if targets.is_empty() && !has_dispatch {
+        return Ok(true);
+    }
+
+    let mut transaction = NftablesTransaction::builder()
+        .family(NfFamily::IP6)
+        .maybe_cancellation_token(cancellation_token)
+        .build();
+    create_harborshield_chain(&mut transaction.batch, NfFamily::IP6);
+    transaction.flush_chain(FILTER_TABLE, HARBORSHIELD_CHAIN);
+    let forward_chain = crate::runtime::kind().forward_chain();
+    let (forward, input, output) = (
+        table.needs_jump(forward_chain),
+        table.needs_jump(INPUT_CHAIN),
+        table.needs_jump(OUTPUT_CHAIN),
+    );
+    if forward || input || output {
+        create_jump_rules(
+            &mut transaction.batch,
+            NfFamily::IP6,
+            forward,
+            input,
+            output,
+        );
+    }
+
+    let mut current = Vec::new();
+    for target in targets {
+        let chain_name = container_chain(target.container_id, target.container_name);
+        transaction
+            .batch
+            .add(NfListObject::Chain(chain(&chain_name)));
+        transaction.flush_chain(FILTER_TABLE, &chain_name);
+        if target.mode != EnforcementMode::Disabled {
+            let config = if target.mode == EnforcementMode::Quarantined {
+                target.config.quarantined()
+            } else {
+                target.config.clone()
+            }
+            .for_family(NfFamily::IP6, crate::dns::default_policy());
+            NftablesTransaction::add_container_rules_to_transaction(
+                NfFamily::IP6,
+                &mut transaction,
+                target.container_id,
+                target.container_name,
+                &target.ips,
+                &target.ports,
+                &config,
+            )?;
+        }
+        NftablesTransaction::add_container_terminal_rule_to_transaction(
+            NfFamily::IP6,
+            &mut transaction,
+            target.container_id,
+            target.container_name,
+            target.mode,
+        )?;
+        current.push(chain_name);
+    }
+    for rule in dispatch_rules(targets) {
+        transaction.batch.add(NfListObject::Rule(rule));
+    }
+
+    // Nothing dispatches to a stale chain once the harborshield chain is
+    // flushed, so it can go
+    for stale in table
+        .dispatched
+        .iter()
+        .filter(|name| !current.contains(name))
+    {
+        transaction.flush_chain(FILTER_TABLE, stale);
+        transaction.batch.delete(NfListObject::Chain(chain(stale)));
+        crate::nftables::hostname::delete_sets(&mut transaction.batch, NfFamily::IP6, stale);
+        crate::nftables::connlimit::delete_sets(&mut transaction.batch, NfFamily::IP6, stale);
+    }
+
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip6_table() {
+        let listing = serde_json::json!({ "nftables": [
+            { "table": { "family": "ip6", "name": "filter", "handle": 1 } },
+            { "chain": { "family": "ip6", "table": "filter", "name": "DOCKER-USER", "handle": 2 } },
+            { "chain": { "family": "ip6", "table": "filter", "name": "INPUT", "handle": 3 } },
+            { "chain": { "family": "ip6", "table": "filter", "name": "harborshield", "handle": 4 } },
+            { "rule": { "family": "ip6", "table": "filter", "chain": "DOCKER-USER", "handle": 5,
+                "expr": [{ "counter": null }, { "jump": { "target": "harborshield" } }] } },
+            { "rule": { "family": "ip6", "table": "filter", "chain": "harborshield", "handle": 6,
+                "expr": [{ "vmap": { "key": { "payload": { "protocol": "ip6", "field": "saddr" } },
+                    "data": { "set": [["fd00::2", { "jump": { "target": "hs-web-0123456789ab" } }]] } } }] } },
+        ]});
+        let table = Ip6Table::parse(&listing);
+        assert_eq!(table.jumps, vec!["DOCKER-USER"]);
+        assert_eq!(table.dispatched, vec!["hs-web-0123456789ab"]);
+        assert!(!table.needs_jump("DOCKER-USER"));
+        assert!(table.needs_jump("INPUT"));
+        assert!(!table.needs_jump("OUTPUT"));
+
+        let config = Config::builder().build();
+        let target = Ipv6Target {
+            container_id: "0123456789abcdef",
+            container_name: "web",
+            ips: vec!["fd00::2".parse().unwrap()],
+            ports: Vec::new(),
+            config: &config,
+            mode: EnforcementMode::Enforce,
+        };
+        let rules = dispatch_rules(&[target]);
+        assert_eq!(rules.len(), 2);
+        let json = serde_json::to_string(&rules[1]).unwrap();
+        assert!(json.contains(r#""protocol":"ip6","field":"daddr""#));
+        assert!(json.contains("hs-web-0123456789ab"));
+        assert!(dispatch_rules(&[]).is_empty());
+    }
+}
@@ -1,16 +1,44 @@
+pub mod applier;
+pub mod blocked;
+pub mod blocklist;
+pub mod capacity;
 mod common;
+pub mod connlimit;
+pub mod counters;
+pub mod dedicated;
 pub mod docker;
 pub mod error;
+pub mod flowtable;
+pub mod flush;
+pub mod forward;
+pub mod hostname;
+pub mod integrity;
+pub mod ipv6;
+pub mod nflog;
+pub mod panic;
+pub mod plan;
+pub mod raw;
+pub mod rdns;
+pub mod rootless;
+pub mod runner;
+pub mod sinkhole;
+pub mod standby;
+pub mod tproxy;
 pub mod transaction;
 
+pub use harborshield_core::nftables::{FILTER_TABLE, RuleDefaults, chain_name, family_to_string};
+
 use crate::{
     Error, Result,
-    docker::config::{Config, RuleContext, ToNftablesRule},
+    database::EnforcementMode,
+    dns::rdns::{RdnsRegistry, RdnsTarget},
+    docker::config::{Config, RuleContext, ToNftablesRule, nftables_convert::merge_output_rules},
     nftables::{
         docker::{
             check_docker_chains, check_harborshield_chain_exists, check_jump_rules_exist,
             create_harborshield_chain, create_jump_rules,
         },
+        error::NftablesError,
         transaction::NftablesTransaction,
     },
 };
@@ -19,7 +47,6 @@ use common::helpers;
 use nftables::{
     batch::Batch,
     expr::{Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField, SetItem, Verdict},
-    helper::NftablesError,
     schema::{Chain, FlushObject, NfCmd, NfListObject, Rule},
     stmt::{Counter, JumpTarget, Log, LogLevel, Match, Operator, Queue, Statement, VerdictMap},
     types::NfFamily,
@@ -27,15 +54,27 @@ use nftables::{
 use std::borrow::Cow;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
 // Constants for Docker filter table integration
-pub const FILTER_TABLE: &str = "filter";
 pub const DOCKER_USER_CHAIN: &str = "DOCKER-USER";
+pub const FORWARD_CHAIN: &str = "FORWARD";
 pub const INPUT_CHAIN: &str = "INPUT";
 pub const OUTPUT_CHAIN: &str = "OUTPUT";
 pub const HARBORSHIELD_CHAIN: &str = "harborshield";
 
+/// What container rules follow unless they choose their own, as
+/// `--dns-family`, `--dns-require-both`, `--dns-refresh` and
+/// `--nflog-group` set it
+pub fn rule_defaults() -> RuleDefaults {
+    RuleDefaults {
+        dns_policy: crate::dns::default_policy(),
+        dns_refresh: crate::dns::default_refresh(),
+        nflog_group: nflog::group(),
+    }
+}
+
 #[derive(Builder)]
 /// Nftables client that integrates with Docker's filter table
 pub struct NftablesClient {
@@ -43,9 +82,38 @@ pub struct NftablesClient {
     batch: Arc<Mutex<Batch<'static>>>,
     #[builder(default = NfFamily::IP)]
     pub family: NfFamily,
+    /// Kills in-flight `nft` processes when fired (e.g. on shutdown)
+    cancellation_token: Option<CancellationToken>,
+    /// Chains with rdns-gated rules, drained by the rdns verifier
+    #[builder(default)]
+    rdns: Arc<RdnsRegistry>,
+    /// Raw rule lines applied after the batch
+    #[builder(default)]
+    raw_rules: Arc<Mutex<Vec<String>>>,
 }
 
 impl NftablesClient {
+    /// Registry shared with the rdns verifier task
+    pub fn rdns_registry(&self) -> Arc<RdnsRegistry> {
+        self.rdns.clone()
+    }
+
+    fn track_rdns(&self, chain_name: &str, config: &Config) {
+        let external = &config.mapped_ports.external;
+        if external.allow && !external.rdns.is_empty() {
+            self.rdns.register(RdnsTarget {
+                chain: chain_name.to_string(),
+                patterns: external.rdns.clone(),
+                ttl: external.rdns_ttl,
+                negative_ttl: external.rdns_negative_ttl,
+                on_failure: external.rdns_on_failure,
+                lookup: external.rdns_lookup(crate::dns::default_policy()),
+            });
+        } else {
+            self.rdns.unregister(chain_name);
+        }
+    }
+
     /// Clear the harborshield chain (flush rules)
     pub async fn clear_table(&mut self) -> Result<()> {
         info!("Clearing harborshield chain rules");
@@ -105,15 +173,17 @@ impl NftablesClient {
         debug!("Clearing all Harborshield container chains");
 
         // Get a list of all chains in the filter table
-        let list_output = std::process::Command::new("nft")
-            .args(&["list", "table", "ip", FILTER_TABLE, "-j"])
-            .output()
-            .map_err(|e| Error::Nftables {
-                message: format!("Failed to list filter table: {}", e),
-                command: Some("nft list table ip filter -j".to_string()),
-                exit_code: None,
-                stderr: Some(e.to_string()),
-            })?;
+        let list_output = runner::run_nft(
+            "list_filter_table",
+            &["list", "table", "ip", FILTER_TABLE, "-j"],
+        )
+        .await
+        .map_err(|e| Error::Nftables {
+            message: format!("Failed to list filter table: {}", e),
+            command: Some("nft list table ip filter -j".to_string()),
+            exit_code: None,
+            stderr: Some(e.to_string()),
+        })?;
 
         if !list_output.status.success() {
             debug!(
@@ -127,25 +197,28 @@ impl NftablesClient {
 
         // Parse JSON to find all chains starting with "hs-"
         if let Some(json) = serde_json::from_str::<serde_json::Value>(&output_str).ok() {
-            json.get("nftables")
+            let names = json
+                .get("nftables")
                 .and_then(|n| n.as_array())
                 .into_iter()
                 .flatten()
                 .filter_map(|item| item.get("chain"))
                 .filter_map(|chain| chain.get("name").and_then(|n| n.as_str()))
-                .filter(|name| name.starts_with("hs-"))
-                .for_each(|name| {
-                    // Flush and delete the chain
-                    let _ = std::process::Command::new("nft")
-                        .args(&["flush", "chain", "ip", FILTER_TABLE, name])
-                        .output();
-
-                    let _ = std::process::Command::new("nft")
-                        .args(&["delete", "chain", "ip", FILTER_TABLE, name])
-                        .output();
-
-                    debug!("Deleted chain {}", name);
-                });
+                .filter(|name| name.starts_with("hs-"));
+            for name in names {
+                // Flush and delete the chain
+                let _ =
+                    runner::run_nft("flush_chain", &["flush", "chain", "ip", FILTER_TABLE, name])
+                        .await;
+
+                let _ = runner::run_nft(
+                    "delete_chain",
+                    &["delete", "chain", "ip", FILTER_TABLE, name],
+                )
+                .await;
+
+                debug!("Deleted chain {}", name);
+            }
         }
 
         Ok(())
@@ -153,6 +226,10 @@ impl NftablesClient {
 
     /// Initialize base rules in Docker's filter table
     pub async fn init_base_chains(&mut self) -> Result<()> {
+        if crate::docker::rootless::is_rootless() {
+            return self.init_rootless_chain().await;
+        }
+
         // Check what chains exist in the filter table
         let (has_filter, has_docker_user, has_input, has_output) =
             check_docker_chains().await.map_err(|e| Error::Nftables {
@@ -162,6 +239,19 @@ impl NftablesClient {
                 stderr: None,
             })?;
 
+        if !has_filter && crate::runtime::kind() == crate::runtime::RuntimeKind::Podman {
+            return Err(Error::Config {
+                message: "Filter table not found".to_string(),
+                location: "init_base_chains".to_string(),
+                suggestion: Some(
+                    "Netavark's nftables driver keeps its rules in its own table. Set \
+                    firewall_driver = \"iptables\" under [network] in containers.conf, \
+                    with iptables-nft as the iptables backend, and restart the containers"
+                        .to_string(),
+                ),
+            });
+        }
+
         if !has_filter {
             return Err(Error::Config {
                 message: "Docker filter table not found".to_string(),
@@ -181,8 +271,10 @@ impl NftablesClient {
 
         if !has_docker_user {
             warn!(
-                "DOCKER-USER chain not found. Harborshield will create jump rules from INPUT/OUTPUT chains only. \
-                Docker's built-in firewall rules may take precedence over Harborshield rules."
+                "{} chain not found. Harborshield will create jump rules from INPUT/OUTPUT chains only. \
+                {}'s built-in firewall rules may take precedence over Harborshield rules.",
+                crate::runtime::kind().forward_chain(),
+                crate::runtime::kind()
             );
         }
 
@@ -199,9 +291,19 @@ impl NftablesClient {
                     stderr: None,
                 })?;
 
+        blocked::create_set(&mut batch, self.family);
+        blocklist::create_set(&mut batch, self.family);
+        sinkhole::create_set(&mut batch, self.family);
         if !harborshield_exists {
             // Create harborshield chain in filter table
             create_harborshield_chain(&mut batch, self.family);
+            for rule in blocked::drop_rules(self.family)
+                .into_iter()
+                .chain(blocklist::drop_rules(self.family))
+                .chain(sinkhole::drop_rules(self.family))
+            {
+                batch.add(NfListObject::Rule(rule));
+            }
         }
 
         // Check which jump rules already exist
@@ -227,51 +329,35 @@ impl NftablesClient {
 
         // We no longer need to create a named set - we use inline verdict maps
 
+        if flowtable::enabled() {
+            info!(
+                "Offloading flows of rules marked offload on {}",
+                flowtable::devices().join(", ")
+            );
+            flowtable::create(&mut batch, self.family, &flowtable::devices());
+        }
+
         // Apply the batch
         let json =
             serde_json::to_string(&batch.clone().to_nftables()).map_err(|e| Error::Json(e))?;
         drop(batch); // Release the lock
 
         // Execute nft command
-        let mut child = std::process::Command::new("nft")
-            .args(&["-j", "-f", "-"])
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .map_err(|e| Error::Nftables {
-                message: format!("Failed to spawn nft process: {}", e),
-                command: Some("nft -j -f -".to_string()),
-                exit_code: None,
-                stderr: Some(e.to_string()),
-            })?;
-
-        // Write to stdin
-        {
-            use std::io::Write;
-            let mut stdin = child.stdin.take().ok_or_else(|| Error::Nftables {
-                message: "Failed to get stdin handle".to_string(),
-                command: Some("nft -j -f -".to_string()),
-                exit_code: None,
-                stderr: None,
-            })?;
-
-            stdin
-                .write_all(json.as_bytes())
-                .map_err(|e| Error::Nftables {
-                    message: format!("Failed to write to nft stdin: {}", e),
-                    command: Some("nft -j -f -".to_string()),
-                    exit_code: None,
-                    stderr: Some(e.to_string()),
-                })?;
-        } // stdin is dropped here, closing the pipe
-
-        // Wait for output
-        let output = child.wait_with_output().map_err(|e| Error::Nftables {
-            message: format!("Failed to read nft output: {}", e),
+        let output = runner::run_program(
+            runner::NFT_PROGRAM,
+            "init_base_chains",
+            &["-j", "-f", "-"],
+            Some(json),
+            runner::nft_timeout(),
+            None,
+            runner::watchdog(),
+        )
+        .await
+        .map_err(|e| Error::Nftables {
+            message: format!("Failed to run nft: {}", e),
             command: Some("nft -j -f -".to_string()),
             exit_code: None,
-            stderr: Some(e.to_string()),
+            stderr: None,
         })?;
 
         if !output.status.success() {
@@ -310,10 +396,9 @@ impl NftablesClient {
         match helpers::find_chain(FILTER_TABLE, &chain_name) {
             Ok(Some(_existing_chain)) => {
                 debug!(
-                    "Container chain {} already exists, skipping creation",
+                    "Container chain {} already exists, rebuilding its rules",
                     chain_name
                 );
-                return Ok(chain_name);
             }
             Ok(None) => {
                 // Chain doesn't exist, continue with creation
@@ -333,8 +418,9 @@ impl NftablesClient {
         // Add chain to the current batch instead of creating a new one
         let mut batch = self.batch.lock().await;
 
-        // Single container chain (matching Go implementation)
-        batch.add(NfListObject::Chain(Chain {
+        // Single container chain (matching Go implementation), emptied so
+        // the rules that follow replace rather than add to what it holds
+        let chain = Chain {
             family: self.family,
             table: Cow::Borrowed(FILTER_TABLE),
             name: Cow::Owned(chain_name.clone()),
@@ -345,7 +431,9 @@ impl NftablesClient {
             prio: None,
             dev: None,
             policy: None,
-        }));
+        };
+        batch.add(NfListObject::Chain(chain.clone()));
+        batch.add_cmd(NfCmd::Flush(FlushObject::Chain(chain)));
 
         debug!("Added container chain {} to batch", chain_name);
 
@@ -375,6 +463,14 @@ impl NftablesClient {
             )));
         }
 
+        for rule in blocked::drop_rules(self.family)
+            .into_iter()
+            .chain(blocklist::drop_rules(self.family))
+            .chain(sinkhole::drop_rules(self.family))
+        {
+            batch.add(NfListObject::Rule(rule));
+        }
+
         // Build set items for all containers
         let mut set_items = Vec::new();
         for (container_id, container_name, ips) in container_mappings {
@@ -445,45 +541,21 @@ impl NftablesClient {
             serde_json::to_string(&batch.clone().to_nftables()).map_err(|e| Error::Json(e))?;
         drop(batch);
 
-        let mut child = std::process::Command::new("nft")
-            .args(&["-j", "-f", "-"])
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .map_err(|e| Error::Nftables {
-                message: format!("Failed to spawn nft process: {}", e),
-                command: Some("nft -j -f -".to_string()),
-                exit_code: None,
-                stderr: Some(e.to_string()),
-            })?;
-
-        // Write to stdin
-        {
-            use std::io::Write;
-            let mut stdin = child.stdin.take().ok_or_else(|| Error::Nftables {
-                message: "Failed to get stdin handle".to_string(),
-                command: Some("nft -j -f -".to_string()),
-                exit_code: None,
-                stderr: None,
-            })?;
-
-            stdin
-                .write_all(json.as_bytes())
-                .map_err(|e| Error::Nftables {
-                    message: format!("Failed to write to nft stdin: {}", e),
-                    command: Some("nft -j -f -".to_string()),
-                    exit_code: None,
-                    stderr: Some(e.to_string()),
-                })?;
-        } // stdin is dropped here, closing the pipe
-
-        // Wait for output
-        let output = child.wait_with_output().map_err(|e| Error::Nftables {
+        let output = runner::run_program(
+            runner::NFT_PROGRAM,
+            "update_verdict_maps",
+            &["-j", "-f", "-"],
+            Some(json),
+            runner::nft_timeout(),
+            None,
+            runner::watchdog(),
+        )
+        .await
+        .map_err(|e| Error::Nftables {
             message: format!("Failed to update verdict map rules: {}", e),
             command: Some("nft -j -f -".to_string()),
             exit_code: None,
-            stderr: Some(e.to_string()),
+            stderr: None,
         })?;
 
         if !output.status.success() {
@@ -511,6 +583,69 @@ impl NftablesClient {
         Ok(())
     }
 
+    /// Rootless Docker leaves the filter table alone, so there is nothing to
+    /// jump from; only the published-port chain is created
+    async fn init_rootless_chain(&self) -> Result<()> {
+        let mut batch = Batch::new();
+        rootless::create(&mut batch, self.family);
+        let json = serde_json::to_string(&batch.to_nftables()).map_err(Error::Json)?;
+        runner::apply_json(
+            "init_rootless_chain",
+            json,
+            self.cancellation_token.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Replace the rootless chain's port rules with those of `targets`
+    pub async fn rebuild_rootless(&self, targets: &[rootless::PublishedTarget<'_>]) -> Result<()> {
+        let batch = rootless::rebuild(self.family, targets);
+        let json = serde_json::to_string(&batch.to_nftables()).map_err(Error::Json)?;
+        runner::apply_json("rebuild_rootless", json, self.cancellation_token.as_ref()).await?;
+        Ok(())
+    }
+
+    /// Rebuild the `ip6 filter` chains from `targets`; false when Docker
+    /// has no such table
+    pub async fn rebuild_ipv6(&self, targets: &[ipv6::Ipv6Target<'_>]) -> Result<bool> {
+        ipv6::rebuild(targets, self.cancellation_token.clone()).await
+    }
+
+    /// Replace the fastpath chain's `flow add` rules with those of `targets`
+    pub async fn rebuild_fastpath(&self, targets: &[flowtable::OffloadTarget<'_>]) -> Result<()> {
+        let batch = flowtable::rebuild(self.family, targets);
+        let json = serde_json::to_string(&batch.to_nftables()).map_err(Error::Json)?;
+        runner::apply_json("rebuild_fastpath", json, self.cancellation_token.as_ref()).await?;
+        Ok(())
+    }
+
+    /// Replace the `forward` DNAT rules with those of `targets`, deleting
+    /// the nat table when there are none
+    pub async fn rebuild_forwards(&self, targets: &[forward::ForwardTarget<'_>]) -> Result<()> {
+        let batch = if targets.is_empty() {
+            forward::remove()
+        } else {
+            forward::rebuild(targets)
+        };
+        let json = serde_json::to_string(&batch.to_nftables()).map_err(Error::Json)?;
+        runner::apply_json("rebuild_forwards", json, self.cancellation_token.as_ref()).await?;
+        Ok(())
+    }
+
+    /// Replace the TPROXY rules with those of `targets`, deleting the
+    /// tproxy table when there are none
+    pub async fn rebuild_tproxy(&self, targets: &[tproxy::TproxyTarget<'_>]) -> Result<()> {
+        let batch = if targets.is_empty() {
+            tproxy::remove()
+        } else {
+            tproxy::rebuild(targets)
+        };
+        let json = serde_json::to_string(&batch.to_nftables()).map_err(Error::Json)?;
+        runner::apply_json("rebuild_tproxy", json, self.cancellation_token.as_ref()).await?;
+        Ok(())
+    }
+
     /// Delete a container chain
     pub async fn delete_container_chain(
         &mut self,
@@ -528,7 +663,7 @@ impl NftablesClient {
         batch.delete(NfListObject::Chain(Chain {
             family: self.family,
             table: Cow::Borrowed(FILTER_TABLE),
-            name: Cow::Owned(chain_name),
+            name: Cow::Owned(chain_name.clone()),
             newname: None,
             handle: None,
             _type: None,
@@ -538,10 +673,18 @@ impl NftablesClient {
             policy: None,
         }));
 
+        // Sets can only be deleted once no rule references them
+        if self.rdns.unregister(&chain_name).is_some() {
+            rdns::delete_sets(&mut batch, self.family, &chain_name);
+        }
+        hostname::delete_sets(&mut batch, self.family, &chain_name);
+        connlimit::delete_sets(&mut batch, self.family, &chain_name);
+
         Ok(())
     }
 
-    /// Rebuild container chain with config
+    /// Rebuild container chain with config, ending it according to `mode`.
+    /// A disabled container keeps its chain but none of its rules.
     pub async fn rebuild_container_chain(
         &mut self,
         container_id: &str,
@@ -549,6 +692,7 @@ impl NftablesClient {
         container_ips: &[std::net::IpAddr],
         container_ports: &[(u16, String)],
         config: &Config,
+        mode: EnforcementMode,
     ) -> Result<()> {
         let chain_name = format!(
             "hs-{}-{}",
@@ -557,18 +701,20 @@ impl NftablesClient {
         );
 
         // First, flush the chain to remove all existing rules
-        let flush_output = std::process::Command::new("nft")
-            .args(&["flush", "chain", "ip", FILTER_TABLE, &chain_name])
-            .output()
-            .map_err(|e| Error::Nftables {
-                message: format!("Failed to flush container chain: {}", e),
-                command: Some(format!(
-                    "nft flush chain ip {} {}",
-                    FILTER_TABLE, &chain_name
-                )),
-                exit_code: None,
-                stderr: Some(e.to_string()),
-            })?;
+        let flush_output = runner::run_nft(
+            "flush_container_chain",
+            &["flush", "chain", "ip", FILTER_TABLE, &chain_name],
+        )
+        .await
+        .map_err(|e| Error::Nftables {
+            message: format!("Failed to flush container chain: {}", e),
+            command: Some(format!(
+                "nft flush chain ip {} {}",
+                FILTER_TABLE, &chain_name
+            )),
+            exit_code: None,
+            stderr: Some(e.to_string()),
+        })?;
 
         if !flush_output.status.success() {
             debug!(
@@ -578,31 +724,44 @@ impl NftablesClient {
         }
 
         // Create a transaction to add all rules in correct order
-        let mut transaction = NftablesTransaction::builder().family(self.family).build();
-
-        // Add rules from config
-        NftablesTransaction::add_container_rules_to_transaction(
-            self.family,
-            &mut transaction,
-            container_id,
-            container_name,
-            container_ips,
-            container_ports,
-            config,
-        )
-        .map_err(|e| Error::Nftables {
-            message: format!("Failed to add container rules to transaction: {}", e),
-            command: None,
-            exit_code: None,
-            stderr: None,
-        })?;
+        let mut transaction = NftablesTransaction::builder()
+            .family(self.family)
+            .maybe_cancellation_token(self.cancellation_token.clone())
+            .build();
+
+        // Add rules from config; a quarantined container keeps only what
+        // its policy lets through
+        let config = if mode == EnforcementMode::Quarantined {
+            config.quarantined()
+        } else {
+            config.clone()
+        }
+        .for_family(self.family, crate::dns::default_policy());
+        if mode != EnforcementMode::Disabled {
+            NftablesTransaction::add_container_rules_to_transaction(
+                self.family,
+                &mut transaction,
+                container_id,
+                container_name,
+                container_ips,
+                container_ports,
+                &config,
+            )
+            .map_err(|e| Error::Nftables {
+                message: format!("Failed to add container rules to transaction: {}", e),
+                command: None,
+                exit_code: None,
+                stderr: None,
+            })?;
+        }
 
-        // IMPORTANT: Add DROP rule at the end
-        NftablesTransaction::add_container_drop_rule_to_transaction(
+        // IMPORTANT: Add DROP (or its permissive/disabled stand-in) at the end
+        NftablesTransaction::add_container_terminal_rule_to_transaction(
             self.family,
             &mut transaction,
             container_id,
             container_name,
+            mode,
         )
         .map_err(|e| Error::Nftables {
             message: format!("Failed to add container rules to transaction: {}", e),
@@ -618,10 +777,11 @@ impl NftablesClient {
             exit_code: None,
             stderr: None,
         })?;
+        self.track_rdns(&chain_name, &config);
 
         debug!(
-            "Rebuilt container chain {} with all rules in correct order",
-            chain_name
+            "Rebuilt container chain {} in {} mode with all rules in correct order",
+            chain_name, mode
         );
 
         Ok(())
@@ -663,7 +823,11 @@ impl NftablesClient {
 
         drop(batch);
 
-        match nftables::helper::apply_and_return_ruleset(&nftables) {
+        let json = serde_json::to_string(&nftables).map_err(Error::Json)?;
+        let raw_rules = std::mem::take(&mut *self.raw_rules.lock().await);
+        let raw_rules = raw::checked(raw_rules).await;
+
+        match runner::apply_json("apply", json, self.cancellation_token.as_ref()).await {
             Ok(_) => {
                 // Reset the batch after successful application
                 self.reset().await.map_err(|e| Error::Nftables {
@@ -672,7 +836,20 @@ impl NftablesClient {
                     exit_code: None,
                     stderr: None,
                 })?;
-                Ok(())
+                raw::apply(&raw_rules, self.cancellation_token.as_ref())
+                    .await
+                    .map_err(|e| Error::Nftables {
+                        message: format!("Failed to apply raw rules: {}", e),
+                        command: Some("nft -f -".to_string()),
+                        exit_code: None,
+                        stderr: None,
+                    })
+            }
+            Err(NftablesError::Timeout { operation }) => {
+                // The watchdog owns the timed-out batch now and will re-apply
+                // it, so start the next batch from a clean slate
+                self.reset().await?;
+                Err(Error::timeout(runner::nft_timeout(), operation))
             }
             Err(e) => {
                 let error_msg = match &e {
@@ -732,6 +909,7 @@ impl NftablesClient {
             table_name: FILTER_TABLE,
             family: self.family,
         };
+        let config = &config.for_family(self.family, crate::dns::default_policy());
 
         let mut batch = self.batch.lock().await;
 
@@ -846,7 +1024,9 @@ impl NftablesClient {
             }
         }
 
-        if config.mapped_ports.external.allow {
+        connlimit::add_to_batch(&mut batch, &ctx, config);
+
+        if config.mapped_ports.external.allow && !config.mapped_ports.external.rdns_only() {
             // Create a rule for each container port
             for (port, protocol) in container_ports {
                 let mut statements = Vec::new();
@@ -955,6 +1135,8 @@ impl NftablesClient {
                     }
                 }
 
+                statements.extend(config.mapped_ports.external.match_limit());
+
                 // Add counter
                 statements.push(Statement::Counter(Counter::Anonymous(None)));
 
@@ -1002,23 +1184,38 @@ impl NftablesClient {
             }
         }
 
-        // Add output rules
+        rdns::add_to_batch(&mut batch, &ctx, config);
+        self.track_rdns(&chain_name, config);
+
+        // Add output rules, merging identical ones
+        hostname::add_to_batch(&mut batch, &ctx, config);
+        let mut numbered = Vec::new();
         for (i, output_rule) in config.output.iter().enumerate() {
             if !output_rule.skip {
-                let rule = output_rule
-                    .to_nftables_rule(
-                        &ctx,
-                        Some(format!("Output rule {} for {}", i + 1, container_name)),
-                    )
-                    .map_err(|e| Error::Nftables {
-                        message: format!("Failed to add container rules to transaction: {}", e),
-                        command: None,
-                        exit_code: None,
-                        stderr: None,
-                    })?;
-                batch.add(NfListObject::Rule(rule));
+                let mut statements =
+                    output_rule
+                        .to_nftables_statements()
+                        .map_err(|e| Error::Nftables {
+                            message: format!("Failed to add container rules to transaction: {}", e),
+                            command: None,
+                            exit_code: None,
+                            stderr: None,
+                        })?;
+                if output_rule.hostname.is_some() {
+                    statements.insert(1, hostname::daddr_match(&ctx, i + 1));
+                }
+                numbered.push((i + 1, statements));
             }
         }
+        for rule in merge_output_rules(&ctx, numbered) {
+            batch.add(NfListObject::Rule(rule));
+        }
+        self.raw_rules.lock().await.extend(raw::commands(
+            self.family,
+            &chain_name,
+            container_name,
+            &config.raw_rules,
+        ));
 
         debug!(
             "Finished adding rules to batch for container {}. Localhost rules: {}, External rules: {}, Output rules: {}",
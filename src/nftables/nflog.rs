@@ -0,0 +1,21 @@
+//! The NFLOG group container chains send dropped packets to, as
+//! `--nflog-group` sets it. The log statements are built in
+//! [`harborshield_core::nftables::nflog`].
+
+use std::sync::RwLock;
+
+pub use harborshield_core::nftables::nflog::parse_prefix;
+
+static GROUP: RwLock<Option<u16>> = RwLock::new(None);
+
+/// NFLOG group dropped packets are sent to; `None` logs them to the kernel
+/// log
+pub fn set_group(group: Option<u16>) {
+    if let Ok(mut current) = GROUP.write() {
+        *current = group;
+    }
+}
+
+pub fn group() -> Option<u16> {
+    GROUP.read().ok().and_then(|g| *g)
+}
@@ -0,0 +1,226 @@
+//! The kill switch table; see [`crate::database::panic`].
+//!
+//! `inet harborshield-panic` is ours alone and rebuilt as a whole. Its
+//! `forward`, `input` and `output` chains run ahead of the `ip` and `ip6`
+//! filter tables and drop everything from or to the given container
+//! addresses, IPv4 and IPv6 alike, so neither container chains nor Docker's
+//! own rules get a say. Traffic with an allowed network is accepted by these
+//! chains only, and still meets the usual rules after them.
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::runner;
+use ipnet::IpNet;
+use nftables::{
+    batch::Batch,
+    expr::{Expression, NamedExpression, Payload, PayloadField, Prefix, SetItem},
+    schema::{Chain, FlushObject, NfCmd, NfListObject, Rule, Table},
+    stmt::{Counter, Match, Operator, Statement},
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook},
+};
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+pub const PANIC_TABLE: &str = "harborshield-panic";
+
+/// Ahead of the filter table's chains, at priority 0
+const PANIC_PRIORITY: i32 = -10;
+
+pub const CHAINS: [(&str, NfHook); 3] = [
+    ("forward", NfHook::Forward),
+    ("input", NfHook::Input),
+    ("output", NfHook::Output),
+];
+
+fn table() -> Table<'static> {
+    Table {
+        family: NfFamily::INet,
+        name: Cow::Borrowed(PANIC_TABLE),
+        handle: None,
+    }
+}
+
+fn chain(name: &'static str, hook: NfHook) -> Chain<'static> {
+    Chain {
+        family: NfFamily::INet,
+        table: Cow::Borrowed(PANIC_TABLE),
+        name: Cow::Borrowed(name),
+        newname: None,
+        handle: None,
+        _type: Some(NfChainType::Filter),
+        hook: Some(hook),
+        prio: Some(PANIC_PRIORITY),
+        dev: None,
+        policy: Some(NfChainPolicy::Accept),
+    }
+}
+
+fn rule(
+    chain: &'static str,
+    protocol: &'static str,
+    field: &'static str,
+    items: Vec<SetItem<'static>>,
+    verdict: Statement<'static>,
+    comment: &'static str,
+) -> Rule<'static> {
+    Rule {
+        family: NfFamily::INet,
+        table: Cow::Borrowed(PANIC_TABLE),
+        chain: Cow::Borrowed(chain),
+        expr: Cow::Owned(vec![
+            Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                    PayloadField {
+                        protocol: Cow::Borrowed(protocol),
+                        field: Cow::Borrowed(field),
+                    },
+                ))),
+                right: Expression::Named(NamedExpression::Set(items)),
+                op: Operator::EQ,
+            }),
+            Statement::Counter(Counter::Anonymous(None)),
+            verdict,
+        ]),
+        handle: None,
+        index: None,
+        comment: Some(Cow::Borrowed(comment)),
+    }
+}
+
+/// The rules of every chain: the allowed networks, then the containers,
+/// IPv4 and IPv6 each matched by their own protocol
+pub fn panic_rules(containers: &[IpAddr], allow: &[IpNet]) -> Vec<Rule<'static>> {
+    let networks = |v4: bool| {
+        allow
+            .iter()
+            .filter(|net| matches!(net, IpNet::V4(_)) == v4)
+            .map(|net| {
+                SetItem::Element(Expression::Named(NamedExpression::Prefix(Prefix {
+                    addr: Box::new(Expression::String(Cow::Owned(net.network().to_string()))),
+                    len: net.prefix_len() as u32,
+                })))
+            })
+            .collect::<Vec<_>>()
+    };
+    let addresses = |v4: bool| {
+        containers
+            .iter()
+            .filter(|ip| ip.is_ipv4() == v4)
+            .map(|ip| SetItem::Element(Expression::String(Cow::Owned(ip.to_string()))))
+            .collect::<Vec<_>>()
+    };
+
+    let mut rules = Vec::new();
+    for (name, _) in CHAINS {
+        for (protocol, v4) in [("ip", true), ("ip6", false)] {
+            let allowed = networks(v4);
+            if !allowed.is_empty() {
+                for field in ["saddr", "daddr"] {
+                    let accept = Statement::Accept(None);
+                    rules.push(rule(
+                        name,
+                        protocol,
+                        field,
+                        allowed.clone(),
+                        accept,
+                        "Kill switch allowlist",
+                    ));
+                }
+            }
+        }
+        for (protocol, v4) in [("ip", true), ("ip6", false)] {
+            let cut_off = addresses(v4);
+            if !cut_off.is_empty() {
+                for field in ["saddr", "daddr"] {
+                    let drop = Statement::Drop(None);
+                    rules.push(rule(
+                        name,
+                        protocol,
+                        field,
+                        cut_off.clone(),
+                        drop,
+                        "Kill switch DROP",
+                    ));
+                }
+            }
+        }
+    }
+    rules
+}
+
+/// Replace the table's rules with those cutting off `containers`
+pub fn rebuild(containers: &[IpAddr], allow: &[IpNet]) -> Batch<'static> {
+    let mut batch = Batch::new();
+    batch.add(NfListObject::Table(table()));
+    for (name, hook) in CHAINS {
+        let chain = chain(name, hook);
+        batch.add(NfListObject::Chain(chain.clone()));
+        batch.add_cmd(NfCmd::Flush(FlushObject::Chain(chain)));
+    }
+    for rule in panic_rules(containers, allow) {
+        batch.add(NfListObject::Rule(rule));
+    }
+    batch
+}
+
+/// Install the table, or bring the one installed up to date
+pub async fn install(containers: &[IpAddr], allow: &[IpNet]) -> Result<()> {
+    let json = serde_json::to_string(&rebuild(containers, allow).to_nftables())
+        .map_err(NftablesError::invalid_json)?;
+    runner::apply_json("panic_install", json, None).await?;
+    Ok(())
+}
+
+/// Remove the table, if installed
+pub async fn uninstall() -> Result<()> {
+    let mut batch = Batch::new();
+    // Added first so the delete can't fail for a table already gone
+    batch.add(NfListObject::Table(table()));
+    batch.delete(NfListObject::Table(table()));
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("panic_uninstall", json, None).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_rules() {
+        let containers: [IpAddr; 3] = [
+            "172.18.0.2".parse().unwrap(),
+            "172.18.0.3".parse().unwrap(),
+            "fd00::3".parse().unwrap(),
+        ];
+        let rules = panic_rules(&containers, &["10.0.9.0/24".parse().unwrap()]);
+        // The IPv4 allowlist, then IPv4 and IPv6 drops, both directions, in
+        // each chain
+        assert_eq!(rules.len(), 18);
+
+        let json = serde_json::to_value(&rules[0]).unwrap();
+        assert_eq!(json["family"], "inet");
+        assert_eq!(json["chain"], "forward");
+        let expr = json["expr"].as_array().unwrap();
+        assert_eq!(expr[0]["match"]["left"]["payload"]["protocol"], "ip");
+        assert_eq!(expr[0]["match"]["left"]["payload"]["field"], "saddr");
+        assert_eq!(
+            expr[0]["match"]["right"]["set"][0]["prefix"]["addr"],
+            "10.0.9.0"
+        );
+        assert!(expr[2].get("accept").is_some(), "{}", json);
+
+        let json = serde_json::to_value(&rules[3]).unwrap();
+        let expr = json["expr"].as_array().unwrap();
+        assert_eq!(expr[0]["match"]["left"]["payload"]["field"], "daddr");
+        assert_eq!(expr[0]["match"]["right"]["set"][1], "172.18.0.3");
+        assert!(expr[2].get("drop").is_some(), "{}", json);
+
+        let json = serde_json::to_value(&rules[5]).unwrap();
+        let expr = json["expr"].as_array().unwrap();
+        assert_eq!(expr[0]["match"]["left"]["payload"]["protocol"], "ip6");
+        assert_eq!(expr[0]["match"]["right"]["set"][0], "fd00::3");
+        assert!(expr[2].get("drop").is_some(), "{}", json);
+
+        assert!(panic_rules(&[], &[]).is_empty());
+    }
+}
@@ -0,0 +1,794 @@
+//! `--dry-run` and `harborshield plan`: the ruleset current containers
+//! would produce, without applying it.
+//!
+//! Once [`begin`] has taken a snapshot of the live ruleset, every change
+//! that would go to `nft` is applied to a copy of that snapshot instead, and
+//! listings are answered from the copy, so handlers see the objects they
+//! created before. Both rulesets are rendered the same way for
+//! [`finish`]'s diff; counter values and handles are left out, so only
+//! changes to the rules themselves show.
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::runner;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+/// Unchanged lines kept around each change in the diff
+const DIFF_CONTEXT: usize = 3;
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+static STATE: LazyLock<Mutex<Option<Plan>>> = LazyLock::new(|| Mutex::new(None));
+
+/// The live ruleset and the one changes are applied to
+struct Plan {
+    live: Vec<Value>,
+    planned: Vec<Value>,
+    next_handle: u64,
+    unsimulated: Vec<String>,
+}
+
+/// Whether `nft` changes are simulated instead of applied
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Snapshot the live ruleset and simulate every change from now on
+pub async fn begin() -> Result<()> {
+    let output = runner::run_nft("plan_list_ruleset", &["-j", "list", "ruleset"]).await?;
+    if !output.status.success() {
+        return Err(NftablesError::command_failed(
+            runner::NFT_PROGRAM,
+            "list ruleset",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    let listing: Value =
+        serde_json::from_slice(&output.stdout).map_err(NftablesError::invalid_json)?;
+    let live = objects(&listing);
+    let next_handle = live
+        .iter()
+        .filter_map(|object| body(object)?.1.get("handle")?.as_u64())
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    if let Ok(mut state) = STATE.lock() {
+        *state = Some(Plan {
+            planned: live.clone(),
+            live,
+            next_handle,
+            unsimulated: Vec::new(),
+        });
+    }
+    DRY_RUN.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// What the simulated changes add up to
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlanReport {
+    /// Unified diff of the live ruleset against the planned one
+    pub diff: Vec<String>,
+    /// `nft` calls that would have run but couldn't be simulated
+    pub unsimulated: Vec<String>,
+}
+
+impl PlanReport {
+    pub fn has_changes(&self) -> bool {
+        !self.diff.is_empty() || !self.unsimulated.is_empty()
+    }
+}
+
+/// Stop simulating and diff the planned ruleset against the live one
+pub fn finish() -> PlanReport {
+    DRY_RUN.store(false, Ordering::Relaxed);
+    let Some(plan) = STATE.lock().ok().and_then(|mut state| state.take()) else {
+        return PlanReport::default();
+    };
+    let mut diff =
+        crate::output::unified_diff(&render(&plan.live), &render(&plan.planned), DIFF_CONTEXT);
+    if !diff.is_empty() {
+        diff.splice(0..0, ["--- live".to_string(), "+++ planned".to_string()]);
+    }
+    PlanReport {
+        diff,
+        unsimulated: plan.unsimulated,
+    }
+}
+
+/// Answer an `nft` call from the planned ruleset while simulating; `None`
+/// lets it run, as reads the plan can't answer do
+pub(crate) fn intercept(args: &[&str], stdin: Option<&str>) -> Option<Output> {
+    if !is_dry_run() {
+        return None;
+    }
+    let mut state = STATE.lock().ok()?;
+    let plan = state.as_mut()?;
+
+    let words: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    if args.contains(&"-f") {
+        let parsed = stdin.and_then(|json| serde_json::from_str::<Value>(json).ok());
+        let Some(parsed) = parsed else {
+            plan.unsimulated.push(format!("nft {}", args.join(" ")));
+            return Some(exited(0, ""));
+        };
+        let commands = parsed
+            .get("nftables")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        return Some(plan.apply_all(&commands));
+    }
+
+    match words.first().copied() {
+        Some("list") if args.contains(&"-j") => Some(plan.list(&words[1..])),
+        Some("add" | "create" | "insert" | "replace" | "delete" | "destroy" | "flush") => {
+            match command_from_words(&words) {
+                Some(command) => Some(plan.apply_all(&[command])),
+                None => {
+                    plan.unsimulated.push(format!("nft {}", args.join(" ")));
+                    Some(exited(0, ""))
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+fn exited(code: i32, stderr: &str) -> Output {
+    Output {
+        status: ExitStatus::from_raw(code << 8),
+        stdout: Vec::new(),
+        stderr: stderr.as_bytes().to_vec(),
+    }
+}
+
+/// `nft flush chain ip filter x` and the like as the JSON command
+fn command_from_words(words: &[&str]) -> Option<Value> {
+    let (verb, kind, family, table) = (words[0], *words.get(1)?, words.get(2)?, words.get(3)?);
+    let mut object = Map::new();
+    object.insert("family".to_string(), json!(family));
+    match (kind, &words[4..]) {
+        ("table", []) => {
+            object.insert("name".to_string(), json!(table));
+        }
+        ("chain" | "set" | "map" | "flowtable", [name]) => {
+            object.insert("table".to_string(), json!(table));
+            object.insert("name".to_string(), json!(name));
+        }
+        ("rule", [chain, "handle", handle]) => {
+            object.insert("table".to_string(), json!(table));
+            object.insert("chain".to_string(), json!(chain));
+            object.insert("handle".to_string(), json!(handle.parse::<u64>().ok()?));
+        }
+        _ => return None,
+    }
+    Some(keyed(verb, keyed(kind, Value::Object(object))))
+}
+
+/// The objects of an `nft -j list` listing
+fn objects(listing: &Value) -> Vec<Value> {
+    listing
+        .get("nftables")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|item| item.get("metainfo").is_none())
+        .cloned()
+        .collect()
+}
+
+fn keyed(key: &str, value: Value) -> Value {
+    let mut map = Map::new();
+    map.insert(key.to_string(), value);
+    Value::Object(map)
+}
+
+/// An object's kind and body, as in `{"chain": {...}}`
+fn body(object: &Value) -> Option<(&str, &Value)> {
+    let map = object.as_object()?;
+    let (kind, body) = map.iter().next()?;
+    Some((kind.as_str(), body))
+}
+
+fn field<'a>(body: &'a Value, key: &str) -> &'a str {
+    body.get(key).and_then(Value::as_str).unwrap_or("")
+}
+
+/// Whether two objects of the same kind are the same named object
+fn same(a: &Value, b: &Value) -> bool {
+    ["family", "table", "name"]
+        .iter()
+        .all(|key| field(a, key) == field(b, key))
+}
+
+/// Whether `object` lives in the table `table` names
+fn in_table(object: &Value, table: &Value) -> bool {
+    body(object).is_some_and(|(kind, body)| {
+        kind != "table"
+            && field(body, "family") == field(table, "family")
+            && field(body, "table") == field(table, "name")
+    })
+}
+
+fn is_rule_of(object: &Value, chain: &Value) -> bool {
+    body(object).is_some_and(|(kind, body)| {
+        kind == "rule"
+            && ["family", "table"]
+                .iter()
+                .all(|key| field(body, key) == field(chain, key))
+            && field(body, "chain") == field(chain, "name")
+    })
+}
+
+/// A set element's value, without its timeout or expiry
+fn element_key(elem: &Value) -> &Value {
+    elem.get("elem").and_then(|e| e.get("val")).unwrap_or(elem)
+}
+
+fn missing(kind: &str, body: &Value) -> String {
+    format!(
+        "Error: No such file or directory; {} {} {} {} not found",
+        kind,
+        field(body, "family"),
+        field(body, "table"),
+        field(body, "name"),
+    )
+}
+
+impl Plan {
+    fn find(&self, kind: &str, body: &Value) -> Option<usize> {
+        self.planned.iter().position(|object| {
+            object
+                .get(kind)
+                .is_some_and(|existing| same(existing, body))
+        })
+    }
+
+    /// Apply a batch as nft does: all of it, or nothing on the first error
+    fn apply_all(&mut self, commands: &[Value]) -> Output {
+        let (planned, next_handle) = (self.planned.clone(), self.next_handle);
+        for command in commands {
+            if let Err(e) = self.apply(command) {
+                self.planned = planned;
+                self.next_handle = next_handle;
+                return exited(1, &e);
+            }
+        }
+        exited(0, "")
+    }
+
+    fn apply(&mut self, command: &Value) -> std::result::Result<(), String> {
+        let Some((verb, object)) = body(command) else {
+            return Ok(());
+        };
+        let Some((kind, body)) = self::body(object) else {
+            return Ok(());
+        };
+        let entry = keyed(kind, body.clone());
+
+        match (verb, kind) {
+            ("add" | "create", "rule") => {
+                let mut rule = body.clone();
+                rule["handle"] = json!(self.next_handle);
+                self.next_handle += 1;
+                self.planned.push(json!({ "rule": rule }));
+            }
+            ("insert", "rule") => {
+                let mut rule = body.clone();
+                rule["handle"] = json!(self.next_handle);
+                self.next_handle += 1;
+                let chain = json!({
+                    "family": field(body, "family"),
+                    "table": field(body, "table"),
+                    "name": field(body, "chain"),
+                });
+                let at = self
+                    .planned
+                    .iter()
+                    .position(|object| is_rule_of(object, &chain))
+                    .unwrap_or(self.planned.len());
+                self.planned.insert(at, json!({ "rule": rule }));
+            }
+            ("replace", "rule") => {
+                let at = self
+                    .rule_by_handle(body)
+                    .ok_or_else(|| missing(kind, body))?;
+                let mut rule = body.clone();
+                rule["handle"] = self.planned[at]["rule"]["handle"].clone();
+                self.planned[at] = json!({ "rule": rule });
+            }
+            ("delete" | "destroy", "rule") => match self.rule_by_handle(body) {
+                Some(at) => {
+                    self.planned.remove(at);
+                }
+                None if verb == "delete" => return Err(missing(kind, body)),
+                None => {}
+            },
+            ("add" | "create", "element") => {
+                let at = self.find("set", body).ok_or_else(|| missing("set", body))?;
+                let set = &mut self.planned[at]["set"];
+                let mut elems = set
+                    .get("elem")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+                for elem in body
+                    .get("elem")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    if !elems.iter().any(|e| element_key(e) == element_key(elem)) {
+                        elems.push(elem.clone());
+                    }
+                }
+                set["elem"] = Value::Array(elems);
+            }
+            ("delete" | "destroy", "element") => {
+                let at = self.find("set", body).ok_or_else(|| missing("set", body))?;
+                let removed: Vec<&Value> = body
+                    .get("elem")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .map(element_key)
+                    .collect();
+                if let Some(elems) = self.planned[at]["set"]
+                    .get_mut("elem")
+                    .and_then(Value::as_array_mut)
+                {
+                    elems.retain(|e| !removed.contains(&element_key(e)));
+                }
+            }
+            ("add" | "create", _) => match self.find(kind, body) {
+                Some(_) if verb == "create" => {
+                    return Err(format!(
+                        "Error: Could not process rule: File exists; {}",
+                        entry
+                    ));
+                }
+                Some(_) => {}
+                None => self.planned.push(entry),
+            },
+            ("flush", "chain") => {
+                self.find(kind, body).ok_or_else(|| missing(kind, body))?;
+                self.planned.retain(|object| !is_rule_of(object, body));
+            }
+            ("flush", "set" | "map") => {
+                let at = self.find(kind, body).ok_or_else(|| missing(kind, body))?;
+                self.planned[at][kind]
+                    .as_object_mut()
+                    .map(|set| set.remove("elem"));
+            }
+            ("flush", "table") => {
+                self.find(kind, body).ok_or_else(|| missing(kind, body))?;
+                self.planned
+                    .retain(|object| !(in_table(object, body) && object.get("rule").is_some()));
+            }
+            ("flush", "ruleset") => self.planned.clear(),
+            ("delete" | "destroy", _) => match self.find(kind, body) {
+                Some(at) => {
+                    self.planned.remove(at);
+                    match kind {
+                        "table" => self.planned.retain(|object| !in_table(object, body)),
+                        "chain" => self.planned.retain(|object| !is_rule_of(object, body)),
+                        _ => {}
+                    }
+                }
+                None if verb == "delete" => return Err(missing(kind, body)),
+                None => {}
+            },
+            _ => self.unsimulated.push(command.to_string()),
+        }
+        Ok(())
+    }
+
+    fn rule_by_handle(&self, rule: &Value) -> Option<usize> {
+        let handle = rule.get("handle")?.as_u64()?;
+        self.planned.iter().position(|object| {
+            object.get("rule").is_some_and(|existing| {
+                ["family", "table", "chain"]
+                    .iter()
+                    .all(|key| field(existing, key) == field(rule, key))
+                    && existing.get("handle").and_then(Value::as_u64) == Some(handle)
+            })
+        })
+    }
+
+    /// `nft -j list ...` of the planned ruleset
+    fn list(&self, words: &[&str]) -> Output {
+        let found: Option<Vec<Value>> = match words {
+            ["ruleset"] => Some(self.planned.clone()),
+            ["tables" | "chains" | "sets" | "flowtables"] => {
+                let kind = words[0].trim_end_matches('s');
+                Some(
+                    self.planned
+                        .iter()
+                        .filter(|object| object.get(kind).is_some())
+                        .cloned()
+                        .collect(),
+                )
+            }
+            ["table", family, name] => {
+                let table = json!({ "family": family, "name": name });
+                self.find("table", &table).map(|at| {
+                    std::iter::once(self.planned[at].clone())
+                        .chain(
+                            self.planned
+                                .iter()
+                                .filter(|object| in_table(object, &table))
+                                .cloned(),
+                        )
+                        .collect()
+                })
+            }
+            ["chain" | "set" | "map", family, table, name] => {
+                let object = json!({ "family": family, "table": table, "name": name });
+                self.find(words[0], &object).map(|at| {
+                    std::iter::once(self.planned[at].clone())
+                        .chain(
+                            self.planned
+                                .iter()
+                                .filter(|rule| is_rule_of(rule, &object))
+                                .cloned(),
+                        )
+                        .collect()
+                })
+            }
+            _ => None,
+        };
+
+        match found {
+            Some(objects) => {
+                let mut nftables = vec![json!({ "metainfo": { "json_schema_version": 1 } })];
+                nftables.extend(objects);
+                Output {
+                    status: ExitStatus::from_raw(0),
+                    stdout: json!({ "nftables": nftables }).to_string().into_bytes(),
+                    stderr: Vec::new(),
+                }
+            }
+            None => exited(1, "Error: No such file or directory"),
+        }
+    }
+}
+
+/// Lines of a ruleset, grouped by table like `nft list ruleset`
+pub fn render(objects: &[Value]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for table in objects.iter().filter_map(|object| object.get("table")) {
+        lines.push(format!(
+            "table {} {}",
+            field(table, "family"),
+            field(table, "name")
+        ));
+        let members: Vec<&Value> = objects
+            .iter()
+            .filter(|object| in_table(object, table))
+            .collect();
+        for (kind, object) in members.iter().filter_map(|object| body(object)) {
+            if matches!(kind, "set" | "map") {
+                lines.push(format!(
+                    "  {} {} {}",
+                    kind,
+                    field(object, "name"),
+                    set(object)
+                ));
+            } else if kind == "flowtable" {
+                lines.push(format!(
+                    "  flowtable {} {}",
+                    field(object, "name"),
+                    bare(object)
+                ));
+            }
+        }
+        for chain in members.iter().filter_map(|object| object.get("chain")) {
+            let mut line = format!("  chain {}", field(chain, "name"));
+            if let Some(hook) = chain.get("hook").and_then(Value::as_str) {
+                line.push_str(&format!(
+                    " {{ type {} hook {} priority {}; policy {}; }}",
+                    field(chain, "type"),
+                    hook,
+                    chain.get("prio").map(expression).unwrap_or_default(),
+                    chain
+                        .get("policy")
+                        .and_then(Value::as_str)
+                        .unwrap_or("accept"),
+                ));
+            }
+            lines.push(line);
+            for rule in members
+                .iter()
+                .filter(|object| is_rule_of(object, chain))
+                .filter_map(|object| object.get("rule"))
+            {
+                lines.push(format!("    {}", rule_text(rule)));
+            }
+        }
+    }
+    lines
+}
+
+fn set(set: &Value) -> String {
+    let mut parts = vec![format!(
+        "type {}",
+        set.get("type").map(expression).unwrap_or_default()
+    )];
+    if let Some(map) = set.get("map") {
+        parts[0].push_str(&format!(" : {}", expression(map)));
+    }
+    if let Some(flags) = set.get("flags") {
+        parts.push(format!("flags {}", expression(flags)));
+    }
+    if let Some(timeout) = set.get("timeout") {
+        parts.push(format!("timeout {}s", expression(timeout)));
+    }
+    if let Some(elems) = set.get("elem").and_then(Value::as_array) {
+        parts.push(format!("elements = {}", elements(elems)));
+    }
+    format!("{{ {} }}", parts.join("; "))
+}
+
+/// A set's members in a stable order; the kernel lists them as it keeps them
+fn elements(elems: &[Value]) -> String {
+    let mut rendered: Vec<String> = elems
+        .iter()
+        .map(|elem| match element_key(elem) {
+            Value::Array(pair) if pair.len() == 2 => {
+                format!("{} : {}", expression(&pair[0]), expression(&pair[1]))
+            }
+            value => expression(value),
+        })
+        .collect();
+    rendered.sort();
+    format!("{{ {} }}", rendered.join(", "))
+}
+
+fn bare(value: &Value) -> String {
+    let mut value = value.clone();
+    if let Some(map) = value.as_object_mut() {
+        for key in ["family", "table", "name", "handle"] {
+            map.remove(key);
+        }
+    }
+    value.to_string()
+}
+
+fn rule_text(rule: &Value) -> String {
+    let statements: Vec<&Value> = rule
+        .get("expr")
+        .and_then(Value::as_array)
+        .map(|expr| expr.iter().collect())
+        .unwrap_or_default();
+    let mut parts: Vec<String> = statements
+        .iter()
+        .enumerate()
+        .filter(|(i, stmt)| !implied_protocol(stmt, &statements[i + 1..]))
+        .map(|(_, stmt)| statement(stmt))
+        .collect();
+    if let Some(comment) = rule.get("comment").and_then(Value::as_str) {
+        parts.push(format!("comment \"{}\"", comment));
+    }
+    parts.join(" ")
+}
+
+fn protocol_name(value: &Value) -> String {
+    match value.as_u64() {
+        Some(1) => "icmp".to_string(),
+        Some(6) => "tcp".to_string(),
+        Some(17) => "udp".to_string(),
+        Some(58) => "ipv6-icmp".to_string(),
+        _ => expression(value),
+    }
+}
+
+/// A protocol match a later header match already implies, which nft leaves
+/// out of its listings
+fn implied_protocol(stmt: &Value, rest: &[&Value]) -> bool {
+    let Some(m) = stmt.get("match") else {
+        return false;
+    };
+    let left = &m["left"];
+    let is_protocol = left
+        .get("meta")
+        .is_some_and(|meta| meta["key"] == "l4proto")
+        || left
+            .get("payload")
+            .is_some_and(|p| p["field"] == "protocol" || p["field"] == "nexthdr");
+    if !is_protocol || m["op"] != "==" {
+        return false;
+    }
+    let protocol = protocol_name(&m["right"]);
+    rest.iter().any(|later| {
+        later
+            .get("match")
+            .and_then(|m| m["left"].get("payload"))
+            .is_some_and(|p| p["protocol"] == protocol.as_str())
+    })
+}
+
+fn statement(stmt: &Value) -> String {
+    let Some((kind, value)) = body(stmt) else {
+        return stmt.to_string();
+    };
+    match kind {
+        "match" => {
+            let left = &value["left"];
+            let right = if left
+                .get("meta")
+                .is_some_and(|meta| meta["key"] == "l4proto")
+                || left
+                    .get("payload")
+                    .is_some_and(|p| p["field"] == "protocol")
+            {
+                protocol_name(&value["right"])
+            } else {
+                expression(&value["right"])
+            };
+            match value["op"].as_str() {
+                Some("==" | "in") | None => format!("{} {}", expression(left), right),
+                Some(op) => format!("{} {} {}", expression(left), op, right),
+            }
+        }
+        "counter" => "counter".to_string(),
+        "accept" | "drop" | "continue" | "return" => kind.to_string(),
+        "jump" | "goto" => format!("{} {}", kind, field(value, "target")),
+        "log" => {
+            let mut text = "log".to_string();
+            if let Some(prefix) = value.get("prefix").and_then(Value::as_str) {
+                text.push_str(&format!(" prefix \"{}\"", prefix));
+            }
+            if let Some(level) = value.get("level").and_then(Value::as_str) {
+                text.push_str(&format!(" level {}", level));
+            }
+            if let Some(group) = value.get("group") {
+                text.push_str(&format!(" group {}", group));
+            }
+            text
+        }
+        "vmap" => format!(
+            "{} vmap {}",
+            expression(&value["key"]),
+            expression(&value["data"])
+        ),
+        "set" => format!(
+            "{} {} {{ {} }}",
+            field(value, "op"),
+            field(value, "set"),
+            expression(&value["elem"])
+        ),
+        "limit" => format!(
+            "limit rate {}/{}",
+            expression(&value["rate"]),
+            field(value, "per")
+        ),
+        "flow" => format!("flow {} {}", field(value, "op"), field(value, "flowtable")),
+        "masquerade" | "notrack" if value.is_null() => kind.to_string(),
+        _ => stmt.to_string(),
+    }
+}
+
+fn expression(expr: &Value) -> String {
+    match expr {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => String::new(),
+        Value::Array(items) => elements(items),
+        Value::Object(map) => {
+            let Some((kind, value)) = map.iter().next() else {
+                return expr.to_string();
+            };
+            match kind.as_str() {
+                "set" => match value {
+                    Value::Array(items) => elements(items),
+                    other => expression(other),
+                },
+                "payload" if value.get("field").is_some() => {
+                    format!("{} {}", field(value, "protocol"), field(value, "field"))
+                }
+                "meta" => format!("meta {}", field(value, "key")),
+                "ct" => match value.get("dir").and_then(Value::as_str) {
+                    Some(dir) => format!("ct {} {}", dir, field(value, "key")),
+                    None => format!("ct {}", field(value, "key")),
+                },
+                "prefix" => format!("{}/{}", expression(&value["addr"]), value["len"]),
+                "range" => match value.as_array().map(Vec::as_slice) {
+                    Some([low, high]) => format!("{}-{}", expression(low), expression(high)),
+                    _ => expr.to_string(),
+                },
+                "elem" => expression(&value["val"]),
+                "concat" => value
+                    .as_array()
+                    .map(|items| items.iter().map(expression).collect::<Vec<_>>().join(" . "))
+                    .unwrap_or_default(),
+                "jump" | "goto" => format!("{} {}", kind, field(value, "target")),
+                "accept" | "drop" | "continue" | "return" => kind.clone(),
+                _ => expr.to_string(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated_changes() {
+        let live = objects(&json!({ "nftables": [
+            { "metainfo": { "json_schema_version": 1 } },
+            { "table": { "family": "ip", "name": "filter", "handle": 1 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "harborshield", "handle": 2 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-old-0123", "handle": 3 } },
+            { "rule": { "family": "ip", "table": "filter", "chain": "hs-old-0123", "handle": 4,
+                "expr": [{ "counter": { "packets": 10, "bytes": 600 } }, { "drop": null }] } },
+        ]}));
+        let mut plan = Plan {
+            planned: live.clone(),
+            live,
+            next_handle: 5,
+            unsimulated: Vec::new(),
+        };
+
+        let batch = json!([
+            { "add": { "chain": { "family": "ip", "table": "filter", "name": "hs-web-abcd" } } },
+            { "flush": { "chain": { "family": "ip", "table": "filter", "name": "hs-web-abcd" } } },
+            { "add": { "rule": { "family": "ip", "table": "filter", "chain": "hs-web-abcd",
+                "expr": [
+                    { "match": { "op": "==", "left": { "meta": { "key": "l4proto" } }, "right": 6 } },
+                    { "match": { "op": "==", "left": { "payload": { "protocol": "tcp", "field": "dport" } }, "right": 443 } },
+                    { "counter": null },
+                    { "accept": null }
+                ],
+                "comment": "Allow https" } } },
+        ]);
+        assert!(plan.apply_all(batch.as_array().unwrap()).status.success());
+        let flush = command_from_words(&["flush", "chain", "ip", "filter", "hs-old-0123"]).unwrap();
+        let delete =
+            command_from_words(&["delete", "chain", "ip", "filter", "hs-old-0123"]).unwrap();
+        assert!(plan.apply_all(&[flush, delete]).status.success());
+
+        // A batch that fails part way leaves the plan as it was
+        let failing = json!([
+            { "add": { "chain": { "family": "ip", "table": "filter", "name": "hs-x" } } },
+            { "delete": { "chain": { "family": "ip", "table": "filter", "name": "hs-missing" } } },
+        ]);
+        assert!(!plan.apply_all(failing.as_array().unwrap()).status.success());
+        assert!(
+            !plan
+                .list(&["chain", "ip", "filter", "hs-x"])
+                .status
+                .success()
+        );
+
+        let listed = plan.list(&["chain", "ip", "filter", "hs-web-abcd"]);
+        let listed: Value = serde_json::from_slice(&listed.stdout).unwrap();
+        assert_eq!(objects(&listed).len(), 2);
+
+        let diff = crate::output::unified_diff(&render(&plan.live), &render(&plan.planned), 3);
+        assert_eq!(
+            diff,
+            vec![
+                "@@ -1,4 +1,4 @@",
+                " table ip filter",
+                "   chain harborshield",
+                "-  chain hs-old-0123",
+                "-    counter drop",
+                "+  chain hs-web-abcd",
+                "+    tcp dport 443 counter accept comment \"Allow https\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Checking and applying the `raw_rules` scripts built in
+//! [`harborshield_core::nftables::raw`]. The script is checked with
+//! `nft --check` first; rules nft refuses are left out and the rest of the
+//! chain is applied without them.
+
+use crate::nftables::error::Result;
+use crate::nftables::runner;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+pub use harborshield_core::nftables::raw::{commands, comment};
+
+/// Lines creating the table and chains `commands` insert into, so a check
+/// passes before the chain exists
+fn prelude(commands: &[String]) -> Vec<String> {
+    let mut prelude = Vec::new();
+    for command in commands {
+        let words: Vec<&str> = command.split_whitespace().take(5).collect();
+        if let ["insert", "rule", family, table, chain] = words.as_slice() {
+            for line in [
+                format!("add table {} {}", family, table),
+                format!("add chain {} {} {}", family, table, chain),
+            ] {
+                if !prelude.contains(&line) {
+                    prelude.push(line);
+                }
+            }
+        }
+    }
+    prelude
+}
+
+/// Whether nft accepts the commands, without changing the ruleset
+pub async fn check(commands: &[String]) -> Result<()> {
+    let mut script = prelude(commands);
+    script.extend(commands.iter().cloned());
+    runner::apply_script("check_raw_rules", true, script.join("\n") + "\n", None).await?;
+    Ok(())
+}
+
+/// The commands nft accepts: all of them, or none when the check fails
+pub async fn checked(commands: Vec<String>) -> Vec<String> {
+    if commands.is_empty() {
+        return commands;
+    }
+    match check(&commands).await {
+        Ok(()) => commands,
+        Err(e) => {
+            warn!(
+                "Raw rules refused by nft --check, applying the chain without them: {}",
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+pub async fn apply(commands: &[String], cancel: Option<&CancellationToken>) -> Result<()> {
+    if commands.is_empty() {
+        return Ok(());
+    }
+    runner::apply_script("apply_raw_rules", false, commands.join("\n") + "\n", cancel).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nftables::types::NfFamily;
+
+    #[test]
+    fn test_raw_prelude() {
+        let rules = ["meta l4proto gre accept".to_string()];
+        let commands = commands(NfFamily::IP, "hs-web-abc123", "web", &rules);
+        assert_eq!(
+            prelude(&commands),
+            ["add table ip filter", "add chain ip filter hs-web-abc123"]
+        );
+    }
+}
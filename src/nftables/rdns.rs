@@ -0,0 +1,79 @@
+//! Filling and reading the reverse-DNS sets of running containers. The
+//! sets and rules themselves are built in
+//! [`harborshield_core::nftables::rdns`].
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::{FILTER_TABLE, runner};
+use nftables::{
+    batch::Batch,
+    expr::{Elem, Expression, NamedExpression},
+    schema::{Element, NfListObject},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+pub use harborshield_core::nftables::rdns::{
+    PENDING_TIMEOUT, SET_SIZE, add_to_batch, delete_set, delete_sets, parse_set_elements,
+    pending_set_name, rdns_objects, rejected_set_name, verified_set_name,
+};
+
+/// Addresses currently waiting for verification on `chain`
+pub async fn list_pending(chain: &str) -> Result<Vec<IpAddr>> {
+    let name = pending_set_name(chain);
+    let output = runner::run_nft(
+        "rdns_list_pending",
+        &["-j", "list", "set", "ip", FILTER_TABLE, &name],
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err(NftablesError::command_failed(
+            runner::NFT_PROGRAM,
+            format!("list set {}", name),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).map_err(NftablesError::invalid_json)?;
+    Ok(parse_set_elements(&json))
+}
+
+/// Add verified sources to the chain's verified set
+pub async fn add_verified(chain: &str, ips: &[IpAddr], ttl: u32) -> Result<()> {
+    add_elements("rdns_add_verified", verified_set_name(chain), ips, ttl).await
+}
+
+/// Add sources that failed verification to the chain's rejected set
+pub async fn add_rejected(chain: &str, ips: &[IpAddr], ttl: u32) -> Result<()> {
+    add_elements("rdns_add_rejected", rejected_set_name(chain), ips, ttl).await
+}
+
+async fn add_elements(operation: &str, set: String, ips: &[IpAddr], ttl: u32) -> Result<()> {
+    let elements: Vec<Expression<'static>> = ips
+        .iter()
+        .map(|ip| {
+            Expression::Named(NamedExpression::Elem(Elem {
+                val: Box::new(Expression::String(Cow::Owned(ip.to_string()))),
+                timeout: Some(ttl),
+                expires: None,
+                comment: None,
+                counter: None,
+            }))
+        })
+        .collect();
+
+    let mut batch = Batch::new();
+    batch.add(NfListObject::Element(Element {
+        family: NfFamily::IP,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Owned(set),
+        elem: Cow::Owned(elements),
+    }));
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json(operation, json, None).await?;
+    Ok(())
+}
@@ -0,0 +1,221 @@
+//! Published-port rules for rootless Docker; see
+//! [`crate::docker::rootless`].
+//!
+//! RootlessKit accepts forwarded connections on the host, so they pass the
+//! input hook with the published port as their destination. The
+//! `harborshield-rootless` base chain hooks input after other filter chains
+//! and only drops: sources `mapped_ports.external` doesn't allow on other
+//! interfaces, and loopback clients unless `mapped_ports.localhost` allows
+//! them.
+
+use crate::docker::config::{AddrOrRange, Config, ToNftablesRule};
+use crate::nftables::FILTER_TABLE;
+use nftables::{
+    batch::Batch,
+    expr::{Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField},
+    schema::{Chain, FlushObject, NfCmd, NfListObject, Rule, Table},
+    stmt::{Counter, Match, Operator, Statement},
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook},
+};
+use std::borrow::Cow;
+
+pub const ROOTLESS_CHAIN: &str = "harborshield-rootless";
+
+/// After the host's own input filtering (priority 0)
+const ROOTLESS_PRIORITY: i32 = 10;
+
+/// A container's published host ports and the rules guarding them
+pub struct PublishedTarget<'a> {
+    pub container_name: &'a str,
+    pub ports: Vec<(u16, &'a str)>,
+    pub config: &'a Config,
+}
+
+fn rootless_chain(family: NfFamily) -> Chain<'static> {
+    Chain {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(ROOTLESS_CHAIN),
+        newname: None,
+        handle: None,
+        _type: Some(NfChainType::Filter),
+        hook: Some(NfHook::Input),
+        prio: Some(ROOTLESS_PRIORITY),
+        dev: None,
+        policy: Some(NfChainPolicy::Accept),
+    }
+}
+
+/// Add the filter table, which rootless Docker never creates, and the
+/// input chain
+pub fn create(batch: &mut Batch<'static>, family: NfFamily) {
+    batch.add(NfListObject::Table(Table {
+        family,
+        name: Cow::Borrowed(FILTER_TABLE),
+        handle: None,
+    }));
+    batch.add(NfListObject::Chain(rootless_chain(family)));
+}
+
+fn loopback(op: Operator) -> Statement<'static> {
+    Statement::Match(Match {
+        left: Expression::Named(NamedExpression::Meta(Meta {
+            key: MetaKey::Iifname,
+        })),
+        right: Expression::String(Cow::Borrowed("lo")),
+        op,
+    })
+}
+
+fn destination(protocol: &str, port: u16) -> Statement<'static> {
+    Statement::Match(Match {
+        left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+            PayloadField {
+                protocol: Cow::Owned(protocol.to_string()),
+                field: Cow::Borrowed("dport"),
+            },
+        ))),
+        right: Expression::Number(port as u32),
+        op: Operator::EQ,
+    })
+}
+
+fn is_ipv4(ip: &AddrOrRange) -> bool {
+    match ip {
+        AddrOrRange::Addr(addr) => addr.is_ipv4(),
+        AddrOrRange::Range(start, _) => start.is_ipv4(),
+        AddrOrRange::Net(net) => net.addr().is_ipv4(),
+    }
+}
+
+/// What to match external sources on before dropping: `None` keeps the
+/// port open to everyone, an empty list drops every source
+fn external_sources(config: &Config) -> Option<Vec<Statement<'static>>> {
+    let external = &config.mapped_ports.external;
+    if !external.allow {
+        return Some(Vec::new());
+    }
+    if external.ips.is_empty() {
+        return None;
+    }
+    // This is an ip table; IPv6-only allowances leave no IPv4 source allowed
+    if !external.ips.iter().any(is_ipv4) {
+        return Some(Vec::new());
+    }
+    let source = external
+        .to_nftables_statements()
+        .ok()?
+        .into_iter()
+        .find_map(|statement| match statement {
+            Statement::Match(mut source) => {
+                source.op = Operator::NEQ;
+                Some(Statement::Match(source))
+            }
+            _ => None,
+        })?;
+    Some(vec![source])
+}
+
+fn drop_rule(
+    family: NfFamily,
+    mut expr: Vec<Statement<'static>>,
+    comment: String,
+) -> Rule<'static> {
+    expr.push(Statement::Counter(Counter::Anonymous(None)));
+    expr.push(Statement::Drop(None));
+    Rule {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        chain: Cow::Borrowed(ROOTLESS_CHAIN),
+        expr: Cow::Owned(expr),
+        handle: None,
+        index: None,
+        comment: Some(Cow::Owned(comment)),
+    }
+}
+
+pub fn port_rules(family: NfFamily, target: &PublishedTarget<'_>) -> Vec<Rule<'static>> {
+    let external = external_sources(target.config);
+    let localhost = target.config.mapped_ports.localhost.allow;
+
+    let mut rules = Vec::new();
+    for (port, protocol) in &target.ports {
+        if !matches!(*protocol, "tcp" | "udp") {
+            continue;
+        }
+        if let Some(sources) = &external {
+            let mut expr = vec![loopback(Operator::NEQ), destination(protocol, *port)];
+            expr.extend(sources.iter().cloned());
+            rules.push(drop_rule(
+                family,
+                expr,
+                format!(
+                    "External access to {}/{} of {}",
+                    port, protocol, target.container_name
+                ),
+            ));
+        }
+        if !localhost {
+            rules.push(drop_rule(
+                family,
+                vec![loopback(Operator::EQ), destination(protocol, *port)],
+                format!(
+                    "Localhost access to {}/{} of {}",
+                    port, protocol, target.container_name
+                ),
+            ));
+        }
+    }
+    rules
+}
+
+/// Replace the rootless chain's rules with those of `targets`
+pub fn rebuild(family: NfFamily, targets: &[PublishedTarget<'_>]) -> Batch<'static> {
+    let mut batch = Batch::new();
+    create(&mut batch, family);
+    batch.add_cmd(NfCmd::Flush(FlushObject::Chain(rootless_chain(family))));
+    for target in targets {
+        for rule in port_rules(family, target) {
+            batch.add(NfListObject::Rule(rule));
+        }
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_rules() {
+        let config: Config = serde_yaml::from_str(
+            "mapped_ports:\n  external:\n    allow: true\n    ips: [192.168.1.0/24]\n  localhost:\n    allow: true",
+        )
+        .unwrap();
+        let target = PublishedTarget {
+            container_name: "web",
+            ports: vec![(8080, "tcp"), (5353, "sctp")],
+            config: &config,
+        };
+        let rules = port_rules(NfFamily::IP, &target);
+        assert_eq!(rules.len(), 1);
+        let json = serde_json::to_string(&rules[0]).unwrap();
+        assert!(json.contains(r#"{"meta":{"key":"iifname"}},"right":"lo","op":"!="}"#));
+        assert!(json.contains(r#""len":24}},"op":"!="}"#));
+        assert!(json.contains("192.168.1.0"));
+        assert!(json.contains(r#"{"drop":null}"#));
+
+        // Blocked externally and from localhost by default
+        let closed = Config::builder().build();
+        let target = PublishedTarget {
+            config: &closed,
+            ..target
+        };
+        let rules = port_rules(NfFamily::IP, &target);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            rules[1].comment.as_deref(),
+            Some("Localhost access to 8080/tcp of web")
+        );
+    }
+}
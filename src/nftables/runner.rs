@@ -0,0 +1,736 @@
+//! Bounded execution of the `nft` binary.
+//!
+//! Every call into the firewall backend goes through this module so that a
+//! hung `nft` process can never stall the event loop: invocations run on the
+//! async process API, are killed once they exceed the configured timeout (or
+//! when their cancellation token fires), and timed-out transactions are parked
+//! in the watchdog's retry queue instead of being silently lost.
+//!
+//! A parked transaction is tagged with the chains, sets and tables it
+//! rebuilds, by flushing or deleting them, and the generation it started in.
+//! Once a later transaction rebuilding the same ones succeeds, the parked
+//! one is stale and dropped rather than replayed over it. Transactions that
+//! only add, such as verified rdns addresses or raw rules, have no target
+//! and are always replayed.
+
+use crate::nftables::error::{NftablesError, Result};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::process::{Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Program invoked for all backend calls
+pub const NFT_PROGRAM: &str = "nft";
+
+/// Default upper bound for a single `nft` invocation
+pub const DEFAULT_NFT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times a timed-out transaction is re-applied before it is dropped
+pub const MAX_REQUEUE_ATTEMPTS: u32 = 5;
+
+static NFT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_NFT_TIMEOUT.as_millis() as u64);
+
+static WATCHDOG: LazyLock<NftWatchdog> = LazyLock::new(NftWatchdog::default);
+
+/// Bumped as each `nft` call that may change the ruleset starts and ends
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// Set the timeout applied to every subsequent `nft` invocation
+pub fn set_nft_timeout(timeout: Duration) {
+    NFT_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Currently configured timeout for `nft` invocations
+pub fn nft_timeout() -> Duration {
+    Duration::from_millis(NFT_TIMEOUT_MS.load(Ordering::Relaxed))
+}
+
+/// Process-wide watchdog tracking in-flight and stuck invocations
+pub fn watchdog() -> &'static NftWatchdog {
+    &WATCHDOG
+}
+
+/// Changes to the ruleset made by this process so far: unchanged across a
+/// span means the ruleset was only changed by others during it
+pub fn write_generation() -> u64 {
+    WRITES.load(Ordering::SeqCst)
+}
+
+/// Run `nft` with the given arguments (e.g. `["-j", "list", "tables"]`)
+pub async fn run_nft(operation: &str, args: &[&str]) -> Result<Output> {
+    run_program(
+        NFT_PROGRAM,
+        operation,
+        args,
+        None,
+        nft_timeout(),
+        None,
+        watchdog(),
+    )
+    .await
+}
+
+/// Apply a JSON ruleset via `nft -j -f -`.
+///
+/// Non-zero exit statuses are returned as [`NftablesError::NftFailed`]. On
+/// timeout the payload is queued on the watchdog so it can be re-applied once
+/// the backend recovers.
+pub async fn apply_json(
+    operation: &str,
+    json: String,
+    cancel: Option<&CancellationToken>,
+) -> Result<Output> {
+    let output = run_program(
+        NFT_PROGRAM,
+        operation,
+        &["-j", "-f", "-"],
+        Some(json),
+        nft_timeout(),
+        cancel,
+        watchdog(),
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err(NftablesError::command_failed(
+            NFT_PROGRAM,
+            format!("{} exited with {}", operation, output.status),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    Ok(output)
+}
+
+/// Apply a script in nft's own syntax via `nft -f -`, or only check it with
+/// `nft -c -f -`. Failures are returned as with [`apply_json`]
+pub async fn apply_script(
+    operation: &str,
+    check: bool,
+    script: String,
+    cancel: Option<&CancellationToken>,
+) -> Result<Output> {
+    let args: &[&str] = if check {
+        &["-c", "-f", "-"]
+    } else {
+        &["-f", "-"]
+    };
+    let output = run_program(
+        NFT_PROGRAM,
+        operation,
+        args,
+        Some(script),
+        nft_timeout(),
+        cancel,
+        watchdog(),
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err(NftablesError::command_failed(
+            NFT_PROGRAM,
+            format!("{} exited with {}", operation, output.status),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    Ok(output)
+}
+
+/// Spawn `program`, optionally feeding `stdin`, and wait for it to finish
+/// within `timeout`. The child is killed if the deadline passes or `cancel`
+/// fires first. With an applier configured, `nft` runs there instead and
+/// the same deadline applies to its reply. With `--nft-thread`, the call is
+/// made from the dedicated thread.
+pub(crate) async fn run_program(
+    program: &str,
+    operation: &str,
+    args: &[&str],
+    stdin: Option<String>,
+    timeout: Duration,
+    cancel: Option<&CancellationToken>,
+    watchdog: &NftWatchdog,
+) -> Result<Output> {
+    if program == NFT_PROGRAM
+        && let Some(output) = super::plan::intercept(args, stdin.as_deref())
+    {
+        return Ok(output);
+    }
+    if program == NFT_PROGRAM
+        && let Some(output) = super::standby::intercept(operation, args)
+    {
+        return Ok(output);
+    }
+
+    let forward_to = if program == NFT_PROGRAM {
+        super::applier::socket()
+    } else {
+        None
+    };
+    let writes = program == NFT_PROGRAM && !args.contains(&"list");
+    if writes {
+        WRITES.fetch_add(1, Ordering::SeqCst);
+    }
+    let guard = watchdog.begin(operation);
+    let generation = watchdog.next_generation();
+    let payload = stdin.clone();
+    // Kept to drop what this transaction supersedes, including anything
+    // that times out while it runs
+    let stdin_copy = if writes { stdin.clone() } else { None };
+
+    let call = {
+        let program = program.to_string();
+        let operation = operation.to_string();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        async move {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            match forward_to {
+                Some(socket) => super::applier::forward(&socket, &operation, &args, stdin).await,
+                None => execute(&program, &args, stdin).await,
+            }
+        }
+    };
+    let run = async {
+        super::dedicated::run(call).await.unwrap_or_else(|e| {
+            Err(NftablesError::execution(
+                program,
+                std::io::Error::other(format!("the nft thread stopped: {}", e)),
+            ))
+        })
+    };
+
+    let cancelled = async {
+        match cancel {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    // The child (or applier connection) is owned by `run`; dropping that
+    // future kills the process
+    let result = tokio::select! {
+        result = tokio::time::timeout(timeout, run) => match result {
+            Ok(output) => output,
+            Err(_) => {
+                warn!(
+                    "nft operation '{}' exceeded {:?}, killing process",
+                    operation, timeout
+                );
+                watchdog.record_timeout(operation, payload, generation);
+                Err(NftablesError::timeout(format!(
+                    "{} exceeded {:?}",
+                    operation, timeout
+                )))
+            }
+        },
+        _ = cancelled => {
+            debug!("nft operation '{}' cancelled", operation);
+            Err(NftablesError::timeout(format!("{} cancelled", operation)))
+        }
+    };
+
+    drop(guard);
+    if writes {
+        WRITES.fetch_add(1, Ordering::SeqCst);
+        // Retries report their own generation once they get through
+        if let (Ok(output), Some(payload)) = (&result, &stdin_copy)
+            && output.status.success()
+            && !operation.starts_with(RETRY_PREFIX)
+        {
+            watchdog.supersede(payload, generation);
+        }
+    }
+    result
+}
+
+/// Operation names of re-applied stuck transactions start with this
+const RETRY_PREFIX: &str = "retry ";
+
+/// What a transaction rebuilds: every chain, set and table it flushes or
+/// deletes, or None when it only adds
+pub fn target(payload: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(payload).ok()?;
+    let mut objects: Vec<String> = json
+        .get("nftables")?
+        .as_array()?
+        .iter()
+        .filter_map(|item| item.get("flush").or_else(|| item.get("delete")))
+        .filter_map(|body| {
+            let (kind, object) = body.as_object()?.iter().next()?;
+            let field = |key| object.get(key).and_then(serde_json::Value::as_str);
+            match kind.as_str() {
+                "table" => Some(format!("table {} {}", field("family")?, field("name")?)),
+                "chain" | "set" | "map" => Some(format!(
+                    "{} {} {} {}",
+                    kind,
+                    field("family")?,
+                    field("table")?,
+                    field("name")?
+                )),
+                _ => None,
+            }
+        })
+        .collect();
+    objects.sort();
+    objects.dedup();
+    (!objects.is_empty()).then(|| objects.join(", "))
+}
+
+async fn execute(program: &str, args: &[&str], stdin: Option<String>) -> Result<Output> {
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| NftablesError::execution(program, e))?;
+
+    if let Some(input) = stdin {
+        let mut pipe = child.stdin.take().ok_or_else(|| {
+            NftablesError::execution(program, std::io::Error::other("stdin not captured"))
+        })?;
+        pipe.write_all(input.as_bytes())
+            .await
+            .map_err(|e| NftablesError::execution(program, e))?;
+        // Dropping the handle closes the pipe so nft starts processing
+    }
+    child
+        .wait_with_output()
+        .await
+        .map_err(|e| NftablesError::execution(program, e))
+}
+
+#[derive(Debug)]
+struct InFlight {
+    operation: String,
+    started: Instant,
+}
+
+/// A transaction that timed out and is waiting to be re-applied
+#[derive(Debug, Clone)]
+pub struct StuckTransaction {
+    pub operation: String,
+    pub payload: String,
+    /// What the payload rebuilds, see [`target`]
+    pub target: Option<String>,
+    /// When the transaction first started, against the watchdog's count
+    pub generation: u64,
+    pub timed_out_at: chrono::DateTime<chrono::Utc>,
+    pub attempts: u32,
+}
+
+/// Snapshot of the watchdog state for the status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchdogStatus {
+    pub timeout_ms: u64,
+    pub timeouts_total: u64,
+    pub in_flight: Vec<InFlightStatus>,
+    pub stuck: Vec<StuckStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InFlightStatus {
+    pub operation: String,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StuckStatus {
+    pub operation: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub timed_out_at: String,
+    pub attempts: u32,
+}
+
+/// Tracks running `nft` invocations and queues timed-out transactions
+#[derive(Debug, Default)]
+pub struct NftWatchdog {
+    next_id: AtomicU64,
+    generations: AtomicU64,
+    timeouts_total: AtomicU64,
+    in_flight: Mutex<HashMap<u64, InFlight>>,
+    stuck: Mutex<VecDeque<StuckTransaction>>,
+}
+
+/// Removes an invocation from the in-flight table when dropped
+pub(crate) struct InFlightGuard<'a> {
+    watchdog: &'a NftWatchdog,
+    id: u64,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.watchdog.in_flight.lock() {
+            in_flight.remove(&self.id);
+        }
+    }
+}
+
+impl NftWatchdog {
+    fn begin(&self, operation: &str) -> InFlightGuard<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.insert(
+                id,
+                InFlight {
+                    operation: operation.to_string(),
+                    started: Instant::now(),
+                },
+            );
+        }
+        InFlightGuard { watchdog: self, id }
+    }
+
+    fn next_generation(&self) -> u64 {
+        self.generations.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn record_timeout(&self, operation: &str, payload: Option<String>, generation: u64) {
+        self.timeouts_total.fetch_add(1, Ordering::Relaxed);
+        crate::server::increment_nft_timeouts(operation);
+
+        if let Some(payload) = payload {
+            self.requeue(StuckTransaction {
+                operation: operation.to_string(),
+                target: target(&payload),
+                payload,
+                generation,
+                timed_out_at: chrono::Utc::now(),
+                attempts: 0,
+            });
+        }
+    }
+
+    /// Drop the stuck transactions that `payload`, applied in `generation`,
+    /// rebuilt over
+    fn supersede(&self, payload: &str, generation: u64) {
+        if self.stuck_count() == 0 {
+            return;
+        }
+        let Some(target) = target(payload) else {
+            return;
+        };
+        let Ok(mut stuck) = self.stuck.lock() else {
+            return;
+        };
+        stuck.retain(|transaction| {
+            let stale =
+                transaction.target.as_ref() == Some(&target) && transaction.generation < generation;
+            if stale {
+                info!(
+                    "Dropping stuck nft transaction '{}': {} was rebuilt since",
+                    transaction.operation, target
+                );
+            }
+            !stale
+        });
+        crate::server::set_nft_stuck_transactions(stuck.len() as u64);
+    }
+
+    fn requeue(&self, transaction: StuckTransaction) {
+        if let Ok(mut stuck) = self.stuck.lock() {
+            stuck.push_back(transaction);
+            crate::server::set_nft_stuck_transactions(stuck.len() as u64);
+        }
+    }
+
+    fn pop_stuck(&self) -> Option<StuckTransaction> {
+        let mut stuck = self.stuck.lock().ok()?;
+        let transaction = stuck.pop_front();
+        crate::server::set_nft_stuck_transactions(stuck.len() as u64);
+        transaction
+    }
+
+    /// Number of transactions waiting to be re-applied
+    pub fn stuck_count(&self) -> usize {
+        self.stuck.lock().map(|s| s.len()).unwrap_or(0)
+    }
+
+    /// Invocations that have been running for longer than `threshold`
+    pub fn overdue(&self, threshold: Duration) -> Vec<InFlightStatus> {
+        self.in_flight
+            .lock()
+            .map(|in_flight| {
+                in_flight
+                    .values()
+                    .filter(|op| op.started.elapsed() > threshold)
+                    .map(|op| InFlightStatus {
+                        operation: op.operation.clone(),
+                        elapsed_ms: op.started.elapsed().as_millis() as u64,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn status(&self) -> WatchdogStatus {
+        let in_flight = self.overdue(Duration::ZERO);
+        let stuck = self
+            .stuck
+            .lock()
+            .map(|stuck| {
+                stuck
+                    .iter()
+                    .map(|t| StuckStatus {
+                        operation: t.operation.clone(),
+                        target: t.target.clone(),
+                        timed_out_at: t.timed_out_at.to_rfc3339(),
+                        attempts: t.attempts,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        WatchdogStatus {
+            timeout_ms: nft_timeout().as_millis() as u64,
+            timeouts_total: self.timeouts_total.load(Ordering::Relaxed),
+            in_flight,
+            stuck,
+        }
+    }
+
+    /// Re-apply every queued transaction once. Transactions that time out
+    /// again are re-queued by `apply_json`; those that fail outright or have
+    /// exhausted their attempts are dropped, and those that get through
+    /// supersede older ones for the same target.
+    pub async fn retry_stuck(&self) -> usize {
+        let mut recovered = 0;
+        let pending = self.stuck_count();
+
+        for _ in 0..pending {
+            let Some(transaction) = self.pop_stuck() else {
+                break;
+            };
+
+            let operation = format!("{}{}", RETRY_PREFIX, transaction.operation);
+            // Raw rule scripts are queued next to JSON transactions
+            let args: &[&str] = if transaction.payload.trim_start().starts_with('{') {
+                &["-j", "-f", "-"]
+            } else {
+                &["-f", "-"]
+            };
+            match run_program(
+                NFT_PROGRAM,
+                &operation,
+                args,
+                Some(transaction.payload.clone()),
+                nft_timeout(),
+                None,
+                self,
+            )
+            .await
+            {
+                Ok(output) if output.status.success() => {
+                    info!(
+                        "Re-applied stuck nft transaction '{}'",
+                        transaction.operation
+                    );
+                    self.supersede(&transaction.payload, transaction.generation);
+                    recovered += 1;
+                }
+                Ok(output) => {
+                    error!(
+                        "Dropping stuck nft transaction '{}': {}",
+                        transaction.operation,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+                Err(NftablesError::Timeout { .. }) => {
+                    // run_program queued a fresh copy; carry the attempt count over
+                    if let Some(mut requeued) = self.take_latest(&operation) {
+                        requeued.operation = transaction.operation.clone();
+                        requeued.generation = transaction.generation;
+                        requeued.attempts = transaction.attempts + 1;
+                        if requeued.attempts >= MAX_REQUEUE_ATTEMPTS {
+                            error!(
+                                "Giving up on nft transaction '{}' after {} attempts",
+                                requeued.operation, requeued.attempts
+                            );
+                        } else {
+                            self.requeue(requeued);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Dropping stuck nft transaction '{}': {}",
+                        transaction.operation, e
+                    );
+                }
+            }
+        }
+
+        recovered
+    }
+
+    fn take_latest(&self, operation: &str) -> Option<StuckTransaction> {
+        let mut stuck = self.stuck.lock().ok()?;
+        let index = stuck.iter().rposition(|t| t.operation == operation)?;
+        stuck.remove(index)
+    }
+}
+
+/// Periodically report overdue invocations and re-apply stuck transactions
+/// until `cancel` fires.
+pub async fn run_watchdog(interval: Duration, cancel: CancellationToken) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = ticker.tick() => {
+                let dog = watchdog();
+                for op in dog.overdue(nft_timeout()) {
+                    warn!(
+                        "nft operation '{}' has been running for {}ms",
+                        op.operation, op.elapsed_ms
+                    );
+                }
+
+                if dog.stuck_count() > 0 {
+                    let recovered = dog.retry_stuck().await;
+                    debug!("Watchdog re-applied {} stuck nft transactions", recovered);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_program_success() {
+        let dog = NftWatchdog::default();
+        let output = run_program(
+            "cat",
+            "echo",
+            &[],
+            Some("ruleset".to_string()),
+            Duration::from_secs(5),
+            None,
+            &dog,
+        )
+        .await
+        .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"ruleset");
+        assert!(dog.status().in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_program_timeout_requeues_payload() {
+        let dog = NftWatchdog::default();
+        let started = Instant::now();
+        let err = run_program(
+            "sleep",
+            "hang",
+            &["5"],
+            Some(String::new()),
+            Duration::from_millis(100),
+            None,
+            &dog,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, NftablesError::Timeout { .. }));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let status = dog.status();
+        assert_eq!(status.timeouts_total, 1);
+        assert!(status.in_flight.is_empty());
+        assert_eq!(status.stuck.len(), 1);
+        assert_eq!(status.stuck[0].operation, "hang");
+    }
+
+    #[tokio::test]
+    async fn test_run_program_cancelled() {
+        let dog = NftWatchdog::default();
+        let token = CancellationToken::new();
+        let child_token = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            child_token.cancel();
+        });
+
+        let err = run_program(
+            "sleep",
+            "cancel",
+            &["5"],
+            None,
+            Duration::from_secs(5),
+            Some(&token),
+            &dog,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, NftablesError::Timeout { .. }));
+        // Cancelled work is abandoned, not retried
+        assert_eq!(dog.stuck_count(), 0);
+    }
+
+    #[test]
+    fn test_nft_timeout_roundtrip() {
+        let original = nft_timeout();
+        set_nft_timeout(Duration::from_millis(1500));
+        assert_eq!(nft_timeout(), Duration::from_millis(1500));
+        set_nft_timeout(original);
+    }
+
+    #[test]
+    fn test_supersede_stale_transactions() {
+        let rebuild = |chain: &str| {
+            serde_json::json!({ "nftables": [
+                { "add": { "chain": { "family": "ip", "table": "filter", "name": chain } } },
+                { "flush": { "chain": { "family": "ip", "table": "filter", "name": chain } } },
+                { "add": { "rule": { "family": "ip", "table": "filter", "chain": chain,
+                    "expr": [{ "accept": null }] } } },
+            ]})
+            .to_string()
+        };
+        let added = serde_json::json!({ "nftables": [
+            { "add": { "element": { "family": "ip", "table": "filter", "name": "hs-web-rdns-ok",
+                "elem": ["192.0.2.1"] } } },
+        ]})
+        .to_string();
+        assert_eq!(
+            target(&rebuild("hs-web")).as_deref(),
+            Some("chain ip filter hs-web")
+        );
+        assert_eq!(target(&added), None);
+        assert_eq!(target("insert rule ip filter hs-web accept"), None);
+
+        let dog = NftWatchdog::default();
+        let web = dog.next_generation();
+        dog.record_timeout("apply", Some(rebuild("hs-web")), web);
+        dog.record_timeout("apply", Some(rebuild("hs-db")), dog.next_generation());
+        dog.record_timeout("rdns_add_verified", Some(added), dog.next_generation());
+
+        // An older rebuild getting through supersedes nothing newer
+        dog.supersede(&rebuild("hs-web"), web - 1);
+        assert_eq!(dog.stuck_count(), 3);
+
+        // Rebuilding web's chain since leaves only db's and the addition
+        dog.supersede(&rebuild("hs-web"), dog.next_generation());
+        let left: Vec<Option<String>> = dog.status().stuck.into_iter().map(|s| s.target).collect();
+        assert_eq!(left, [Some("chain ip filter hs-db".to_string()), None]);
+    }
+}
@@ -0,0 +1,158 @@
+//! The set of sinkholed addresses, resolved from `--sinkhole` lists by
+//! [`crate::dns::sinkhole`].
+//!
+//! The harborshield chain drops forwarded traffic to any of its members
+//! before dispatching to the container chains. The host's own traffic, whose
+//! source is a local address, isn't matched. The last addresses are kept so
+//! a recreated set is filled again straight away.
+
+use crate::nftables::error::{NftablesError, Result};
+use crate::nftables::{FILTER_TABLE, HARBORSHIELD_CHAIN, runner};
+use nftables::{
+    batch::Batch,
+    expr::{Expression, Fib, FibFlag, FibResult, NamedExpression, Payload, PayloadField},
+    schema::{Element, FlushObject, NfCmd, NfListObject, Rule, Set, SetType, SetTypeValue},
+    stmt::{Counter, Match, Operator, Statement},
+    types::NfFamily,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const SINKHOLE_SET: &str = "harborshield-sinkhole";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ADDRESSES: Mutex<Vec<Ipv4Addr>> = Mutex::new(Vec::new());
+
+/// Install the set and its rule, when `--sinkhole` is given
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn sinkhole_set(family: NfFamily) -> Set<'static> {
+    Set {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(SINKHOLE_SET),
+        handle: None,
+        set_type: SetTypeValue::Single(SetType::Ipv4Addr),
+        policy: None,
+        flags: None,
+        elem: None,
+        timeout: None,
+        gc_interval: None,
+        size: None,
+        comment: None,
+    }
+}
+
+fn fill(batch: &mut Batch<'static>, family: NfFamily, addrs: &[Ipv4Addr]) {
+    if addrs.is_empty() {
+        return;
+    }
+    batch.add(NfListObject::Element(Element {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        name: Cow::Borrowed(SINKHOLE_SET),
+        elem: Cow::Owned(
+            addrs
+                .iter()
+                .map(|addr| Expression::String(Cow::Owned(addr.to_string())))
+                .collect(),
+        ),
+    }));
+}
+
+/// Add the set with the last sinkholed addresses, if enabled
+pub fn create_set(batch: &mut Batch<'static>, family: NfFamily) {
+    if !enabled() {
+        return;
+    }
+    batch.add(NfListObject::Set(Box::new(sinkhole_set(family))));
+    let addrs = ADDRESSES
+        .lock()
+        .map(|addrs| addrs.clone())
+        .unwrap_or_default();
+    fill(batch, family, &addrs);
+}
+
+/// The rule dropping forwarded traffic to sinkholed addresses, to head the
+/// harborshield chain, if enabled
+pub fn drop_rules(family: NfFamily) -> Vec<Rule<'static>> {
+    if !enabled() {
+        return Vec::new();
+    }
+    vec![drop_rule(family)]
+}
+
+fn drop_rule(family: NfFamily) -> Rule<'static> {
+    Rule {
+        family,
+        table: Cow::Borrowed(FILTER_TABLE),
+        chain: Cow::Borrowed(HARBORSHIELD_CHAIN),
+        expr: Cow::Owned(vec![
+            Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Fib(Fib {
+                    result: FibResult::Type,
+                    flags: HashSet::from([FibFlag::Saddr]),
+                })),
+                right: Expression::String(Cow::Borrowed("local")),
+                op: Operator::NEQ,
+            }),
+            Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                    PayloadField {
+                        protocol: Cow::Borrowed("ip"),
+                        field: Cow::Borrowed("daddr"),
+                    },
+                ))),
+                right: Expression::String(Cow::Owned(format!("@{}", SINKHOLE_SET))),
+                op: Operator::EQ,
+            }),
+            Statement::Counter(Counter::Anonymous(None)),
+            Statement::Drop(None),
+        ]),
+        handle: None,
+        index: None,
+        comment: Some(Cow::Borrowed("Drop sinkholed daddr")),
+    }
+}
+
+/// Swap the set's addresses for `addrs`, in one batch
+pub async fn replace_elements(addrs: &[Ipv4Addr]) -> Result<()> {
+    let mut batch = Batch::new();
+    // Recreated in case the table was flushed since startup
+    batch.add(NfListObject::Set(Box::new(sinkhole_set(NfFamily::IP))));
+    batch.add_cmd(NfCmd::Flush(FlushObject::Set(Box::new(sinkhole_set(
+        NfFamily::IP,
+    )))));
+    fill(&mut batch, NfFamily::IP, addrs);
+
+    let json = serde_json::to_string(&batch.to_nftables()).map_err(NftablesError::invalid_json)?;
+    runner::apply_json("sinkhole_replace_elements", json, None).await?;
+    if let Ok(mut current) = ADDRESSES.lock() {
+        *current = addrs.to_vec();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_rule() {
+        let json = serde_json::to_value(drop_rule(NfFamily::IP)).unwrap();
+        let expr = json["expr"].as_array().unwrap();
+        assert_eq!(expr[0]["match"]["op"], "!=");
+        assert_eq!(expr[0]["match"]["left"]["fib"]["flags"][0], "saddr");
+        assert_eq!(expr[1]["match"]["right"], "@harborshield-sinkhole");
+        assert!(expr[3].get("drop").is_some(), "{}", json);
+    }
+}
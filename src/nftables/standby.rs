@@ -0,0 +1,54 @@
+//! Withholding ruleset changes while another host enforces; see
+//! [`crate::handlers::leader`].
+//!
+//! A standby keeps handling events and storing what its containers need, so
+//! it can take over with a full sync. Until then, `nft` calls that would
+//! change the ruleset report success without running; listings and checks
+//! still run.
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::debug;
+
+static STANDBY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_standby(standby: bool) {
+    STANDBY.store(standby, Ordering::SeqCst);
+}
+
+/// Whether ruleset changes are withheld
+pub fn is_standby() -> bool {
+    STANDBY.load(Ordering::SeqCst)
+}
+
+/// Whether `nft` with these arguments changes the ruleset
+fn writes(args: &[&str]) -> bool {
+    !args.contains(&"list") && !args.contains(&"-c") && !args.contains(&"--check")
+}
+
+/// The made-up success of a withheld change, if this call is one
+pub(crate) fn intercept(operation: &str, args: &[&str]) -> Option<Output> {
+    if !is_standby() || !writes(args) {
+        return None;
+    }
+    debug!("Standby, not applying nft operation '{}'", operation);
+    Some(Output {
+        status: ExitStatus::from_raw(0),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standby_writes() {
+        assert!(writes(&["-j", "-f", "-"]));
+        assert!(writes(&["flush", "chain", "ip", "filter", "hs-web-abc"]));
+        assert!(!writes(&["-j", "list", "ruleset"]));
+        assert!(!writes(&["-c", "-f", "-"]));
+    }
+}
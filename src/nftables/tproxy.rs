@@ -0,0 +1,293 @@
+//! Transparent interception of output rules marked `tproxy`.
+//!
+//! TPROXY only works before routing, so it can't live in the container
+//! chains. Everything is in the `ip harborshield-tproxy` table, which is ours
+//! alone and rebuilt as a whole like the nat table of forwards. Its
+//! `prerouting` chain hooks in at mangle priority and, for each marked rule,
+//! sets the rule's mark on the flows its container opens and hands them to
+//! the local port.
+//!
+//! The kernel only delivers those packets locally when the mark is routed
+//! to the host, which harborshield leaves to the operator:
+//!
+//! ```text
+//! ip rule add fwmark 1 lookup 100
+//! ip route add local 0.0.0.0/0 dev lo table 100
+//! ```
+//!
+//! `harborshield status` lists the interceptions in place with these routes.
+//! Intercepted flows then reach the container's chain through INPUT, where
+//! the rule still has to accept them. Only IPv4 is intercepted.
+
+use crate::docker::config::{RuleConfig, ToNftablesRule};
+use crate::nftables::error::Result;
+use crate::nftables::runner;
+use nftables::{
+    batch::Batch,
+    expr::{Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField, SetItem},
+    schema::{Chain, FlushObject, NfCmd, NfListObject, Rule, Table},
+    stmt::{Counter, Mangle, Match, Operator, Statement, TProxy},
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::net::Ipv4Addr;
+
+pub const TPROXY_TABLE: &str = "harborshield-tproxy";
+
+pub(crate) const PREROUTING_CHAIN: &str = "prerouting";
+
+/// nft's `mangle` priority, ahead of the routing decision
+const MANGLE_PRIORITY: i32 = -150;
+
+/// Routing table the printed routes send marked packets to
+pub const ROUTE_TABLE: u32 = 100;
+
+/// A container's intercepted output rules, numbered from 1
+pub struct TproxyTarget<'a> {
+    pub container_name: &'a str,
+    pub ips: Vec<Ipv4Addr>,
+    pub rules: Vec<(usize, &'a RuleConfig)>,
+}
+
+fn chain() -> Chain<'static> {
+    Chain {
+        family: NfFamily::IP,
+        table: Cow::Borrowed(TPROXY_TABLE),
+        name: Cow::Borrowed(PREROUTING_CHAIN),
+        newname: None,
+        handle: None,
+        _type: Some(NfChainType::Filter),
+        hook: Some(NfHook::Prerouting),
+        prio: Some(MANGLE_PRIORITY),
+        dev: None,
+        policy: Some(NfChainPolicy::Accept),
+    }
+}
+
+fn table() -> Table<'static> {
+    Table {
+        family: NfFamily::IP,
+        name: Cow::Borrowed(TPROXY_TABLE),
+        handle: None,
+    }
+}
+
+/// The interception rules of `target`: the source is the container, the
+/// rest of the match is the rule's own. A rule that cannot be rendered is
+/// left out, as it is from the container chain
+pub fn tproxy_rules(target: &TproxyTarget<'_>) -> Vec<Rule<'static>> {
+    if target.ips.is_empty() {
+        return Vec::new();
+    }
+    let source = Statement::Match(Match {
+        left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+            PayloadField {
+                protocol: Cow::Borrowed("ip"),
+                field: Cow::Borrowed("saddr"),
+            },
+        ))),
+        right: Expression::Named(NamedExpression::Set(
+            target
+                .ips
+                .iter()
+                .map(|ip| SetItem::Element(Expression::String(Cow::Owned(ip.to_string()))))
+                .collect(),
+        )),
+        op: Operator::EQ,
+    });
+
+    let mut rules = Vec::new();
+    for (number, rule) in &target.rules {
+        let Some(tproxy) = rule.tproxy else {
+            continue;
+        };
+        let Ok(statements) = rule.to_nftables_statements() else {
+            continue;
+        };
+        let mut expr = vec![source.clone()];
+        // Logging and the verdict stay in the container chain
+        expr.extend(
+            statements
+                .into_iter()
+                .filter(|statement| matches!(statement, Statement::Match(_))),
+        );
+        expr.extend([
+            Statement::Counter(Counter::Anonymous(None)),
+            Statement::Mangle(Mangle {
+                key: Expression::Named(NamedExpression::Meta(Meta { key: MetaKey::Mark })),
+                value: Expression::Number(tproxy.mark),
+            }),
+            Statement::TProxy(TProxy {
+                family: None,
+                port: tproxy.port,
+                addr: None,
+            }),
+            Statement::Accept(None),
+        ]);
+        rules.push(Rule {
+            family: NfFamily::IP,
+            table: Cow::Borrowed(TPROXY_TABLE),
+            chain: Cow::Borrowed(PREROUTING_CHAIN),
+            expr: Cow::Owned(expr),
+            handle: None,
+            index: None,
+            comment: Some(Cow::Owned(format!(
+                "Intercept output rule {} for {}",
+                number, target.container_name
+            ))),
+        });
+    }
+    rules
+}
+
+/// Replace the table's rules with those of `targets`
+pub fn rebuild(targets: &[TproxyTarget<'_>]) -> Batch<'static> {
+    let mut batch = Batch::new();
+    batch.add(NfListObject::Table(table()));
+    batch.add(NfListObject::Chain(chain()));
+    batch.add_cmd(NfCmd::Flush(FlushObject::Chain(chain())));
+    for target in targets {
+        for rule in tproxy_rules(target) {
+            batch.add(NfListObject::Rule(rule));
+        }
+    }
+    batch
+}
+
+/// Delete the table, once no container intercepts anything
+pub fn remove() -> Batch<'static> {
+    let mut batch = Batch::new();
+    // Adding first makes the delete succeed when the table is already gone
+    batch.add(NfListObject::Table(table()));
+    batch.delete(NfListObject::Table(table()));
+    batch
+}
+
+/// An interception in place, as `harborshield status` shows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Interception {
+    pub container_name: String,
+    /// What the rule was built from, such as `output rule 2`
+    pub rule: String,
+    pub port: u16,
+    pub mark: u32,
+}
+
+impl Interception {
+    /// The policy routing the kernel needs to deliver the marked packets
+    pub fn routes(&self) -> [String; 2] {
+        [
+            format!("ip rule add fwmark {} lookup {}", self.mark, ROUTE_TABLE),
+            format!("ip route add local 0.0.0.0/0 dev lo table {}", ROUTE_TABLE),
+        ]
+    }
+}
+
+/// The interceptions in `nft -j list table ip harborshield-tproxy` output
+pub fn parse_interceptions(listing: &Value) -> Vec<Interception> {
+    let rules = listing
+        .get("nftables")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("rule"));
+
+    let mut interceptions = Vec::new();
+    for rule in rules {
+        let Some((origin, container)) = rule
+            .get("comment")
+            .and_then(Value::as_str)
+            .and_then(|comment| comment.strip_prefix("Intercept "))
+            .and_then(|comment| comment.rsplit_once(" for "))
+        else {
+            continue;
+        };
+        let expr = rule.get("expr").and_then(Value::as_array);
+        let statement = |key: &str| {
+            expr.into_iter()
+                .flatten()
+                .find_map(|statement| statement.get(key))
+        };
+        let port = statement("tproxy")
+            .and_then(|tproxy| tproxy.get("port")?.as_u64())
+            .and_then(|port| u16::try_from(port).ok());
+        let mark = statement("mangle")
+            .and_then(|mangle| mangle.get("value")?.as_u64())
+            .and_then(|mark| u32::try_from(mark).ok());
+        if let (Some(port), Some(mark)) = (port, mark) {
+            interceptions.push(Interception {
+                container_name: container.to_string(),
+                rule: origin.to_string(),
+                port,
+                mark,
+            });
+        }
+    }
+    interceptions
+}
+
+/// The interceptions installed now; none when the table doesn't exist
+pub async fn list_interceptions() -> Result<Vec<Interception>> {
+    let output =
+        runner::run_nft("list_tproxy", &["-j", "list", "table", "ip", TPROXY_TABLE]).await?;
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+    let listing: Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    Ok(parse_interceptions(&listing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tproxy_rules() {
+        let rule: RuleConfig = serde_yaml::from_str(
+            "ips: [10.0.0.0/8]\nproto: tcp\ndst_ports: [443]\ntproxy: {port: 8443, mark: 1}",
+        )
+        .unwrap();
+        let target = TproxyTarget {
+            container_name: "web",
+            ips: vec!["172.17.0.2".parse().unwrap()],
+            rules: vec![(3, &rule)],
+        };
+        let rules = tproxy_rules(&target);
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            rules[0].comment.as_deref(),
+            Some("Intercept output rule 3 for web")
+        );
+        let json = serde_json::to_string(&rules[0]).unwrap();
+        assert!(json.contains(r#"{"tproxy":{"port":8443}}"#), "{}", json);
+        assert!(json.contains(r#""value":1"#));
+        assert!(json.contains("172.17.0.2"));
+
+        // What the table lists comes back as the interception
+        let listing = serde_json::json!({
+            "nftables": [{"rule": serde_json::to_value(&rules[0]).unwrap()}]
+        });
+        let interceptions = parse_interceptions(&listing);
+        assert_eq!(
+            interceptions,
+            vec![Interception {
+                container_name: "web".to_string(),
+                rule: "output rule 3".to_string(),
+                port: 8443,
+                mark: 1,
+            }]
+        );
+        assert_eq!(
+            interceptions[0].routes()[0],
+            "ip rule add fwmark 1 lookup 100"
+        );
+
+        let unaddressed = TproxyTarget {
+            ips: Vec::new(),
+            ..target
+        };
+        assert!(tproxy_rules(&unaddressed).is_empty());
+    }
+}
@@ -1,19 +1,21 @@
 use crate::Result;
-use crate::docker::config::{Config, RuleContext, ToNftablesRule};
-use crate::nftables::FILTER_TABLE;
-use crate::nftables::common::helpers::family_to_string;
+use crate::database::EnforcementMode;
+use crate::docker::config::{Config, RuleContext};
+use crate::nftables::{FILTER_TABLE, connlimit, family_to_string, hostname, nflog, rule_defaults};
+use crate::nftables::{error::NftablesError, raw, runner};
 use bon::Builder;
 use bon::builder;
+use harborshield_core::nftables::{chain_name, container_chain, terminal_rule};
 use nftables::schema::{FlushObject, NfCmd};
 use nftables::{
     batch::Batch,
-    helper::{NftablesError, get_current_ruleset_raw},
+    helper::get_current_ruleset_raw,
     schema::{Chain, NfListObject, Rule},
-    stmt::{Counter, Log, LogLevel, Statement},
     types::NfFamily,
 };
 use serde_json;
 use std::borrow::Cow;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
 #[derive(Builder)]
@@ -25,6 +27,11 @@ pub struct NftablesTransaction {
     pub family: NfFamily,
     #[builder(default = Vec::new())]
     pub deferred_drop_rules: Vec<Rule<'static>>,
+    /// `nft -f -` lines inserting raw rules, applied after the batch
+    #[builder(default = Vec::new())]
+    pub raw_rules: Vec<String>,
+    /// Kills the `nft` process if fired while the commit is in flight
+    pub cancellation_token: Option<CancellationToken>,
 }
 
 impl NftablesTransaction {
@@ -67,15 +74,18 @@ impl NftablesTransaction {
             }
         };
 
-        match get_current_ruleset_raw::<String, String, _>(None, &args) {
-            Ok(ruleset) => {
-                debug!(
-                    "Ruleset dump for item being deleted - {:#?}:\n{:#?}",
-                    obj, ruleset
-                );
-            }
-            Err(e) => {
-                debug!("Failed to get ruleset dump before deletion: {:#?}", e);
+        // The dump is a blocking nft call, so only pay for it when it will be logged
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            match get_current_ruleset_raw::<String, String, _>(None, &args) {
+                Ok(ruleset) => {
+                    debug!(
+                        "Ruleset dump for item being deleted - {:#?}:\n{:#?}",
+                        obj, ruleset
+                    );
+                }
+                Err(e) => {
+                    debug!("Failed to get ruleset dump before deletion: {:#?}", e);
+                }
             }
         }
 
@@ -118,12 +128,23 @@ impl NftablesTransaction {
             Err(e) => tracing::error!("Failed to serialize nftables object: {:#?}", e),
         }
 
-        // Use apply_and_return_ruleset for better error details
-        match nftables::helper::apply_and_return_ruleset(&nftables_obj) {
-            Ok(_ruleset) => {
+        let json = serde_json::to_string(&nftables_obj).map_err(crate::Error::Json)?;
+        let raw_rules = raw::checked(self.raw_rules).await;
+
+        match runner::apply_json("commit", json, self.cancellation_token.as_ref()).await {
+            Ok(_) => {
                 // Log success
                 tracing::debug!("Successfully applied nftables transaction");
-                Ok(())
+                raw::apply(&raw_rules, self.cancellation_token.as_ref())
+                    .await
+                    .map_err(|e| crate::Error::Config {
+                        message: format!("Failed to apply raw rules: {}", e),
+                        location: "raw_rules".to_string(),
+                        suggestion: Some("Check the rules with nft --check".to_string()),
+                    })
+            }
+            Err(NftablesError::Timeout { operation }) => {
+                Err(crate::Error::timeout(runner::nft_timeout(), operation))
             }
             Err(e) => {
                 // Get more detailed error information
@@ -171,25 +192,11 @@ impl NftablesTransaction {
         container_id: &str,
         container_name: &str,
     ) -> Result<()> {
-        let chain_name = format!(
-            "hs-{}-{}",
-            container_name.replace(['_', '.', '/'], "-"),
-            &container_id[..12.min(container_id.len())]
-        );
-
         // Delete container chain
-        self.delete(NfListObject::Chain(Chain {
-            family: self.family,
-            table: Cow::Borrowed(FILTER_TABLE),
-            name: Cow::Owned(chain_name),
-            newname: None,
-            handle: None,
-            _type: None,
-            hook: None,
-            prio: None,
-            dev: None,
-            policy: None,
-        }));
+        self.delete(NfListObject::Chain(container_chain(
+            self.family,
+            chain_name(container_id, container_name),
+        )));
 
         // Note: vmap rules will be removed when we re-create them with updated IPs
 
@@ -203,25 +210,11 @@ impl NftablesTransaction {
         container_id: &str,
         container_name: &str,
     ) -> Result<String> {
-        let chain_name = format!(
-            "hs-{}-{}",
-            container_name.replace(['_', '.', '/'], "-"),
-            &container_id[..12.min(container_id.len())]
-        );
-
-        transaction.batch.add(NfListObject::Chain(Chain {
-            family: family,
-            table: Cow::Borrowed(FILTER_TABLE),
-            name: Cow::Owned(chain_name.clone()),
-            newname: None,
-            handle: None,
-            _type: None,
-            hook: None,
-            prio: None,
-            dev: None,
-            policy: None,
-        }));
-
+        let chain_name = chain_name(container_id, container_name);
+        transaction.batch.add(NfListObject::Chain(container_chain(
+            family,
+            chain_name.clone(),
+        )));
         Ok(chain_name)
     }
 
@@ -232,37 +225,31 @@ impl NftablesTransaction {
         container_id: &str,
         container_name: &str,
     ) -> Result<()> {
-        let chain_name = format!(
-            "hs-{}-{}",
-            container_name.replace(['_', '.', '/'], "-"),
-            &container_id[..12.min(container_id.len())]
-        );
-
-        let drop_rule = Rule {
-            family: family,
-            table: Cow::Borrowed(FILTER_TABLE),
-            chain: Cow::Owned(chain_name.clone()),
-            expr: Cow::Owned(vec![
-                Statement::Counter(Counter::Anonymous(None)),
-                Statement::Log(Some(Log {
-                    prefix: Some(Cow::Owned(format!("{} DROP: ", chain_name))),
-                    level: Some(LogLevel::Info),
-                    flags: None,
-                    group: None,
-                    queue_threshold: None,
-                    snaplen: None,
-                })),
-                Statement::Drop(None),
-            ]),
-            handle: None,
-            index: None,
-            comment: Some(Cow::Owned(format!(
-                "Default DROP for container {}",
-                container_name
-            ))),
-        };
+        Self::add_container_terminal_rule_to_transaction(
+            family,
+            transaction,
+            container_id,
+            container_name,
+            EnforcementMode::Enforce,
+        )
+    }
 
-        transaction.deferred_drop_rules.push(drop_rule);
+    /// Add the rule that ends a container chain: DROP when enforcing, a
+    /// logged ACCEPT when permissive and a plain ACCEPT when disabled
+    pub fn add_container_terminal_rule_to_transaction(
+        family: NfFamily,
+        transaction: &mut NftablesTransaction,
+        container_id: &str,
+        container_name: &str,
+        mode: EnforcementMode,
+    ) -> Result<()> {
+        transaction.deferred_drop_rules.push(terminal_rule(
+            family,
+            container_id,
+            container_name,
+            mode,
+            nflog::group(),
+        ));
 
         Ok(())
     }
@@ -277,12 +264,7 @@ impl NftablesTransaction {
         container_ports: &[(u16, String)],
         config: &Config,
     ) -> Result<()> {
-        let chain_name = format!(
-            "hs-{}-{}",
-            container_name.replace(['_', '.', '/'], "-"),
-            &container_id[..12.min(container_id.len())]
-        );
-
+        let chain_name = chain_name(container_id, container_name);
         let ctx = RuleContext {
             container_id,
             container_name,
@@ -293,290 +275,15 @@ impl NftablesTransaction {
             family: family,
         };
 
-        // Add mapped port rules - create individual rules for each container port
-        if config.mapped_ports.localhost.allow || config.mapped_ports.external.allow {
-            tracing::debug!(
-                "Creating mapped port rules for container {}: ports={:?}",
-                container_name,
-                container_ports
-            );
-
-            // Group ports by protocol
-            let mut tcp_ports = Vec::new();
-            let mut udp_ports = Vec::new();
-
-            for (port, protocol) in container_ports {
-                match protocol.as_str() {
-                    "tcp" => tcp_ports.push(*port),
-                    "udp" => udp_ports.push(*port),
-                    _ => {} // Ignore other protocols for now
-                }
-            }
-
-            // Create localhost rules for each port
-            if config.mapped_ports.localhost.allow {
-                tracing::debug!("Creating localhost rules for TCP ports: {:?}", tcp_ports);
-                for port in &tcp_ports {
-                    let mut statements = Vec::new();
-
-                    // Match source IP as localhost
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_src_ip(
-                            "127.0.0.1",
-                        ),
-                    );
-
-                    // Match protocol and destination port
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_protocol(
-                            "tcp",
-                        ),
-                    );
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_dst_port(
-                            "tcp", *port,
-                        ),
-                    );
-
-                    // Add counter
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::counter_statement(),
-                    );
-
-                    // Add log if configured
-                    if !config.mapped_ports.localhost.log_prefix.is_empty() {
-                        statements.push(
-                            <crate::docker::config::LocalRules as ToNftablesRule>::log_statement(
-                                Some(&config.mapped_ports.localhost.log_prefix),
-                            ),
-                        );
-                    }
-
-                    // Add verdict
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::verdict_to_statement(
-                            &config.mapped_ports.localhost.verdict,
-                        ),
-                    );
-
-                    let rule = Rule {
-                        family: ctx.family,
-                        table: Cow::Owned(ctx.table_name.to_string()),
-                        chain: Cow::Owned(ctx.chain_name.to_string()),
-                        expr: Cow::Owned(statements),
-                        handle: None,
-                        index: None,
-                        comment: Some(Cow::Owned(format!(
-                            "Localhost access to port {} for {}",
-                            port, container_name
-                        ))),
-                    };
-
-                    transaction.batch.add(NfListObject::Rule(rule));
-                }
-
-                for port in &udp_ports {
-                    let mut statements = Vec::new();
-
-                    // Match source IP as localhost
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_src_ip(
-                            "127.0.0.1",
-                        ),
-                    );
-
-                    // Match protocol and destination port
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_protocol(
-                            "udp",
-                        ),
-                    );
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_dst_port(
-                            "udp", *port,
-                        ),
-                    );
-
-                    // Add counter
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::counter_statement(),
-                    );
-
-                    // Add log if configured
-                    if !config.mapped_ports.localhost.log_prefix.is_empty() {
-                        statements.push(
-                            <crate::docker::config::LocalRules as ToNftablesRule>::log_statement(
-                                Some(&config.mapped_ports.localhost.log_prefix),
-                            ),
-                        );
-                    }
-
-                    // Add verdict
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::verdict_to_statement(
-                            &config.mapped_ports.localhost.verdict,
-                        ),
-                    );
-
-                    let rule = Rule {
-                        family: ctx.family,
-                        table: Cow::Owned(ctx.table_name.to_string()),
-                        chain: Cow::Owned(ctx.chain_name.to_string()),
-                        expr: Cow::Owned(statements),
-                        handle: None,
-                        index: None,
-                        comment: Some(Cow::Owned(format!(
-                            "Localhost access to UDP port {} for {}",
-                            port, container_name
-                        ))),
-                    };
-
-                    transaction.batch.add(NfListObject::Rule(rule));
-                }
-            }
-
-            // Create external rules for each port
-            if config.mapped_ports.external.allow {
-                for port in &tcp_ports {
-                    let mut statements = Vec::new();
-
-                    // Match source IPs if specified
-                    if !config.mapped_ports.external.ips.is_empty() {
-                        // For simplicity, handle the first IP only for now
-                        if let Some(first_ip) = config.mapped_ports.external.ips.first() {
-                            match first_ip {
-                                crate::docker::config::AddrOrRange::Addr(ip) => {
-                                    statements.push(<crate::docker::config::ExternalRules as ToNftablesRule>::match_src_ip(&ip.to_string()));
-                                }
-                                crate::docker::config::AddrOrRange::Net(net) => {
-                                    statements.push(<crate::docker::config::ExternalRules as ToNftablesRule>::match_src_ip(&net.to_string()));
-                                }
-                                _ => {} // Handle ranges later
-                            }
-                        }
-                    }
-
-                    // Match protocol and destination port
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_protocol(
-                            "tcp",
-                        ),
-                    );
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_dst_port(
-                            "tcp", *port,
-                        ),
-                    );
-
-                    // Add counter
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::counter_statement(),
-                    );
-
-                    // Add log if configured
-                    if !config.mapped_ports.external.log_prefix.is_empty() {
-                        statements.push(
-                            <crate::docker::config::ExternalRules as ToNftablesRule>::log_statement(
-                                Some(&config.mapped_ports.external.log_prefix),
-                            ),
-                        );
-                    }
-
-                    // Add verdict
-                    statements.push(<crate::docker::config::ExternalRules as ToNftablesRule>::verdict_to_statement(&config.mapped_ports.external.verdict));
-
-                    let rule = Rule {
-                        family: ctx.family,
-                        table: Cow::Owned(ctx.table_name.to_string()),
-                        chain: Cow::Owned(ctx.chain_name.to_string()),
-                        expr: Cow::Owned(statements),
-                        handle: None,
-                        index: None,
-                        comment: Some(Cow::Owned(format!(
-                            "External access to port {} for {}",
-                            port, container_name
-                        ))),
-                    };
-
-                    transaction.batch.add(NfListObject::Rule(rule));
-                }
-
-                for port in &udp_ports {
-                    let mut statements = Vec::new();
-
-                    // Match source IPs if specified
-                    if !config.mapped_ports.external.ips.is_empty() {
-                        // For simplicity, handle the first IP only for now
-                        if let Some(first_ip) = config.mapped_ports.external.ips.first() {
-                            match first_ip {
-                                crate::docker::config::AddrOrRange::Addr(ip) => {
-                                    statements.push(<crate::docker::config::ExternalRules as ToNftablesRule>::match_src_ip(&ip.to_string()));
-                                }
-                                crate::docker::config::AddrOrRange::Net(net) => {
-                                    statements.push(<crate::docker::config::ExternalRules as ToNftablesRule>::match_src_ip(&net.to_string()));
-                                }
-                                _ => {} // Handle ranges later
-                            }
-                        }
-                    }
-
-                    // Match protocol and destination port
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_protocol(
-                            "udp",
-                        ),
-                    );
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::match_dst_port(
-                            "udp", *port,
-                        ),
-                    );
-
-                    // Add counter
-                    statements.push(
-                        <crate::docker::config::LocalRules as ToNftablesRule>::counter_statement(),
-                    );
-
-                    // Add log if configured
-                    if !config.mapped_ports.external.log_prefix.is_empty() {
-                        statements.push(
-                            <crate::docker::config::ExternalRules as ToNftablesRule>::log_statement(
-                                Some(&config.mapped_ports.external.log_prefix),
-                            ),
-                        );
-                    }
-
-                    // Add verdict
-                    statements.push(<crate::docker::config::ExternalRules as ToNftablesRule>::verdict_to_statement(&config.mapped_ports.external.verdict));
-
-                    let rule = Rule {
-                        family: ctx.family,
-                        table: Cow::Owned(ctx.table_name.to_string()),
-                        chain: Cow::Owned(ctx.chain_name.to_string()),
-                        expr: Cow::Owned(statements),
-                        handle: None,
-                        index: None,
-                        comment: Some(Cow::Owned(format!(
-                            "External access to UDP port {} for {}",
-                            port, container_name
-                        ))),
-                    };
-
-                    transaction.batch.add(NfListObject::Rule(rule));
-                }
-            }
-        }
-
-        // Add output rules
-        for (i, output_rule) in config.output.iter().enumerate() {
-            if !output_rule.skip {
-                let rule = output_rule.to_nftables_rule(
-                    &ctx,
-                    Some(format!("Output rule {} for {}", i + 1, container_name)),
-                )?;
-                transaction.batch.add(NfListObject::Rule(rule));
-            }
-        }
+        let sets = harborshield_core::nftables::add_container_rules(
+            &mut transaction.batch,
+            &mut transaction.raw_rules,
+            &ctx,
+            config,
+            &rule_defaults(),
+        )?;
+        connlimit::register(&mut transaction.batch, family, &chain_name, sets.meters);
+        hostname::register(&mut transaction.batch, family, &chain_name, sets.hosts);
 
         Ok(())
     }
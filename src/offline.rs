@@ -0,0 +1,110 @@
+//! Air-gapped operation: no outbound calls harborshield starts on its own.
+//!
+//! Some features reach the network without the user naming a destination:
+//! GeoIP database downloads, blocklist fetches and update checks. In an
+//! offline build (`cargo build --features offline`) or with `--offline`
+//! every such fetch is refused through [`guard`] before a connection is
+//! made. Destinations the user configured are unaffected: the Docker API,
+//! `--reconcile-webhook`, and hostnames in rules resolved through the
+//! system resolver.
+//!
+//! `GET /about` and `harborshield --version-info` report which of the two
+//! applies, so an audit can check the guarantee on a running host.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether this binary was built with the `offline` feature
+pub const COMPILED: bool = cfg!(feature = "offline");
+
+static RUNTIME: AtomicBool = AtomicBool::new(false);
+
+/// Refuse automatic outbound calls for the rest of the process
+pub fn set_offline(offline: bool) {
+    RUNTIME.store(offline, Ordering::Relaxed);
+}
+
+/// Whether automatic outbound calls are refused
+pub fn is_offline() -> bool {
+    COMPILED || RUNTIME.load(Ordering::Relaxed)
+}
+
+/// Outbound calls harborshield makes without a user-configured destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fetch {
+    GeoIp,
+    Blocklist,
+    UpdateCheck,
+}
+
+impl Fetch {
+    pub const ALL: [Fetch; 3] = [Fetch::GeoIp, Fetch::Blocklist, Fetch::UpdateCheck];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Fetch::GeoIp => "geoip",
+            Fetch::Blocklist => "blocklist",
+            Fetch::UpdateCheck => "update_check",
+        }
+    }
+}
+
+/// Check that `fetch` may go out; call before opening any connection for it
+pub fn guard(fetch: Fetch) -> Result<(), String> {
+    if COMPILED {
+        return Err(format!(
+            "{} disabled: harborshield was built with the offline feature",
+            fetch.as_str()
+        ));
+    }
+    if is_offline() {
+        return Err(format!("{} disabled by --offline", fetch.as_str()));
+    }
+    Ok(())
+}
+
+/// The guarantee as reported by `GET /about`
+#[derive(Debug, Clone, Serialize)]
+pub struct Network {
+    /// Automatic outbound calls are refused
+    pub offline: bool,
+    /// Refused because of the build, so no flag can turn them back on
+    pub compiled_offline: bool,
+    /// Fetches that would be refused right now
+    pub disabled_fetches: Vec<&'static str>,
+}
+
+pub fn network() -> Network {
+    Network {
+        offline: is_offline(),
+        compiled_offline: COMPILED,
+        disabled_fetches: Fetch::ALL
+            .into_iter()
+            .filter(|fetch| guard(*fetch).is_err())
+            .map(Fetch::as_str)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_guard() {
+        set_offline(false);
+        assert_eq!(is_offline(), COMPILED);
+        assert_eq!(guard(Fetch::UpdateCheck).is_err(), COMPILED);
+
+        set_offline(true);
+        let err = guard(Fetch::Blocklist).unwrap_err();
+        assert!(err.starts_with("blocklist disabled"), "{}", err);
+        let network = network();
+        assert!(network.offline);
+        assert_eq!(
+            network.disabled_fetches,
+            ["geoip", "blocklist", "update_check"]
+        );
+        set_offline(false);
+    }
+}
@@ -0,0 +1,506 @@
+//! Terminal output shared by the CLI subcommands.
+//!
+//! Commands build a [`Render`] value and hand it to [`emit`], which prints it
+//! as an aligned table (optionally with extra `wide` columns) or as JSON.
+//! Colors are only used for tables written to a terminal and honour
+//! `NO_COLOR`, so JSON and piped output are always plain.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns for humans
+    #[default]
+    Table,
+    /// Table with additional detail columns
+    Wide,
+    /// Machine-readable JSON
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Yellow,
+    Green,
+    Dim,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        match self {
+            Color::Red => "31",
+            Color::Yellow => "33",
+            Color::Green => "32",
+            Color::Dim => "2",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    header: &'static str,
+    align: Align,
+    wide_only: bool,
+}
+
+impl Column {
+    pub fn left(header: &'static str) -> Self {
+        Self {
+            header,
+            align: Align::Left,
+            wide_only: false,
+        }
+    }
+
+    pub fn right(header: &'static str) -> Self {
+        Self {
+            header,
+            align: Align::Right,
+            wide_only: false,
+        }
+    }
+
+    /// Only shown with `--output wide`
+    pub fn wide(mut self) -> Self {
+        self.wide_only = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cell {
+    text: String,
+    color: Option<Color>,
+}
+
+impl Cell {
+    pub fn colored(text: impl Into<String>, color: Color) -> Self {
+        Self {
+            text: text.into(),
+            color: Some(color),
+        }
+    }
+}
+
+impl<T: Into<String>> From<T> for Cell {
+    fn from(text: T) -> Self {
+        Self {
+            text: text.into(),
+            color: None,
+        }
+    }
+}
+
+/// A table plus free-form lines printed below it
+#[derive(Debug, Clone)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<Cell>>,
+    footer: Vec<Cell>,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            rows: Vec::new(),
+            footer: Vec::new(),
+        }
+    }
+
+    /// Add a row; cells must match the columns, including wide-only ones
+    pub fn row(&mut self, cells: Vec<Cell>) -> &mut Self {
+        debug_assert_eq!(cells.len(), self.columns.len());
+        self.rows.push(cells);
+        self
+    }
+
+    pub fn footer(&mut self, line: impl Into<Cell>) -> &mut Self {
+        self.footer.push(line.into());
+        self
+    }
+
+    pub fn warning(&mut self, message: impl std::fmt::Display) -> &mut Self {
+        self.footer.push(Cell::colored(
+            format!("warning: {}", message),
+            Color::Yellow,
+        ));
+        self
+    }
+
+    pub fn render(&self, wide: bool, color: bool) -> String {
+        let visible: Vec<usize> = (0..self.columns.len())
+            .filter(|i| wide || !self.columns[*i].wide_only)
+            .collect();
+
+        let headers: Vec<_> = self
+            .columns
+            .iter()
+            .map(|column| crate::i18n::header(column.header))
+            .collect();
+
+        let widths: Vec<usize> = visible
+            .iter()
+            .map(|&i| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].text.chars().count())
+                    .chain([headers[i].chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let paint = |text: &str, c: Option<Color>| match c {
+            Some(c) if color => format!("\x1b[{}m{}\x1b[0m", c.code(), text),
+            _ => text.to_string(),
+        };
+
+        let line = |cells: Vec<(&str, Option<Color>)>| {
+            let padded: Vec<String> = cells
+                .into_iter()
+                .zip(visible.iter().zip(&widths))
+                .map(|((text, c), (&i, &width))| {
+                    // Pad before painting so escape codes don't skew alignment
+                    let text = match self.columns[i].align {
+                        Align::Left => format!("{:<width$}", text),
+                        Align::Right => format!("{:>width$}", text),
+                    };
+                    paint(&text, c)
+                })
+                .collect();
+            padded.join("  ").trim_end().to_string()
+        };
+
+        let mut out = String::new();
+        let header = line(
+            visible
+                .iter()
+                .map(|&i| (headers[i].as_ref(), None))
+                .collect(),
+        );
+        if color {
+            out.push_str(&format!("\x1b[1m{}\x1b[0m", header));
+        } else {
+            out.push_str(&header);
+        }
+        out.push('\n');
+
+        for row in &self.rows {
+            out.push_str(&line(
+                visible
+                    .iter()
+                    .map(|&i| (row[i].text.as_str(), row[i].color))
+                    .collect(),
+            ));
+            out.push('\n');
+        }
+
+        if !self.footer.is_empty() {
+            out.push('\n');
+            for cell in &self.footer {
+                out.push_str(&paint(&cell.text, cell.color));
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+
+    /// The table as an HTML `<table>` with every column, footer lines
+    /// following as paragraphs
+    pub fn html(&self) -> String {
+        let cell = |tag: &str, align: Align, text: &str| {
+            let style = match align {
+                Align::Left => "",
+                Align::Right => " style=\"text-align:right\"",
+            };
+            format!("<{tag}{style}>{}</{tag}>", html_escape(text))
+        };
+
+        let mut out = String::from("<table>\n<tr>");
+        for column in &self.columns {
+            out.push_str(&cell(
+                "th",
+                column.align,
+                &crate::i18n::header(column.header),
+            ));
+        }
+        out.push_str("</tr>\n");
+        for row in &self.rows {
+            out.push_str("<tr>");
+            for (column, value) in self.columns.iter().zip(row) {
+                out.push_str(&cell("td", column.align, &value.text));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+        for line in &self.footer {
+            out.push_str(&format!("<p>{}</p>\n", html_escape(&line.text)));
+        }
+        out
+    }
+}
+
+pub fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Something a CLI command can print
+pub trait Render: Serialize {
+    fn table(&self) -> Table;
+}
+
+/// Whether stdout should get ANSI colors
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+/// Format `value` for `format`
+pub fn format<T: Render>(value: &T, format: OutputFormat, color: bool) -> String {
+    match format {
+        OutputFormat::Json => {
+            let mut json = serde_json::to_string_pretty(value)
+                .unwrap_or_else(|e| format!("{{\"error\": \"{}\"}}", e));
+            json.push('\n');
+            json
+        }
+        OutputFormat::Table => value.table().render(false, color),
+        OutputFormat::Wide => value.table().render(true, color),
+    }
+}
+
+/// Print `value` to stdout
+pub fn emit<T: Render>(value: &T, output: OutputFormat) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(format(value, output, use_color()).as_bytes());
+}
+
+/// Human-readable byte size
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Unified diff of `old` against `new` with `context` unchanged lines
+/// around each change; empty when they are the same
+pub fn unified_diff(old: &[String], new: &[String], context: usize) -> Vec<String> {
+    // Only the middle between a common prefix and suffix needs the LCS table
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    if a.is_empty() && b.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    // Each line as (tag, old index, new index) over the whole input
+    let mut ops: Vec<(char, usize, usize)> = (0..prefix).map(|i| (' ', i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', prefix + i, prefix + j));
+            i += 1;
+        } else {
+            ops.push(('+', prefix + i, prefix + j));
+            j += 1;
+        }
+    }
+    ops.extend((0..suffix).map(|k| (' ', old.len() - suffix + k, new.len() - suffix + k)));
+
+    let mut out = Vec::new();
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(context);
+        let mut end = changed[k];
+        while k < changed.len() && changed[k] <= end + 2 * context + 1 {
+            end = changed[k];
+            k += 1;
+        }
+        let end = (end + context + 1).min(ops.len());
+        let hunk = &ops[start..end];
+        let old_len = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_len = hunk.iter().filter(|op| op.0 != '-').count();
+        out.push(format!(
+            "@@ -{},{} +{},{} @@",
+            hunk[0].1 + usize::from(old_len > 0),
+            old_len,
+            hunk[0].2 + usize::from(new_len > 0),
+            new_len
+        ));
+        for &(tag, o, n) in hunk {
+            let line = if tag == '+' { &new[n] } else { &old[o] };
+            out.push(format!("{}{}", tag, line));
+        }
+    }
+    out
+}
+
+/// Color the lines of a [`unified_diff`] for a terminal
+pub fn paint_diff(lines: &[String], color: bool) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let c = match line.chars().next() {
+            Some('+') => Some(Color::Green),
+            Some('-') => Some(Color::Red),
+            Some('@') => Some(Color::Dim),
+            _ => None,
+        };
+        match c {
+            Some(c) if color => out.push_str(&format!("\x1b[{}m{}\x1b[0m", c.code(), line)),
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Sample {
+        name: String,
+        count: u64,
+    }
+
+    impl Render for Sample {
+        fn table(&self) -> Table {
+            let mut table = Table::new(vec![
+                Column::left("NAME"),
+                Column::right("COUNT"),
+                Column::right("EXACT").wide(),
+            ]);
+            table.row(vec![
+                self.name.clone().into(),
+                Cell::colored(self.count.to_string(), Color::Red),
+                format!("{}", self.count).into(),
+            ]);
+            table.warning("almost full");
+            table
+        }
+    }
+
+    fn sample() -> Sample {
+        Sample {
+            name: "web".to_string(),
+            count: 1234,
+        }
+    }
+
+    #[test]
+    fn test_table_alignment() {
+        let out = format(&sample(), OutputFormat::Table, false);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "NAME  COUNT");
+        assert_eq!(lines[1], "web    1234");
+        assert_eq!(lines[3], "warning: almost full");
+    }
+
+    #[test]
+    fn test_wide_columns() {
+        let out = format(&sample(), OutputFormat::Wide, false);
+        assert!(out.lines().next().unwrap().ends_with("EXACT"));
+        assert!(!format(&sample(), OutputFormat::Table, false).contains("EXACT"));
+    }
+
+    #[test]
+    fn test_json_is_plain() {
+        let out = format(&sample(), OutputFormat::Json, true);
+        assert!(!out.contains('\x1b'));
+        let value: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(value["count"], 1234);
+    }
+
+    #[test]
+    fn test_color_does_not_skew_alignment() {
+        let out = format(&sample(), OutputFormat::Table, true);
+        let row = out.lines().nth(1).unwrap();
+        assert_eq!(row, "web   \x1b[31m 1234\x1b[0m");
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(100), "100 B");
+        assert_eq!(human_bytes(2048), "2.0 KiB");
+        assert_eq!(human_bytes(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let lines = |text: &str| -> Vec<String> { text.split(' ').map(str::to_string).collect() };
+        let old = lines("a b c d e f g h i j");
+        let new = lines("a b c X e f g h i j k");
+
+        assert!(unified_diff(&old, &old, 3).is_empty());
+        assert_eq!(
+            unified_diff(&old, &new, 1),
+            vec![
+                "@@ -3,3 +3,3 @@",
+                " c",
+                "-d",
+                "+X",
+                " e",
+                "@@ -10,1 +10,2 @@",
+                " j",
+                "+k"
+            ]
+        );
+        // Changes closer than twice the context share a hunk
+        assert_eq!(unified_diff(&old, &new, 3)[0], "@@ -1,10 +1,11 @@");
+    }
+}
@@ -0,0 +1,315 @@
+//! Consistency report over a period, usually the last day: policy changes,
+//! drift found and repaired, the containers dropping the most traffic and
+//! rule sets that fail validation.
+//!
+//! Printed by `harborshield report` for mail cron jobs, and with
+//! `--report-at` built by the daemon daily, written to `--report-dir` and
+//! posted to `--report-webhook`.
+
+use crate::database::audit::{
+    AuditReport, KIND_ADDRESS_BANNED, KIND_ADDRESS_BLOCKED, KIND_ADDRESS_UNBANNED,
+    KIND_ADHOC_APPLIED, KIND_ADHOC_EXPIRED, KIND_BAN_EXPIRED, KIND_DRIFT, KIND_ENFORCEMENT_CHANGED,
+    KIND_FROZEN, KIND_PANIC_ENGAGED, KIND_PANIC_RELEASED, KIND_QUARANTINE_RELEASED,
+    KIND_QUARANTINED, KIND_RULE_DISABLED, KIND_RULE_ENABLED, KIND_RULE_REMOVED, KIND_UNFROZEN,
+};
+use crate::database::{AuditEntry, DB, StatsBucket, audit, stats};
+use crate::docker::container::Container;
+use crate::docker::labels::{self, RULES_KEY};
+use crate::output::{Cell, Color, Column, Render, Table, html_escape, human_bytes};
+use crate::validate::{self, Problem};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Audit kinds that change what a container is allowed
+const POLICY_KINDS: &[&str] = &[
+    KIND_RULE_DISABLED,
+    KIND_RULE_ENABLED,
+    KIND_RULE_REMOVED,
+    KIND_ENFORCEMENT_CHANGED,
+    KIND_QUARANTINED,
+    KIND_QUARANTINE_RELEASED,
+    KIND_ADHOC_APPLIED,
+    KIND_ADHOC_EXPIRED,
+    KIND_ADDRESS_BLOCKED,
+    KIND_ADDRESS_BANNED,
+    KIND_ADDRESS_UNBANNED,
+    KIND_BAN_EXPIRED,
+    KIND_FROZEN,
+    KIND_UNFROZEN,
+    KIND_PANIC_ENGAGED,
+    KIND_PANIC_RELEASED,
+];
+
+/// Containers listed under top dropped traffic
+const TOP_DROPPED: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Text => "txt",
+            ReportFormat::Html => "html",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsistencyReport {
+    /// Unix seconds
+    pub since: i64,
+    /// Unix seconds
+    pub until: i64,
+    pub policy_changes: Vec<AuditEntry>,
+    pub drift: Vec<AuditEntry>,
+    /// Busiest droppers first
+    pub top_dropped: Vec<StatsBucket>,
+    pub warnings: Vec<Problem>,
+}
+
+impl ConsistencyReport {
+    pub fn new(
+        entries: Vec<AuditEntry>,
+        stats: Vec<StatsBucket>,
+        warnings: Vec<Problem>,
+        since: i64,
+        until: i64,
+    ) -> Self {
+        let (drift, policy_changes): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .filter(|e| e.kind == KIND_DRIFT || POLICY_KINDS.contains(&e.kind.as_str()))
+            .partition(|e| e.kind == KIND_DRIFT);
+        Self {
+            since,
+            until,
+            policy_changes,
+            drift,
+            top_dropped: stats
+                .into_iter()
+                .filter(|s| s.dropped_packets > 0)
+                .take(TOP_DROPPED)
+                .collect(),
+            warnings,
+        }
+    }
+
+    /// Read the audit log and stats of the `window` before `now`
+    pub async fn collect(
+        db: &DB,
+        window: Duration,
+        now: i64,
+        warnings: Vec<Problem>,
+    ) -> crate::Result<Self> {
+        let since = now - window.as_secs() as i64;
+        let entries = audit::list_since(db, since).await?;
+        let stats = stats::stats_since(db, window, now).await?;
+        Ok(Self::new(entries, stats, warnings, since, now))
+    }
+
+    pub fn repaired(&self) -> usize {
+        self.drift.iter().filter(|e| e.repaired).count()
+    }
+
+    /// One line for a mail subject or the log
+    pub fn summary(&self) -> String {
+        format!(
+            "{} policy change(s), {} drift ({} repaired), {} validation warning(s)",
+            self.policy_changes.len(),
+            self.drift.len(),
+            self.repaired(),
+            self.warnings.len()
+        )
+    }
+
+    /// `harborshield-report-<date>.<ext>`, dated by the end of the period
+    pub fn file_name(&self, format: ReportFormat) -> String {
+        let date = chrono::DateTime::from_timestamp(self.until, 0).map_or_else(
+            || self.until.to_string(),
+            |t| t.format("%Y-%m-%d").to_string(),
+        );
+        format!("harborshield-report-{}.{}", date, format.extension())
+    }
+
+    fn sections(&self) -> Vec<(&'static str, Table)> {
+        let mut dropped = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::right("DROPPED PKTS"),
+            Column::right("DROPPED"),
+            Column::right("RULE CHANGES"),
+        ]);
+        for row in &self.top_dropped {
+            dropped.row(vec![
+                row.container_name.clone().into(),
+                row.dropped_packets.to_string().into(),
+                human_bytes(row.dropped_bytes.max(0) as u64).into(),
+                row.rule_changes.to_string().into(),
+            ]);
+        }
+        if self.top_dropped.is_empty() {
+            dropped.footer("no dropped traffic in this period");
+        }
+
+        vec![
+            (
+                "Policy changes",
+                AuditReport {
+                    entries: self.policy_changes.clone(),
+                }
+                .table(),
+            ),
+            (
+                "Drift",
+                AuditReport {
+                    entries: self.drift.clone(),
+                }
+                .table(),
+            ),
+            ("Top dropped traffic", dropped),
+            ("Validation warnings", self.table()),
+        ]
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        let period = |ts: i64| {
+            chrono::DateTime::from_timestamp(ts, 0)
+                .map_or_else(|| ts.to_string(), |t| t.to_rfc3339())
+        };
+        let title = format!(
+            "harborshield report {} to {}",
+            period(self.since),
+            period(self.until)
+        );
+
+        match format {
+            ReportFormat::Text => {
+                let mut out = format!("{}\n{}\n", title, self.summary());
+                for (heading, table) in self.sections() {
+                    out.push_str(&format!("\n{}\n\n{}", heading, table.render(true, false)));
+                }
+                out
+            }
+            ReportFormat::Html => {
+                let mut out = format!(
+                    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<p>{1}</p>\n",
+                    html_escape(&title),
+                    html_escape(&self.summary())
+                );
+                for (heading, table) in self.sections() {
+                    out.push_str(&format!(
+                        "<h2>{}</h2>\n{}",
+                        html_escape(heading),
+                        table.html()
+                    ));
+                }
+                out.push_str("</body>\n</html>\n");
+                out
+            }
+        }
+    }
+}
+
+/// The validation warnings table; the other sections are in
+/// [`ConsistencyReport::render`]
+impl Render for ConsistencyReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::left("KIND"),
+            Column::left("DETAIL"),
+        ]);
+        for problem in &self.warnings {
+            table.row(vec![
+                problem.source.clone().into(),
+                Cell::colored(problem.kind.as_str(), Color::Yellow),
+                problem.detail.clone().into(),
+            ]);
+        }
+        if self.warnings.is_empty() {
+            table.footer("all rule sets are valid");
+        }
+        table
+    }
+}
+
+/// Problems in the rules labels of `containers` that have harborshield
+/// enabled
+pub fn rule_warnings(containers: &[Container]) -> Vec<Problem> {
+    containers
+        .iter()
+        .filter(|c| c.is_harborshield_enabled())
+        .filter_map(|c| Some((c, labels::get(&c.labels, RULES_KEY)?)))
+        .flat_map(|(c, rules)| validate::check_rules(&c.name, rules, None))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::Kind;
+
+    #[test]
+    fn test_consistency_report() {
+        let entry = |kind: &str, repaired| {
+            AuditEntry::builder()
+                .ts(1_760_400_000)
+                .kind(kind)
+                .container_name("web".to_string())
+                .detail("rule <1>")
+                .repaired(repaired)
+                .build()
+        };
+        let stats = vec![
+            StatsBucket {
+                container_name: "web".to_string(),
+                dropped_packets: 40,
+                dropped_bytes: 2400,
+                ..Default::default()
+            },
+            StatsBucket {
+                container_name: "db".to_string(),
+                rule_changes: 1,
+                ..Default::default()
+            },
+        ];
+        let warnings = vec![Problem {
+            source: "cache".to_string(),
+            kind: Kind::UnknownKey,
+            detail: "unknown key 'output[1].dst_port'".to_string(),
+        }];
+
+        let report = ConsistencyReport::new(
+            vec![
+                entry(KIND_RULE_DISABLED, false),
+                entry(KIND_DRIFT, true),
+                entry(KIND_DRIFT, false),
+                entry("reconcile", false),
+            ],
+            stats,
+            warnings,
+            1_760_313_600,
+            1_760_400_000,
+        );
+        assert_eq!(
+            report.summary(),
+            "1 policy change(s), 2 drift (1 repaired), 1 validation warning(s)"
+        );
+        assert_eq!(report.top_dropped.len(), 1);
+        assert_eq!(
+            report.file_name(ReportFormat::Html),
+            "harborshield-report-2025-10-14.html"
+        );
+
+        let text = report.render(ReportFormat::Text);
+        assert!(text.contains("Top dropped traffic"), "{}", text);
+        assert!(text.contains("output[1].dst_port"), "{}", text);
+        let html = report.render(ReportFormat::Html);
+        assert!(html.contains("rule &lt;1&gt;"), "{}", html);
+        assert!(!html.contains("rule <1>"), "{}", html);
+    }
+}
@@ -0,0 +1,425 @@
+//! In-memory engine for unit tests.
+//!
+//! [`FakeRuntime`] answers the [`ContainerRuntime`] calls from containers
+//! and networks added to it, in the shapes Docker's API returns, so label
+//! parsing, rule rendering and the event pipeline run as they do against a
+//! daemon. Events are injected with [`FakeRuntime::emit`] or the lifecycle
+//! helpers and reach every stream `events` has handed out.
+
+use super::{ContainerRuntime, RuntimeKind};
+use crate::docker::container::{Container, PortMapping, Tracker};
+use crate::docker::network::{NetworkGatewayInfo, extract_network_gateway};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bollard::models::{
+    ContainerConfig, ContainerInspectResponse, ContainerState, ContainerStateStatusEnum,
+    ContainerSummary, ContainerSummaryStateEnum, EndpointSettings, EventActor, EventMessage,
+    EventMessageTypeEnum, HostConfig, Ipam, IpamConfig, Network, NetworkSettings, PortBinding,
+};
+use bon::Builder;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::stream::{BoxStream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::Mutex;
+
+/// A container as the fake engine reports it
+#[derive(Debug, Clone, Builder)]
+pub struct FakeContainer {
+    #[builder(into)]
+    pub id: String,
+    #[builder(into)]
+    pub name: String,
+    #[builder(default)]
+    pub labels: HashMap<String, String>,
+    /// Addresses on each network, by network name
+    #[builder(default)]
+    pub networks: HashMap<String, Vec<String>>,
+    /// Exposed ports, published when they have a host port
+    #[builder(default)]
+    pub ports: Vec<PortMapping>,
+    #[builder(default = true)]
+    pub running: bool,
+}
+
+impl FakeContainer {
+    /// What `docker inspect` would return
+    pub fn inspect(&self) -> ContainerInspectResponse {
+        let key = |port: &PortMapping| format!("{}/{}", port.container_port, port.protocol);
+        let networks = self
+            .networks
+            .iter()
+            .map(|(name, addrs)| {
+                let (v6, v4): (Vec<&String>, Vec<&String>) =
+                    addrs.iter().partition(|addr| addr.contains(':'));
+                let endpoint = EndpointSettings {
+                    ip_address: v4.first().map(|addr| addr.to_string()),
+                    global_ipv6_address: v6.first().map(|addr| addr.to_string()),
+                    ..Default::default()
+                };
+                (name.clone(), endpoint)
+            })
+            .collect();
+        let port_bindings = self
+            .ports
+            .iter()
+            .filter_map(|port| {
+                let binding = PortBinding {
+                    host_ip: Some(
+                        port.host_ip
+                            .map_or("0.0.0.0".to_string(), |ip| ip.to_string()),
+                    ),
+                    host_port: Some(port.host_port?.to_string()),
+                };
+                Some((key(port), Some(vec![binding])))
+            })
+            .collect();
+
+        ContainerInspectResponse {
+            id: Some(self.id.clone()),
+            name: Some(format!("/{}", self.name)),
+            config: Some(ContainerConfig {
+                labels: Some(self.labels.clone()),
+                exposed_ports: Some(
+                    self.ports
+                        .iter()
+                        .map(|port| (key(port), HashMap::new()))
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                port_bindings: Some(port_bindings),
+                ..Default::default()
+            }),
+            network_settings: Some(NetworkSettings {
+                networks: Some(networks),
+                ..Default::default()
+            }),
+            state: Some(ContainerState {
+                running: Some(self.running),
+                status: Some(if self.running {
+                    ContainerStateStatusEnum::RUNNING
+                } else {
+                    ContainerStateStatusEnum::EXITED
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// What `docker ps` would list
+    pub fn summary(&self) -> ContainerSummary {
+        ContainerSummary {
+            id: Some(self.id.clone()),
+            names: Some(vec![format!("/{}", self.name)]),
+            labels: Some(self.labels.clone()),
+            state: Some(if self.running {
+                ContainerSummaryStateEnum::RUNNING
+            } else {
+                ContainerSummaryStateEnum::EXITED
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// A container event as Docker emits it
+pub fn event(action: &str, id: &str, attributes: &[(&str, &str)]) -> EventMessage {
+    EventMessage {
+        typ: Some(EventMessageTypeEnum::CONTAINER),
+        action: Some(action.to_string()),
+        actor: Some(EventActor {
+            id: Some(id.to_string()),
+            attributes: Some(
+                attributes
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+        }),
+        time_nano: chrono::Utc::now().timestamp_nanos_opt(),
+        ..Default::default()
+    }
+}
+
+fn no_such_container(id: &str) -> Error {
+    Error::Docker(bollard::errors::Error::DockerResponseServerError {
+        status_code: 404,
+        message: format!("No such container: {}", id),
+    })
+}
+
+pub struct FakeRuntime {
+    kind: RuntimeKind,
+    tracker: Arc<Tracker>,
+    gateways: Arc<Mutex<HashMap<String, NetworkGatewayInfo>>>,
+    containers: StdMutex<BTreeMap<String, FakeContainer>>,
+    networks: StdMutex<Vec<Network>>,
+    security_options: StdMutex<Vec<String>>,
+    /// Senders of every event stream handed out
+    streams: StdMutex<Vec<UnboundedSender<Result<EventMessage>>>>,
+}
+
+impl Default for FakeRuntime {
+    fn default() -> Self {
+        Self::new(RuntimeKind::Docker)
+    }
+}
+
+impl FakeRuntime {
+    pub fn new(kind: RuntimeKind) -> Self {
+        Self {
+            kind,
+            tracker: Arc::new(Tracker::builder().build()),
+            gateways: Arc::new(Mutex::new(HashMap::new())),
+            containers: StdMutex::new(BTreeMap::new()),
+            networks: StdMutex::new(Vec::new()),
+            security_options: StdMutex::new(Vec::new()),
+            streams: StdMutex::new(Vec::new()),
+        }
+    }
+
+    /// Add or replace a container, without an event
+    pub fn add_container(&self, container: FakeContainer) {
+        self.containers
+            .lock()
+            .unwrap()
+            .insert(container.id.clone(), container);
+    }
+
+    /// Add a network with a gateway and subnet
+    pub fn add_network(&self, name: &str, subnet: &str, gateway: &str) {
+        self.networks.lock().unwrap().push(Network {
+            id: Some(format!("{}-id", name)),
+            name: Some(name.to_string()),
+            ipam: Some(Ipam {
+                config: Some(vec![IpamConfig {
+                    subnet: Some(subnet.to_string()),
+                    gateway: Some(gateway.to_string()),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
+    pub fn set_security_options(&self, options: &[&str]) {
+        *self.security_options.lock().unwrap() =
+            options.iter().map(|option| option.to_string()).collect();
+    }
+
+    /// Deliver `event` to every open event stream
+    pub fn emit(&self, event: EventMessage) {
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|stream| stream.unbounded_send(Ok(event.clone())).is_ok());
+    }
+
+    /// End every open event stream with an error, as a daemon restart would
+    pub fn fail_streams(&self, message: &str) {
+        for stream in self.streams.lock().unwrap().drain(..) {
+            let _ = stream.unbounded_send(Err(Error::network(message.to_string())));
+        }
+    }
+
+    /// Mark a container running and emit `start`
+    pub fn start(&self, id: &str) -> Result<()> {
+        self.set_running(id, true)?;
+        self.emit(event("start", id, &[]));
+        Ok(())
+    }
+
+    /// Mark a container stopped and emit `die`
+    pub fn stop(&self, id: &str) -> Result<()> {
+        self.set_running(id, false)?;
+        self.emit(event("die", id, &[("exitCode", "0")]));
+        Ok(())
+    }
+
+    fn set_running(&self, id: &str, running: bool) -> Result<()> {
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| no_such_container(id))?;
+        container.running = running;
+        Ok(())
+    }
+
+    fn find(&self, id: &str) -> Result<FakeContainer> {
+        let containers = self.containers.lock().unwrap();
+        // Docker accepts names and ID prefixes too
+        containers
+            .get(id)
+            .or_else(|| {
+                containers
+                    .values()
+                    .find(|c| c.name == id.trim_start_matches('/') || c.id.starts_with(id))
+            })
+            .cloned()
+            .ok_or_else(|| no_such_container(id))
+    }
+
+    fn summaries(&self, all: bool) -> Vec<ContainerSummary> {
+        self.containers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|container| all || container.running)
+            .map(FakeContainer::summary)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    fn kind(&self) -> RuntimeKind {
+        self.kind
+    }
+
+    fn container_tracker(&self) -> &Arc<Tracker> {
+        &self.tracker
+    }
+
+    fn network_gateway_cache(&self) -> &Arc<Mutex<HashMap<String, NetworkGatewayInfo>>> {
+        &self.gateways
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>> {
+        Ok(self.summaries(false))
+    }
+
+    async fn list_all_containers(&self) -> Result<Vec<ContainerSummary>> {
+        Ok(self.summaries(true))
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse> {
+        Ok(self.find(id)?.inspect())
+    }
+
+    async fn try_get_container_by_id(&self, id: &str) -> Result<Container> {
+        Container::from_inspect(self.find(id)?.inspect())
+    }
+
+    async fn events(&self) -> Result<BoxStream<'_, Result<EventMessage>>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.streams.lock().unwrap().push(tx);
+        Ok(rx.boxed())
+    }
+
+    async fn start_container(&self, id: &str) -> Result<()> {
+        let id = self.find(id)?.id;
+        self.start(&id)
+    }
+
+    async fn refresh_network_gateways(&self) -> Result<()> {
+        let networks = self.networks.lock().unwrap().clone();
+        let mut gateways = self.gateways.lock().await;
+        for network in &networks {
+            let info = extract_network_gateway(network)?;
+            gateways.insert(info.network_name.clone(), info);
+        }
+        Ok(())
+    }
+
+    async fn security_options(&self) -> Result<Vec<String>> {
+        Ok(self.security_options.lock().unwrap().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nftables::transaction::NftablesTransaction;
+    use nftables::types::NfFamily;
+
+    fn web() -> FakeContainer {
+        FakeContainer::builder()
+            .id("0123456789abcdef")
+            .name("web")
+            .labels(HashMap::from([
+                ("harborshield.enabled".to_string(), "true".to_string()),
+                (
+                    "harborshield.rules".to_string(),
+                    "output:\n  - ips: [10.0.0.5]\n    proto: tcp\n    dst_ports: [5432]\n"
+                        .to_string(),
+                ),
+            ]))
+            .networks(HashMap::from([(
+                "bridge".to_string(),
+                vec!["172.17.0.2".to_string()],
+            )]))
+            .ports(vec![
+                PortMapping::builder()
+                    .container_port(80)
+                    .host_port(8080)
+                    .protocol("tcp".to_string())
+                    .build(),
+            ])
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_fake_runtime() {
+        let runtime = FakeRuntime::default();
+        runtime.add_container(web());
+        runtime.add_network("bridge", "172.17.0.0/16", "172.17.0.1");
+
+        let container = runtime.try_get_container_by_id("web").await.unwrap();
+        assert!(container.enabled);
+        assert_eq!(container.ports[0].host_port, Some(8080));
+        let config = container.config.clone().unwrap();
+        assert_eq!(config.output.len(), 1);
+
+        // The rules render to the chain they would get from a daemon
+        let ips = container.ip_addresses(false);
+        let ports = vec![(80, "tcp".to_string())];
+        let mut transaction = NftablesTransaction::builder().family(NfFamily::IP).build();
+        NftablesTransaction::add_container_rules_to_transaction(
+            NfFamily::IP,
+            &mut transaction,
+            &container.id,
+            &container.name,
+            &ips,
+            &ports,
+            &config,
+        )
+        .unwrap();
+        let json = serde_json::to_string(&transaction.batch.to_nftables()).unwrap();
+        assert!(json.contains(r#""chain":"hs-web-0123456789ab""#));
+        assert!(json.contains("10.0.0.5"));
+
+        runtime.refresh_network_gateways().await.unwrap();
+        assert!(
+            runtime
+                .network_gateway_cache()
+                .lock()
+                .await
+                .contains_key("bridge")
+        );
+
+        // Lifecycle changes reach open event streams
+        let mut events = runtime.events().await.unwrap();
+        runtime.stop("0123456789abcdef").unwrap();
+        assert!(runtime.list_containers().await.unwrap().is_empty());
+        assert_eq!(runtime.list_all_containers().await.unwrap().len(), 1);
+        runtime.start_container("web").await.unwrap();
+        let actions: Vec<String> = [events.next().await, events.next().await]
+            .into_iter()
+            .map(|event| event.unwrap().unwrap().action.unwrap())
+            .collect();
+        assert_eq!(actions, vec!["die", "start"]);
+
+        runtime.fail_streams("daemon restarted");
+        assert!(events.next().await.unwrap().is_err());
+        assert!(events.next().await.is_none());
+        assert!(runtime.inspect_container("gone").await.is_err());
+    }
+}
@@ -0,0 +1,460 @@
+//! Several Docker daemons watched by one instance.
+//!
+//! The daemon harborshield would use on its own stays the primary one;
+//! each `--endpoint NAME=HOST` adds another. Containers, their aliases and
+//! networks of another endpoint are namespaced `NAME/...`, which keeps them
+//! apart in the tracker, the database, chain names and metric labels even
+//! when names repeat across daemons. A rule's `container:` and `network:`
+//! mean those of the same endpoint; `NAME/web` names one of another
+//! endpoint and `/web` one of the primary daemon.
+//!
+//! Rules are only applied on this host, so another daemon's containers are
+//! only filtered on the traffic passing through it, e.g. across a shared
+//! L2 segment. An endpoint that is down is skipped with a warning and
+//! reported in `/status` and the `harborshield_endpoint_up` metric.
+
+use super::{ContainerRuntime, RuntimeKind};
+use crate::docker::DockerClient;
+use crate::docker::container::{Container, Tracker};
+use crate::docker::network::NetworkGatewayInfo;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bollard::models::{ContainerInspectResponse, ContainerSummary, EventMessage};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex as StdMutex, RwLock};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// How each endpoint fared when last asked, by name
+static STATUS: LazyLock<RwLock<BTreeMap<String, EndpointStatus>>> =
+    LazyLock::new(|| RwLock::new(BTreeMap::new()));
+
+/// Another daemon to watch, as given to `--endpoint`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// Namespace of its containers
+    pub name: String,
+    /// Address as DOCKER_HOST takes it, e.g. `tcp://10.0.0.2:2376`
+    pub host: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, host) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=HOST, got '{}'", s))?;
+        let valid = !name.is_empty()
+            && name.len() <= 32
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(format!(
+                "endpoint name '{}' must be lowercase letters, digits and dashes",
+                name
+            ));
+        }
+        if host.is_empty() {
+            return Err(format!("endpoint {} has no host", name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            host: host.to_string(),
+        })
+    }
+}
+
+/// An endpoint as listed in `/status`
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct EndpointStatus {
+    pub host: String,
+    pub up: bool,
+    /// Running containers when last listed
+    pub containers: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Endpoints other than the primary one, for `/status`; `None` when there
+/// are none
+pub fn status() -> Option<BTreeMap<String, EndpointStatus>> {
+    let status = STATUS.read().map(|s| s.clone()).unwrap_or_default();
+    (!status.is_empty()).then_some(status)
+}
+
+fn set_status(name: &str, host: &str, result: std::result::Result<usize, String>) {
+    crate::server::set_endpoint_up(name, result.is_ok());
+    if let Ok(mut status) = STATUS.write() {
+        let previous = status.get(name).map(|s| s.containers).unwrap_or_default();
+        status.insert(
+            name.to_string(),
+            EndpointStatus {
+                host: host.to_string(),
+                up: result.is_ok(),
+                containers: *result.as_ref().unwrap_or(&previous),
+                error: result.err(),
+            },
+        );
+    }
+}
+
+/// `name` within `namespace`
+pub fn namespaced(namespace: &str, name: &str) -> String {
+    format!("{}/{}", namespace, name.trim_start_matches('/'))
+}
+
+/// A container or network a rule of `namespace` refers to
+fn reference(namespace: &str, name: &str) -> String {
+    match name {
+        "" => String::new(),
+        primary if primary.starts_with('/') => primary[1..].to_string(),
+        other if other.contains('/') => other.to_string(),
+        local => namespaced(namespace, local),
+    }
+}
+
+/// A container of another endpoint, with its names, networks and
+/// `container:` references namespaced
+pub fn namespace_container(mut container: Container, namespace: &str) -> Container {
+    container.name = namespaced(namespace, &container.name);
+    container.aliases = container
+        .aliases
+        .iter()
+        .map(|alias| namespaced(namespace, alias))
+        .collect();
+    container.networks = container
+        .networks
+        .into_iter()
+        .map(|(name, mut network)| {
+            network.name = namespaced(namespace, &network.name);
+            network.aliases = network
+                .aliases
+                .iter()
+                .map(|alias| namespaced(namespace, alias))
+                .collect();
+            (namespaced(namespace, &name), network)
+        })
+        .collect();
+    if let Some(config) = &mut container.config {
+        for rule in &mut config.output {
+            rule.container = reference(namespace, &rule.container);
+            rule.network = reference(namespace, &rule.network);
+        }
+    }
+    container
+}
+
+fn namespace_summary(mut summary: ContainerSummary, namespace: &str) -> ContainerSummary {
+    summary.names = summary.names.map(|names| {
+        names
+            .iter()
+            .map(|name| format!("/{}", namespaced(namespace, name)))
+            .collect()
+    });
+    summary
+}
+
+fn namespace_event(mut event: EventMessage, namespace: &str) -> EventMessage {
+    if let Some(attributes) = event
+        .actor
+        .as_mut()
+        .and_then(|actor| actor.attributes.as_mut())
+        && let Some(name) = attributes.get_mut("name")
+    {
+        *name = namespaced(namespace, name);
+    }
+    event
+}
+
+struct Member {
+    /// `None` for the primary daemon
+    endpoint: Option<Endpoint>,
+    client: DockerClient,
+}
+
+impl Member {
+    fn namespace(&self) -> Option<&str> {
+        self.endpoint.as_ref().map(|e| e.name.as_str())
+    }
+}
+
+/// The primary Docker daemon and the other endpoints, as one runtime
+pub struct FleetRuntime {
+    /// The primary daemon first
+    members: Vec<Member>,
+    /// Which member each container ID was seen on
+    owners: StdMutex<HashMap<String, usize>>,
+}
+
+impl FleetRuntime {
+    /// The primary daemon's client and one for each endpoint
+    pub fn new(primary: DockerClient, endpoints: Vec<(Endpoint, DockerClient)>) -> Self {
+        let mut members = vec![Member {
+            endpoint: None,
+            client: primary,
+        }];
+        members.extend(endpoints.into_iter().map(|(endpoint, client)| Member {
+            endpoint: Some(endpoint),
+            client,
+        }));
+        Self {
+            members,
+            owners: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    fn primary(&self) -> &DockerClient {
+        &self.members[0].client
+    }
+
+    fn remember(&self, id: &str, member: usize) {
+        if let Ok(mut owners) = self.owners.lock() {
+            owners.insert(id.to_string(), member);
+        }
+    }
+
+    /// The member `id` was seen on, asking each in turn if not seen yet
+    async fn owner(&self, id: &str) -> Result<usize> {
+        if let Some(index) = self.owners.lock().ok().and_then(|o| o.get(id).copied()) {
+            return Ok(index);
+        }
+        let mut first_error = None;
+        for (index, member) in self.members.iter().enumerate() {
+            match member.client.inspect_container(id).await {
+                Ok(_) => {
+                    self.remember(id, index);
+                    return Ok(index);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| Error::config("No endpoints")))
+    }
+
+    async fn list(&self, all: bool) -> Result<Vec<ContainerSummary>> {
+        let mut listed = Vec::new();
+        for (index, member) in self.members.iter().enumerate() {
+            let result = if all {
+                member.client.list_all_containers().await
+            } else {
+                member.client.list_containers().await
+            };
+            let summaries = match (result, &member.endpoint) {
+                (Ok(summaries), endpoint) => {
+                    if let (Some(endpoint), false) = (endpoint, all) {
+                        set_status(&endpoint.name, &endpoint.host, Ok(summaries.len()));
+                    }
+                    summaries
+                }
+                // The primary daemon is required
+                (Err(e), None) => return Err(e),
+                (Err(e), Some(endpoint)) => {
+                    warn!(
+                        "Endpoint {} at {} is unavailable, skipping its containers: {}",
+                        endpoint.name, endpoint.host, e
+                    );
+                    set_status(&endpoint.name, &endpoint.host, Err(e.to_string()));
+                    continue;
+                }
+            };
+            for summary in summaries {
+                if let Some(id) = &summary.id {
+                    self.remember(id, index);
+                }
+                listed.push(match member.namespace() {
+                    Some(namespace) => namespace_summary(summary, namespace),
+                    None => summary,
+                });
+            }
+        }
+        Ok(listed)
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FleetRuntime {
+    fn kind(&self) -> RuntimeKind {
+        RuntimeKind::Docker
+    }
+
+    fn container_tracker(&self) -> &Arc<Tracker> {
+        &self.primary().container_tracker
+    }
+
+    fn network_gateway_cache(&self) -> &Arc<Mutex<HashMap<String, NetworkGatewayInfo>>> {
+        &self.primary().network_gateway_cache
+    }
+
+    async fn ping(&self) -> Result<()> {
+        for member in &self.members[1..] {
+            if let (Err(e), Some(endpoint)) = (member.client.ping().await, &member.endpoint) {
+                warn!("Endpoint {} does not answer: {}", endpoint.name, e);
+                set_status(&endpoint.name, &endpoint.host, Err(e.to_string()));
+            }
+        }
+        self.primary().ping().await
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>> {
+        self.list(false).await
+    }
+
+    async fn list_all_containers(&self) -> Result<Vec<ContainerSummary>> {
+        self.list(true).await
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse> {
+        let index = self.owner(id).await?;
+        let mut inspect = self.members[index].client.inspect_container(id).await?;
+        if let (Some(namespace), Some(name)) = (self.members[index].namespace(), &inspect.name) {
+            inspect.name = Some(format!("/{}", namespaced(namespace, name)));
+        }
+        Ok(inspect)
+    }
+
+    async fn try_get_container_by_id(&self, id: &str) -> Result<Container> {
+        let index = self.owner(id).await?;
+        let member = &self.members[index];
+        let container = member.client.try_get_container_by_id(id).await?;
+        Ok(match member.namespace() {
+            Some(namespace) => namespace_container(container, namespace),
+            None => container,
+        })
+    }
+
+    /// Events of all endpoints, merged; an endpoint whose stream fails ends
+    /// the merged stream so the listener reconnects to all of them
+    async fn events(&self) -> Result<BoxStream<'_, Result<EventMessage>>> {
+        let mut streams = Vec::new();
+        for (index, member) in self.members.iter().enumerate() {
+            let events = match member.client.events().await {
+                Ok(events) => events,
+                Err(e) if index > 0 => {
+                    warn!("Not following the events of an endpoint: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let events = events.map_ok(move |event| {
+                if let Some(id) = event.actor.as_ref().and_then(|a| a.id.as_ref()) {
+                    self.remember(id, index);
+                }
+                match member.namespace() {
+                    Some(namespace) => namespace_event(event, namespace),
+                    None => event,
+                }
+            });
+            streams.push(events.boxed());
+        }
+        Ok(stream::select_all(streams).boxed())
+    }
+
+    async fn start_container(&self, id: &str) -> Result<()> {
+        let index = self.owner(id).await?;
+        self.members[index].client.start_container(id).await
+    }
+
+    /// Gateways of every endpoint's networks, those of other endpoints
+    /// under their namespaced names
+    async fn refresh_network_gateways(&self) -> Result<()> {
+        self.primary().refresh_network_gateways().await?;
+        for member in &self.members[1..] {
+            let Some(namespace) = member.namespace() else {
+                continue;
+            };
+            if let Err(e) = member.client.refresh_network_gateways().await {
+                warn!(
+                    "Failed to refresh networks of endpoint {}: {}",
+                    namespace, e
+                );
+                continue;
+            }
+            let remote = member.client.network_gateway_cache.lock().await.clone();
+            let mut cache = self.primary().network_gateway_cache.lock().await;
+            for (name, mut info) in remote {
+                info.network_name = namespaced(namespace, &name);
+                cache.insert(info.network_name.clone(), info);
+            }
+        }
+        Ok(())
+    }
+
+    /// The primary daemon's; rootless detection only concerns this host
+    async fn security_options(&self) -> Result<Vec<String>> {
+        self.primary().security_options().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::container::Network;
+
+    #[test]
+    fn test_namespace_container() {
+        assert_eq!(
+            "box2=tcp://10.0.0.2:2376".parse::<Endpoint>(),
+            Ok(Endpoint {
+                name: "box2".to_string(),
+                host: "tcp://10.0.0.2:2376".to_string(),
+            })
+        );
+        assert!("Box 2=tcp://10.0.0.2:2376".parse::<Endpoint>().is_err());
+        assert!("tcp://10.0.0.2:2376".parse::<Endpoint>().is_err());
+
+        let config: crate::docker::config::Config = serde_yaml::from_str(
+            r#"
+output:
+  - container: db
+    network: backend
+    proto: tcp
+    dst_ports: [80]
+  - container: /cache
+    network: /shared
+    proto: tcp
+    dst_ports: [80]
+  - container: box3/queue
+    network: box3/backend
+    proto: tcp
+    dst_ports: [80]
+"#,
+        )
+        .unwrap();
+        let container = Container::builder()
+            .id("abc".to_string())
+            .name("web".to_string())
+            .networks(HashMap::from([(
+                "backend".to_string(),
+                Network::builder().name("backend".to_string()).build(),
+            )]))
+            .config(config)
+            .build();
+
+        let container = namespace_container(container, "box2");
+        assert_eq!(container.name, "box2/web");
+        assert_eq!(container.networks["box2/backend"].name, "box2/backend");
+        let refs: Vec<(&str, &str)> = container
+            .config
+            .as_ref()
+            .unwrap()
+            .output
+            .iter()
+            .map(|rule| (rule.container.as_str(), rule.network.as_str()))
+            .collect();
+        assert_eq!(
+            refs,
+            vec![
+                ("box2/db", "box2/backend"),
+                ("cache", "shared"),
+                ("box3/queue", "box3/backend")
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! The container engine harborshield watches.
+//!
+//! Handlers only see a [`ContainerRuntime`]: listing and inspecting
+//! containers, following their events and the tracker built from them.
+//! Docker is talked to through [`DockerClient`]; rootful or rootless Podman
+//! through [`podman::PodmanClient`], which uses Podman's Docker-compatible
+//! API for containers and events and its libpod API for what that leaves
+//! out. Containers carry the same labels either way, so rules render the
+//! same. Several Docker daemons are watched as one through
+//! [`fleet::FleetRuntime`]. Unit tests run against the in-memory engine in
+//! `fake`.
+
+#[cfg(test)]
+pub mod fake;
+pub mod fleet;
+pub mod podman;
+
+use crate::Result;
+use crate::docker::container::{Container, Tracker};
+use crate::docker::network::NetworkGatewayInfo;
+use crate::docker::{DockerClient, compose};
+use async_trait::async_trait;
+use bollard::models::{ContainerInspectResponse, ContainerSummary, EventMessage};
+use futures::stream::{BoxStream, StreamExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+static KIND: AtomicU8 = AtomicU8::new(RuntimeKind::Docker as u8);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RuntimeKind {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl RuntimeKind {
+    /// The filter chain forwarded container traffic is jumped to
+    /// harborshield from. Docker reserves DOCKER-USER for this; netavark
+    /// has no such chain, so Podman's traffic is caught in FORWARD ahead of
+    /// netavark's own jump
+    pub fn forward_chain(self) -> &'static str {
+        match self {
+            Self::Docker => crate::nftables::DOCKER_USER_CHAIN,
+            Self::Podman => crate::nftables::FORWARD_CHAIN,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Docker => write!(f, "Docker"),
+            Self::Podman => write!(f, "Podman"),
+        }
+    }
+}
+
+pub fn set_kind(kind: RuntimeKind) {
+    KIND.store(kind as u8, Ordering::Relaxed);
+}
+
+/// The runtime the running process was started for
+pub fn kind() -> RuntimeKind {
+    match KIND.load(Ordering::Relaxed) {
+        1 => RuntimeKind::Podman,
+        _ => RuntimeKind::Docker,
+    }
+}
+
+/// Container engine operations harborshield relies on
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    fn kind(&self) -> RuntimeKind;
+
+    /// Containers seen so far, with their parsed rules
+    fn container_tracker(&self) -> &Arc<Tracker>;
+
+    fn network_gateway_cache(&self) -> &Arc<Mutex<HashMap<String, NetworkGatewayInfo>>>;
+
+    async fn ping(&self) -> Result<()>;
+
+    /// Running containers
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>>;
+
+    /// Running and stopped containers
+    async fn list_all_containers(&self) -> Result<Vec<ContainerSummary>>;
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse>;
+
+    /// The container with its rules, published ports and networks
+    async fn try_get_container_by_id(&self, id: &str) -> Result<Container>;
+
+    /// Container and network lifecycle events
+    async fn events(&self) -> Result<BoxStream<'_, Result<EventMessage>>>;
+
+    async fn start_container(&self, id: &str) -> Result<()>;
+
+    /// Re-read the gateways of every network into the cache
+    async fn refresh_network_gateways(&self) -> Result<()>;
+
+    /// Security options the engine runs with, e.g. `name=rootless`
+    async fn security_options(&self) -> Result<Vec<String>>;
+
+    /// Running containers, dependencies first
+    async fn get_sorted_containers(&self) -> Result<Vec<ContainerSummary>> {
+        let mut containers = self.list_containers().await?;
+        compose::sort_by_dependencies(&mut containers);
+        Ok(containers)
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for DockerClient {
+    fn kind(&self) -> RuntimeKind {
+        RuntimeKind::Docker
+    }
+
+    fn container_tracker(&self) -> &Arc<Tracker> {
+        &self.container_tracker
+    }
+
+    fn network_gateway_cache(&self) -> &Arc<Mutex<HashMap<String, NetworkGatewayInfo>>> {
+        &self.network_gateway_cache
+    }
+
+    async fn ping(&self) -> Result<()> {
+        DockerClient::ping(self).await
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>> {
+        DockerClient::list_containers(self).await
+    }
+
+    async fn list_all_containers(&self) -> Result<Vec<ContainerSummary>> {
+        DockerClient::list_all_containers(self).await
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse> {
+        DockerClient::inspect_container(self, id).await
+    }
+
+    async fn try_get_container_by_id(&self, id: &str) -> Result<Container> {
+        DockerClient::try_get_container_by_id(self, id).await
+    }
+
+    async fn events(&self) -> Result<BoxStream<'_, Result<EventMessage>>> {
+        Ok(DockerClient::events(self).await?.boxed())
+    }
+
+    async fn start_container(&self, id: &str) -> Result<()> {
+        DockerClient::start_container(self, id).await
+    }
+
+    async fn refresh_network_gateways(&self) -> Result<()> {
+        DockerClient::refresh_network_gateways(self).await
+    }
+
+    async fn security_options(&self) -> Result<Vec<String>> {
+        DockerClient::security_options(self).await
+    }
+}
+
+/// Connect to the `kind` engine and make it the process's runtime
+pub fn connect(kind: RuntimeKind, timeout: Duration) -> Result<Arc<dyn ContainerRuntime>> {
+    set_kind(kind);
+    Ok(match kind {
+        RuntimeKind::Docker => Arc::new(DockerClient::builder().timeout_duration(timeout).build()?),
+        RuntimeKind::Podman => Arc::new(podman::PodmanClient::connect(timeout)?),
+    })
+}
+
+/// Connect as [`connect`] does, watching the Docker daemons of `endpoints`
+/// next to the primary one
+pub fn connect_with_endpoints(
+    kind: RuntimeKind,
+    timeout: Duration,
+    endpoints: &[fleet::Endpoint],
+) -> Result<Arc<dyn ContainerRuntime>> {
+    if endpoints.is_empty() {
+        return connect(kind, timeout);
+    }
+    if kind != RuntimeKind::Docker {
+        return Err(crate::Error::config(format!(
+            "--endpoint is only supported with Docker, not {}",
+            kind
+        )));
+    }
+    let mut clients: Vec<(fleet::Endpoint, DockerClient)> = Vec::new();
+    for endpoint in endpoints {
+        if clients.iter().any(|(e, _)| e.name == endpoint.name) {
+            return Err(crate::Error::config(format!(
+                "Endpoint {} is given twice",
+                endpoint.name
+            )));
+        }
+        let client = DockerClient::builder()
+            .timeout_duration(timeout)
+            .host(endpoint.host.clone())
+            .build()?;
+        clients.push((endpoint.clone(), client));
+    }
+    let primary = DockerClient::builder().timeout_duration(timeout).build()?;
+    set_kind(kind);
+    Ok(Arc::new(fleet::FleetRuntime::new(primary, clients)))
+}
@@ -0,0 +1,227 @@
+//! Podman through its REST socket.
+//!
+//! Podman serves the Docker API next to its own libpod API on the same
+//! socket, so containers, events and networks are read with the Docker
+//! client pointed at it. The libpod API is only asked what the Docker one
+//! doesn't say reliably: whether Podman runs rootless. Netavark sets up
+//! container networking in the filter table's FORWARD chain, which is
+//! where harborshield's jump goes instead of DOCKER-USER.
+//!
+//! Containers in a pod share its infra container's network namespace and
+//! have no addresses of their own; like Docker's `container:` network
+//! mode, their labels have nothing to match on.
+
+use super::{ContainerRuntime, RuntimeKind};
+use crate::docker::DockerClient;
+use crate::docker::container::{Container, Tracker};
+use crate::docker::network::NetworkGatewayInfo;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bollard::models::{ContainerInspectResponse, ContainerSummary, EventMessage};
+use futures::stream::{BoxStream, StreamExt};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tracing::debug;
+
+/// Podman's own variable for its service address
+pub const CONTAINER_HOST_ENV: &str = "CONTAINER_HOST";
+
+/// The socket `podman.socket` listens on when run as root
+pub const ROOTFUL_SOCKET: &str = "/run/podman/podman.sock";
+
+pub struct PodmanClient {
+    docker: DockerClient,
+    socket: PathBuf,
+    timeout_duration: Duration,
+}
+
+/// CONTAINER_HOST when set, otherwise the socket of the user running
+/// harborshield. Only unix sockets are supported
+pub fn socket_path(
+    container_host: Option<&str>,
+    root: bool,
+    runtime_dir: Option<&str>,
+) -> io::Result<PathBuf> {
+    if let Some(host) = container_host {
+        return match host.strip_prefix("unix://") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unsupported {} '{}', expected a unix:// socket",
+                    CONTAINER_HOST_ENV, host
+                ),
+            )),
+        };
+    }
+    match (root, runtime_dir) {
+        (false, Some(dir)) => Ok(Path::new(dir).join("podman/podman.sock")),
+        _ => Ok(PathBuf::from(ROOTFUL_SOCKET)),
+    }
+}
+
+/// The JSON body of a raw HTTP/1.0 response, or an error for any status
+/// but 200
+fn parse_response(raw: &[u8]) -> io::Result<serde_json::Value> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let text = String::from_utf8_lossy(raw);
+    let (head, body) = text
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid("truncated response".to_string()))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(invalid(format!("answered '{}'", status)));
+    }
+    serde_json::from_str(body).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(target_os = "linux")]
+fn running_as_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn running_as_root() -> bool {
+    true
+}
+
+impl PodmanClient {
+    pub fn connect(timeout_duration: Duration) -> Result<Self> {
+        let socket = socket_path(
+            std::env::var(CONTAINER_HOST_ENV).ok().as_deref(),
+            running_as_root(),
+            std::env::var("XDG_RUNTIME_DIR").ok().as_deref(),
+        )?;
+        debug!("Connecting to Podman at {}", socket.display());
+        let docker = DockerClient::builder()
+            .timeout_duration(timeout_duration)
+            .host(format!("unix://{}", socket.display()))
+            .build()?;
+        Ok(Self {
+            docker,
+            socket,
+            timeout_duration,
+        })
+    }
+
+    /// GET a libpod endpoint such as `/libpod/info`
+    async fn libpod_get(&self, path: &str) -> Result<serde_json::Value> {
+        let request = async {
+            let mut stream = UnixStream::connect(&self.socket).await?;
+            stream
+                .write_all(format!("GET {} HTTP/1.0\r\nHost: podman\r\n\r\n", path).as_bytes())
+                .await?;
+            let mut raw = Vec::new();
+            stream.read_to_end(&mut raw).await?;
+            parse_response(&raw)
+        };
+        timeout(self.timeout_duration, request)
+            .await
+            .map_err(|_| Error::timeout(self.timeout_duration, format!("GET {}", path)))?
+            .map_err(|e| {
+                Error::network_with_endpoint(
+                    format!("Podman {}: {}", path, e),
+                    self.socket.display().to_string(),
+                )
+            })
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for PodmanClient {
+    fn kind(&self) -> RuntimeKind {
+        RuntimeKind::Podman
+    }
+
+    fn container_tracker(&self) -> &Arc<Tracker> {
+        &self.docker.container_tracker
+    }
+
+    fn network_gateway_cache(&self) -> &Arc<Mutex<HashMap<String, NetworkGatewayInfo>>> {
+        &self.docker.network_gateway_cache
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.docker.ping().await
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>> {
+        self.docker.list_containers().await
+    }
+
+    async fn list_all_containers(&self) -> Result<Vec<ContainerSummary>> {
+        self.docker.list_all_containers().await
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse> {
+        self.docker.inspect_container(id).await
+    }
+
+    async fn try_get_container_by_id(&self, id: &str) -> Result<Container> {
+        self.docker.try_get_container_by_id(id).await
+    }
+
+    async fn events(&self) -> Result<BoxStream<'_, Result<EventMessage>>> {
+        Ok(self.docker.events().await?.boxed())
+    }
+
+    async fn start_container(&self, id: &str) -> Result<()> {
+        self.docker.start_container(id).await
+    }
+
+    async fn refresh_network_gateways(&self) -> Result<()> {
+        self.docker.refresh_network_gateways().await
+    }
+
+    /// The compatible API's options, with `name=rootless` added when libpod
+    /// reports a rootless service
+    async fn security_options(&self) -> Result<Vec<String>> {
+        let mut options = self.docker.security_options().await?;
+        let info = self.libpod_get("/libpod/info").await?;
+        let rootless = info
+            .pointer("/host/security/rootless")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if rootless && !crate::docker::rootless::detect(&options) {
+            options.push("name=rootless".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_path() {
+        assert_eq!(
+            socket_path(None, true, Some("/run/user/0")).unwrap(),
+            PathBuf::from(ROOTFUL_SOCKET)
+        );
+        assert_eq!(
+            socket_path(None, false, Some("/run/user/1000")).unwrap(),
+            PathBuf::from("/run/user/1000/podman/podman.sock")
+        );
+        assert_eq!(
+            socket_path(Some("unix:///tmp/podman.sock"), true, None).unwrap(),
+            PathBuf::from("/tmp/podman.sock")
+        );
+        assert!(socket_path(Some("ssh://core@host/run/podman.sock"), true, None).is_err());
+
+        let info = parse_response(
+            b"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{\"host\":{\"security\":{\"rootless\":true}}}",
+        )
+        .unwrap();
+        assert_eq!(info.pointer("/host/security/rootless"), Some(&true.into()));
+        assert!(parse_response(b"HTTP/1.0 404 Not Found\r\n\r\n{}").is_err());
+    }
+}
@@ -3,7 +3,11 @@ use landlock::{ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
 use std::path::Path;
 use tracing::{info, warn};
 
-pub fn apply_landlock_rules(db_path: &Path, log_path: Option<&Path>) -> Result<()> {
+pub fn apply_landlock_rules(
+    db_path: &Path,
+    log_path: Option<&Path>,
+    reloaded: &[&Path],
+) -> Result<()> {
     let abi = ABI::V1;
 
     let mut ruleset = match Ruleset::default()
@@ -116,6 +120,28 @@ pub fn apply_landlock_rules(db_path: &Path, log_path: Option<&Path>) -> Result<(
         }
     }
 
+    // Allow reading files re-read on reload. Their directory is allowed, as
+    // editors usually replace a file rather than write to it
+    for path in reloaded {
+        let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+            continue;
+        };
+        if let Ok(dir_fd) = std::fs::File::open(dir) {
+            ruleset = match ruleset.add_rule(landlock::PathBeneath::new(
+                dir_fd,
+                AccessFs::ReadFile | AccessFs::ReadDir,
+            )) {
+                Ok(r) => r,
+                Err(e) => {
+                    return Err(SecurityError::rule_addition(
+                        format!("Failed to add landlock rule for {}: {}", dir.display(), e),
+                        Some(e),
+                    ));
+                }
+            };
+        }
+    }
+
     // Allow read access to system files that Go's runtime might need
     let system_files = [
         "/etc/protocols",
@@ -150,6 +176,35 @@ pub fn apply_landlock_rules(db_path: &Path, log_path: Option<&Path>) -> Result<(
         }
     }
 
+    // Allow execute access to conntrack, sampled for containers in learning mode
+    let conntrack_paths = [
+        "/usr/sbin/conntrack",
+        "/sbin/conntrack",
+        "/usr/bin/conntrack",
+        "/bin/conntrack",
+    ];
+
+    for conntrack_path in &conntrack_paths {
+        let path = Path::new(conntrack_path);
+        if path.exists()
+            && let Ok(conntrack_fd) = std::fs::File::open(path)
+        {
+            ruleset = match ruleset.add_rule(landlock::PathBeneath::new(
+                conntrack_fd,
+                AccessFs::ReadFile | AccessFs::Execute,
+            )) {
+                Ok(r) => r,
+                Err(e) => {
+                    return Err(SecurityError::rule_addition(
+                        format!("Failed to add landlock rule for conntrack binary: {}", e),
+                        Some(e),
+                    ));
+                }
+            };
+            break;
+        }
+    }
+
     // Allow access to shared libraries and system files needed for process execution
     let execution_paths = [
         "/lib",
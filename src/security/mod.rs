@@ -25,11 +25,17 @@ pub fn check_capabilities() -> Result<()> {
     Ok(())
 }
 
-pub fn apply_restrictions(db_path: &Path, log_path: Option<&Path>) -> Result<()> {
+/// Restrict the process, keeping `reloaded` readable so it can be re-read
+/// on reload
+pub fn apply_restrictions(
+    db_path: &Path,
+    log_path: Option<&Path>,
+    reloaded: &[&Path],
+) -> Result<()> {
     #[cfg(target_os = "linux")]
     {
         // Apply landlock restrictions
-        landlock::apply_landlock_rules(db_path, log_path)?;
+        landlock::apply_landlock_rules(db_path, log_path, reloaded)?;
 
         // Apply seccomp filters
         seccomp::apply_seccomp_filters()?;
@@ -38,7 +44,7 @@ pub fn apply_restrictions(db_path: &Path, log_path: Option<&Path>) -> Result<()>
     #[cfg(not(target_os = "linux"))]
     {
         tracing::warn!("Security restrictions are only available on Linux");
-        let _ = (db_path, log_path); // Avoid unused variable warnings
+        let _ = (db_path, log_path, reloaded); // Avoid unused variable warnings
     }
 
     Ok(())
@@ -1,16 +1,315 @@
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use serde_json::json;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::Mutex;
 use tracing::{error, info};
 
 use crate::Result;
+use crate::access::{TokenScope, Tokens};
+use crate::database::DB;
+use crate::docker::compose::COMPOSE_PROJECT_LABEL;
+use crate::listing::ListQuery;
+use crate::runtime::ContainerRuntime;
 
-pub struct HealthServer {
-    listener: TcpListener,
+/// Largest request read, headers and body together
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Window reported by `/stats` when no `since` parameter is given
+const DEFAULT_STATS_WINDOW: std::time::Duration = std::time::Duration::from_secs(86400);
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// `VMADDR_CID_ANY`, listening for every CID
+const VSOCK_CID_ANY: u32 = u32::MAX;
+
+/// Where the admin server accepts connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// `host:port`
+    Tcp(String),
+    /// `unix:/path/to/socket`
+    Unix(PathBuf),
+    /// `unix:@name`: a Linux abstract socket, which has no file and so no
+    /// file permissions; anything in the network namespace can connect, so
+    /// without tokens only root and the daemon's user may make changes
+    AbstractUnix(String),
+    /// `vsock:<port>` or `vsock:<cid>:<port>`, for a hypervisor reaching a
+    /// guest without a network; read-only without tokens
+    Vsock { cid: u32, port: u32 },
+    /// `systemd`: the first socket passed via `LISTEN_FDS`
+    Systemd,
+}
+
+impl FromStr for ListenAddr {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "systemd" {
+            Ok(Self::Systemd)
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            if let Some(name) = path.strip_prefix('@') {
+                if name.is_empty() {
+                    return Err(crate::Error::config("unix:@ listen address needs a name"));
+                }
+                return Ok(Self::AbstractUnix(name.to_string()));
+            }
+            if path.is_empty() {
+                return Err(crate::Error::config("unix: listen address needs a path"));
+            }
+            Ok(Self::Unix(PathBuf::from(path)))
+        } else if let Some(addr) = s.strip_prefix("vsock:") {
+            let invalid = || crate::Error::config(format!("invalid vsock listen address: {}", s));
+            let (cid, port) = match addr.split_once(':') {
+                Some((cid, port)) => (cid.parse().map_err(|_| invalid())?, port),
+                None => (VSOCK_CID_ANY, addr),
+            };
+            Ok(Self::Vsock {
+                cid,
+                port: port.parse().map_err(|_| invalid())?,
+            })
+        } else {
+            Ok(Self::Tcp(s.to_string()))
+        }
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::AbstractUnix(name) => write!(f, "unix:@{}", name),
+            Self::Vsock {
+                cid: VSOCK_CID_ANY,
+                port,
+            } => write!(f, "vsock:{}", port),
+            Self::Vsock { cid, port } => write!(f, "vsock:{}:{}", cid, port),
+            Self::Systemd => write!(f, "systemd"),
+        }
+    }
+}
+
+/// Endpoint groups served on the admin listener, each independently switchable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoints {
+    /// `/health` and `/ready`
+    pub health: bool,
+    /// `/metrics`
+    pub metrics: bool,
+    /// `/version`, `/about`, `/status`, `/stats`, `/enforcement` and `/check`
+    pub api: bool,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            health: true,
+            metrics: true,
+            api: true,
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+    #[cfg(target_os = "linux")]
+    Vsock(crate::host::vsock::VsockListener),
+}
+
+impl Listener {
+    pub(crate) async fn bind(addr: &ListenAddr) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                // A socket left behind by a previous run would make bind fail
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+                Ok(Self::Unix(listener))
+            }
+            ListenAddr::AbstractUnix(name) => Ok(Self::bind_abstract(name)?),
+            ListenAddr::Vsock { cid, port } => Ok(Self::bind_vsock(*cid, *port)?),
+            ListenAddr::Systemd => Ok(Self::from_systemd()?),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_abstract(name: &str) -> std::io::Result<Self> {
+        use std::os::linux::net::SocketAddrExt;
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self::Unix(UnixListener::from_std(listener)?))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_vsock(cid: u32, port: u32) -> std::io::Result<Self> {
+        Ok(Self::Vsock(crate::host::vsock::VsockListener::bind(
+            cid, port,
+        )?))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn bind_abstract(_name: &str) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "abstract unix sockets are only available on Linux",
+        ))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn bind_vsock(_cid: u32, _port: u32) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "vsock is only available on Linux",
+        ))
+    }
+
+    fn from_systemd() -> std::io::Result<Self> {
+        use std::os::fd::{FromRawFd, IntoRawFd};
+
+        let pid_matches = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pid == std::process::id());
+        let fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<i32>().ok())
+            .unwrap_or(0);
+        if !pid_matches || fds < 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "socket activation requested but no socket was passed (check LISTEN_FDS/LISTEN_PID)",
+            ));
+        }
+
+        // SAFETY: systemd hands this process ownership of the descriptors
+        // starting at SD_LISTEN_FDS_START, and nothing else has claimed them
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            return Ok(Self::Unix(UnixListener::from_std(unix)?));
+        }
+
+        // Not a unix socket, so it must be an inet one
+        let fd = unix.into_raw_fd();
+        // SAFETY: same descriptor, ownership moved out of `unix` above
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        tcp.set_nonblocking(true)?;
+        Ok(Self::Tcp(TcpListener::from_std(tcp)?))
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::Tcp(l) => l
+                .local_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_else(|_| "tcp".to_string()),
+            Self::Unix(l) => l
+                .local_addr()
+                .ok()
+                .and_then(|a| {
+                    a.as_pathname()
+                        .map(|p| format!("unix:{}", p.display()))
+                        .or_else(|| abstract_name(&a.into()).map(|name| format!("unix:@{}", name)))
+                })
+                .unwrap_or_else(|| "unix socket".to_string()),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(l) => l.describe(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_name(addr: &std::os::unix::net::SocketAddr) -> Option<String> {
+    use std::os::linux::net::SocketAddrExt;
+    addr.as_abstract_name()
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_name(_addr: &std::os::unix::net::SocketAddr) -> Option<String> {
+    None
+}
+
+/// How a connection reached the admin listener, which decides whether it
+/// may make changes when no tokens are configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Peer {
+    /// Through a socket file, guarded by its permissions
+    SocketFile,
+    /// Through an abstract socket, from the user `SO_PEERCRED` reports
+    Abstract(Option<u32>),
+    /// Over TCP or vsock
+    Network,
+}
+
+impl Peer {
+    pub(crate) fn of_unix(stream: &tokio::net::UnixStream) -> Self {
+        if stream
+            .local_addr()
+            .is_ok_and(|addr| addr.as_pathname().is_some())
+        {
+            return Self::SocketFile;
+        }
+        Self::Abstract(stream.peer_cred().ok().map(|cred| cred.uid()))
+    }
+
+    /// Whether the peer may make changes without a token: over a socket
+    /// file, or as root or the daemon's own user over an abstract socket
+    pub(crate) fn trusted(self) -> bool {
+        match self {
+            Self::SocketFile => true,
+            Self::Abstract(Some(uid)) => uid == 0 || uid == crate::datadir::euid(),
+            Self::Abstract(None) | Self::Network => false,
+        }
+    }
+}
+
+/// Refuses a change from a peer that isn't trusted when no tokens are
+/// configured, such as Prometheus scraping over TCP
+fn refuse_untrusted_change(method: &str, context: &ServerContext) -> Option<Response> {
+    if method == "GET" || context.tokens.is_some() || context.peer.trusted() {
+        return None;
+    }
+    Some(Response::text(
+        403,
+        "Forbidden",
+        "changes over this listener need --api-tokens; without them only a unix socket file, \
+         or an abstract socket from root, may make changes",
+    ))
+}
+
+#[derive(Clone)]
+struct ServerContext {
     prometheus_handle: PrometheusHandle,
     start_time: chrono::DateTime<chrono::Utc>,
     version: String,
+    db: Option<Arc<Mutex<DB>>>,
+    endpoints: Endpoints,
+    /// Required on `/enforcement` requests when set
+    tokens: Option<Arc<Tokens>>,
+    docker: Option<Arc<dyn ContainerRuntime>>,
+    host_addrs: Option<Arc<crate::host::HostAddrs>>,
+    /// Set for each connection
+    peer: Peer,
+}
+
+/// Admin HTTP server for health checks, metrics and the REST API
+pub struct HealthServer {
+    listener: Listener,
+    context: ServerContext,
 }
 
 impl HealthServer {
@@ -19,182 +318,921 @@ impl HealthServer {
         prometheus_handle: PrometheusHandle,
         version: String,
     ) -> Result<Self> {
-        let listener = TcpListener::bind(bind_addr).await?;
-        let bind_addr = listener.local_addr()?;
+        let listener = Listener::bind(&bind_addr.parse()?).await?;
 
-        info!("Health check server will bind to {}", bind_addr);
+        info!("Health check server will bind to {}", listener.describe());
 
         Ok(Self {
             listener,
-            prometheus_handle,
-            start_time: chrono::Utc::now(),
-            version,
+            context: ServerContext {
+                prometheus_handle,
+                start_time: chrono::Utc::now(),
+                version,
+                db: None,
+                endpoints: Endpoints::default(),
+                tokens: None,
+                docker: None,
+                host_addrs: None,
+                peer: Peer::Network,
+            },
         })
     }
 
+    /// Serve `/stats` from the rollup tables of `db`
+    pub fn with_db(mut self, db: Arc<Mutex<DB>>) -> Self {
+        self.context.db = Some(db);
+        self
+    }
+
+    /// Restrict which endpoint groups are served
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.context.endpoints = endpoints;
+        self
+    }
+
+    /// Require one of `tokens` on the enforcement endpoint, limited to the
+    /// containers and modes it is scoped to
+    pub fn with_tokens(mut self, tokens: Tokens) -> Self {
+        self.context.tokens = Some(Arc::new(tokens));
+        self
+    }
+
+    /// Look up containers in `docker` to match tokens scoped to compose
+    /// projects
+    pub fn with_docker(mut self, docker: Arc<dyn ContainerRuntime>) -> Self {
+        self.context.docker = Some(docker);
+        self
+    }
+
+    /// Resolve `host` in rules checked by `POST /check` to `host_addrs`
+    pub fn with_host_addrs(mut self, host_addrs: Arc<crate::host::HostAddrs>) -> Self {
+        self.context.host_addrs = Some(host_addrs);
+        self
+    }
+
     pub async fn serve(self) -> Result<()> {
         info!(
             "Starting health check server on {}",
-            self.listener.local_addr()?
+            self.listener.describe()
         );
 
         loop {
-            match self.listener.accept().await {
-                Ok((stream, _)) => {
-                    let prometheus_handle = self.prometheus_handle.clone();
-                    let start_time = self.start_time;
-                    let version = self.version.clone();
-
-                    tokio::spawn(async move {
-                        if let Err(e) =
-                            handle_connection(stream, prometheus_handle, start_time, version).await
-                        {
-                            error!("Error handling connection: {}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    error!("Error accepting connection: {}", e);
-                }
+            let accepted = match &self.listener {
+                Listener::Tcp(l) => l.accept().await.map(|(stream, _)| {
+                    let context = self.context.clone();
+                    tokio::spawn(async move { handle_connection(stream, context).await })
+                }),
+                Listener::Unix(l) => l.accept().await.map(|(stream, _)| {
+                    let mut context = self.context.clone();
+                    context.peer = Peer::of_unix(&stream);
+                    tokio::spawn(async move { handle_connection(stream, context).await })
+                }),
+                #[cfg(target_os = "linux")]
+                Listener::Vsock(l) => l.accept().await.map(|stream| {
+                    let context = self.context.clone();
+                    tokio::spawn(async move { handle_connection(stream, context).await })
+                }),
+            };
+
+            if let Err(e) = accepted {
+                error!("Error accepting connection: {}", e);
             }
         }
     }
 
+    /// Bound address, for TCP listeners
     pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
-        self.listener.local_addr()
+        match &self.listener {
+            Listener::Tcp(l) => l.local_addr(),
+            Listener::Unix(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "admin server listens on a unix socket",
+            )),
+            #[cfg(target_os = "linux")]
+            Listener::Vsock(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "admin server listens on vsock",
+            )),
+        }
     }
 }
 
-async fn handle_connection(
-    mut stream: TcpStream,
-    prometheus_handle: PrometheusHandle,
-    start_time: chrono::DateTime<chrono::Utc>,
-    version: String,
-) -> Result<()> {
-    let mut buffer = [0; 1024];
-    let n = stream.read(&mut buffer).await?;
+struct Response {
+    status_code: u16,
+    status_text: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status_code: u16, status_text: &'static str, value: &serde_json::Value) -> Self {
+        Self {
+            status_code,
+            status_text,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    fn text(status_code: u16, status_text: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status_code,
+            status_text,
+            content_type: "text/plain",
+            body: body.into(),
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::text(404, "Not Found", "Not Found")
+    }
+}
+
+async fn handle_connection<S>(mut stream: S, context: ServerContext)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Err(e) = serve_request(&mut stream, &context).await {
+        error!("Error handling connection: {}", e);
+    }
+}
+
+async fn serve_request<S>(stream: &mut S, context: &ServerContext) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0; 1024];
+    let mut n = stream.read(&mut buffer).await?;
+    // Read the rest of a body announced by Content-Length
+    while let Some(expected) = expected_length(&buffer[..n])
+        && n < expected.min(MAX_REQUEST_SIZE)
+    {
+        if buffer.len() == n {
+            buffer.resize((n * 2).min(MAX_REQUEST_SIZE), 0);
+        }
+        let read = stream.read(&mut buffer[n..]).await?;
+        if read == 0 {
+            break;
+        }
+        n += read;
+    }
     let request = String::from_utf8_lossy(&buffer[..n]);
+    let body = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
 
     // Parse the HTTP request line
     let first_line = request.lines().next().unwrap_or("");
     let parts: Vec<&str> = first_line.split_whitespace().collect();
 
-    if parts.len() < 2 {
-        send_response(&mut stream, 400, "Bad Request", "text/plain", "Bad Request").await?;
-        return Ok(());
+    let authorization = request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("authorization")
+                .then(|| value.trim())
+        });
+
+    let response = if parts.len() < 2 {
+        Response::text(400, "Bad Request", "Bad Request")
+    } else {
+        route(parts[0], parts[1], authorization, body, context).await
+    };
+
+    send_response(stream, &response).await
+}
+
+/// Size of the whole request, headers included, once its headers are in
+fn expected_length(request: &[u8]) -> Option<usize> {
+    let end = request.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let headers = String::from_utf8_lossy(&request[..end]);
+    let length = headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("content-length")
+            .then(|| value.trim().parse::<usize>().ok())?
+    })?;
+    Some(end + length)
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        pair.split_once('=')
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    })
+}
+
+async fn route(
+    method: &str,
+    target: &str,
+    authorization: Option<&str>,
+    body: &str,
+    context: &ServerContext,
+) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let endpoints = context.endpoints;
+
+    if endpoints.api && path == "/check" {
+        return route_check(method, authorization, body, context).await;
     }
 
-    let path = parts[1];
+    if endpoints.api
+        && let Some(response) = route_enforcement(method, path, query, authorization, context).await
+    {
+        return response;
+    }
+
+    if endpoints.api
+        && let Some(response) = route_bans(method, path, authorization, body, context).await
+    {
+        return response;
+    }
 
     match path {
-        "/health" => {
+        "/health" if endpoints.health => {
             let response = json!({
                 "status": "healthy",
                 "timestamp": chrono::Utc::now().to_rfc3339()
             });
-            send_json_response(&mut stream, 200, "OK", &response).await?;
+            Response::json(200, "OK", &response)
         }
-        "/ready" => {
+        "/ready" if endpoints.health => {
             let response = json!({
                 "status": "ready",
                 "timestamp": chrono::Utc::now().to_rfc3339(),
-                "uptime_seconds": (chrono::Utc::now() - start_time).num_seconds()
+                "uptime_seconds": (chrono::Utc::now() - context.start_time).num_seconds()
             });
-            send_json_response(&mut stream, 200, "OK", &response).await?;
+            Response::json(200, "OK", &response)
+        }
+        ready if endpoints.health && ready.starts_with("/ready/") => {
+            let container = &ready["/ready/".len()..];
+            match crate::handlers::readiness::lookup(container) {
+                Some(ready) => Response::json(200, "OK", &json!(ready)),
+                None => Response::json(
+                    503,
+                    "Service Unavailable",
+                    &json!({ "container": container, "ready": false }),
+                ),
+            }
         }
-        "/metrics" => {
-            let metrics = prometheus_handle.render();
-            send_response(&mut stream, 200, "OK", "text/plain", &metrics).await?;
+        "/metrics" if endpoints.metrics => {
+            Response::text(200, "OK", context.prometheus_handle.render())
         }
-        "/version" => {
+        "/version" if endpoints.api => {
             let response = json!({
-                "version": version,
+                "version": context.version,
                 "build_time": option_env!("BUILD_TIME").unwrap_or("unknown"),
                 "git_commit": option_env!("GIT_COMMIT").unwrap_or("unknown"),
                 "rust_version": option_env!("RUST_VERSION").unwrap_or("unknown")
             });
-            send_json_response(&mut stream, 200, "OK", &response).await?;
+            Response::json(200, "OK", &response)
         }
-        "/status" => {
-            let uptime = chrono::Utc::now() - start_time;
+        "/about" if endpoints.api => Response::json(
+            200,
+            "OK",
+            &json!(crate::about::collect(&context.version).await),
+        ),
+        "/schema/events" if endpoints.api => Response::json(200, "OK", &crate::events::schema()),
+        "/status" if endpoints.api => {
+            let uptime = chrono::Utc::now() - context.start_time;
             let response = json!({
                 "status": "running",
-                "version": version,
+                "version": context.version,
                 "uptime_seconds": uptime.num_seconds(),
-                "start_time": start_time.to_rfc3339(),
-                "timestamp": chrono::Utc::now().to_rfc3339()
+                "start_time": context.start_time.to_rfc3339(),
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "nftables": crate::nftables::runner::watchdog().status(),
+                "update": crate::update::status(),
+                "rootless": crate::docker::rootless::status(),
+                "restart_loops": crate::handlers::restarts::status(),
+                "endpoints": crate::runtime::fleet::status(),
+                "leader": crate::handlers::leader::status(),
+            });
+            Response::json(200, "OK", &response)
+        }
+        "/stats" if endpoints.api => {
+            let Some(db) = &context.db else {
+                return Response::not_found();
+            };
+
+            let now = chrono::Utc::now().timestamp();
+            let list = match ListQuery::parse(query, now) {
+                Ok(list) => list,
+                Err(e) => return Response::text(400, "Bad Request", e),
+            };
+            let window = list.since.map_or(DEFAULT_STATS_WINDOW, |since| {
+                std::time::Duration::from_secs(now.saturating_sub(since).max(0) as u64)
             });
-            send_json_response(&mut stream, 200, "OK", &response).await?;
+
+            let db = db.lock().await;
+            match crate::database::stats::stats_since(&db, window, now).await {
+                Ok(mut rows) => {
+                    rows.retain(|row| {
+                        list.matches(&row.container_name, || {
+                            compose_project(&row.container_name, context)
+                        })
+                    });
+                    let total = list.paginate(&mut rows);
+                    let report = crate::database::stats::StatsReport {
+                        since_seconds: window.as_secs(),
+                        containers: rows,
+                    };
+                    Response::json(200, "OK", &list.to_json(&report, "containers", total))
+                }
+                Err(e) => Response::json(
+                    500,
+                    "Internal Server Error",
+                    &json!({ "error": e.to_string() }),
+                ),
+            }
         }
-        _ => {
-            send_response(&mut stream, 404, "Not Found", "text/plain", "Not Found").await?;
+        _ => Response::not_found(),
+    }
+}
+
+/// The compose project of a tracked container, found by name or identity
+fn compose_project(container_name: &str, context: &ServerContext) -> Option<String> {
+    let tracker = context.docker.as_ref()?.container_tracker();
+    tracker
+        .find_container(container_name)
+        .or_else(|| tracker.find_by_identity(container_name).into_iter().next())
+        .and_then(|container| container.labels.get(COMPOSE_PROJECT_LABEL).cloned())
+}
+
+/// Whether `scope` covers `container_name`, looking up its compose project
+/// when the token names projects
+fn in_scope(scope: &TokenScope, container_name: &str, context: &ServerContext) -> bool {
+    let project = if scope.projects.is_empty() {
+        None
+    } else {
+        compose_project(container_name, context)
+    };
+    scope.covers(container_name, project.as_deref())
+}
+
+/// `POST /check` with a [`crate::check::Flow`] answers whether the live
+/// policy allows it. With tokens configured, the token must cover the
+/// container
+async fn route_check(
+    method: &str,
+    authorization: Option<&str>,
+    body: &str,
+    context: &ServerContext,
+) -> Response {
+    let (Some(db), Some(docker)) = (&context.db, &context.docker) else {
+        return Response::not_found();
+    };
+    if method != "POST" {
+        return Response::text(405, "Method Not Allowed", "Method Not Allowed");
+    }
+    let flow: crate::check::Flow = match serde_json::from_str(body) {
+        Ok(flow) => flow,
+        Err(e) => return Response::text(400, "Bad Request", format!("invalid flow: {}", e)),
+    };
+    if let Err(e) = flow.validate() {
+        return Response::text(400, "Bad Request", e);
+    }
+    if let Some(tokens) = &context.tokens {
+        let Some(scope) = tokens.authorize(authorization) else {
+            return Response::text(401, "Unauthorized", "Unauthorized");
+        };
+        if !in_scope(scope, &flow.container, context) {
+            return Response::text(
+                403,
+                "Forbidden",
+                format!(
+                    "token {} does not cover container {}",
+                    scope.name, flow.container
+                ),
+            );
         }
     }
 
-    Ok(())
+    let host_addrs = context
+        .host_addrs
+        .as_ref()
+        .map(|addrs| addrs.get())
+        .unwrap_or_default();
+    let db = db.lock().await;
+    match crate::check::check(&db, docker.as_ref(), &host_addrs, &flow).await {
+        Some(result) => Response::json(200, "OK", &json!(result)),
+        None => Response::json(
+            404,
+            "Not Found",
+            &json!({ "error": format!("container {} is not tracked", flow.container) }),
+        ),
+    }
 }
 
-async fn send_response(
-    stream: &mut TcpStream,
-    status_code: u16,
-    status_text: &str,
-    content_type: &str,
+/// `GET /enforcement` lists overrides, filtered and paged as described in
+/// [`crate::listing`], `PUT /enforcement/<name>?mode=<mode>`
+/// stores one for the daemon to apply, recording it in the audit log. With
+/// tokens configured, both only reach the containers the caller's token
+/// covers; without, changes need a trusted [`Peer`]
+async fn route_enforcement(
+    method: &str,
+    path: &str,
+    query: &str,
+    authorization: Option<&str>,
+    context: &ServerContext,
+) -> Option<Response> {
+    use crate::database::{EnforcementMode, enforcement};
+
+    let rest = path.strip_prefix("/enforcement")?;
+    let Some(db) = &context.db else {
+        return Some(Response::not_found());
+    };
+    if let Some(refused) = refuse_untrusted_change(method, context) {
+        return Some(refused);
+    }
+    let scope = match &context.tokens {
+        Some(tokens) => match tokens.authorize(authorization) {
+            Some(scope) => Some(scope),
+            None => return Some(Response::text(401, "Unauthorized", "Unauthorized")),
+        },
+        None => None,
+    };
+    let forbidden = |reason: String| Response::text(403, "Forbidden", reason);
+    let mut db = db.lock().await;
+    let failed = |e: crate::Error| {
+        Response::json(
+            500,
+            "Internal Server Error",
+            &json!({ "error": e.to_string() }),
+        )
+    };
+
+    let response = match (method, rest.strip_prefix('/')) {
+        ("GET", None) if rest.is_empty() => {
+            let list = match ListQuery::parse(query, chrono::Utc::now().timestamp()) {
+                Ok(list) => list,
+                Err(e) => return Some(Response::text(400, "Bad Request", e)),
+            };
+            match enforcement::list_overrides(&db).await {
+                Ok(mut containers) => {
+                    containers.retain(|c| {
+                        scope.is_none_or(|scope| in_scope(scope, &c.container_name, context))
+                            && list.since.is_none_or(|since| c.updated_at >= since)
+                            && list.matches(&c.container_name, || {
+                                compose_project(&c.container_name, context)
+                            })
+                    });
+                    let total = list.paginate(&mut containers);
+                    Response::json(
+                        200,
+                        "OK",
+                        &list.to_json(
+                            &enforcement::EnforcementReport { containers },
+                            "containers",
+                            total,
+                        ),
+                    )
+                }
+                Err(e) => failed(e),
+            }
+        }
+        ("PUT" | "POST", Some(name)) if !name.is_empty() && !name.contains('/') => {
+            let mode = match query_param(query, "mode").map(str::parse::<EnforcementMode>) {
+                Some(Ok(mode)) => mode,
+                Some(Err(e)) => return Some(Response::text(400, "Bad Request", e)),
+                None => return Some(Response::text(400, "Bad Request", "missing mode")),
+            };
+            if let Some(scope) = scope {
+                if !in_scope(scope, name, context) {
+                    return Some(forbidden(format!(
+                        "token {} does not cover container {}",
+                        scope.name, name
+                    )));
+                }
+                if !scope.allows_mode(mode) {
+                    return Some(forbidden(format!(
+                        "token {} may not set mode {}",
+                        scope.name,
+                        mode.as_str()
+                    )));
+                }
+                let ports = context
+                    .docker
+                    .as_ref()
+                    .and_then(|docker| docker.container_tracker().find_container(name))
+                    .map(|container| container.ports);
+                if let Err(reason) = scope.check_opening(mode, ports.as_deref()) {
+                    return Some(forbidden(reason));
+                }
+            }
+            if mode != EnforcementMode::Quarantined {
+                match crate::database::freeze::current(&db, chrono::Utc::now().timestamp()).await {
+                    Ok(None) => {}
+                    Ok(Some(_)) => {
+                        return Some(Response::text(
+                            409,
+                            "Conflict",
+                            "rule changes are frozen; only quarantining is allowed",
+                        ));
+                    }
+                    Err(e) => return Some(failed(e)),
+                }
+            }
+            // Overrides are kept under the container's identity, so they
+            // outlive a recreated container
+            let identity = context
+                .docker
+                .as_ref()
+                .and_then(|docker| docker.container_tracker().find_container(name))
+                .map_or_else(|| name.to_string(), |container| container.identity());
+            let by = scope.map_or_else(
+                || "the admin API".to_string(),
+                |scope| format!("token {}", scope.name),
+            );
+            match enforcement::change_mode(&mut db, &identity, mode, &by).await {
+                Ok(()) => Response::json(
+                    200,
+                    "OK",
+                    &json!({ "container_name": identity, "mode": mode }),
+                ),
+                Err(e) => failed(e),
+            }
+        }
+        _ => Response::not_found(),
+    };
+    Some(response)
+}
+
+/// A ban posted to `/bans`
+#[derive(Debug, serde::Deserialize)]
+struct BanRequest {
+    ip: std::net::Ipv4Addr,
+    /// Such as "1h"; an hour unless given
+    #[serde(default)]
+    duration: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// `GET /bans` lists the running bans, `POST /bans` with a [`BanRequest`]
+/// stores one for the daemon to apply and `DELETE /bans/<ip>` lifts one; see
+/// [`crate::database::bans`]. With tokens configured, only tokens covering
+/// every container may change them, as bans reach them all
+async fn route_bans(
+    method: &str,
+    path: &str,
+    authorization: Option<&str>,
     body: &str,
-) -> Result<()> {
+    context: &ServerContext,
+) -> Option<Response> {
+    use crate::database::bans;
+
+    let rest = path.strip_prefix("/bans")?;
+    let Some(db) = &context.db else {
+        return Some(Response::not_found());
+    };
+    if let Some(refused) = refuse_untrusted_change(method, context) {
+        return Some(refused);
+    }
+    let scope = match &context.tokens {
+        Some(tokens) => match tokens.authorize(authorization) {
+            Some(scope) => Some(scope),
+            None => return Some(Response::text(401, "Unauthorized", "Unauthorized")),
+        },
+        None => None,
+    };
+    if method != "GET"
+        && let Some(scope) = scope.filter(|scope| !scope.unrestricted())
+    {
+        return Some(Response::text(
+            403,
+            "Forbidden",
+            format!("token {} may not change bans", scope.name),
+        ));
+    }
+    let failed = |e: crate::Error| {
+        Response::json(
+            500,
+            "Internal Server Error",
+            &json!({ "error": e.to_string() }),
+        )
+    };
+    let mut db = db.lock().await;
+
+    let response = match (method, rest.strip_prefix('/')) {
+        ("GET", None) if rest.is_empty() => {
+            let now = chrono::Utc::now().timestamp();
+            match bans::list(&db).await {
+                Ok(mut list) => {
+                    list.retain(|ban| ban.expires_at > now);
+                    Response::json(200, "OK", &json!(bans::BanReport { bans: list }))
+                }
+                Err(e) => failed(e),
+            }
+        }
+        ("POST", None) if rest.is_empty() => {
+            let request: BanRequest = match serde_json::from_str(body) {
+                Ok(request) => request,
+                Err(e) => {
+                    return Some(Response::text(
+                        400,
+                        "Bad Request",
+                        format!("invalid ban: {}", e),
+                    ));
+                }
+            };
+            let duration = match request.duration.as_deref().map(crate::parse_duration) {
+                None => std::time::Duration::from_secs(3600),
+                Some(Ok(duration)) if !duration.is_zero() => duration,
+                Some(Ok(_)) => {
+                    return Some(Response::text(
+                        400,
+                        "Bad Request",
+                        "duration must be longer than zero",
+                    ));
+                }
+                Some(Err(e)) => return Some(Response::text(400, "Bad Request", e)),
+            };
+            match bans::ban(&mut db, request.ip, duration, request.reason.as_deref()).await {
+                Ok(ban) => Response::json(200, "OK", &json!(ban)),
+                Err(e) => failed(e),
+            }
+        }
+        ("DELETE", Some(ip)) => {
+            let Ok(ip) = ip.parse::<std::net::Ipv4Addr>() else {
+                return Some(Response::text(
+                    400,
+                    "Bad Request",
+                    format!("not an IPv4 address: {:?}", ip),
+                ));
+            };
+            match bans::unban(&mut db, ip, None).await {
+                Ok(true) => Response::json(200, "OK", &json!({ "ip": ip, "banned": false })),
+                Ok(false) => Response::json(
+                    404,
+                    "Not Found",
+                    &json!({ "error": format!("{} is not banned", ip) }),
+                ),
+                Err(e) => failed(e),
+            }
+        }
+        _ => Response::not_found(),
+    };
+    Some(response)
+}
+
+async fn send_response<S>(stream: &mut S, response: &Response) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
     let response = format!(
         "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        status_code,
-        status_text,
-        content_type,
-        body.len(),
-        body
+        response.status_code,
+        response.status_text,
+        response.content_type,
+        response.body.len(),
+        response.body
     );
     stream.write_all(response.as_bytes()).await?;
     stream.flush().await?;
     Ok(())
 }
 
-async fn send_json_response(
-    stream: &mut TcpStream,
-    status_code: u16,
-    status_text: &str,
-    json_value: &serde_json::Value,
-) -> Result<()> {
-    let body = json_value.to_string();
-    send_response(stream, status_code, status_text, "application/json", &body).await
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    /// Exported as a Prometheus summary with quantiles
+    Histogram,
 }
 
+/// One exported metric
+#[derive(Debug, Clone, Copy)]
+pub struct MetricSpec {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub help: &'static str,
+    pub labels: &'static [&'static str],
+    /// Dashboard row the metric is shown in
+    pub group: &'static str,
+}
+
+/// Every metric the daemon exports.
+///
+/// Names follow the Prometheus conventions: a `harborshield_` prefix,
+/// `_total` for counters and a base unit suffix such as `_seconds`. Dashboards
+/// and alerts are built on these names and labels, so existing entries are
+/// not renamed; new metrics are added here and get a dashboard panel from
+/// `harborshield export dashboard`.
+pub const METRICS: &[MetricSpec] = &[
+    MetricSpec {
+        name: "harborshield_rules_applied_total",
+        kind: MetricKind::Counter,
+        help: "Total number of firewall rules applied",
+        labels: &[],
+        group: "rules",
+    },
+    MetricSpec {
+        name: "harborshield_containers_tracked_total",
+        kind: MetricKind::Counter,
+        help: "Total number of containers being tracked",
+        labels: &[],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_errors_total",
+        kind: MetricKind::Counter,
+        help: "Total number of errors encountered",
+        labels: &[],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_endpoint_up",
+        kind: MetricKind::Gauge,
+        help: "Whether a Docker endpoint besides the primary one answered when last asked",
+        labels: &["endpoint"],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_active_containers",
+        kind: MetricKind::Gauge,
+        help: "Number of currently active containers",
+        labels: &[],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_active_rules",
+        kind: MetricKind::Gauge,
+        help: "Number of currently active firewall rules",
+        labels: &[],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_rule_apply_duration_seconds",
+        kind: MetricKind::Histogram,
+        help: "Time taken to apply firewall rules",
+        labels: &[],
+        group: "rules",
+    },
+    MetricSpec {
+        name: "harborshield_nft_timeouts_total",
+        kind: MetricKind::Counter,
+        help: "Total number of nft invocations killed after exceeding the timeout",
+        labels: &["operation"],
+        group: "nftables",
+    },
+    MetricSpec {
+        name: "harborshield_nft_stuck_transactions",
+        kind: MetricKind::Gauge,
+        help: "Number of timed-out nft transactions waiting to be re-applied",
+        labels: &[],
+        group: "nftables",
+    },
+    MetricSpec {
+        name: "harborshield_self_heals_total",
+        kind: MetricKind::Counter,
+        help: "Rules reinstalled after being removed or changed by someone else",
+        labels: &["scope"],
+        group: "nftables",
+    },
+    MetricSpec {
+        name: "harborshield_event_queue_depth",
+        kind: MetricKind::Gauge,
+        help: "Docker events queued and waiting to be handled",
+        labels: &[],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_event_backpressure_total",
+        kind: MetricKind::Counter,
+        help: "Times the event reader found the queue full and had to wait",
+        labels: &[],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_events_shed_total",
+        kind: MetricKind::Counter,
+        help: "Docker events dropped because the queue stayed full",
+        labels: &[],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_event_resyncs_total",
+        kind: MetricKind::Counter,
+        help: "Resyncs run to recover from shed events, by scope",
+        labels: &["scope"],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_event_lag_resyncs_total",
+        kind: MetricKind::Counter,
+        help: "Backlogs of stale Docker events dropped for a full resync",
+        labels: &[],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_docker_reconnects_total",
+        kind: MetricKind::Counter,
+        help: "Times the Docker event stream was lost and reopened",
+        labels: &[],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_pipeline_stage_depth",
+        kind: MetricKind::Gauge,
+        help: "Items currently in each event pipeline stage",
+        labels: &["stage"],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_pipeline_stage_duration_seconds",
+        kind: MetricKind::Histogram,
+        help: "Time spent in each event pipeline stage",
+        labels: &["stage"],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_pipeline_stage_errors_total",
+        kind: MetricKind::Counter,
+        help: "Failures in each event pipeline stage",
+        labels: &["stage"],
+        group: "events",
+    },
+    MetricSpec {
+        name: "harborshield_rdns_lookup_failures_total",
+        kind: MetricKind::Counter,
+        help: "rdns verifications whose DNS lookups failed, by failure policy",
+        labels: &["policy"],
+        group: "dns",
+    },
+    MetricSpec {
+        name: "harborshield_rdns_failing_chains",
+        kind: MetricKind::Gauge,
+        help: "Chains whose rdns lookups are currently failing",
+        labels: &[],
+        group: "dns",
+    },
+    MetricSpec {
+        name: "harborshield_subnet_violations_total",
+        kind: MetricKind::Counter,
+        help: "Rule applications refused because a container was outside its expected subnet",
+        labels: &["container"],
+        group: "rules",
+    },
+    MetricSpec {
+        name: "harborshield_quarantines_total",
+        kind: MetricKind::Counter,
+        help: "Containers quarantined for exceeding their drop threshold",
+        labels: &["container"],
+        group: "rules",
+    },
+    MetricSpec {
+        name: "harborshield_dropped_packets_total",
+        kind: MetricKind::Counter,
+        help: "Packets dropped by container chains, from the NFLOG group given with --nflog-group",
+        labels: &["container"],
+        group: "rules",
+    },
+    MetricSpec {
+        name: "harborshield_restart_loops",
+        kind: MetricKind::Gauge,
+        help: "Containers in a restart loop, whose rule churn is damped",
+        labels: &[],
+        group: "rules",
+    },
+    MetricSpec {
+        name: "harborshield_leader",
+        kind: MetricKind::Gauge,
+        help: "1 while this host holds the --ha-lock and enforces, 0 on standby",
+        labels: &[],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_update_available",
+        kind: MetricKind::Gauge,
+        help: "1 when the signed release manifest lists a newer version, 2 when that is a security release",
+        labels: &[],
+        group: "overview",
+    },
+    MetricSpec {
+        name: "harborshield_event_bus_dropped_total",
+        kind: MetricKind::Counter,
+        help: "Events not published because the event bus queue was full",
+        labels: &[],
+        group: "events",
+    },
+];
+
 pub fn setup_metrics() -> Result<PrometheusHandle> {
     let builder = PrometheusBuilder::new();
     let handle = builder
         .install_recorder()
         .map_err(|e| crate::Error::metrics(format!("Failed to setup metrics: {}", e)))?;
 
-    // Register some custom metrics
-    metrics::describe_counter!(
-        "harborshield_rules_applied_total",
-        "Total number of firewall rules applied"
-    );
-    metrics::describe_counter!(
-        "harborshield_containers_tracked_total",
-        "Total number of containers being tracked"
-    );
-    metrics::describe_counter!(
-        "harborshield_errors_total",
-        "Total number of errors encountered"
-    );
-    metrics::describe_gauge!(
-        "harborshield_active_containers",
-        "Number of currently active containers"
-    );
-    metrics::describe_gauge!(
-        "harborshield_active_rules",
-        "Number of currently active firewall rules"
-    );
-    metrics::describe_histogram!(
-        "harborshield_rule_apply_duration_seconds",
-        "Time taken to apply firewall rules"
-    );
+    for spec in METRICS {
+        match spec.kind {
+            MetricKind::Counter => metrics::describe_counter!(spec.name, spec.help),
+            MetricKind::Gauge => metrics::describe_gauge!(spec.name, spec.help),
+            MetricKind::Histogram => metrics::describe_histogram!(spec.name, spec.help),
+        }
+    }
 
     Ok(handle)
 }
@@ -223,3 +1261,449 @@ pub fn set_active_rules(count: u64) {
 pub fn record_rule_apply_duration(duration: std::time::Duration) {
     metrics::histogram!("harborshield_rule_apply_duration_seconds").record(duration.as_secs_f64());
 }
+
+pub fn increment_nft_timeouts(operation: &str) {
+    metrics::counter!("harborshield_nft_timeouts_total", "operation" => operation.to_string())
+        .increment(1);
+}
+
+pub fn set_nft_stuck_transactions(count: u64) {
+    metrics::gauge!("harborshield_nft_stuck_transactions").set(count as f64);
+}
+
+pub fn increment_self_heals(scope: &'static str) {
+    metrics::counter!("harborshield_self_heals_total", "scope" => scope).increment(1);
+}
+
+pub fn set_event_queue_depth(depth: u64) {
+    metrics::gauge!("harborshield_event_queue_depth").set(depth as f64);
+}
+
+pub fn increment_event_backpressure() {
+    metrics::counter!("harborshield_event_backpressure_total").increment(1);
+}
+
+pub fn increment_events_shed() {
+    metrics::counter!("harborshield_events_shed_total").increment(1);
+}
+
+pub fn increment_event_resyncs(scope: &str) {
+    metrics::counter!("harborshield_event_resyncs_total", "scope" => scope.to_string())
+        .increment(1);
+}
+
+pub fn increment_event_lag_resyncs() {
+    metrics::counter!("harborshield_event_lag_resyncs_total").increment(1);
+}
+
+pub fn increment_docker_reconnects() {
+    metrics::counter!("harborshield_docker_reconnects_total").increment(1);
+}
+
+pub fn set_stage_depth(stage: &'static str, depth: u64) {
+    metrics::gauge!("harborshield_pipeline_stage_depth", "stage" => stage).set(depth as f64);
+}
+
+pub fn record_stage_duration(stage: &'static str, duration: std::time::Duration) {
+    metrics::histogram!("harborshield_pipeline_stage_duration_seconds", "stage" => stage)
+        .record(duration.as_secs_f64());
+}
+
+pub fn increment_stage_errors(stage: &'static str) {
+    metrics::counter!("harborshield_pipeline_stage_errors_total", "stage" => stage).increment(1);
+}
+
+pub fn increment_rdns_lookup_failures(policy: &'static str) {
+    metrics::counter!("harborshield_rdns_lookup_failures_total", "policy" => policy).increment(1);
+}
+
+pub fn set_rdns_failing_chains(count: u64) {
+    metrics::gauge!("harborshield_rdns_failing_chains").set(count as f64);
+}
+
+pub fn increment_subnet_violations(container: &str) {
+    metrics::counter!("harborshield_subnet_violations_total", "container" => container.to_string())
+        .increment(1);
+}
+
+pub fn increment_quarantines(container: &str) {
+    metrics::counter!("harborshield_quarantines_total", "container" => container.to_string())
+        .increment(1);
+}
+
+pub fn increment_dropped_packets(container: &str) {
+    metrics::counter!("harborshield_dropped_packets_total", "container" => container.to_string())
+        .increment(1);
+}
+
+pub fn set_restart_loops(count: u64) {
+    metrics::gauge!("harborshield_restart_loops").set(count as f64);
+}
+
+pub fn set_endpoint_up(endpoint: &str, up: bool) {
+    metrics::gauge!("harborshield_endpoint_up", "endpoint" => endpoint.to_string()).set(if up {
+        1.0
+    } else {
+        0.0
+    });
+}
+
+pub fn set_leader(active: bool) {
+    metrics::gauge!("harborshield_leader").set(if active { 1.0 } else { 0.0 });
+}
+
+pub fn set_update_available(level: u8) {
+    metrics::gauge!("harborshield_update_available").set(level as f64);
+}
+
+pub fn increment_event_bus_dropped() {
+    metrics::counter!("harborshield_event_bus_dropped_total").increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp("127.0.0.1:8080".to_string())
+        );
+        assert_eq!(
+            "unix:/run/hs.sock".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Unix(PathBuf::from("/run/hs.sock"))
+        );
+        assert_eq!(
+            "systemd".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Systemd
+        );
+        assert!("unix:".parse::<ListenAddr>().is_err());
+        assert_eq!(
+            "unix:@harborshield".parse::<ListenAddr>().unwrap(),
+            ListenAddr::AbstractUnix("harborshield".to_string())
+        );
+        assert!("unix:@".parse::<ListenAddr>().is_err());
+        for addr in ["vsock:5000", "vsock:3:5000"] {
+            assert_eq!(addr.parse::<ListenAddr>().unwrap().to_string(), addr);
+        }
+        assert_eq!(
+            "vsock:5000".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Vsock {
+                cid: VSOCK_CID_ANY,
+                port: 5000
+            }
+        );
+        assert!("vsock:host:5000".parse::<ListenAddr>().is_err());
+    }
+
+    #[test]
+    fn test_expected_length() {
+        assert_eq!(expected_length(b"GET /health HTTP/1.1\r\n"), None);
+        assert_eq!(expected_length(b"GET /health HTTP/1.1\r\n\r\n"), None);
+        let post = b"POST /check HTTP/1.1\r\ncontent-length: 12\r\n\r\n{";
+        assert_eq!(expected_length(post), Some(post.len() - 1 + 12));
+    }
+
+    #[test]
+    fn test_metric_catalog() {
+        for spec in METRICS {
+            assert!(spec.name.starts_with("harborshield_"), "{}", spec.name);
+            match spec.kind {
+                MetricKind::Counter => assert!(spec.name.ends_with("_total"), "{}", spec.name),
+                MetricKind::Histogram => {
+                    assert!(spec.name.ends_with("_seconds"), "{}", spec.name)
+                }
+                MetricKind::Gauge => assert!(!spec.name.ends_with("_total"), "{}", spec.name),
+            }
+        }
+
+        // Every helper emits a catalogued metric with its catalogued labels
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            increment_rules_applied();
+            increment_containers_tracked();
+            increment_errors();
+            set_active_containers(1);
+            set_active_rules(1);
+            record_rule_apply_duration(std::time::Duration::from_millis(5));
+            increment_nft_timeouts("apply");
+            set_nft_stuck_transactions(1);
+            increment_self_heals("full");
+            set_event_queue_depth(1);
+            increment_event_backpressure();
+            increment_events_shed();
+            increment_event_resyncs("full");
+            increment_event_lag_resyncs();
+            increment_docker_reconnects();
+            set_stage_depth("apply", 1);
+            record_stage_duration("apply", std::time::Duration::from_millis(5));
+            increment_stage_errors("apply");
+            increment_rdns_lookup_failures("closed");
+            set_rdns_failing_chains(1);
+            increment_subnet_violations("web");
+            increment_quarantines("web");
+            increment_dropped_packets("web");
+            set_restart_loops(1);
+            set_endpoint_up("box2", true);
+            set_leader(true);
+            set_update_available(1);
+            increment_event_bus_dropped();
+        });
+        let rendered = handle.render();
+        for spec in METRICS {
+            let sample = rendered
+                .lines()
+                .find(|line| {
+                    line.strip_prefix(spec.name)
+                        .is_some_and(|rest| rest.starts_with(['{', ' ']))
+                })
+                .unwrap_or_else(|| panic!("{} not emitted", spec.name));
+            for label in spec.labels {
+                assert!(sample.contains(&format!("{}=", label)), "{}", sample);
+            }
+        }
+        for line in rendered.lines().filter(|line| line.starts_with("# TYPE")) {
+            let name = line.split_whitespace().nth(2).unwrap();
+            assert!(
+                METRICS.iter().any(|spec| spec.name == name),
+                "{} is not in METRICS",
+                name
+            );
+        }
+    }
+
+    async fn get(path: &std::path::Path, target: &str) -> String {
+        let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    async fn send(path: &std::path::Path, request: &str) -> String {
+        let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_scoped_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("admin.sock");
+        let digest = ring::digest::digest(&ring::digest::SHA256, b"bot-secret");
+        let hex: String = digest
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let tokens_path = dir.path().join("tokens.yaml");
+        std::fs::write(
+            &tokens_path,
+            format!(
+                "tokens:\n  - name: bot\n    sha256: {}\n    containers: [\"web-*\"]\n    modes: [enforce, permissive]\n",
+                hex
+            ),
+        )
+        .unwrap();
+        let db = DB::builder()
+            .db_path(&dir.path().join("db.sqlite"))
+            .build()
+            .await
+            .unwrap();
+
+        let server = HealthServer::new(
+            &format!("unix:{}", socket.display()),
+            PrometheusBuilder::new().build_recorder().handle(),
+            "test".to_string(),
+        )
+        .await
+        .unwrap()
+        .with_db(Arc::new(Mutex::new(db)))
+        .with_tokens(crate::access::load(&tokens_path).unwrap());
+        tokio::spawn(server.serve());
+
+        let put = |target: &str, token: &str| {
+            format!(
+                "PUT {} HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+                target, token
+            )
+        };
+        assert!(
+            send(&socket, "GET /enforcement HTTP/1.1\r\n\r\n")
+                .await
+                .starts_with("HTTP/1.1 401")
+        );
+        assert!(
+            send(&socket, &put("/enforcement/web-1?mode=permissive", "wrong"))
+                .await
+                .starts_with("HTTP/1.1 401")
+        );
+        assert!(
+            send(
+                &socket,
+                &put("/enforcement/db?mode=permissive", "bot-secret")
+            )
+            .await
+            .starts_with("HTTP/1.1 403")
+        );
+        assert!(
+            send(
+                &socket,
+                &put("/enforcement/web-1?mode=disabled", "bot-secret")
+            )
+            .await
+            .starts_with("HTTP/1.1 403")
+        );
+        assert!(
+            send(
+                &socket,
+                &put("/enforcement/web-1?mode=permissive", "bot-secret")
+            )
+            .await
+            .starts_with("HTTP/1.1 200")
+        );
+        // Bans reach every container, beyond the token's
+        let ban = "POST /bans HTTP/1.1\r\nAuthorization: Bearer bot-secret\r\nContent-Length: 21\r\n\r\n{\"ip\": \"203.0.113.7\"}";
+        assert!(send(&socket, ban).await.starts_with("HTTP/1.1 403"));
+        // The health endpoints stay open
+        assert!(get(&socket, "/health").await.starts_with("HTTP/1.1 200"));
+        assert!(
+            get(&socket, "/ready/not-started")
+                .await
+                .starts_with("HTTP/1.1 503")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes_without_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::builder()
+            .db_path(&dir.path().join("db.sqlite"))
+            .build()
+            .await
+            .unwrap();
+        let db = Arc::new(Mutex::new(db));
+        let new_server = |addr: String| {
+            let db = db.clone();
+            async move {
+                HealthServer::new(
+                    &addr,
+                    PrometheusBuilder::new().build_recorder().handle(),
+                    "test".to_string(),
+                )
+                .await
+                .unwrap()
+                .with_db(db)
+            }
+        };
+        let put = "PUT /enforcement/web?mode=disabled HTTP/1.1\r\n\r\n";
+
+        // A TCP listener, say one Prometheus scrapes, only reads
+        let tcp = new_server("127.0.0.1:0".to_string()).await;
+        let addr = tcp.local_addr().unwrap();
+        tokio::spawn(tcp.serve());
+        let send_tcp = |request: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        assert!(send_tcp(put).await.starts_with("HTTP/1.1 403"));
+        let ban = "POST /bans HTTP/1.1\r\nContent-Length: 21\r\n\r\n{\"ip\": \"203.0.113.7\"}";
+        assert!(send_tcp(ban).await.starts_with("HTTP/1.1 403"));
+        assert!(
+            send_tcp("GET /enforcement HTTP/1.1\r\n\r\n")
+                .await
+                .starts_with("HTTP/1.1 200")
+        );
+
+        // A socket file's permissions guard it
+        let socket = dir.path().join("admin.sock");
+        let unix = new_server(format!("unix:{}", socket.display())).await;
+        tokio::spawn(unix.serve());
+        assert!(send(&socket, put).await.starts_with("HTTP/1.1 200"));
+
+        let entries = crate::database::audit::list_since(&*db.lock().await, 0)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].kind,
+            crate::database::audit::KIND_ENFORCEMENT_CHANGED
+        );
+        assert_eq!(
+            entries[0].detail,
+            "enforcement mode set to disabled by the admin API"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("harborshield-test-{}", std::process::id());
+        let server = HealthServer::new(
+            &format!("unix:@{}", name),
+            PrometheusBuilder::new().build_recorder().handle(),
+            "test".to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(server.listener.describe(), format!("unix:@{}", name));
+        tokio::spawn(server.serve());
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let stream = std::os::unix::net::UnixStream::connect_addr(&addr).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut stream = tokio::net::UnixStream::from_std(stream).unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_routing() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("admin.sock");
+        let handle = PrometheusBuilder::new().build_recorder().handle();
+
+        let server = HealthServer::new(
+            &format!("unix:{}", socket.display()),
+            handle,
+            "test".to_string(),
+        )
+        .await
+        .unwrap()
+        .with_endpoints(Endpoints {
+            metrics: false,
+            ..Default::default()
+        });
+        tokio::spawn(server.serve());
+
+        assert!(get(&socket, "/health").await.starts_with("HTTP/1.1 200"));
+        assert!(
+            get(&socket, "/version")
+                .await
+                .contains("\"version\":\"test\"")
+        );
+        assert!(get(&socket, "/metrics").await.starts_with("HTTP/1.1 404"));
+        assert!(
+            get(&socket, "/about")
+                .await
+                .contains("\"name\":\"nftables\"")
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Per-container ruleset summary behind `harborshield status`, and with
+//! `--counters` the hits of every rule.
+//!
+//! Every rule in a container chain carries a counter. Rules are mapped back
+//! to their container through the chain name and to the rule set entry
+//! they were built from through their comment. Counters start from zero
+//! whenever a chain is rebuilt, so an allow rule without hits has matched
+//! nothing since the container's rules last changed.
+
+use crate::database::AuditEntry;
+use crate::nftables::counters::RuleCounter;
+use crate::nftables::tproxy::Interception;
+use crate::output::{Cell, Color, Column, Render, Table, human_bytes};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleHits {
+    pub container_name: String,
+    pub chain: String,
+    /// Position in the chain, from 1
+    pub position: usize,
+    /// What the rule was built from, such as `Output rule 2`
+    pub rule: String,
+    pub verdict: Option<&'static str>,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Lets traffic through, unlike drops and the chain's terminal rule
+    pub allow: bool,
+    /// An allow rule that has matched nothing
+    pub unused: bool,
+}
+
+/// The rule a comment describes, without the container it ends in
+fn origin(comment: Option<&str>, position: usize) -> String {
+    let Some(comment) = comment else {
+        return format!("rule {}", position);
+    };
+    match comment.rsplit_once(" for ") {
+        Some((rule, _)) => rule.trim_end_matches(" container").to_string(),
+        None => comment.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CountersReport {
+    pub rules: Vec<RuleHits>,
+}
+
+impl CountersReport {
+    /// Rules by container and chain order. Chains are shown by container
+    /// name where `names` knows it.
+    pub fn new(counters: Vec<RuleCounter>, names: &HashMap<String, String>) -> Self {
+        let mut last: HashMap<&str, usize> = HashMap::new();
+        for counter in &counters {
+            let position = last.entry(&counter.chain).or_default();
+            *position = (*position).max(counter.position);
+        }
+
+        let mut rules: Vec<RuleHits> = counters
+            .iter()
+            .map(|counter| {
+                let container_name = names
+                    .get(&counter.chain)
+                    .cloned()
+                    .unwrap_or_else(|| counter.chain.clone());
+                // The last rule is the enforcement mode's terminal rule
+                let terminal = last.get(counter.chain.as_str()) == Some(&counter.position);
+                let allow = !terminal && counter.verdict.is_some_and(|v| v != "drop");
+                RuleHits {
+                    rule: origin(counter.comment.as_deref(), counter.position),
+                    container_name,
+                    chain: counter.chain.clone(),
+                    position: counter.position,
+                    verdict: counter.verdict,
+                    packets: counter.packets,
+                    bytes: counter.bytes,
+                    allow,
+                    unused: allow && counter.packets == 0,
+                }
+            })
+            .collect();
+        rules.sort_by(|a, b| {
+            a.container_name
+                .cmp(&b.container_name)
+                .then_with(|| a.chain.cmp(&b.chain))
+                .then(a.position.cmp(&b.position))
+        });
+        Self { rules }
+    }
+
+    pub fn unused(&self) -> usize {
+        self.rules.iter().filter(|r| r.unused).count()
+    }
+}
+
+impl Render for CountersReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::right("#"),
+            Column::left("RULE"),
+            Column::left("VERDICT"),
+            Column::right("PACKETS"),
+            Column::right("BYTES"),
+            Column::left("CHAIN").wide(),
+        ]);
+        for rule in &self.rules {
+            let packets = if rule.unused {
+                Cell::colored("0 (unused)", Color::Yellow)
+            } else {
+                rule.packets.to_string().into()
+            };
+            table.row(vec![
+                rule.container_name.clone().into(),
+                rule.position.to_string().into(),
+                rule.rule.clone().into(),
+                rule.verdict.unwrap_or("-").into(),
+                packets,
+                human_bytes(rule.bytes).into(),
+                rule.chain.clone().into(),
+            ]);
+        }
+        if self.rules.is_empty() {
+            table.footer(Cell::colored("no container chains installed", Color::Dim));
+        } else if self.unused() > 0 {
+            table.footer(format!(
+                "{} allow rule(s) matched nothing since their chain was last built",
+                self.unused()
+            ));
+        }
+        table
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusRow {
+    pub container_name: String,
+    pub chain: String,
+    pub rules: usize,
+    pub accept_packets: u64,
+    pub drop_packets: u64,
+    pub unused_rules: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub containers: Vec<StatusRow>,
+    /// TPROXY interceptions in place, whose marks need routing to the host
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub interceptions: Vec<Interception>,
+    /// Allow rules recently found letting flows through uncounted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suspect_rules: Vec<AuditEntry>,
+}
+
+impl From<&CountersReport> for StatusReport {
+    fn from(counters: &CountersReport) -> Self {
+        let mut chains: BTreeMap<(&str, &str), StatusRow> = BTreeMap::new();
+        for rule in &counters.rules {
+            let row = chains
+                .entry((&rule.container_name, &rule.chain))
+                .or_insert_with(|| StatusRow {
+                    container_name: rule.container_name.clone(),
+                    chain: rule.chain.clone(),
+                    rules: 0,
+                    accept_packets: 0,
+                    drop_packets: 0,
+                    unused_rules: 0,
+                });
+            row.rules += 1;
+            row.unused_rules += rule.unused as usize;
+            match rule.verdict {
+                Some("drop") => row.drop_packets += rule.packets,
+                Some(_) => row.accept_packets += rule.packets,
+                None => {}
+            }
+        }
+        Self {
+            containers: chains.into_values().collect(),
+            interceptions: Vec::new(),
+            suspect_rules: Vec::new(),
+        }
+    }
+}
+
+impl Render for StatusReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::right("RULES"),
+            Column::right("ACCEPTED"),
+            Column::right("DROPPED"),
+            Column::right("UNUSED"),
+            Column::left("CHAIN").wide(),
+        ]);
+        for row in &self.containers {
+            let unused = if row.unused_rules > 0 {
+                Cell::colored(row.unused_rules.to_string(), Color::Yellow)
+            } else {
+                row.unused_rules.to_string().into()
+            };
+            table.row(vec![
+                row.container_name.clone().into(),
+                row.rules.to_string().into(),
+                row.accept_packets.to_string().into(),
+                row.drop_packets.to_string().into(),
+                unused,
+                row.chain.clone().into(),
+            ]);
+        }
+        if self.containers.is_empty() {
+            table.footer(Cell::colored("no container chains installed", Color::Dim));
+        } else if self.containers.iter().any(|row| row.unused_rules > 0) {
+            table.footer("run with --counters to list the unused rules");
+        }
+        let mut routes: Vec<String> = Vec::new();
+        for interception in &self.interceptions {
+            table.footer(format!(
+                "{} of {} is intercepted on port {} with mark {}",
+                interception.rule,
+                interception.container_name,
+                interception.port,
+                interception.mark
+            ));
+            for route in interception.routes() {
+                if !routes.contains(&route) {
+                    routes.push(route);
+                }
+            }
+        }
+        if !routes.is_empty() {
+            table.footer(format!("intercepted flows need: {}", routes.join("; ")));
+        }
+        for suspect in &self.suspect_rules {
+            table.footer(Cell::colored(
+                format!(
+                    "{}: {}",
+                    suspect.container_name.as_deref().unwrap_or("-"),
+                    suspect.detail
+                ),
+                Color::Yellow,
+            ));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nftables::counters::parse_rule_counters;
+    use serde_json::json;
+
+    #[test]
+    fn test_counters_report() {
+        let output = json!({
+            "nftables": [
+                {"rule": {"chain": "hs-web-abc", "comment": "Output rule 1 for web", "expr": [
+                    {"counter": {"packets": 12, "bytes": 1200}}, {"accept": null}
+                ]}},
+                {"rule": {"chain": "hs-web-abc", "comment": "Output rules 2, 3 for web", "expr": [
+                    {"counter": {"packets": 0, "bytes": 0}}, {"accept": null}
+                ]}},
+                {"rule": {"chain": "hs-web-abc", "expr": [
+                    {"counter": {"packets": 0, "bytes": 0}}, {"set": {"op": "add"}}
+                ]}},
+                {"rule": {"chain": "hs-web-abc", "comment": "Default DROP for container web", "expr": [
+                    {"counter": {"packets": 3, "bytes": 180}}, {"drop": null}
+                ]}},
+                {"rule": {"chain": "hs-db-def", "comment": "Enforcement disabled for container db", "expr": [
+                    {"counter": {"packets": 0, "bytes": 0}}, {"accept": null}
+                ]}}
+            ]
+        });
+        let names = HashMap::from([("hs-web-abc".to_string(), "web".to_string())]);
+
+        let report = CountersReport::new(parse_rule_counters(&output), &names);
+        let rules: Vec<(&str, &str, bool)> = report
+            .rules
+            .iter()
+            .map(|r| (r.container_name.as_str(), r.rule.as_str(), r.unused))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("hs-db-def", "Enforcement disabled", false),
+                ("web", "Output rule 1", false),
+                ("web", "Output rules 2, 3", true),
+                ("web", "rule 3", false),
+                ("web", "Default DROP", false),
+            ]
+        );
+
+        let status = StatusReport::from(&report);
+        let web = &status.containers[1];
+        assert_eq!(
+            (
+                web.rules,
+                web.accept_packets,
+                web.drop_packets,
+                web.unused_rules
+            ),
+            (4, 12, 3, 1)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! systemd service integration over `sd_notify`, compiled in with the
+//! `systemd` feature.
+//!
+//! Under a `Type=notify` unit, harborshield sends `READY=1` once the initial
+//! ruleset is installed, so units ordered after it start with containers
+//! already firewalled. With `WatchdogSec=` set it also pings the watchdog,
+//! but only while the Docker event loop is healthy: the event stream is
+//! connected and no event has been in handling for longer than the watchdog
+//! timeout. A stalled loop therefore gets the service restarted.
+//!
+//! Without the feature, or outside systemd, every call here does nothing.
+
+use crate::handlers::pipeline;
+use std::io;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Whether this binary was built with the `systemd` feature
+pub const COMPILED: bool = cfg!(feature = "systemd");
+
+/// The notification socket named by `NOTIFY_SOCKET`, which starts with `@`
+/// for an abstract one
+fn socket_addr(socket: &str) -> io::Result<SocketAddr> {
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "abstract unix sockets are only available on Linux",
+        )),
+        None => SocketAddr::from_pathname(socket),
+    }
+}
+
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    let addr = socket_addr(socket)?;
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Send `state` to the service manager, returning whether there was one to
+/// tell
+pub fn notify(state: &str) -> io::Result<bool> {
+    if !COMPILED {
+        return Ok(false);
+    }
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    send(&socket.to_string_lossy(), state)?;
+    Ok(true)
+}
+
+/// Report that the initial ruleset is installed
+pub fn ready() {
+    match notify("READY=1\nSTATUS=Firewalling containers") {
+        Ok(true) => debug!("Notified systemd of readiness"),
+        Ok(false) => {}
+        Err(e) => warn!("Failed to notify systemd of readiness: {}", e),
+    }
+}
+
+/// Report that shutdown has begun
+pub fn stopping() {
+    if let Err(e) = notify("STOPPING=1") {
+        debug!("Failed to notify systemd of shutdown: {}", e);
+    }
+}
+
+/// The watchdog timeout from `WATCHDOG_USEC`, when it is meant for this
+/// process
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// The watchdog timeout systemd expects pings within, if it is enabled
+pub fn watchdog_timeout() -> Option<Duration> {
+    if !COMPILED {
+        return None;
+    }
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// Ping the watchdog at half its timeout while the event loop is healthy,
+/// until cancelled
+pub async fn run_watchdog(timeout: Duration, cancellation: CancellationToken) {
+    let mut ticker = tokio::time::interval(timeout / 2);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = cancellation.cancelled() => break,
+            _ = ticker.tick() => {
+                if !pipeline::event_loop_healthy(chrono::Utc::now().timestamp(), timeout) {
+                    warn!("Docker event loop is unhealthy; withholding the systemd watchdog ping");
+                    continue;
+                }
+                if let Err(e) = notify("WATCHDOG=1") {
+                    warn!("Failed to ping the systemd watchdog: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        assert_eq!(
+            parse_watchdog(Some("30000000"), None, 7),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog(Some("30000000"), Some("7"), 7),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_watchdog(Some("30000000"), Some("8"), 7), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 7), None);
+        assert_eq!(parse_watchdog(None, None, 7), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let manager = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let len = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+}
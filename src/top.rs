@@ -0,0 +1,229 @@
+//! Live per-container traffic view behind `harborshield top`.
+//!
+//! Chain counters are read every refresh and turned into rates against the
+//! previous reading. Docker events are followed alongside, so a container
+//! starting or a rule set being rebuilt shows up next to the rates it
+//! changes. The view is redrawn in place with plain ANSI escapes and the
+//! shared [`Table`] renderer.
+
+use crate::nftables::counters::ChainCounters;
+use crate::output::{Cell, Color, Column, Render, Table, human_bytes};
+use bollard::models::EventMessage;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Docker events kept for the view, newest last
+pub const RECENT_EVENTS: usize = 8;
+
+/// Moves the cursor home and clears the screen
+pub const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopRow {
+    pub container_name: String,
+    pub drop_pps: f64,
+    pub accept_pps: f64,
+    pub drop_bps: f64,
+    pub accept_bps: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentEvent {
+    /// Unix seconds
+    pub ts: i64,
+    pub action: String,
+    pub container_name: String,
+}
+
+impl RecentEvent {
+    pub fn from_event(event: &EventMessage) -> Option<Self> {
+        let actor = event.actor.as_ref()?;
+        let name = actor
+            .attributes
+            .as_ref()
+            .and_then(|attrs| attrs.get("name"))
+            .cloned()
+            .or_else(|| {
+                actor
+                    .id
+                    .as_ref()
+                    .map(|id| id[..12.min(id.len())].to_string())
+            })?;
+        Some(Self {
+            ts: event.time.unwrap_or_else(|| chrono::Utc::now().timestamp()),
+            action: event.action.clone()?,
+            container_name: name,
+        })
+    }
+}
+
+/// Last events, bounded to [`RECENT_EVENTS`]
+#[derive(Debug, Default)]
+pub struct EventLog {
+    events: VecDeque<RecentEvent>,
+}
+
+impl EventLog {
+    pub fn push(&mut self, event: RecentEvent) {
+        if self.events.len() == RECENT_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn snapshot(&self) -> Vec<RecentEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopView {
+    pub interval_seconds: f64,
+    pub containers: Vec<TopRow>,
+    pub events: Vec<RecentEvent>,
+}
+
+impl TopView {
+    /// Rates from counter increments over `elapsed`, busiest droppers first.
+    /// Chains are shown by container name where `names` knows it.
+    pub fn new(
+        deltas: &HashMap<String, ChainCounters>,
+        names: &HashMap<String, String>,
+        elapsed: Duration,
+        events: Vec<RecentEvent>,
+    ) -> Self {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut containers: Vec<TopRow> = deltas
+            .iter()
+            .map(|(chain, delta)| TopRow {
+                container_name: names.get(chain).cloned().unwrap_or_else(|| chain.clone()),
+                drop_pps: delta.drop_packets as f64 / secs,
+                accept_pps: delta.accept_packets as f64 / secs,
+                drop_bps: delta.drop_bytes as f64 / secs,
+                accept_bps: delta.accept_bytes as f64 / secs,
+            })
+            .collect();
+        containers.sort_by(|a, b| {
+            b.drop_pps
+                .total_cmp(&a.drop_pps)
+                .then(b.accept_pps.total_cmp(&a.accept_pps))
+                .then_with(|| a.container_name.cmp(&b.container_name))
+        });
+
+        Self {
+            interval_seconds: secs,
+            containers,
+            events,
+        }
+    }
+}
+
+fn rate(per_sec: f64) -> String {
+    if per_sec >= 10.0 || per_sec == 0.0 {
+        format!("{:.0}/s", per_sec)
+    } else {
+        format!("{:.1}/s", per_sec)
+    }
+}
+
+impl Render for TopView {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("CONTAINER"),
+            Column::right("DROP"),
+            Column::right("ACCEPT"),
+            Column::right("DROP BYTES").wide(),
+            Column::right("ACCEPT BYTES").wide(),
+        ]);
+
+        for row in &self.containers {
+            let drops = if row.drop_pps > 0.0 {
+                Cell::colored(rate(row.drop_pps), Color::Red)
+            } else {
+                Cell::colored(rate(row.drop_pps), Color::Dim)
+            };
+            table.row(vec![
+                row.container_name.clone().into(),
+                drops,
+                rate(row.accept_pps).into(),
+                format!("{}/s", human_bytes(row.drop_bps as u64)).into(),
+                format!("{}/s", human_bytes(row.accept_bps as u64)).into(),
+            ]);
+        }
+
+        if self.containers.is_empty() {
+            table.footer(Cell::colored(
+                "no traffic through container chains",
+                Color::Dim,
+            ));
+        }
+        if !self.events.is_empty() {
+            table.footer("recent events:");
+            for event in self.events.iter().rev() {
+                let time = chrono::DateTime::from_timestamp(event.ts, 0).map_or_else(
+                    || event.ts.to_string(),
+                    |t| t.format("%H:%M:%S").to_string(),
+                );
+                table.footer(format!(
+                    "  {}  {:<10} {}",
+                    time, event.action, event.container_name
+                ));
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_view_rates() {
+        let deltas = HashMap::from([
+            (
+                "hs-web-abc".to_string(),
+                ChainCounters {
+                    drop_packets: 0,
+                    drop_bytes: 0,
+                    accept_packets: 40,
+                    accept_bytes: 4000,
+                },
+            ),
+            (
+                "hs-db-def".to_string(),
+                ChainCounters {
+                    drop_packets: 4,
+                    drop_bytes: 240,
+                    accept_packets: 0,
+                    accept_bytes: 0,
+                },
+            ),
+        ]);
+        let names = HashMap::from([("hs-web-abc".to_string(), "web".to_string())]);
+
+        let mut log = EventLog::default();
+        for i in 0..RECENT_EVENTS + 2 {
+            log.push(RecentEvent {
+                ts: i as i64,
+                action: "start".to_string(),
+                container_name: format!("c{}", i),
+            });
+        }
+        let events = log.snapshot();
+        assert_eq!(events.len(), RECENT_EVENTS);
+        assert_eq!(events[0].container_name, "c2");
+
+        let view = TopView::new(&deltas, &names, Duration::from_secs(2), events);
+        // Chains dropping traffic come first
+        assert_eq!(view.containers[0].container_name, "hs-db-def");
+        assert_eq!(view.containers[0].drop_pps, 2.0);
+        assert_eq!(view.containers[1].container_name, "web");
+        assert_eq!(view.containers[1].accept_bps, 2000.0);
+
+        let out = view.table().render(false, false);
+        assert!(out.contains("20/s"));
+        assert!(out.contains("recent events:"));
+    }
+}
@@ -0,0 +1,317 @@
+//! Opt-in check for new releases, enabled with `--check-updates`.
+//!
+//! The checker fetches a release manifest and its detached signature,
+//! verifies the signature against the release key before reading anything
+//! from the manifest, and compares the listed version with the running one.
+//! A newer release is reported in `GET /status`, the
+//! `harborshield_update_available` gauge, the log and, when configured, a
+//! webhook. Nothing is ever downloaded or installed.
+//!
+//! The manifest is JSON:
+//!
+//! ```json
+//! { "version": "0.2.0", "security": true, "url": "https://…/releases/v0.2.0" }
+//! ```
+//!
+//! and `<manifest url>.sig` holds the base64 of its raw Ed25519 signature.
+//! Release builds embed the public key from `HARBORSHIELD_RELEASE_KEY`;
+//! `--update-key` overrides it for self-hosted manifests.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::RwLock;
+use std::time::Duration;
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::offline::{self, Fetch};
+
+pub const DEFAULT_MANIFEST_URL: &str =
+    "https://github.com/rymskip/harborshield/releases/latest/download/release.json";
+
+/// Base64 Ed25519 public key releases are signed with, set at build time
+pub const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("HARBORSHIELD_RELEASE_KEY");
+
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time allowed for each manifest, signature or webhook request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+const PUBLIC_KEY_LEN: usize = 32;
+
+static STATUS: RwLock<Option<UpdateStatus>> = RwLock::new(None);
+
+#[derive(Error, Debug)]
+pub enum UpdateError {
+    #[error("{0}")]
+    Offline(String),
+
+    #[error("Invalid release key: {0}")]
+    InvalidKey(String),
+
+    #[error("Failed to fetch {url}: {reason}")]
+    Fetch { url: String, reason: String },
+
+    #[error("Release manifest signature does not verify")]
+    BadSignature,
+
+    #[error("Invalid release manifest: {0}")]
+    InvalidManifest(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ReleaseManifest {
+    pub version: String,
+    /// The release fixes a security issue
+    #[serde(default)]
+    pub security: bool,
+    /// Release notes
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateCheck {
+    pub manifest_url: String,
+    pub public_key: Vec<u8>,
+    pub interval: Duration,
+    /// URL a JSON notice is POSTed to once per newly seen release
+    pub webhook: Option<String>,
+}
+
+/// Outcome of the latest check, served in `GET /status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpdateStatus {
+    pub current: String,
+    pub latest: Option<String>,
+    pub update_available: bool,
+    pub security: bool,
+    pub release_url: Option<String>,
+    /// Unix seconds
+    pub checked_at: i64,
+    /// Why the latest check failed; `latest` is then from an earlier one
+    pub error: Option<String>,
+}
+
+/// The latest check's outcome, if the checker has run
+pub fn status() -> Option<UpdateStatus> {
+    STATUS.read().ok().and_then(|status| status.clone())
+}
+
+fn set_status(status: UpdateStatus) {
+    if let Ok(mut current) = STATUS.write() {
+        *current = Some(status);
+    }
+}
+
+/// The release key given as base64, or the one embedded at build time
+pub fn parse_public_key(text: Option<&str>) -> Result<Vec<u8>, UpdateError> {
+    let text = text.or(RELEASE_PUBLIC_KEY).ok_or_else(|| {
+        UpdateError::InvalidKey("this build embeds no release key; pass --update-key".to_string())
+    })?;
+    let key = STANDARD
+        .decode(text.trim())
+        .map_err(|e| UpdateError::InvalidKey(format!("not base64: {}", e)))?;
+    if key.len() != PUBLIC_KEY_LEN {
+        return Err(UpdateError::InvalidKey(format!(
+            "expected {} bytes, got {}",
+            PUBLIC_KEY_LEN,
+            key.len()
+        )));
+    }
+    Ok(key)
+}
+
+/// Parse `manifest` once `signature` (base64) verifies under `public_key`
+pub fn verify_manifest(
+    manifest: &[u8],
+    signature: &str,
+    public_key: &[u8],
+) -> Result<ReleaseManifest, UpdateError> {
+    let signature = STANDARD
+        .decode(signature.trim())
+        .map_err(|_| UpdateError::BadSignature)?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(manifest, &signature)
+        .map_err(|_| UpdateError::BadSignature)?;
+    serde_json::from_slice(manifest).map_err(|e| UpdateError::InvalidManifest(e.to_string()))
+}
+
+/// Numeric `major.minor.patch` and the pre-release suffix, if any
+fn version_parts(version: &str) -> (Vec<u64>, Option<&str>) {
+    let version = version.trim().trim_start_matches('v');
+    let (release, pre) = match version.split_once('-') {
+        Some((release, pre)) => (release, Some(pre)),
+        None => (version, None),
+    };
+    let numbers = release
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect();
+    (numbers, pre)
+}
+
+/// Order two versions; a pre-release sorts before its release
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_numbers, a_pre) = version_parts(a);
+    let (b_numbers, b_pre) = version_parts(b);
+    a_numbers.cmp(&b_numbers).then(match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => a.cmp(b),
+    })
+}
+
+async fn get_text(client: &reqwest::Client, url: &str) -> Result<String, UpdateError> {
+    let fetch_error = |e: reqwest::Error| UpdateError::Fetch {
+        url: url.to_string(),
+        reason: e.to_string(),
+    };
+    client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(fetch_error)?
+        .text()
+        .await
+        .map_err(fetch_error)
+}
+
+/// Fetch and verify the release manifest
+pub async fn fetch_manifest(check: &UpdateCheck) -> Result<ReleaseManifest, UpdateError> {
+    offline::guard(Fetch::UpdateCheck).map_err(UpdateError::Offline)?;
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| UpdateError::Fetch {
+            url: check.manifest_url.clone(),
+            reason: e.to_string(),
+        })?;
+    let manifest = get_text(&client, &check.manifest_url).await?;
+    let signature = get_text(&client, &format!("{}.sig", check.manifest_url)).await?;
+    verify_manifest(manifest.as_bytes(), &signature, &check.public_key)
+}
+
+/// Status for `manifest` as seen by `current`
+pub fn evaluate(current: &str, manifest: &ReleaseManifest, checked_at: i64) -> UpdateStatus {
+    let update_available = compare_versions(&manifest.version, current) == Ordering::Greater;
+    UpdateStatus {
+        current: current.to_string(),
+        latest: Some(manifest.version.clone()),
+        update_available,
+        security: update_available && manifest.security,
+        release_url: manifest.url.clone(),
+        checked_at,
+        error: None,
+    }
+}
+
+async fn notify(url: &str, status: &UpdateStatus) -> reqwest::Result<()> {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?
+        .post(url)
+        .json(&crate::events::envelope(
+            crate::events::EVENT_UPDATE_AVAILABLE,
+            &serde_json::json!({ "update": status }),
+        ))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Check for updates every `check.interval` until cancelled
+pub async fn run_checker(check: UpdateCheck, current: String, cancellation: CancellationToken) {
+    let mut notified: Option<String> = None;
+
+    loop {
+        let now = chrono::Utc::now().timestamp();
+        let status = match fetch_manifest(&check).await {
+            Ok(manifest) => evaluate(&current, &manifest, now),
+            Err(e) => {
+                warn!("Update check failed: {}", e);
+                let previous = status();
+                UpdateStatus {
+                    current: current.clone(),
+                    latest: previous.as_ref().and_then(|s| s.latest.clone()),
+                    update_available: previous.as_ref().is_some_and(|s| s.update_available),
+                    security: previous.as_ref().is_some_and(|s| s.security),
+                    release_url: previous.and_then(|s| s.release_url),
+                    checked_at: now,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+
+        crate::server::set_update_available(match (status.update_available, status.security) {
+            (false, _) => 0,
+            (true, false) => 1,
+            (true, true) => 2,
+        });
+        if status.update_available && status.error.is_none() && notified != status.latest {
+            let latest = status.latest.as_deref().unwrap_or_default();
+            if status.security {
+                warn!(
+                    "Security update available: {} (running {})",
+                    latest, current
+                );
+            } else {
+                info!("Update available: {} (running {})", latest, current);
+            }
+            if let Some(url) = &check.webhook
+                && let Err(e) = notify(url, &status).await
+            {
+                warn!("Failed to post update notice to {}: {}", url, e);
+            }
+            notified = status.latest.clone();
+        }
+        set_status(status);
+
+        tokio::select! {
+            _ = cancellation.cancelled() => break,
+            _ = tokio::time::sleep(check.interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn test_signed_manifest() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = STANDARD.encode(pair.public_key().as_ref());
+        let public_key = parse_public_key(Some(&public_key)).unwrap();
+
+        let manifest = br#"{"version": "0.2.0", "security": true}"#;
+        let signature = STANDARD.encode(pair.sign(manifest).as_ref());
+        let parsed = verify_manifest(manifest, &signature, &public_key).unwrap();
+        assert_eq!(parsed.version, "0.2.0");
+
+        let tampered = br#"{"version": "9.0.0", "security": true}"#;
+        assert!(matches!(
+            verify_manifest(tampered, &signature, &public_key),
+            Err(UpdateError::BadSignature)
+        ));
+        assert!(parse_public_key(Some("c2hvcnQ=")).is_err());
+
+        let status = evaluate("0.1.0", &parsed, 100);
+        assert!(status.update_available && status.security);
+        assert!(!evaluate("0.2.0", &parsed, 100).update_available);
+
+        assert_eq!(compare_versions("0.2.0", "0.2.0-rc.1"), Ordering::Greater);
+        assert_eq!(compare_versions("v0.10.0", "0.9.3"), Ordering::Greater);
+        assert_eq!(compare_versions("0.1.0", "0.1.0"), Ordering::Equal);
+    }
+}
@@ -0,0 +1,445 @@
+//! Offline checks of rules for `harborshield validate`, meant to run in CI
+//! before a deploy.
+//!
+//! A document is either a rule set in the label format or a compose file,
+//! told apart by a top-level `services` mapping. Rule sets are parsed the
+//! way the daemon parses a container's rules label, so schema errors and
+//! invalid addresses or ports are reported as the daemon would reject them.
+//! Unknown keys, which the daemon silently ignores, are reported too, and
+//! in a compose file `container` references must name one of its services.
+
+use crate::docker::compose::ComposeInfo;
+use crate::docker::config::profiles::{self, EXTENDS_KEY};
+use crate::docker::labels::{self, ALIASES_KEY, RULES_KEY};
+use crate::output::{Column, Render, Table};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// The daemon would reject the rules
+    Schema,
+    /// A key the daemon ignores, usually a typo
+    UnknownKey,
+    /// A `container` no service answers to
+    Reference,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Schema => "schema",
+            Kind::UnknownKey => "unknown_key",
+            Kind::Reference => "reference",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    /// The compose service, or `rules` for a plain rule set
+    pub source: String,
+    pub kind: Kind,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidateReport {
+    /// Rule sets checked
+    pub rule_sets: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl ValidateReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Render for ValidateReport {
+    fn table(&self) -> Table {
+        let mut table = Table::new(vec![
+            Column::left("SOURCE"),
+            Column::left("PROBLEM"),
+            Column::left("DETAIL"),
+        ]);
+        for problem in &self.problems {
+            table.row(vec![
+                problem.source.clone().into(),
+                problem.kind.as_str().into(),
+                problem.detail.clone().into(),
+            ]);
+        }
+        if self.problems.is_empty() {
+            table.footer(format!("{} rule set(s) valid", self.rule_sets));
+        }
+        table
+    }
+}
+
+/// Keys a part of the rule set accepts
+enum Shape {
+    /// Not checked further
+    Any,
+    Map(&'static [(&'static str, Shape)]),
+    List(&'static Shape),
+}
+
+const VERDICT: Shape = Shape::Map(&[
+    ("chain", Shape::Any),
+    ("queue", Shape::Any),
+    ("input_est_queue", Shape::Any),
+    ("output_est_queue", Shape::Any),
+]);
+
+const FROM: Shape = Shape::Map(&[("service", Shape::Any), ("project", Shape::Any)]);
+
+const TIME: Shape = Shape::Map(&[
+    ("days", Shape::Any),
+    ("start", Shape::Any),
+    ("end", Shape::Any),
+    ("tz", Shape::Any),
+]);
+
+const LOCALHOST: Shape = Shape::Map(&[
+    ("allow", Shape::Any),
+    ("log_prefix", Shape::Any),
+    ("verdict", VERDICT),
+    ("include_gateway_ips", Shape::Any),
+    ("enable_nat", Shape::Any),
+]);
+
+const EXTERNAL: Shape = Shape::Map(&[
+    ("allow", Shape::Any),
+    ("log_prefix", Shape::Any),
+    ("ips", Shape::Any),
+    ("host", Shape::Any),
+    ("verdict", VERDICT),
+    ("rdns", Shape::Any),
+    ("rdns_ttl", Shape::Any),
+    ("rdns_negative_ttl", Shape::Any),
+    ("rdns_on_failure", Shape::Any),
+    ("rdns_family", Shape::Any),
+    ("rdns_require_both", Shape::Any),
+    ("time", TIME),
+    ("iif_in", Shape::Any),
+    (
+        "limit",
+        Shape::Map(&[("rate", Shape::Any), ("burst", Shape::Any)]),
+    ),
+]);
+
+const RULE: Shape = Shape::Map(&[
+    ("log_prefix", Shape::Any),
+    ("network", Shape::Any),
+    ("ips", Shape::Any),
+    ("host", Shape::Any),
+    ("hostname", Shape::Any),
+    ("dns_family", Shape::Any),
+    ("dns_require_both", Shape::Any),
+    ("dns_refresh", Shape::Any),
+    ("container", Shape::Any),
+    ("from", FROM),
+    ("proto", Shape::Any),
+    ("src_ports", Shape::Any),
+    ("dst_ports", Shape::Any),
+    ("verdict", VERDICT),
+    ("time", TIME),
+    ("enabled", Shape::Any),
+    ("reason", Shape::Any),
+    ("offload", Shape::Any),
+    ("notify_on_hit", Shape::Any),
+]);
+
+const FORWARD: Shape = Shape::Map(&[
+    ("host_port", Shape::Any),
+    ("port", Shape::Any),
+    ("proto", Shape::Any),
+    ("host_ip", Shape::Any),
+    ("hairpin", Shape::Any),
+]);
+
+const RULE_SET: Shape = Shape::Map(&[
+    (EXTENDS_KEY, Shape::Any),
+    (
+        "mapped_ports",
+        Shape::Map(&[("localhost", LOCALHOST), ("external", EXTERNAL)]),
+    ),
+    ("output", Shape::List(&RULE)),
+    ("expected_subnet", Shape::Any),
+    ("storage_egress", Shape::Any),
+    ("raw_rules", Shape::Any),
+    ("forward", Shape::List(&FORWARD)),
+    (
+        "quarantine",
+        Shape::Map(&[
+            ("max_drops", Shape::Any),
+            ("window", Shape::Any),
+            ("management", Shape::Any),
+        ]),
+    ),
+]);
+
+/// Paths of the keys in `value` that `shape` doesn't accept
+fn unknown_keys(value: &Value, shape: &Shape, path: &str, found: &mut Vec<String>) {
+    match (shape, value) {
+        (Shape::Map(keys), Value::Mapping(map)) => {
+            for (key, value) in map {
+                let name = key.as_str().map(str::to_string).unwrap_or_else(|| {
+                    serde_yaml::to_string(key)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string()
+                });
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                match keys.iter().find(|(known, _)| *known == name) {
+                    Some((_, shape)) => unknown_keys(value, shape, &path, found),
+                    None => found.push(path),
+                }
+            }
+        }
+        (Shape::List(shape), Value::Sequence(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                unknown_keys(item, shape, &format!("{}[{}]", path, i + 1), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Check one rule set in the label format. With `known` given, `container`
+/// references must name one of them.
+pub fn check_rules(source: &str, yaml: &str, known: Option<&BTreeSet<String>>) -> Vec<Problem> {
+    let problem = |kind, detail: String| Problem {
+        source: source.to_string(),
+        kind,
+        detail,
+    };
+
+    let value: Value = match serde_yaml::from_str(yaml) {
+        Ok(value) => value,
+        Err(e) => return vec![problem(Kind::Schema, e.to_string())],
+    };
+    let mut unknown = Vec::new();
+    unknown_keys(&value, &RULE_SET, "", &mut unknown);
+    let mut problems: Vec<Problem> = unknown
+        .into_iter()
+        .map(|key| problem(Kind::UnknownKey, format!("unknown key '{}'", key)))
+        .collect();
+
+    let config = match profiles::parse_rules(yaml) {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(problem(Kind::Schema, e.to_string()));
+            return problems;
+        }
+    };
+    if let Err(e) = config.validate() {
+        problems.push(problem(Kind::Schema, e.to_string()));
+    }
+
+    if let Some(known) = known {
+        for (i, rule) in config.output.iter().enumerate() {
+            if !rule.container.is_empty() && !known.contains(&rule.container) {
+                problems.push(problem(
+                    Kind::Reference,
+                    format!(
+                        "output[{}] references container '{}', which is not defined",
+                        i + 1,
+                        rule.container
+                    ),
+                ));
+            }
+            // Services of other projects are outside the document
+            if let Some(from) = rule.from.as_ref().filter(|from| from.project.is_none())
+                && !known.contains(&from.service)
+            {
+                problems.push(problem(
+                    Kind::Reference,
+                    format!(
+                        "output[{}] allows traffic from service '{}', which is not defined",
+                        i + 1,
+                        from.service
+                    ),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// A service's labels, written as a mapping or as `KEY=VALUE` items
+fn service_labels(service: &Value) -> HashMap<String, String> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    };
+    match service.get("labels") {
+        Some(Value::Mapping(map)) => map
+            .iter()
+            .filter_map(|(key, value)| Some((key.as_str()?.to_string(), scalar(value))))
+            .collect(),
+        Some(Value::Sequence(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str()?.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+/// Names the containers of a compose file answer to: service names, their
+/// compose aliases, `container_name` and the aliases label
+fn compose_names(
+    project: Option<&str>,
+    services: &[(String, HashMap<String, String>, Option<String>)],
+) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for (service, labels, container_name) in services {
+        let info = ComposeInfo {
+            project: project.map(str::to_string),
+            service: Some(service.clone()),
+            ..Default::default()
+        };
+        names.extend(info.generate_aliases());
+        names.extend(container_name.clone());
+        if let Some(aliases) = labels::get(labels, ALIASES_KEY) {
+            names.extend(
+                aliases
+                    .split(',')
+                    .map(|alias| alias.trim().to_string())
+                    .filter(|alias| !alias.is_empty()),
+            );
+        }
+    }
+    names
+}
+
+/// Check a rule set or a compose file. `known` names containers outside the
+/// document that references may point at; a plain rule set's references
+/// are only checked when it is given.
+pub fn check_document(text: &str, known: &[String]) -> ValidateReport {
+    let value: Value = match serde_yaml::from_str(text) {
+        Ok(value) => value,
+        Err(e) => {
+            return ValidateReport {
+                rule_sets: 0,
+                problems: vec![Problem {
+                    source: "document".to_string(),
+                    kind: Kind::Schema,
+                    detail: e.to_string(),
+                }],
+            };
+        }
+    };
+
+    let Some(Value::Mapping(services)) = value.get("services") else {
+        let known: BTreeSet<String> = known.iter().cloned().collect();
+        let known = (!known.is_empty()).then_some(&known);
+        return ValidateReport {
+            rule_sets: 1,
+            problems: check_rules("rules", text, known),
+        };
+    };
+
+    let services: Vec<(String, HashMap<String, String>, Option<String>)> = services
+        .iter()
+        .filter_map(|(name, service)| {
+            let container_name = service
+                .get("container_name")
+                .and_then(|n| n.as_str())
+                .map(str::to_string);
+            Some((
+                name.as_str()?.to_string(),
+                service_labels(service),
+                container_name,
+            ))
+        })
+        .collect();
+    let project = value.get("name").and_then(|n| n.as_str());
+    let mut names = compose_names(project, &services);
+    names.extend(known.iter().cloned());
+
+    let mut report = ValidateReport::default();
+    for (service, service_labels, _) in &services {
+        let Some(rules) = labels::get(service_labels, RULES_KEY) else {
+            continue;
+        };
+        report.rule_sets += 1;
+        report
+            .problems
+            .extend(check_rules(service, rules, Some(&names)));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_document() {
+        let compose = r#"
+name: shop
+services:
+  db:
+    image: postgres
+    labels:
+      harborshield.enabled: "true"
+  web:
+    image: nginx
+    labels:
+      - harborshield.enabled=true
+      - "harborshield.rules=output: [{network: backend, container: db, proto: tcp, dst_ports: [5432]}, {network: backend, container: cache, proto: tcp, dst_port: [6379]}]"
+  api:
+    image: api
+    labels:
+      harborshield.rules: |
+        mapped_ports:
+          external:
+            allow: true
+            ips: ["10.0.0.300/8"]
+"#;
+        let report = check_document(compose, &[]);
+        assert_eq!(report.rule_sets, 2);
+        let kinds: Vec<(&str, Kind)> = report
+            .problems
+            .iter()
+            .map(|p| (p.source.as_str(), p.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("web", Kind::UnknownKey),
+                ("web", Kind::Schema),
+                ("api", Kind::Schema),
+            ]
+        );
+        assert!(report.problems[0].detail.contains("output[2].dst_port"));
+
+        // A missing dst_ports fails the tcp rule; with it the reference is
+        // what's left to report
+        let fixed = compose.replace("dst_port:", "dst_ports:");
+        let report = check_document(&fixed, &[]);
+        assert_eq!(report.problems[0].kind, Kind::Reference);
+        assert!(
+            check_document(&fixed, &["cache".to_string()])
+                .problems
+                .len()
+                == 1
+        );
+
+        let rules = "output:\n  - network: backend\n    container: db.shop\n    proto: udp\n    dst_ports: [53]\n";
+        assert!(check_document(rules, &[]).is_valid());
+        assert!(!check_document(rules, &["db".to_string()]).is_valid());
+    }
+}
@@ -0,0 +1,345 @@
+//! Read-only web dashboard behind `--web-addr`.
+//!
+//! `/` is a single page that follows `/events`, a server-sent event stream
+//! sending a fresh `state` every [`REFRESH`]: the tracked containers as the
+//! admin gRPC service lists them, the rules of their chains with counters as
+//! `harborshield status --counters` shows them, traffic rates and recent
+//! Docker events as in `harborshield top`, and the last reconcile.
+//! `/api/state` answers with one such state, without rates.
+//!
+//! Nothing served changes anything. With API tokens configured, a token is
+//! required, in an `Authorization` header or a `token` query parameter since
+//! browsers can't set headers on an event stream, and only the containers
+//! it covers are shown.
+
+use crate::access::{TokenScope, Tokens};
+use crate::handlers::admin::{ContainerSummary, chain_name};
+use crate::handlers::reconcile::ReconcileReport;
+use crate::nftables::counters::{self, CounterSampler};
+use crate::nftables::runner::WatchdogStatus;
+use crate::server::{ListenAddr, Listener};
+use crate::status::{CountersReport, RuleHits};
+use crate::top::{EventLog, RecentEvent, TopRow, TopView};
+use crate::{Harborshield, Result};
+use bollard::models::EventMessage;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, error, info};
+
+/// How often the event stream sends a new state
+pub const REFRESH: Duration = Duration::from_secs(2);
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Docker events the daemon handled, newest last
+static EVENTS: LazyLock<Mutex<EventLog>> = LazyLock::new(|| Mutex::new(EventLog::default()));
+
+/// Note a Docker event for the dashboard
+pub fn record_event(event: &EventMessage) {
+    if let (Some(event), Ok(mut log)) = (RecentEvent::from_event(event), EVENTS.lock()) {
+        log.push(event);
+    }
+}
+
+/// What the dashboard shows at one moment
+#[derive(Debug, Clone, Serialize)]
+pub struct DashboardState {
+    pub version: String,
+    /// Unix seconds
+    pub timestamp: i64,
+    pub containers: Vec<ContainerSummary>,
+    /// The rules of every container chain, with their counters
+    pub rules: Vec<RuleHits>,
+    /// Rates since the stream's previous state; empty in the first
+    pub traffic: Vec<TopRow>,
+    pub events: Vec<RecentEvent>,
+    pub reconcile: Option<ReconcileReport>,
+    pub nftables: WatchdogStatus,
+}
+
+impl DashboardState {
+    /// Keep only what `scope` covers
+    fn restrict(&mut self, scope: &TokenScope) {
+        self.containers
+            .retain(|c| scope.covers(&c.name, c.project.as_deref()));
+        let visible = |name: &str| self.containers.iter().any(|c| c.name == name);
+        self.rules.retain(|rule| visible(&rule.container_name));
+        self.traffic.retain(|row| visible(&row.container_name));
+        self.events.retain(|event| visible(&event.container_name));
+        if !scope.unrestricted() {
+            self.reconcile = None;
+        }
+    }
+}
+
+/// Chain counters read for the previous state of one stream
+#[derive(Debug, Default)]
+pub struct TrafficSample {
+    sampler: CounterSampler,
+    at: Option<Instant>,
+}
+
+impl Harborshield {
+    /// The dashboard's view of the daemon. With `sample`, traffic rates are
+    /// taken against the stream's previous reading
+    pub async fn dashboard_state(&self, sample: Option<&mut TrafficSample>) -> DashboardState {
+        let tracked = self.docker_client.container_tracker().list_containers();
+        let names: HashMap<String, String> = tracked
+            .iter()
+            .map(|container| (chain_name(container), container.name.clone()))
+            .collect();
+
+        let rules = match counters::list_rule_counters().await {
+            Ok(rules) => CountersReport::new(rules, &names).rules,
+            Err(e) => {
+                debug!("Dashboard could not read rule counters: {}", e);
+                Vec::new()
+            }
+        };
+
+        let events = EVENTS.lock().map(|log| log.snapshot()).unwrap_or_default();
+        let mut traffic = Vec::new();
+        if let Some(sample) = sample
+            && let Ok(current) = counters::list_chain_counters().await
+        {
+            let deltas = sample.sampler.deltas(current);
+            if let Some(at) = sample.at {
+                traffic = TopView::new(&deltas, &names, at.elapsed(), Vec::new()).containers;
+            }
+            sample.at = Some(Instant::now());
+        }
+
+        DashboardState {
+            version: crate::VERSION.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            containers: self.admin_containers().await,
+            rules,
+            traffic,
+            events,
+            reconcile: crate::handlers::reconcile::last(),
+            nftables: crate::nftables::runner::watchdog().status(),
+        }
+    }
+}
+
+/// The dashboard's socket, bound before the process is sandboxed
+pub struct WebListener(Listener);
+
+impl WebListener {
+    pub async fn bind(addr: &ListenAddr) -> Result<Self> {
+        Ok(Self(Listener::bind(addr).await?))
+    }
+}
+
+pub struct WebDashboard {
+    listener: Listener,
+    handlers: Harborshield,
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl WebDashboard {
+    pub fn new(listener: WebListener, handlers: Harborshield, tokens: Option<Tokens>) -> Self {
+        Self {
+            listener: listener.0,
+            handlers,
+            tokens: tokens.map(Arc::new),
+        }
+    }
+
+    pub async fn serve(self) {
+        info!("Serving the web dashboard on {}", self.listener.describe());
+        let cancelled = self.handlers.cancellation_token.clone();
+        loop {
+            let accepted = tokio::select! {
+                _ = cancelled.cancelled() => break,
+                accepted = self.accept() => accepted,
+            };
+            if let Err(e) = accepted {
+                error!("Error accepting dashboard connection: {}", e);
+            }
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<()> {
+        let (handlers, tokens) = (self.handlers.clone(), self.tokens.clone());
+        match &self.listener {
+            Listener::Tcp(l) => {
+                let (stream, _) = l.accept().await?;
+                tokio::spawn(serve_connection(stream, handlers, tokens));
+            }
+            Listener::Unix(l) => {
+                let (stream, _) = l.accept().await?;
+                tokio::spawn(serve_connection(stream, handlers, tokens));
+            }
+            #[cfg(target_os = "linux")]
+            Listener::Vsock(l) => {
+                let stream = l.accept().await?;
+                tokio::spawn(serve_connection(stream, handlers, tokens));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A request's method, path and credentials
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    /// The `Authorization` header, or the `token` parameter as a bearer
+    /// token
+    authorization: Option<String>,
+}
+
+fn parse_request(head: &str) -> Option<Request> {
+    let mut lines = head.lines();
+    let mut parts = lines.next()?.split_whitespace();
+    let (method, target) = (parts.next()?, parts.next()?);
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let header = lines.take_while(|line| !line.is_empty()).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("authorization")
+            .then(|| value.trim().to_string())
+    });
+    let param = query.split('&').find_map(|pair| {
+        pair.strip_prefix("token=")
+            .map(|token| format!("Bearer {}", token))
+    });
+    Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization: header.or(param),
+    })
+}
+
+async fn respond<S>(stream: &mut S, status: &str, content_type: &str, body: &str) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn serve_connection<S>(mut stream: S, handlers: Harborshield, tokens: Option<Arc<Tokens>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Err(e) = serve_request(&mut stream, &handlers, tokens.as_deref()).await {
+        debug!("Dashboard connection ended: {}", e);
+    }
+}
+
+async fn serve_request<S>(
+    stream: &mut S,
+    handlers: &Harborshield,
+    tokens: Option<&Tokens>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0; 4096];
+    let n = stream.read(&mut buffer).await?;
+    let Some(request) = parse_request(&String::from_utf8_lossy(&buffer[..n])) else {
+        return respond(stream, "400 Bad Request", "text/plain", "Bad Request").await;
+    };
+    if request.method != "GET" {
+        return respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "The dashboard is read-only",
+        )
+        .await;
+    }
+
+    // The page holds no data, so it needs no token
+    if request.path == "/" {
+        return respond(stream, "200 OK", "text/html; charset=utf-8", PAGE).await;
+    }
+    let scope = match tokens {
+        Some(tokens) => match tokens.authorize(request.authorization.as_deref()) {
+            Some(scope) => Some(scope),
+            None => return respond(stream, "401 Unauthorized", "text/plain", "Unauthorized").await,
+        },
+        None => None,
+    };
+    let state = async |sample: Option<&mut TrafficSample>| {
+        let mut state = handlers.dashboard_state(sample).await;
+        if let Some(scope) = scope {
+            state.restrict(scope);
+        }
+        state
+    };
+
+    match request.path.as_str() {
+        "/api/state" => {
+            let json = serde_json::to_string(&state(None).await)?;
+            respond(stream, "200 OK", "application/json", &json).await
+        }
+        "/events" => {
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+                )
+                .await?;
+            let mut sample = TrafficSample::default();
+            let mut ticker = tokio::time::interval(REFRESH);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = handlers.cancellation_token.cancelled() => break,
+                    _ = ticker.tick() => {}
+                }
+                let json = serde_json::to_string(&state(Some(&mut sample)).await)?;
+                // Fails once the browser goes away
+                stream
+                    .write_all(format!("event: state\ndata: {}\n\n", json).as_bytes())
+                    .await?;
+                stream.flush().await?;
+            }
+            Ok(())
+        }
+        _ => respond(stream, "404 Not Found", "text/plain", "Not Found").await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(
+            "GET /events?token=s3cret HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "GET".to_string(),
+                path: "/events".to_string(),
+                authorization: Some("Bearer s3cret".to_string()),
+            }
+        );
+
+        // A header wins over the parameter
+        let request =
+            parse_request("GET /api/state?token=a HTTP/1.1\r\nAuthorization: Bearer b\r\n\r\n")
+                .unwrap();
+        assert_eq!(request.authorization.as_deref(), Some("Bearer b"));
+        assert!(parse_request("").is_none());
+    }
+}
@@ -0,0 +1,259 @@
+//! `cargo xtask check --changed`: fmt, clippy and tests for what changed
+//! since the merge base only.
+//!
+//! Changed files are those differing from the merge base with `--base`,
+//! committed or not, plus untracked ones; deleted files count too. Each
+//! belongs to the workspace package whose directory holds it most closely;
+//! those packages and every workspace package depending on them, directly
+//! or not, are clippy'd and tested. Only the changed Rust files themselves are format-checked.
+//! Changes to files every build reads (the lockfile, toolchain or cargo
+//! configuration) select the whole workspace.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{project_root, run_command};
+
+/// Files outside any package that every package is built with
+const WORKSPACE_FILES: &[&str] = &[
+    "Cargo.lock",
+    "rust-toolchain",
+    "rust-toolchain.toml",
+    ".cargo/",
+    "clippy.toml",
+    ".clippy.toml",
+    "rustfmt.toml",
+    ".rustfmt.toml",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    /// Directory of its manifest, relative to the workspace root
+    pub dir: PathBuf,
+    pub edition: String,
+    /// Workspace packages it depends on
+    pub depends_on: Vec<String>,
+}
+
+/// Workspace packages from `cargo metadata`
+fn packages(root: &Path) -> Result<Vec<Package>> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(root)
+        .output()
+        .context("Failed to run cargo metadata")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let metadata: Value =
+        serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata")?;
+    Ok(parse_packages(&metadata, root))
+}
+
+pub fn parse_packages(metadata: &Value, root: &Path) -> Vec<Package> {
+    let listed = metadata["packages"].as_array().cloned().unwrap_or_default();
+    let name_of_dir = |dir: &Path| {
+        listed.iter().find_map(|package| {
+            let manifest = Path::new(package["manifest_path"].as_str()?);
+            (manifest.parent()? == dir).then(|| package["name"].as_str().map(str::to_string))?
+        })
+    };
+
+    listed
+        .iter()
+        .filter_map(|package| {
+            let manifest = Path::new(package["manifest_path"].as_str()?);
+            let dir = manifest.parent()?;
+            let depends_on = package["dependencies"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|dependency| name_of_dir(Path::new(dependency["path"].as_str()?)))
+                .collect();
+            Some(Package {
+                name: package["name"].as_str()?.to_string(),
+                dir: dir.strip_prefix(root).unwrap_or(dir).to_path_buf(),
+                edition: package["edition"].as_str().unwrap_or("2021").to_string(),
+                depends_on,
+            })
+        })
+        .collect()
+}
+
+/// The package holding `file`, the one with the deepest directory
+pub fn owner<'a>(file: &Path, packages: &'a [Package]) -> Option<&'a Package> {
+    packages
+        .iter()
+        .filter(|package| file.starts_with(&package.dir))
+        .max_by_key(|package| package.dir.components().count())
+}
+
+/// Packages to check for `files`: their owners and everything depending on
+/// those in the workspace
+pub fn affected(files: &[PathBuf], packages: &[Package]) -> BTreeSet<String> {
+    let everything = files.iter().any(|file| {
+        let file = file.to_string_lossy();
+        WORKSPACE_FILES
+            .iter()
+            .any(|shared| match shared.strip_suffix('/') {
+                Some(dir) => file.starts_with(&format!("{}/", dir)),
+                None => file == *shared,
+            })
+    });
+    if everything {
+        return packages
+            .iter()
+            .map(|package| package.name.clone())
+            .collect();
+    }
+
+    let mut selected: BTreeSet<String> = files
+        .iter()
+        .filter_map(|file| owner(file, packages))
+        .map(|package| package.name.clone())
+        .collect();
+    loop {
+        let dependents: Vec<String> = packages
+            .iter()
+            .filter(|package| !selected.contains(&package.name))
+            .filter(|package| package.depends_on.iter().any(|d| selected.contains(d)))
+            .map(|package| package.name.clone())
+            .collect();
+        if dependents.is_empty() {
+            return selected;
+        }
+        selected.extend(dependents);
+    }
+}
+
+fn git(root: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .with_context(|| format!("Failed to run: git {}", args.join(" ")))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Files changed since the merge base with `base`, and untracked ones
+fn changed_files(root: &Path, base: &str) -> Result<Vec<PathBuf>> {
+    let merge_base = git(root, &["merge-base", "HEAD", base])
+        .with_context(|| format!("No merge base with {}; pass another --base", base))?;
+    let merge_base = merge_base.trim();
+
+    let diffed = git(root, &["diff", "--name-only", merge_base])?;
+    let untracked = git(root, &["ls-files", "--others", "--exclude-standard"])?;
+    let files: BTreeSet<PathBuf> = diffed
+        .lines()
+        .chain(untracked.lines())
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    Ok(files.into_iter().collect())
+}
+
+pub fn run(fix: bool, base: &str) -> Result<()> {
+    let root = project_root();
+    let files = changed_files(&root, base)?;
+    let packages = packages(&root)?;
+    let selected = affected(&files, &packages);
+    if selected.is_empty() {
+        println!("Nothing to check: no package changed since {}", base);
+        return Ok(());
+    }
+    println!(
+        "Checking {} changed since {}...\n",
+        selected.iter().cloned().collect::<Vec<_>>().join(", "),
+        base
+    );
+
+    // Format check, per edition since rustfmt is handed the files directly
+    println!("==> Checking formatting of changed files...");
+    for package in packages.iter().filter(|p| selected.contains(&p.name)) {
+        let sources: Vec<String> = files
+            .iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+            .filter(|file| root.join(file).exists())
+            .filter(|file| owner(file, &packages).is_some_and(|o| o.name == package.name))
+            .map(|file| file.to_string_lossy().into_owned())
+            .collect();
+        if sources.is_empty() {
+            continue;
+        }
+        let mut args = vec!["--edition", package.edition.as_str()];
+        if !fix {
+            args.push("--check");
+        }
+        args.extend(sources.iter().map(String::as_str));
+        run_command("rustfmt", &args)?;
+    }
+
+    let mut selection: Vec<&str> = Vec::new();
+    for name in &selected {
+        selection.extend(["-p", name.as_str()]);
+    }
+
+    // Clippy
+    println!("\n==> Running clippy...");
+    let mut clippy_args = vec!["clippy"];
+    clippy_args.extend(&selection);
+    clippy_args.extend(["--all-targets", "--all-features"]);
+    if fix {
+        clippy_args.extend(["--fix", "--allow-dirty"]);
+    }
+    clippy_args.extend(["--", "-D", "warnings"]);
+    run_command("cargo", &clippy_args)?;
+
+    // Tests
+    println!("\n==> Running tests...");
+    let mut test_args = vec!["test"];
+    test_args.extend(&selection);
+    run_command("cargo", &test_args)?;
+
+    println!("\nAll checks passed!");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affected_packages() {
+        let metadata = serde_json::json!({ "packages": [
+            { "name": "app", "manifest_path": "/ws/Cargo.toml", "edition": "2024",
+              "dependencies": [{ "name": "core", "path": "/ws/crates/core" }, { "name": "clap" }] },
+            { "name": "core", "manifest_path": "/ws/crates/core/Cargo.toml", "edition": "2024",
+              "dependencies": [] },
+            { "name": "xtask", "manifest_path": "/ws/xtask/Cargo.toml", "edition": "2021",
+              "dependencies": [] },
+        ]});
+        let packages = parse_packages(&metadata, Path::new("/ws"));
+        assert_eq!(packages[0].depends_on, vec!["core"]);
+        assert_eq!(packages[1].dir, PathBuf::from("crates/core"));
+
+        let names = |files: &[&str]| -> Vec<String> {
+            let files: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+            affected(&files, &packages).into_iter().collect()
+        };
+        assert_eq!(names(&["src/main.rs"]), vec!["app"]);
+        assert_eq!(names(&["crates/core/src/lib.rs"]), vec!["app", "core"]);
+        assert_eq!(names(&["xtask/src/main.rs"]), vec!["xtask"]);
+        assert_eq!(names(&["Cargo.lock"]), vec!["app", "core", "xtask"]);
+        assert!(names(&[]).is_empty());
+    }
+}
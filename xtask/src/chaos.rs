@@ -0,0 +1,399 @@
+//! `cargo xtask chaos`: start, stop and rename labeled containers, restart
+//! the Docker daemon and kill harborshield at random, then check that the
+//! kernel ended up with exactly the chains the running containers call for.
+//!
+//! Needs root on a Linux host with Docker and nft, and changes both: run it
+//! on a throwaway VM. Containers are named `chaos-*` and removed afterwards,
+//! and only `hs-chaos-*` chains are compared, so other containers on the
+//! host don't matter. A failing run prints its seed; `--seed` replays the
+//! same sequence of actions.
+
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{project_root, run_command};
+
+/// Prefix of the containers the scenario manages
+const CONTAINER_PREFIX: &str = "chaos-";
+
+const IMAGE: &str = "alpine:3";
+
+/// Rules on every chaos container; any valid config will do
+const RULES: &str = "output: [{ips: [1.1.1.1], proto: udp, dst_ports: [53]}]";
+
+pub struct Options {
+    pub steps: usize,
+    pub seed: Option<u64>,
+    pub max_containers: usize,
+    /// Command that restarts the Docker daemon; not restarted when unset
+    pub daemon_restart: Option<String>,
+    /// How long harborshield gets to converge after the last step
+    pub settle: Duration,
+    pub binary: Option<PathBuf>,
+}
+
+/// xorshift64*, enough to make runs reproducible from a seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number below `n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Start,
+    Stop,
+    Restart,
+    Rename,
+    Remove,
+    RestartDaemon,
+    KillHarborshield,
+}
+
+impl Action {
+    /// Container actions come up far more often than restarts, or the
+    /// daemons would spend the run coming back up
+    const WEIGHTED: &[(Action, usize)] = &[
+        (Action::Start, 6),
+        (Action::Stop, 3),
+        (Action::Restart, 2),
+        (Action::Rename, 3),
+        (Action::Remove, 2),
+        (Action::RestartDaemon, 1),
+        (Action::KillHarborshield, 1),
+    ];
+
+    pub fn pick(rng: &mut Rng, daemon_restart: bool) -> Action {
+        let choices: Vec<(Action, usize)> = Self::WEIGHTED
+            .iter()
+            .copied()
+            .filter(|(action, _)| daemon_restart || *action != Action::RestartDaemon)
+            .collect();
+        let mut roll = rng.below(choices.iter().map(|(_, weight)| weight).sum());
+        for (action, weight) in choices {
+            if roll < weight {
+                return action;
+            }
+            roll -= weight;
+        }
+        unreachable!("the roll is below the total weight")
+    }
+}
+
+/// The chain harborshield gives a container
+pub fn chain_name(name: &str, id: &str) -> String {
+    format!(
+        "hs-{}-{}",
+        name.replace(['_', '.', '/'], "-"),
+        &id[..12.min(id.len())]
+    )
+}
+
+/// Names of the chaos chains in `nft -j list table ip filter` output
+pub fn parse_chains(json: &serde_json::Value) -> BTreeSet<String> {
+    json.get("nftables")
+        .and_then(|n| n.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("chain")?.get("name")?.as_str())
+        .filter(|name| name.starts_with(&format!("hs-{}", CONTAINER_PREFIX)))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Chains that should exist but don't, and chains that exist but shouldn't
+pub fn diff(desired: &BTreeSet<String>, actual: &BTreeSet<String>) -> (Vec<String>, Vec<String>) {
+    (
+        desired.difference(actual).cloned().collect(),
+        actual.difference(desired).cloned().collect(),
+    )
+}
+
+fn output(cmd: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(cmd)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run: {} {}", cmd, args.join(" ")))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            cmd,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Chaos containers as (name, id), running ones only unless `all`
+fn containers(all: bool) -> Result<Vec<(String, String)>> {
+    let filter = format!("name={}", CONTAINER_PREFIX);
+    let mut args = vec!["ps", "--filter", &filter, "--format", "{{.Names}} {{.ID}}"];
+    if all {
+        args.insert(1, "-a");
+    }
+    Ok(output("docker", &args)?
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, id)| (name.to_string(), id.to_string()))
+        .collect())
+}
+
+/// Chains for the running chaos containers, with full ids
+fn desired_chains() -> Result<BTreeSet<String>> {
+    let mut chains = BTreeSet::new();
+    for (name, _) in containers(false)? {
+        let id = output("docker", &["inspect", "--format", "{{.Id}}", &name])?;
+        chains.insert(chain_name(&name, id.trim()));
+    }
+    Ok(chains)
+}
+
+fn actual_chains() -> Result<BTreeSet<String>> {
+    let listing = output("nft", &["-j", "list", "table", "ip", "filter"])?;
+    Ok(parse_chains(&serde_json::from_str(&listing)?))
+}
+
+/// A new name for the next container, unique within the run
+fn fresh_name(counter: &mut usize) -> String {
+    *counter += 1;
+    format!("{}{}", CONTAINER_PREFIX, counter)
+}
+
+fn spawn_harborshield(binary: &Path, data_dir: &Path, log: &Path) -> Result<Child> {
+    let log = File::options()
+        .create(true)
+        .append(true)
+        .open(log)
+        .with_context(|| format!("Failed to open {}", log.display()))?;
+    Command::new(binary)
+        .arg("--data-dir")
+        .arg(data_dir)
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", binary.display()))
+}
+
+fn step(action: Action, rng: &mut Rng, counter: &mut usize, options: &Options) -> Result<String> {
+    let all = containers(true)?;
+    let running = containers(false)?;
+    let pick = |rng: &mut Rng, list: &[(String, String)]| list[rng.below(list.len())].0.clone();
+
+    match action {
+        Action::Start if all.len() < options.max_containers => {
+            let name = fresh_name(counter);
+            let rules = format!("harborshield.rules={}", RULES);
+            output(
+                "docker",
+                &[
+                    "run",
+                    "-d",
+                    "--name",
+                    &name,
+                    "--restart",
+                    "unless-stopped",
+                    "--label",
+                    "harborshield.enabled=true",
+                    "--label",
+                    &rules,
+                    IMAGE,
+                    "sleep",
+                    "infinity",
+                ],
+            )?;
+            Ok(format!("started {}", name))
+        }
+        Action::Start => {
+            // At the limit: bring back a stopped one instead
+            let stopped: Vec<_> = all.into_iter().filter(|c| !running.contains(c)).collect();
+            if stopped.is_empty() {
+                return Ok("at the container limit".to_string());
+            }
+            let name = pick(rng, &stopped);
+            output("docker", &["start", &name])?;
+            Ok(format!("started {} again", name))
+        }
+        Action::Stop | Action::Restart | Action::Rename if running.is_empty() => {
+            Ok("nothing running".to_string())
+        }
+        Action::Stop => {
+            let name = pick(rng, &running);
+            output("docker", &["stop", "-t", "1", &name])?;
+            Ok(format!("stopped {}", name))
+        }
+        Action::Restart => {
+            let name = pick(rng, &running);
+            output("docker", &["restart", "-t", "1", &name])?;
+            Ok(format!("restarted {}", name))
+        }
+        Action::Rename => {
+            let name = pick(rng, &running);
+            let new_name = fresh_name(counter);
+            output("docker", &["rename", &name, &new_name])?;
+            Ok(format!("renamed {} to {}", name, new_name))
+        }
+        Action::Remove if all.is_empty() => Ok("nothing to remove".to_string()),
+        Action::Remove => {
+            let name = pick(rng, &all);
+            output("docker", &["rm", "-f", &name])?;
+            Ok(format!("removed {}", name))
+        }
+        Action::RestartDaemon => {
+            let command = options.daemon_restart.as_deref().unwrap_or_default();
+            output("sh", &["-c", command])?;
+            // Later steps need the daemon answering again
+            let deadline = Instant::now() + Duration::from_secs(60);
+            while output("docker", &["info"]).is_err() {
+                if Instant::now() >= deadline {
+                    bail!("the Docker daemon did not come back after '{}'", command);
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+            Ok("restarted the Docker daemon".to_string())
+        }
+        Action::KillHarborshield => unreachable!("handled by the caller"),
+    }
+}
+
+fn cleanup(harborshield: &mut Child) {
+    let _ = harborshield.kill();
+    let _ = harborshield.wait();
+    if let Ok(all) = containers(true) {
+        for (name, _) in all {
+            let _ = output("docker", &["rm", "-f", &name]);
+        }
+    }
+}
+
+pub fn run(options: Options) -> Result<()> {
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+    });
+    let mut rng = Rng::new(seed);
+    println!("==> Chaos run with seed {} ({} steps)", seed, options.steps);
+
+    let binary = match &options.binary {
+        Some(binary) => binary.clone(),
+        None => {
+            run_command("cargo", &["build", "--release"])?;
+            project_root().join("target/release/harborshield")
+        }
+    };
+    let work = std::env::temp_dir().join(format!("harborshield-chaos-{}", seed));
+    std::fs::create_dir_all(&work)?;
+    let log = work.join("harborshield.log");
+    output("docker", &["pull", "-q", IMAGE])?;
+
+    let mut harborshield = spawn_harborshield(&binary, &work, &log)?;
+    let mut counter = 0;
+    let result = (|| -> Result<()> {
+        for n in 1..=options.steps {
+            // Let events land at varying points of handling
+            thread::sleep(Duration::from_millis(rng.below(1500) as u64));
+            let action = Action::pick(&mut rng, options.daemon_restart.is_some());
+            let done = if action == Action::KillHarborshield {
+                harborshield.kill()?;
+                harborshield.wait()?;
+                harborshield = spawn_harborshield(&binary, &work, &log)?;
+                "killed and restarted harborshield".to_string()
+            } else {
+                step(action, &mut rng, &mut counter, &options)?
+            };
+            println!("[{:>3}/{}] {}", n, options.steps, done);
+        }
+
+        println!(
+            "==> Waiting up to {:?} for the ruleset to settle",
+            options.settle
+        );
+        let deadline = Instant::now() + options.settle;
+        loop {
+            let (missing, stale) = diff(&desired_chains()?, &actual_chains()?);
+            if missing.is_empty() && stale.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "kernel state differs from the containers (seed {}, log {}):\n  missing: {:?}\n  stale: {:?}",
+                    seed,
+                    log.display(),
+                    missing,
+                    stale
+                );
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    })();
+
+    cleanup(&mut harborshield);
+    result?;
+    println!("==> Kernel state matches the running containers");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chaos_checks() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let picks: Vec<Action> = (0..50).map(|_| Action::pick(&mut a, false)).collect();
+        assert_eq!(
+            picks,
+            (0..50)
+                .map(|_| Action::pick(&mut b, false))
+                .collect::<Vec<_>>()
+        );
+        assert!(!picks.contains(&Action::RestartDaemon));
+        assert!(picks.contains(&Action::Start));
+
+        assert_eq!(
+            chain_name("chaos-3", "0123456789abcdef"),
+            "hs-chaos-3-0123456789ab"
+        );
+        let listing = serde_json::json!({ "nftables": [
+            { "metainfo": { "json_schema_version": 1 } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-chaos-1-aaaaaaaaaaaa" } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-web-bbbbbbbbbbbb" } },
+            { "chain": { "family": "ip", "table": "filter", "name": "hs-chaos-2-cccccccccccc" } },
+            { "rule": { "chain": "hs-chaos-1-aaaaaaaaaaaa" } },
+        ]});
+        let actual = parse_chains(&listing);
+        let desired = BTreeSet::from([
+            "hs-chaos-1-aaaaaaaaaaaa".to_string(),
+            "hs-chaos-4-dddddddddddd".to_string(),
+        ]);
+        assert_eq!(
+            diff(&desired, &actual),
+            (
+                vec!["hs-chaos-4-dddddddddddd".to_string()],
+                vec!["hs-chaos-2-cccccccccccc".to_string()]
+            )
+        );
+    }
+}
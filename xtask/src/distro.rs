@@ -0,0 +1,294 @@
+//! Dev and test containers on other distributions: `cargo xtask dev
+//! --distro <distro>` and `cargo xtask e2e`.
+//!
+//! Each distro gets an image built from its own base with its own nftables
+//! package, so the integration suite runs against that distro's `nft` and
+//! libnftables. Containers share the host's kernel and Docker daemon: to
+//! cover another kernel, run `cargo xtask e2e` on a host running it. Because
+//! the ruleset and daemon are shared, distros are tested one after another.
+//!
+//! The source tree is mounted at `/app` as in the dev container, with a
+//! target directory per distro since glibc and musl builds don't mix.
+
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::{project_root, run_command, run_command_silent};
+
+/// Toolchain installed in every image, as in `Dockerfile.dev`
+const RUST_VERSION: &str = "1.88";
+
+/// Where the static `docker` CLI and compose plugin are copied from
+const DOCKER_CLI_IMAGE: &str = "docker:27-cli";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Distro {
+    Debian12,
+    Ubuntu24,
+    Alpine,
+    Fedora,
+}
+
+impl Distro {
+    pub const ALL: [Distro; 4] = [
+        Distro::Debian12,
+        Distro::Ubuntu24,
+        Distro::Alpine,
+        Distro::Fedora,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Distro::Debian12 => "debian12",
+            Distro::Ubuntu24 => "ubuntu24",
+            Distro::Alpine => "alpine",
+            Distro::Fedora => "fedora",
+        }
+    }
+
+    fn base_image(self) -> &'static str {
+        match self {
+            Distro::Debian12 => "debian:12",
+            Distro::Ubuntu24 => "ubuntu:24.04",
+            Distro::Alpine => "alpine:3.20",
+            Distro::Fedora => "fedora:40",
+        }
+    }
+
+    /// Installs nftables, a C toolchain, OpenSSL headers and what the
+    /// integration tests shell out to
+    fn install_packages(self) -> &'static str {
+        match self {
+            Distro::Debian12 | Distro::Ubuntu24 => {
+                "apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y \
+                 --no-install-recommends build-essential pkg-config libssl-dev \
+                 ca-certificates curl git nftables procps \
+                 && rm -rf /var/lib/apt/lists/*"
+            }
+            Distro::Alpine => {
+                "apk add --no-cache build-base pkgconf openssl-dev ca-certificates \
+                 curl git nftables procps bash"
+            }
+            Distro::Fedora => {
+                "dnf install -y gcc make pkgconf-pkg-config openssl-devel \
+                 ca-certificates curl git nftables procps-ng && dnf clean all"
+            }
+        }
+    }
+
+    pub fn image(self) -> String {
+        format!("harborshield-dev:{}", self.name())
+    }
+
+    pub fn container(self) -> String {
+        format!("harborshield-dev-{}", self.name())
+    }
+
+    pub fn dockerfile(self) -> String {
+        format!(
+            "FROM {base}\n\
+             RUN {install}\n\
+             COPY --from={cli} /usr/local/bin/docker /usr/local/bin/docker\n\
+             COPY --from={cli} /usr/local/libexec/docker/cli-plugins/docker-compose \
+             /usr/local/libexec/docker/cli-plugins/docker-compose\n\
+             ENV RUSTUP_HOME=/usr/local/rustup CARGO_HOME=/usr/local/cargo \
+             PATH=/usr/local/cargo/bin:$PATH\n\
+             RUN curl -sSf https://sh.rustup.rs | sh -s -- -y --profile minimal \
+             --default-toolchain {rust}\n\
+             WORKDIR /app\n\
+             CMD [\"sleep\", \"infinity\"]\n",
+            base = self.base_image(),
+            install = self.install_packages(),
+            cli = DOCKER_CLI_IMAGE,
+            rust = RUST_VERSION,
+        )
+    }
+}
+
+fn build_image(distro: Distro) -> Result<()> {
+    println!("==> Building {}...", distro.image());
+    // No build context: the Dockerfile only pulls from images
+    let mut child = Command::new("docker")
+        .args(["build", "-t", &distro.image(), "-"])
+        .current_dir(project_root())
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run: docker build")?;
+    child
+        .stdin
+        .take()
+        .context("docker build has no stdin")?
+        .write_all(distro.dockerfile().as_bytes())
+        .context("Failed to write the Dockerfile to docker build")?;
+    let status = child.wait().context("Failed to wait for docker build")?;
+    if !status.success() {
+        bail!("Building {} failed with status: {}", distro.image(), status);
+    }
+    Ok(())
+}
+
+/// (Re)create the distro's container, building its image first when asked
+/// to or when missing
+pub fn start(distro: Distro, build: bool) -> Result<()> {
+    let image = distro.image();
+    let missing = !run_command_silent("docker", &["image", "inspect", &image])?.success();
+    if build || missing {
+        build_image(distro)?;
+    }
+
+    let container = distro.container();
+    let _ = run_command_silent("docker", &["rm", "-f", &container]);
+
+    let root = project_root();
+    let source = format!("{}:/app", root.display());
+    let target = format!("harborshield-target-{}:/app/target", distro.name());
+    run_command(
+        "docker",
+        &[
+            "run",
+            "-d",
+            "--name",
+            &container,
+            "--privileged",
+            // Rules have to land where the test containers' traffic goes
+            "--network",
+            "host",
+            "-v",
+            &source,
+            "-v",
+            &target,
+            "-v",
+            "harborshield-cargo-registry:/usr/local/cargo/registry",
+            "-v",
+            "/var/run/docker.sock:/var/run/docker.sock:rw",
+            "-e",
+            "SQLX_OFFLINE=true",
+            "-e",
+            "RUST_BACKTRACE=1",
+            "--label",
+            "harborshield.enabled=false",
+            &image,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Remove every distro container
+pub fn remove_all() {
+    for distro in Distro::ALL {
+        let _ = run_command_silent("docker", &["rm", "-f", &distro.container()]);
+    }
+}
+
+fn nft_version(distro: Distro) -> String {
+    Command::new("docker")
+        .args(["exec", &distro.container(), "nft", "--version"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "nft version unknown".to_string())
+}
+
+pub struct E2eOptions {
+    /// Distros to test; all of them when empty
+    pub distros: Vec<Distro>,
+    pub build: bool,
+    /// Leave the containers running afterwards
+    pub keep: bool,
+    /// Only run the integration tests matching this
+    pub filter: Option<String>,
+}
+
+/// Run the ignored integration tests in each distro's container
+pub fn e2e(options: E2eOptions) -> Result<()> {
+    let distros = if options.distros.is_empty() {
+        Distro::ALL.to_vec()
+    } else {
+        options.distros
+    };
+
+    let mut results = Vec::new();
+    for distro in distros {
+        println!("\n==> {}: starting container...", distro.name());
+        if let Err(e) = start(distro, options.build) {
+            results.push((distro, "nft version unknown".to_string(), Err(e)));
+            continue;
+        }
+        let version = nft_version(distro);
+        println!(
+            "==> {}: running integration tests ({})...",
+            distro.name(),
+            version
+        );
+
+        let container = distro.container();
+        let mut args = vec![
+            "exec",
+            &container,
+            "cargo",
+            "test",
+            "--test",
+            "integration_tests",
+            "--",
+            "--ignored",
+            // The tests share the kernel ruleset and Docker daemon
+            "--test-threads=1",
+        ];
+        if let Some(filter) = &options.filter {
+            args.push(filter);
+        }
+        let result = run_command("docker", &args).map(|_| ());
+        results.push((distro, version, result));
+
+        if !options.keep {
+            let _ = run_command_silent("docker", &["rm", "-f", &container]);
+        }
+    }
+
+    println!("\nEnd-to-end results:");
+    let mut failed = Vec::new();
+    for (distro, version, result) in &results {
+        match result {
+            Ok(()) => println!("  {:<10} ok      {}", distro.name(), version),
+            Err(e) => {
+                println!("  {:<10} FAILED  {} ({})", distro.name(), version, e);
+                failed.push(distro.name());
+            }
+        }
+    }
+    if !failed.is_empty() {
+        bail!("Integration tests failed on: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_distros() {
+        for distro in Distro::ALL {
+            // The names printed are the ones `--distro` takes
+            assert_eq!(
+                Distro::from_str(distro.name(), false).unwrap(),
+                distro,
+                "{}",
+                distro.name()
+            );
+            let dockerfile = distro.dockerfile();
+            assert!(dockerfile.starts_with(&format!("FROM {}\n", distro.base_image())));
+            assert!(dockerfile.contains("nftables"), "{}", dockerfile);
+            assert!(
+                dockerfile.contains("--default-toolchain 1.88"),
+                "{}",
+                dockerfile
+            );
+        }
+        assert_eq!(Distro::Fedora.container(), "harborshield-dev-fedora");
+        assert_eq!(Distro::Ubuntu24.image(), "harborshield-dev:ubuntu24");
+    }
+}
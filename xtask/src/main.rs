@@ -4,6 +4,11 @@ use std::fs::{self, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::process::{Command, ExitStatus, Stdio};
 
+mod changed;
+mod chaos;
+mod distro;
+mod release;
+
 #[derive(Parser)]
 #[command(name = "xtask", about = "HarborShield development tasks")]
 struct Cli {
@@ -22,6 +27,11 @@ enum Commands {
         /// Run with test containers
         #[arg(short, long)]
         test: bool,
+
+        /// Start a container on this distro's nft userland instead,
+        /// named harborshield-dev-<distro>
+        #[arg(long, value_enum, conflicts_with = "test")]
+        distro: Option<distro::Distro>,
     },
 
     /// Open a shell in the dev container
@@ -54,6 +64,15 @@ enum Commands {
         /// Auto-fix issues where possible
         #[arg(short, long)]
         fix: bool,
+
+        /// Only check files and packages changed since the merge base, and
+        /// the packages depending on them
+        #[arg(short, long)]
+        changed: bool,
+
+        /// What `--changed` compares against
+        #[arg(long, default_value = "origin/main", requires = "changed")]
+        base: String,
     },
 
     /// Build release binary
@@ -84,17 +103,94 @@ enum Commands {
 
     /// Setup SSH config for Zed remote development
     SetupZed,
+
+    /// Randomly start, stop and rename labeled containers, restart Docker
+    /// and kill harborshield, then check the kernel ruleset matches the
+    /// running containers. Needs root, Docker and nft; use a throwaway VM
+    Chaos {
+        /// Random actions to take
+        #[arg(long, default_value_t = 100)]
+        steps: usize,
+
+        /// Replay the actions of an earlier run
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Most chaos containers to have at once
+        #[arg(long, default_value_t = 8)]
+        max_containers: usize,
+
+        /// Command restarting the Docker daemon, e.g. "systemctl restart
+        /// docker"; the daemon is left alone when unset
+        #[arg(long, value_name = "CMD")]
+        daemon_restart: Option<String>,
+
+        /// Seconds harborshield gets to converge after the last action
+        #[arg(long, default_value_t = 60)]
+        settle: u64,
+
+        /// harborshield binary to run instead of a fresh release build
+        #[arg(long)]
+        binary: Option<std::path::PathBuf>,
+    },
+
+    /// Cut a release: bump the version, write the changelog, build, sign
+    /// and checksum the artifacts, then commit and tag
+    Release {
+        /// Version to release, e.g. 1.4.0
+        version: String,
+
+        /// Target triples to build (defaults to x86_64 and aarch64 Linux)
+        #[arg(long = "target")]
+        targets: Vec<String>,
+
+        /// How to sign the artifacts and checksums
+        #[arg(long, value_enum, default_value_t = release::Signer::Minisign)]
+        sign: release::Signer,
+
+        /// Release even with uncommitted changes
+        #[arg(long)]
+        allow_dirty: bool,
+
+        /// Leave the version bump and changelog uncommitted and untagged
+        #[arg(long)]
+        no_commit: bool,
+    },
+
+    /// Run the integration tests in a container per distro, one distro
+    /// after another. Needs Docker and root on a Linux host
+    E2e {
+        /// Distro to test, repeatable (defaults to all of them)
+        #[arg(long = "distro", value_enum)]
+        distros: Vec<distro::Distro>,
+
+        /// Rebuild the distro images
+        #[arg(short, long)]
+        build: bool,
+
+        /// Leave the containers running afterwards
+        #[arg(long)]
+        keep: bool,
+
+        /// Only run the integration tests matching this
+        filter: Option<String>,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Dev { build, test } => cmd_dev(build, test),
+        Commands::Dev { build, test, distro } => cmd_dev(build, test, distro),
         Commands::Shell => cmd_shell(),
         Commands::Run { release, watch } => cmd_run(release, watch),
         Commands::Test { ignored, unit } => cmd_test(ignored, unit),
-        Commands::Check { fix } => cmd_check(fix),
+        Commands::Check {
+            fix,
+            changed: true,
+            base,
+        } => changed::run(fix, &base),
+        Commands::Check { fix, .. } => cmd_check(fix),
         Commands::Build { linux } => cmd_build(linux),
         Commands::Stop => cmd_stop(),
         Commands::Restart => cmd_restart(),
@@ -102,10 +198,60 @@ fn main() -> Result<()> {
         Commands::Migrate => cmd_migrate(),
         Commands::SqlxPrepare => cmd_sqlx_prepare(),
         Commands::SetupZed => cmd_setup_zed(),
+        Commands::Chaos {
+            steps,
+            seed,
+            max_containers,
+            daemon_restart,
+            settle,
+            binary,
+        } => chaos::run(chaos::Options {
+            steps,
+            seed,
+            max_containers,
+            daemon_restart,
+            settle: std::time::Duration::from_secs(settle),
+            binary,
+        }),
+        Commands::Release {
+            version,
+            targets,
+            sign,
+            allow_dirty,
+            no_commit,
+        } => release::run(release::Options {
+            version,
+            targets,
+            signer: sign,
+            allow_dirty,
+            no_commit,
+        }),
+        Commands::E2e {
+            distros,
+            build,
+            keep,
+            filter,
+        } => distro::e2e(distro::E2eOptions {
+            distros,
+            build,
+            keep,
+            filter,
+        }),
     }
 }
 
-fn cmd_dev(build: bool, test: bool) -> Result<()> {
+fn cmd_dev(build: bool, test: bool, distro: Option<distro::Distro>) -> Result<()> {
+    if let Some(distro) = distro {
+        println!("Starting {} development container...", distro.name());
+        distro::start(distro, build)?;
+
+        println!("\nDev container started!");
+        println!("  - Open a shell:  docker exec -it {} bash", distro.container());
+        println!("  - Run the tests: cargo xtask e2e --distro {} --keep", distro.name());
+        println!("  - Remove:        docker rm -f {}", distro.container());
+        return Ok(());
+    }
+
     println!("Starting development environment...");
 
     let mut args = vec![
@@ -273,6 +419,7 @@ fn cmd_clean(volumes: bool) -> Result<()> {
         "docker",
         &["rm", "-f", "harborshield-dev", "test-nginx"],
     );
+    distro::remove_all();
 
     println!("Cleanup complete.");
     Ok(())
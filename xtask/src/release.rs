@@ -0,0 +1,431 @@
+//! `cargo xtask release`: bump the version, write the changelog, build and
+//! sign the artifacts, then commit and tag the release.
+//!
+//! The changelog is generated from conventional commit subjects
+//! (`feat(api): ...`, `fix!: ...`) since the previous tag. Subjects that
+//! don't follow the convention are listed under "Other changes" rather than
+//! dropped.
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{project_root, run_command};
+
+pub const DEFAULT_TARGETS: &[&str] = &["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Signer {
+    Minisign,
+    Cosign,
+    None,
+}
+
+pub struct Options {
+    pub version: String,
+    pub targets: Vec<String>,
+    pub signer: Signer,
+    pub allow_dirty: bool,
+    pub no_commit: bool,
+}
+
+/// Changelog sections, in the order they are written
+const SECTIONS: &[(&str, &[&str])] = &[
+    ("Features", &["feat"]),
+    ("Fixes", &["fix"]),
+    ("Performance", &["perf"]),
+    ("Security", &["security"]),
+    ("Documentation", &["docs"]),
+    (
+        "Maintenance",
+        &["refactor", "build", "ci", "chore", "test", "style"],
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionalCommit {
+    pub kind: String,
+    pub scope: Option<String>,
+    pub breaking: bool,
+    pub description: String,
+}
+
+/// Parse `type(scope)!: description`
+pub fn parse_commit(subject: &str, body: &str) -> Option<ConventionalCommit> {
+    let (head, description) = subject.split_once(": ")?;
+    let (head, bang) = match head.strip_suffix('!') {
+        Some(head) => (head, true),
+        None => (head, false),
+    };
+    let (kind, scope) = match head.split_once('(') {
+        Some((kind, scope)) => (kind, Some(scope.strip_suffix(')')?.to_string())),
+        None => (head, None),
+    };
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_lowercase()) {
+        return None;
+    }
+    Some(ConventionalCommit {
+        kind: kind.to_string(),
+        scope,
+        breaking: bang || body.contains("BREAKING CHANGE"),
+        description: description.trim().to_string(),
+    })
+}
+
+fn entry(commit: &ConventionalCommit) -> String {
+    match &commit.scope {
+        Some(scope) => format!("- **{}:** {}\n", scope, commit.description),
+        None => format!("- {}\n", commit.description),
+    }
+}
+
+/// The changelog section for `version`, from `(subject, body)` pairs
+pub fn changelog_section(version: &str, date: &str, commits: &[(String, String)]) -> String {
+    let parsed: Vec<(Option<ConventionalCommit>, &str)> = commits
+        .iter()
+        .map(|(subject, body)| (parse_commit(subject, body), subject.as_str()))
+        .collect();
+
+    let mut out = format!("## v{} - {}\n", version, date);
+    let mut write_section = |title: &str, entries: Vec<String>| {
+        if !entries.is_empty() {
+            out.push_str(&format!("\n### {}\n\n", title));
+            out.extend(entries);
+        }
+    };
+
+    write_section(
+        "Breaking changes",
+        parsed
+            .iter()
+            .filter_map(|(c, _)| c.as_ref().filter(|c| c.breaking).map(entry))
+            .collect(),
+    );
+    for (title, kinds) in SECTIONS {
+        write_section(
+            title,
+            parsed
+                .iter()
+                .filter_map(|(c, _)| c.as_ref())
+                .filter(|c| !c.breaking && kinds.contains(&c.kind.as_str()))
+                .map(entry)
+                .collect(),
+        );
+    }
+    write_section(
+        "Other changes",
+        parsed
+            .iter()
+            .filter(|(c, _)| {
+                c.as_ref().is_none_or(|c| {
+                    !c.breaking && !SECTIONS.iter().any(|(_, k)| k.contains(&c.kind.as_str()))
+                })
+            })
+            .map(|(_, subject)| format!("- {}\n", subject))
+            .collect(),
+    );
+    out
+}
+
+/// Whether `version` looks like `MAJOR.MINOR.PATCH[-pre]`
+pub fn is_valid_version(version: &str) -> bool {
+    let core = version.split_once('-').map_or(version, |(core, _)| core);
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Replace the `version` of the `[package]` table in a Cargo.toml
+pub fn bump_manifest(manifest: &str, version: &str) -> Option<String> {
+    let mut in_package = false;
+    let mut bumped = false;
+    let lines: Vec<String> = manifest
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                in_package = trimmed == "[package]";
+            } else if in_package && !bumped && trimmed.starts_with("version") {
+                bumped = true;
+                return format!("version = \"{}\"", version);
+            }
+            line.to_string()
+        })
+        .collect();
+    bumped.then(|| lines.join("\n") + "\n")
+}
+
+fn output(cmd: &str, args: &[&str]) -> Result<String> {
+    let out = Command::new(cmd)
+        .args(args)
+        .current_dir(project_root())
+        .output()
+        .with_context(|| format!("Failed to run: {} {}", cmd, args.join(" ")))?;
+    if !out.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            cmd,
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// `(subject, body)` of the commits since the last tag
+fn commits_since_last_tag() -> Result<Vec<(String, String)>> {
+    let range = match output("git", &["describe", "--tags", "--abbrev=0"]) {
+        Ok(tag) => format!("{}..HEAD", tag.trim()),
+        Err(_) => "HEAD".to_string(),
+    };
+    // Unit and record separators keep multi-line bodies intact
+    let log = output("git", &["log", "--format=%s%x1f%b%x1e", &range])?;
+    Ok(log
+        .split('\x1e')
+        .filter_map(|record| {
+            let (subject, body) = record.trim().split_once('\x1f')?;
+            Some((subject.to_string(), body.to_string()))
+        })
+        .collect())
+}
+
+fn write_changelog(root: &Path, section: &str) -> Result<()> {
+    let path = root.join("CHANGELOG.md");
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    let rest = existing
+        .strip_prefix("# Changelog\n")
+        .unwrap_or(&existing)
+        .trim_start();
+    let mut changelog = format!("# Changelog\n\n{}", section);
+    if !rest.is_empty() {
+        changelog.push('\n');
+        changelog.push_str(rest);
+    }
+    fs::write(&path, changelog).context("Failed to write CHANGELOG.md")
+}
+
+fn build_artifact(root: &Path, dist: &Path, version: &str, target: &str) -> Result<PathBuf> {
+    println!("\n==> Building {}...", target);
+    run_command(
+        "cargo",
+        &["build", "--release", "--locked", "--target", target],
+    )?;
+
+    let name = format!("harborshield-{}-{}", version, target);
+    let staging = dist.join(&name);
+    fs::create_dir_all(&staging)?;
+    fs::copy(
+        root.join("target")
+            .join(target)
+            .join("release/harborshield"),
+        staging.join("harborshield"),
+    )
+    .with_context(|| format!("No binary was built for {}", target))?;
+    for extra in ["README.md", "LICENSE", "CHANGELOG.md"] {
+        fs::copy(root.join(extra), staging.join(extra))?;
+    }
+
+    let archive = format!("{}.tar.gz", name);
+    run_command(
+        "tar",
+        &[
+            "-C",
+            &dist.to_string_lossy(),
+            "-czf",
+            &dist.join(&archive).to_string_lossy(),
+            &name,
+        ],
+    )?;
+    fs::remove_dir_all(&staging)?;
+    Ok(dist.join(archive))
+}
+
+fn sign(signer: Signer, file: &Path) -> Result<()> {
+    let file = file.to_string_lossy();
+    match signer {
+        Signer::None => return Ok(()),
+        Signer::Minisign => {
+            let mut args = vec!["-S", "-m", &*file];
+            let key = std::env::var("MINISIGN_KEY").ok();
+            if let Some(key) = &key {
+                args.extend(["-s", key.as_str()]);
+            }
+            run_command("minisign", &args)?;
+        }
+        Signer::Cosign => {
+            let signature = format!("{}.sig", file);
+            let certificate = format!("{}.pem", file);
+            run_command(
+                "cosign",
+                &[
+                    "sign-blob",
+                    "--yes",
+                    "--output-signature",
+                    &signature,
+                    "--output-certificate",
+                    &certificate,
+                    &file,
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(options: Options) -> Result<()> {
+    let Options {
+        version,
+        targets,
+        signer,
+        allow_dirty,
+        no_commit,
+    } = options;
+    let version = version.strip_prefix('v').unwrap_or(&version).to_string();
+    if !is_valid_version(&version) {
+        bail!("'{}' is not a MAJOR.MINOR.PATCH version", version);
+    }
+    if !allow_dirty && !output("git", &["status", "--porcelain"])?.trim().is_empty() {
+        bail!("The working tree has uncommitted changes (use --allow-dirty to release anyway)");
+    }
+    let root = project_root();
+
+    println!("==> Bumping version to {}...", version);
+    let manifest_path = root.join("Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path)?;
+    let bumped = bump_manifest(&manifest, &version)
+        .context("Cargo.toml has no [package] version to bump")?;
+    fs::write(&manifest_path, bumped)?;
+    // Cargo.lock isn't tracked, but --locked builds need it to agree
+    run_command("cargo", &["update", "--workspace", "--offline"])?;
+
+    println!("==> Writing changelog...");
+    let date = output("date", &["-u", "+%Y-%m-%d"])?;
+    let commits = commits_since_last_tag()?;
+    let section = changelog_section(&version, date.trim(), &commits);
+    write_changelog(&root, &section)?;
+    print!("\n{}", section);
+
+    let dist = root.join("dist").join(&version);
+    if dist.exists() {
+        fs::remove_dir_all(&dist)?;
+    }
+    fs::create_dir_all(&dist)?;
+    let targets: Vec<String> = if targets.is_empty() {
+        DEFAULT_TARGETS.iter().map(|t| t.to_string()).collect()
+    } else {
+        targets
+    };
+    let mut artifacts = Vec::new();
+    for target in &targets {
+        artifacts.push(build_artifact(&root, &dist, &version, target)?);
+    }
+
+    println!("\n==> Writing checksums...");
+    let names: Vec<String> = artifacts
+        .iter()
+        .filter_map(|a| a.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect();
+    let mut sha_args = vec!["--"];
+    sha_args.extend(names.iter().map(String::as_str));
+    let sums = Command::new("sha256sum")
+        .args(&sha_args)
+        .current_dir(&dist)
+        .output()
+        .context("Failed to run sha256sum")?;
+    if !sums.status.success() {
+        bail!("sha256sum failed");
+    }
+    let checksums = dist.join("SHA256SUMS");
+    fs::write(&checksums, sums.stdout)?;
+
+    if signer != Signer::None {
+        println!("\n==> Signing artifacts...");
+        for file in artifacts.iter().chain(std::iter::once(&checksums)) {
+            sign(signer, file)?;
+        }
+    }
+
+    if !no_commit {
+        let tag = format!("v{}", version);
+        run_command("git", &["add", "Cargo.toml", "CHANGELOG.md"])?;
+        run_command("git", &["commit", "-m", &format!("Release {}", tag)])?;
+        run_command(
+            "git",
+            &["tag", "-a", &tag, "-m", &format!("Release {}", tag)],
+        )?;
+        println!("\nTagged {}. Push it with: git push --follow-tags", tag);
+    }
+    println!("Artifacts in {}", dist.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(subject: &str) -> (String, String) {
+        (subject.to_string(), String::new())
+    }
+
+    #[test]
+    fn test_changelog_section() {
+        assert_eq!(
+            parse_commit("feat(api)!: drop v1", ""),
+            Some(ConventionalCommit {
+                kind: "feat".to_string(),
+                scope: Some("api".to_string()),
+                breaking: true,
+                description: "drop v1".to_string(),
+            })
+        );
+        assert!(parse_commit("Fix the thing", "").is_none());
+        assert!(
+            parse_commit("fix: x", "BREAKING CHANGE: y")
+                .unwrap()
+                .breaking
+        );
+
+        let section = changelog_section(
+            "1.2.0",
+            "2026-01-02",
+            &[
+                commit("feat(dns): cache negative lookups"),
+                commit("fix: keep chains on restart"),
+                commit("chore: bump deps"),
+                commit("refactor!: rename the config file"),
+                commit("Merge branch 'main'"),
+            ],
+        );
+        assert!(section.starts_with("## v1.2.0 - 2026-01-02\n"));
+        let order: Vec<usize> = [
+            "### Breaking changes",
+            "### Features",
+            "### Fixes",
+            "### Maintenance",
+            "### Other changes",
+        ]
+        .iter()
+        .map(|title| section.find(title).unwrap())
+        .collect();
+        assert!(order.windows(2).all(|w| w[0] < w[1]), "{}", section);
+        assert!(section.contains("- **dns:** cache negative lookups\n"));
+        assert!(section.contains("- Merge branch 'main'\n"));
+    }
+
+    #[test]
+    fn test_bump_manifest() {
+        let manifest = "[workspace]\nmembers = [\"xtask\"]\n\n[package]\nname = \"harborshield\"\nversion = \"0.1.0\"\n\n[dependencies]\nbon = { version = \"3\" }\n";
+        let bumped = bump_manifest(manifest, "0.2.0").unwrap();
+        assert!(bumped.contains("version = \"0.2.0\"\n"));
+        assert!(bumped.contains("bon = { version = \"3\" }"));
+        assert!(bump_manifest("[dependencies]\n", "0.2.0").is_none());
+
+        assert!(is_valid_version("1.2.3"));
+        assert!(is_valid_version("1.2.3-rc.1"));
+        assert!(!is_valid_version("1.2"));
+        assert!(!is_valid_version("1.x.3"));
+    }
+}